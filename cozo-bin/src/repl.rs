@@ -13,54 +13,17 @@ use std::error::Error;
 use std::fs;
 use std::fs::File;
 use std::io::{Read, Write};
+use std::time::Instant;
 
 use clap::Args;
 use miette::{bail, miette, IntoDiagnostic};
-use rustyline::history::DefaultHistory;
-use rustyline::Changeset;
+use prettytable::format::Alignment;
+use rustyline::DefaultEditor;
 use serde_json::{json, Value};
 
-use cozo::{evaluate_expressions, DataValue, DbInstance, NamedRows, ScriptMutability};
-
-struct Indented;
-
-impl rustyline::hint::Hinter for Indented {
-    type Hint = String;
-}
-
-impl rustyline::highlight::Highlighter for Indented {}
-impl rustyline::completion::Completer for Indented {
-    type Candidate = String;
-
-    fn update(
-        &self,
-        _line: &mut rustyline::line_buffer::LineBuffer,
-        _start: usize,
-        _elected: &str,
-        _cl: &mut Changeset,
-    ) {
-        unreachable!();
-    }
-}
-
-impl rustyline::Helper for Indented {}
-
-impl rustyline::validate::Validator for Indented {
-    fn validate(
-        &self,
-        ctx: &mut rustyline::validate::ValidationContext<'_>,
-    ) -> rustyline::Result<rustyline::validate::ValidationResult> {
-        Ok(if ctx.input().starts_with(' ') {
-            if ctx.input().ends_with('\n') {
-                rustyline::validate::ValidationResult::Valid(None)
-            } else {
-                rustyline::validate::ValidationResult::Incomplete
-            }
-        } else {
-            rustyline::validate::ValidationResult::Valid(None)
-        })
-    }
-}
+use cozo::{
+    ends_with_terminator, evaluate_expressions, DataValue, DbInstance, NamedRows, ScriptMutability,
+};
 
 #[derive(Args, Debug)]
 pub(crate) struct ReplArgs {
@@ -75,6 +38,29 @@ pub(crate) struct ReplArgs {
     /// Extra config in JSON format
     #[clap(short, long, default_value_t = String::from("{}"))]
     config: String,
+
+    /// Cells wider than this many characters are cut short in tables, 0 for no limit
+    #[clap(long, default_value_t = 40)]
+    max_col_width: usize,
+
+    /// At most this many rows of a result are printed as a table, 0 for no limit
+    #[clap(long, default_value_t = 100)]
+    max_rows: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Table,
+    JsonLines,
+}
+
+struct ReplState {
+    params: BTreeMap<String, DataValue>,
+    save_next: Option<String>,
+    format: OutputFormat,
+    timing: bool,
+    max_col_width: usize,
+    max_rows: usize,
 }
 
 pub(crate) fn repl_main(args: ReplArgs) -> Result<(), Box<dyn Error>> {
@@ -100,13 +86,20 @@ pub(crate) fn repl_main(args: ReplArgs) -> Result<(), Box<dyn Error>> {
     .expect("Error setting Ctrl-C handler");
 
     println!("Welcome to the Cozo REPL.");
-    println!("Type a space followed by newline to enter multiline mode.");
+    println!("End queries with `;`, they can span several lines. Type \\help for meta commands.");
 
     let mut exit = false;
-    let mut rl = rustyline::Editor::<Indented, DefaultHistory>::new()?;
-    let mut params = BTreeMap::new();
-    let mut save_next: Option<String> = None;
-    rl.set_helper(Some(Indented));
+    let mut rl = DefaultEditor::new()?;
+    let mut state = ReplState {
+        params: BTreeMap::new(),
+        save_next: None,
+        format: OutputFormat::Table,
+        timing: false,
+        max_col_width: args.max_col_width,
+        max_rows: args.max_rows,
+    };
+    // the lines of the query being entered
+    let mut pending = String::new();
 
     let history_file = ".cozo_repl_history";
     if rl.load_history(history_file).is_ok() {
@@ -114,19 +107,36 @@ pub(crate) fn repl_main(args: ReplArgs) -> Result<(), Box<dyn Error>> {
     }
 
     loop {
-        let readline = rl.readline("=> ");
+        let prompt = if pending.is_empty() { "=> " } else { ".. " };
+        let readline = rl.readline(prompt);
         match readline {
             Ok(line) => {
-                if let Err(err) = process_line(&line, &db, &mut params, &mut save_next) {
+                exit = false;
+                let entry = if pending.is_empty() && is_meta_command(&line) {
+                    line
+                } else {
+                    pending.push_str(&line);
+                    pending.push('\n');
+                    if !ends_with_terminator(&pending) {
+                        continue;
+                    }
+                    std::mem::take(&mut pending)
+                };
+                let entry = entry.trim();
+                if entry.is_empty() {
+                    continue;
+                }
+                if let Err(err) = process_entry(entry, &db, &mut state) {
                     eprintln!("{err:?}");
                 }
-                if let Err(err) = rl.add_history_entry(line) {
+                if let Err(err) = rl.add_history_entry(entry) {
                     eprintln!("{err:?}");
                 }
-                exit = false;
             }
             Err(rustyline::error::ReadlineError::Interrupted) => {
-                if exit {
+                if !pending.is_empty() {
+                    pending.clear();
+                } else if exit {
                     break;
                 } else {
                     println!("Again to exit");
@@ -144,158 +154,243 @@ pub(crate) fn repl_main(args: ReplArgs) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn process_line(
-    line: &str,
-    db: &DbInstance,
-    params: &mut BTreeMap<String, DataValue>,
-    save_next: &mut Option<String>,
-) -> miette::Result<()> {
-    let line = line.trim();
-    if line.is_empty() {
-        return Ok(());
-    }
-
-    let mut process_out = |out: NamedRows| -> miette::Result<()> {
-        if let Some(path) = save_next.as_ref() {
-            println!(
-                "Query has returned {} rows, saving to file {}",
-                out.rows.len(),
-                path
-            );
-
-            let to_save = out
-                .rows
-                .iter()
-                .map(|row| -> Value {
-                    row.iter()
-                        .zip(out.headers.iter())
-                        .map(|(v, k)| (k.to_string(), v.clone()))
-                        .collect()
-                })
-                .collect();
+fn is_meta_command(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with('%') || line.starts_with('\\')
+}
 
-            let j_payload = Value::Array(to_save);
+const HELP: &str = r"Queries are ended with `;`. Meta commands start with `\` or `%`:
+  \tables                 list the stored relations
+  \schema <RELATION>      show the columns of a stored relation
+  \format table|json      print results as tables or as JSON lines
+  \timing on|off          show how long each query takes
+  \width <N>              cut cells in tables short at N characters, 0 for no limit
+  \limit <N>              print at most N rows in tables, 0 for no limit
+  \set <KEY> <VALUE>      set a parameter to a JSON value
+  \unset <KEY>            unset a parameter
+  \clear                  unset all parameters
+  \params                 print all parameters
+  \eval <EXPR>            evaluate an expression
+  \run <FILE>             run the script in a file
+  \import <FILE OR URL>   import relations from JSON
+  \save [<FILE>]          save the next result to a file as JSON
+  \backup <FILE>          back up the database to a file
+  \restore <FILE>         restore the database from a backup";
 
-            let mut file = File::create(path).into_diagnostic()?;
-            file.write_all(j_payload.to_string().as_bytes())
-                .into_diagnostic()?;
-            *save_next = None;
-        } else {
-            use prettytable::format;
-            let mut table = prettytable::Table::new();
-            let headers = out
-                .headers
-                .iter()
-                .map(prettytable::Cell::from)
-                .collect::<Vec<_>>();
-            table.set_titles(prettytable::Row::new(headers));
-            let rows = out
-                .rows
-                .iter()
-                .map(|r| r.iter().map(|c| format!("{c}")).collect::<Vec<_>>())
-                .collect::<Vec<_>>();
-            let rows = rows
-                .iter()
-                .map(|r| r.iter().map(prettytable::Cell::from).collect::<Vec<_>>());
-            for row in rows {
-                table.add_row(prettytable::Row::new(row));
-            }
-            table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
-            table.printstd();
-        }
-        Ok(())
+fn process_entry(entry: &str, db: &DbInstance, state: &mut ReplState) -> miette::Result<()> {
+    let Some(remaining) = entry.strip_prefix('%').or_else(|| entry.strip_prefix('\\')) else {
+        let script = entry.strip_suffix(';').unwrap_or(entry);
+        return run_query(script, db, state);
     };
-
-    if let Some(remaining) = line.strip_prefix('%') {
-        let remaining = remaining.trim();
-        let (op, payload) = remaining
-            .split_once(|c: char| c.is_whitespace())
-            .unwrap_or((remaining, ""));
-        match op {
-            "eval" => {
-                let out = evaluate_expressions(payload, params, params)?;
-                println!("{out}");
-            }
-            "set" => {
-                let (key, v_str) = payload
-                    .trim()
-                    .split_once(|c: char| c.is_whitespace())
-                    .ok_or_else(|| miette!("Bad set syntax. Should be '%set <KEY> <VALUE>'."))?;
-                let val: Value = serde_json::from_str(v_str).into_diagnostic()?;
-                let val = DataValue::from(val);
-                params.insert(key.to_string(), val);
-            }
-            "unset" => {
-                let key = payload.trim();
-                if params.remove(key).is_none() {
-                    bail!("Key not found: '{}'", key)
-                }
+    let remaining = remaining.trim();
+    let (op, payload) = remaining
+        .split_once(|c: char| c.is_whitespace())
+        .unwrap_or((remaining, ""));
+    let payload = payload.trim();
+    let params = &mut state.params;
+    match op {
+        "help" => println!("{HELP}"),
+        "tables" => run_query("::relations", db, state)?,
+        "schema" => {
+            if payload.is_empty() {
+                bail!("Schema requires the name of a relation");
             }
-            "clear" => {
-                params.clear();
+            run_query(&format!("::columns {payload}"), db, state)?
+        }
+        "format" => {
+            state.format = match payload {
+                "table" => OutputFormat::Table,
+                "json" => OutputFormat::JsonLines,
+                _ => bail!("Bad format '{payload}'. Should be 'table' or 'json'."),
             }
-            "params" => {
-                let display = serde_json::to_string_pretty(&json!(&params)).into_diagnostic()?;
-                println!("{display}");
+        }
+        "timing" => {
+            state.timing = match payload {
+                "on" => true,
+                "off" => false,
+                "" => !state.timing,
+                _ => bail!("Bad timing syntax. Should be '\\timing on' or '\\timing off'."),
+            };
+            println!("Timing is {}", if state.timing { "on" } else { "off" });
+        }
+        "width" => {
+            state.max_col_width = payload
+                .parse()
+                .map_err(|_| miette!("Bad width '{payload}'. Should be a number."))?
+        }
+        "limit" => {
+            state.max_rows = payload
+                .parse()
+                .map_err(|_| miette!("Bad limit '{payload}'. Should be a number."))?
+        }
+        "eval" => {
+            let out = evaluate_expressions(payload, params, params)?;
+            println!("{out}");
+        }
+        "set" => {
+            let (key, v_str) = payload
+                .split_once(|c: char| c.is_whitespace())
+                .ok_or_else(|| miette!("Bad set syntax. Should be '%set <KEY> <VALUE>'."))?;
+            let val: Value = serde_json::from_str(v_str).into_diagnostic()?;
+            let val = DataValue::from(val);
+            params.insert(key.to_string(), val);
+        }
+        "unset" => {
+            if params.remove(payload).is_none() {
+                bail!("Key not found: '{}'", payload)
             }
-            "backup" => {
-                let path = payload.trim();
-                if path.is_empty() {
-                    bail!("Backup requires a path");
-                };
-                db.backup_db(path)?;
-                println!("Backup written successfully to {path}")
+        }
+        "clear" => {
+            params.clear();
+        }
+        "params" => {
+            let display = serde_json::to_string_pretty(&json!(&params)).into_diagnostic()?;
+            println!("{display}");
+        }
+        "backup" => {
+            if payload.is_empty() {
+                bail!("Backup requires a path");
+            };
+            db.backup_db(payload)?;
+            println!("Backup written successfully to {payload}")
+        }
+        "run" => {
+            if payload.is_empty() {
+                bail!("Run requires path to a script");
             }
-            "run" => {
-                let path = payload.trim();
-                if path.is_empty() {
-                    bail!("Run requires path to a script");
-                }
-                let content = fs::read_to_string(path).into_diagnostic()?;
-                let out = db.run_script(&content, params.clone(), ScriptMutability::Mutable)?;
-                process_out(out)?;
+            let content = fs::read_to_string(payload).into_diagnostic()?;
+            run_query(&content, db, state)?;
+        }
+        "restore" => {
+            if payload.is_empty() {
+                bail!("Restore requires a path");
+            };
+            db.restore_backup(payload)?;
+            println!("Backup successfully loaded from {payload}")
+        }
+        "save" => {
+            if payload.is_empty() {
+                println!("Next result will NOT be saved to file");
+                state.save_next = None;
+            } else {
+                println!("Next result will be saved to file: {payload}");
+                state.save_next = Some(payload.to_string())
             }
-            "restore" => {
-                let path = payload.trim();
-                if path.is_empty() {
-                    bail!("Restore requires a path");
-                };
-                db.restore_backup(path)?;
-                println!("Backup successfully loaded from {path}")
+        }
+        "import" => {
+            let url = payload;
+            if url.starts_with("http://") || url.starts_with("https://") {
+                let data = minreq::get(url).send().into_diagnostic()?;
+                let data = data.as_str().into_diagnostic()?;
+                db.import_relations_str_with_err(data)?;
+                println!("Imported data from {url}")
+            } else {
+                let file_path = url.strip_prefix("file://").unwrap_or(url);
+                let mut file = File::open(file_path).into_diagnostic()?;
+                let mut content = String::new();
+                file.read_to_string(&mut content).into_diagnostic()?;
+                db.import_relations_str_with_err(&content)?;
+                println!("Imported data from {url}");
             }
-            "save" => {
-                let next_path = payload.trim();
-                if next_path.is_empty() {
-                    println!("Next result will NOT be saved to file");
-                } else {
-                    println!("Next result will be saved to file: {next_path}");
-                    *save_next = Some(next_path.to_string())
-                }
+        }
+        _ => bail!("Unknown meta command '{op}'. Type \\help for the list of commands."),
+    }
+    Ok(())
+}
+
+fn run_query(script: &str, db: &DbInstance, state: &mut ReplState) -> miette::Result<()> {
+    let start = Instant::now();
+    let out = db
+        .run_script(script, state.params.clone(), ScriptMutability::Mutable)
+        .map_err(|err| {
+            if err.source_code().is_none() {
+                err.with_source_code(format!("{script} "))
+            } else {
+                err
             }
-            "import" => {
-                let url = payload.trim();
-                if url.starts_with("http://") || url.starts_with("https://") {
-                    let data = minreq::get(url).send().into_diagnostic()?;
-                    let data = data.as_str().into_diagnostic()?;
-                    db.import_relations_str_with_err(data)?;
-                    println!("Imported data from {url}")
-                } else {
-                    let file_path = url.strip_prefix("file://").unwrap_or(url);
-                    let mut file = File::open(file_path).into_diagnostic()?;
-                    let mut content = String::new();
-                    file.read_to_string(&mut content).into_diagnostic()?;
-                    db.import_relations_str_with_err(&content)?;
-                    println!("Imported data from {url}");
+        })?;
+    let elapsed = start.elapsed();
+    if let Some(path) = state.save_next.take() {
+        println!(
+            "Query has returned {} rows, saving to file {}",
+            out.rows.len(),
+            path
+        );
+        let to_save = out.rows.iter().map(|row| row_to_json(&out, row)).collect();
+        let j_payload = Value::Array(to_save);
+
+        let mut file = File::create(path).into_diagnostic()?;
+        file.write_all(j_payload.to_string().as_bytes())
+            .into_diagnostic()?;
+    } else {
+        match state.format {
+            OutputFormat::Table => print_table(&out, state.max_col_width, state.max_rows),
+            OutputFormat::JsonLines => {
+                for row in &out.rows {
+                    println!("{}", row_to_json(&out, row));
                 }
             }
-            _ => {
-                let out = db.run_script(line, params.clone(), ScriptMutability::Mutable)?;
-                process_out(out)?;
-            }
         }
-    } else {
-        let out = db.run_script(line, params.clone(), ScriptMutability::Mutable)?;
-        process_out(out)?;
+    }
+    if state.timing {
+        println!("Time: {:.3} ms", elapsed.as_secs_f64() * 1000.);
     }
     Ok(())
 }
+
+fn row_to_json(out: &NamedRows, row: &[DataValue]) -> Value {
+    row.iter()
+        .zip(out.headers.iter())
+        .map(|(v, k)| (k.to_string(), v.clone()))
+        .collect()
+}
+
+/// Print the rows as an aligned table, with numbers aligned right, cells longer than
+/// `max_col_width` characters cut short and at most `max_rows` rows.
+fn print_table(out: &NamedRows, max_col_width: usize, max_rows: usize) {
+    use prettytable::format;
+    let mut table = prettytable::Table::new();
+    let headers = out
+        .headers
+        .iter()
+        .map(prettytable::Cell::from)
+        .collect::<Vec<_>>();
+    table.set_titles(prettytable::Row::new(headers));
+    let shown = if max_rows == 0 {
+        out.rows.len()
+    } else {
+        out.rows.len().min(max_rows)
+    };
+    for row in &out.rows[..shown] {
+        let cells = row
+            .iter()
+            .map(|v| {
+                let text = truncate_cell(&v.to_string(), max_col_width);
+                let align = match v {
+                    DataValue::Num(_) => Alignment::RIGHT,
+                    _ => Alignment::LEFT,
+                };
+                prettytable::Cell::new_align(&text, align)
+            })
+            .collect();
+        table.add_row(prettytable::Row::new(cells));
+    }
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.printstd();
+    if shown < out.rows.len() {
+        println!(
+            "({shown} of {} rows shown, change the limit with \\limit)",
+            out.rows.len()
+        );
+    }
+}
+
+fn truncate_cell(text: &str, max_width: usize) -> String {
+    let text = text.replace('\n', "\\n");
+    if max_width == 0 || text.chars().count() <= max_width {
+        return text;
+    }
+    let mut cut: String = text.chars().take(max_width.saturating_sub(1)).collect();
+    cut.push('…');
+    cut
+}
@@ -35,7 +35,9 @@ use tower_http::auth::{AsyncAuthorizeRequest, AsyncRequireAuthorizationLayer};
 use tower_http::compression::CompressionLayer;
 use tower_http::cors::{Any, CorsLayer};
 
-use cozo::{DataValue, DbInstance, format_error_as_json, MultiTransaction, NamedRows, ScriptMutability, SimpleFixedRule};
+use cozo::data::functions::current_validity;
+use cozo::parse::{parse_script, CozoScript};
+use cozo::{DataValue, DbInstance, error_status_code, format_error_as_json, MultiTransaction, NamedRows, ScriptMutability, SimpleFixedRule};
 
 #[derive(Args, Debug)]
 pub(crate) struct ServerArgs {
@@ -66,6 +68,11 @@ pub(crate) struct ServerArgs {
     /// When set, the content of the named table will be used as a token table
     #[clap(long)]
     token_table: Option<String>,
+
+    /// When set, requests with the header `Authorization: Bearer <token>` are given full access,
+    /// and requests without it are rejected even if the server is bound to 127.0.0.1
+    #[clap(long)]
+    token: Option<String>,
 }
 
 #[derive(Clone)]
@@ -81,6 +88,7 @@ struct DbState {
 struct MyAuth {
     skip_auth: bool,
     auth_guard: String,
+    token: Option<String>,
     token_table: Option<Arc<(String, DbInstance)>>,
 }
 
@@ -94,11 +102,23 @@ impl AsyncAuthorizeRequest<Body> for MyAuth
         let skip_auth = self.skip_auth;
         let auth_guard = self.auth_guard.clone();
         let token_table = self.token_table.clone();
+        let token = self.token.clone();
         Box::pin(async move {
             if skip_auth {
                 request.extensions_mut().insert(ScriptMutability::Mutable);
                 return Ok(request);
             }
+            if let Some(token) = token {
+                let bearer = request
+                    .headers()
+                    .get("Authorization")
+                    .and_then(|h| h.to_str().ok())
+                    .and_then(|s| s.strip_prefix("Bearer "));
+                if bearer == Some(token.as_str()) {
+                    request.extensions_mut().insert(ScriptMutability::Mutable);
+                    return Ok(request);
+                }
+            }
 
             let mutability = match request.headers().get("x-cozo-auth") {
                 None => match request.uri().query() {
@@ -198,15 +218,18 @@ pub(crate) async fn server_main(args: ServerArgs) {
         }
     }
 
-    let skip_auth = args.bind == "127.0.0.1";
+    let skip_auth = args.bind == "127.0.0.1" && args.token.is_none();
 
-    let conf_path = if skip_auth {
+    let conf_path = if skip_auth || args.token.is_some() {
         "".to_string()
     } else {
         format!("{}.{}.cozo_auth", args.path, args.engine)
     };
     let auth_guard = if skip_auth {
         "".to_string()
+    } else if let Some(token) = &args.token {
+        // the static token is also accepted wherever the generated one is
+        token.clone()
     } else {
         match tokio::fs::read_to_string(&conf_path).await {
             Ok(s) => s.trim().to_string(),
@@ -225,6 +248,7 @@ pub(crate) async fn server_main(args: ServerArgs) {
     let auth_obj = MyAuth {
         skip_auth,
         auth_guard,
+        token: args.token.clone(),
         token_table: args.token_table.map(|t| Arc::new((t, db.clone()))),
     };
 
@@ -238,10 +262,16 @@ pub(crate) async fn server_main(args: ServerArgs) {
     let cors = CorsLayer::new()
         .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
         .allow_origin(Any)
-        .allow_headers([header::CONTENT_TYPE, HeaderName::from_static("x-cozo-auth")]);
+        .allow_headers([
+            header::CONTENT_TYPE,
+            header::AUTHORIZATION,
+            HeaderName::from_static("x-cozo-auth"),
+        ]);
 
     let app = Router::new()
         .route("/text-query", post(text_query))
+        .route("/query", post(single_query))
+        .route("/script", post(script_query))
         .route("/export/:relations", get(export_relations))
         .route("/import", put(import_relations))
         .route("/backup", post(backup))
@@ -270,15 +300,19 @@ pub(crate) async fn server_main(args: ServerArgs) {
 
     if args.bind != "127.0.0.1" {
         warn!("{}", include_str!("./security.txt"));
-        info!("The auth token is in the file: {conf_path}");
+        if args.token.is_none() {
+            info!("The auth token is in the file: {conf_path}");
+        }
     }
 
+    // bind first, so that the actual port is reported when asked for port 0
+    let listener = TcpListener::bind(&addr).await.unwrap();
     info!(
         "Starting Cozo ({}-backed) API at http://{}",
-        args.engine, addr
+        args.engine,
+        listener.local_addr().unwrap()
     );
 
-    let listener = TcpListener::bind(&addr).await.unwrap();
     axum::serve(listener, app.into_make_service()).await.unwrap();
 }
 
@@ -319,10 +353,7 @@ async fn transact_query(
         .await;
     match result {
         Ok(Ok(res)) => (StatusCode::OK, res.into_json().into()),
-        Ok(Err(err)) => (
-            StatusCode::BAD_REQUEST,
-            format_error_as_json(err, Some(&src)).into(),
-        ),
+        Ok(Err(err)) => error_response(err, &src),
         Err(err) => internal_error(err),
     }
 }
@@ -394,6 +425,85 @@ async fn text_query(
     }
 }
 
+#[derive(serde_derive::Deserialize)]
+struct SingleQueryPayload {
+    query: String,
+    #[serde(default)]
+    params: BTreeMap<String, serde_json::Value>,
+    immutable: Option<bool>,
+}
+
+/// Runs a single query, rejecting scripts with imperative statements
+async fn single_query(
+    Extension(mutability): Extension<ScriptMutability>,
+    State(st): State<DbState>,
+    Json(payload): Json<SingleQueryPayload>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let mutability = request_mutability(mutability, payload.immutable);
+    let src = payload.query.clone();
+    let result = spawn_blocking(move || {
+        let params = json_params(payload.params);
+        let cur_vld = current_validity();
+        let script = parse_script(&payload.query, &params, &st.db.get_fixed_rules(), cur_vld)?;
+        if !matches!(script, CozoScript::Single(_)) {
+            miette::bail!("expected a single query, use /script for imperative scripts");
+        }
+        st.db.run_script_ast(script, cur_vld, mutability)
+    })
+        .await;
+    match result {
+        Ok(Ok(res)) => ok_rows(res),
+        Ok(Err(err)) => error_response(err, &src),
+        Err(err) => internal_error(err),
+    }
+}
+
+/// Runs a script, which may contain several statements
+async fn script_query(
+    Extension(mutability): Extension<ScriptMutability>,
+    State(st): State<DbState>,
+    Json(payload): Json<QueryPayload>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let mutability = request_mutability(mutability, payload.immutable);
+    let src = payload.script.clone();
+    let result = spawn_blocking(move || {
+        st.db
+            .run_script(&payload.script, json_params(payload.params), mutability)
+    })
+        .await;
+    match result {
+        Ok(Ok(res)) => ok_rows(res),
+        Ok(Err(err)) => error_response(err, &src),
+        Err(err) => internal_error(err),
+    }
+}
+
+fn request_mutability(granted: ScriptMutability, immutable: Option<bool>) -> ScriptMutability {
+    match granted {
+        ScriptMutability::Mutable if !immutable.unwrap_or(false) => ScriptMutability::Mutable,
+        _ => ScriptMutability::Immutable,
+    }
+}
+
+fn json_params(params: BTreeMap<String, serde_json::Value>) -> BTreeMap<String, DataValue> {
+    params
+        .into_iter()
+        .map(|(k, v)| (k, DataValue::from(v)))
+        .collect()
+}
+
+fn ok_rows(res: NamedRows) -> (StatusCode, Json<serde_json::Value>) {
+    let mut json = res.into_json();
+    json["ok"] = json!(true);
+    (StatusCode::OK, json.into())
+}
+
+fn error_response(err: miette::Report, src: &str) -> (StatusCode, Json<serde_json::Value>) {
+    let code =
+        StatusCode::from_u16(error_status_code(&err)).unwrap_or(StatusCode::BAD_REQUEST);
+    (code, format_error_as_json(err, Some(src)).into())
+}
+
 async fn export_relations(
     State(st): State<DbState>,
     Path(relations): Path<String>,
@@ -0,0 +1,79 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Feed `input` to a REPL on an in-memory database, returning its stdout and stderr.
+fn run_repl(input: &str, args: &[&str]) -> (String, String) {
+    // the REPL keeps its history in the working directory
+    let dir = std::env::temp_dir().join(format!("cozo-repl-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cozo-bin"))
+        .arg("repl")
+        .args(args)
+        .current_dir(&dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let out = child.wait_with_output().unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+    (
+        String::from_utf8(out.stdout).unwrap(),
+        String::from_utf8(out.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn repl_session() {
+    let (out, err) = run_repl(
+        r#":create t {k: Int => v: String};
+?[k, v] <- [[1, "a rather long string"],
+            [22, "b"], [3, "c"]]
+:put t {k, v};
+\tables
+\schema t
+?[k, v] := *t[k, v];
+\format json
+?[k, v] := *t[k, v], k < 3 # not the end;
+;
+\format table
+?[k] := *t[k, _] /* not the end; */ ;
+?[x] := x = "not the end;
+";
+?[k] := j = 1;
+\bogus
+"#,
+        &["--max-col-width", "10", "--max-rows", "2"],
+    );
+
+    // multi-line queries run once complete and tables are cut short
+    assert!(out.contains(" 1 | \"a rather… \n"), "{out}");
+    assert!(out.contains("(2 of 3 rows shown"), "{out}");
+    assert!(out.contains("\"t\""), "{out}");
+    assert!(out.contains("\"String\""), "{out}");
+    assert!(
+        out.contains("{\"k\":1,\"v\":\"a rather long string\"}\n"),
+        "{out}"
+    );
+    assert!(!out.contains("{\"k\":22,\"v\":\"b\"}"), "{out}");
+    assert!(out.contains("\"not the …"), "{out}");
+
+    // errors point at the query
+    assert!(err.contains("Symbol 'k' in rule head is unbound"), "{err}");
+    assert!(err.contains("?[k] := j = 1"), "{err}");
+    assert!(err.contains("Unknown meta command 'bogus'"), "{err}");
+}
@@ -0,0 +1,169 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+
+use serde_json::{json, Value};
+
+const TOKEN: &str = "s3cret";
+
+struct Server {
+    child: Child,
+    url: String,
+}
+
+impl Server {
+    fn start() -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_cozo-bin"))
+            .args(["server", "--port", "0", "--token", TOKEN])
+            .env("RUST_LOG", "info")
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut lines = BufReader::new(child.stderr.take().unwrap()).lines();
+        let url = loop {
+            let line = lines.next().expect("server exited").unwrap();
+            if let Some((_, addr)) = line.split_once("API at ") {
+                break addr.trim().to_string();
+            }
+        };
+        // keep draining the log so that the server never blocks on it
+        std::thread::spawn(move || for _ in lines {});
+        Server { child, url }
+    }
+    fn request(&self, method: &str, path: &str, body: Option<Value>, auth: bool) -> (i32, Value) {
+        let url = format!("{}{}", self.url, path);
+        let mut req = match method {
+            "GET" => minreq::get(url),
+            "PUT" => minreq::put(url),
+            _ => minreq::post(url),
+        };
+        if auth {
+            req = req.with_header("Authorization", format!("Bearer {TOKEN}"));
+        }
+        if let Some(body) = body {
+            req = req
+                .with_header("Content-Type", "application/json")
+                .with_body(body.to_string());
+        }
+        let resp = req.send().unwrap();
+        let json = serde_json::from_str(resp.as_str().unwrap()).unwrap_or(Value::Null);
+        (resp.status_code, json)
+    }
+    fn post(&self, path: &str, body: Value) -> (i32, Value) {
+        self.request("POST", path, Some(body), true)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn http_queries() {
+    let server = Server::start();
+
+    let (status, _) = server.request(
+        "POST",
+        "/query",
+        Some(json!({"query": "?[a] <- [[1]]"})),
+        false,
+    );
+    assert_eq!(status, 401);
+
+    let (status, res) = server.post(
+        "/script",
+        json!({"script": r"
+            {:create department {id: Int => name: String}}
+            {:create employee {id: Int => name: String, dept: Int, salary: Float}}
+        ", "params": {}}),
+    );
+    assert_eq!(status, 200, "{res}");
+
+    let hr = json!({
+        "department": {
+            "headers": ["id", "name"],
+            "rows": [[1, "Engineering"], [2, "Sales"]]
+        },
+        "employee": {
+            "headers": ["id", "name", "dept", "salary"],
+            "rows": [
+                [1, "Ann", 1, 120.0],
+                [2, "Bob", 1, 100.0],
+                [3, "Cid", 2, 80.0]
+            ]
+        }
+    });
+    let (status, res) = server.request("PUT", "/import", Some(hr), true);
+    assert_eq!(status, 200, "{res}");
+
+    let (status, res) = server.post(
+        "/query",
+        json!({
+            "query": "?[dept, sum(salary)] := *employee{dept: d, salary}, *department{id: d, name: dept}",
+            "params": {}
+        }),
+    );
+    assert_eq!(status, 200, "{res}");
+    assert_eq!(res["headers"], json!(["dept", "sum(salary)"]));
+    assert_eq!(
+        res["rows"],
+        json!([["Engineering", 220.0], ["Sales", 80.0]])
+    );
+
+    let (status, res) = server.post(
+        "/query",
+        json!({"query": "?[name] := *employee{name, salary}, salary > $min", "params": {"min": 90}}),
+    );
+    assert_eq!(status, 200, "{res}");
+    assert_eq!(res["rows"], json!([["Ann"], ["Bob"]]));
+
+    let (status, res) = server.request("GET", "/export/employee", None, true);
+    assert_eq!(status, 200, "{res}");
+    assert_eq!(res["data"]["employee"]["rows"].as_array().unwrap().len(), 3);
+
+    // parse errors
+    let (status, res) = server.post("/query", json!({"query": "?[x] := x ="}));
+    assert_eq!(status, 400, "{res}");
+    assert_eq!(res["ok"], json!(false));
+    // imperative scripts are only run by /script
+    let (status, res) = server.post(
+        "/query",
+        json!({"query": "{?[a] <- [[1]]} {?[a] <- [[2]]}"}),
+    );
+    assert_eq!(status, 400, "{res}");
+    let (status, res) = server.post(
+        "/script",
+        json!({"script": "{?[a] <- [[1]]} {?[a] <- [[2]]}", "params": {}}),
+    );
+    assert_eq!(status, 200, "{res}");
+    assert_eq!(res["rows"], json!([[2]]));
+    // conflicts
+    let (status, res) = server.post("/query", json!({"query": ":create employee {id}"}));
+    assert_eq!(status, 409, "{res}");
+    let (status, res) = server.post(
+        "/query",
+        json!({"query": "?[id, name, dept, salary] <- [[1, 'Ann', 1, 1.0]] :insert employee {id => name, dept, salary}"}),
+    );
+    assert_eq!(status, 409, "{res}");
+    // timeouts
+    let (status, res) = server.post(
+        "/query",
+        json!({"query": r"
+            n[x] := x = 0
+            n[y] := n[x], y = x + 1, y < 100000000
+            ?[count(x)] := n[x]
+            :timeout 0.1
+        "}),
+    );
+    assert_eq!(status, 408, "{res}");
+}
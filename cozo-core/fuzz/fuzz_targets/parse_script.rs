@@ -0,0 +1,36 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Parses arbitrary scripts into their syntax trees, without running them.
+//! Parsing must return an error for bad scripts instead of panicking.
+//!
+//! Run with `cargo fuzz run parse_script` from the `cozo-core` directory.
+
+#![no_main]
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use cozo::data::functions::current_validity;
+use cozo::parse::parse_script;
+use cozo::{new_cozo_mem, DataValue, FixedRule};
+use lazy_static::lazy_static;
+use libfuzzer_sys::fuzz_target;
+
+lazy_static! {
+    static ref FIXED_RULES: BTreeMap<String, Arc<Box<dyn FixedRule>>> =
+        new_cozo_mem().unwrap().get_fixed_rules();
+    static ref PARAMS: BTreeMap<String, DataValue> =
+        BTreeMap::from([("x".to_string(), DataValue::from(1))]);
+}
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(src) = std::str::from_utf8(data) {
+        let _ = parse_script(src, &PARAMS, &FIXED_RULES, current_validity());
+    }
+});
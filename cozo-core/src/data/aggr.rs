@@ -6,12 +6,16 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::cmp::Ordering;
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Formatter};
+use std::hash::Hasher;
 
 use miette::{bail, ensure, miette, Result};
 use rand::prelude::*;
+use twox_hash::XxHash64;
 
+use crate::data::memcmp::MemCmpEncoder;
 use crate::data::value::DataValue;
 
 pub struct Aggregation {
@@ -214,6 +218,209 @@ impl NormalAggrObj for AggrCountUnique {
     }
 }
 
+define_aggr!(AGGR_COUNT_DISTINCT, false);
+
+/// Exact distinct count, keeping only the memcmp encodings of the values seen.
+#[derive(Default)]
+pub(crate) struct AggrCountDistinct {
+    accum: BTreeSet<Vec<u8>>,
+}
+
+impl NormalAggrObj for AggrCountDistinct {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        let mut encoded = vec![];
+        encoded.encode_datavalue(value);
+        self.accum.insert(encoded);
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(DataValue::from(self.accum.len() as i64))
+    }
+}
+
+/// A HyperLogLog sketch for estimating the number of distinct values.
+///
+/// With precision `p` the sketch uses `2^p` one-byte registers, and the relative
+/// standard error of the estimate is about `1.04 / sqrt(2^p)`, e.g. 0.81% for the
+/// default precision of 14. The serialized form is the precision byte followed by
+/// the registers.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+pub(crate) const HLL_MIN_PRECISION: u8 = 4;
+pub(crate) const HLL_MAX_PRECISION: u8 = 18;
+pub(crate) const HLL_DEFAULT_PRECISION: u8 = 14;
+
+impl HyperLogLog {
+    pub(crate) fn new(precision: u8) -> Result<Self> {
+        ensure!(
+            (HLL_MIN_PRECISION..=HLL_MAX_PRECISION).contains(&precision),
+            "HyperLogLog precision must be between {} and {}, got {}",
+            HLL_MIN_PRECISION,
+            HLL_MAX_PRECISION,
+            precision
+        );
+        Ok(Self {
+            precision,
+            registers: vec![0; 1 << precision],
+        })
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut hll = Self::new(*bytes.first().unwrap_or(&0))
+            .map_err(|_| miette!("bytes are not a HyperLogLog sketch"))?;
+        ensure!(
+            bytes.len() == hll.registers.len() + 1,
+            "bytes are not a HyperLogLog sketch"
+        );
+        hll.registers.copy_from_slice(&bytes[1..]);
+        Ok(hll)
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut ret = Vec::with_capacity(self.registers.len() + 1);
+        ret.push(self.precision);
+        ret.extend_from_slice(&self.registers);
+        ret
+    }
+
+    pub(crate) fn add(&mut self, value: &DataValue) {
+        let mut encoded = vec![];
+        encoded.encode_datavalue(value);
+        let mut hasher = XxHash64::with_seed(0);
+        hasher.write(&encoded);
+        let hash = hasher.finish();
+        let p = self.precision as u32;
+        let idx = (hash >> (64 - p)) as usize;
+        // the guard bit bounds the rank when the remaining bits are all zero
+        let rank = ((hash << p) | (1 << (p - 1))).leading_zeros() + 1;
+        self.registers[idx] = self.registers[idx].max(rank as u8);
+    }
+
+    pub(crate) fn merge(&mut self, other: &Self) -> Result<()> {
+        ensure!(
+            self.precision == other.precision,
+            "cannot merge HyperLogLog sketches with precisions {} and {}",
+            self.precision,
+            other.precision
+        );
+        for (l, r) in self.registers.iter_mut().zip(other.registers.iter()) {
+            *l = (*l).max(*r);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn estimate(&self) -> i64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1. + 1.079 / m),
+        };
+        let sum: f64 = self.registers.iter().map(|r| 2f64.powi(-(*r as i32))).sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        // linear counting is more accurate for small cardinalities
+        let est = if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        };
+        est.round() as i64
+    }
+}
+
+fn get_hll_precision(name: &str, args: &[DataValue]) -> Result<u8> {
+    match args.first() {
+        None => Ok(HLL_DEFAULT_PRECISION),
+        Some(arg) => {
+            let p = arg.get_int().ok_or_else(|| {
+                miette!(
+                    "the argument to '{}' must be an integer, got {:?}",
+                    name,
+                    arg
+                )
+            })?;
+            ensure!(
+                (HLL_MIN_PRECISION as i64..=HLL_MAX_PRECISION as i64).contains(&p),
+                "the precision for '{}' must be between {} and {}, got {}",
+                name,
+                HLL_MIN_PRECISION,
+                HLL_MAX_PRECISION,
+                p
+            );
+            Ok(p as u8)
+        }
+    }
+}
+
+define_aggr!(AGGR_APPROX_COUNT_DISTINCT, false);
+
+pub(crate) struct AggrApproxCountDistinct {
+    sketch: HyperLogLog,
+}
+
+impl NormalAggrObj for AggrApproxCountDistinct {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        self.sketch.add(value);
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(DataValue::from(self.sketch.estimate()))
+    }
+}
+
+define_aggr!(AGGR_HLL_SKETCH, false);
+
+pub(crate) struct AggrHllSketch {
+    sketch: HyperLogLog,
+}
+
+impl NormalAggrObj for AggrHllSketch {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        self.sketch.add(value);
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(DataValue::Bytes(self.sketch.to_bytes()))
+    }
+}
+
+define_aggr!(AGGR_HLL_MERGE, false);
+
+#[derive(Default)]
+pub(crate) struct AggrHllMerge {
+    sketch: Option<HyperLogLog>,
+}
+
+impl NormalAggrObj for AggrHllMerge {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        let bytes = value
+            .get_bytes()
+            .ok_or_else(|| miette!("cannot apply 'hll_merge' to {:?}", value))?;
+        let other = HyperLogLog::from_bytes(bytes)?;
+        match &mut self.sketch {
+            None => self.sketch = Some(other),
+            Some(sketch) => sketch.merge(&other)?,
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(match &self.sketch {
+            None => DataValue::Null,
+            Some(sketch) => DataValue::Bytes(sketch.to_bytes()),
+        })
+    }
+}
+
 define_aggr!(AGGR_UNION, true);
 
 #[derive(Default)]
@@ -496,6 +703,90 @@ impl NormalAggrObj for AggrStdDev {
     }
 }
 
+define_aggr!(AGGR_PERCENTILE_CONT, false);
+
+/// The `p`-th percentile interpolated between the two values around it, as SQL's `percentile_cont`.
+/// Nulls are skipped.
+pub(crate) struct AggrPercentileCont {
+    p: f64,
+    values: Vec<f64>,
+}
+
+impl NormalAggrObj for AggrPercentileCont {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        match value {
+            DataValue::Null => {}
+            DataValue::Num(n) => self.values.push(n.get_float()),
+            v => bail!(
+                "cannot compute 'percentile_cont': encountered value {:?}",
+                v
+            ),
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        if self.values.is_empty() {
+            return Ok(DataValue::Null);
+        }
+        let mut sorted = self.values.clone();
+        sorted.sort_by(f64::total_cmp);
+        let pos = self.p * (sorted.len() - 1) as f64;
+        let lower = sorted[pos.floor() as usize];
+        let upper = sorted[pos.ceil() as usize];
+        Ok(DataValue::from(lower + (upper - lower) * pos.fract()))
+    }
+}
+
+define_aggr!(AGGR_PERCENTILE_DISC, false);
+
+/// The first value in sorted order such that at least a fraction `p` of the values are not
+/// greater than it, as SQL's `percentile_disc`. When that fraction is reached exactly by a value,
+/// as for the median of an even number of values, that value is taken rather than the next one.
+/// Nulls are skipped.
+pub(crate) struct AggrPercentileDisc {
+    p: f64,
+    values: Vec<DataValue>,
+}
+
+impl NormalAggrObj for AggrPercentileDisc {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        if *value != DataValue::Null {
+            self.values.push(value.clone());
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        if self.values.is_empty() {
+            return Ok(DataValue::Null);
+        }
+        let mut sorted = self.values.clone();
+        sorted.sort();
+        let idx = ((self.p * sorted.len() as f64).ceil() as usize).max(1) - 1;
+        Ok(sorted[idx].clone())
+    }
+}
+
+/// The fraction given to `percentile_cont` or `percentile_disc`, the median if not given
+fn get_percentile(name: &str, args: &[DataValue]) -> Result<f64> {
+    match args.first() {
+        None => Ok(0.5),
+        Some(arg) => {
+            let p = arg.get_float().ok_or_else(|| {
+                miette!("the argument to '{}' must be a number, got {:?}", name, arg)
+            })?;
+            ensure!(
+                (0. ..=1.).contains(&p),
+                "the percentile for '{}' must be between 0 and 1, got {}",
+                name,
+                p
+            );
+            Ok(p)
+        }
+    }
+}
+
 define_aggr!(AGGR_MEAN, false);
 
 #[derive(Default)]
@@ -718,6 +1009,8 @@ impl MeetAggrObj for MeetAggrMax {
 
 define_aggr!(AGGR_LATEST_BY, false);
 
+/// `latest_by`, also named `max_by`: given `[value, key]`, the value of the first row with
+/// the largest key. Rows whose keys are null are skipped.
 pub(crate) struct AggrLatestBy {
     found: DataValue,
     cost: DataValue,
@@ -758,6 +1051,8 @@ impl NormalAggrObj for AggrLatestBy {
 
 define_aggr!(AGGR_SMALLEST_BY, false);
 
+/// `smallest_by`, also named `min_by`: given `[value, key]`, the value of the first row with
+/// the smallest key. Rows whose keys are null are skipped.
 pub(crate) struct AggrSmallestBy {
     found: DataValue,
     cost: DataValue,
@@ -781,7 +1076,7 @@ impl NormalAggrObj for AggrSmallestBy {
                     "'smallest_by' requires a list of exactly two items as argument"
                 );
                 let c = &l[1];
-                if self.cost == DataValue::Null || *c < self.cost {
+                if *c != DataValue::Null && (self.cost == DataValue::Null || *c < self.cost) {
                     self.cost = c.clone();
                     self.found = l[0].clone();
                 }
@@ -796,6 +1091,87 @@ impl NormalAggrObj for AggrSmallestBy {
     }
 }
 
+define_aggr!(AGGR_FIRST, false);
+define_aggr!(AGGR_LAST, false);
+
+/// `first` and `last`: the value of the first or last row of a group.
+///
+/// Without an order, rows are taken in the order the group receives them. With an order
+/// of `'asc'` or `'desc'`, each value is given as a list `[value, key]` and rows are taken
+/// in that order of their keys: ties keep the order the rows are received in, and rows
+/// whose keys are null are skipped, giving null if all of them are.
+pub(crate) struct AggrFirstLast {
+    last: bool,
+    /// Whether keys are ordered descending, if rows are ordered by keys
+    desc: Option<bool>,
+    found: Option<(DataValue, DataValue)>,
+}
+
+impl NormalAggrObj for AggrFirstLast {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        let name = if self.last { "last" } else { "first" };
+        let Some(desc) = self.desc else {
+            if self.last || self.found.is_none() {
+                self.found = Some((value.clone(), DataValue::Null));
+            }
+            return Ok(());
+        };
+        let (val, key) = match value {
+            DataValue::List(l) if l.len() == 2 => (&l[0], &l[1]),
+            v => bail!(
+                "'{}' with an order requires a list of the value and the key to order by, got {:?}",
+                name,
+                v
+            ),
+        };
+        if *key == DataValue::Null {
+            return Ok(());
+        }
+        let replace = match &self.found {
+            None => true,
+            Some((_, found_key)) => {
+                let ord = if desc {
+                    found_key.cmp(key)
+                } else {
+                    key.cmp(found_key)
+                };
+                if self.last {
+                    ord != Ordering::Less
+                } else {
+                    ord == Ordering::Less
+                }
+            }
+        };
+        if replace {
+            self.found = Some((val.clone(), key.clone()));
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(match &self.found {
+            None => DataValue::Null,
+            Some((val, _)) => val.clone(),
+        })
+    }
+}
+
+/// The order given to `first` or `last`, if any: whether it is descending
+fn get_first_last_order(name: &str, args: &[DataValue]) -> Result<Option<bool>> {
+    match args.first() {
+        None => Ok(None),
+        Some(arg) => match arg.get_str() {
+            Some("asc") => Ok(Some(false)),
+            Some("desc") => Ok(Some(true)),
+            _ => bail!(
+                "the order given to '{}' must be 'asc' or 'desc', got {:?}",
+                name,
+                arg
+            ),
+        },
+    }
+}
+
 define_aggr!(AGGR_MIN_COST, true);
 
 pub(crate) struct AggrMinCost {
@@ -1165,6 +1541,10 @@ pub(crate) fn parse_aggr(name: &str) -> Option<&'static Aggregation> {
         "intersection" => &AGGR_INTERSECTION,
         "count" => &AGGR_COUNT,
         "count_unique" => &AGGR_COUNT_UNIQUE,
+        "count_distinct" => &AGGR_COUNT_DISTINCT,
+        "approx_count_distinct" => &AGGR_APPROX_COUNT_DISTINCT,
+        "hll_sketch" => &AGGR_HLL_SKETCH,
+        "hll_merge" => &AGGR_HLL_MERGE,
         "variance" => &AGGR_VARIANCE,
         "std_dev" => &AGGR_STD_DEV,
         "sum" => &AGGR_SUM,
@@ -1172,6 +1552,8 @@ pub(crate) fn parse_aggr(name: &str) -> Option<&'static Aggregation> {
         "min" => &AGGR_MIN,
         "max" => &AGGR_MAX,
         "mean" => &AGGR_MEAN,
+        "percentile_cont" => &AGGR_PERCENTILE_CONT,
+        "percentile_disc" => &AGGR_PERCENTILE_DISC,
         "choice" => &AGGR_CHOICE,
         "collect" => &AGGR_COLLECT,
         "shortest" => &AGGR_SHORTEST,
@@ -1179,6 +1561,10 @@ pub(crate) fn parse_aggr(name: &str) -> Option<&'static Aggregation> {
         "bit_and" => &AGGR_BIT_AND,
         "bit_or" => &AGGR_BIT_OR,
         "bit_xor" => &AGGR_BIT_XOR,
+        "first" => &AGGR_FIRST,
+        "last" => &AGGR_LAST,
+        "min_by" => &AGGR_SMALLEST_BY,
+        "max_by" => &AGGR_LATEST_BY,
         "latest_by" => &AGGR_LATEST_BY,
         "smallest_by" => &AGGR_SMALLEST_BY,
         "choice_rand" => &AGGR_CHOICE_RAND,
@@ -1187,6 +1573,20 @@ pub(crate) fn parse_aggr(name: &str) -> Option<&'static Aggregation> {
 }
 
 impl Aggregation {
+    /// Whether the aggregation keeps the values it is given, rather than a summary of them.
+    pub(crate) fn retains_values(&self) -> bool {
+        [
+            AGGR_COLLECT.name,
+            AGGR_UNIQUE.name,
+            AGGR_GROUP_COUNT.name,
+            AGGR_COUNT_UNIQUE.name,
+            AGGR_COUNT_DISTINCT.name,
+            AGGR_UNION.name,
+            AGGR_PERCENTILE_CONT.name,
+            AGGR_PERCENTILE_DISC.name,
+        ]
+        .contains(&self.name)
+    }
     pub(crate) fn meet_init(&mut self, _args: &[DataValue]) -> Result<()> {
         self.meet_op.replace(match self.name {
             name if name == AGGR_AND.name => Box::new(MeetAggrAnd),
@@ -1212,6 +1612,14 @@ impl Aggregation {
             name if name == AGGR_COUNT.name => Box::new(AggrCount::default()),
             name if name == AGGR_GROUP_COUNT.name => Box::new(AggrGroupCount::default()),
             name if name == AGGR_COUNT_UNIQUE.name => Box::new(AggrCountUnique::default()),
+            name if name == AGGR_COUNT_DISTINCT.name => Box::new(AggrCountDistinct::default()),
+            name if name == AGGR_APPROX_COUNT_DISTINCT.name => Box::new(AggrApproxCountDistinct {
+                sketch: HyperLogLog::new(get_hll_precision("approx_count_distinct", args)?)?,
+            }),
+            name if name == AGGR_HLL_SKETCH.name => Box::new(AggrHllSketch {
+                sketch: HyperLogLog::new(get_hll_precision("hll_sketch", args)?)?,
+            }),
+            name if name == AGGR_HLL_MERGE.name => Box::new(AggrHllMerge::default()),
             name if name == AGGR_SUM.name => Box::new(AggrSum::default()),
             name if name == AGGR_PRODUCT.name => Box::new(AggrProduct::default()),
             name if name == AGGR_MIN.name => Box::new(AggrMin::default()),
@@ -1219,6 +1627,14 @@ impl Aggregation {
             name if name == AGGR_MEAN.name => Box::new(AggrMean::default()),
             name if name == AGGR_VARIANCE.name => Box::new(AggrVariance::default()),
             name if name == AGGR_STD_DEV.name => Box::new(AggrStdDev::default()),
+            name if name == AGGR_PERCENTILE_CONT.name => Box::new(AggrPercentileCont {
+                p: get_percentile("percentile_cont", args)?,
+                values: vec![],
+            }),
+            name if name == AGGR_PERCENTILE_DISC.name => Box::new(AggrPercentileDisc {
+                p: get_percentile("percentile_disc", args)?,
+                values: vec![],
+            }),
             name if name == AGGR_CHOICE.name => Box::new(AggrChoice::default()),
             name if name == AGGR_BIT_AND.name => Box::new(AggrBitAnd::default()),
             name if name == AGGR_BIT_OR.name => Box::new(AggrBitOr::default()),
@@ -1228,8 +1644,30 @@ impl Aggregation {
             name if name == AGGR_INTERSECTION.name => Box::new(AggrIntersection::default()),
             name if name == AGGR_SHORTEST.name => Box::new(AggrShortest::default()),
             name if name == AGGR_MIN_COST.name => Box::new(AggrMinCost::default()),
-            name if name == AGGR_LATEST_BY.name => Box::new(AggrLatestBy::default()),
-            name if name == AGGR_SMALLEST_BY.name => Box::new(AggrSmallestBy::default()),
+            name if name == AGGR_FIRST.name => Box::new(AggrFirstLast {
+                last: false,
+                desc: get_first_last_order("first", args)?,
+                found: None,
+            }),
+            name if name == AGGR_LAST.name => Box::new(AggrFirstLast {
+                last: true,
+                desc: get_first_last_order("last", args)?,
+                found: None,
+            }),
+            name if name == AGGR_LATEST_BY.name => {
+                ensure!(
+                    args.is_empty(),
+                    "'latest_by' and 'max_by' take no arguments besides the aggregated value"
+                );
+                Box::new(AggrLatestBy::default())
+            }
+            name if name == AGGR_SMALLEST_BY.name => {
+                ensure!(
+                    args.is_empty(),
+                    "'smallest_by' and 'min_by' take no arguments besides the aggregated value"
+                );
+                Box::new(AggrSmallestBy::default())
+            }
             name if name == AGGR_CHOICE_RAND.name => Box::new(AggrChoiceRand::default()),
             name if name == AGGR_COLLECT.name => Box::new({
                 if args.is_empty() {
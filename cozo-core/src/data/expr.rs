@@ -12,6 +12,7 @@ use std::fmt::{Debug, Display, Formatter};
 use std::mem;
 
 use itertools::Itertools;
+use log::warn;
 use miette::{bail, miette, Diagnostic, Result};
 use serde::de::{Error, Visitor};
 use serde::{Deserializer, Serializer};
@@ -78,6 +79,9 @@ pub fn eval_bytecode_pred(
 ) -> Result<bool> {
     match eval_bytecode(bytecodes, bindings, stack)? {
         DataValue::Bool(b) => Ok(b),
+        // unknown, as from `in` over a list with nulls, does not let the row through,
+        // just as an unknown condition of `if` or `cond` is not taken
+        DataValue::Null => Ok(false),
         v => bail!(PredicateTypeError(span, v)),
     }
 }
@@ -136,10 +140,11 @@ pub fn eval_bytecode(
                 pointer += 1;
             }
             Bytecode::JumpIfFalse { jump_to, span } => {
-                let val = stack.pop().unwrap();
-                let cond = val
-                    .get_bool()
-                    .ok_or_else(|| PredicateTypeError(*span, val))?;
+                let cond = match stack.pop().unwrap() {
+                    DataValue::Bool(b) => b,
+                    DataValue::Null => false,
+                    val => bail!(PredicateTypeError(*span, val)),
+                };
                 if cond {
                     pointer += 1;
                 } else {
@@ -313,6 +318,54 @@ impl Expr {
             span,
         }
     }
+    /// Lowers `x between lower and upper`, and `x in lower..upper` if not `inclusive`,
+    /// into the pair of comparisons the scans of stored relations know how to narrow down with.
+    pub(crate) fn build_between(
+        x: Expr,
+        lower: Expr,
+        upper: Expr,
+        inclusive: bool,
+        span: SourceSpan,
+    ) -> Self {
+        if let (Some(l), Some(u)) = (lower.get_const(), upper.get_const()) {
+            if l > u {
+                warn!("the range from {l} to {u} is reversed, so nothing is in it");
+            }
+        }
+        let upper_op = if inclusive { &OP_LE } else { &OP_LT };
+        Expr::build_and(
+            vec![
+                Expr::Apply {
+                    op: &OP_GE,
+                    args: [x.clone(), lower].into(),
+                    span,
+                },
+                Expr::Apply {
+                    op: upper_op,
+                    args: [x, upper].into(),
+                    span,
+                },
+            ],
+            span,
+        )
+    }
+    /// Lowers `x in a..b` and `x in a..=b` with [`Expr::build_between`], if `range` is such a literal.
+    pub(crate) fn build_in_range(x: Expr, range: &Expr, span: SourceSpan) -> Option<Self> {
+        match range {
+            Expr::Apply { op, args, .. }
+                if op.name == OP_RANGE.name || op.name == OP_RANGE_INCLUSIVE.name =>
+            {
+                Some(Expr::build_between(
+                    x,
+                    args[0].clone(),
+                    args[1].clone(),
+                    op.name == OP_RANGE_INCLUSIVE.name,
+                    span,
+                ))
+            }
+            _ => None,
+        }
+    }
     pub(crate) fn negate(self, span: SourceSpan) -> Self {
         Expr::Apply {
             op: &OP_NEGATE,
@@ -330,6 +383,15 @@ impl Expr {
         &mut self,
         binding_map: &BTreeMap<Symbol, usize>,
     ) -> Result<()> {
+        // `a.b` refers to a binding of its own if there is one, such as `old.b` in updates
+        if let Some(var) = self.get_dotted_binding() {
+            if binding_map.contains_key(&var) {
+                *self = Expr::Binding {
+                    var,
+                    tuple_pos: None,
+                };
+            }
+        }
         match self {
             Expr::Binding { var, tuple_pos, .. } => {
                 #[derive(Debug, Error, Diagnostic)]
@@ -360,6 +422,19 @@ impl Expr {
         }
         Ok(())
     }
+    fn get_dotted_binding(&self) -> Option<Symbol> {
+        match self {
+            Expr::Apply { op, args, span } if op.name == OP_ACCESS.name => {
+                let field = args[1].get_const()?.get_str()?;
+                let base = match &args[0] {
+                    Expr::Binding { var, .. } => var.name.to_string(),
+                    expr => expr.get_dotted_binding()?.name.to_string(),
+                };
+                Some(Symbol::new(format!("{base}.{field}"), *span))
+            }
+            _ => None,
+        }
+    }
     #[allow(dead_code)]
     pub(crate) fn binding_indices(&self) -> Result<BTreeSet<usize>> {
         let mut ret = BTreeSet::default();
@@ -406,13 +481,15 @@ impl Expr {
         self.partial_eval()?;
         match self {
             Expr::Const { val, .. } => Ok(val),
+            // non-deterministic functions are left unfolded, but still evaluate here
+            _ if self.bindings()?.is_empty() => self.eval(vec![]),
             _ => bail!(NotConstError),
         }
     }
     pub(crate) fn partial_eval(&mut self) -> Result<()> {
-        if let Expr::Apply { args, span, .. } = self {
+        if let Expr::Apply { op, args, span } = self {
             let span = *span;
-            let mut all_evaluated = true;
+            let mut all_evaluated = op.deterministic;
             for arg in args.iter_mut() {
                 arg.partial_eval()?;
                 all_evaluated = all_evaluated && matches!(arg, Expr::Const { .. });
@@ -473,6 +550,28 @@ impl Expr {
         }
         Ok(())
     }
+    /// Replaces every use of the binding `from` with `to`.
+    pub(crate) fn rename_binding(&mut self, from: &Symbol, to: &Symbol) {
+        match self {
+            Expr::Binding { var, .. } => {
+                if var == from {
+                    *var = to.clone();
+                }
+            }
+            Expr::Const { .. } => {}
+            Expr::Apply { args, .. } | Expr::UnboundApply { args, .. } => {
+                for arg in args.iter_mut() {
+                    arg.rename_binding(from, to);
+                }
+            }
+            Expr::Cond { clauses, .. } => {
+                for (cond, val) in clauses {
+                    cond.rename_binding(from, to);
+                    val.rename_binding(from, to);
+                }
+            }
+        }
+    }
     pub(crate) fn eval(&self, bindings: impl AsRef<[DataValue]>) -> Result<DataValue> {
         match self {
             Expr::Binding { var, tuple_pos, .. } => match tuple_pos {
@@ -503,10 +602,11 @@ impl Expr {
             }
             Expr::Cond { clauses, .. } => {
                 for (cond, val) in clauses {
-                    let cond_val = cond.eval(bindings.as_ref())?;
-                    let cond_val = cond_val
-                        .get_bool()
-                        .ok_or_else(|| PredicateTypeError(cond.span(), cond_val))?;
+                    let cond_val = match cond.eval(bindings.as_ref())? {
+                        DataValue::Bool(b) => b,
+                        DataValue::Null => false,
+                        val => bail!(PredicateTypeError(cond.span(), val)),
+                    };
 
                     if cond_val {
                         return val.eval(bindings.as_ref());
@@ -523,6 +623,29 @@ impl Expr {
         Ok(match self {
             Expr::Binding { .. } | Expr::Const { .. } | Expr::Cond { .. } => ValueRange::default(),
             Expr::Apply { op, args, .. } => match op.name {
+                n if n == OP_EQ.name => {
+                    let (symb, val) = match (args[0].get_binding(), args[1].get_const()) {
+                        (Some(symb), Some(val)) => (symb, val),
+                        _ => match (args[1].get_binding(), args[0].get_const()) {
+                            (Some(symb), Some(val)) => (symb, val),
+                            _ => return Ok(ValueRange::default()),
+                        },
+                    };
+                    if target != symb {
+                        return Ok(ValueRange::default());
+                    }
+                    // integers sort before floats of equal value, so widen the range
+                    // to cover both representations
+                    let lower = match val.get_int() {
+                        Some(i) => DataValue::from(i),
+                        None => val.clone(),
+                    };
+                    let upper = match val.get_float() {
+                        Some(f) => DataValue::from(f),
+                        None => val.clone(),
+                    };
+                    ValueRange::new(lower, upper)
+                }
                 n if n == OP_GE.name || n == OP_GT.name => {
                     if let Some(symb) = args[0].get_binding() {
                         if let Some(val) = args[1].get_const() {
@@ -729,6 +852,9 @@ pub struct Op {
     pub(crate) name: &'static str,
     pub(crate) min_arity: usize,
     pub(crate) vararg: bool,
+    /// Unset for functions such as `rand_float` that may give different results
+    /// for the same arguments, which are never evaluated ahead of time.
+    pub(crate) deterministic: bool,
     pub(crate) inner: fn(&[DataValue]) -> Result<DataValue>,
 }
 
@@ -800,7 +926,11 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "remove_json_path" => &OP_REMOVE_JSON_PATH,
         "parse_json" => &OP_PARSE_JSON,
         "dump_json" => &OP_DUMP_JSON,
+        "to_json" => &OP_TO_JSON,
+        "from_json" => &OP_FROM_JSON,
         "json_object" => &OP_JSON_OBJECT,
+        "json_merge" => &OP_JSON_MERGE,
+        "json_override" => &OP_JSON_OVERRIDE,
         "is_json" => &OP_IS_JSON,
         "json_to_scalar" => &OP_JSON_TO_SCALAR,
         "add" => &OP_ADD,
@@ -868,6 +998,8 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "is_list" => &OP_IS_LIST,
         "is_bytes" => &OP_IS_BYTES,
         "is_in" => &OP_IS_IN,
+        "in" => &OP_IN,
+        "not_in" => &OP_NOT_IN,
         "is_finite" => &OP_IS_FINITE,
         "is_infinite" => &OP_IS_INFINITE,
         "is_nan" => &OP_IS_NAN,
@@ -897,6 +1029,7 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "t2s" => &OP_T2S,
         "encode_base64" => &OP_ENCODE_BASE64,
         "decode_base64" => &OP_DECODE_BASE64,
+        "hll_estimate" => &OP_HLL_ESTIMATE,
         "first" => &OP_FIRST,
         "last" => &OP_LAST,
         "chunks" => &OP_CHUNKS,
@@ -905,12 +1038,16 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "to_int" => &OP_TO_INT,
         "to_float" => &OP_TO_FLOAT,
         "to_string" => &OP_TO_STRING,
+        "cast" => &OP_CAST,
+        "try_cast" => &OP_TRY_CAST,
         "l2_dist" => &OP_L2_DIST,
         "l2_normalize" => &OP_L2_NORMALIZE,
         "ip_dist" => &OP_IP_DIST,
         "cos_dist" => &OP_COS_DIST,
         "int_range" => &OP_INT_RANGE,
         "rand_float" => &OP_RAND_FLOAT,
+        "random" => &OP_RANDOM,
+        "random_int" => &OP_RANDOM_INT,
         "rand_bernoulli" => &OP_RAND_BERNOULLI,
         "rand_int" => &OP_RAND_INT,
         "rand_choose" => &OP_RAND_CHOOSE,
@@ -6,6 +6,7 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::cell::RefCell;
 use std::cmp::Reverse;
 use std::collections::BTreeSet;
 use std::mem;
@@ -27,6 +28,7 @@ use smartstring::SmartString;
 use unicode_normalization::UnicodeNormalization;
 use uuid::v1::Timestamp;
 
+use crate::data::aggr::HyperLogLog;
 use crate::data::expr::Op;
 use crate::data::json::JsonValue;
 use crate::data::relation::VecElementType;
@@ -36,15 +38,50 @@ use crate::data::value::{
 
 macro_rules! define_op {
     ($name:ident, $min_arity:expr, $vararg:expr) => {
+        define_op!($name, $min_arity, $vararg, true);
+    };
+    ($name:ident, $min_arity:expr, $vararg:expr, $deterministic:expr) => {
         pub(crate) const $name: Op = Op {
             name: stringify!($name),
             min_arity: $min_arity,
             vararg: $vararg,
+            deterministic: $deterministic,
             inner: ::casey::lower!($name),
         };
     };
 }
 
+thread_local! {
+    /// The random generator of the session running a script on this thread,
+    /// present only if the session has been given a seed.
+    static SEEDED_RNG: RefCell<Option<StdRng>> = const { RefCell::new(None) };
+}
+
+/// Makes `rng` the source of the random functions evaluated by `f` on this thread.
+pub(crate) fn with_seeded_rng<T>(rng: &mut Option<StdRng>, f: impl FnOnce() -> T) -> T {
+    if rng.is_none() {
+        return f();
+    }
+    SEEDED_RNG.with(|cell| *cell.borrow_mut() = rng.take());
+    let ret = f();
+    *rng = SEEDED_RNG.with(|cell| cell.borrow_mut().take());
+    ret
+}
+
+/// Whether the random functions currently draw from a seeded generator,
+/// in which case they must be evaluated in a fixed order.
+#[cfg(all(feature = "rayon", not(target_arch = "wasm32")))]
+pub(crate) fn has_seeded_rng() -> bool {
+    SEEDED_RNG.with(|cell| cell.borrow().is_some())
+}
+
+fn with_rng<T>(f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+    SEEDED_RNG.with(|cell| match cell.borrow_mut().as_mut() {
+        Some(rng) => f(rng),
+        None => f(&mut thread_rng()),
+    })
+}
+
 fn ensure_same_value_type(a: &DataValue, b: &DataValue) -> Result<()> {
     use DataValue::*;
     if !matches!(
@@ -197,6 +234,57 @@ pub(crate) fn op_json_object(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::Json(JsonData(Value::Object(obj))))
 }
 
+define_op!(OP_JSON_MERGE, 0, true);
+/// Merges JSON objects from left to right, as `{...a, ...b}` does: nested objects
+/// are merged key by key, and any other value is replaced. Nulls count as empty.
+pub(crate) fn op_json_merge(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::Json(JsonData(Value::Object(merge_json_args(
+        args,
+        "json_merge",
+    )?))))
+}
+
+define_op!(OP_JSON_OVERRIDE, 1, true);
+/// Merges all but the last argument as `json_merge` does, and then sets the keys of the
+/// last object, replacing what was there. This is how `{...a, ...b, k: v}` is built.
+pub(crate) fn op_json_override(args: &[DataValue]) -> Result<DataValue> {
+    let mut ret = merge_json_args(&args[..args.len() - 1], "json_override")?;
+    for (k, v) in merge_json_args(&args[args.len() - 1..], "json_override")? {
+        ret.insert(k, v);
+    }
+    Ok(DataValue::Json(JsonData(Value::Object(ret))))
+}
+
+fn merge_json_args(args: &[DataValue], op: &str) -> Result<serde_json::Map<String, JsonValue>> {
+    let mut ret = serde_json::Map::new();
+    for arg in args {
+        match arg {
+            DataValue::Null => {}
+            DataValue::Json(JsonData(Value::Object(obj))) => {
+                merge_json_object(&mut ret, obj);
+            }
+            _ => bail!("'{op}' requires JSON objects"),
+        }
+    }
+    Ok(ret)
+}
+
+fn merge_json_object(
+    target: &mut serde_json::Map<String, JsonValue>,
+    source: &serde_json::Map<String, JsonValue>,
+) {
+    for (k, v) in source {
+        match (target.get_mut(k), v) {
+            (Some(Value::Object(existing)), Value::Object(new)) => {
+                merge_json_object(existing, new);
+            }
+            _ => {
+                target.insert(k.clone(), v.clone());
+            }
+        }
+    }
+}
+
 fn to_json(d: &DataValue) -> JsonValue {
     match d {
         DataValue::Null => {
@@ -284,6 +372,41 @@ pub(crate) fn op_dump_json(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
+define_op!(OP_TO_JSON, 1, false);
+/// Serializes any value to a JSON string: lists, sets and vectors become arrays, and
+/// bytes and UUIDs arrays of bytes.
+pub(crate) fn op_to_json(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::Str(to_json(&args[0]).to_string().into()))
+}
+
+define_op!(OP_FROM_JSON, 1, true);
+/// Parses a JSON string into a value: arrays become lists, and objects JSON values.
+/// Invalid JSON is an error, unless the optional second argument is `true`, in
+/// which case it gives null.
+pub(crate) fn op_from_json(args: &[DataValue]) -> Result<DataValue> {
+    ensure!(args.len() <= 2, "'from_json' takes one or two arguments");
+    let null_on_error = match args.get(1) {
+        None => false,
+        Some(DataValue::Bool(b)) => *b,
+        Some(_) => bail!("the second argument of 'from_json' must be a boolean"),
+    };
+    let s = args[0]
+        .get_str()
+        .ok_or_else(|| miette!("'from_json' requires a string argument"))?;
+    match serde_json::from_str(s) {
+        Ok(value) => Ok(json_to_value(value)),
+        Err(_) if null_on_error => Ok(DataValue::Null),
+        Err(err) => bail!("'from_json' cannot parse {s:?}: {err}"),
+    }
+}
+
+fn json_to_value(value: JsonValue) -> DataValue {
+    match value {
+        JsonValue::Array(arr) => DataValue::List(arr.into_iter().map(json_to_value).collect()),
+        v => json2val(v),
+    }
+}
+
 define_op!(OP_COALESCE, 0, true);
 pub(crate) fn op_coalesce(args: &[DataValue]) -> Result<DataValue> {
     for val in args {
@@ -295,6 +418,10 @@ pub(crate) fn op_coalesce(args: &[DataValue]) -> Result<DataValue> {
 }
 
 define_op!(OP_EQ, 2, false);
+/// Unlike in SQL, `null == null` is true, so `==` (and its alias `<=>`) is null-safe,
+/// as are joins on shared variables. Only `in` and `not in` follow three-valued logic.
+/// Integers and floats are compared by value, so `100 == 100.0`, whereas joins and keys
+/// tell them apart.
 pub(crate) fn op_eq(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(f)), DataValue::Num(Num::Int(i)))
@@ -330,6 +457,42 @@ pub(crate) fn op_is_in(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(right.contains(left)))
 }
 
+define_op!(OP_IN, 2, false);
+/// The `in` operator, which unlike `is_in` follows three-valued logic:
+/// the result is null when nothing matches and either side has a null in it.
+pub(crate) fn op_in(args: &[DataValue]) -> Result<DataValue> {
+    let right = args[1]
+        .get_slice()
+        .ok_or_else(|| miette!("right hand side of 'in' must be a list"))?;
+    if right.is_empty() {
+        return Ok(DataValue::from(false));
+    }
+    if args[0] == DataValue::Null {
+        return Ok(DataValue::Null);
+    }
+    let mut has_null = false;
+    for item in right {
+        if *item == DataValue::Null {
+            has_null = true;
+        } else if op_eq(&[args[0].clone(), item.clone()])? == DataValue::from(true) {
+            return Ok(DataValue::from(true));
+        }
+    }
+    Ok(if has_null {
+        DataValue::Null
+    } else {
+        DataValue::from(false)
+    })
+}
+
+define_op!(OP_NOT_IN, 2, false);
+pub(crate) fn op_not_in(args: &[DataValue]) -> Result<DataValue> {
+    Ok(match op_in(args)? {
+        DataValue::Bool(b) => DataValue::from(!b),
+        v => v,
+    })
+}
+
 define_op!(OP_NEQ, 2, false);
 pub(crate) fn op_neq(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
@@ -1089,37 +1252,50 @@ pub(crate) fn op_mod(args: &[DataValue]) -> Result<DataValue> {
 }
 
 define_op!(OP_AND, 0, true);
+/// Follows three-valued logic: false if any argument is false, otherwise null if any is null.
 pub(crate) fn op_and(args: &[DataValue]) -> Result<DataValue> {
+    let mut has_null = false;
     for arg in args {
-        if !arg
-            .get_bool()
-            .ok_or_else(|| miette!("'and' requires booleans"))?
-        {
-            return Ok(DataValue::from(false));
+        match arg {
+            DataValue::Bool(false) => return Ok(DataValue::from(false)),
+            DataValue::Bool(true) => {}
+            DataValue::Null => has_null = true,
+            _ => bail!("'and' requires booleans"),
         }
     }
-    Ok(DataValue::from(true))
+    Ok(if has_null {
+        DataValue::Null
+    } else {
+        DataValue::from(true)
+    })
 }
 
 define_op!(OP_OR, 0, true);
+/// Follows three-valued logic: true if any argument is true, otherwise null if any is null.
 pub(crate) fn op_or(args: &[DataValue]) -> Result<DataValue> {
+    let mut has_null = false;
     for arg in args {
-        if arg
-            .get_bool()
-            .ok_or_else(|| miette!("'or' requires booleans"))?
-        {
-            return Ok(DataValue::from(true));
+        match arg {
+            DataValue::Bool(true) => return Ok(DataValue::from(true)),
+            DataValue::Bool(false) => {}
+            DataValue::Null => has_null = true,
+            _ => bail!("'or' requires booleans"),
         }
     }
-    Ok(DataValue::from(false))
+    Ok(if has_null {
+        DataValue::Null
+    } else {
+        DataValue::from(false)
+    })
 }
 
 define_op!(OP_NEGATE, 1, false);
+/// The negation of null is null.
 pub(crate) fn op_negate(args: &[DataValue]) -> Result<DataValue> {
-    if let DataValue::Bool(b) = &args[0] {
-        Ok(DataValue::from(!*b))
-    } else {
-        bail!("'negate' requires booleans");
+    match &args[0] {
+        DataValue::Bool(b) => Ok(DataValue::from(!*b)),
+        DataValue::Null => Ok(DataValue::Null),
+        _ => bail!("'negate' requires booleans"),
     }
 }
 
@@ -1812,6 +1988,81 @@ pub(crate) fn op_maybe_get(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
+define_op!(OP_ACCESS, 2, false);
+/// Implements `x.field` and `x[key]`: missing keys and indices, as well as accessing
+/// anything in null, give null, but accessing into other non-container values is an error.
+pub(crate) fn op_access(args: &[DataValue]) -> Result<DataValue> {
+    Ok(match (&args[0], &args[1]) {
+        (DataValue::Null, _) => DataValue::Null,
+        (DataValue::List(l), DataValue::Num(n)) => {
+            let n = n
+                .get_int()
+                .ok_or_else(|| miette!("list index must be an integer, got {}", n))?;
+            match get_index(n, l.len(), false) {
+                Ok(idx) => l[idx].clone(),
+                Err(_) => DataValue::Null,
+            }
+        }
+        (DataValue::Json(JsonData(json)), DataValue::Str(s)) => match json {
+            Value::Object(obj) => obj
+                .get(s as &str)
+                .cloned()
+                .map_or(DataValue::Null, json2val),
+            Value::Null => DataValue::Null,
+            v => bail!("cannot access field '{}' of JSON value {}", s, v),
+        },
+        (DataValue::Json(JsonData(json)), DataValue::Num(n)) => match json {
+            Value::Array(arr) => {
+                let n = n
+                    .get_int()
+                    .ok_or_else(|| miette!("array index must be an integer, got {}", n))?;
+                match get_index(n, arr.len(), false) {
+                    Ok(idx) => json2val(arr[idx].clone()),
+                    Err(_) => DataValue::Null,
+                }
+            }
+            Value::Null => DataValue::Null,
+            v => bail!("cannot index into JSON value {}", v),
+        },
+        (v, k) => bail!("cannot access {} in {}", k, v),
+    })
+}
+
+define_op!(OP_ACCESS_SLICE, 3, false);
+/// Implements `x[from..to]`, where either bound may be null for the start or end,
+/// and bounds out of range are clamped.
+pub(crate) fn op_access_slice(args: &[DataValue]) -> Result<DataValue> {
+    fn clamp(bound: &DataValue, len: usize, default: usize) -> Result<usize> {
+        Ok(match bound {
+            DataValue::Null => default,
+            v => {
+                let i = v
+                    .get_int()
+                    .ok_or_else(|| miette!("slice bounds must be integers, got {}", v))?;
+                let i = if i < 0 { i + len as i64 } else { i };
+                i.clamp(0, len as i64) as usize
+            }
+        })
+    }
+
+    Ok(match &args[0] {
+        DataValue::Null => DataValue::Null,
+        DataValue::List(l) => {
+            let from = clamp(&args[1], l.len(), 0)?;
+            let to = clamp(&args[2], l.len(), l.len())?;
+            DataValue::List(l.get(from..to).unwrap_or_default().to_vec())
+        }
+        DataValue::Json(JsonData(Value::Array(arr))) => {
+            let from = clamp(&args[1], arr.len(), 0)?;
+            let to = clamp(&args[2], arr.len(), arr.len())?;
+            DataValue::Json(JsonData(Value::Array(
+                arr.get(from..to).unwrap_or_default().to_vec(),
+            )))
+        }
+        v => bail!("cannot slice {}", v),
+    })
+}
+
 define_op!(OP_SLICE, 3, false);
 pub(crate) fn op_slice(args: &[DataValue]) -> Result<DataValue> {
     let l = args[0]
@@ -1916,6 +2167,14 @@ pub(crate) fn op_decode_base64(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
+define_op!(OP_HLL_ESTIMATE, 1, false);
+pub(crate) fn op_hll_estimate(args: &[DataValue]) -> Result<DataValue> {
+    match &args[0] {
+        DataValue::Bytes(b) => Ok(DataValue::from(HyperLogLog::from_bytes(b)?.estimate())),
+        _ => bail!("'hll_estimate' requires a sketch produced by 'hll_sketch' or 'hll_merge'"),
+    }
+}
+
 define_op!(OP_TO_BOOL, 1, false);
 pub(crate) fn op_to_bool(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match &args[0] {
@@ -2027,6 +2286,81 @@ fn val2str(arg: &DataValue) -> String {
     }
 }
 
+define_op!(OP_CAST, 2, false);
+/// `cast(x as t)`: converts `x` to the type named by `t`, failing where that cannot be done.
+/// Null stays null. Strings are parsed for `int`, `float`, `bool` and `uuid`, floats are
+/// truncated towards zero for `int`, booleans become 0 and 1 for the numeric types, numbers
+/// are true unless zero for `bool`, and any value is written out for `string` and `json`.
+pub(crate) fn op_cast(args: &[DataValue]) -> Result<DataValue> {
+    let typ = args[1]
+        .get_str()
+        .ok_or_else(|| miette!("'cast' requires a type name"))?;
+    cast_value(&args[0], typ)
+}
+
+define_op!(OP_TRY_CAST, 2, false);
+/// `try_cast(x as t)`: like `cast`, but gives null where the conversion fails.
+pub(crate) fn op_try_cast(args: &[DataValue]) -> Result<DataValue> {
+    let typ = args[1]
+        .get_str()
+        .ok_or_else(|| miette!("'try_cast' requires a type name"))?;
+    ensure!(
+        CAST_TYPES.contains(&typ.to_ascii_lowercase().as_str()),
+        "cannot cast to unknown type '{typ}'"
+    );
+    Ok(cast_value(&args[0], typ).unwrap_or(DataValue::Null))
+}
+
+/// The type names accepted by `cast` and `try_cast`, matched case-insensitively.
+pub(crate) const CAST_TYPES: &[&str] = &["int", "float", "string", "bool", "uuid", "json"];
+
+fn cast_value(val: &DataValue, typ: &str) -> Result<DataValue> {
+    if *val == DataValue::Null {
+        return Ok(DataValue::Null);
+    }
+    let fail = || miette!("cannot cast {:?} to {}", val, typ);
+    Ok(match typ.to_ascii_lowercase().as_str() {
+        "int" => match val {
+            DataValue::Num(Num::Int(i)) => DataValue::from(*i),
+            DataValue::Num(Num::Float(f)) => {
+                // `as` would saturate out-of-range floats instead of failing
+                let t = f.trunc();
+                ensure!(t >= i64::MIN as f64 && t < -(i64::MIN as f64), fail());
+                DataValue::from(t as i64)
+            }
+            DataValue::Bool(b) => DataValue::from(*b as i64),
+            DataValue::Str(s) => DataValue::from(i64::from_str(s.trim()).map_err(|_| fail())?),
+            _ => bail!(fail()),
+        },
+        "float" => match val {
+            DataValue::Num(n) => DataValue::from(n.get_float()),
+            DataValue::Bool(b) => DataValue::from(*b as i64 as f64),
+            DataValue::Str(s) => DataValue::from(f64::from_str(s.trim()).map_err(|_| fail())?),
+            _ => bail!(fail()),
+        },
+        "string" => DataValue::Str(val2str(val).into()),
+        "bool" => match val {
+            DataValue::Bool(b) => DataValue::from(*b),
+            DataValue::Num(n) => DataValue::from(n.get_float() != 0.),
+            DataValue::Str(s) => match s.trim() {
+                "true" => DataValue::from(true),
+                "false" => DataValue::from(false),
+                _ => bail!(fail()),
+            },
+            _ => bail!(fail()),
+        },
+        "uuid" => match val {
+            DataValue::Uuid(_) => val.clone(),
+            DataValue::Str(s) => {
+                DataValue::uuid(uuid::Uuid::try_parse(s.trim()).map_err(|_| fail())?)
+            }
+            _ => bail!(fail()),
+        },
+        "json" => DataValue::Json(JsonData(to_json(val))),
+        _ => bail!("cannot cast to unknown type '{typ}'"),
+    })
+}
+
 define_op!(OP_VEC, 1, true);
 pub(crate) fn op_vec(args: &[DataValue]) -> Result<DataValue> {
     let t = match args.get(1) {
@@ -2131,7 +2465,7 @@ pub(crate) fn op_vec(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_RAND_VEC, 1, true);
+define_op!(OP_RAND_VEC, 1, true, false);
 pub(crate) fn op_rand_vec(args: &[DataValue]) -> Result<DataValue> {
     let len = args[0]
         .get_int()
@@ -2146,8 +2480,7 @@ pub(crate) fn op_rand_vec(args: &[DataValue]) -> Result<DataValue> {
         _ => bail!("'vec' requires a string as second argument"),
     };
 
-    let mut rng = thread_rng();
-    match t {
+    with_rng(|rng| match t {
         VecElementType::F32 => {
             let mut res_arr = ndarray::Array1::zeros(len);
             for mut row in res_arr.axis_iter_mut(ndarray::Axis(0)) {
@@ -2162,7 +2495,7 @@ pub(crate) fn op_rand_vec(args: &[DataValue]) -> Result<DataValue> {
             }
             Ok(DataValue::Vec(Vector::F64(res_arr)))
         }
-    }
+    })
 }
 
 define_op!(OP_L2_NORMALIZE, 1, false);
@@ -2302,12 +2635,43 @@ pub(crate) fn op_int_range(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::List((start..end).map(DataValue::from).collect()))
 }
 
-define_op!(OP_RAND_FLOAT, 0, false);
+define_op!(OP_RANGE, 2, false);
+/// The range literal `a..b`, which outside of `in` is the list of integers in it.
+pub(crate) fn op_range(args: &[DataValue]) -> Result<DataValue> {
+    let start = args[0]
+        .get_int()
+        .ok_or_else(|| miette!("range literals require integer bounds"))?;
+    let end = args[1]
+        .get_int()
+        .ok_or_else(|| miette!("range literals require integer bounds"))?;
+    Ok(DataValue::List((start..end).map(DataValue::from).collect()))
+}
+
+define_op!(OP_RANGE_INCLUSIVE, 2, false);
+/// The range literal `a..=b`.
+pub(crate) fn op_range_inclusive(args: &[DataValue]) -> Result<DataValue> {
+    let start = args[0]
+        .get_int()
+        .ok_or_else(|| miette!("range literals require integer bounds"))?;
+    let end = args[1]
+        .get_int()
+        .ok_or_else(|| miette!("range literals require integer bounds"))?;
+    Ok(DataValue::List(
+        (start..=end).map(DataValue::from).collect(),
+    ))
+}
+
+define_op!(OP_RAND_FLOAT, 0, false, false);
 pub(crate) fn op_rand_float(_args: &[DataValue]) -> Result<DataValue> {
-    Ok(thread_rng().gen::<f64>().into())
+    Ok(with_rng(|rng| rng.gen::<f64>()).into())
 }
 
-define_op!(OP_RAND_BERNOULLI, 1, false);
+define_op!(OP_RANDOM, 0, false, false);
+pub(crate) fn op_random(args: &[DataValue]) -> Result<DataValue> {
+    op_rand_float(args)
+}
+
+define_op!(OP_RAND_BERNOULLI, 1, false, false);
 pub(crate) fn op_rand_bernoulli(args: &[DataValue]) -> Result<DataValue> {
     let prob = match &args[0] {
         DataValue::Num(n) => {
@@ -2320,10 +2684,10 @@ pub(crate) fn op_rand_bernoulli(args: &[DataValue]) -> Result<DataValue> {
         }
         _ => bail!("'rand_bernoulli' requires number between 0. and 1."),
     };
-    Ok(DataValue::from(thread_rng().gen_bool(prob)))
+    Ok(DataValue::from(with_rng(|rng| rng.gen_bool(prob))))
 }
 
-define_op!(OP_RAND_INT, 2, false);
+define_op!(OP_RAND_INT, 2, false, false);
 pub(crate) fn op_rand_int(args: &[DataValue]) -> Result<DataValue> {
     let lower = &args[0]
         .get_int()
@@ -2331,23 +2695,26 @@ pub(crate) fn op_rand_int(args: &[DataValue]) -> Result<DataValue> {
     let upper = &args[1]
         .get_int()
         .ok_or_else(|| miette!("'rand_int' requires integers"))?;
-    Ok(thread_rng().gen_range(*lower..=*upper).into())
+    ensure!(
+        lower <= upper,
+        "'rand_int' requires the lower bound not to exceed the upper"
+    );
+    Ok(with_rng(|rng| rng.gen_range(*lower..=*upper)).into())
 }
 
-define_op!(OP_RAND_CHOOSE, 1, false);
+define_op!(OP_RANDOM_INT, 2, false, false);
+pub(crate) fn op_random_int(args: &[DataValue]) -> Result<DataValue> {
+    op_rand_int(args)
+}
+
+define_op!(OP_RAND_CHOOSE, 1, false, false);
 pub(crate) fn op_rand_choose(args: &[DataValue]) -> Result<DataValue> {
     match &args[0] {
-        DataValue::List(l) => Ok(l
-            .choose(&mut thread_rng())
-            .cloned()
-            .unwrap_or(DataValue::Null)),
-        DataValue::Set(l) => Ok(l
-            .iter()
-            .collect_vec()
-            .choose(&mut thread_rng())
-            .cloned()
-            .cloned()
-            .unwrap_or(DataValue::Null)),
+        DataValue::List(l) => Ok(with_rng(|rng| l.choose(rng).cloned()).unwrap_or(DataValue::Null)),
+        DataValue::Set(l) => {
+            let items = l.iter().collect_vec();
+            Ok(with_rng(|rng| items.choose(rng).cloned().cloned()).unwrap_or(DataValue::Null))
+        }
         _ => bail!("'rand_choice' requires lists"),
     }
 }
@@ -2438,7 +2805,7 @@ pub(crate) fn op_to_uuid(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_NOW, 0, false);
+define_op!(OP_NOW, 0, false, false);
 #[cfg(target_arch = "wasm32")]
 pub(crate) fn op_now(_args: &[DataValue]) -> Result<DataValue> {
     let d: f64 = Date::now() / 1000.;
@@ -2511,26 +2878,24 @@ pub(crate) fn op_parse_timestamp(args: &[DataValue]) -> Result<DataValue> {
         .get_str()
         .ok_or_else(|| miette!("'parse_timestamp' expects a string"))?;
     let dt = DateTime::parse_from_rfc3339(s).map_err(|_| miette!("bad datetime: {}", s))?;
-    let st: SystemTime = dt.into();
+    // times before the epoch are negative
     Ok(DataValue::from(
-        st.duration_since(UNIX_EPOCH).unwrap().as_secs_f64(),
+        dt.timestamp() as f64 + dt.timestamp_subsec_nanos() as f64 / 1e9,
     ))
 }
 
 pub(crate) fn str2vld(s: &str) -> Result<ValidityTs> {
     let dt = DateTime::parse_from_rfc3339(s).map_err(|_| miette!("bad datetime: {}", s))?;
-    let st: SystemTime = dt.into();
-    let microseconds = st.duration_since(UNIX_EPOCH).unwrap().as_micros();
-    Ok(ValidityTs(Reverse(microseconds as i64)))
+    Ok(ValidityTs(Reverse(dt.timestamp_micros())))
 }
 
-define_op!(OP_RAND_UUID_V1, 0, false);
+define_op!(OP_RAND_UUID_V1, 0, false, false);
 pub(crate) fn op_rand_uuid_v1(_args: &[DataValue]) -> Result<DataValue> {
     let mut rng = rand::thread_rng();
     let uuid_ctx = uuid::v1::Context::new(rng.gen());
     #[cfg(target_arch = "wasm32")]
     let ts = {
-        let since_epoch: f64 = Date::now();
+        let since_epoch: f64 = Date::now() / 1000.;
         let seconds = since_epoch.floor();
         let fractional = (since_epoch - seconds) * 1.0e9;
         Timestamp::from_unix(uuid_ctx, seconds as u64, fractional as u32)
@@ -2547,9 +2912,10 @@ pub(crate) fn op_rand_uuid_v1(_args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::uuid(id))
 }
 
-define_op!(OP_RAND_UUID_V4, 0, false);
+define_op!(OP_RAND_UUID_V4, 0, false, false);
 pub(crate) fn op_rand_uuid_v4(_args: &[DataValue]) -> Result<DataValue> {
-    let id = uuid::Uuid::new_v4();
+    let bytes = with_rng(|rng| rng.gen::<[u8; 16]>());
+    let id = uuid::Builder::from_random_bytes(bytes).into_uuid();
     Ok(DataValue::uuid(id))
 }
 
@@ -56,7 +56,8 @@ pub(crate) trait MemCmpEncoder: Write {
                         let l = a.len();
                         self.write_u64::<BigEndian>(l as u64).unwrap();
                         for el in a {
-                            self.write_f32::<BigEndian>(*el).unwrap();
+                            self.write_u32::<BigEndian>(order_encode_vec_f32(*el))
+                                .unwrap();
                         }
                     }
                     Vector::F64(a) => {
@@ -64,7 +65,8 @@ pub(crate) trait MemCmpEncoder: Write {
                         let l = a.len();
                         self.write_u64::<BigEndian>(l as u64).unwrap();
                         for el in a {
-                            self.write_f64::<BigEndian>(*el).unwrap();
+                            self.write_u64::<BigEndian>(order_encode_vec_f64(*el))
+                                .unwrap();
                         }
                     }
                 }
@@ -163,15 +165,25 @@ pub(crate) trait MemCmpEncoder: Write {
     }
 }
 
+#[cfg(test)]
 pub fn decode_bytes(data: &[u8]) -> (Vec<u8>, &[u8]) {
+    try_decode_bytes(data).expect("malformed encoded bytes")
+}
+
+/// Splits off the first `n` bytes, failing on truncated data.
+fn take(data: &[u8], n: usize) -> Result<(&[u8], &[u8]), String> {
+    if data.len() < n {
+        return Err(format!("expected {n} more bytes, found {}", data.len()));
+    }
+    Ok(data.split_at(n))
+}
+
+pub(crate) fn try_decode_bytes(data: &[u8]) -> Result<(Vec<u8>, &[u8]), String> {
     let mut key = Vec::with_capacity(data.len() / (ENC_GROUP_SIZE + 1) * ENC_GROUP_SIZE);
-    let mut offset = 0;
-    let chunk_len = ENC_GROUP_SIZE + 1;
+    let mut remaining = data;
     loop {
-        let next_offset = offset + chunk_len;
-        debug_assert!(next_offset <= data.len());
-        let chunk = &data[offset..next_offset];
-        offset = next_offset;
+        let (chunk, rest) = take(remaining, ENC_GROUP_SIZE + 1)?;
+        remaining = rest;
 
         let (&marker, bytes) = chunk.split_last().unwrap();
         let pad_size = (ENC_MARKER - marker) as usize;
@@ -180,14 +192,18 @@ pub fn decode_bytes(data: &[u8]) -> (Vec<u8>, &[u8]) {
             key.write_all(bytes).unwrap();
             continue;
         }
-        debug_assert!(pad_size <= ENC_GROUP_SIZE);
+        if pad_size > ENC_GROUP_SIZE {
+            return Err(format!("invalid padding marker {marker:#04x}"));
+        }
 
         let (bytes, padding) = bytes.split_at(ENC_GROUP_SIZE - pad_size);
         key.write_all(bytes).unwrap();
 
-        debug_assert!(!padding.iter().any(|x| *x != 0));
+        if padding.iter().any(|x| *x != 0) {
+            return Err("non-zero padding".to_string());
+        }
 
-        return (key, &data[offset..]);
+        return Ok((key, remaining));
     }
 }
 
@@ -219,70 +235,105 @@ fn order_decode_f64(u: u64) -> f64 {
     f64::from_bits(u)
 }
 
+/// Vectors compare their elements as `OrderedFloat` does, so `-0.0` is encoded as `0.0`
+/// and all NaNs as the same one, which comes after all other numbers
+fn order_encode_vec_f64(v: f64) -> u64 {
+    if v.is_nan() {
+        order_encode_f64(f64::NAN)
+    } else if v == 0.0 {
+        order_encode_f64(0.0)
+    } else {
+        order_encode_f64(v)
+    }
+}
+
+fn order_encode_vec_f32(v: f32) -> u32 {
+    let v = if v.is_nan() {
+        f32::NAN
+    } else if v == 0.0 {
+        0.0
+    } else {
+        v
+    };
+    let u = v.to_bits();
+    if v.is_sign_positive() {
+        u | F32_SIGN_MARK
+    } else {
+        !u
+    }
+}
+
+fn order_decode_f32(u: u32) -> f32 {
+    let u = if u & F32_SIGN_MARK > 0 {
+        u & (!F32_SIGN_MARK)
+    } else {
+        !u
+    };
+    f32::from_bits(u)
+}
+
+const F32_SIGN_MARK: u32 = 0x80000000;
 const ENC_GROUP_SIZE: usize = 8;
 const ENC_MARKER: u8 = b'\xff';
 const ENC_ASC_PADDING: [u8; ENC_GROUP_SIZE] = [0; ENC_GROUP_SIZE];
 
 impl Num {
+    #[cfg(test)]
     pub(crate) fn decode_from_key(bs: &[u8]) -> (Self, &[u8]) {
-        let (float_part, remaining) = bs.split_at(8);
+        Self::try_decode_from_key(bs).expect("malformed encoded number")
+    }
+    pub(crate) fn try_decode_from_key(bs: &[u8]) -> Result<(Self, &[u8]), String> {
+        let (float_part, remaining) = take(bs, 8)?;
         let fu = BigEndian::read_u64(float_part);
         let f = order_decode_f64(fu);
-        let (tag, remaining) = remaining.split_first().unwrap();
-        match *tag {
+        let (tag, remaining) = take(remaining, 1)?;
+        Ok(match tag[0] {
             IS_FLOAT => (Num::Float(f), remaining),
             IS_EXACT_INT => (Num::Int(f as i64), remaining),
             IS_APPROX_INT => {
-                let (int_part, remaining) = remaining.split_at(8);
+                let (int_part, remaining) = take(remaining, 8)?;
                 let iu = BigEndian::read_u64(int_part);
                 let i = order_decode_i64(iu);
                 (Num::Int(i), remaining)
             }
-            _ => unreachable!(),
-        }
-        // if *tag == 0x80 {
-        //     return (Num::F(f), remaining);
-        // }
-        // let (subtag, remaining) = remaining.split_first().unwrap();
-        // let n = f as i64;
-        // let mut n_bytes = n.to_be_bytes();
-        // n_bytes[6] &= 0x80;
-        // n_bytes[6] |= tag;
-        // n_bytes[7] = *subtag;
-        // let n = BigEndian::read_i64(&n_bytes);
-        // (Num::I(n), remaining)
+            tag => return Err(format!("unknown number tag {tag:#04x}")),
+        })
     }
 }
 
 impl DataValue {
+    #[cfg(test)]
     pub(crate) fn decode_from_key(bs: &[u8]) -> (Self, &[u8]) {
-        let (tag, remaining) = bs.split_first().unwrap();
-        match *tag {
+        Self::try_decode_from_key(bs).unwrap_or_else(|err| panic!("{err}: {bs:?}"))
+    }
+    /// Decodes a value encoded by [MemCmpEncoder::encode_datavalue], failing on malformed data
+    /// instead of panicking.
+    pub(crate) fn try_decode_from_key(bs: &[u8]) -> Result<(Self, &[u8]), String> {
+        let (tag, remaining) = take(bs, 1)?;
+        Ok(match tag[0] {
             NULL_TAG => (DataValue::Null, remaining),
             FALSE_TAG => (DataValue::from(false), remaining),
             TRUE_TAG => (DataValue::from(true), remaining),
             NUM_TAG => {
-                let (n, remaining) = Num::decode_from_key(remaining);
+                let (n, remaining) = Num::try_decode_from_key(remaining)?;
                 (DataValue::Num(n), remaining)
             }
             STR_TAG => {
-                let (bytes, remaining) = decode_bytes(remaining);
-                let s = unsafe { String::from_utf8_unchecked(bytes) };
+                let (bytes, remaining) = try_decode_bytes(remaining)?;
+                let s = String::from_utf8(bytes).map_err(|err| err.to_string())?;
                 (DataValue::Str(s.into()), remaining)
             }
             JSON_TAG => {
-                let (bytes, remaining) = decode_bytes(remaining);
-                (
-                    DataValue::Json(JsonData(serde_json::from_slice(&bytes).unwrap())),
-                    remaining,
-                )
+                let (bytes, remaining) = try_decode_bytes(remaining)?;
+                let json = serde_json::from_slice(&bytes).map_err(|err| err.to_string())?;
+                (DataValue::Json(JsonData(json)), remaining)
             }
             BYTES_TAG => {
-                let (bytes, remaining) = decode_bytes(remaining);
+                let (bytes, remaining) = try_decode_bytes(remaining)?;
                 (DataValue::Bytes(bytes), remaining)
             }
             UUID_TAG => {
-                let (uuid_data, remaining) = remaining.split_at(16);
+                let (uuid_data, remaining) = take(remaining, 16)?;
                 let s_h = BigEndian::read_u16(&uuid_data[0..2]);
                 let s_m = BigEndian::read_u16(&uuid_data[2..4]);
                 let s_l = BigEndian::read_u32(&uuid_data[4..8]);
@@ -292,18 +343,16 @@ impl DataValue {
                 (DataValue::Uuid(UuidWrapper(uuid)), remaining)
             }
             REGEX_TAG => {
-                let (bytes, remaining) = decode_bytes(remaining);
-                let s = unsafe { String::from_utf8_unchecked(bytes) };
-                (
-                    DataValue::Regex(RegexWrapper(Regex::from_str(&s).unwrap())),
-                    remaining,
-                )
+                let (bytes, remaining) = try_decode_bytes(remaining)?;
+                let s = String::from_utf8(bytes).map_err(|err| err.to_string())?;
+                let re = Regex::from_str(&s).map_err(|err| err.to_string())?;
+                (DataValue::Regex(RegexWrapper(re)), remaining)
             }
             LIST_TAG => {
                 let mut collected = vec![];
                 let mut remaining = remaining;
-                while remaining[0] != INIT_TAG {
-                    let (val, next_chunk) = DataValue::decode_from_key(remaining);
+                while take(remaining, 1)?.0[0] != INIT_TAG {
+                    let (val, next_chunk) = DataValue::try_decode_from_key(remaining)?;
                     remaining = next_chunk;
                     collected.push(val);
                 }
@@ -312,20 +361,20 @@ impl DataValue {
             SET_TAG => {
                 let mut collected = BTreeSet::default();
                 let mut remaining = remaining;
-                while remaining[0] != INIT_TAG {
-                    let (val, next_chunk) = DataValue::decode_from_key(remaining);
+                while take(remaining, 1)?.0[0] != INIT_TAG {
+                    let (val, next_chunk) = DataValue::try_decode_from_key(remaining)?;
                     remaining = next_chunk;
                     collected.insert(val);
                 }
                 (DataValue::Set(collected), &remaining[1..])
             }
             VLD_TAG => {
-                let (ts_flipped_bytes, rest) = remaining.split_at(8);
+                let (ts_flipped_bytes, rest) = take(remaining, 8)?;
                 let ts_flipped = BigEndian::read_u64(ts_flipped_bytes);
                 let ts_u64 = !ts_flipped;
                 let ts = order_decode_i64(ts_u64);
-                let (is_assert_byte, rest) = rest.split_first().unwrap();
-                let is_assert = *is_assert_byte == 0;
+                let (is_assert_byte, rest) = take(rest, 1)?;
+                let is_assert = is_assert_byte[0] == 0;
                 (
                     DataValue::Validity(Validity {
                         timestamp: ValidityTs(Reverse(ts)),
@@ -336,35 +385,43 @@ impl DataValue {
             }
             BOT_TAG => (DataValue::Bot, remaining),
             VEC_TAG => {
-                let (t_tag, remaining) = remaining.split_first().unwrap();
-                let (len_bytes, mut rest) = remaining.split_at(8);
+                let (t_tag, remaining) = take(remaining, 1)?;
+                let (len_bytes, mut rest) = take(remaining, 8)?;
                 let len = BigEndian::read_u64(len_bytes) as usize;
-                match *t_tag {
+                let el_size = match t_tag[0] {
+                    VEC_F32 => 4,
+                    VEC_F64 => 8,
+                    tag => return Err(format!("unknown vector tag {tag:#04x}")),
+                };
+                let fits = len.checked_mul(el_size).is_some_and(|n| n <= rest.len());
+                if !fits {
+                    return Err(format!("vector of {len} elements is truncated"));
+                }
+                match t_tag[0] {
                     VEC_F32 => {
                         let mut res_arr = ndarray::Array1::zeros(len);
                         for mut row in res_arr.axis_iter_mut(ndarray::Axis(0)) {
                             let (f_bytes, next_chunk) = rest.split_at(4);
                             rest = next_chunk;
-                            let f = BigEndian::read_f32(f_bytes);
+                            let f = order_decode_f32(BigEndian::read_u32(f_bytes));
                             row.fill(f);
                         }
                         (DataValue::Vec(Vector::F32(res_arr)), rest)
                     }
-                    VEC_F64 => {
+                    _ => {
                         let mut res_arr = ndarray::Array1::zeros(len);
                         for mut row in res_arr.axis_iter_mut(ndarray::Axis(0)) {
                             let (f_bytes, next_chunk) = rest.split_at(8);
                             rest = next_chunk;
-                            let f = BigEndian::read_f64(f_bytes);
+                            let f = order_decode_f64(BigEndian::read_u64(f_bytes));
                             row.fill(f);
                         }
                         (DataValue::Vec(Vector::F64(res_arr)), rest)
                     }
-                    _ => unreachable!(),
                 }
             }
-            _ => unreachable!("{:?}", bs),
-        }
+            tag => return Err(format!("unknown value tag {tag:#04x}")),
+        })
     }
 }
 
@@ -6,30 +6,35 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::cmp::Ordering;
 use std::collections::btree_map::Entry;
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
+use std::hash::Hasher;
 use std::sync::Arc;
 
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
 use miette::{bail, ensure, miette, Diagnostic, Result};
 use smallvec::SmallVec;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
+use twox_hash::XxHash64;
 
 use crate::data::aggr::Aggregation;
 use crate::data::expr::Expr;
 use crate::data::relation::StoredRelationMetadata;
 use crate::data::symb::{Symbol, PROG_ENTRY};
-use crate::data::value::{DataValue, ValidityTs};
+use crate::data::value::{DataValue, Num, ValidityTs};
 use crate::fixed_rule::{FixedRule, FixedRuleHandle};
 use crate::fts::FtsIndexManifest;
-use crate::parse::SourceSpan;
+use crate::parse::{quote_ident, SourceSpan};
 use crate::query::compile::ContainedRuleMultiplicity;
 use crate::query::logical::{Disjunction, NamedFieldNotFound};
 use crate::runtime::hnsw::HnswIndexManifest;
 use crate::runtime::minhash_lsh::{LshSearch, MinHashLshIndexManifest};
 use crate::runtime::relation::{
-    AccessLevel, InputRelationHandle, InsufficientAccessLevel, RelationHandle,
+    AccessLevel, InputRelationHandle, InsufficientAccessLevel, OnConflict, RelationHandle,
 };
 use crate::runtime::temp_store::EpochStore;
 use crate::runtime::transact::SessionTx;
@@ -54,11 +59,44 @@ pub struct QueryOutOptions {
     pub timeout: Option<f64>,
     /// Sleep after performing the query for this number of seconds. Ignored in WASM.
     pub sleep: Option<f64>,
+    /// Evaluate recursive rules for at most this many rounds after the first, failing
+    /// if they would derive more rows. Each round, rules see the rows the others
+    /// derived in the previous one.
+    pub max_hops: Option<u32>,
+    /// With `max_hops`, return the rows derived so far instead of failing.
+    pub truncate_hops: bool,
     pub sorters: Vec<(Symbol, SortDir)>,
+    /// Only return rows sorting strictly after this cursor.
+    pub after: Option<Box<QueryCursor>>,
     pub store_relation: Option<(InputRelationHandle, RelationOp, ReturnMutation)>,
     pub assertion: Option<QueryAssertion>,
 }
 
+/// Position in the sorted output of a query, from which the next page resumes.
+#[derive(Clone, PartialEq, Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct QueryCursor {
+    /// Fingerprint of the query the cursor was issued for
+    pub(crate) fingerprint: u64,
+    /// Values of the sort keys in the last row returned
+    pub(crate) key: Vec<DataValue>,
+}
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("Malformed query cursor")]
+#[diagnostic(code(parser::bad_cursor))]
+#[diagnostic(help("Cursors must be passed back exactly as they were returned"))]
+pub(crate) struct BadCursorError;
+
+impl QueryCursor {
+    pub(crate) fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(rmp_serde::to_vec(self).unwrap())
+    }
+    pub(crate) fn decode(s: &str) -> Result<Self> {
+        let bytes = URL_SAFE_NO_PAD.decode(s).map_err(|_| BadCursorError)?;
+        Ok(rmp_serde::from_slice(&bytes).map_err(|_| BadCursorError)?)
+    }
+}
+
 impl Debug for QueryOutOptions {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{self}")
@@ -76,6 +114,15 @@ impl Display for QueryOutOptions {
         if let Some(l) = self.timeout {
             writeln!(f, ":timeout {l};")?;
         }
+        if let Some(l) = self.max_hops {
+            writeln!(f, ":max_hops {l};")?;
+        }
+        if self.truncate_hops {
+            writeln!(f, ":truncate_hops;")?;
+        }
+        if let Some(cursor) = &self.after {
+            writeln!(f, ":after {:?};", cursor.encode())?;
+        }
         for (symb, dir) in &self.sorters {
             write!(f, ":order ")?;
             if *dir == SortDir::Dsc {
@@ -89,6 +136,8 @@ impl Display for QueryOutOptions {
                 metadata: StoredRelationMetadata { keys, non_keys },
                 key_bindings,
                 dep_bindings,
+                binding_exprs,
+                on_conflict,
                 ..
             },
             op,
@@ -111,6 +160,9 @@ impl Display for QueryOutOptions {
                 RelationOp::Put => {
                     write!(f, ":put ")?;
                 }
+                RelationOp::Upsert => {
+                    write!(f, ":upsert ")?;
+                }
                 RelationOp::Update => {
                     write!(f, ":update ")?;
                 }
@@ -127,7 +179,7 @@ impl Display for QueryOutOptions {
                     write!(f, ":ensure_not ")?;
                 }
             }
-            write!(f, "{name} {{")?;
+            write!(f, "{} {{", quote_ident(name))?;
             let mut is_first = true;
             for (col, bind) in keys.iter().zip(key_bindings) {
                 if is_first {
@@ -135,9 +187,11 @@ impl Display for QueryOutOptions {
                 } else {
                     write!(f, ", ")?;
                 }
-                write!(f, "{}: {}", col.name, col.typing)?;
+                write!(f, "{}: {}", quote_ident(&col.name), col.typing)?;
                 if let Some(gen) = &col.default_gen {
                     write!(f, " default {gen}")?;
+                } else if let Some(expr) = binding_exprs.get(&bind.name) {
+                    write!(f, " = {expr}")?;
                 } else {
                     write!(f, " = {bind}")?;
                 }
@@ -150,14 +204,32 @@ impl Display for QueryOutOptions {
                 } else {
                     write!(f, ", ")?;
                 }
-                write!(f, "{}: {}", col.name, col.typing)?;
+                write!(f, "{}: {}", quote_ident(&col.name), col.typing)?;
                 if let Some(gen) = &col.default_gen {
                     write!(f, " default {gen}")?;
+                } else if let Some(expr) = binding_exprs.get(&bind.name) {
+                    write!(f, " = {expr}")?;
                 } else {
                     write!(f, " = {bind}")?;
                 }
             }
             writeln!(f, "}};")?;
+            match on_conflict {
+                OnConflict::Error => {}
+                OnConflict::Ignore => {
+                    writeln!(f, ":on_conflict ignore;")?;
+                }
+                OnConflict::Update(assignments) => {
+                    write!(f, ":on_conflict update {{")?;
+                    for (i, (col, expr)) in assignments.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ", ")?;
+                        }
+                        write!(f, "{}: {expr}", quote_ident(col))?;
+                    }
+                    writeln!(f, "}};")?;
+                }
+            }
         }
 
         if let Some(a) = &self.assertion {
@@ -185,7 +257,7 @@ impl QueryOutOptions {
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
 pub enum SortDir {
     Asc,
     Dsc,
@@ -193,14 +265,27 @@ pub enum SortDir {
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum RelationOp {
+    /// Create a new stored relation and write the rows into it
     Create,
+    /// Create or overwrite a stored relation and write the rows into it
     Replace,
+    /// Write whole rows, overwriting any existing rows with the same keys
     Put,
+    /// Write whole rows, failing if any of the keys already exist
     Insert,
+    /// Write only the columns given, keeping the other stored values of
+    /// existing rows and using the defaults for new rows
+    Upsert,
+    /// Write only the columns given into existing rows, failing if any of
+    /// the keys do not exist
     Update,
+    /// Remove rows by key, ignoring keys that do not exist
     Rm,
+    /// Remove rows by key, failing if any of the keys do not exist
     Delete,
+    /// Assert that the rows exist as given
     Ensure,
+    /// Assert that the keys do not exist
     EnsureNot,
 }
 
@@ -561,6 +646,39 @@ struct EntryHeadNotExplicitlyDefinedError(#[label] SourceSpan);
 pub(crate) struct NoEntryError;
 
 impl InputProgram {
+    /// Hash of the program, ignoring any `:after` option, that cursors are checked against.
+    pub(crate) fn fingerprint(&self) -> u64 {
+        let mut prog = self.clone();
+        prog.out_opts.after = None;
+        let mut hasher = XxHash64::with_seed(0);
+        hasher.write(prog.to_string().as_bytes());
+        hasher.finish()
+    }
+
+    /// Checks that the cursor in the `:after` option, if any, was issued for this program.
+    pub(crate) fn check_cursor(&self) -> Result<()> {
+        #[derive(Debug, Diagnostic, Error)]
+        #[error("Query cursor does not belong to this query")]
+        #[diagnostic(code(eval::cursor_mismatch))]
+        #[diagnostic(help(
+            "A cursor can only resume the query that returned it, with the same parameters"
+        ))]
+        struct CursorMismatchError;
+
+        if let Some(cursor) = &self.out_opts.after {
+            ensure!(
+                !self.out_opts.sorters.is_empty(),
+                "the ':after' option requires the query to be sorted with ':order'"
+            );
+            ensure!(
+                cursor.key.len() == self.out_opts.sorters.len()
+                    && cursor.fingerprint == self.fingerprint(),
+                CursorMismatchError
+            );
+        }
+        Ok(())
+    }
+
     pub(crate) fn needs_write_lock(&self) -> Option<SmartString<LazyCompact>> {
         if let Some((h, _, _)) = &self.out_opts.store_relation {
             if !h.name.name.starts_with('_') {
@@ -771,7 +889,9 @@ pub(crate) struct MagicProgram {
     pub(crate) prog: BTreeMap<MagicSymbol, MagicRulesOrFixed>,
 }
 
-#[derive(Clone, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(
+    Clone, Ord, PartialOrd, Eq, PartialEq, serde_derive::Serialize, serde_derive::Deserialize,
+)]
 pub(crate) enum MagicSymbol {
     Muggle {
         inner: Symbol,
@@ -961,6 +1081,11 @@ pub enum InputAtom {
     Search {
         inner: SearchInput,
     },
+    /// `Series(x: a..b step s)`
+    Series {
+        /// The series, with its bounds already evaluated
+        inner: Series,
+    },
 }
 
 #[derive(Clone)]
@@ -1007,6 +1132,9 @@ pub(crate) struct FtsSearch {
     // pub(crate) b: f64,
     pub(crate) query: Symbol,
     pub(crate) score_kind: FtsScoreKind,
+    /// Match documents containing any of the top-level terms of the query,
+    /// instead of all of them
+    pub(crate) match_any: bool,
     pub(crate) bind_score: Option<Symbol>,
     // pub(crate) lax_mode: bool,
     pub(crate) filter: Option<Expr>,
@@ -1297,6 +1425,22 @@ impl SearchInput {
             None => FtsScoreKind::TfIdf,
         };
 
+        let match_any = match self.parameters.remove("mode") {
+            Some(expr) => {
+                let r = expr.eval_to_const()?;
+                let r = r
+                    .get_str()
+                    .ok_or_else(|| miette!("Match mode for FTS must be a string"))?;
+
+                match r {
+                    "all" => false,
+                    "any" => true,
+                    s => bail!("Unknown match mode for FTS: {}", s),
+                }
+            }
+            None => false,
+        };
+
         let filter = self.parameters.remove("filter");
 
         let bind_score = match self.parameters.remove("bind_score") {
@@ -1328,6 +1472,7 @@ impl SearchInput {
             k: k as usize,
             query,
             score_kind,
+            match_any,
             bind_score,
             // lax_mode,
             // k1,
@@ -1629,23 +1774,30 @@ impl Display for InputAtom {
             InputAtom::NamedFieldRelation {
                 inner: InputNamedFieldRelationApplyAtom { name, args, .. },
             } => {
-                f.write_str("*")?;
-                let mut sf = f.debug_struct(name);
-                for (k, v) in args {
-                    sf.field(k, v);
+                write!(f, "*{}{{", quote_ident(name))?;
+                for (i, (k, v)) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {v}", quote_ident(k))?;
                 }
-                sf.finish()?;
+                write!(f, "}}")?;
             }
             InputAtom::Relation {
                 inner: InputRelationApplyAtom { name, args, .. },
             } => {
-                write!(f, ":{name}")?;
+                write!(f, ":{}", quote_ident(name))?;
                 f.debug_list().entries(args).finish()?;
             }
             InputAtom::Search { inner } => {
-                write!(f, "~{}:{}{{", inner.relation, inner.index)?;
+                write!(
+                    f,
+                    "~{}:{}{{",
+                    quote_ident(&inner.relation),
+                    quote_ident(&inner.index)
+                )?;
                 for (binding, expr) in &inner.bindings {
-                    write!(f, "{binding}: {expr}, ")?;
+                    write!(f, "{}: {expr}, ", quote_ident(binding))?;
                 }
                 write!(f, "| ")?;
                 for (k, v) in inner.parameters.iter() {
@@ -1653,6 +1805,9 @@ impl Display for InputAtom {
                 }
                 write!(f, "}}")?;
             }
+            InputAtom::Series { inner } => {
+                write!(f, "{inner}")?;
+            }
             InputAtom::Predicate { inner } => {
                 write!(f, "{inner}")?;
             }
@@ -1719,6 +1874,7 @@ impl InputAtom {
             InputAtom::Predicate { inner, .. } => inner.span(),
             InputAtom::Unification { inner, .. } => inner.span,
             InputAtom::Search { inner, .. } => inner.span,
+            InputAtom::Series { inner, .. } => inner.span,
         }
     }
 }
@@ -1731,6 +1887,7 @@ pub(crate) enum NormalFormAtom {
     NegatedRelation(NormalFormRelationApplyAtom),
     Predicate(Expr),
     Unification(Unification),
+    Series(Series),
     HnswSearch(HnswSearch),
     FtsSearch(FtsSearch),
     LshSearch(LshSearch),
@@ -1744,11 +1901,54 @@ pub(crate) enum MagicAtom {
     NegatedRule(MagicRuleApplyAtom),
     NegatedRelation(MagicRelationApplyAtom),
     Unification(Unification),
+    Series(Series),
     HnswSearch(HnswSearch),
     FtsSearch(FtsSearch),
     LshSearch(LshSearch),
 }
 
+impl MagicAtom {
+    /// Collects every variable that the atom binds or uses.
+    pub(crate) fn collect_mentioned(&self, coll: &mut BTreeSet<Symbol>) -> Result<()> {
+        match self {
+            MagicAtom::Rule(r) | MagicAtom::NegatedRule(r) => coll.extend(r.args.iter().cloned()),
+            MagicAtom::Relation(r) | MagicAtom::NegatedRelation(r) => {
+                coll.extend(r.args.iter().cloned())
+            }
+            MagicAtom::Predicate(p) => p.collect_bindings(coll)?,
+            MagicAtom::Unification(u) => {
+                coll.insert(u.binding.clone());
+                u.expr.collect_bindings(coll)?;
+            }
+            MagicAtom::Series(s) => {
+                coll.insert(s.binding.clone());
+            }
+            MagicAtom::HnswSearch(s) => {
+                coll.extend(s.all_bindings().cloned());
+                coll.insert(s.query.clone());
+                if let Some(filter) = &s.filter {
+                    filter.collect_bindings(coll)?;
+                }
+            }
+            MagicAtom::FtsSearch(s) => {
+                coll.extend(s.all_bindings().cloned());
+                coll.insert(s.query.clone());
+                if let Some(filter) = &s.filter {
+                    filter.collect_bindings(coll)?;
+                }
+            }
+            MagicAtom::LshSearch(s) => {
+                coll.extend(s.all_bindings().cloned());
+                coll.insert(s.query.clone());
+                if let Some(filter) = &s.filter {
+                    filter.collect_bindings(coll)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct InputRuleApplyAtom {
     pub name: Symbol,
@@ -1761,6 +1961,8 @@ pub struct InputNamedFieldRelationApplyAtom {
     pub name: Symbol,
     pub args: BTreeMap<SmartString<LazyCompact>, Expr>,
     pub valid_at: Option<ValidityTs>,
+    /// Whether soft-deleted rows are returned as well
+    pub include_deleted: bool,
     pub span: SourceSpan,
 }
 
@@ -1769,6 +1971,8 @@ pub struct InputRelationApplyAtom {
     pub name: Symbol,
     pub args: Vec<Expr>,
     pub valid_at: Option<ValidityTs>,
+    /// Whether soft-deleted rows are returned as well
+    pub include_deleted: bool,
     pub span: SourceSpan,
 }
 
@@ -1784,6 +1988,7 @@ pub(crate) struct NormalFormRelationApplyAtom {
     pub(crate) name: Symbol,
     pub(crate) args: Vec<Symbol>,
     pub(crate) valid_at: Option<ValidityTs>,
+    pub(crate) include_deleted: bool,
     pub(crate) span: SourceSpan,
 }
 
@@ -1799,6 +2004,7 @@ pub(crate) struct MagicRelationApplyAtom {
     pub(crate) name: Symbol,
     pub(crate) args: Vec<Symbol>,
     pub(crate) valid_at: Option<ValidityTs>,
+    pub(crate) include_deleted: bool,
     pub(crate) span: SourceSpan,
 }
 
@@ -1812,6 +2018,128 @@ pub struct Unification {
     pub span: SourceSpan,
 }
 
+/// `Series(x: start..end step s)`, binding `x` to each element of an arithmetic series.
+/// The elements are integers if both `start` and `step` are, and floats otherwise.
+#[derive(Clone, Debug)]
+pub struct Series {
+    /// Symbol bound to the elements.
+    pub binding: Symbol,
+    /// The first element.
+    pub start: DataValue,
+    /// The bound at which the series stops.
+    pub end: DataValue,
+    /// The difference between consecutive elements, `1` if not given.
+    pub step: DataValue,
+    /// Whether `end` itself is part of the series, as for `..=`.
+    pub inclusive: bool,
+    /// Location of the atom in the script.
+    pub span: SourceSpan,
+}
+
+impl Display for Series {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let range = if self.inclusive { "..=" } else { ".." };
+        write!(
+            f,
+            "Series({}: {}{range}{} step {})",
+            self.binding, self.start, self.end, self.step
+        )
+    }
+}
+
+impl Series {
+    pub(crate) fn new(
+        binding: Symbol,
+        start: DataValue,
+        end: DataValue,
+        step: DataValue,
+        inclusive: bool,
+        span: SourceSpan,
+    ) -> Result<Self> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("The bounds and step of a series must be finite numbers")]
+        #[diagnostic(code(parser::bad_series_bounds))]
+        struct BadSeriesBounds(#[label] SourceSpan);
+
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("A series cannot go from {0} to {1} with step {2}")]
+        #[diagnostic(code(parser::bad_series_step))]
+        #[diagnostic(help("The step must be nonzero, and negative for a descending series"))]
+        struct BadSeriesStep(DataValue, DataValue, DataValue, #[label] SourceSpan);
+
+        let (lo, hi, by) = match (start.get_float(), end.get_float(), step.get_float()) {
+            (Some(lo), Some(hi), Some(by))
+                if lo.is_finite() && hi.is_finite() && by.is_finite() =>
+            {
+                (lo, hi, by)
+            }
+            _ => bail!(BadSeriesBounds(span)),
+        };
+        ensure!(
+            by != 0. && (hi - lo) * by >= 0.,
+            BadSeriesStep(start, end, step, span)
+        );
+        Ok(Self {
+            binding,
+            start,
+            end,
+            step,
+            inclusive,
+            span,
+        })
+    }
+    fn nth(&self, i: u64) -> Option<DataValue> {
+        match (&self.start, &self.step) {
+            (DataValue::Num(Num::Int(start)), DataValue::Num(Num::Int(step))) => {
+                let offset = i64::try_from(i).ok()?.checked_mul(*step)?;
+                Some(DataValue::from(start.checked_add(offset)?))
+            }
+            _ => {
+                let start = self.start.get_float().unwrap();
+                let step = self.step.get_float().unwrap();
+                Some(DataValue::from(start + i as f64 * step))
+            }
+        }
+    }
+    fn in_bounds(&self, val: &DataValue) -> bool {
+        let ord = match (val, &self.end) {
+            (DataValue::Num(Num::Int(v)), DataValue::Num(Num::Int(end))) => v.cmp(end),
+            _ => val
+                .get_float()
+                .unwrap()
+                .total_cmp(&self.end.get_float().unwrap()),
+        };
+        let towards_end = if self.step.get_float().unwrap() > 0. {
+            Ordering::Less
+        } else {
+            Ordering::Greater
+        };
+        ord == towards_end || (self.inclusive && ord == Ordering::Equal)
+    }
+    /// The elements of the series, computed as they are consumed.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = DataValue> + '_ {
+        (0..)
+            .map_while(|i| self.nth(i))
+            .take_while(|val| self.in_bounds(val))
+    }
+    /// Whether `val` is an element of the series, without going through it.
+    pub(crate) fn contains(&self, val: &DataValue) -> bool {
+        let x = match val.get_float() {
+            Some(x) => x,
+            None => return false,
+        };
+        let start = self.start.get_float().unwrap();
+        let i = ((x - start) / self.step.get_float().unwrap()).round();
+        if !(i >= 0. && i < u64::MAX as f64) {
+            return false;
+        }
+        match self.nth(i as u64) {
+            Some(elem) => elem == *val && self.in_bounds(&elem),
+            None => false,
+        }
+    }
+}
+
 impl Unification {
     pub(crate) fn is_const(&self) -> bool {
         matches!(self.expr, Expr::Const { .. })
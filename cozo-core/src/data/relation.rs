@@ -111,7 +111,7 @@ pub enum VecElementType {
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, serde_derive::Deserialize, serde_derive::Serialize)]
-pub(crate) struct ColumnDef {
+pub struct ColumnDef {
     pub(crate) name: SmartString<LazyCompact>,
     pub(crate) typing: NullableColType,
     pub(crate) default_gen: Option<Expr>,
@@ -164,9 +164,16 @@ impl StoredRelationMetadata {
         #[derive(Debug, Error, Diagnostic)]
         #[error("required column {0} not found")]
         #[diagnostic(code(eval::required_col_not_found))]
-        struct ColumnNotFound(String);
+        #[diagnostic(help("Valid columns are: {1}"))]
+        struct ColumnNotFound(String, String);
 
-        bail!(ColumnNotFound(col.name.to_string()))
+        let valid = self
+            .keys
+            .iter()
+            .chain(self.non_keys.iter())
+            .map(|c| &c.name)
+            .join(", ");
+        bail!(ColumnNotFound(col.name.to_string(), valid))
     }
 }
 
@@ -12,11 +12,35 @@ use std::hash::{Hash, Hasher};
 use std::ops::Deref;
 
 use miette::{bail, Diagnostic, Result};
+use pest::Parser;
 use serde_derive::{Deserialize, Serialize};
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use crate::parse::SourceSpan;
+use crate::parse::{CozoScriptParser, Rule, SourceSpan};
+
+/// Words that parse as literals or operators wherever a binding is expected,
+/// so columns named after them could never be bound in queries.
+pub(crate) const RESERVED_NAMES: [&str; 7] = ["null", "true", "false", "not", "or", "and", "in"];
+
+/// What a name passed to [Symbol::new_checked] is going to name
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum NameKind {
+    Relation,
+    Index,
+    Column,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("'{0}' is not a valid name")]
+#[diagnostic(code(parser::invalid_name))]
+#[diagnostic(help("Names start with a letter and continue with letters, digits or underscores"))]
+pub(crate) struct InvalidName(pub(crate) String, #[label] pub(crate) SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("'{0}' is a reserved word and cannot be used as a name")]
+#[diagnostic(code(parser::reserved_name))]
+pub(crate) struct ReservedName(pub(crate) String, #[label] pub(crate) SourceSpan);
 
 /// Names with associated source span
 #[derive(Clone, Deserialize, Serialize)]
@@ -79,6 +103,26 @@ impl Symbol {
             span,
         }
     }
+    /// Like [Symbol::new], but only accepts names the script grammar would accept
+    /// unquoted in the same position, so that the name can be written back in queries.
+    pub(crate) fn new_checked(name: &str, span: SourceSpan, kind: NameKind) -> Result<Self> {
+        let name_rule = match kind {
+            NameKind::Relation => Rule::compound_ident,
+            NameKind::Index | NameKind::Column => Rule::ident,
+        };
+        // names are given unquoted, so backticks can only be part of the name
+        let whole_match = !name.contains('`')
+            && CozoScriptParser::parse(name_rule, name)
+                .is_ok_and(|mut p| p.next().unwrap().as_str().len() == name.len());
+        if !whole_match {
+            bail!(InvalidName(name.to_string(), span))
+        }
+        // relation and index names are always prefixed by `*`, `:` or `~` in scripts
+        if kind == NameKind::Column && RESERVED_NAMES.contains(&name) {
+            bail!(ReservedName(name.to_string(), span))
+        }
+        Ok(Self::new(name, span))
+    }
     pub(crate) fn is_temp_store_name(&self) -> bool {
         self.name.starts_with('_')
     }
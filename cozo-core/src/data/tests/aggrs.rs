@@ -9,7 +9,7 @@
 use approx::AbsDiffEq;
 use itertools::Itertools;
 
-use crate::data::aggr::parse_aggr;
+use crate::data::aggr::{parse_aggr, HyperLogLog};
 use crate::data::value::DataValue;
 
 #[test]
@@ -210,6 +210,79 @@ fn test_count_unique() {
     assert_eq!(count_unique_aggr.get().unwrap(), DataValue::from(3));
 }
 
+#[test]
+fn test_count_distinct() {
+    let mut aggr = parse_aggr("count_distinct").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+
+    let mut count_distinct_aggr = aggr.normal_op.unwrap();
+    for v in [1, 2, 3, 1, 2, 1] {
+        count_distinct_aggr.set(&DataValue::from(v)).unwrap();
+    }
+    count_distinct_aggr.set(&DataValue::from("1")).unwrap();
+    assert_eq!(count_distinct_aggr.get().unwrap(), DataValue::from(4));
+}
+
+#[test]
+fn test_approx_count_distinct() {
+    for (precision, n) in [(14, 100000), (10, 50000), (14, 100)] {
+        let mut exact = parse_aggr("count_distinct").unwrap().clone();
+        exact.normal_init(&[]).unwrap();
+        let mut exact = exact.normal_op.unwrap();
+        let mut approx = parse_aggr("approx_count_distinct").unwrap().clone();
+        approx.normal_init(&[DataValue::from(precision)]).unwrap();
+        let mut approx = approx.normal_op.unwrap();
+        for i in 0..n {
+            // every value appears twice
+            let v = DataValue::from(format!("item-{}", i % (n / 2)));
+            exact.set(&v).unwrap();
+            approx.set(&v).unwrap();
+        }
+        let exact = exact.get().unwrap().get_int().unwrap() as f64;
+        let approx = approx.get().unwrap().get_int().unwrap() as f64;
+        assert_eq!(exact, (n / 2) as f64);
+        // four times the standard error
+        let tolerance = 4. * 1.04 / 2f64.powi(precision).sqrt();
+        assert!(
+            (approx - exact).abs() / exact < tolerance,
+            "{approx} vs {exact}"
+        );
+    }
+
+    let mut aggr = parse_aggr("approx_count_distinct").unwrap().clone();
+    assert!(aggr.normal_init(&[DataValue::from(3)]).is_err());
+    assert!(aggr.normal_init(&[DataValue::from(19)]).is_err());
+}
+
+#[test]
+fn test_hll_merge() {
+    let mut sketches = vec![];
+    for part in 0..4 {
+        let mut aggr = parse_aggr("hll_sketch").unwrap().clone();
+        aggr.normal_init(&[DataValue::from(12)]).unwrap();
+        let mut aggr = aggr.normal_op.unwrap();
+        // the parts overlap by half
+        for i in (part * 5000)..(part * 5000 + 10000) {
+            aggr.set(&DataValue::from(i)).unwrap();
+        }
+        sketches.push(aggr.get().unwrap());
+    }
+    let mut merge = parse_aggr("hll_merge").unwrap().clone();
+    merge.normal_init(&[]).unwrap();
+    let mut merge = merge.normal_op.unwrap();
+    for sketch in &sketches {
+        merge.set(sketch).unwrap();
+    }
+    let merged = HyperLogLog::from_bytes(merge.get().unwrap().get_bytes().unwrap()).unwrap();
+    let est = merged.estimate() as f64;
+    assert!((est - 25000.).abs() / 25000. < 4. * 1.04 / 64., "{est}");
+
+    let mut other = parse_aggr("hll_sketch").unwrap().clone();
+    other.normal_init(&[DataValue::from(10)]).unwrap();
+    assert!(merge.set(&other.normal_op.unwrap().get().unwrap()).is_err());
+    assert!(merge.set(&DataValue::from(1)).is_err());
+}
+
 #[test]
 fn test_collect() {
     let mut aggr = parse_aggr("collect").unwrap().clone();
@@ -285,6 +358,166 @@ fn test_mean() {
     assert_eq!(mean_aggr.get().unwrap(), DataValue::from(3.));
 }
 
+#[test]
+fn test_percentile() {
+    let percentile = |name: &str, args: &[DataValue], values: &[i64]| {
+        let mut aggr = parse_aggr(name).unwrap().clone();
+        aggr.normal_init(args).unwrap();
+        let mut op = aggr.normal_op.unwrap();
+        for v in values {
+            op.set(&DataValue::from(*v)).unwrap();
+        }
+        op.set(&DataValue::Null).unwrap();
+        op.get().unwrap()
+    };
+    let values = [4, 1, 3, 2];
+    // the median of an even number of values lies between the two middle ones
+    assert_eq!(
+        percentile("percentile_cont", &[], &values),
+        DataValue::from(2.5)
+    );
+    assert_eq!(
+        percentile("percentile_disc", &[], &values),
+        DataValue::from(2)
+    );
+    // of an odd number of values, both take the middle one
+    assert_eq!(
+        percentile("percentile_cont", &[], &[5, 1, 3]),
+        DataValue::from(3.)
+    );
+    assert_eq!(
+        percentile("percentile_disc", &[], &[5, 1, 3]),
+        DataValue::from(3)
+    );
+
+    let p = [DataValue::from(0.9)];
+    let cont = percentile("percentile_cont", &p, &values)
+        .get_float()
+        .unwrap();
+    assert!(cont.abs_diff_eq(&3.7, 1e-10));
+    assert_eq!(
+        percentile("percentile_disc", &p, &values),
+        DataValue::from(4)
+    );
+    let p = [DataValue::from(0.25)];
+    assert_eq!(
+        percentile("percentile_cont", &p, &values),
+        DataValue::from(1.75)
+    );
+    assert_eq!(
+        percentile("percentile_disc", &p, &values),
+        DataValue::from(1)
+    );
+    let p = [DataValue::from(0)];
+    assert_eq!(
+        percentile("percentile_cont", &p, &values),
+        DataValue::from(1.)
+    );
+    assert_eq!(
+        percentile("percentile_disc", &p, &values),
+        DataValue::from(1)
+    );
+    let p = [DataValue::from(1)];
+    assert_eq!(
+        percentile("percentile_cont", &p, &values),
+        DataValue::from(4.)
+    );
+    assert_eq!(
+        percentile("percentile_disc", &p, &values),
+        DataValue::from(4)
+    );
+    assert_eq!(percentile("percentile_disc", &[], &[]), DataValue::Null);
+
+    let mut aggr = parse_aggr("percentile_cont").unwrap().clone();
+    assert!(aggr.normal_init(&[DataValue::from(1.5)]).is_err());
+    aggr.normal_init(&[]).unwrap();
+    assert!(aggr.normal_op.unwrap().set(&DataValue::from("a")).is_err());
+}
+
+#[test]
+fn test_first_last() {
+    let first_last = |name: &str, args: &[DataValue], values: &[DataValue]| {
+        let mut aggr = parse_aggr(name).unwrap().clone();
+        aggr.normal_init(args).unwrap();
+        let mut op = aggr.normal_op.unwrap();
+        for v in values {
+            op.set(v).unwrap();
+        }
+        op.get().unwrap()
+    };
+    let pair = |v: &str, k: DataValue| DataValue::List(vec![DataValue::from(v), k]);
+
+    // in the order the rows are received
+    let values = [DataValue::from(3), DataValue::Null, DataValue::from(1)];
+    assert_eq!(first_last("first", &[], &values), DataValue::from(3));
+    assert_eq!(first_last("last", &[], &values), DataValue::from(1));
+    assert_eq!(first_last("last", &[], &values[..2]), DataValue::Null);
+
+    let values = [
+        pair("b", DataValue::from(2)),
+        pair("x", DataValue::Null),
+        pair("a", DataValue::from(1)),
+        pair("c", DataValue::from(3)),
+        pair("d", DataValue::from(3)),
+        pair("e", DataValue::from(1)),
+    ];
+    let asc = [DataValue::from("asc")];
+    let desc = [DataValue::from("desc")];
+    // ties keep the order the rows are received in
+    assert_eq!(first_last("first", &asc, &values), DataValue::from("a"));
+    assert_eq!(first_last("last", &asc, &values), DataValue::from("d"));
+    assert_eq!(first_last("first", &desc, &values), DataValue::from("c"));
+    assert_eq!(first_last("last", &desc, &values), DataValue::from("e"));
+    // rows with null keys are skipped
+    assert_eq!(first_last("first", &asc, &values[1..2]), DataValue::Null);
+
+    let mut aggr = parse_aggr("first").unwrap().clone();
+    assert!(aggr.normal_init(&[DataValue::from("up")]).is_err());
+    aggr.normal_init(&asc).unwrap();
+    assert!(aggr.normal_op.unwrap().set(&DataValue::from(1)).is_err());
+}
+
+#[test]
+fn test_min_max_by() {
+    let by = |name: &str, values: &[DataValue]| {
+        let mut aggr = parse_aggr(name).unwrap().clone();
+        aggr.normal_init(&[]).unwrap();
+        let mut op = aggr.normal_op.unwrap();
+        for v in values {
+            op.set(v).unwrap();
+        }
+        op.get().unwrap()
+    };
+    let pair = |v: &str, k: DataValue| DataValue::List(vec![DataValue::from(v), k]);
+
+    let values = [
+        pair("b", DataValue::from(2)),
+        pair("x", DataValue::Null),
+        pair("a", DataValue::from(1)),
+        pair("c", DataValue::from(3)),
+        pair("d", DataValue::from(3)),
+        pair("e", DataValue::from(1)),
+    ];
+    // ties go to the row seen first
+    assert_eq!(by("min_by", &values), DataValue::from("a"));
+    assert_eq!(by("max_by", &values), DataValue::from("c"));
+    // rows with null keys are skipped
+    assert_eq!(by("max_by", &values[1..2]), DataValue::Null);
+    assert_eq!(by("min_by", &values[..2]), DataValue::from("b"));
+    assert_eq!(by("min_by", &[]), DataValue::Null);
+    // they are the same as `latest_by` and `smallest_by`
+    assert_eq!(by("latest_by", &values), DataValue::from("c"));
+    assert_eq!(by("smallest_by", &values), DataValue::from("a"));
+
+    let mut aggr = parse_aggr("max_by").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+    assert!(aggr.normal_op.unwrap().set(&DataValue::from(1)).is_err());
+    for name in ["min_by", "max_by"] {
+        let mut aggr = parse_aggr(name).unwrap().clone();
+        assert!(aggr.normal_init(&[DataValue::from("desc")]).is_err());
+    }
+}
+
 #[test]
 fn test_sum() {
     let mut aggr = parse_aggr("sum").unwrap().clone();
@@ -12,7 +12,7 @@ use regex::Regex;
 use serde_json::json;
 
 use crate::data::functions::*;
-use crate::data::value::{DataValue, RegexWrapper};
+use crate::data::value::{DataValue, JsonData, RegexWrapper};
 use crate::DbInstance;
 
 #[test]
@@ -144,6 +144,44 @@ fn test_is_in() {
     );
 }
 
+#[test]
+fn test_in() {
+    let list = DataValue::List(vec![DataValue::from(1), DataValue::Null]);
+    assert_eq!(
+        op_in(&[DataValue::from(1.0), list.clone()]).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        op_in(&[DataValue::from(2), list.clone()]).unwrap(),
+        DataValue::Null
+    );
+    assert_eq!(
+        op_in(&[DataValue::Null, list.clone()]).unwrap(),
+        DataValue::Null
+    );
+    assert_eq!(
+        op_in(&[DataValue::Null, DataValue::List(vec![])]).unwrap(),
+        DataValue::from(false)
+    );
+    assert_eq!(
+        op_not_in(&[DataValue::from(1), list.clone()]).unwrap(),
+        DataValue::from(false)
+    );
+    assert_eq!(
+        op_not_in(&[DataValue::from(2), list]).unwrap(),
+        DataValue::Null
+    );
+    assert_eq!(
+        op_not_in(&[
+            DataValue::from(2),
+            DataValue::List(vec![DataValue::from(1)])
+        ])
+        .unwrap(),
+        DataValue::from(true)
+    );
+    assert!(op_in(&[DataValue::from(1), DataValue::from(1)]).is_err());
+}
+
 #[test]
 fn test_comparators() {
     assert_eq!(
@@ -561,6 +599,24 @@ fn test_boolean() {
         op_negate(&[DataValue::from(false)]).unwrap(),
         DataValue::from(true)
     );
+    assert_eq!(
+        op_and(&[DataValue::Null, DataValue::from(false)]).unwrap(),
+        DataValue::from(false)
+    );
+    assert_eq!(
+        op_and(&[DataValue::Null, DataValue::from(true)]).unwrap(),
+        DataValue::Null
+    );
+    assert_eq!(
+        op_or(&[DataValue::Null, DataValue::from(true)]).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        op_or(&[DataValue::Null, DataValue::from(false)]).unwrap(),
+        DataValue::Null
+    );
+    assert_eq!(op_negate(&[DataValue::Null]).unwrap(), DataValue::Null);
+    assert!(op_and(&[DataValue::from(1)]).is_err());
 }
 
 #[test]
@@ -1487,3 +1543,100 @@ fn test_range() {
         .into_json();
     assert_eq!(res["rows"][0][0], json!([15, 13, 11, 9, 7, 5]));
 }
+
+#[test]
+fn test_to_from_json() {
+    let db = DbInstance::default();
+    let res = db
+        .run_default(
+            r#"?[s, v] := m = {'a': 1, 'b': {'c': [1, 'x', null], 'd': true}},
+                          s = to_json(m), v = from_json(s)"#,
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(
+        res["rows"][0][0],
+        json!(r#"{"a":1,"b":{"c":[1,"x",null],"d":true}}"#)
+    );
+    assert_eq!(
+        res["rows"][0][1],
+        json!({"a": 1, "b": {"c": [1, "x", null], "d": true}})
+    );
+    let res = db
+        .run_default("?[a, b] := a = to_json([1, 'x']), b = from_json(a)")
+        .unwrap()
+        .rows;
+    assert_eq!(res[0][0], DataValue::from("[1,\"x\"]"));
+    assert_eq!(
+        res[0][1],
+        DataValue::List(vec![DataValue::from(1), DataValue::from("x")])
+    );
+    assert_eq!(
+        op_to_json(&[DataValue::from(1.5)]).unwrap(),
+        DataValue::from("1.5")
+    );
+    assert!(op_from_json(&[DataValue::from("{not json")]).is_err());
+    assert_eq!(
+        op_from_json(&[DataValue::from("{not json"), DataValue::from(true)]).unwrap(),
+        DataValue::Null
+    );
+    assert!(op_from_json(&[DataValue::from("[1]"), DataValue::from(1)]).is_err());
+}
+
+#[test]
+fn test_cast() {
+    let db = DbInstance::default();
+    let cast = |expr: &str| {
+        db.run_default(&format!("?[a] := a = {expr}"))
+            .map(|res| res.rows[0][0].clone())
+    };
+    assert_eq!(cast("cast('42' as int)").unwrap(), DataValue::from(42));
+    assert_eq!(cast("cast(-3.9 as int)").unwrap(), DataValue::from(-3));
+    assert_eq!(cast("cast(true as int)").unwrap(), DataValue::from(1));
+    assert_eq!(
+        cast("cast(' 2.5 ' as float)").unwrap(),
+        DataValue::from(2.5)
+    );
+    assert_eq!(cast("cast(2 as float)").unwrap(), DataValue::from(2.0));
+    assert_eq!(cast("cast(12 as string)").unwrap(), DataValue::from("12"));
+    assert_eq!(
+        cast("cast([1, 'a'] as String)").unwrap(),
+        DataValue::from("[1,\"a\"]")
+    );
+    assert_eq!(
+        cast("cast('false' as bool)").unwrap(),
+        DataValue::from(false)
+    );
+    assert_eq!(cast("cast(0.5 as bool)").unwrap(), DataValue::from(true));
+    assert_eq!(
+        cast("cast('8d3c7a0e-6b5e-11ed-9022-0242ac120002' as uuid)").unwrap(),
+        DataValue::uuid(uuid::Uuid::parse_str("8d3c7a0e-6b5e-11ed-9022-0242ac120002").unwrap())
+    );
+    assert_eq!(
+        cast("cast([1, 2] as json)").unwrap(),
+        DataValue::Json(JsonData(json!([1, 2])))
+    );
+    assert_eq!(cast("cast(null as int)").unwrap(), DataValue::Null);
+
+    assert!(cast("cast('abc' as int)").is_err());
+    assert_eq!(cast("try_cast('abc' as int)").unwrap(), DataValue::Null);
+    assert_eq!(cast("try_cast('7' as int)").unwrap(), DataValue::from(7));
+    assert!(cast("cast(1.0 / 0 as int)").is_err());
+    assert!(cast("cast(1e30 as int)").is_err());
+    assert_eq!(cast("try_cast(-1e300 as int)").unwrap(), DataValue::Null);
+    assert_eq!(
+        cast("cast(-9223372036854775808.0 as int)").unwrap(),
+        DataValue::from(i64::MIN)
+    );
+    assert!(cast("cast(9223372036854775808.0 as int)").is_err());
+    assert_eq!(cast("try_cast('yes' as bool)").unwrap(), DataValue::Null);
+    assert!(cast("cast([1] as float)").is_err());
+    assert!(cast("try_cast(1 as date)").is_err());
+    assert!(cast("cast(1 as date)").is_err());
+
+    assert_eq!(
+        op_cast(&[DataValue::from("5"), DataValue::from("int")]).unwrap(),
+        DataValue::from(5)
+    );
+    assert!(op_try_cast(&[DataValue::from("5"), DataValue::from("date")]).is_err());
+}
@@ -7,10 +7,15 @@
  *
  */
 
+use ndarray::Array1;
+use proptest::prelude::*;
+use regex::Regex;
+use serde_json::json;
 use uuid::Uuid;
 
 use crate::data::memcmp::{decode_bytes, MemCmpEncoder};
-use crate::data::value::{DataValue, Num, UuidWrapper};
+use crate::data::tuple::{decode_key, encode_key, stable_tuple_hash};
+use crate::data::value::{DataValue, JsonData, Num, RegexWrapper, UuidWrapper, Validity, Vector};
 
 #[test]
 fn encode_decode_num() {
@@ -135,3 +140,148 @@ fn encode_decode_datavalues() {
     assert!(remaining.is_empty());
     assert_eq!(decoded, v);
 }
+
+#[test]
+fn stable_tuple_hashes() {
+    // these must never change, as data may be sharded by them
+    let cases = [
+        (vec![], 17241709254077376921),
+        (vec![DataValue::Null], 9962287286179718960),
+        (vec![DataValue::from(1)], 7695144972122396715),
+        (vec![DataValue::from(1.0)], 16904033413031700750),
+        (vec![DataValue::from("hello")], 16123266204179787976),
+        (
+            vec![
+                DataValue::from(1),
+                DataValue::from("a"),
+                DataValue::from(true),
+            ],
+            100135120060392702,
+        ),
+        (
+            vec![DataValue::List(vec![DataValue::from(-2), DataValue::Null])],
+            14507993387258419099,
+        ),
+    ];
+    for (tuple, expected) in cases {
+        assert_eq!(stable_tuple_hash(&tuple), expected);
+    }
+}
+
+fn arb_float() -> impl Strategy<Value = f64> {
+    prop_oneof![
+        any::<f64>(),
+        Just(0.0),
+        Just(-0.0),
+        Just(f64::NAN),
+        Just(f64::INFINITY),
+        Just(f64::NEG_INFINITY),
+        (-1000i64..1000).prop_map(|i| i as f64),
+    ]
+}
+
+fn arb_int() -> impl Strategy<Value = i64> {
+    prop_oneof![
+        any::<i64>(),
+        -1000i64..1000,
+        (0u32..64).prop_map(|shift| 1i64.wrapping_shl(shift)),
+        Just(i64::MAX),
+        Just(i64::MIN),
+    ]
+}
+
+fn arb_value() -> impl Strategy<Value = DataValue> {
+    let leaf = prop_oneof![
+        Just(DataValue::Null),
+        Just(DataValue::Bot),
+        any::<bool>().prop_map(DataValue::from),
+        arb_int().prop_map(DataValue::from),
+        arb_float().prop_map(DataValue::from),
+        ".{0,12}".prop_map(|s: String| DataValue::from(s)),
+        prop::collection::vec(any::<u8>(), 0..20).prop_map(DataValue::Bytes),
+        any::<u128>().prop_map(|u| DataValue::Uuid(UuidWrapper(Uuid::from_u128(u)))),
+        prop::sample::select(vec!["a", "a+", "[a-z]*", "^x|y$"])
+            .prop_map(|r| DataValue::Regex(RegexWrapper(Regex::new(r).unwrap()))),
+        prop::collection::vec(any::<f32>(), 0..4)
+            .prop_map(|v| DataValue::Vec(Vector::F32(Array1::from(v)))),
+        prop::collection::vec(arb_float(), 0..4)
+            .prop_map(|v| DataValue::Vec(Vector::F64(Array1::from(v)))),
+        (arb_int(), any::<bool>()).prop_map(|v| DataValue::Validity(Validity::from(v))),
+        prop_oneof![
+            arb_int().prop_map(|i| json!(i)),
+            ".{0,6}".prop_map(|s: String| json!(s)),
+            prop::collection::vec(-10i64..10, 0..3).prop_map(|v| json!(v)),
+        ]
+        .prop_map(|j| DataValue::Json(JsonData(j))),
+    ];
+    leaf.prop_recursive(3, 24, 4, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..4).prop_map(DataValue::List),
+            prop::collection::btree_set(inner, 0..4).prop_map(DataValue::Set),
+        ]
+    })
+}
+
+proptest! {
+    #[test]
+    fn key_round_trip(values in prop::collection::vec(arb_value(), 0..4)) {
+        let encoded = encode_key(&values);
+        prop_assert_eq!(decode_key(&encoded).unwrap(), values);
+    }
+
+    #[test]
+    fn key_order_matches_value_order(a in arb_value(), b in arb_value()) {
+        let ea = encode_key(&[a.clone()]);
+        let eb = encode_key(&[b.clone()]);
+        prop_assert_eq!(ea.cmp(&eb), a.cmp(&b), "{:?} vs {:?}", a, b);
+    }
+
+    #[test]
+    fn key_order_matches_tuple_order(
+        a in prop::collection::vec(arb_value(), 0..3),
+        b in prop::collection::vec(arb_value(), 0..3),
+    ) {
+        prop_assert_eq!(encode_key(&a).cmp(&encode_key(&b)), a.cmp(&b));
+    }
+}
+
+#[test]
+fn vectors_sort_as_encoded() {
+    let vecs = [
+        vec![0.0f64, 1.0],
+        vec![-0.0, 1.0],
+        vec![1.0, -1.0],
+        vec![-1.0, 1.0],
+        vec![f64::NAN, 0.0],
+        vec![-f64::NAN, 0.0],
+        vec![f64::NEG_INFINITY, 0.0],
+    ]
+    .map(|v| DataValue::Vec(Vector::F64(Array1::from(v))));
+    let vecs_f32 = [
+        vec![0.0f32],
+        vec![-0.0],
+        vec![-1.0],
+        vec![1.0],
+        vec![f32::NAN],
+    ]
+    .map(|v| DataValue::Vec(Vector::F32(Array1::from(v))));
+    for vecs in [&vecs[..], &vecs_f32[..]] {
+        for a in vecs {
+            for b in vecs {
+                assert_eq!(
+                    encode_key(&[a.clone()]).cmp(&encode_key(&[b.clone()])),
+                    a.cmp(b),
+                    "{a:?} vs {b:?}"
+                );
+            }
+        }
+    }
+    // elements compare numerically, with -0.0 equal to 0.0 and NaN after everything else
+    assert!(vecs[3] < vecs[2]);
+    assert_eq!(vecs[0], vecs[1]);
+    assert_eq!(vecs[4], vecs[5]);
+    assert!(vecs[6] < vecs[3] && vecs[2] < vecs[4]);
+    assert!(vecs_f32[2] < vecs_f32[3]);
+    assert_eq!(vecs_f32[0], vecs_f32[1]);
+    assert!(decode_key(&[0xfe]).is_err());
+}
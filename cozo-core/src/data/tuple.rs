@@ -7,12 +7,15 @@
  */
 
 use crate::data::functions::TERMINAL_VALIDITY;
-use miette::Result;
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
 use std::cmp::Reverse;
+use std::hash::Hasher;
+use twox_hash::XxHash64;
 
 use crate::data::memcmp::MemCmpEncoder;
 use crate::data::value::{DataValue, Validity, ValidityTs};
-use crate::runtime::relation::RelationId;
+use crate::runtime::relation::{CorruptData, RelationId};
 
 pub type Tuple = Vec<DataValue>;
 
@@ -38,15 +41,66 @@ where
     }
 }
 
-pub fn decode_tuple_from_key(key: &[u8], size_hint: usize) -> Tuple {
+/// A hash of the tuple that stays the same across releases, for use outside the database,
+/// e.g. to shard tuples or keys across several databases.
+///
+/// It is the 64-bit xxHash, with seed 0, of the values encoded one after another as they are
+/// in the keys of stored relations. As this encoding is part of the storage format, the hash
+/// of a tuple does not change between versions. Values that compare equal but are encoded
+/// differently, such as `1` and `1.0`, hash differently.
+pub fn stable_tuple_hash(tuple: &[DataValue]) -> u64 {
+    let mut encoded = Vec::with_capacity(4 * tuple.len() + 10 * tuple.len());
+    for val in tuple {
+        encoded.encode_datavalue(val);
+    }
+    let mut hasher = XxHash64::with_seed(0);
+    hasher.write(&encoded);
+    hasher.finish()
+}
+
+/// Encodes values one after another as they are in the keys of stored relations.
+///
+/// The bytewise order of encoded values agrees with the order of the values, so the
+/// encoding can be used as a sort key outside the database. A stored key is the id of
+/// its relation, as a big-endian `u64`, followed by this encoding of the key columns.
+pub fn encode_key(values: &[DataValue]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(14 * values.len());
+    for val in values {
+        ret.encode_datavalue(val);
+    }
+    ret
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot decode key: {0}")]
+#[diagnostic(code(data::bad_key))]
+struct BadKey(String);
+
+/// Decodes bytes produced by [encode_key] back into values.
+pub fn decode_key(bytes: &[u8]) -> Result<Tuple> {
+    let mut remaining = bytes;
+    let mut ret = vec![];
+    while !remaining.is_empty() {
+        let (val, next) = DataValue::try_decode_from_key(remaining).map_err(BadKey)?;
+        ret.push(val);
+        remaining = next;
+    }
+    Ok(ret)
+}
+
+/// Decodes the key columns of a stored key, failing with the reason if the key is malformed.
+pub(crate) fn try_decode_tuple_from_key(key: &[u8], size_hint: usize) -> Result<Tuple, String> {
+    if key.len() < ENCODED_KEY_MIN_LEN {
+        return Err(format!("key of {} bytes is too short", key.len()));
+    }
     let mut remaining = &key[ENCODED_KEY_MIN_LEN..];
     let mut ret = Vec::with_capacity(size_hint);
     while !remaining.is_empty() {
-        let (val, next) = DataValue::decode_from_key(remaining);
+        let (val, next) = DataValue::try_decode_from_key(remaining)?;
         ret.push(val);
         remaining = next;
     }
-    ret
+    Ok(ret)
 }
 
 const DEFAULT_SIZE_HINT: usize = 16;
@@ -56,13 +110,21 @@ const DEFAULT_SIZE_HINT: usize = 16;
 /// Returns two elements, the first element contains `Some(tuple)` if the key should be included
 /// in the return set and `None` otherwise,
 /// the second element gives the next binary key for the seek to be used as an inclusive
-/// lower bound.
-pub fn check_key_for_validity(key: &[u8], valid_at: ValidityTs, size_hint: Option<usize>) -> (Option<Tuple>, Vec<u8>) {
-    let mut decoded = decode_tuple_from_key(key, size_hint.unwrap_or(DEFAULT_SIZE_HINT));
+/// lower bound. Fails with [CorruptData] if the key is malformed.
+pub(crate) fn try_check_key_for_validity(
+    key: &[u8],
+    valid_at: ValidityTs,
+    size_hint: Option<usize>,
+) -> Result<(Option<Tuple>, Vec<u8>)> {
+    let mut decoded = try_decode_tuple_from_key(key, size_hint.unwrap_or(DEFAULT_SIZE_HINT))
+        .map_err(|reason| CorruptData::new(key, reason))?;
     let rel_id = RelationId::raw_decode(key);
-    let vld = match decoded.last().unwrap() {
-        DataValue::Validity(vld) => vld,
-        _ => unreachable!(),
+    let vld = match decoded.last() {
+        Some(DataValue::Validity(vld)) => *vld,
+        _ => bail!(CorruptData::new(
+            key,
+            "the key does not end with a validity".to_string()
+        )),
     };
     if vld.timestamp < valid_at {
         *decoded.last_mut().unwrap() = DataValue::Validity(Validity {
@@ -70,16 +132,16 @@ pub fn check_key_for_validity(key: &[u8], valid_at: ValidityTs, size_hint: Optio
             is_assert: Reverse(true),
         });
         let nxt_seek = decoded.encode_as_key(rel_id);
-        (None, nxt_seek)
+        Ok((None, nxt_seek))
     } else if !vld.is_assert.0 {
         *decoded.last_mut().unwrap() = DataValue::Validity(TERMINAL_VALIDITY);
         let nxt_seek = decoded.encode_as_key(rel_id);
-        (None, nxt_seek)
+        Ok((None, nxt_seek))
     } else {
         let ret = decoded.clone();
         *decoded.last_mut().unwrap() = DataValue::Validity(TERMINAL_VALIDITY);
         let nxt_seek = decoded.encode_as_key(rel_id);
-        (Some(ret), nxt_seek)
+        Ok((Some(ret), nxt_seek))
     }
 }
 
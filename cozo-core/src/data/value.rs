@@ -139,10 +139,14 @@ impl From<(i64, bool)> for Validity {
     }
 }
 
-/// A Value in the database
-#[derive(
-Clone, PartialEq, Eq, PartialOrd, Ord, serde_derive::Deserialize, serde_derive::Serialize, Hash,
-)]
+/// A Value in the database.
+///
+/// Values are totally ordered. Values of different types are ordered by type:
+/// null < bool < vector < number < string < bytes < UUID < regex < list < set
+/// < validity < JSON, which is also their order in the keys of stored relations.
+/// Within numbers, ints and floats compare by value, with an int coming before
+/// a float equal to it.
+#[derive(Clone, PartialEq, Eq, serde_derive::Deserialize, serde_derive::Serialize, Hash)]
 pub enum DataValue {
     /// null
     Null,
@@ -173,6 +177,53 @@ pub enum DataValue {
     Bot,
 }
 
+impl DataValue {
+    /// Position of the type of the value in the total order, agreeing with the tags
+    /// of the memcmp encoding
+    fn type_rank(&self) -> u8 {
+        match self {
+            DataValue::Null => 0,
+            DataValue::Bool(_) => 1,
+            DataValue::Vec(_) => 2,
+            DataValue::Num(_) => 3,
+            DataValue::Str(_) => 4,
+            DataValue::Bytes(_) => 5,
+            DataValue::Uuid(_) => 6,
+            DataValue::Regex(_) => 7,
+            DataValue::List(_) => 8,
+            DataValue::Set(_) => 9,
+            DataValue::Validity(_) => 10,
+            DataValue::Json(_) => 11,
+            DataValue::Bot => 12,
+        }
+    }
+}
+
+impl PartialOrd for DataValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DataValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (DataValue::Bool(l), DataValue::Bool(r)) => l.cmp(r),
+            (DataValue::Vec(l), DataValue::Vec(r)) => l.cmp(r),
+            (DataValue::Num(l), DataValue::Num(r)) => l.cmp(r),
+            (DataValue::Str(l), DataValue::Str(r)) => l.cmp(r),
+            (DataValue::Bytes(l), DataValue::Bytes(r)) => l.cmp(r),
+            (DataValue::Uuid(l), DataValue::Uuid(r)) => l.cmp(r),
+            (DataValue::Regex(l), DataValue::Regex(r)) => l.cmp(r),
+            (DataValue::List(l), DataValue::List(r)) => l.cmp(r),
+            (DataValue::Set(l), DataValue::Set(r)) => l.cmp(r),
+            (DataValue::Validity(l), DataValue::Validity(r)) => l.cmp(r),
+            (DataValue::Json(l), DataValue::Json(r)) => l.cmp(r),
+            _ => self.type_rank().cmp(&other.type_rank()),
+        }
+    }
+}
+
 /// Wrapper for JsonValue
 #[derive(Clone, PartialEq, Eq, serde_derive::Deserialize, serde_derive::Serialize)]
 pub struct JsonData(pub JsonValue);
@@ -490,6 +541,12 @@ impl<T: Into<DataValue>> From<Vec<T>> for DataValue {
 }
 
 /// Representing a number
+///
+/// Literals without a decimal point or an exponent, such as `100`, are parsed as `Int`,
+/// and all others, such as `100.0` or `1e2`, as `Float`. The two are distinct values:
+/// they sort apart (an `Int` just before an equal `Float`), may both be keys of the
+/// same relation and serialize differently, though `100 == 100.0` is true in queries,
+/// where `==` compares numbers by value.
 #[derive(Copy, Clone, serde_derive::Deserialize, serde_derive::Serialize)]
 pub enum Num {
     /// intger number
@@ -612,7 +669,7 @@ impl Display for DataValue {
             DataValue::Str(s) => write!(f, "{s:?}"),
             DataValue::Bytes(b) => {
                 let bs = STANDARD.encode(b);
-                write!(f, "decode_base64({bs:?})")
+                write!(f, "b64({bs:?})")
             }
             DataValue::Uuid(u) => {
                 let us = u.0.to_string();
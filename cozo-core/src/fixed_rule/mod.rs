@@ -28,13 +28,14 @@ use crate::data::program::{
     WrongFixedRuleOptionError,
 };
 use crate::data::symb::Symbol;
-use crate::data::tuple::TupleIter;
+use crate::data::tuple::{Tuple, TupleIter};
 use crate::data::value::DataValue;
 #[cfg(feature = "graph-algo")]
 use crate::fixed_rule::algos::*;
 use crate::fixed_rule::utilities::*;
 use crate::parse::SourceSpan;
 use crate::runtime::db::Poison;
+use crate::runtime::relation::RelationHandle;
 use crate::runtime::temp_store::{EpochStore, RegularTempStore};
 use crate::runtime::transact::SessionTx;
 use crate::NamedRows;
@@ -50,6 +51,19 @@ pub struct FixedRulePayload<'a, 'b> {
     pub(crate) tx: &'a SessionTx<'b>,
 }
 
+/// The removal mark of a relation with soft deletes, or the expiry mark of one with a TTL,
+/// is not part of its rows as seen by fixed rules: it would otherwise be taken for edge weights.
+fn without_trailing_mark<'a>(relation: &RelationHandle, it: TupleIter<'a>) -> TupleIter<'a> {
+    if relation.has_trailing_mark() {
+        Box::new(it.map_ok(|mut tuple| {
+            tuple.pop();
+            tuple
+        }))
+    } else {
+        it
+    }
+}
+
 /// Represents an input relation during the execution of a fixed rule
 #[derive(Copy, Clone)]
 pub struct FixedRuleInputRelation<'a, 'b> {
@@ -93,11 +107,12 @@ impl<'a, 'b> FixedRuleInputRelation<'a, 'b> {
             }
             MagicFixedRuleRuleArg::Stored { name, valid_at, .. } => {
                 let relation = self.tx.get_relation(name, false)?;
-                if let Some(valid_at) = valid_at {
+                let it: TupleIter<'a> = if let Some(valid_at) = valid_at {
                     Box::new(relation.skip_scan_all(self.tx, *valid_at))
                 } else {
                     Box::new(relation.scan_all(self.tx))
-                }
+                };
+                without_trailing_mark(&relation, it)
             }
         })
     }
@@ -114,11 +129,53 @@ impl<'a, 'b> FixedRuleInputRelation<'a, 'b> {
             MagicFixedRuleRuleArg::Stored { name, valid_at, .. } => {
                 let relation = self.tx.get_relation(name, false)?;
                 let t = vec![prefix.clone()];
-                if let Some(valid_at) = valid_at {
+                let it: TupleIter<'_> = if let Some(valid_at) = valid_at {
                     Box::new(relation.skip_scan_prefix(self.tx, &t, *valid_at))
                 } else {
                     Box::new(relation.scan_prefix(self.tx, &t))
-                }
+                };
+                without_trailing_mark(&relation, it)
+            }
+        })
+    }
+    /// The number of key columns: those of a stored relation, not counting the validity
+    /// when it is read at a point in time, or all the columns of a rule.
+    pub fn key_arity(&self) -> Result<usize> {
+        Ok(match self.arg_manifest {
+            MagicFixedRuleRuleArg::InMem { .. } => self.arity()?,
+            MagicFixedRuleRuleArg::Stored { name, valid_at, .. } => {
+                let relation = self.tx.get_relation(name, false)?;
+                relation.metadata.keys.len() - usize::from(valid_at.is_some())
+            }
+        })
+    }
+    /// Get the row with the given key, which must have [Self::key_arity] values
+    pub fn get(&self, key: &[DataValue]) -> Result<Option<Tuple>> {
+        let key = key.to_vec();
+        Ok(match self.arg_manifest {
+            MagicFixedRuleRuleArg::InMem { name, .. } => {
+                let store = self.stores.get(name).ok_or_else(|| {
+                    RuleNotFoundError(name.symbol().to_string(), name.symbol().span)
+                })?;
+                let found = store.prefix_iter(&key).next();
+                found.map(|t| t.into_tuple())
+            }
+            MagicFixedRuleRuleArg::Stored { name, valid_at, .. } => {
+                let relation = self.tx.get_relation(name, false)?;
+                let found = if let Some(valid_at) = valid_at {
+                    relation
+                        .skip_scan_prefix(self.tx, &key, *valid_at)
+                        .next()
+                        .transpose()?
+                } else {
+                    relation.get(self.tx, &key)?
+                };
+                found.map(|mut tuple| {
+                    if relation.has_trailing_mark() {
+                        tuple.pop();
+                    }
+                    tuple
+                })
             }
         })
     }
@@ -819,6 +876,22 @@ lazy_static! {
                 "ReorderSort".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(ReorderSort)),
             ),
+            (
+                "LatestBy".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(LatestBy)),
+            ),
+            (
+                "Fetch".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(Fetch)),
+            ),
+            (
+                "Relations".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(Relations)),
+            ),
+            (
+                "Columns".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(Columns)),
+            ),
             (
                 "JsonReader".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(JsonReader)),
@@ -913,7 +986,11 @@ impl MagicFixedRuleRuleArg {
             }
             MagicFixedRuleRuleArg::Stored { name, .. } => {
                 let handle = tx.get_relation(name, false)?;
-                handle.arity()
+                if handle.has_trailing_mark() {
+                    handle.arity() - 1
+                } else {
+                    handle.arity()
+                }
             }
         })
     }
@@ -0,0 +1,114 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use miette::{bail, ensure, Result};
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::program::WrongFixedRuleOptionError;
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{CannotDetermineArity, FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Gets the rows of the input relation with the given `keys`, looking each key up once
+/// in key order. A key is a list of the key values, or the value itself for relations with
+/// a single key column. With `keep_missing: true`, keys without a row give the key
+/// followed by nulls.
+pub(crate) struct Fetch;
+
+impl FixedRule for Fetch {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let in_rel = payload.get_input(0)?;
+        let arity = in_rel.arity()?;
+        ensure!(
+            arity == payload.manifest.arity,
+            WrongFixedRuleOptionError {
+                name: "head".to_string(),
+                span: payload.span(),
+                rule_name: payload.name().to_string(),
+                help: format!(
+                    "The rule head must have the same arity as the input relation, which is {arity}"
+                ),
+            }
+        );
+        let key_arity = in_rel.key_arity()?;
+        let keep_missing = payload.bool_option("keep_missing", Some(false))?;
+
+        let bad_keys = |help: String| -> Result<WrongFixedRuleOptionError> {
+            Ok(WrongFixedRuleOptionError {
+                name: "keys".to_string(),
+                span: payload.option_span("keys")?,
+                rule_name: payload.name().to_string(),
+                help,
+            })
+        };
+        let keys = match payload.expr_option("keys", None)?.eval_to_const()? {
+            DataValue::List(l) => l,
+            _ => bail!(bad_keys("a list of keys is required".to_string())?),
+        };
+        let mut keys: Vec<Tuple> = keys
+            .into_iter()
+            .map(|key| -> Result<Tuple> {
+                let key = match key {
+                    DataValue::List(l) if key_arity != 1 || l.len() == 1 => l,
+                    v => vec![v],
+                };
+                ensure!(
+                    key.len() == key_arity,
+                    bad_keys(format!(
+                        "each key must have {key_arity} value(s), but {key:?} does not"
+                    ))?
+                );
+                Ok(key)
+            })
+            .collect::<Result<_>>()?;
+        keys.sort();
+        keys.dedup();
+
+        for key in keys {
+            match in_rel.get(&key)? {
+                Some(tuple) => out.put(tuple),
+                None if keep_missing => {
+                    let mut tuple = key;
+                    tuple.resize(arity, DataValue::Null);
+                    out.put(tuple)
+                }
+                None => {}
+            }
+            poison.check()?;
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        rule_head: &[Symbol],
+        span: SourceSpan,
+    ) -> Result<usize> {
+        if rule_head.is_empty() {
+            bail!(CannotDetermineArity(
+                "Fetch".to_string(),
+                "the rule head must name the columns of the input relation".to_string(),
+                span
+            ))
+        }
+        Ok(rule_head.len())
+    }
+}
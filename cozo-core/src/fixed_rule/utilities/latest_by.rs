@@ -0,0 +1,113 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::hash_map::Entry;
+use std::collections::BTreeMap;
+
+use miette::{bail, ensure, Result};
+use rustc_hash::FxHashMap;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::{eval_bytecode, Expr};
+use crate::data::memcmp::MemCmpEncoder;
+use crate::data::program::WrongFixedRuleOptionError;
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{CannotDetermineArity, FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Keeps one row per key: the one for which `by` is largest, or smallest with `keep: 'min'`.
+/// The rows are passed through unchanged, and ties keep the row seen first.
+pub(crate) struct LatestBy;
+
+impl FixedRule for LatestBy {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let in_rel = payload.get_input(0)?;
+        let arity = in_rel.arity()?;
+        ensure!(
+            arity == payload.manifest.arity,
+            WrongFixedRuleOptionError {
+                name: "head".to_string(),
+                span: payload.span(),
+                rule_name: payload.name().to_string(),
+                help: format!(
+                    "The rule head must have the same arity as the input relation, which is {arity}"
+                ),
+            }
+        );
+
+        let mut key = payload.expr_option("key", None)?;
+        let mut by = payload.expr_option("by", None)?;
+        let keep_max = match payload.string_option("keep", Some("max"))?.as_str() {
+            "max" => true,
+            "min" => false,
+            _ => bail!(WrongFixedRuleOptionError {
+                name: "keep".to_string(),
+                span: payload.option_span("keep")?,
+                rule_name: payload.name().to_string(),
+                help: "This option must be either 'max' or 'min'".to_string()
+            }),
+        };
+
+        let binding_map = in_rel.get_binding_map(0);
+        key.fill_binding_indices(&binding_map)?;
+        by.fill_binding_indices(&binding_map)?;
+        let key_bytecodes = key.compile()?;
+        let by_bytecodes = by.compile()?;
+        let mut stack = vec![];
+
+        let mut best: FxHashMap<Vec<u8>, (DataValue, Tuple)> = FxHashMap::default();
+        for tuple in in_rel.iter()? {
+            let tuple = tuple?;
+            let mut encoded_key = vec![];
+            encoded_key.encode_datavalue(&eval_bytecode(&key_bytecodes, &tuple, &mut stack)?);
+            let order = eval_bytecode(&by_bytecodes, &tuple, &mut stack)?;
+            match best.entry(encoded_key) {
+                Entry::Vacant(e) => {
+                    e.insert((order, tuple));
+                }
+                Entry::Occupied(mut e) => {
+                    let current = &e.get().0;
+                    if (keep_max && order > *current) || (!keep_max && order < *current) {
+                        e.insert((order, tuple));
+                    }
+                }
+            }
+            poison.check()?;
+        }
+
+        for (_, tuple) in best.into_values() {
+            out.put(tuple);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        rule_head: &[Symbol],
+        span: SourceSpan,
+    ) -> Result<usize> {
+        if rule_head.is_empty() {
+            bail!(CannotDetermineArity(
+                "LatestBy".to_string(),
+                "the rule head must name the columns of the input relation".to_string(),
+                span
+            ))
+        }
+        Ok(rule_head.len())
+    }
+}
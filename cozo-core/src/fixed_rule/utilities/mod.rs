@@ -8,10 +8,16 @@
 
 pub(crate) mod constant;
 pub(crate) mod csv;
+pub(crate) mod fetch;
 pub(crate) mod jlines;
+pub(crate) mod latest_by;
 pub(crate) mod reorder_sort;
+pub(crate) mod schema;
 
 pub(crate) use self::csv::CsvReader;
 pub(crate) use constant::Constant;
+pub(crate) use fetch::Fetch;
 pub(crate) use jlines::JsonReader;
+pub(crate) use latest_by::LatestBy;
 pub(crate) use reorder_sort::ReorderSort;
+pub(crate) use schema::{Columns, Relations};
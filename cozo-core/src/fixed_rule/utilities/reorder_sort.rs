@@ -6,7 +6,8 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::collections::BTreeMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap};
 
 use itertools::Itertools;
 use miette::{bail, Result};
@@ -16,6 +17,7 @@ use crate::data::expr::{eval_bytecode, Expr};
 use crate::data::functions::OP_LIST;
 use crate::data::program::WrongFixedRuleOptionError;
 use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
 use crate::data::value::DataValue;
 use crate::fixed_rule::{CannotDetermineArity, FixedRule, FixedRulePayload};
 use crate::parse::SourceSpan;
@@ -24,6 +26,40 @@ use crate::runtime::temp_store::RegularTempStore;
 
 pub(crate) struct ReorderSort;
 
+/// A row waiting to be output, ordered by where it goes in its group
+struct SortedRow {
+    sorter: DataValue,
+    seq: usize,
+    descending: bool,
+    row: Tuple,
+}
+
+impl PartialEq for SortedRow {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SortedRow {}
+
+impl PartialOrd for SortedRow {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SortedRow {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let by_sorter = if self.descending {
+            other.sorter.cmp(&self.sorter)
+        } else {
+            self.sorter.cmp(&other.sorter)
+        };
+        // ties keep the input order
+        by_sorter.then(self.seq.cmp(&other.seq))
+    }
+}
+
 impl FixedRule for ReorderSort {
     fn run(
         &self,
@@ -62,6 +98,13 @@ impl FixedRule for ReorderSort {
                 span: SourceSpan(0, 0),
             }),
         )?;
+        let mut group_by = payload.expr_option(
+            "group_by",
+            Some(Expr::Const {
+                val: DataValue::Null,
+                span: SourceSpan(0, 0),
+            }),
+        )?;
         let sort_descending = payload.bool_option("descending", Some(false))?;
         let break_ties = payload.bool_option("break_ties", Some(false))?;
         let skip = payload.non_neg_integer_option("skip", Some(0))?;
@@ -69,57 +112,59 @@ impl FixedRule for ReorderSort {
 
         let binding_map = in_rel.get_binding_map(0);
         sort_by.fill_binding_indices(&binding_map)?;
+        group_by.fill_binding_indices(&binding_map)?;
         for out in out_list.iter_mut() {
             out.fill_binding_indices(&binding_map)?;
         }
         let out_bytecods: Vec<_> = out_list.iter().map(|e| e.compile()).try_collect()?;
         let sort_by_bytecodes = sort_by.compile()?;
+        let group_by_bytecodes = group_by.compile()?;
         let mut stack = vec![];
 
-        let mut buffer = vec![];
-        for tuple in in_rel.iter()? {
+        // with `take`, each group only ever holds the rows that can make it to the output,
+        // the last of which sits on top of the heap
+        let take_plus_skip = take.saturating_add(skip);
+        let mut groups: BTreeMap<DataValue, BinaryHeap<SortedRow>> = BTreeMap::new();
+        for (seq, tuple) in in_rel.iter()?.enumerate() {
             let tuple = tuple?;
+            let group = eval_bytecode(&group_by_bytecodes, &tuple, &mut stack)?;
             let sorter = eval_bytecode(&sort_by_bytecodes, &tuple, &mut stack)?;
-            let mut s_tuple: Vec<_> = out_bytecods
+            let row: Vec<_> = out_bytecods
                 .iter()
                 .map(|ex| eval_bytecode(ex, &tuple, &mut stack))
                 .try_collect()?;
-            s_tuple.push(sorter);
-            buffer.push(s_tuple);
+            let heap = groups.entry(group).or_default();
+            heap.push(SortedRow {
+                sorter,
+                seq,
+                descending: sort_descending,
+                row,
+            });
+            if take != 0 && heap.len() > take_plus_skip {
+                heap.pop();
+            }
             poison.check()?;
         }
-        if sort_descending {
-            buffer.sort_by(|l, r| r.last().cmp(&l.last()));
-        } else {
-            buffer.sort_by(|l, r| l.last().cmp(&r.last()));
-        }
 
-        let mut count = 0usize;
-        let mut rank = 0usize;
-        let mut last = &DataValue::Bot;
-        let take_plus_skip = take.saturating_add(skip);
-        for val in &buffer {
-            let sorter = val.last().unwrap();
-
-            if sorter == last {
+        for heap in groups.into_values() {
+            let mut count = 0usize;
+            let mut rank = 0usize;
+            let mut last = DataValue::Bot;
+            for SortedRow { sorter, row, .. } in heap.into_sorted_vec() {
                 count += 1;
-            } else {
-                count += 1;
-                rank = count;
-                last = sorter;
-            }
+                if sorter != last {
+                    rank = count;
+                    last = sorter;
+                }
 
-            if take != 0 && count > take_plus_skip {
-                break;
+                if count <= skip {
+                    continue;
+                }
+                let mut out_t = vec![DataValue::from(if break_ties { count } else { rank } as i64)];
+                out_t.extend(row);
+                out.put(out_t);
+                poison.check()?;
             }
-
-            if count <= skip {
-                continue;
-            }
-            let mut out_t = vec![DataValue::from(if break_ties { count } else { rank } as i64)];
-            out_t.extend_from_slice(&val[0..val.len() - 1]);
-            out.put(out_t);
-            poison.check()?;
         }
         Ok(())
     }
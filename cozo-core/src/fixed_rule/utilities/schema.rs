@@ -0,0 +1,89 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::relation::{ColumnInfo, RelationInfo};
+use crate::runtime::temp_store::RegularTempStore;
+
+/// The stored relations as a relation, with the same columns as `::relations`.
+pub(crate) struct Relations;
+
+impl FixedRule for Relations {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        _poison: Poison,
+    ) -> Result<()> {
+        for info in payload.tx.relation_infos()? {
+            out.put(info.into_tuple());
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(RelationInfo::HEADERS.len())
+    }
+}
+
+/// The columns of the stored relations, or only of the one given as `relation`,
+/// with the name of the relation followed by the same columns as `::columns`.
+pub(crate) struct Columns;
+
+impl FixedRule for Columns {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let relations = if payload.manifest.options.contains_key("relation") {
+            vec![payload.string_option("relation", None)?.to_string()]
+        } else {
+            payload
+                .tx
+                .relation_infos()?
+                .into_iter()
+                .map(|info| info.name)
+                .collect()
+        };
+        for relation in relations {
+            for info in payload.tx.column_infos(&relation)? {
+                let mut tuple = vec![DataValue::from(relation.as_str())];
+                tuple.extend(info.into_tuple());
+                out.put(tuple);
+            }
+            poison.check()?;
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(ColumnInfo::HEADERS.len() + 1)
+    }
+}
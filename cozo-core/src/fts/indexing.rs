@@ -8,16 +8,16 @@
 
 use crate::data::expr::{eval_bytecode, eval_bytecode_pred, Bytecode};
 use crate::data::program::{FtsScoreKind, FtsSearch};
-use crate::data::tuple::{decode_tuple_from_key, Tuple, ENCODED_KEY_MIN_LEN};
+use crate::data::tuple::{try_decode_tuple_from_key, Tuple, ENCODED_KEY_MIN_LEN};
 use crate::data::value::LARGEST_UTF_CHAR;
 use crate::fts::ast::{FtsExpr, FtsLiteral, FtsNear};
 use crate::fts::tokenizer::TextAnalyzer;
 use crate::parse::fts::parse_fts_query;
-use crate::runtime::relation::RelationHandle;
+use crate::runtime::relation::{CorruptData, RelationHandle};
 use crate::runtime::transact::SessionTx;
 use crate::{DataValue, SourceSpan};
 use itertools::Itertools;
-use miette::{bail, miette, Diagnostic, Result};
+use miette::{bail, Diagnostic, Result};
 use ordered_float::OrderedFloat;
 use rustc_hash::{FxHashMap, FxHashSet};
 use smartstring::{LazyCompact, SmartString};
@@ -74,8 +74,17 @@ impl<'a> SessionTx<'a> {
         let mut results = vec![];
         for item in self.store_tx.range_scan(&start_key_bytes, &end_key_bytes) {
             let (kvec, vvec) = item?;
-            let key_tuple = decode_tuple_from_key(&kvec, idx_handle.metadata.keys.len());
-            let found_str_key = key_tuple[0].get_str().unwrap();
+            let corrupt = |reason: &str| CorruptData {
+                table: idx_handle.name.to_string(),
+                key_bytes: kvec.clone(),
+                reason: reason.to_string(),
+            };
+            let key_tuple = try_decode_tuple_from_key(&kvec, idx_handle.metadata.keys.len())
+                .map_err(|reason| corrupt(&reason))?;
+            let found_str_key = key_tuple
+                .first()
+                .and_then(|v| v.get_str())
+                .ok_or_else(|| corrupt("the indexed token is not a string"))?;
             if literal.is_prefix {
                 if !found_str_key.starts_with(start_key_str) {
                     break;
@@ -84,21 +93,28 @@ impl<'a> SessionTx<'a> {
                 break;
             }
 
-            let vals: Vec<DataValue> = rmp_serde::from_slice(&vvec[ENCODED_KEY_MIN_LEN..]).unwrap();
-            let froms = vals[0].get_slice().unwrap();
-            let tos = vals[1].get_slice().unwrap();
-            let positions = vals[2].get_slice().unwrap();
+            let vals: Vec<DataValue> = vvec
+                .get(ENCODED_KEY_MIN_LEN..)
+                .and_then(|v| rmp_serde::from_slice(v).ok())
+                .ok_or_else(|| corrupt("the positions of the token cannot be decoded"))?;
+            let bad_positions = || corrupt("the positions of the token are not lists of integers");
+            let slice_at = |i: usize| vals.get(i).and_then(|v| v.get_slice());
+            let froms = slice_at(0).ok_or_else(bad_positions)?;
+            let tos = slice_at(1).ok_or_else(bad_positions)?;
+            let positions = slice_at(2).ok_or_else(bad_positions)?;
             // let total_length = vals[3].get_int().unwrap();
             let position_info = froms
                 .iter()
                 .zip(tos.iter())
                 .zip(positions.iter())
-                .map(|(_, p)| PositionInfo {
-                    // from: f.get_int().unwrap() as u32,
-                    // to: t.get_int().unwrap() as u32,
-                    position: p.get_int().unwrap() as u32,
+                .map(|(_, p)| {
+                    Ok(PositionInfo {
+                        // from: f.get_int().unwrap() as u32,
+                        // to: t.get_int().unwrap() as u32,
+                        position: p.get_int().ok_or_else(bad_positions)? as u32,
+                    })
                 })
-                .collect_vec();
+                .collect::<Result<Vec<_>, CorruptData>>()?;
             results.push(LiteralStats {
                 key: key_tuple[1..].to_vec(),
                 position_info,
@@ -263,21 +279,33 @@ impl<'a> SessionTx<'a> {
         } else {
             0
         };
-        let mut result: Vec<_> = self
-            .fts_search_impl(&ast, config, n)?
-            .into_iter()
-            .collect();
+        let found = match &ast {
+            // documents matching more of the terms score higher
+            FtsExpr::And(terms) if config.match_any => {
+                let mut res: FxHashMap<Tuple, f64> = FxHashMap::default();
+                for term in terms {
+                    for (k, v) in self.fts_search_impl(term, config, n)? {
+                        *res.entry(k).or_default() += v;
+                    }
+                }
+                res
+            }
+            ast => self.fts_search_impl(ast, config, n)?,
+        };
+        let mut result: Vec<_> = found.into_iter().collect();
         result.sort_by_key(|(_, score)| Reverse(OrderedFloat(*score)));
-        if config.filter.is_none() {
+        // expired rows are skipped like filtered ones
+        if config.filter.is_none() && config.base_handle.ttl.is_none() {
             result.truncate(config.k);
         }
 
         let mut ret = Vec::with_capacity(config.k);
         for (found_key, score) in result {
-            let mut cand_tuple = config
-                .base_handle
-                .get(self, &found_key)?
-                .ok_or_else(|| miette!("corrupted index"))?;
+            let mut cand_tuple = match config.base_handle.get(self, &found_key)? {
+                Some(tuple) => tuple,
+                None if config.base_handle.ttl.is_some() => continue,
+                None => bail!("corrupted index"),
+            };
 
             if config.bind_score.is_some() {
                 cand_tuple.push(DataValue::from(score));
@@ -32,13 +32,13 @@
 #![allow(clippy::too_many_arguments)]
 
 use std::collections::BTreeMap;
+use std::io::Read;
 use std::path::Path;
 use std::sync::Arc;
 #[allow(unused_imports)]
 use std::time::Instant;
 
 use crossbeam::channel::{bounded, Receiver, Sender};
-use data::functions::current_validity;
 use lazy_static::lazy_static;
 pub use miette::Error;
 use miette::Report;
@@ -47,15 +47,20 @@ use miette::{
     bail, miette, GraphicalReportHandler, GraphicalTheme, IntoDiagnostic, JSONReportHandler,
     Result, ThemeCharacters, ThemeStyles,
 };
-use parse::parse_script;
 use parse::CozoScript;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json::json;
 
+pub use data::tuple::{decode_key, encode_key, stable_tuple_hash};
 pub use data::value::{DataValue, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs};
 pub use fixed_rule::{FixedRule, FixedRuleInputRelation, FixedRulePayload};
 pub use runtime::db::Db;
 pub use runtime::db::NamedRows;
 pub use runtime::relation::decode_tuple_from_kv;
+pub use runtime::relation::try_decode_tuple_from_kv;
+pub use runtime::relation::CorruptData;
+pub use runtime::relation::{ColumnInfo, RelationInfo};
 pub use runtime::temp_store::RegularTempStore;
 pub use storage::mem::{new_cozo_mem, MemStorage};
 #[cfg(feature = "storage-rocksdb")]
@@ -67,20 +72,32 @@ pub use storage::sqlite::{new_cozo_sqlite, SqliteStorage};
 #[cfg(feature = "storage-tikv")]
 pub use storage::tikv::{new_cozo_tikv, TiKvStorage};
 pub use storage::{Storage, StoreTx};
+#[cfg(feature = "arrow")]
+pub use {arrow_array, arrow_schema};
 
 pub use crate::data::expr::Expr;
 use crate::data::json::JsonValue;
 pub use crate::data::symb::Symbol;
 pub use crate::data::value::{JsonData, Vector};
 pub use crate::fixed_rule::SimpleFixedRule;
-pub use crate::parse::SourceSpan;
+pub use crate::parse::{ends_with_terminator, ParseLimits, SourceSpan};
+pub use crate::query::builder::{QueryBuilder, Term};
+pub use crate::query::metrics::QueryMetrics;
+#[cfg(feature = "async")]
+pub use crate::runtime::async_db::{AsyncDb, AsyncSession, RowStream};
 pub use crate::runtime::callback::CallbackOp;
 pub use crate::runtime::db::evaluate_expressions;
 pub use crate::runtime::db::get_variables;
 pub use crate::runtime::db::Payload;
 pub use crate::runtime::db::Poison;
+pub use crate::runtime::db::ReadConsistency;
+pub use crate::runtime::db::ResultRowLimitExceeded;
 pub use crate::runtime::db::ScriptMutability;
 pub use crate::runtime::db::TransactionPayload;
+pub use crate::runtime::memory::MemoryLimits;
+pub use crate::runtime::migration::{MigrationPlan, MigrationStep};
+pub use crate::runtime::plan_cache::PlanCacheStats;
+pub use crate::runtime::rows_diff::{diff_rows, diff_rows_ordered, RowsDiff};
 
 pub mod data;
 pub(crate) mod fixed_rule;
@@ -196,17 +213,41 @@ impl DbInstance {
         params: BTreeMap<String, DataValue>,
         mutability: ScriptMutability,
     ) -> Result<NamedRows> {
-        let cur_vld = current_validity();
-        self.run_script_ast(
-            parse_script(payload, &params, &self.get_fixed_rules(), cur_vld)?,
-            cur_vld,
-            mutability,
-        )
+        match self {
+            DbInstance::Mem(db) => db.run_script(payload, params, mutability),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_script(payload, params, mutability),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_script(payload, params, mutability),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_script(payload, params, mutability),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_script(payload, params, mutability),
+        }
     }
     /// `run_script` with mutable script and no parameters
     pub fn run_default(&self, payload: &str) -> Result<NamedRows> {
         self.run_script(payload, BTreeMap::new(), ScriptMutability::Mutable)
     }
+    /// Dispatcher method. See [crate::Db::run_query_after].
+    pub fn run_query_after(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        cursor: Option<&str>,
+    ) -> Result<(NamedRows, Option<String>)> {
+        match self {
+            DbInstance::Mem(db) => db.run_query_after(payload, params, cursor),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_query_after(payload, params, cursor),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_query_after(payload, params, cursor),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_query_after(payload, params, cursor),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_query_after(payload, params, cursor),
+        }
+    }
     /// Run a parsed (AST) program. If you have a string script, use `run_script` or `run_default`.
     pub fn run_script_ast(
         &self,
@@ -299,6 +340,498 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.export_relations(relations),
         }
     }
+    /// Dispatcher method. See [crate::Db::relations].
+    pub fn relations(&self) -> Result<Vec<RelationInfo>> {
+        match self {
+            DbInstance::Mem(db) => db.relations(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.relations(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.relations(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.relations(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.relations(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::relation_disk_usage].
+    pub fn relation_disk_usage(&self, relation: &str) -> Result<u64> {
+        match self {
+            DbInstance::Mem(db) => db.relation_disk_usage(relation),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.relation_disk_usage(relation),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.relation_disk_usage(relation),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.relation_disk_usage(relation),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.relation_disk_usage(relation),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_namespace].
+    pub fn set_namespace(&self, namespace: Option<&str>) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.set_namespace(namespace),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_namespace(namespace),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_namespace(namespace),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_namespace(namespace),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_namespace(namespace),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::namespace].
+    pub fn namespace(&self) -> Option<String> {
+        match self {
+            DbInstance::Mem(db) => db.namespace(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.namespace(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.namespace(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.namespace(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.namespace(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::columns].
+    pub fn columns(&self, relation: &str) -> Result<Vec<ColumnInfo>> {
+        match self {
+            DbInstance::Mem(db) => db.columns(relation),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.columns(relation),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.columns(relation),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.columns(relation),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.columns(relation),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::remove_relation].
+    pub fn remove_relation(&self, relation: &str, cascade: bool) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.remove_relation(relation, cascade),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.remove_relation(relation, cascade),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.remove_relation(relation, cascade),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.remove_relation(relation, cascade),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.remove_relation(relation, cascade),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::rename_relation].
+    pub fn rename_relation(&self, old: &str, new: &str) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.rename_relation(old, new),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.rename_relation(old, new),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.rename_relation(old, new),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.rename_relation(old, new),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.rename_relation(old, new),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::create_index].
+    pub fn create_index(
+        &self,
+        relation: &str,
+        index: &str,
+        columns: &[&str],
+        payload: &[&str],
+    ) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.create_index(relation, index, columns, payload),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.create_index(relation, index, columns, payload),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.create_index(relation, index, columns, payload),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.create_index(relation, index, columns, payload),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.create_index(relation, index, columns, payload),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::parse_limits].
+    pub fn parse_limits(&self) -> ParseLimits {
+        match self {
+            DbInstance::Mem(db) => db.parse_limits(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.parse_limits(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.parse_limits(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.parse_limits(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.parse_limits(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_parse_limits].
+    pub fn set_parse_limits(&self, limits: ParseLimits) {
+        match self {
+            DbInstance::Mem(db) => db.set_parse_limits(limits),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_parse_limits(limits),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_parse_limits(limits),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_parse_limits(limits),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_parse_limits(limits),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_collect_metrics].
+    pub fn set_collect_metrics(&self, enabled: bool) {
+        match self {
+            DbInstance::Mem(db) => db.set_collect_metrics(enabled),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_collect_metrics(enabled),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_collect_metrics(enabled),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_collect_metrics(enabled),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_collect_metrics(enabled),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::last_query_metrics].
+    pub fn last_query_metrics(&self) -> Option<QueryMetrics> {
+        match self {
+            DbInstance::Mem(db) => db.last_query_metrics(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.last_query_metrics(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.last_query_metrics(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.last_query_metrics(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.last_query_metrics(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_plan_cache_size].
+    pub fn set_plan_cache_size(&self, size: usize) {
+        match self {
+            DbInstance::Mem(db) => db.set_plan_cache_size(size),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_plan_cache_size(size),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_plan_cache_size(size),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_plan_cache_size(size),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_plan_cache_size(size),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::plan_cache_stats].
+    pub fn plan_cache_stats(&self) -> PlanCacheStats {
+        match self {
+            DbInstance::Mem(db) => db.plan_cache_stats(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.plan_cache_stats(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.plan_cache_stats(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.plan_cache_stats(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.plan_cache_stats(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::memory_limits].
+    pub fn memory_limits(&self) -> MemoryLimits {
+        match self {
+            DbInstance::Mem(db) => db.memory_limits(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.memory_limits(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.memory_limits(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.memory_limits(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.memory_limits(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_memory_limits].
+    pub fn set_memory_limits(&self, limits: MemoryLimits) {
+        match self {
+            DbInstance::Mem(db) => db.set_memory_limits(limits),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_memory_limits(limits),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_memory_limits(limits),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_memory_limits(limits),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_memory_limits(limits),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_skip_corrupt].
+    pub fn set_skip_corrupt(&self, skip: bool) {
+        match self {
+            DbInstance::Mem(db) => db.set_skip_corrupt(skip),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_skip_corrupt(skip),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_skip_corrupt(skip),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_skip_corrupt(skip),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_skip_corrupt(skip),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_case_insensitive_names].
+    pub fn set_case_insensitive_names(&self, enabled: bool) {
+        match self {
+            DbInstance::Mem(db) => db.set_case_insensitive_names(enabled),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_case_insensitive_names(enabled),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_case_insensitive_names(enabled),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_case_insensitive_names(enabled),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_case_insensitive_names(enabled),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::run_built_query].
+    pub fn run_built_query(&self, query: QueryBuilder) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => db.run_built_query(query),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_built_query(query),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_built_query(query),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_built_query(query),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_built_query(query),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::explain].
+    pub fn explain(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<JsonValue> {
+        match self {
+            DbInstance::Mem(db) => db.explain(payload, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.explain(payload, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.explain(payload, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.explain(payload, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.explain(payload, params),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::insert_structs].
+    pub fn insert_structs<T: Serialize>(&self, relation: &str, rows: &[T]) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.insert_structs(relation, rows),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.insert_structs(relation, rows),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.insert_structs(relation, rows),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.insert_structs(relation, rows),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.insert_structs(relation, rows),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::run_query_arrow].
+    #[cfg(feature = "arrow")]
+    pub fn run_query_arrow(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        batch_size: usize,
+    ) -> Result<Vec<arrow_array::RecordBatch>> {
+        match self {
+            DbInstance::Mem(db) => db.run_query_arrow(payload, params, batch_size),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_query_arrow(payload, params, batch_size),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_query_arrow(payload, params, batch_size),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_query_arrow(payload, params, batch_size),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_query_arrow(payload, params, batch_size),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::insert_arrow].
+    #[cfg(feature = "arrow")]
+    pub fn insert_arrow(&self, relation: &str, batches: &[arrow_array::RecordBatch]) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.insert_arrow(relation, batches),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.insert_arrow(relation, batches),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.insert_arrow(relation, batches),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.insert_arrow(relation, batches),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.insert_arrow(relation, batches),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::query_as].
+    pub fn query_as<T: DeserializeOwned>(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<Vec<T>> {
+        match self {
+            DbInstance::Mem(db) => db.query_as(payload, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.query_as(payload, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.query_as(payload, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.query_as(payload, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.query_as(payload, params),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::plan_json].
+    pub fn plan_json(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<JsonValue> {
+        match self {
+            DbInstance::Mem(db) => db.plan_json(payload, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.plan_json(payload, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.plan_json(payload, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.plan_json(payload, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.plan_json(payload, params),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::run_plan_json].
+    pub fn run_plan_json(&self, plan: &JsonValue) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => db.run_plan_json(plan),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_plan_json(plan),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_plan_json(plan),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_plan_json(plan),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_plan_json(plan),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::result_schema].
+    pub fn result_schema(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<Vec<(String, String)>> {
+        match self {
+            DbInstance::Mem(db) => db.result_schema(payload, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.result_schema(payload, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.result_schema(payload, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.result_schema(payload, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.result_schema(payload, params),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_max_result_rows].
+    pub fn set_max_result_rows(&self, max: Option<usize>) {
+        match self {
+            DbInstance::Mem(db) => db.set_max_result_rows(max),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_max_result_rows(max),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_max_result_rows(max),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_max_result_rows(max),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_max_result_rows(max),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_clock].
+    pub fn set_clock(&self, now: Option<f64>) {
+        match self {
+            DbInstance::Mem(db) => db.set_clock(now),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_clock(now),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_clock(now),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_clock(now),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_clock(now),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_read_only].
+    pub fn set_read_only(&self, read_only: bool) {
+        match self {
+            DbInstance::Mem(db) => db.set_read_only(read_only),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_read_only(read_only),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_read_only(read_only),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_read_only(read_only),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_read_only(read_only),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::with_cancellation].
+    pub fn with_cancellation(&self, poison: Poison) -> Self {
+        match self {
+            DbInstance::Mem(db) => DbInstance::Mem(db.with_cancellation(poison)),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => DbInstance::Sqlite(db.with_cancellation(poison)),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => DbInstance::RocksDb(db.with_cancellation(poison)),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => DbInstance::Sled(db.with_cancellation(poison)),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => DbInstance::TiKv(db.with_cancellation(poison)),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::with_row_sink].
+    #[cfg(feature = "async")]
+    pub(crate) fn with_row_sink(&self, sink: Arc<dyn runtime::db::RowSink>) -> Self {
+        match self {
+            DbInstance::Mem(db) => DbInstance::Mem(db.with_row_sink(sink)),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => DbInstance::Sqlite(db.with_row_sink(sink)),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => DbInstance::RocksDb(db.with_row_sink(sink)),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => DbInstance::Sled(db.with_row_sink(sink)),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => DbInstance::TiKv(db.with_row_sink(sink)),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_rng_seed].
+    pub fn set_rng_seed(&self, seed: u64) {
+        match self {
+            DbInstance::Mem(db) => db.set_rng_seed(seed),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_rng_seed(seed),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_rng_seed(seed),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_rng_seed(seed),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_rng_seed(seed),
+        }
+    }
     /// Export relations to JSON-encoded string.
     /// See [crate::Db::export_relations]
     pub fn export_relations_str(&self, data: &str) -> String {
@@ -366,6 +899,24 @@ impl DbInstance {
             .collect::<Result<_>>()?;
         self.import_relations(mapping)
     }
+    /// Dispatcher method. See [crate::Db::import_relations_from_reader].
+    pub fn import_relations_from_reader(
+        &self,
+        reader: impl Read,
+        batch_size: usize,
+    ) -> Result<BTreeMap<String, usize>> {
+        match self {
+            DbInstance::Mem(db) => db.import_relations_from_reader(reader, batch_size),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.import_relations_from_reader(reader, batch_size),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.import_relations_from_reader(reader, batch_size),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.import_relations_from_reader(reader, batch_size),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.import_relations_from_reader(reader, batch_size),
+        }
+    }
     /// Dispatcher method. See [crate::Db::backup_db].
     pub fn backup_db(&self, out_file: impl AsRef<Path>) -> Result<()> {
         match self {
@@ -388,6 +939,104 @@ impl DbInstance {
             Err(err) => json!({"ok": false, "message": err.to_string()}).to_string(),
         }
     }
+    /// Dispatcher method. See [crate::Db::checkpoint].
+    pub fn checkpoint(&self, dir: impl AsRef<Path>) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.checkpoint(dir),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.checkpoint(dir),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.checkpoint(dir),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.checkpoint(dir),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.checkpoint(dir),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::dump_schema].
+    pub fn dump_schema(&self) -> Result<String> {
+        match self {
+            DbInstance::Mem(db) => db.dump_schema(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.dump_schema(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.dump_schema(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.dump_schema(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.dump_schema(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::apply_schema].
+    pub fn apply_schema(&self, script: &str) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.apply_schema(script),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.apply_schema(script),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.apply_schema(script),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.apply_schema(script),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.apply_schema(script),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::plan_migration].
+    pub fn plan_migration(&self, target_schema: &str) -> Result<MigrationPlan> {
+        match self {
+            DbInstance::Mem(db) => db.plan_migration(target_schema),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.plan_migration(target_schema),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.plan_migration(target_schema),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.plan_migration(target_schema),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.plan_migration(target_schema),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::apply_migration].
+    pub fn apply_migration(&self, plan: &MigrationPlan, allow_destructive: bool) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.apply_migration(plan, allow_destructive),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.apply_migration(plan, allow_destructive),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.apply_migration(plan, allow_destructive),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.apply_migration(plan, allow_destructive),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.apply_migration(plan, allow_destructive),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::export_all].
+    pub fn export_all(&self, out_file: impl AsRef<Path>) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.export_all(out_file),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.export_all(out_file),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.export_all(out_file),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.export_all(out_file),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.export_all(out_file),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::import_all].
+    pub fn import_all(&self, in_file: impl AsRef<Path>) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.import_all(in_file),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.import_all(in_file),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.import_all(in_file),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.import_all(in_file),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.import_all(in_file),
+        }
+    }
     /// Dispatcher method. See [crate::Db::restore_backup].
     pub fn restore_backup(&self, in_file: impl AsRef<Path>) -> Result<()> {
         match self {
@@ -533,16 +1182,59 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.run_multi_transaction(write, payloads, results),
         }
     }
+    /// Dispatcher method. See [crate::Db::run_multi_transaction_with_consistency]
+    pub fn run_multi_transaction_with_consistency(
+        &self,
+        write: bool,
+        consistency: ReadConsistency,
+        payloads: Receiver<TransactionPayload>,
+        results: Sender<Result<NamedRows>>,
+    ) {
+        match self {
+            DbInstance::Mem(db) => {
+                db.run_multi_transaction_with_consistency(write, consistency, payloads, results)
+            }
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => {
+                db.run_multi_transaction_with_consistency(write, consistency, payloads, results)
+            }
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => {
+                db.run_multi_transaction_with_consistency(write, consistency, payloads, results)
+            }
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => {
+                db.run_multi_transaction_with_consistency(write, consistency, payloads, results)
+            }
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => {
+                db.run_multi_transaction_with_consistency(write, consistency, payloads, results)
+            }
+        }
+    }
     /// A higher-level, blocking wrapper for [crate::Db::run_multi_transaction]. Runs the transaction on a dedicated thread.
     /// Write transactions _may_ block other reads, but we guarantee that this does not happen for the RocksDB backend.
     pub fn multi_transaction(&self, write: bool) -> MultiTransaction {
+        self.multi_transaction_with_consistency(write, ReadConsistency::Snapshot)
+    }
+    /// Like [DbInstance::multi_transaction], with the committed data read by the scripts
+    /// given by `consistency`.
+    pub fn multi_transaction_with_consistency(
+        &self,
+        write: bool,
+        consistency: ReadConsistency,
+    ) -> MultiTransaction {
         let (app2db_send, app2db_recv) = bounded(1);
         let (db2app_send, db2app_recv) = bounded(1);
         let db = self.clone();
-        #[cfg(target_arch = "wasm32")]
-        std::thread::spawn(move || db.run_multi_transaction(write, app2db_recv, db2app_send));
-        #[cfg(not(target_arch = "wasm32"))]
-        rayon::spawn(move || db.run_multi_transaction(write, app2db_recv, db2app_send));
+        #[cfg(any(not(feature = "rayon"), target_arch = "wasm32"))]
+        std::thread::spawn(move || {
+            db.run_multi_transaction_with_consistency(write, consistency, app2db_recv, db2app_send)
+        });
+        #[cfg(all(feature = "rayon", not(target_arch = "wasm32")))]
+        rayon::spawn(move || {
+            db.run_multi_transaction_with_consistency(write, consistency, app2db_recv, db2app_send)
+        });
         MultiTransaction {
             sender: app2db_send,
             receiver: db2app_recv,
@@ -622,6 +1314,24 @@ pub fn format_error_as_json(mut err: Report, source: Option<&str>) -> JsonValue
     json
 }
 
+/// The HTTP status code describing `err`, for serving queries over HTTP:
+/// 408 if the query was killed, e.g. by its `:timeout`, 409 if it conflicts with
+/// stored data or relations, and 400 otherwise, e.g. for parse errors.
+pub fn error_status_code(err: &Report) -> u16 {
+    let code = match err.code() {
+        Some(code) => code.to_string(),
+        None => return 400,
+    };
+    match code.as_str() {
+        "eval::killed" => 408,
+        "transact::assertion_failure"
+        | "eval::rel_name_conflict"
+        | "eval::stored_relation_conflict"
+        | "tx::index_already_exists" => 409,
+        _ => 400,
+    }
+}
+
 lazy_static! {
     static ref TEXT_ERR_HANDLER: GraphicalReportHandler = miette::GraphicalReportHandler::new()
         .with_theme(GraphicalTheme {
@@ -8,6 +8,8 @@
 
 use std::collections::BTreeMap;
 
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use miette::{bail, ensure, Diagnostic, Result};
@@ -17,13 +19,14 @@ use thiserror::Error;
 
 use crate::data::expr::{get_op, Bytecode, Expr, NoImplementationError};
 use crate::data::functions::{
-    OP_ADD, OP_AND, OP_COALESCE, OP_CONCAT, OP_DIV, OP_EQ, OP_GE, OP_GT, OP_JSON_OBJECT, OP_LE,
-    OP_LIST, OP_LT, OP_MAYBE_GET, OP_MINUS, OP_MOD, OP_MUL, OP_NEGATE, OP_NEQ, OP_OR, OP_POW,
-    OP_SUB,
+    CAST_TYPES, OP_ACCESS, OP_ACCESS_SLICE, OP_ADD, OP_AND, OP_CAST, OP_COALESCE, OP_CONCAT,
+    OP_DIV, OP_EQ, OP_GE, OP_GT, OP_IN, OP_JSON_OBJECT, OP_JSON_OVERRIDE, OP_LE, OP_LIST, OP_LT,
+    OP_MAYBE_GET, OP_MINUS, OP_MOD, OP_MUL, OP_NEGATE, OP_NEQ, OP_NOT_IN, OP_OR, OP_POW, OP_RANGE,
+    OP_RANGE_INCLUSIVE, OP_SUB, OP_TRY_CAST,
 };
 use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
-use crate::parse::{ExtractSpan, Pair, Rule, SourceSpan};
+use crate::parse::{ExtractSpan, NextPair, Pair, Pairs, Rule, SourceSpan, UnexpectedSyntax};
 
 lazy_static! {
     static ref PRATT_PARSER: PrattParser<Rule> = {
@@ -35,8 +38,14 @@ lazy_static! {
             .op(Op::infix(Rule::op_gt, Left)
                 | Op::infix(Rule::op_lt, Left)
                 | Op::infix(Rule::op_ge, Left)
-                | Op::infix(Rule::op_le, Left))
-            .op(Op::infix(Rule::op_eq, Left) | Op::infix(Rule::op_ne, Left))
+                | Op::infix(Rule::op_le, Left)
+                | Op::postfix(Rule::between_suffix))
+            .op(Op::infix(Rule::op_eq, Left)
+                | Op::infix(Rule::op_ne, Left)
+                | Op::infix(Rule::op_null_eq, Left)
+                | Op::infix(Rule::op_in, Left)
+                | Op::infix(Rule::op_not_in, Left))
+            .op(Op::infix(Rule::op_range, Left) | Op::infix(Rule::op_range_inclusive, Left))
             .op(Op::infix(Rule::op_mod, Left))
             .op(Op::infix(Rule::op_add, Left)
                 | Op::infix(Rule::op_sub, Left)
@@ -47,6 +56,7 @@ lazy_static! {
             .op(Op::prefix(Rule::minus))
             .op(Op::prefix(Rule::negate))
             .op(Op::infix(Rule::op_field_access, Left))
+            .op(Op::postfix(Rule::subscript))
     };
 }
 
@@ -119,7 +129,13 @@ pub(crate) fn build_expr(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue
         pair.as_rule() == Rule::expr,
         InvalidExpression(pair.extract_span())
     );
+    build_expr_from_pairs(pair.into_inner(), param_pool)
+}
 
+fn build_expr_from_pairs(
+    pairs: Pairs<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+) -> Result<Expr> {
     PRATT_PARSER
         .map_primary(|v| build_term(v, param_pool))
         .map_infix(build_expr_infix)
@@ -137,14 +153,78 @@ pub(crate) fn build_expr(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue
                     args: [rhs].into(),
                     span: op.extract_span().merge(rhs_span),
                 },
-                _ => unreachable!(),
+                r => bail!(UnexpectedSyntax::rule(r)),
+            })
+        })
+        .map_postfix(|lhs, op| {
+            let lhs = lhs?;
+            let span = lhs.span().merge(op.extract_span());
+            Ok(match op.as_rule() {
+                Rule::subscript => {
+                    let mut from = None;
+                    let mut to = None;
+                    let mut is_slice = false;
+                    for part in op.into_inner() {
+                        match part.as_rule() {
+                            Rule::slice_from => {
+                                from = Some(build_expr_from_pairs(part.into_inner(), param_pool)?)
+                            }
+                            Rule::slice_to => {
+                                to = Some(build_expr_from_pairs(part.into_inner(), param_pool)?)
+                            }
+                            _ => is_slice = true,
+                        }
+                    }
+                    let null = Expr::Const {
+                        val: DataValue::Null,
+                        span,
+                    };
+                    match (is_slice, from) {
+                        (false, Some(key)) => Expr::Apply {
+                            op: &OP_ACCESS,
+                            args: [lhs, key].into(),
+                            span,
+                        },
+                        (_, from) => Expr::Apply {
+                            op: &OP_ACCESS_SLICE,
+                            args: [
+                                lhs,
+                                from.unwrap_or_else(|| null.clone()),
+                                to.unwrap_or(null),
+                            ]
+                            .into(),
+                            span,
+                        },
+                    }
+                }
+                Rule::between_suffix => {
+                    let mut bounds = op
+                        .into_inner()
+                        .filter(|p| p.as_rule() == Rule::between_bound);
+                    let lower =
+                        build_expr_from_pairs(bounds.next_pair()?.into_inner(), param_pool)?;
+                    let upper =
+                        build_expr_from_pairs(bounds.next_pair()?.into_inner(), param_pool)?;
+                    Expr::build_between(lhs, lower, upper, true, span)
+                }
+                r => bail!(UnexpectedSyntax::rule(r)),
             })
         })
-        .parse(pair.into_inner())
+        .parse(pairs)
 }
 
 fn build_expr_infix(lhs: Result<Expr>, op: Pair<'_>, rhs: Result<Expr>) -> Result<Expr> {
     let args = vec![lhs?, rhs?];
+    let span = args[0].span().merge(args[1].span());
+    if matches!(op.as_rule(), Rule::op_in | Rule::op_not_in) {
+        if let Some(lowered) = Expr::build_in_range(args[0].clone(), &args[1], span) {
+            return Ok(if op.as_rule() == Rule::op_in {
+                lowered
+            } else {
+                lowered.negate(span)
+            });
+        }
+    }
     let op = match op.as_rule() {
         Rule::op_add => &OP_ADD,
         Rule::op_sub => &OP_SUB,
@@ -154,6 +234,12 @@ fn build_expr_infix(lhs: Result<Expr>, op: Pair<'_>, rhs: Result<Expr>) -> Resul
         Rule::op_pow => &OP_POW,
         Rule::op_eq => &OP_EQ,
         Rule::op_ne => &OP_NEQ,
+        // `==` already holds for two nulls, so `<=>` is only there for those used to SQL
+        Rule::op_null_eq => &OP_EQ,
+        Rule::op_in => &OP_IN,
+        Rule::op_not_in => &OP_NOT_IN,
+        Rule::op_range => &OP_RANGE,
+        Rule::op_range_inclusive => &OP_RANGE_INCLUSIVE,
         Rule::op_gt => &OP_GT,
         Rule::op_ge => &OP_GE,
         Rule::op_lt => &OP_LT,
@@ -163,7 +249,7 @@ fn build_expr_infix(lhs: Result<Expr>, op: Pair<'_>, rhs: Result<Expr>) -> Resul
         Rule::op_and => &OP_AND,
         Rule::op_coalesce => &OP_COALESCE,
         Rule::op_field_access => &OP_MAYBE_GET,
-        _ => unreachable!(),
+        r => bail!(UnexpectedSyntax::rule(r)),
     };
     let start = args[0].span().0;
     let end = args[1].span().0 + args[1].span().1;
@@ -179,17 +265,36 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
     let span = pair.extract_span();
     let op = pair.as_rule();
     Ok(match op {
-        Rule::var => Expr::Binding {
-            var: Symbol::new(pair.as_str(), pair.extract_span()),
-            tuple_pos: None,
-        },
+        Rule::var => {
+            // `a.b.c` accesses the field `c` of the field `b` of the binding `a`
+            let mut parts = pair.as_str().split('.');
+            let mut expr = Expr::Binding {
+                var: Symbol::new(parts.next_pair()?, span),
+                tuple_pos: None,
+            };
+            for field in parts {
+                expr = Expr::Apply {
+                    op: &OP_ACCESS,
+                    args: [
+                        expr,
+                        Expr::Const {
+                            val: DataValue::from(field),
+                            span,
+                        },
+                    ]
+                    .into(),
+                    span,
+                };
+            }
+            expr
+        }
         Rule::param => {
             #[derive(Error, Diagnostic, Debug)]
             #[error("Required parameter {0} not found")]
             #[diagnostic(code(parser::param_not_found))]
             struct ParamNotFoundError(String, #[label] SourceSpan);
 
-            let param_str = pair.as_str().strip_prefix('$').unwrap();
+            let param_str = pair.as_str().trim_start_matches('$');
             Expr::Const {
                 val: param_pool
                     .get(param_str)
@@ -199,11 +304,7 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
             }
         }
         Rule::pos_int => {
-            #[derive(Error, Diagnostic, Debug)]
-            #[error("Cannot parse integer")]
-            #[diagnostic(code(parser::bad_pos_int))]
-            struct BadIntError(#[label] SourceSpan);
-
+            check_digit_separators(pair.as_str(), 10, span)?;
             let i = pair
                 .as_str()
                 .replace('_', "")
@@ -215,21 +316,21 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
             }
         }
         Rule::hex_pos_int => {
-            let i = parse_int(pair.as_str(), 16);
+            let i = parse_int(pair.as_str(), 16, span)?;
             Expr::Const {
                 val: DataValue::from(i),
                 span,
             }
         }
         Rule::octo_pos_int => {
-            let i = parse_int(pair.as_str(), 8);
+            let i = parse_int(pair.as_str(), 8, span)?;
             Expr::Const {
                 val: DataValue::from(i),
                 span,
             }
         }
         Rule::bin_pos_int => {
-            let i = parse_int(pair.as_str(), 2);
+            let i = parse_int(pair.as_str(), 2, span)?;
             Expr::Const {
                 val: DataValue::from(i),
                 span,
@@ -241,11 +342,18 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
             #[diagnostic(code(parser::bad_float))]
             struct BadFloatError(#[label] SourceSpan);
 
+            #[derive(Error, Diagnostic, Debug)]
+            #[error("Float literal is too large")]
+            #[diagnostic(code(parser::float_overflow))]
+            struct FloatOverflowError(#[label] SourceSpan);
+
+            check_digit_separators(pair.as_str(), 10, span)?;
             let f = pair
                 .as_str()
                 .replace('_', "")
                 .parse::<f64>()
                 .map_err(|_| BadFloatError(span))?;
+            ensure!(f.is_finite(), FloatOverflowError(span));
             Expr::Const {
                 val: DataValue::from(f),
                 span,
@@ -259,13 +367,41 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
             val: DataValue::from(pair.as_str() == "true"),
             span,
         },
-        Rule::quoted_string | Rule::s_quoted_string | Rule::raw_string => {
+        Rule::quoted_string
+        | Rule::s_quoted_string
+        | Rule::raw_string
+        | Rule::r_string
+        | Rule::triple_quoted_string => {
             let s = parse_string(pair)?;
             Expr::Const {
                 val: DataValue::Str(s),
                 span,
             }
         }
+        Rule::hex_bytes | Rule::b64_bytes => {
+            #[derive(Error, Diagnostic, Debug)]
+            #[error("Cannot parse bytes literal")]
+            #[diagnostic(code(parser::bad_bytes))]
+            #[diagnostic(help("{0}"))]
+            struct BadBytesError(&'static str, #[label] SourceSpan);
+
+            let is_hex = pair.as_rule() == Rule::hex_bytes;
+            let s = parse_string(pair.into_inner().next_pair()?)?;
+            let bytes = if is_hex {
+                decode_hex(&s).ok_or(BadBytesError(
+                    "hex literals need an even number of hex digits",
+                    span,
+                ))?
+            } else {
+                STANDARD
+                    .decode(s.as_bytes())
+                    .map_err(|_| BadBytesError("the string is not valid base64", span))?
+            };
+            Expr::Const {
+                val: DataValue::Bytes(bytes),
+                span,
+            }
+        }
         Rule::list => {
             let mut collected = vec![];
             for p in pair.into_inner() {
@@ -278,163 +414,262 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
             }
         }
         Rule::object => {
+            // `{...a, ...b, k: v}` merges the spreads from left to right, and then sets the
+            // explicit pairs, which replace what the spreads give for their keys wherever
+            // they are written
+            let mut parts = vec![];
             let mut args = vec![];
             for p in pair.into_inner() {
+                let is_spread = p.as_rule() == Rule::object_spread;
                 let mut p = p.into_inner();
-                let k = p.next().unwrap();
-                let v = p.next().unwrap();
+                if is_spread {
+                    parts.push(build_expr(p.next_pair()?, param_pool)?);
+                    continue;
+                }
+                let k = p.next_pair()?;
+                let v = p.next_pair()?;
                 let k = build_expr(k, param_pool)?;
                 let v = build_expr(v, param_pool)?;
                 args.push(k);
                 args.push(v);
             }
-            Expr::Apply {
+            let obj = Expr::Apply {
                 op: &OP_JSON_OBJECT,
                 args: args.into(),
                 span,
+            };
+            if parts.is_empty() {
+                obj
+            } else {
+                parts.push(obj);
+                Expr::Apply {
+                    op: &OP_JSON_OVERRIDE,
+                    args: parts.into(),
+                    span,
+                }
             }
         }
         Rule::apply => {
             let mut p = pair.into_inner();
-            let ident_p = p.next().unwrap();
+            let ident_p = p.next_pair()?;
             let ident = ident_p.as_str();
-            let mut args: Vec<_> = p
-                .next()
-                .unwrap()
+            let args: Vec<_> = p
+                .next_pair()?
                 .into_inner()
                 .map(|v| build_expr(v, param_pool))
                 .try_collect()?;
+            build_apply(ident, args, span)?
+        }
+        Rule::cast_apply => {
             #[derive(Error, Diagnostic, Debug)]
-            #[error("Named function '{0}' not found")]
-            #[diagnostic(code(parser::func_not_function))]
-            struct FuncNotFoundError(String, #[label] SourceSpan);
+            #[error("Cannot cast to unknown type '{0}'")]
+            #[diagnostic(code(parser::bad_cast_type))]
+            #[diagnostic(help("The types are: {}", CAST_TYPES.join(", ")))]
+            struct BadCastType(String, #[label] SourceSpan);
 
-            match ident {
-                "cond" => {
-                    if args.is_empty() {
-                        #[derive(Error, Diagnostic, Debug)]
-                        #[error("'cond' cannot have empty body")]
-                        #[diagnostic(code(parser::empty_cond))]
-                        struct EmptyCond(#[label] SourceSpan);
-                        bail!(EmptyCond(span));
-                    }
-                    if args.len() & 1 == 1 {
-                        args.insert(
-                            args.len() - 1,
+            let mut p = pair.into_inner();
+            let op = match p.next_pair()?.as_str() {
+                "try_cast" => &OP_TRY_CAST,
+                _ => &OP_CAST,
+            };
+            let arg = build_expr(p.next_pair()?, param_pool)?;
+            p.next_pair()?;
+            let typ_p = p.next_pair()?;
+            let typ = typ_p.as_str();
+            ensure!(
+                CAST_TYPES.contains(&typ.to_ascii_lowercase().as_str()),
+                BadCastType(typ.to_string(), typ_p.extract_span())
+            );
+            Expr::Apply {
+                op,
+                args: [
+                    arg,
+                    Expr::Const {
+                        val: DataValue::from(typ),
+                        span: typ_p.extract_span(),
+                    },
+                ]
+                .into(),
+                span,
+            }
+        }
+        Rule::grouping => build_expr(pair.into_inner().next_pair()?, param_pool)?,
+        r => bail!(UnexpectedSyntax::rule(r)),
+    })
+}
+
+/// Apply the function named `ident` to `args`, handling the special forms `cond` and `if`.
+/// Functions not known to the database stay unbound, to be reported when the query is compiled.
+pub(crate) fn build_apply(ident: &str, mut args: Vec<Expr>, span: SourceSpan) -> Result<Expr> {
+    #[derive(Error, Diagnostic, Debug)]
+    #[error("Named function '{0}' not found")]
+    #[diagnostic(code(parser::func_not_function))]
+    struct FuncNotFoundError(String, #[label] SourceSpan);
+
+    Ok(match ident {
+        "cond" => {
+            if args.is_empty() {
+                #[derive(Error, Diagnostic, Debug)]
+                #[error("'cond' cannot have empty body")]
+                #[diagnostic(code(parser::empty_cond))]
+                struct EmptyCond(#[label] SourceSpan);
+                bail!(EmptyCond(span));
+            }
+            if args.len() & 1 == 1 {
+                args.insert(
+                    args.len() - 1,
+                    Expr::Const {
+                        val: DataValue::Null,
+                        span: args.last().unwrap().span(),
+                    },
+                )
+            }
+            let mut clauses = args
+                .chunks(2)
+                .map(|pair| (pair[0].clone(), pair[1].clone()))
+                .collect_vec();
+            if let Some((cond, _)) = clauses.last() {
+                match cond {
+                    Expr::Const {
+                        val: DataValue::Bool(true),
+                        ..
+                    } => {}
+                    _ => {
+                        clauses.push((
                             Expr::Const {
-                                val: DataValue::Null,
-                                span: args.last().unwrap().span(),
+                                val: DataValue::from(true),
+                                span,
                             },
-                        )
-                    }
-                    let mut clauses = args
-                        .chunks(2)
-                        .map(|pair| (pair[0].clone(), pair[1].clone()))
-                        .collect_vec();
-                    if let Some((cond, _)) = clauses.last() {
-                        match cond {
                             Expr::Const {
-                                val: DataValue::Bool(true),
-                                ..
-                            } => {}
-                            _ => {
-                                clauses.push((
-                                    Expr::Const {
-                                        val: DataValue::from(true),
-                                        span,
-                                    },
-                                    Expr::Const {
-                                        val: DataValue::Null,
-                                        span,
-                                    },
-                                ));
-                            }
-                        }
+                                val: DataValue::Null,
+                                span,
+                            },
+                        ));
                     }
-                    Expr::Cond { clauses, span }
                 }
-                "if" => {
-                    #[derive(Debug, Error, Diagnostic)]
-                    #[error("wrong number of arguments to if: 2 or 3 required")]
-                    #[diagnostic(code(parser::bad_if))]
-                    struct WrongArgsToIf(#[label] SourceSpan);
+            }
+            Expr::Cond { clauses, span }
+        }
+        "if" => {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("wrong number of arguments to if: 2 or 3 required")]
+            #[diagnostic(code(parser::bad_if))]
+            struct WrongArgsToIf(#[label] SourceSpan);
 
-                    ensure!(args.len() == 2 || args.len() == 3, WrongArgsToIf(span));
+            ensure!(args.len() == 2 || args.len() == 3, WrongArgsToIf(span));
 
-                    let mut clauses = vec![];
-                    let mut args = args.into_iter();
-                    let cond = args.next().unwrap();
-                    let then = args.next().unwrap();
-                    clauses.push((cond, then));
-                    clauses.push((
-                        Expr::Const {
-                            val: DataValue::from(true),
+            let mut clauses = vec![];
+            let mut args = args.into_iter();
+            let cond = args.next_pair()?;
+            let then = args.next_pair()?;
+            clauses.push((cond, then));
+            clauses.push((
+                Expr::Const {
+                    val: DataValue::from(true),
+                    span,
+                },
+                args.next().unwrap_or(Expr::Const {
+                    val: DataValue::Null,
+                    span,
+                }),
+            ));
+            Expr::Cond { clauses, span }
+        }
+        _ => match get_op(ident) {
+            None => Expr::UnboundApply {
+                op: ident.into(),
+                args: args.into(),
+                span,
+            },
+            Some(op) => {
+                op.post_process_args(&mut args);
+                #[derive(Error, Diagnostic, Debug)]
+                #[error("Wrong number of arguments for function '{0}'")]
+                #[diagnostic(code(parser::func_wrong_num_args))]
+                struct WrongNumArgsError(String, #[label] SourceSpan, #[help] String);
+
+                if op.vararg {
+                    ensure!(
+                        op.min_arity <= args.len(),
+                        WrongNumArgsError(
+                            ident.to_string(),
                             span,
-                        },
-                        args.next().unwrap_or(Expr::Const {
-                            val: DataValue::Null,
+                            format!("Need at least {} argument(s)", op.min_arity)
+                        )
+                    );
+                } else {
+                    ensure!(
+                        op.min_arity == args.len(),
+                        WrongNumArgsError(
+                            ident.to_string(),
                             span,
-                        }),
-                    ));
-                    Expr::Cond { clauses, span }
+                            format!("Need exactly {} argument(s)", op.min_arity)
+                        )
+                    );
                 }
-                _ => match get_op(ident) {
-                    None => Expr::UnboundApply {
-                        op: ident.into(),
-                        args: args.into(),
-                        span,
-                    },
-                    Some(op) => {
-                        op.post_process_args(&mut args);
-                        #[derive(Error, Diagnostic, Debug)]
-                        #[error("Wrong number of arguments for function '{0}'")]
-                        #[diagnostic(code(parser::func_wrong_num_args))]
-                        struct WrongNumArgsError(String, #[label] SourceSpan, #[help] String);
+                Expr::Apply {
+                    op,
+                    args: args.into(),
+                    span,
+                }
+            }
+        },
+    })
+}
 
-                        if op.vararg {
-                            ensure!(
-                                op.min_arity <= args.len(),
-                                WrongNumArgsError(
-                                    ident.to_string(),
-                                    span,
-                                    format!("Need at least {} argument(s)", op.min_arity)
-                                )
-                            );
-                        } else {
-                            ensure!(
-                                op.min_arity == args.len(),
-                                WrongNumArgsError(
-                                    ident.to_string(),
-                                    span,
-                                    format!("Need exactly {} argument(s)", op.min_arity)
-                                )
-                            );
-                        }
-                        Expr::Apply {
-                            op,
-                            args: args.into(),
-                            span,
-                        }
-                    }
-                },
+#[derive(Error, Diagnostic, Debug)]
+#[error("Cannot parse integer")]
+#[diagnostic(code(parser::bad_pos_int))]
+struct BadIntError(#[label] SourceSpan);
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("Malformed number literal '{0}'")]
+#[diagnostic(code(parser::bad_number))]
+#[diagnostic(help("Underscores in numbers can only be put between two digits"))]
+struct MalformedNumberError(String, #[label] SourceSpan);
+
+/// Numbers may have underscores between their digits, as in `1_000_000`, but not
+/// at either end of a part of the number, as in `1_.5` or `0x_1F`.
+fn check_digit_separators(s: &str, radix: u32, span: SourceSpan) -> Result<()> {
+    let bytes = s.as_bytes();
+    for (i, b) in bytes.iter().enumerate() {
+        if *b == b'_' {
+            let is_digit = |j: Option<usize>| {
+                j.and_then(|j| bytes.get(j))
+                    .is_some_and(|c| (*c as char).is_digit(radix))
+            };
+            if !is_digit(i.checked_sub(1)) || !is_digit(Some(i + 1)) {
+                bail!(MalformedNumberError(s.to_string(), span))
             }
         }
-        Rule::grouping => build_expr(pair.into_inner().next().unwrap(), param_pool)?,
-        r => unreachable!("Encountered unknown op {:?}", r),
-    })
+    }
+    Ok(())
+}
+
+/// Parse an integer written with a two-character prefix such as `0x`, failing if it is too large.
+pub(crate) fn parse_int(s: &str, radix: u32, span: SourceSpan) -> Result<i64> {
+    check_digit_separators(s, radix, span)?;
+    Ok(i64::from_str_radix(&s[2..].replace('_', ""), radix).map_err(|_| BadIntError(span))?)
 }
 
-pub(crate) fn parse_int(s: &str, radix: u32) -> i64 {
-    i64::from_str_radix(&s[2..].replace('_', ""), radix).unwrap()
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
 }
 
 pub(crate) fn parse_string(pair: Pair<'_>) -> Result<SmartString<LazyCompact>> {
     match pair.as_rule() {
-        Rule::quoted_string => Ok(parse_quoted_string(pair)?),
-        Rule::s_quoted_string => Ok(parse_s_quoted_string(pair)?),
-        Rule::raw_string => Ok(parse_raw_string(pair)?),
+        Rule::quoted_string | Rule::s_quoted_string | Rule::triple_quoted_string => {
+            Ok(parse_escaped_string(pair)?)
+        }
+        Rule::raw_string | Rule::r_string => Ok(parse_raw_string(pair)?),
         Rule::ident => Ok(SmartString::from(pair.as_str())),
-        t => unreachable!("{:?}", t),
+        t => bail!(UnexpectedSyntax::rule(t)),
     }
 }
 
@@ -446,15 +681,19 @@ struct InvalidUtf8Error(u32, #[label] SourceSpan);
 #[derive(Error, Diagnostic, Debug)]
 #[error("invalid escape sequence {0}")]
 #[diagnostic(code(parser::invalid_escape_seq))]
+#[diagnostic(help(
+    r#"Escape sequences are \n, \t, \r, \b, \f, \/, \\, \', \" and unicode escapes like \u00E9 or \u{{1F600}}"#
+))]
 struct InvalidEscapeSeqError(String, #[label] SourceSpan);
 
-fn parse_quoted_string(pair: Pair<'_>) -> Result<SmartString<LazyCompact>> {
-    let pairs = pair.into_inner().next().unwrap().into_inner();
+fn parse_escaped_string(pair: Pair<'_>) -> Result<SmartString<LazyCompact>> {
+    let pairs = pair.into_inner().next_pair()?.into_inner();
     let mut ret = SmartString::new();
     for pair in pairs {
         let s = pair.as_str();
         match s {
             r#"\""# => ret.push('"'),
+            r"\'" => ret.push('\''),
             r"\\" => ret.push('\\'),
             r"\/" => ret.push('/'),
             r"\b" => ret.push('\x08'),
@@ -463,36 +702,9 @@ fn parse_quoted_string(pair: Pair<'_>) -> Result<SmartString<LazyCompact>> {
             r"\r" => ret.push('\r'),
             r"\t" => ret.push('\t'),
             s if s.starts_with(r"\u") => {
-                let code = parse_int(s, 16) as u32;
-                let ch = char::from_u32(code)
-                    .ok_or_else(|| InvalidUtf8Error(code, pair.extract_span()))?;
-                ret.push(ch);
-            }
-            s if s.starts_with('\\') => {
-                bail!(InvalidEscapeSeqError(s.to_string(), pair.extract_span()))
-            }
-            s => ret.push_str(s),
-        }
-    }
-    Ok(ret)
-}
-
-fn parse_s_quoted_string(pair: Pair<'_>) -> Result<SmartString<LazyCompact>> {
-    let pairs = pair.into_inner().next().unwrap().into_inner();
-    let mut ret = SmartString::new();
-    for pair in pairs {
-        let s = pair.as_str();
-        match s {
-            r#"\'"# => ret.push('\''),
-            r"\\" => ret.push('\\'),
-            r"\/" => ret.push('/'),
-            r"\b" => ret.push('\x08'),
-            r"\f" => ret.push('\x0c'),
-            r"\n" => ret.push('\n'),
-            r"\r" => ret.push('\r'),
-            r"\t" => ret.push('\t'),
-            s if s.starts_with(r"\u") => {
-                let code = parse_int(s, 16) as u32;
+                let code =
+                    u32::from_str_radix(s[2..].trim_start_matches('{').trim_end_matches('}'), 16)
+                        .map_err(|_| InvalidEscapeSeqError(s.to_string(), pair.extract_span()))?;
                 let ch = char::from_u32(code)
                     .ok_or_else(|| InvalidUtf8Error(code, pair.extract_span()))?;
                 ret.push(ch);
@@ -507,7 +719,5 @@ fn parse_s_quoted_string(pair: Pair<'_>) -> Result<SmartString<LazyCompact>> {
 }
 
 fn parse_raw_string(pair: Pair<'_>) -> Result<SmartString<LazyCompact>> {
-    Ok(SmartString::from(
-        pair.into_inner().next().unwrap().as_str(),
-    ))
+    Ok(SmartString::from(pair.into_inner().next_pair()?.as_str()))
 }
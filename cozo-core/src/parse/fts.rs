@@ -8,23 +8,23 @@
 
 use crate::fts::ast::{FtsExpr, FtsLiteral, FtsNear};
 use crate::parse::expr::parse_string;
-use crate::parse::{CozoScriptParser, Pair, Rule};
+use crate::parse::{CozoScriptParser, NextPair, Pair, Rule, UnexpectedSyntax};
 use itertools::Itertools;
 use lazy_static::lazy_static;
-use miette::{IntoDiagnostic, Result};
+use miette::{bail, IntoDiagnostic, Result};
 use pest::pratt_parser::{Op, PrattParser};
 use pest::Parser;
 use smartstring::SmartString;
 
 pub(crate) fn parse_fts_query(q: &str) -> Result<FtsExpr> {
     let mut pairs = CozoScriptParser::parse(Rule::fts_doc, q).into_diagnostic()?;
-    let pairs = pairs.next().unwrap().into_inner();
+    let pairs = pairs.next_pair()?.into_inner();
     let pairs: Vec<_> = pairs
         .filter(|r| r.as_rule() != Rule::EOI)
         .map(parse_fts_expr)
         .try_collect()?;
     Ok(if pairs.len() == 1 {
-        pairs.into_iter().next().unwrap()
+        pairs.into_iter().next_pair()?
     } else {
         FtsExpr::And(pairs)
     })
@@ -46,7 +46,7 @@ fn build_infix(lhs: Result<FtsExpr>, op: Pair<'_>, rhs: Result<FtsExpr>) -> Resu
         Rule::fts_and => FtsExpr::And(vec![lhs, rhs]),
         Rule::fts_or => FtsExpr::Or(vec![lhs, rhs]),
         Rule::fts_not => FtsExpr::Not(Box::new(lhs), Box::new(rhs)),
-        _ => unreachable!("unexpected rule: {:?}", op.as_rule()),
+        _ => bail!(UnexpectedSyntax::rule(op.as_rule())),
     })
 }
 
@@ -55,7 +55,7 @@ fn build_term(pair: Pair<'_>) -> Result<FtsExpr> {
         Rule::fts_grouped => {
             let collected: Vec<_> = pair.into_inner().map(parse_fts_expr).try_collect()?;
             if collected.len() == 1 {
-                collected.into_iter().next().unwrap()
+                collected.into_iter().next_pair()?
             } else {
                 FtsExpr::And(collected)
             }
@@ -79,17 +79,17 @@ fn build_term(pair: Pair<'_>) -> Result<FtsExpr> {
             FtsExpr::Near(FtsNear { literals, distance })
         }
         Rule::fts_phrase => FtsExpr::Literal(build_phrase(pair)?),
-        r => panic!("unexpected rule: {:?}", r),
+        r => bail!(UnexpectedSyntax::rule(r)),
     })
 }
 
 fn build_phrase(pair: Pair<'_>) -> Result<FtsLiteral> {
     let mut inner = pair.into_inner();
-    let kernel = inner.next().unwrap();
+    let kernel = inner.next_pair()?;
     let core_text = match kernel.as_rule() {
         Rule::fts_phrase_group => SmartString::from(kernel.as_str().trim()),
         Rule::quoted_string | Rule::s_quoted_string | Rule::raw_string => parse_string(kernel)?,
-        _ => unreachable!("unexpected rule: {:?}", kernel.as_rule()),
+        _ => bail!(UnexpectedSyntax::rule(kernel.as_rule())),
     };
     let mut is_quoted = false;
     let mut booster = 1.0;
@@ -97,7 +97,7 @@ fn build_phrase(pair: Pair<'_>) -> Result<FtsLiteral> {
         match pair.as_rule() {
             Rule::fts_prefix_marker => is_quoted = true,
             Rule::fts_booster => {
-                let boosted = pair.into_inner().next().unwrap();
+                let boosted = pair.into_inner().next_pair()?;
                 match boosted.as_rule() {
                     Rule::dot_float => {
                         let f = boosted
@@ -115,10 +115,10 @@ fn build_phrase(pair: Pair<'_>) -> Result<FtsLiteral> {
                             .into_diagnostic()?;
                         booster = i as f64;
                     }
-                    _ => unreachable!("unexpected rule: {:?}", boosted.as_rule()),
+                    _ => bail!(UnexpectedSyntax::rule(boosted.as_rule())),
                 }
             }
-            _ => unreachable!("unexpected rule: {:?}", pair.as_rule()),
+            _ => bail!(UnexpectedSyntax::rule(pair.as_rule())),
         }
     }
     Ok(FtsLiteral {
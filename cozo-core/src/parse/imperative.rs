@@ -12,15 +12,15 @@ use std::sync::Arc;
 
 use either::{Left, Right};
 use itertools::Itertools;
-use miette::{Diagnostic, Result};
+use miette::{bail, Diagnostic, Result};
 use smartstring::SmartString;
 use thiserror::Error;
 
 use crate::parse::query::parse_query;
 use crate::parse::sys::parse_sys;
 use crate::parse::{
-    ExtractSpan, ImperativeProgram, ImperativeStmt, ImperativeStmtClause, ImperativeSysop, Pair,
-    Rule, SourceSpan,
+    ExtractSpan, ImperativeProgram, ImperativeStmt, ImperativeStmtClause, ImperativeSysop,
+    NextPair, Pair, Rule, SourceSpan, UnexpectedSyntax,
 };
 use crate::{DataValue, FixedRule, ValidityTs};
 
@@ -89,10 +89,10 @@ fn parse_imperative_stmt(
                         let rel = SmartString::from(p.as_str());
                         rets.push(Right(rel));
                     }
-                    Rule::query_script_inner => {
+                    Rule::imperative_clause => {
                         let mut src = p.into_inner();
                         let prog = parse_query(
-                            src.next().unwrap().into_inner(),
+                            src.next_pair()?.into_inner(),
                             param_pool,
                             fixed_rules,
                             cur_vld,
@@ -100,7 +100,7 @@ fn parse_imperative_stmt(
                         let store_as = src.next().map(|p| SmartString::from(p.as_str().trim()));
                         rets.push(Left(ImperativeStmtClause { prog, store_as }))
                     }
-                    _ => unreachable!(),
+                    r => bail!(UnexpectedSyntax::rule(r)),
                 }
             }
             ImperativeStmt::Return { returns: rets }
@@ -108,13 +108,13 @@ fn parse_imperative_stmt(
         Rule::if_chain | Rule::if_not_chain => {
             let negated = pair.as_rule() == Rule::if_not_chain;
             let mut inner = pair.into_inner();
-            let condition = inner.next().unwrap();
+            let condition = inner.next_pair()?;
             let cond = match condition.as_rule() {
                 Rule::underscore_ident => Left(SmartString::from(condition.as_str())),
                 Rule::imperative_clause => {
                     let mut src = condition.into_inner();
                     let prog = parse_query(
-                        src.next().unwrap().into_inner(),
+                        src.next_pair()?.into_inner(),
                         param_pool,
                         fixed_rules,
                         cur_vld,
@@ -122,11 +122,10 @@ fn parse_imperative_stmt(
                     let store_as = src.next().map(|p| SmartString::from(p.as_str().trim()));
                     Right(ImperativeStmtClause { prog, store_as })
                 }
-                _ => unreachable!(),
+                r => bail!(UnexpectedSyntax::rule(r)),
             };
             let body = inner
-                .next()
-                .unwrap()
+                .next_pair()?
                 .into_inner()
                 .map(|p| parse_imperative_stmt(p, param_pool, fixed_rules, cur_vld))
                 .try_collect()?;
@@ -147,10 +146,10 @@ fn parse_imperative_stmt(
         Rule::loop_block => {
             let mut inner = pair.into_inner();
             let mut mark = None;
-            let mut nxt = inner.next().unwrap();
+            let mut nxt = inner.next_pair()?;
             if nxt.as_rule() == Rule::ident {
                 mark = Some(SmartString::from(nxt.as_str()));
-                nxt = inner.next().unwrap();
+                nxt = inner.next_pair()?;
             }
             let body = parse_imperative_block(nxt, param_pool, fixed_rules, cur_vld)?;
             ImperativeStmt::Loop { label: mark, body }
@@ -158,9 +157,9 @@ fn parse_imperative_stmt(
         Rule::temp_swap => {
             // let span = pair.extract_span();
             let mut pairs = pair.into_inner();
-            let left = pairs.next().unwrap();
+            let left = pairs.next_pair()?;
             let left_name = left.as_str();
-            let right = pairs.next().unwrap();
+            let right = pairs.next_pair()?;
             let right_name = right.as_str();
 
             ImperativeStmt::TempSwap {
@@ -170,7 +169,7 @@ fn parse_imperative_stmt(
         }
         Rule::debug_stmt => {
             // let span = pair.extract_span();
-            let name_p = pair.into_inner().next().unwrap();
+            let name_p = pair.into_inner().next_pair()?;
             let name = name_p.as_str();
 
             ImperativeStmt::TempDebug {
@@ -180,7 +179,7 @@ fn parse_imperative_stmt(
         Rule::imperative_sysop => {
             let mut src = pair.into_inner();
             let sysop = parse_sys(
-                src.next().unwrap().into_inner(),
+                src.next_pair()?.into_inner(),
                 param_pool,
                 fixed_rules,
                 cur_vld,
@@ -193,7 +192,7 @@ fn parse_imperative_stmt(
         Rule::imperative_clause => {
             let mut src = pair.into_inner();
             let prog = parse_query(
-                src.next().unwrap().into_inner(),
+                src.next_pair()?.into_inner(),
                 param_pool,
                 fixed_rules,
                 cur_vld,
@@ -204,10 +203,10 @@ fn parse_imperative_stmt(
             }
         }
         Rule::ignore_error_script => {
-            let pair = pair.into_inner().next().unwrap();
+            let pair = pair.into_inner().next_pair()?;
             let mut src = pair.into_inner();
             let prog = parse_query(
-                src.next().unwrap().into_inner(),
+                src.next_pair()?.into_inner(),
                 param_pool,
                 fixed_rules,
                 cur_vld,
@@ -217,6 +216,6 @@ fn parse_imperative_stmt(
                 prog: ImperativeStmtClause { prog, store_as },
             }
         }
-        r => unreachable!("{r:?}"),
+        r => bail!(UnexpectedSyntax::rule(r)),
     })
 }
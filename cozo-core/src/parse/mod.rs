@@ -9,6 +9,7 @@
 //!
 //! NOTE! This is unstable, the AST structure and method signatures may change in any release. Use at your own risk.
 
+use std::borrow::Cow;
 use std::cmp::{max, min};
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Display, Formatter};
@@ -162,7 +163,7 @@ impl ImperativeStmt {
             | ImperativeStmt::Continue { .. }
             | ImperativeStmt::TempSwap { .. } => {}
             ImperativeStmt::SysOp { sysop } => match &sysop.sysop {
-                SysOp::RemoveRelation(rels) => {
+                SysOp::RemoveRelation(rels, _) => {
                     for rel in rels {
                         collector.insert(rel.name.clone());
                     }
@@ -173,7 +174,7 @@ impl ImperativeStmt {
                         collector.insert(new.name.clone());
                     }
                 }
-                SysOp::CreateIndex(symb, subs, _) => {
+                SysOp::CreateIndex(symb, subs, _, _) => {
                     collector.insert(symb.name.clone());
                     collector.insert(SmartString::from(format!("{}:{}", symb.name, subs.name)));
                 }
@@ -198,6 +199,17 @@ impl ImperativeStmt {
                         m.base_relation, m.index_name
                     )));
                 }
+                SysOp::SoftDelete(rel)
+                | SysOp::AddColumn(rel, _)
+                | SysOp::DropColumn(rel, _)
+                | SysOp::PurgeDeleted(rel, _)
+                | SysOp::SetTtl(rel, _)
+                | SysOp::ExpireRows(rel)
+                | SysOp::CreateView(rel, _)
+                | SysOp::RefreshView(rel)
+                | SysOp::CompactHistory(rel, _) => {
+                    collector.insert(rel.name.clone());
+                }
                 SysOp::RemoveIndex(rel, idx) => {
                     collector.insert(SmartString::from(format!("{}:{}", rel.name, idx.name)));
                 }
@@ -266,18 +278,312 @@ pub(crate) struct ParseError {
     pub(crate) span: SourceSpan,
 }
 
+/// The name an identifier written in a script stands for: parts quoted with backticks,
+/// as in `` ns.`Weird Table` ``, are unquoted, with doubled backticks standing for one.
+pub(crate) fn unquote_ident(src: &str) -> SmartString<LazyCompact> {
+    if !src.contains('`') {
+        return SmartString::from(src);
+    }
+    let mut ret = SmartString::new();
+    let mut quoted = false;
+    let mut chars = src.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '`' if quoted && chars.peek() == Some(&'`') => {
+                chars.next();
+                ret.push('`');
+            }
+            '`' => quoted = !quoted,
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+/// The inverse of [unquote_ident]: quotes the parts of a relation, index or column name
+/// that cannot be written as plain identifiers.
+pub(crate) fn quote_ident(name: &str) -> Cow<'_, str> {
+    let is_plain = |seg: &str| {
+        CozoScriptParser::parse(Rule::plain_ident, seg)
+            .is_ok_and(|mut p| p.next().unwrap().as_str().len() == seg.len())
+    };
+    if name.split(['.', ':']).all(is_plain) {
+        return Cow::Borrowed(name);
+    }
+    let mut ret = String::new();
+    let mut seg_start = 0;
+    for (i, c) in name.char_indices().chain([(name.len(), '.')]) {
+        if c == '.' || c == ':' {
+            let seg = &name[seg_start..i];
+            if is_plain(seg) {
+                ret.push_str(seg);
+            } else {
+                ret.push('`');
+                ret.push_str(&seg.replace('`', "``"));
+                ret.push('`');
+            }
+            if i < name.len() {
+                ret.push(c);
+            }
+            seg_start = i + 1;
+        }
+    }
+    Cow::Owned(ret)
+}
+
 pub(crate) fn parse_type(src: &str) -> Result<NullableColType> {
     let parsed = CozoScriptParser::parse(Rule::col_type_with_term, src)
         .into_diagnostic()?
-        .next()
-        .unwrap();
-    parse_nullable_type(parsed.into_inner().next().unwrap())
+        .next_pair()?;
+    parse_nullable_type(parsed.into_inner().next_pair()?)
+}
+
+/// Limits on the shape of scripts, so that pathological inputs are rejected with an
+/// error instead of exhausting the stack while being parsed or run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// How deeply brackets may nest, e.g. in expressions and list literals.
+    pub max_nesting_depth: usize,
+    /// How many atoms the body of a single rule may have.
+    pub max_rule_body_len: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        Self {
+            max_nesting_depth: 64,
+            max_rule_body_len: 512,
+        }
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The script is nested more than {0} levels deep")]
+#[diagnostic(code(parser::nesting_too_deep))]
+#[diagnostic(help("The limit is set by `max_nesting_depth` of the parse limits"))]
+struct NestingTooDeep(usize, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The rule body has more than {0} atoms")]
+#[diagnostic(code(parser::rule_body_too_long))]
+#[diagnostic(help("The limit is set by `max_rule_body_len` of the parse limits"))]
+struct RuleBodyTooLong(usize, #[label] SourceSpan);
+
+/// Checks the nesting of brackets before the script is handed to the parser,
+/// which would otherwise recurse once for every level. Brackets inside strings,
+/// comments and quoted names are skipped.
+fn check_nesting_depth(src: &str, limits: &ParseLimits) -> Result<()> {
+    let bytes = src.as_bytes();
+    let mut depth = 0usize;
+    scan_script(src, |piece| {
+        let ScriptPiece::Code(i) = piece else {
+            return Ok(());
+        };
+        match bytes[i] {
+            b'(' | b'[' | b'{' => {
+                depth += 1;
+                if depth > limits.max_nesting_depth {
+                    bail!(NestingTooDeep(limits.max_nesting_depth, SourceSpan(i, 1)))
+                }
+            }
+            b')' | b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// Whether the script ends with `;` outside any string, comment, quoted name or brackets,
+/// as a query entered line by line in an interactive shell is.
+pub fn ends_with_terminator(src: &str) -> bool {
+    let bytes = src.as_bytes();
+    let mut depth = 0i64;
+    let mut last = None;
+    let unterminated = scan_script(src, |piece| {
+        match piece {
+            ScriptPiece::Code(i) => {
+                match bytes[i] {
+                    b'(' | b'[' | b'{' => depth += 1,
+                    b')' | b']' | b'}' => depth -= 1,
+                    _ => {}
+                }
+                if !bytes[i].is_ascii_whitespace() {
+                    last = Some(bytes[i]);
+                }
+            }
+            ScriptPiece::Quoted(..) => last = None,
+            ScriptPiece::Comment => {}
+        }
+        Ok(())
+    })
+    .unwrap_or(true);
+    !unterminated && depth <= 0 && last == Some(b';')
+}
+
+/// A piece of a script as the grammar splits it, see [scan_script].
+pub(crate) enum ScriptPiece {
+    /// A byte outside strings, comments and quoted names, at the given position
+    Code(usize),
+    /// A string or a quoted name, from the first position up to the second
+    Quoted(usize, usize),
+    /// A comment
+    Comment,
+}
+
+/// Walks the script the way the grammar splits it, calling `visit` with each of its
+/// pieces in order. Returns whether the script ends inside a string, a block comment
+/// or a quoted name.
+pub(crate) fn scan_script(
+    src: &str,
+    mut visit: impl FnMut(ScriptPiece) -> Result<()>,
+) -> Result<bool> {
+    let bytes = src.as_bytes();
+    // the position just past the first occurrence of `pat` from `from`
+    let find_end = |from: usize, pat: &[u8]| {
+        bytes[from.min(bytes.len())..]
+            .windows(pat.len())
+            .position(|w| w == pat)
+            .map(|p| from + p + pat.len())
+    };
+    // the position just past the closing `quote`, skipping escapes
+    let find_escaped_end = |mut i: usize, quote: &[u8]| {
+        while i < bytes.len() {
+            if bytes[i] == b'\\' {
+                i += 2;
+            } else if bytes[i..].starts_with(quote) {
+                return Some(i + quote.len());
+            } else {
+                i += 1;
+            }
+        }
+        None
+    };
+    let block_comment_end = |mut i: usize| {
+        let mut depth = 0;
+        while i < bytes.len() {
+            if bytes[i..].starts_with(b"/*") {
+                depth += 1;
+                i += 2;
+            } else if bytes[i..].starts_with(b"*/") {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return Some(i);
+                }
+            } else {
+                i += 1;
+            }
+        }
+        None
+    };
+    // quoted names, where a doubled backtick stands for itself
+    let quoted_name_end = |mut i: usize| {
+        while i < bytes.len() {
+            if bytes[i..].starts_with(b"``") {
+                i += 2;
+            } else if bytes[i] == b'`' {
+                return Some(i + 1);
+            } else {
+                i += 1;
+            }
+        }
+        None
+    };
+    let follows_ident = |i: usize| i > 0 && is_ident_byte(bytes[i - 1]);
+    let mut unterminated = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        // the end of the comment or the literal starting here, if any, and whether
+        // it is a literal
+        let is_space = |b: Option<&u8>| matches!(b, Some(b' ' | b'\t' | b'\r' | b'\n'));
+        let found = if rest.starts_with(b"#")
+            || rest.starts_with(b"//")
+            || (rest.starts_with(b"--")
+                && (i == 0 || is_space(bytes.get(i - 1)))
+                && (rest.len() == 2 || is_space(rest.get(2))))
+        {
+            Some((Some(find_end(i, b"\n").unwrap_or(bytes.len())), false))
+        } else if rest.starts_with(b"/*") {
+            Some((block_comment_end(i), false))
+        } else if rest.starts_with(b"`") {
+            Some((quoted_name_end(i + 1), true))
+        } else if rest.starts_with(b"\"\"\"") || rest.starts_with(b"'''") {
+            Some((find_escaped_end(i + 3, &rest[..3]), true))
+        } else if rest[0] == b'r' && !follows_ident(i) {
+            // r'...', r#"..."# etc., closed by the same quote and number of #
+            let hashes = rest[1..].iter().take_while(|b| **b == b'#').count();
+            match rest.get(1 + hashes) {
+                Some(quote @ (b'"' | b'\'')) => {
+                    let mut closing = vec![*quote];
+                    closing.extend(&rest[1..1 + hashes]);
+                    Some((find_end(i + 2 + hashes, &closing), true))
+                }
+                _ => None,
+            }
+        } else if rest[0] == b'_' || rest[0] == b'"' {
+            // "...", _"..."_ etc., closed by a quotation mark and the same number of _
+            let underscores = rest.iter().take_while(|b| **b == b'_').count();
+            if rest.get(underscores) == Some(&b'"') && (underscores == 0 || !follows_ident(i)) {
+                let mut closing = vec![b'"'];
+                closing.extend(&rest[..underscores]);
+                Some((find_end(i + underscores + 1, &closing), true))
+            } else {
+                None
+            }
+        } else if rest[0] == b'\'' {
+            Some((find_escaped_end(i + 1, b"'"), true))
+        } else {
+            None
+        };
+        match found {
+            Some((end, quoted)) => {
+                let end = end.unwrap_or_else(|| {
+                    unterminated = true;
+                    bytes.len()
+                });
+                visit(if quoted {
+                    ScriptPiece::Quoted(i, end)
+                } else {
+                    ScriptPiece::Comment
+                })?;
+                i = end;
+            }
+            None => {
+                visit(ScriptPiece::Code(i))?;
+                i += 1;
+            }
+        }
+    }
+    Ok(unterminated)
+}
+
+fn is_ident_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b >= 0x80
+}
+
+/// Checks the parsed script against limits that depend on its structure.
+fn check_parsed_limits(parsed: &Pair<'_>, limits: &ParseLimits) -> Result<()> {
+    // `flatten` walks the tree without recursion
+    for pair in parsed.clone().into_inner().flatten() {
+        if pair.as_rule() == Rule::rule_body
+            && pair.clone().into_inner().count() > limits.max_rule_body_len
+        {
+            bail!(RuleBodyTooLong(
+                limits.max_rule_body_len,
+                pair.extract_span()
+            ))
+        }
+    }
+    Ok(())
 }
 
 pub(crate) fn parse_expressions(
     src: &str,
     param_pool: &BTreeMap<String, DataValue>,
 ) -> Result<Expr> {
+    check_nesting_depth(src, &ParseLimits::default())?;
     let parsed = CozoScriptParser::parse(Rule::expression_script, src)
         .map_err(|err| {
             let span = match err.location {
@@ -286,10 +592,9 @@ pub(crate) fn parse_expressions(
             };
             ParseError { span }
         })?
-        .next()
-        .unwrap();
+        .next_pair()?;
 
-    build_expr(parsed.into_inner().next().unwrap(), param_pool)
+    build_expr(parsed.into_inner().next_pair()?, param_pool)
 }
 
 /// This parses a text script into the AST used by Cozo.
@@ -309,6 +614,25 @@ pub fn parse_script(
     fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
     cur_vld: ValidityTs,
 ) -> Result<CozoScript> {
+    parse_script_with_limits(
+        src,
+        param_pool,
+        fixed_rules,
+        cur_vld,
+        &ParseLimits::default(),
+    )
+}
+
+/// The same as [parse_script], but rejects scripts exceeding `limits` instead of
+/// those exceeding the default ones.
+pub fn parse_script_with_limits(
+    src: &str,
+    param_pool: &BTreeMap<String, DataValue>,
+    fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
+    cur_vld: ValidityTs,
+    limits: &ParseLimits,
+) -> Result<CozoScript> {
+    check_nesting_depth(src, limits)?;
     let parsed = CozoScriptParser::parse(Rule::script, src)
         .map_err(|err| {
             let span = match err.location {
@@ -317,8 +641,8 @@ pub fn parse_script(
             };
             ParseError { span }
         })?
-        .next()
-        .unwrap();
+        .next_pair()?;
+    check_parsed_limits(&parsed, limits)?;
     Ok(match parsed.as_rule() {
         Rule::query_script => {
             let q = parse_query(parsed.into_inner(), param_pool, fixed_rules, cur_vld)?;
@@ -335,7 +659,7 @@ pub fn parse_script(
             fixed_rules,
             cur_vld,
         )?),
-        _ => unreachable!(),
+        r => bail!(UnexpectedSyntax::rule(r)),
     })
 }
 
@@ -351,3 +675,30 @@ impl ExtractSpan for Pair<'_> {
         SourceSpan(start, end - start)
     }
 }
+
+/// Raised instead of panicking when the parse tree does not have the shape the
+/// builders expect of it.
+#[derive(Debug, Error, Diagnostic)]
+#[error("The parser has encountered an unexpected {0} in the script")]
+#[diagnostic(code(parser::unexpected_syntax))]
+pub(crate) struct UnexpectedSyntax(pub(crate) String);
+
+impl UnexpectedSyntax {
+    pub(crate) fn rule(r: Rule) -> Self {
+        Self(format!("{r:?}"))
+    }
+}
+
+trait NextPair<T> {
+    /// The next item of the parse tree, or an error if there are no more.
+    fn next_pair(&mut self) -> Result<T>;
+}
+
+impl<T, I: Iterator<Item = T>> NextPair<T> for I {
+    fn next_pair(&mut self) -> Result<T> {
+        match self.next() {
+            Some(item) => Ok(item),
+            None => bail!(UnexpectedSyntax("end of input".to_string())),
+        }
+    }
+}
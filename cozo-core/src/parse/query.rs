@@ -8,7 +8,7 @@
 
 use std::cmp::Reverse;
 use std::collections::btree_map::Entry;
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use std::sync::Arc;
@@ -22,11 +22,12 @@ use thiserror::Error;
 
 use crate::data::aggr::{parse_aggr, Aggregation};
 use crate::data::expr::Expr;
-use crate::data::functions::{str2vld, MAX_VALIDITY_TS};
+use crate::data::functions::{str2vld, MAX_VALIDITY_TS, OP_RANGE, OP_RANGE_INCLUSIVE};
 use crate::data::program::{
     FixedRuleApply, FixedRuleArg, InputAtom, InputInlineRule, InputInlineRulesOrFixed,
     InputNamedFieldRelationApplyAtom, InputProgram, InputRelationApplyAtom, InputRuleApplyAtom,
-    QueryAssertion, QueryOutOptions, RelationOp, ReturnMutation, SearchInput, SortDir, Unification,
+    QueryAssertion, QueryCursor, QueryOutOptions, RelationOp, ReturnMutation, SearchInput, Series,
+    SortDir, Unification,
 };
 use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::{Symbol, PROG_ENTRY};
@@ -34,9 +35,12 @@ use crate::data::value::{DataValue, ValidityTs};
 use crate::fixed_rule::utilities::constant::Constant;
 use crate::fixed_rule::{FixedRuleHandle, FixedRuleNotFoundError};
 use crate::parse::expr::build_expr;
-use crate::parse::schema::parse_schema;
-use crate::parse::{CozoScriptParser, ExtractSpan, Pair, Pairs, Rule, SourceSpan};
-use crate::runtime::relation::InputRelationHandle;
+use crate::parse::schema::{parse_nullable_type, parse_schema};
+use crate::parse::{
+    unquote_ident, CozoScriptParser, ExtractSpan, NextPair, Pair, Pairs, Rule, SourceSpan,
+    UnexpectedSyntax,
+};
+use crate::runtime::relation::{InputRelationHandle, OnConflict};
 use crate::FixedRule;
 
 #[derive(Error, Diagnostic, Debug)]
@@ -49,6 +53,11 @@ struct OptionNotConstantError(&'static str, #[label] SourceSpan, #[related] [Rep
 #[diagnostic(code(parser::option_not_non_neg))]
 struct OptionNotNonNegIntError(&'static str, #[label] SourceSpan);
 
+#[derive(Error, Diagnostic, Debug)]
+#[error("Query option after requires a cursor string or null")]
+#[diagnostic(code(parser::option_not_cursor))]
+struct OptionNotCursorError(#[label] SourceSpan);
+
 #[derive(Error, Diagnostic, Debug)]
 #[error("Query option {0} requires a positive integer")]
 #[diagnostic(code(parser::option_not_pos))]
@@ -96,11 +105,11 @@ impl Diagnostic for MultipleRuleDefinitionError {
 }
 
 fn merge_spans(symbs: &[Symbol]) -> SourceSpan {
-    let mut fst = symbs.first().unwrap().span;
-    for nxt in symbs.iter().skip(1) {
-        fst = fst.merge(nxt.span);
-    }
-    fst
+    symbs
+        .iter()
+        .map(|s| s.span)
+        .reduce(SourceSpan::merge)
+        .unwrap_or_default()
 }
 
 pub(crate) fn parse_query(
@@ -115,6 +124,7 @@ pub(crate) fn parse_query(
 
     let mut stored_relation = None;
     let mut returning_mutation = ReturnMutation::NotReturning;
+    let mut on_conflict = None;
 
     for pair in src {
         match pair.as_rule() {
@@ -185,7 +195,15 @@ pub(crate) fn parse_query(
             Rule::const_rule => {
                 let span = pair.extract_span();
                 let mut src = pair.into_inner();
-                let (name, mut head, aggr) = parse_rule_head(src.next().unwrap(), param_pool)?;
+                let head_pair = src.next_pair()?;
+                let (name, mut head, aggr, types) = if head_pair.as_rule() == Rule::typed_rule_head
+                {
+                    let (name, head, types) = parse_typed_rule_head(head_pair)?;
+                    (name, head, vec![], types)
+                } else {
+                    let (name, head, aggr) = parse_rule_head(head_pair, param_pool)?;
+                    (name, head, aggr, vec![])
+                };
 
                 if let Some(found) = progs.get(&name) {
                     let mut found_span = match found {
@@ -211,7 +229,7 @@ pub(crate) fn parse_query(
                 for (a, v) in aggr.iter().zip(head.iter()) {
                     ensure!(a.is_none(), AggrInConstRuleError(v.span));
                 }
-                let data_part = src.next().unwrap();
+                let data_part = src.next_pair()?;
                 let data_part_str = data_part.as_str();
                 let data = build_expr(data_part.clone(), param_pool)?;
                 let mut options = BTreeMap::new();
@@ -228,14 +246,23 @@ pub(crate) fn parse_query(
                     head.is_empty() || arity == head.len(),
                     FixedRuleHeadArityMismatch(arity, head.len(), span)
                 );
+                if types.iter().any(|t| t.is_some()) {
+                    coerce_const_rows(
+                        &mut options,
+                        &head,
+                        &types,
+                        data_part.extract_span(),
+                        cur_vld,
+                    )?;
+                }
                 if head.is_empty() && name.is_prog_entry() {
                     if let Ok(mut datalist) =
                         CozoScriptParser::parse(Rule::param_list, data_part_str)
                     {
-                        for s in datalist.next().unwrap().into_inner() {
+                        for s in datalist.next_pair()?.into_inner() {
                             if s.as_rule() == Rule::param {
                                 head.push(Symbol::new(
-                                    s.as_str().strip_prefix('$').unwrap(),
+                                    s.as_str().trim_start_matches('$'),
                                     Default::default(),
                                 ));
                             }
@@ -258,7 +285,7 @@ pub(crate) fn parse_query(
                 );
             }
             Rule::timeout_option => {
-                let pair = pair.into_inner().next().unwrap();
+                let pair = pair.into_inner().next_pair()?;
                 let span = pair.extract_span();
                 let timeout = build_expr(pair, param_pool)?
                     .eval_to_const()
@@ -277,7 +304,7 @@ pub(crate) fn parse_query(
 
                 #[cfg(not(target_arch = "wasm32"))]
                 {
-                    let pair = pair.into_inner().next().unwrap();
+                    let pair = pair.into_inner().next_pair()?;
                     let span = pair.extract_span();
                     let sleep = build_expr(pair, param_pool)?
                         .eval_to_const()
@@ -289,7 +316,7 @@ pub(crate) fn parse_query(
                 }
             }
             Rule::limit_option => {
-                let pair = pair.into_inner().next().unwrap();
+                let pair = pair.into_inner().next_pair()?;
                 let span = pair.extract_span();
                 let limit = build_expr(pair, param_pool)?
                     .eval_to_const()
@@ -299,7 +326,7 @@ pub(crate) fn parse_query(
                 out_opts.limit = Some(limit as usize);
             }
             Rule::offset_option => {
-                let pair = pair.into_inner().next().unwrap();
+                let pair = pair.into_inner().next_pair()?;
                 let span = pair.extract_span();
                 let offset = build_expr(pair, param_pool)?
                     .eval_to_const()
@@ -308,6 +335,18 @@ pub(crate) fn parse_query(
                     .ok_or(OptionNotNonNegIntError("offset", span))?;
                 out_opts.offset = Some(offset as usize);
             }
+            Rule::after_option => {
+                let pair = pair.into_inner().next_pair()?;
+                let span = pair.extract_span();
+                let after = build_expr(pair, param_pool)?
+                    .eval_to_const()
+                    .map_err(|err| OptionNotConstantError("after", span, [err]))?;
+                out_opts.after = match after {
+                    DataValue::Null => None,
+                    DataValue::Str(s) => Some(Box::new(QueryCursor::decode(&s)?)),
+                    _ => bail!(OptionNotCursorError(span)),
+                };
+            }
             Rule::sort_option => {
                 for part in pair.into_inner() {
                     let mut var = "";
@@ -321,7 +360,7 @@ pub(crate) fn parse_query(
                             }
                             Rule::sort_asc => dir = SortDir::Asc,
                             Rule::sort_desc => dir = SortDir::Dsc,
-                            _ => unreachable!(),
+                            r => bail!(UnexpectedSyntax::rule(r)),
                         }
                     }
                     out_opts.sorters.push((Symbol::new(var, span), dir));
@@ -330,29 +369,75 @@ pub(crate) fn parse_query(
             Rule::returning_option => {
                 returning_mutation = ReturnMutation::Returning;
             }
+            Rule::max_hops_option => {
+                let pair = pair.into_inner().next_pair()?;
+                let span = pair.extract_span();
+                let max_hops = build_expr(pair, param_pool)?
+                    .eval_to_const()
+                    .map_err(|err| OptionNotConstantError("max_hops", span, [err]))?
+                    .get_non_neg_int()
+                    .ok_or(OptionNotNonNegIntError("max_hops", span))?;
+                out_opts.max_hops = Some(u32::try_from(max_hops).unwrap_or(u32::MAX));
+            }
+            Rule::truncate_hops_option => {
+                out_opts.truncate_hops = true;
+            }
+            Rule::on_conflict_option => {
+                let span = pair.extract_span();
+                let policy = pair.into_inner().next_pair()?;
+                let policy = match policy.as_rule() {
+                    Rule::on_conflict_error => OnConflict::Error,
+                    Rule::on_conflict_ignore => OnConflict::Ignore,
+                    Rule::on_conflict_update => {
+                        let mut assignments = BTreeMap::new();
+                        for assign in policy.into_inner() {
+                            let mut src = assign.into_inner();
+                            let col = src.next_pair()?;
+                            let expr = build_expr(src.next_pair()?, param_pool)?;
+                            if assignments
+                                .insert(unquote_ident(col.as_str()), expr)
+                                .is_some()
+                            {
+                                #[derive(Debug, Error, Diagnostic)]
+                                #[error("Column {0} is assigned more than once on conflict")]
+                                #[diagnostic(code(parser::dup_on_conflict_col))]
+                                struct DuplicateOnConflictColumn(String, #[label] SourceSpan);
+                                bail!(DuplicateOnConflictColumn(
+                                    col.as_str().to_string(),
+                                    col.extract_span()
+                                ))
+                            }
+                        }
+                        OnConflict::Update(assignments)
+                    }
+                    r => bail!(UnexpectedSyntax::rule(r)),
+                };
+                on_conflict = Some((policy, span));
+            }
             Rule::relation_option => {
                 let span = pair.extract_span();
                 let mut args = pair.into_inner();
-                let op = match args.next().unwrap().as_rule() {
+                let op = match args.next_pair()?.as_rule() {
                     Rule::relation_create => RelationOp::Create,
                     Rule::relation_replace => RelationOp::Replace,
                     Rule::relation_put => RelationOp::Put,
                     Rule::relation_insert => RelationOp::Insert,
+                    Rule::relation_upsert => RelationOp::Upsert,
                     Rule::relation_update => RelationOp::Update,
                     Rule::relation_rm => RelationOp::Rm,
                     Rule::relation_delete => RelationOp::Delete,
                     Rule::relation_ensure => RelationOp::Ensure,
                     Rule::relation_ensure_not => RelationOp::EnsureNot,
-                    _ => unreachable!(),
+                    r => bail!(UnexpectedSyntax::rule(r)),
                 };
 
-                let name_p = args.next().unwrap();
-                let name = Symbol::new(name_p.as_str(), name_p.extract_span());
+                let name_p = args.next_pair()?;
+                let name = Symbol::new(unquote_ident(name_p.as_str()), name_p.extract_span());
                 match args.next() {
                     None => stored_relation = Some(Left((name, span, op))),
                     Some(schema_p) => {
-                        let (mut metadata, mut key_bindings, mut dep_bindings) =
-                            parse_schema(schema_p)?;
+                        let (mut metadata, mut key_bindings, mut dep_bindings, binding_exprs) =
+                            parse_schema(schema_p, param_pool)?;
                         if !matches!(op, RelationOp::Create | RelationOp::Replace) {
                             key_bindings.extend(dep_bindings);
                             dep_bindings = vec![];
@@ -365,6 +450,8 @@ pub(crate) fn parse_query(
                                 metadata,
                                 key_bindings,
                                 dep_bindings,
+                                binding_exprs,
+                                on_conflict: Default::default(),
                                 span,
                             },
                             op,
@@ -387,7 +474,7 @@ pub(crate) fn parse_query(
                 out_opts.assertion = Some(QueryAssertion::AssertSome(pair.extract_span()))
             }
             Rule::disable_magic_rewrite_option => {
-                let pair = pair.into_inner().next().unwrap();
+                let pair = pair.into_inner().next_pair()?;
                 let span = pair.extract_span();
                 let val = build_expr(pair, param_pool)?
                     .eval_to_const()
@@ -397,7 +484,7 @@ pub(crate) fn parse_query(
                 disable_magic_rewrite = val;
             }
             Rule::EOI => break,
-            r => unreachable!("{:?}", r),
+            r => bail!(UnexpectedSyntax::rule(r)),
         }
     }
 
@@ -426,6 +513,22 @@ pub(crate) fn parse_query(
 
     // let head_arity = prog.get_entry_arity()?;
 
+    if let Some((_, span)) = &on_conflict {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Conflict handling is only applicable to ':insert'")]
+        #[diagnostic(code(parser::on_conflict_without_insert))]
+        struct OnConflictWithoutInsert(#[label] SourceSpan);
+
+        ensure!(
+            matches!(
+                stored_relation,
+                Some(Left((_, _, RelationOp::Insert)) | Right((_, RelationOp::Insert)))
+            ),
+            OnConflictWithoutInsert(*span)
+        );
+    }
+    let on_conflict = on_conflict.map(|(policy, _)| policy).unwrap_or_default();
+
     match stored_relation {
         None => {}
         Some(Left((name, span, op))) => {
@@ -454,11 +557,16 @@ pub(crate) fn parse_query(
                 metadata,
                 key_bindings: head,
                 dep_bindings: vec![],
+                binding_exprs: Default::default(),
+                on_conflict,
                 span,
             };
             prog.out_opts.store_relation = Some((handle, op, returning_mutation))
         }
-        Some(Right((h, o))) => prog.out_opts.store_relation = Some((h, o, returning_mutation)),
+        Some(Right((mut h, o))) => {
+            h.on_conflict = on_conflict;
+            prog.out_opts.store_relation = Some((h, o, returning_mutation))
+        }
     }
 
     if prog.prog.is_empty() {
@@ -538,7 +646,7 @@ fn parse_rule(
 ) -> Result<(Symbol, InputInlineRule)> {
     let span = src.extract_span();
     let mut src = src.into_inner();
-    let head = src.next().unwrap();
+    let head = src.next_pair()?;
     let head_span = head.extract_span();
     let (name, head, aggr) = parse_rule_head(head, param_pool)?;
 
@@ -548,7 +656,7 @@ fn parse_rule(
     struct EmptyRuleHead(#[label] SourceSpan);
 
     ensure!(!head.is_empty(), EmptyRuleHead(head_span));
-    let body = src.next().unwrap();
+    let body = src.next_pair()?;
     let mut body_clauses = vec![];
     let mut ignored_counter = 0;
     for atom_src in body.into_inner() {
@@ -586,7 +694,7 @@ fn parse_disjunction(
         })
         .try_collect()?;
     Ok(if res.len() == 1 {
-        res.into_iter().next().unwrap()
+        res.into_iter().next_pair()?
     } else {
         InputAtom::Disjunction { inner: res, span }
     })
@@ -614,8 +722,8 @@ fn parse_atom(
         Rule::negation => {
             let span = src.extract_span();
             let mut src = src.into_inner();
-            src.next().unwrap();
-            let inner = parse_atom(src.next().unwrap(), param_pool, cur_vld, ignored_counter)?;
+            src.next_pair()?;
+            let inner = parse_atom(src.next_pair()?, param_pool, cur_vld, ignored_counter)?;
             InputAtom::Negation {
                 inner: inner.into(),
                 span,
@@ -628,13 +736,13 @@ fn parse_atom(
         Rule::unify => {
             let span = src.extract_span();
             let mut src = src.into_inner();
-            let var = src.next().unwrap();
+            let var = src.next_pair()?;
             let mut symb = Symbol::new(var.as_str(), var.extract_span());
             if symb.is_ignored_symbol() {
                 symb.name = format!("*^*{}", *ignored_counter).into();
                 *ignored_counter += 1;
             }
-            let expr = build_expr(src.next().unwrap(), param_pool)?;
+            let expr = build_expr(src.next_pair()?, param_pool)?;
             InputAtom::Unification {
                 inner: Unification {
                     binding: symb,
@@ -647,14 +755,14 @@ fn parse_atom(
         Rule::unify_multi => {
             let span = src.extract_span();
             let mut src = src.into_inner();
-            let var = src.next().unwrap();
+            let var = src.next_pair()?;
             let mut symb = Symbol::new(var.as_str(), var.extract_span());
             if symb.is_ignored_symbol() {
                 symb.name = format!("*^*{}", *ignored_counter).into();
                 *ignored_counter += 1;
             }
-            src.next().unwrap();
-            let expr = build_expr(src.next().unwrap(), param_pool)?;
+            src.next_pair()?;
+            let expr = build_expr(src.next_pair()?, param_pool)?;
             InputAtom::Unification {
                 inner: Unification {
                     binding: symb,
@@ -664,13 +772,49 @@ fn parse_atom(
                 },
             }
         }
+        Rule::series_apply => {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("A series requires a range literal such as `1..10` or `1..=10`")]
+            #[diagnostic(code(parser::series_without_range))]
+            struct SeriesWithoutRange(#[label] SourceSpan);
+
+            let span = src.extract_span();
+            let mut src = src.into_inner();
+            let var = src.next_pair()?;
+            let mut symb = Symbol::new(var.as_str(), var.extract_span());
+            if symb.is_ignored_symbol() {
+                symb.name = format!("*^*{}", *ignored_counter).into();
+                *ignored_counter += 1;
+            }
+            let range_p = src.next_pair()?;
+            let range_span = range_p.extract_span();
+            let (start, end, inclusive) = match build_expr(range_p, param_pool)? {
+                Expr::Apply { op, args, .. }
+                    if op.name == OP_RANGE.name || op.name == OP_RANGE_INCLUSIVE.name =>
+                {
+                    let [start, end]: [Expr; 2] = args.into_vec().try_into().unwrap();
+                    (
+                        start.eval_to_const()?,
+                        end.eval_to_const()?,
+                        op.name == OP_RANGE_INCLUSIVE.name,
+                    )
+                }
+                _ => bail!(SeriesWithoutRange(range_span)),
+            };
+            let step = match src.next() {
+                None => DataValue::from(1),
+                Some(step_p) => build_expr(step_p, param_pool)?.eval_to_const()?,
+            };
+            InputAtom::Series {
+                inner: Series::new(symb, start, end, step, inclusive, span)?,
+            }
+        }
         Rule::rule_apply => {
             let span = src.extract_span();
             let mut src = src.into_inner();
-            let name = src.next().unwrap();
+            let name = src.next_pair()?;
             let args: Vec<_> = src
-                .next()
-                .unwrap()
+                .next_pair()?
                 .into_inner()
                 .map(|v| build_expr(v, param_pool))
                 .try_collect()?;
@@ -685,25 +829,19 @@ fn parse_atom(
         Rule::relation_apply => {
             let span = src.extract_span();
             let mut src = src.into_inner();
-            let name = src.next().unwrap();
+            let name = src.next_pair()?;
             let args: Vec<_> = src
-                .next()
-                .unwrap()
+                .next_pair()?
                 .into_inner()
                 .map(|v| build_expr(v, param_pool))
                 .try_collect()?;
-            let valid_at = match src.next() {
-                None => None,
-                Some(vld_clause) => {
-                    let vld_expr = build_expr(vld_clause.into_inner().next().unwrap(), param_pool)?;
-                    Some(expr2vld_spec(vld_expr, cur_vld)?)
-                }
-            };
+            let (valid_at, include_deleted) = parse_scan_clauses(src, param_pool, cur_vld)?;
             InputAtom::Relation {
                 inner: InputRelationApplyAtom {
-                    name: Symbol::new(&name.as_str()[1..], name.extract_span()),
+                    name: Symbol::new(unquote_ident(&name.as_str()[1..]), name.extract_span()),
                     args,
                     valid_at,
+                    include_deleted,
                     span,
                 },
             }
@@ -711,7 +849,7 @@ fn parse_atom(
         Rule::search_apply => {
             let span = src.extract_span();
             let mut src = src.into_inner();
-            let name_p = src.next().unwrap();
+            let name_p = src.next_pair()?;
             let name_segs = name_p.as_str().split(':').collect_vec();
 
             #[derive(Debug, Error, Diagnostic)]
@@ -723,11 +861,10 @@ fn parse_atom(
                 name_segs.len() == 2,
                 InvalidSearchHead(name_p.extract_span())
             );
-            let relation = Symbol::new(name_segs[0], name_p.extract_span());
-            let index = Symbol::new(name_segs[1], name_p.extract_span());
+            let relation = Symbol::new(unquote_ident(name_segs[0]), name_p.extract_span());
+            let index = Symbol::new(unquote_ident(name_segs[1]), name_p.extract_span());
             let bindings: BTreeMap<SmartString<LazyCompact>, Expr> = src
-                .next()
-                .unwrap()
+                .next_pair()?
                 .into_inner()
                 .map(|arg| extract_named_apply_arg(arg, param_pool))
                 .try_collect()?;
@@ -748,43 +885,49 @@ fn parse_atom(
         Rule::relation_named_apply => {
             let span = src.extract_span();
             let mut src = src.into_inner();
-            let name_p = src.next().unwrap();
-            let name = Symbol::new(&name_p.as_str()[1..], name_p.extract_span());
+            let name_p = src.next_pair()?;
+            let name = Symbol::new(unquote_ident(&name_p.as_str()[1..]), name_p.extract_span());
             let args = src
-                .next()
-                .unwrap()
+                .next_pair()?
                 .into_inner()
                 .map(|arg| extract_named_apply_arg(arg, param_pool))
                 .try_collect()?;
-            let valid_at = match src.next() {
-                None => None,
-                Some(vld_clause) => {
-                    let vld_expr = build_expr(vld_clause.into_inner().next().unwrap(), param_pool)?;
-                    Some(expr2vld_spec(vld_expr, cur_vld)?)
-                }
-            };
+            let (valid_at, include_deleted) = parse_scan_clauses(src, param_pool, cur_vld)?;
             InputAtom::NamedFieldRelation {
                 inner: InputNamedFieldRelationApplyAtom {
                     name,
                     args,
                     span,
                     valid_at,
+                    include_deleted,
                 },
             }
         }
-        r => unreachable!("{:?}", r),
+        r => bail!(UnexpectedSyntax::rule(r)),
     })
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("The quoted column {0} must be bound to a variable explicitly")]
+#[diagnostic(code(parser::unbound_quoted_column))]
+#[diagnostic(help("Bind the column as in `{{`my column`: my_column}}`"))]
+struct UnboundQuotedColumn(String, #[label] SourceSpan);
+
 fn extract_named_apply_arg(
     pair: Pair<'_>,
     param_pool: &BTreeMap<String, DataValue>,
 ) -> Result<(SmartString<LazyCompact>, Expr)> {
     let mut inner = pair.into_inner();
-    let name_p = inner.next().unwrap();
-    let name = SmartString::from(name_p.as_str());
+    let name_p = inner.next_pair()?;
+    let name = unquote_ident(name_p.as_str());
     let arg = match inner.next() {
         Some(a) => build_expr(a, param_pool)?,
+        None if name_p.as_rule() == Rule::quoted_ident => {
+            bail!(UnboundQuotedColumn(
+                name_p.as_str().to_string(),
+                name_p.extract_span()
+            ))
+        }
         None => Expr::Binding {
             var: Symbol::new(name.clone(), name_p.extract_span()),
             tuple_pos: None,
@@ -802,7 +945,7 @@ fn parse_rule_head(
     Vec<Option<(Aggregation, Vec<DataValue>)>>,
 )> {
     let mut src = src.into_inner();
-    let name = src.next().unwrap();
+    let name = src.next_pair()?;
     let mut args = vec![];
     let mut aggrs = vec![];
     for p in src {
@@ -813,6 +956,104 @@ fn parse_rule_head(
     Ok((Symbol::new(name.as_str(), name.extract_span()), args, aggrs))
 }
 
+/// Parse the head of a constant rule, in which each variable may be given a type
+fn parse_typed_rule_head(
+    src: Pair<'_>,
+) -> Result<(
+    Symbol,
+    Vec<Symbol>,
+    Vec<Option<(NullableColType, SourceSpan)>>,
+)> {
+    let mut src = src.into_inner();
+    let name = src.next_pair()?;
+    let mut args = vec![];
+    let mut types = vec![];
+    for p in src {
+        let span = p.extract_span();
+        let mut inner = p.into_inner();
+        let var = inner.next_pair()?;
+        args.push(Symbol::new(var.as_str(), var.extract_span()));
+        types.push(match inner.next() {
+            Some(t) => Some((parse_nullable_type(t)?, span)),
+            None => None,
+        });
+    }
+    Ok((Symbol::new(name.as_str(), name.extract_span()), args, types))
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("row {0} of the constant rule does not fit column '{1}: {2}'")]
+#[diagnostic(code(parser::const_rule_type_mismatch))]
+struct ConstRuleTypeMismatch(
+    usize,
+    String,
+    NullableColType,
+    #[label("declared here")] SourceSpan,
+    #[label("in this data")] SourceSpan,
+    #[related] Vec<Report>,
+);
+
+/// Coerce the data of a constant rule to the types declared in its head, and keep the
+/// types in the `types` option so that the rule has a schema even when it has no rows
+fn coerce_const_rows(
+    options: &mut BTreeMap<SmartString<LazyCompact>, Expr>,
+    head: &[Symbol],
+    types: &[Option<(NullableColType, SourceSpan)>],
+    data_span: SourceSpan,
+    cur_vld: ValidityTs,
+) -> Result<()> {
+    let rows = match options.get("data").and_then(|data| data.get_const()) {
+        Some(DataValue::List(rows)) => rows.clone(),
+        _ => return Ok(()),
+    };
+    let mut coerced = Vec::with_capacity(rows.len());
+    for (i, row) in rows.into_iter().enumerate() {
+        let DataValue::List(row) = row else {
+            unreachable!()
+        };
+        let mut new_row = Vec::with_capacity(row.len());
+        for ((val, typing), symb) in row.into_iter().zip(types).zip(head) {
+            new_row.push(match typing {
+                None => val,
+                Some((typing, typing_span)) => typing.coerce(val, cur_vld).map_err(|err| {
+                    ConstRuleTypeMismatch(
+                        i,
+                        symb.name.to_string(),
+                        typing.clone(),
+                        *typing_span,
+                        data_span,
+                        vec![err],
+                    )
+                })?,
+            })
+        }
+        coerced.push(DataValue::List(new_row));
+    }
+    options.insert(
+        SmartString::from("data"),
+        Expr::Const {
+            val: DataValue::List(coerced),
+            span: data_span,
+        },
+    );
+    options.insert(
+        SmartString::from("types"),
+        Expr::Const {
+            val: DataValue::List(
+                types
+                    .iter()
+                    .map(|t| match t {
+                        None => DataValue::Null,
+                        Some((typing, _)) => DataValue::from(typing.to_string()),
+                    })
+                    .collect(),
+            ),
+            span: Default::default(),
+        },
+    );
+    Ok(())
+}
+
 #[derive(Error, Diagnostic, Debug)]
 #[diagnostic(code(parser::aggr_not_found))]
 #[error("Aggregation '{0}' not found")]
@@ -822,14 +1063,14 @@ fn parse_rule_head_arg(
     src: Pair<'_>,
     param_pool: &BTreeMap<String, DataValue>,
 ) -> Result<(Symbol, Option<(Aggregation, Vec<DataValue>)>)> {
-    let src = src.into_inner().next().unwrap();
+    let src = src.into_inner().next_pair()?;
     Ok(match src.as_rule() {
         Rule::var => (Symbol::new(src.as_str(), src.extract_span()), None),
         Rule::aggr_arg => {
             let mut inner = src.into_inner();
-            let aggr_p = inner.next().unwrap();
+            let aggr_p = inner.next_pair()?;
             let aggr_name = aggr_p.as_str();
-            let var = inner.next().unwrap();
+            let var = inner.next_pair()?;
             let args: Vec<_> = inner
                 .map(|v| -> Result<DataValue> { build_expr(v, param_pool)?.eval_to_const() })
                 .try_collect()?;
@@ -843,7 +1084,7 @@ fn parse_rule_head_arg(
                 )),
             )
         }
-        _ => unreachable!(),
+        r => bail!(UnexpectedSyntax::rule(r)),
     })
 }
 
@@ -852,14 +1093,14 @@ fn parse_rule_head_arg(
 #[diagnostic(code(parser::bad_validity_spec))]
 struct BadValiditySpecification(#[label] SourceSpan);
 
-fn parse_fixed_rule(
-    src: Pair<'_>,
+fn parse_fixed_rule<'a>(
+    src: Pair<'a>,
     param_pool: &BTreeMap<String, DataValue>,
     fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
     cur_vld: ValidityTs,
 ) -> Result<(Symbol, FixedRuleApply)> {
     let mut src = src.into_inner();
-    let (out_symbol, head, aggr) = parse_rule_head(src.next().unwrap(), param_pool)?;
+    let (out_symbol, head, aggr) = parse_rule_head(src.next_pair()?, param_pool)?;
 
     #[derive(Debug, Error, Diagnostic)]
     #[error("fixed rule cannot be combined with aggregation")]
@@ -867,33 +1108,57 @@ fn parse_fixed_rule(
     struct AggrInfixedError(#[label] SourceSpan);
 
     #[derive(Debug, Error, Diagnostic)]
-    #[error("fixed rule cannot have duplicate bindings")]
+    #[error("fixed rule cannot have duplicate bindings: '{0}' is bound {1}")]
     #[diagnostic(code(parser::duplicate_bindings_for_fixed_rule))]
-    struct DuplicateBindingError(#[label] SourceSpan);
+    #[diagnostic(help("Use distinct variables and join the results in another rule instead"))]
+    struct DuplicateBindingError(
+        String,
+        String,
+        #[label("first bound here")] SourceSpan,
+        #[label("bound again here")] SourceSpan,
+    );
+
+    // the name of each binding, with where it is bound and the relation binding it
+    let mut seen_bindings: BTreeMap<&str, (SourceSpan, &str)> = BTreeMap::new();
+    let mut bind = |var: &'a str, span: SourceSpan, source: &'a str| -> Result<()> {
+        if let Some((first_span, first_source)) = seen_bindings.insert(var, (span, source)) {
+            let sources = if first_source == source {
+                format!("twice by {source}")
+            } else {
+                format!("by both {first_source} and {source}")
+            };
+            bail!(DuplicateBindingError(
+                var.to_string(),
+                sources,
+                first_span,
+                span
+            ))
+        }
+        Ok(())
+    };
 
     for (a, v) in aggr.iter().zip(head.iter()) {
         ensure!(a.is_none(), AggrInfixedError(v.span))
     }
 
-    let mut seen_bindings = BTreeSet::new();
     let mut binding_gen_id = 0;
 
-    let name_pair = src.next().unwrap();
+    let name_pair = src.next_pair()?;
     let fixed_name = &name_pair.as_str();
     let mut rule_args: Vec<FixedRuleArg> = vec![];
     let mut options: BTreeMap<SmartString<LazyCompact>, Expr> = Default::default();
-    let args_list = src.next().unwrap();
+    let args_list = src.next_pair()?;
     let args_list_span = args_list.extract_span();
 
     for nxt in args_list.into_inner() {
         match nxt.as_rule() {
             Rule::fixed_rel => {
-                let inner = nxt.into_inner().next().unwrap();
+                let inner = nxt.into_inner().next_pair()?;
                 let span = inner.extract_span();
                 match inner.as_rule() {
                     Rule::fixed_rule_rel => {
                         let mut els = inner.into_inner();
-                        let name = els.next().unwrap();
+                        let name = els.next_pair()?;
                         let mut bindings = Vec::with_capacity(els.size_hint().1.unwrap_or(4));
                         for v in els {
                             let s = v.as_str();
@@ -903,9 +1168,7 @@ fn parse_fixed_rule(
                                 binding_gen_id += 1;
                                 bindings.push(symb);
                             } else {
-                                if !seen_bindings.insert(s) {
-                                    bail!(DuplicateBindingError(v.extract_span()))
-                                }
+                                bind(s, v.extract_span(), name.as_str())?;
                                 let symb = Symbol::new(s, v.extract_span());
                                 bindings.push(symb);
                             }
@@ -918,7 +1181,7 @@ fn parse_fixed_rule(
                     }
                     Rule::fixed_relation_rel => {
                         let mut els = inner.into_inner();
-                        let name = els.next().unwrap();
+                        let name = els.next_pair()?;
                         let mut bindings = vec![];
                         let mut valid_at = None;
                         for v in els {
@@ -933,23 +1196,21 @@ fn parse_fixed_rule(
                                         binding_gen_id += 1;
                                         bindings.push(symb);
                                     } else {
-                                        if !seen_bindings.insert(s) {
-                                            bail!(DuplicateBindingError(v.extract_span()))
-                                        }
+                                        bind(s, v.extract_span(), name.as_str())?;
                                         bindings.push(Symbol::new(v.as_str(), v.extract_span()))
                                     }
                                 }
                                 Rule::validity_clause => {
-                                    let vld_inner = v.into_inner().next().unwrap();
+                                    let vld_inner = v.into_inner().next_pair()?;
                                     let vld_expr = build_expr(vld_inner, param_pool)?;
                                     valid_at = Some(expr2vld_spec(vld_expr, cur_vld)?)
                                 }
-                                _ => unreachable!(),
+                                r => bail!(UnexpectedSyntax::rule(r)),
                             }
                         }
                         rule_args.push(FixedRuleArg::Stored {
                             name: Symbol::new(
-                                name.as_str().strip_prefix('*').unwrap(),
+                                unquote_ident(name.as_str().trim_start_matches('*')),
                                 name.extract_span(),
                             ),
                             bindings,
@@ -959,43 +1220,45 @@ fn parse_fixed_rule(
                     }
                     Rule::fixed_named_relation_rel => {
                         let mut els = inner.into_inner();
-                        let name = els.next().unwrap();
+                        let name = els.next_pair()?;
                         let mut bindings = BTreeMap::new();
                         let mut valid_at = None;
                         for p in els {
                             match p.as_rule() {
                                 Rule::fixed_named_relation_arg_pair => {
                                     let mut vs = p.into_inner();
-                                    let kp = vs.next().unwrap();
-                                    let k = SmartString::from(kp.as_str());
+                                    let kp = vs.next_pair()?;
+                                    let k = unquote_ident(kp.as_str());
                                     let v = match vs.next() {
                                         Some(vp) => {
-                                            if !seen_bindings.insert(vp.as_str()) {
-                                                bail!(DuplicateBindingError(vp.extract_span()))
-                                            }
+                                            bind(vp.as_str(), vp.extract_span(), name.as_str())?;
                                             Symbol::new(vp.as_str(), vp.extract_span())
                                         }
+                                        None if kp.as_str().starts_with('`') => {
+                                            bail!(UnboundQuotedColumn(
+                                                kp.as_str().to_string(),
+                                                kp.extract_span()
+                                            ))
+                                        }
                                         None => {
-                                            if !seen_bindings.insert(kp.as_str()) {
-                                                bail!(DuplicateBindingError(kp.extract_span()))
-                                            }
+                                            bind(kp.as_str(), kp.extract_span(), name.as_str())?;
                                             Symbol::new(k.clone(), kp.extract_span())
                                         }
                                     };
                                     bindings.insert(k, v);
                                 }
                                 Rule::validity_clause => {
-                                    let vld_inner = p.into_inner().next().unwrap();
+                                    let vld_inner = p.into_inner().next_pair()?;
                                     let vld_expr = build_expr(vld_inner, param_pool)?;
                                     valid_at = Some(expr2vld_spec(vld_expr, cur_vld)?)
                                 }
-                                _ => unreachable!(),
+                                r => bail!(UnexpectedSyntax::rule(r)),
                             }
                         }
 
                         rule_args.push(FixedRuleArg::NamedStored {
                             name: Symbol::new(
-                                name.as_str().strip_prefix(':').unwrap(),
+                                unquote_ident(name.as_str().trim_start_matches('*')),
                                 name.extract_span(),
                             ),
                             bindings,
@@ -1003,17 +1266,17 @@ fn parse_fixed_rule(
                             span,
                         })
                     }
-                    _ => unreachable!(),
+                    r => bail!(UnexpectedSyntax::rule(r)),
                 }
             }
             Rule::fixed_opt_pair => {
                 let mut inner = nxt.into_inner();
-                let name = inner.next().unwrap().as_str();
-                let val = inner.next().unwrap();
+                let name = inner.next_pair()?.as_str();
+                let val = inner.next_pair()?;
                 let val = build_expr(val, param_pool)?;
                 options.insert(SmartString::from(name), val);
             }
-            _ => unreachable!(),
+            r => bail!(UnexpectedSyntax::rule(r)),
         }
     }
 
@@ -1083,7 +1346,37 @@ fn make_empty_const_rule(prog: &mut InputProgram, bindings: &[Symbol]) {
     );
 }
 
-fn expr2vld_spec(expr: Expr, cur_vld: ValidityTs) -> Result<ValidityTs> {
+/// Parses the optional validity and `include_deleted` clauses trailing the
+/// arguments of a stored relation atom
+fn parse_scan_clauses(
+    src: Pairs<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+    cur_vld: ValidityTs,
+) -> Result<(Option<ValidityTs>, bool)> {
+    let mut valid_at = None;
+    let mut include_deleted = false;
+    for clause in src {
+        match clause.as_rule() {
+            Rule::validity_clause => {
+                let vld_expr = build_expr(clause.into_inner().next_pair()?, param_pool)?;
+                valid_at = Some(expr2vld_spec(vld_expr, cur_vld)?);
+            }
+            Rule::include_deleted_clause => {
+                let pair = clause.into_inner().next_pair()?;
+                let span = pair.extract_span();
+                include_deleted = build_expr(pair, param_pool)?
+                    .eval_to_const()
+                    .map_err(|err| OptionNotConstantError("include_deleted", span, [err]))?
+                    .get_bool()
+                    .ok_or(OptionNotBoolError("include_deleted", span))?;
+            }
+            r => bail!(UnexpectedSyntax::rule(r)),
+        }
+    }
+    Ok((valid_at, include_deleted))
+}
+
+pub(crate) fn expr2vld_spec(expr: Expr, cur_vld: ValidityTs) -> Result<ValidityTs> {
     let vld_span = expr.span();
     match expr.eval_to_const()? {
         DataValue::Num(n) => {
@@ -6,36 +6,48 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 use itertools::Itertools;
 use miette::{bail, ensure, Diagnostic, Result, IntoDiagnostic};
-use smartstring::SmartString;
+use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
 use crate::data::relation::{VecElementType, ColType, ColumnDef, NullableColType, StoredRelationMetadata};
-use crate::data::symb::Symbol;
+use crate::data::expr::Expr;
+use crate::data::symb::{NameKind, Symbol};
 use crate::data::value::DataValue;
 use crate::parse::expr::{build_expr};
-use crate::parse::{ExtractSpan, Pair, Rule, SourceSpan};
+use crate::parse::{
+    unquote_ident, ExtractSpan, NextPair, Pair, Rule, SourceSpan, UnexpectedSyntax,
+};
+
+pub(crate) type BindingExprs = BTreeMap<SmartString<LazyCompact>, Expr>;
 
 pub(crate) fn parse_schema(
     pair: Pair<'_>,
-) -> Result<(StoredRelationMetadata, Vec<Symbol>, Vec<Symbol>)> {
+    param_pool: &BTreeMap<String, DataValue>,
+) -> Result<(
+    StoredRelationMetadata,
+    Vec<Symbol>,
+    Vec<Symbol>,
+    BindingExprs,
+)> {
     let mut src = pair.into_inner();
     let mut keys = vec![];
     let mut dependents = vec![];
     let mut key_bindings = vec![];
     let mut dep_bindings = vec![];
+    let mut binding_exprs = BTreeMap::new();
     let mut seen_names = BTreeSet::new();
 
     #[derive(Debug, Error, Diagnostic)]
     #[error("Column {0} is defined multiple times")]
     #[diagnostic(code(parser::dup_name_in_cols))]
     struct DuplicateNameInCols(String, #[label] SourceSpan);
-    for p in src.next().unwrap().into_inner() {
+    for p in src.next_pair()?.into_inner() {
         let span = p.extract_span();
-        let (col, ident) = parse_col(p)?;
+        let (col, ident) = parse_col(p, param_pool, &mut binding_exprs)?;
         if !seen_names.insert(col.name.clone()) {
             bail!(DuplicateNameInCols(col.name.to_string(), span));
         }
@@ -45,7 +57,7 @@ pub(crate) fn parse_schema(
     if let Some(ps) = src.next() {
         for p in ps.into_inner() {
             let span = p.extract_span();
-            let (col, ident) = parse_col(p)?;
+            let (col, ident) = parse_col(p, param_pool, &mut binding_exprs)?;
             if !seen_names.insert(col.name.clone()) {
                 bail!(DuplicateNameInCols(col.name.to_string(), span));
             }
@@ -61,13 +73,22 @@ pub(crate) fn parse_schema(
         },
         key_bindings,
         dep_bindings,
+        binding_exprs,
     ))
 }
 
-fn parse_col(pair: Pair<'_>) -> Result<(ColumnDef, Symbol)> {
+pub(crate) fn parse_col(
+    pair: Pair<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+    binding_exprs: &mut BindingExprs,
+) -> Result<(ColumnDef, Symbol)> {
     let mut src = pair.into_inner();
-    let name_p = src.next().unwrap();
-    let name = SmartString::from(name_p.as_str());
+    let name_p = src.next_pair()?;
+    // quoted names are taken as they are, even if they are reserved words
+    if !name_p.as_str().starts_with('`') {
+        Symbol::new_checked(name_p.as_str(), name_p.extract_span(), NameKind::Column)?;
+    }
+    let name = unquote_ident(name_p.as_str());
     let mut typing = NullableColType {
         coltype: ColType::Any,
         nullable: true,
@@ -78,10 +99,19 @@ fn parse_col(pair: Pair<'_>) -> Result<(ColumnDef, Symbol)> {
         match nxt.as_rule() {
             Rule::col_type => typing = parse_nullable_type(nxt)?,
             Rule::expr => default_gen = Some(build_expr(nxt, &Default::default())?),
-            Rule::out_arg => {
-                binding_candidate = Some(Symbol::new(nxt.as_str(), nxt.extract_span()))
+            Rule::col_mapping => {
+                let span = nxt.extract_span();
+                match build_expr(nxt.into_inner().next_pair()?, param_pool)? {
+                    Expr::Binding { var, .. } => binding_candidate = Some(var),
+                    expr => {
+                        // the placeholder cannot clash with user bindings
+                        let placeholder = Symbol::new(format!("={name}"), span);
+                        binding_exprs.insert(placeholder.name.clone(), expr);
+                        binding_candidate = Some(placeholder);
+                    }
+                }
             }
-            r => unreachable!("{:?}", r),
+            r => bail!(UnexpectedSyntax::rule(r)),
         }
     }
     let binding =
@@ -98,7 +128,7 @@ fn parse_col(pair: Pair<'_>) -> Result<(ColumnDef, Symbol)> {
 
 pub(crate) fn parse_nullable_type(pair: Pair<'_>) -> Result<NullableColType> {
     let nullable = pair.as_str().ends_with('?');
-    let coltype = parse_type_inner(pair.into_inner().next().unwrap())?;
+    let coltype = parse_type_inner(pair.into_inner().next_pair()?)?;
     Ok(NullableColType { coltype, nullable })
 }
 
@@ -115,7 +145,7 @@ fn parse_type_inner(pair: Pair<'_>) -> Result<ColType> {
         Rule::validity_type => ColType::Validity,
         Rule::list_type => {
             let mut inner = pair.into_inner();
-            let eltype = parse_nullable_type(inner.next().unwrap())?;
+            let eltype = parse_nullable_type(inner.next_pair()?)?;
             let len = match inner.next() {
                 None => None,
                 Some(len_p) => {
@@ -140,12 +170,12 @@ fn parse_type_inner(pair: Pair<'_>) -> Result<ColType> {
         }
         Rule::vec_type => {
             let mut inner = pair.into_inner();
-            let eltype = match inner.next().unwrap().as_str() {
+            let eltype = match inner.next_pair()?.as_str() {
                 "F32" | "Float" => VecElementType::F32,
                 "F64" | "Double" => VecElementType::F64,
-                _ => unreachable!()
+                s => bail!(UnexpectedSyntax(s.to_string()))
             };
-            let len = inner.next().unwrap();
+            let len = inner.next_pair()?;
             let len = len.as_str().replace('_', "").parse::<usize>().into_diagnostic()?;
             ColType::Vec {
                 eltype,
@@ -155,6 +185,6 @@ fn parse_type_inner(pair: Pair<'_>) -> Result<ColType> {
         Rule::tuple_type => {
             ColType::Tuple(pair.into_inner().map(parse_nullable_type).try_collect()?)
         }
-        _ => unreachable!(),
+        r => bail!(UnexpectedSyntax::rule(r)),
     })
 }
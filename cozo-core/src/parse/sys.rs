@@ -16,19 +16,24 @@ use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
 use crate::data::program::InputProgram;
-use crate::data::relation::VecElementType;
+use crate::data::relation::{ColumnDef, VecElementType};
 use crate::data::symb::Symbol;
 use crate::data::value::{DataValue, ValidityTs};
 use crate::fts::TokenizerConfig;
 use crate::parse::expr::{build_expr, parse_string};
-use crate::parse::query::parse_query;
-use crate::parse::{ExtractSpan, Pairs, Rule, SourceSpan};
+use crate::parse::query::{expr2vld_spec, parse_query};
+use crate::parse::schema::parse_col;
+use crate::parse::{
+    unquote_ident, ExtractSpan, NextPair, Pairs, Rule, SourceSpan, UnexpectedSyntax,
+};
 use crate::runtime::relation::AccessLevel;
 use crate::{Expr, FixedRule};
 
 #[derive(Debug)]
 pub enum SysOp {
-    Compact,
+    /// Compacts the given relation with its indices, or everything,
+    /// also dropping the tombstones of removed rows if the flag is set.
+    Compact(Option<Symbol>, bool),
     ListColumns(Symbol),
     ListIndices(Symbol),
     ListRelations,
@@ -36,12 +41,30 @@ pub enum SysOp {
     ListFixedRules,
     KillRunning(u64),
     Explain(Box<InputProgram>),
-    RemoveRelation(Vec<Symbol>),
+    /// The flag is set for `cascade`, which removes the indices of the relations as well.
+    RemoveRelation(Vec<Symbol>, bool),
     RenameRelation(Vec<(Symbol, Symbol)>),
     ShowTrigger(Symbol),
     SetTriggers(Symbol, Vec<String>, Vec<String>, Vec<String>),
     SetAccessLevel(Vec<Symbol>, AccessLevel),
-    CreateIndex(Symbol, Symbol, Vec<Symbol>),
+    AddColumn(Symbol, ColumnDef),
+    DropColumn(Symbol, Symbol),
+    SoftDelete(Symbol),
+    PurgeDeleted(Symbol, f64),
+    SetTtl(Symbol, f64),
+    ExpireRows(Symbol),
+    /// Creates a materialized view holding the result of the query given.
+    CreateView(Symbol, String),
+    /// Recomputes the rows of a materialized view.
+    RefreshView(Symbol),
+    CompactHistory(Symbol, ValidityTs),
+    /// Scans a relation for rows that cannot be decoded.
+    CheckRelation(Symbol),
+    CreateNamespace(Symbol),
+    /// The flag is set for `cascade`, which removes the relations in the namespace as well.
+    DropNamespace(Symbol, bool),
+    ListNamespaces,
+    CreateIndex(Symbol, Symbol, Vec<Symbol>, Vec<Symbol>),
     CreateVectorIndex(HnswIndexConfig),
     CreateFtsIndex(FtsIndexConfig),
     CreateMinHashLshIndex(MinHashLshConfig),
@@ -108,12 +131,49 @@ pub(crate) fn parse_sys(
     algorithms: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
     cur_vld: ValidityTs,
 ) -> Result<SysOp> {
-    let inner = src.next().unwrap();
+    let inner = src.next_pair()?;
     Ok(match inner.as_rule() {
-        Rule::compact_op => SysOp::Compact,
+        Rule::compact_op => {
+            let mut rel = None;
+            let mut drop_tombstones = false;
+            for p in inner.into_inner() {
+                match p.as_rule() {
+                    Rule::compact_drop_tombstones => drop_tombstones = true,
+                    _ => rel = Some(Symbol::new(unquote_ident(p.as_str()), p.extract_span())),
+                }
+            }
+            SysOp::Compact(rel, drop_tombstones)
+        }
+        Rule::compact_history_op => {
+            let mut ps = inner.into_inner();
+            let rel_p = ps.next_pair()?;
+            let rel = Symbol::new(unquote_ident(rel_p.as_str()), rel_p.extract_span());
+            let before = expr2vld_spec(build_expr(ps.next_pair()?, param_pool)?, cur_vld)?;
+            SysOp::CompactHistory(rel, before)
+        }
+        Rule::list_namespaces_op => SysOp::ListNamespaces,
+        Rule::namespace_op => {
+            let op_p = inner.into_inner().next_pair()?;
+            let is_create = op_p.as_rule() == Rule::namespace_create;
+            let mut ps = op_p.into_inner();
+            let ns_p = ps.next_pair()?;
+            let ns = Symbol::new(unquote_ident(ns_p.as_str()), ns_p.extract_span());
+            if is_create {
+                SysOp::CreateNamespace(ns)
+            } else {
+                SysOp::DropNamespace(ns, ps.next().is_some())
+            }
+        }
+        Rule::check_op => {
+            let rel_p = inner.into_inner().next_pair()?;
+            SysOp::CheckRelation(Symbol::new(
+                unquote_ident(rel_p.as_str()),
+                rel_p.extract_span(),
+            ))
+        }
         Rule::running_op => SysOp::ListRunning,
         Rule::kill_op => {
-            let i_expr = inner.into_inner().next().unwrap();
+            let i_expr = inner.into_inner().next_pair()?;
             let i_val = build_expr(i_expr, param_pool)?;
             let i_val = i_val.eval_to_const()?;
             let i_val = i_val
@@ -123,7 +183,7 @@ pub(crate) fn parse_sys(
         }
         Rule::explain_op => {
             let prog = parse_query(
-                inner.into_inner().next().unwrap().into_inner(),
+                inner.into_inner().next_pair()?.into_inner(),
                 param_pool,
                 algorithms,
                 cur_vld,
@@ -132,8 +192,8 @@ pub(crate) fn parse_sys(
         }
         Rule::describe_relation_op => {
             let mut inner = inner.into_inner();
-            let rels_p = inner.next().unwrap();
-            let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+            let rels_p = inner.next_pair()?;
+            let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
             let description = match inner.next() {
                 None => Default::default(),
                 Some(desc_p) => parse_string(desc_p)?,
@@ -142,69 +202,154 @@ pub(crate) fn parse_sys(
         }
         Rule::list_relations_op => SysOp::ListRelations,
         Rule::remove_relations_op => {
-            let rel = inner
-                .into_inner()
-                .map(|rels_p| Symbol::new(rels_p.as_str(), rels_p.extract_span()))
-                .collect_vec();
+            let mut cascade = false;
+            let mut rel = vec![];
+            for rels_p in inner.into_inner() {
+                if rels_p.as_rule() == Rule::cascade_kw {
+                    cascade = true;
+                } else {
+                    rel.push(Symbol::new(
+                        unquote_ident(rels_p.as_str()),
+                        rels_p.extract_span(),
+                    ));
+                }
+            }
 
-            SysOp::RemoveRelation(rel)
+            SysOp::RemoveRelation(rel, cascade)
         }
         Rule::list_columns_op => {
-            let rels_p = inner.into_inner().next().unwrap();
-            let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+            let rels_p = inner.into_inner().next_pair()?;
+            let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
             SysOp::ListColumns(rel)
         }
         Rule::list_indices_op => {
-            let rels_p = inner.into_inner().next().unwrap();
-            let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+            let rels_p = inner.into_inner().next_pair()?;
+            let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
             SysOp::ListIndices(rel)
         }
         Rule::rename_relations_op => {
             let rename_pairs = inner
                 .into_inner()
-                .map(|pair| {
+                .map(|pair| -> Result<_> {
                     let mut src = pair.into_inner();
-                    let rels_p = src.next().unwrap();
-                    let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
-                    let rels_p = src.next().unwrap();
-                    let new_rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
-                    (rel, new_rel)
+                    let rels_p = src.next_pair()?;
+                    let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
+                    let rels_p = src.next_pair()?;
+                    let new_rel =
+                        Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
+                    Ok((rel, new_rel))
                 })
-                .collect_vec();
+                .try_collect()?;
             SysOp::RenameRelation(rename_pairs)
         }
         Rule::access_level_op => {
             let mut ps = inner.into_inner();
-            let access_level = match ps.next().unwrap().as_str() {
+            let access_level = match ps.next_pair()?.as_str() {
                 "normal" => AccessLevel::Normal,
                 "protected" => AccessLevel::Protected,
                 "read_only" => AccessLevel::ReadOnly,
                 "hidden" => AccessLevel::Hidden,
-                _ => unreachable!(),
+                s => bail!(UnexpectedSyntax(s.to_string())),
             };
             let mut rels = vec![];
             for rel_p in ps {
-                let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+                let rel = Symbol::new(unquote_ident(rel_p.as_str()), rel_p.extract_span());
                 rels.push(rel)
             }
             SysOp::SetAccessLevel(rels, access_level)
         }
+        Rule::alter_relation_op => {
+            let mut ps = inner.into_inner();
+            let rel_p = ps.next_pair()?;
+            let rel = Symbol::new(unquote_ident(rel_p.as_str()), rel_p.extract_span());
+            let alter_p = ps.next_pair()?;
+            match alter_p.as_rule() {
+                Rule::alter_add_col => {
+                    let col_p = alter_p.into_inner().next_pair()?;
+                    let (col, _) = parse_col(col_p, param_pool, &mut Default::default())?;
+                    SysOp::AddColumn(rel, col)
+                }
+                Rule::alter_drop_col => {
+                    let col_p = alter_p.into_inner().next_pair()?;
+                    SysOp::DropColumn(
+                        rel,
+                        Symbol::new(unquote_ident(col_p.as_str()), col_p.extract_span()),
+                    )
+                }
+                r => bail!(UnexpectedSyntax::rule(r)),
+            }
+        }
+        Rule::soft_delete_op => {
+            let rel_p = inner.into_inner().next_pair()?;
+            let rel = Symbol::new(unquote_ident(rel_p.as_str()), rel_p.extract_span());
+            SysOp::SoftDelete(rel)
+        }
+        Rule::purge_deleted_op => {
+            let mut ps = inner.into_inner();
+            let rel_p = ps.next_pair()?;
+            let rel = Symbol::new(unquote_ident(rel_p.as_str()), rel_p.extract_span());
+            let before = build_expr(ps.next_pair()?, param_pool)?.eval_to_const()?;
+            let before = before
+                .get_float()
+                .ok_or_else(|| miette!("Purge time must be a number of seconds since the epoch"))?;
+            SysOp::PurgeDeleted(rel, before)
+        }
+        Rule::ttl_op => {
+            let mut ps = inner.into_inner();
+            let rel_p = ps.next_pair()?;
+            let rel = Symbol::new(unquote_ident(rel_p.as_str()), rel_p.extract_span());
+            let ttl = build_expr(ps.next_pair()?, param_pool)?.eval_to_const()?;
+            let ttl = ttl
+                .get_float()
+                .ok_or_else(|| miette!("The TTL must be a number of seconds"))?;
+            SysOp::SetTtl(rel, ttl)
+        }
+        Rule::expire_op => {
+            let rel_p = inner.into_inner().next_pair()?;
+            let rel = Symbol::new(unquote_ident(rel_p.as_str()), rel_p.extract_span());
+            SysOp::ExpireRows(rel)
+        }
+        Rule::view_op => {
+            let inner = inner.into_inner().next_pair()?;
+            match inner.as_rule() {
+                Rule::view_create => {
+                    let mut src = inner.into_inner();
+                    let rel_p = src.next_pair()?;
+                    let rel = Symbol::new(unquote_ident(rel_p.as_str()), rel_p.extract_span());
+                    let script = src.next_pair()?;
+                    let script_str = script.as_str();
+                    parse_query(
+                        script.into_inner(),
+                        &Default::default(),
+                        algorithms,
+                        cur_vld,
+                    )?;
+                    SysOp::CreateView(rel, script_str.to_string())
+                }
+                Rule::view_refresh => {
+                    let rel_p = inner.into_inner().next_pair()?;
+                    let rel = Symbol::new(unquote_ident(rel_p.as_str()), rel_p.extract_span());
+                    SysOp::RefreshView(rel)
+                }
+                r => bail!(UnexpectedSyntax::rule(r)),
+            }
+        }
         Rule::trigger_relation_show_op => {
-            let rels_p = inner.into_inner().next().unwrap();
-            let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+            let rels_p = inner.into_inner().next_pair()?;
+            let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
             SysOp::ShowTrigger(rel)
         }
         Rule::trigger_relation_op => {
             let mut src = inner.into_inner();
-            let rels_p = src.next().unwrap();
-            let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+            let rels_p = src.next_pair()?;
+            let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
             let mut puts = vec![];
             let mut rms = vec![];
             let mut replaces = vec![];
             for clause in src {
                 let mut clause_inner = clause.into_inner();
-                let op = clause_inner.next().unwrap();
-                let script = clause_inner.next().unwrap();
+                let op = clause_inner.next_pair()?;
+                let script = clause_inner.next_pair()?;
                 let script_str = script.as_str();
                 parse_query(
                     script.into_inner(),
@@ -216,18 +361,18 @@ pub(crate) fn parse_sys(
                     Rule::trigger_put => puts.push(script_str.to_string()),
                     Rule::trigger_rm => rms.push(script_str.to_string()),
                     Rule::trigger_replace => replaces.push(script_str.to_string()),
-                    r => unreachable!("{:?}", r),
+                    r => bail!(UnexpectedSyntax::rule(r)),
                 }
             }
             SysOp::SetTriggers(rel, puts, rms, replaces)
         }
         Rule::lsh_idx_op => {
-            let inner = inner.into_inner().next().unwrap();
+            let inner = inner.into_inner().next_pair()?;
             match inner.as_rule() {
                 Rule::index_create_adv => {
                     let mut inner = inner.into_inner();
-                    let rel = inner.next().unwrap();
-                    let name = inner.next().unwrap();
+                    let rel = inner.next_pair()?;
+                    let name = inner.next_pair()?;
                     let mut filters = vec![];
                     let mut tokenizer = TokenizerConfig {
                         name: Default::default(),
@@ -242,8 +387,8 @@ pub(crate) fn parse_sys(
                     let mut false_negative_weight = 1.0;
                     for opt_pair in inner {
                         let mut opt_inner = opt_pair.into_inner();
-                        let opt_name = opt_inner.next().unwrap();
-                        let opt_val = opt_inner.next().unwrap();
+                        let opt_name = opt_inner.next_pair()?;
+                        let opt_val = opt_inner.next_pair()?;
                         match opt_name.as_str() {
                             "false_positive_weight" => {
                                 let mut expr = build_expr(opt_val, param_pool)?;
@@ -377,8 +522,8 @@ pub(crate) fn parse_sys(
                     }
 
                     let config = MinHashLshConfig {
-                        base_relation: SmartString::from(rel.as_str()),
-                        index_name: SmartString::from(name.as_str()),
+                        base_relation: unquote_ident(rel.as_str()),
+                        index_name: unquote_ident(name.as_str()),
                         extractor,
                         tokenizer,
                         filters,
@@ -392,23 +537,23 @@ pub(crate) fn parse_sys(
                 }
                 Rule::index_drop => {
                     let mut inner = inner.into_inner();
-                    let rel = inner.next().unwrap();
-                    let name = inner.next().unwrap();
+                    let rel = inner.next_pair()?;
+                    let name = inner.next_pair()?;
                     SysOp::RemoveIndex(
-                        Symbol::new(rel.as_str(), rel.extract_span()),
-                        Symbol::new(name.as_str(), name.extract_span()),
+                        Symbol::new(unquote_ident(rel.as_str()), rel.extract_span()),
+                        Symbol::new(unquote_ident(name.as_str()), name.extract_span()),
                     )
                 }
-                r => unreachable!("{:?}", r),
+                r => bail!(UnexpectedSyntax::rule(r)),
             }
         }
         Rule::fts_idx_op => {
-            let inner = inner.into_inner().next().unwrap();
+            let inner = inner.into_inner().next_pair()?;
             match inner.as_rule() {
                 Rule::index_create_adv => {
                     let mut inner = inner.into_inner();
-                    let rel = inner.next().unwrap();
-                    let name = inner.next().unwrap();
+                    let rel = inner.next_pair()?;
+                    let name = inner.next_pair()?;
                     let mut filters = vec![];
                     let mut tokenizer = TokenizerConfig {
                         name: Default::default(),
@@ -418,8 +563,8 @@ pub(crate) fn parse_sys(
                     let mut extract_filter = "".to_string();
                     for opt_pair in inner {
                         let mut opt_inner = opt_pair.into_inner();
-                        let opt_name = opt_inner.next().unwrap();
-                        let opt_val = opt_inner.next().unwrap();
+                        let opt_name = opt_inner.next_pair()?;
+                        let opt_val = opt_inner.next_pair()?;
                         match opt_name.as_str() {
                             "extractor" => {
                                 let mut ex = build_expr(opt_val, param_pool)?;
@@ -492,8 +637,8 @@ pub(crate) fn parse_sys(
                         extractor = format!("if({}, {})", extract_filter, extractor);
                     }
                     let config = FtsIndexConfig {
-                        base_relation: SmartString::from(rel.as_str()),
-                        index_name: SmartString::from(name.as_str()),
+                        base_relation: unquote_ident(rel.as_str()),
+                        index_name: unquote_ident(name.as_str()),
                         extractor,
                         tokenizer,
                         filters,
@@ -502,23 +647,23 @@ pub(crate) fn parse_sys(
                 }
                 Rule::index_drop => {
                     let mut inner = inner.into_inner();
-                    let rel = inner.next().unwrap();
-                    let name = inner.next().unwrap();
+                    let rel = inner.next_pair()?;
+                    let name = inner.next_pair()?;
                     SysOp::RemoveIndex(
-                        Symbol::new(rel.as_str(), rel.extract_span()),
-                        Symbol::new(name.as_str(), name.extract_span()),
+                        Symbol::new(unquote_ident(rel.as_str()), rel.extract_span()),
+                        Symbol::new(unquote_ident(name.as_str()), name.extract_span()),
                     )
                 }
-                r => unreachable!("{:?}", r),
+                r => bail!(UnexpectedSyntax::rule(r)),
             }
         }
         Rule::vec_idx_op => {
-            let inner = inner.into_inner().next().unwrap();
+            let inner = inner.into_inner().next_pair()?;
             match inner.as_rule() {
                 Rule::index_create_adv => {
                     let mut inner = inner.into_inner();
-                    let rel = inner.next().unwrap();
-                    let name = inner.next().unwrap();
+                    let rel = inner.next_pair()?;
+                    let name = inner.next_pair()?;
                     // options
                     let mut vec_dim = 0;
                     let mut dtype = VecElementType::F32;
@@ -532,8 +677,8 @@ pub(crate) fn parse_sys(
 
                     for opt_pair in inner {
                         let mut opt_inner = opt_pair.into_inner();
-                        let opt_name = opt_inner.next().unwrap();
-                        let opt_val = opt_inner.next().unwrap();
+                        let opt_name = opt_inner.next_pair()?;
+                        let opt_val = opt_inner.next_pair()?;
                         let opt_val_str = opt_val.as_str();
                         match opt_name.as_str() {
                             "dim" => {
@@ -609,8 +754,8 @@ pub(crate) fn parse_sys(
                         bail!("m_neighbours must be set");
                     }
                     SysOp::CreateVectorIndex(HnswIndexConfig {
-                        base_relation: SmartString::from(rel.as_str()),
-                        index_name: SmartString::from(name.as_str()),
+                        base_relation: unquote_ident(rel.as_str()),
+                        index_name: unquote_ident(name.as_str()),
                         vec_dim,
                         dtype,
                         vec_fields,
@@ -624,27 +769,37 @@ pub(crate) fn parse_sys(
                 }
                 Rule::index_drop => {
                     let mut inner = inner.into_inner();
-                    let rel = inner.next().unwrap();
-                    let name = inner.next().unwrap();
+                    let rel = inner.next_pair()?;
+                    let name = inner.next_pair()?;
                     SysOp::RemoveIndex(
-                        Symbol::new(rel.as_str(), rel.extract_span()),
-                        Symbol::new(name.as_str(), name.extract_span()),
+                        Symbol::new(unquote_ident(rel.as_str()), rel.extract_span()),
+                        Symbol::new(unquote_ident(name.as_str()), name.extract_span()),
                     )
                 }
-                r => unreachable!("{:?}", r),
+                r => bail!(UnexpectedSyntax::rule(r)),
             }
         }
         Rule::index_op => {
-            let inner = inner.into_inner().next().unwrap();
+            let inner = inner.into_inner().next_pair()?;
             match inner.as_rule() {
                 Rule::index_create => {
                     let span = inner.extract_span();
                     let mut inner = inner.into_inner();
-                    let rel = inner.next().unwrap();
-                    let name = inner.next().unwrap();
-                    let cols = inner
-                        .map(|p| Symbol::new(p.as_str(), p.extract_span()))
-                        .collect_vec();
+                    let rel = inner.next_pair()?;
+                    let name = inner.next_pair()?;
+                    let mut cols = vec![];
+                    let mut payload = vec![];
+                    for p in inner {
+                        if p.as_rule() == Rule::index_payload {
+                            payload.extend(
+                                p.into_inner().map(|p| {
+                                    Symbol::new(unquote_ident(p.as_str()), p.extract_span())
+                                }),
+                            );
+                        } else {
+                            cols.push(Symbol::new(unquote_ident(p.as_str()), p.extract_span()));
+                        }
+                    }
 
                     #[derive(Debug, Diagnostic, Error)]
                     #[error("index must have at least one column specified")]
@@ -653,24 +808,25 @@ pub(crate) fn parse_sys(
 
                     ensure!(!cols.is_empty(), EmptyIndex(span));
                     SysOp::CreateIndex(
-                        Symbol::new(rel.as_str(), rel.extract_span()),
-                        Symbol::new(name.as_str(), name.extract_span()),
+                        Symbol::new(unquote_ident(rel.as_str()), rel.extract_span()),
+                        Symbol::new(unquote_ident(name.as_str()), name.extract_span()),
                         cols,
+                        payload,
                     )
                 }
                 Rule::index_drop => {
                     let mut inner = inner.into_inner();
-                    let rel = inner.next().unwrap();
-                    let name = inner.next().unwrap();
+                    let rel = inner.next_pair()?;
+                    let name = inner.next_pair()?;
                     SysOp::RemoveIndex(
-                        Symbol::new(rel.as_str(), rel.extract_span()),
-                        Symbol::new(name.as_str(), name.extract_span()),
+                        Symbol::new(unquote_ident(rel.as_str()), rel.extract_span()),
+                        Symbol::new(unquote_ident(name.as_str()), name.extract_span()),
                     )
                 }
-                _ => unreachable!(),
+                r => bail!(UnexpectedSyntax::rule(r)),
             }
         }
         Rule::list_fixed_rules => SysOp::ListFixedRules,
-        r => unreachable!("{:?}", r),
+        r => bail!(UnexpectedSyntax::rule(r)),
     })
 }
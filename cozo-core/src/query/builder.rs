@@ -0,0 +1,331 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Building single queries from Rust instead of writing CozoScript.
+//!
+//! ```
+//! use cozo::{DbInstance, QueryBuilder, Term};
+//!
+//! let db = DbInstance::default();
+//! db.run_default("?[id, name] <- [[1, 'Ann'], [2, 'Bob']] :create employee {id => name}")
+//!     .unwrap();
+//! let query = QueryBuilder::new()
+//!     .relation("employee", [("id", Term::var("id")), ("name", Term::var("name"))])
+//!     .filter(Term::var("id").ge(Term::val(2)))
+//!     .bind("name_len", Term::call("length", [Term::var("name")]))
+//!     .select(["name", "name_len"]);
+//! let res = db.run_built_query(query).unwrap();
+//! assert_eq!(res.rows, vec![vec!["Bob".into(), 3.into()]]);
+//! ```
+//!
+//! The queries are the same [InputProgram]s the parser produces for the equivalent scripts,
+//! and names of relations, columns and functions are checked the same way when they run.
+
+use std::collections::BTreeMap;
+use std::ops::{Add, Div, Mul, Not, Sub};
+
+use miette::{bail, Result};
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::aggr::parse_aggr;
+use crate::data::expr::Expr;
+use crate::data::program::{
+    InputAtom, InputInlineRule, InputInlineRulesOrFixed, InputNamedFieldRelationApplyAtom,
+    InputProgram, QueryOutOptions, SortDir, Unification,
+};
+use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::data::value::DataValue;
+use crate::parse::expr::build_apply;
+
+/// An expression in a query built with [QueryBuilder].
+///
+/// Arithmetic is written with the usual operators, e.g. `Term::var("a") + Term::val(1)`.
+#[derive(Debug, Clone)]
+pub struct Term(Expr);
+
+impl Term {
+    /// The variable `name`.
+    pub fn var(name: &str) -> Self {
+        Term(Expr::Binding {
+            var: Symbol::new(name, Default::default()),
+            tuple_pos: None,
+        })
+    }
+    /// A constant.
+    pub fn val(val: impl Into<DataValue>) -> Self {
+        Term(Expr::Const {
+            val: val.into(),
+            span: Default::default(),
+        })
+    }
+    /// The function `name` applied to `args`, as `name(args..)` in scripts.
+    /// Wrong numbers of arguments are reported by [QueryBuilder::build],
+    /// unknown functions when the query runs.
+    pub fn call(name: &str, args: impl IntoIterator<Item = Term>) -> Self {
+        Term(Expr::UnboundApply {
+            op: name.into(),
+            args: args.into_iter().map(|t| t.0).collect(),
+            span: Default::default(),
+        })
+    }
+    fn binary(self, op: &str, other: Term) -> Self {
+        Term::call(op, [self, other])
+    }
+    /// `self == other`
+    pub fn eq(self, other: Term) -> Self {
+        self.binary("eq", other)
+    }
+    /// `self != other`
+    pub fn neq(self, other: Term) -> Self {
+        self.binary("neq", other)
+    }
+    /// `self > other`
+    pub fn gt(self, other: Term) -> Self {
+        self.binary("gt", other)
+    }
+    /// `self >= other`
+    pub fn ge(self, other: Term) -> Self {
+        self.binary("ge", other)
+    }
+    /// `self < other`
+    pub fn lt(self, other: Term) -> Self {
+        self.binary("lt", other)
+    }
+    /// `self <= other`
+    pub fn le(self, other: Term) -> Self {
+        self.binary("le", other)
+    }
+    /// `self && other`
+    pub fn and(self, other: Term) -> Self {
+        self.binary("and", other)
+    }
+    /// `self || other`
+    pub fn or(self, other: Term) -> Self {
+        self.binary("or", other)
+    }
+}
+
+impl Not for Term {
+    type Output = Term;
+    fn not(self) -> Term {
+        Term::call("negate", [self])
+    }
+}
+
+impl Add for Term {
+    type Output = Term;
+    fn add(self, other: Term) -> Term {
+        self.binary("add", other)
+    }
+}
+
+impl Sub for Term {
+    type Output = Term;
+    fn sub(self, other: Term) -> Term {
+        self.binary("sub", other)
+    }
+}
+
+impl Mul for Term {
+    type Output = Term;
+    fn mul(self, other: Term) -> Term {
+        self.binary("mul", other)
+    }
+}
+
+impl Div for Term {
+    type Output = Term;
+    fn div(self, other: Term) -> Term {
+        self.binary("div", other)
+    }
+}
+
+/// Resolve the functions applied in `expr` as the parser does
+fn resolve_calls(expr: Expr) -> Result<Expr> {
+    Ok(match expr {
+        Expr::UnboundApply { op, args, span } => {
+            let args = args
+                .into_vec()
+                .into_iter()
+                .map(resolve_calls)
+                .collect::<Result<Vec<_>>>()?;
+            build_apply(&op, args, span)?
+        }
+        e => e,
+    })
+}
+
+/// A single query, the equivalent of a script with only the entry rule `?[..] := ..`.
+///
+/// Atoms are added to the body of the rule in the order the methods are called.
+#[derive(Debug, Clone, Default)]
+pub struct QueryBuilder {
+    head: Vec<(String, Option<String>)>,
+    body: Vec<BodyItem>,
+    /// Columns selected with [Self::select_terms], bound after the rest of the body
+    computed: Vec<(String, Term)>,
+    sorters: Vec<(String, SortDir)>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+enum BodyItem {
+    Relation(String, Vec<(String, Term)>),
+    Filter(Term),
+    Bind(String, Term),
+}
+
+impl QueryBuilder {
+    /// An empty query.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Read the stored relation `name`, unifying its columns with the terms,
+    /// as `*name{col: term, ..}` does.
+    pub fn relation<'a>(
+        mut self,
+        name: &str,
+        columns: impl IntoIterator<Item = (&'a str, Term)>,
+    ) -> Self {
+        self.body.push(BodyItem::Relation(
+            name.to_string(),
+            columns
+                .into_iter()
+                .map(|(col, term)| (col.to_string(), term))
+                .collect(),
+        ));
+        self
+    }
+    /// Keep only rows for which `cond` is true.
+    pub fn filter(mut self, cond: Term) -> Self {
+        self.body.push(BodyItem::Filter(cond));
+        self
+    }
+    /// Bind the variable `var` to `term`, as `var = term` does.
+    pub fn bind(mut self, var: &str, term: Term) -> Self {
+        self.body.push(BodyItem::Bind(var.to_string(), term));
+        self
+    }
+    /// Return the variables `vars`, after those already selected.
+    pub fn select<'a>(mut self, vars: impl IntoIterator<Item = &'a str>) -> Self {
+        self.head
+            .extend(vars.into_iter().map(|v| (v.to_string(), None)));
+        self
+    }
+    /// Return the values of `terms`, after the columns already selected.
+    ///
+    /// The columns are positional: each is named after its position in the output,
+    /// `_0`, `_1` and so on, so these names must not be used for variables of the query.
+    pub fn select_terms(mut self, terms: impl IntoIterator<Item = Term>) -> Self {
+        for term in terms {
+            let name = format!("_{}", self.head.len());
+            self.head.push((name.clone(), None));
+            self.computed.push((name, term));
+        }
+        self
+    }
+    /// Return the aggregation `aggr` of the variable `var`, as `aggr(var)` in the head does.
+    pub fn aggregate(mut self, aggr: &str, var: &str) -> Self {
+        self.head.push((var.to_string(), Some(aggr.to_string())));
+        self
+    }
+    /// Sort the output by `var`, after the sort keys already given, as `:order` does.
+    pub fn order_by(mut self, var: &str, dir: SortDir) -> Self {
+        self.sorters.push((var.to_string(), dir));
+        self
+    }
+    /// Return at most `n` rows, as `:limit` does.
+    pub fn limit(mut self, n: usize) -> Self {
+        self.limit = Some(n);
+        self
+    }
+    /// Skip the first `n` rows, as `:offset` does.
+    pub fn offset(mut self, n: usize) -> Self {
+        self.offset = Some(n);
+        self
+    }
+
+    /// The program for this query, as the parser would produce it.
+    pub fn build(self) -> Result<InputProgram> {
+        if self.head.is_empty() {
+            bail!("query has nothing selected");
+        }
+        let mut head = Vec::with_capacity(self.head.len());
+        let mut aggr = Vec::with_capacity(self.head.len());
+        for (var, aggr_name) in self.head {
+            head.push(Symbol::new(var, Default::default()));
+            aggr.push(match aggr_name {
+                None => None,
+                Some(name) => match parse_aggr(&name) {
+                    Some(a) => Some((a.clone(), vec![])),
+                    None => bail!("aggregation '{}' not found", name),
+                },
+            });
+        }
+        let mut body = Vec::with_capacity(self.body.len() + self.computed.len());
+        let computed = self
+            .computed
+            .into_iter()
+            .map(|(var, term)| BodyItem::Bind(var, term));
+        for item in self.body.into_iter().chain(computed) {
+            body.push(match item {
+                BodyItem::Relation(name, columns) => {
+                    let mut args: BTreeMap<SmartString<LazyCompact>, Expr> = BTreeMap::new();
+                    for (col, term) in columns {
+                        args.insert(col.into(), resolve_calls(term.0)?);
+                    }
+                    InputAtom::NamedFieldRelation {
+                        inner: InputNamedFieldRelationApplyAtom {
+                            name: Symbol::new(name, Default::default()),
+                            args,
+                            valid_at: None,
+                            include_deleted: false,
+                            span: Default::default(),
+                        },
+                    }
+                }
+                BodyItem::Filter(cond) => InputAtom::Predicate {
+                    inner: resolve_calls(cond.0)?,
+                },
+                BodyItem::Bind(var, term) => InputAtom::Unification {
+                    inner: Unification {
+                        binding: Symbol::new(var, Default::default()),
+                        expr: resolve_calls(term.0)?,
+                        one_many_unif: false,
+                        span: Default::default(),
+                    },
+                },
+            });
+        }
+        let rule = InputInlineRule {
+            head,
+            aggr,
+            body,
+            span: Default::default(),
+        };
+        let out_opts = QueryOutOptions {
+            limit: self.limit,
+            offset: self.offset,
+            sorters: self
+                .sorters
+                .into_iter()
+                .map(|(var, dir)| (Symbol::new(var, Default::default()), dir))
+                .collect(),
+            ..Default::default()
+        };
+        Ok(InputProgram {
+            prog: BTreeMap::from([(
+                Symbol::new(PROG_ENTRY, Default::default()),
+                InputInlineRulesOrFixed::Rules { rules: vec![rule] },
+            )]),
+            out_opts,
+            disable_magic_rewrite: false,
+        })
+    }
+}
@@ -13,16 +13,16 @@ use miette::{bail, ensure, Context, Diagnostic, Result};
 use thiserror::Error;
 
 use crate::data::aggr::Aggregation;
-use crate::data::expr::Expr;
+use crate::data::expr::{Expr, ValueRange};
 use crate::data::program::{
-    MagicAtom, MagicFixedRuleApply, MagicInlineRule, MagicRulesOrFixed, MagicSymbol,
-    StratifiedMagicProgram,
+    MagicAtom, MagicFixedRuleApply, MagicInlineRule, MagicRelationApplyAtom, MagicRulesOrFixed,
+    MagicSymbol, StratifiedMagicProgram,
 };
 use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
 use crate::parse::SourceSpan;
 use crate::query::ra::RelAlgebra;
-use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel};
+use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel, RelationHandle};
 use crate::runtime::transact::SessionTx;
 
 pub(crate) type CompiledProgram = BTreeMap<MagicSymbol, CompiledRuleSet>;
@@ -77,7 +77,7 @@ impl CompiledRuleSet {
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
 pub(crate) enum ContainedRuleMultiplicity {
     One,
     Many,
@@ -90,6 +90,13 @@ pub(crate) struct CompiledRule {
     pub(crate) contained_rules: BTreeMap<MagicSymbol, ContainedRuleMultiplicity>,
 }
 
+impl CompiledRule {
+    /// Identifies the aggregation of the rule in the metrics collected for a query.
+    pub(crate) fn aggr_metrics_key(&self) -> usize {
+        &self.aggr as *const _ as usize
+    }
+}
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("Requested rule {0} not found")]
 #[diagnostic(code(eval::rule_not_found))]
@@ -104,6 +111,8 @@ struct ArityMismatch(String, usize, usize, #[label] SourceSpan);
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub(crate) enum IndexPositionUse {
     Join,
+    /// Bound here, but a later filter compares it with a constant, so it can be seeked in an index
+    Bounded,
     BindForLater,
     Ignored,
 }
@@ -176,7 +185,23 @@ impl<'a> SessionTx<'a> {
             serial_id += 1;
             ret
         };
-        for atom in &rule.body {
+        // filters in the body that bound the variable by constants
+        let seek_filters = |var: &Symbol| -> Vec<Expr> {
+            rule.body
+                .iter()
+                .filter_map(|atom| match atom {
+                    MagicAtom::Predicate(p)
+                        if p.bindings().is_ok_and(|b| b.len() == 1)
+                            && p.extract_bound(var)
+                                .is_ok_and(|b| b != ValueRange::default()) =>
+                    {
+                        Some(p.clone())
+                    }
+                    _ => None,
+                })
+                .collect_vec()
+        };
+        for (atom_idx, atom) in rule.body.iter().enumerate() {
             match atom {
                 MagicAtom::Rule(rule_app) => {
                     let store_arity = store_arities.get(&rule_app.name).ok_or_else(|| {
@@ -217,7 +242,7 @@ impl<'a> SessionTx<'a> {
                     ret = ret.join(right, prev_joiner_vars, right_joiner_vars, rule_app.span);
                 }
                 MagicAtom::Relation(rel_app) => {
-                    let store = self.get_relation(&rel_app.name, false)?;
+                    let mut store = self.get_relation(&rel_app.name, false)?;
                     if store.access_level < AccessLevel::ReadOnly {
                         bail!(InsufficientAccessLevel(
                             store.name.to_string(),
@@ -234,6 +259,12 @@ impl<'a> SessionTx<'a> {
                             rel_app.span
                         )
                     );
+                    let through_relation = self.read_index_through_expiring_relation(
+                        &mut store,
+                        rel_app,
+                        &mut gen_symb,
+                    )?;
+                    let rel_app = through_relation.as_ref().unwrap_or(rel_app);
                     // already existing vars
                     let mut prev_joiner_vars = vec![];
                     // vars introduced by right and joined
@@ -246,6 +277,11 @@ impl<'a> SessionTx<'a> {
                     let mut right_vars = vec![];
                     // used for choosing indices
                     let mut join_indices = vec![];
+                    // columns not needed downstream need not come from the relation
+                    let mut used_later: BTreeSet<Symbol> = ret_vars.iter().cloned().collect();
+                    for later in &rule.body[atom_idx + 1..] {
+                        later.collect_mentioned(&mut used_later)?;
+                    }
 
                     for (i, var) in rel_app.args.iter().enumerate() {
                         if seen_variables.contains(var) {
@@ -259,16 +295,23 @@ impl<'a> SessionTx<'a> {
                         } else {
                             seen_variables.insert(var.clone());
                             right_vars.push(var.clone());
-                            if var.is_generated_ignored_symbol() {
+                            if var.is_generated_ignored_symbol() || !used_later.contains(var) {
                                 join_indices.push(IndexPositionUse::Ignored)
+                            } else if !seek_filters(var).is_empty() {
+                                join_indices.push(IndexPositionUse::Bounded)
                             } else {
                                 join_indices.push(IndexPositionUse::BindForLater)
                             }
                         }
                     }
 
-                    let chosen_index =
-                        store.choose_index(&join_indices, rel_app.valid_at.is_some());
+                    // soft-deleted rows are never indexed, so they can only come from the relation
+                    let chosen_index = if rel_app.include_deleted && store.soft_delete {
+                        store.soft_delete = false;
+                        None
+                    } else {
+                        store.choose_index(&join_indices, rel_app.valid_at.is_some())
+                    };
 
                     match chosen_index {
                         None => {
@@ -321,12 +364,22 @@ impl<'a> SessionTx<'a> {
                                     }
                                     index_vars.push(tv);
                                 }
-                                let index = RelAlgebra::relation(
+                                let mut index = RelAlgebra::relation(
                                     index_vars.clone(),
                                     chosen_index,
                                     rel_app.span,
                                     rel_app.valid_at,
                                 )?;
+                                // Seek the index with the filters on its columns
+                                for (&orig_idx, tv) in mapper.iter().zip(index_vars.iter()) {
+                                    if join_indices[orig_idx] != IndexPositionUse::Bounded {
+                                        continue;
+                                    }
+                                    for mut filter in seek_filters(&right_vars[orig_idx]) {
+                                        filter.rename_binding(&right_vars[orig_idx], tv);
+                                        index = index.filter(filter)?;
+                                    }
+                                }
                                 ret = ret.join(
                                     index,
                                     left_keys,
@@ -412,7 +465,7 @@ impl<'a> SessionTx<'a> {
                     ret = ret.neg_join(right, prev_joiner_vars, right_joiner_vars, rule_app.span);
                 }
                 MagicAtom::NegatedRelation(rel_app) => {
-                    let store = self.get_relation(&rel_app.name, false)?;
+                    let mut store = self.get_relation(&rel_app.name, false)?;
                     ensure!(
                         store.arity() == rel_app.args.len(),
                         ArityMismatch(
@@ -422,6 +475,12 @@ impl<'a> SessionTx<'a> {
                             rel_app.span
                         )
                     );
+                    let through_relation = self.read_index_through_expiring_relation(
+                        &mut store,
+                        rel_app,
+                        &mut gen_symb,
+                    )?;
+                    let rel_app = through_relation.as_ref().unwrap_or(rel_app);
 
                     // already existing vars
                     let mut prev_joiner_vars = vec![];
@@ -453,8 +512,13 @@ impl<'a> SessionTx<'a> {
                         }
                     }
 
-                    let chosen_index =
-                        store.choose_index(&join_indices, rel_app.valid_at.is_some());
+                    // soft-deleted rows are never indexed, so they can only come from the relation
+                    let chosen_index = if rel_app.include_deleted && store.soft_delete {
+                        store.soft_delete = false;
+                        None
+                    } else {
+                        store.choose_index(&join_indices, rel_app.valid_at.is_some())
+                    };
 
                     match chosen_index {
                         None | Some((_, _, true)) => {
@@ -602,16 +666,14 @@ impl<'a> SessionTx<'a> {
                 MagicAtom::Unification(u) => {
                     if seen_variables.contains(&u.binding) {
                         let expr = if u.one_many_unif {
-                            Expr::build_is_in(
-                                vec![
-                                    Expr::Binding {
-                                        var: u.binding.clone(),
-                                        tuple_pos: None,
-                                    },
-                                    u.expr.clone(),
-                                ],
-                                u.span,
-                            )
+                            let binding = Expr::Binding {
+                                var: u.binding.clone(),
+                                tuple_pos: None,
+                            };
+                            match Expr::build_in_range(binding.clone(), &u.expr, u.span) {
+                                Some(expr) => expr,
+                                None => Expr::build_is_in(vec![binding, u.expr.clone()], u.span),
+                            }
                         } else {
                             Expr::build_equate(
                                 vec![
@@ -630,6 +692,17 @@ impl<'a> SessionTx<'a> {
                         ret = ret.unify(u.binding.clone(), u.expr.clone(), u.one_many_unif, u.span);
                     }
                 }
+                MagicAtom::Series(s) => {
+                    if seen_variables.contains(&s.binding) {
+                        let rk = gen_symb(s.binding.span);
+                        let right = RelAlgebra::series(s.clone(), rk.clone());
+                        ret = ret.join(right, vec![s.binding.clone()], vec![rk], s.span);
+                    } else {
+                        seen_variables.insert(s.binding.clone());
+                        let right = RelAlgebra::series(s.clone(), s.binding.clone());
+                        ret = ret.cartesian_join(right, s.span);
+                    }
+                }
             }
         }
 
@@ -662,4 +735,39 @@ impl<'a> SessionTx<'a> {
 
         Ok(ret)
     }
+
+    /// Expired rows stay indexed until they are purged, so an index of a relation with a TTL
+    /// is read through the relation instead, which leaves them out and may still use the index.
+    /// Returns the atom reading the relation if `store` is such an index, replacing it.
+    fn read_index_through_expiring_relation(
+        &self,
+        store: &mut RelationHandle,
+        rel_app: &MagicRelationApplyAtom,
+        mut gen_symb: impl FnMut(SourceSpan) -> Symbol,
+    ) -> Result<Option<MagicRelationApplyAtom>> {
+        let (base_name, idx_name) = match rel_app.name.split_once(':') {
+            Some(names) => names,
+            None => return Ok(None),
+        };
+        let base = self.get_relation(base_name, false)?;
+        let mapper = match base.indices.get(idx_name) {
+            Some((_, mapper)) if base.ttl.is_some() => mapper.clone(),
+            _ => return Ok(None),
+        };
+        // the columns not in the index are ignored
+        let mut args = (0..base.arity())
+            .map(|_| {
+                let symb = gen_symb(rel_app.span);
+                Symbol::new(format!("~{}", symb.name), symb.span)
+            })
+            .collect_vec();
+        for (arg, i) in rel_app.args.iter().zip(mapper) {
+            args[i] = arg.clone();
+        }
+        *store = base;
+        Ok(Some(MagicRelationApplyAtom {
+            args,
+            ..rel_app.clone()
+        }))
+    }
 }
@@ -7,16 +7,19 @@
  */
 
 use std::collections::btree_map::Entry;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use itertools::Itertools;
 use log::{debug, trace};
-use miette::Result;
-#[cfg(not(target_arch = "wasm32"))]
+use miette::{bail, Diagnostic, Result};
+#[cfg(all(feature = "rayon", not(target_arch = "wasm32")))]
 use rayon::prelude::*;
+use thiserror::Error;
 
 use crate::data::aggr::Aggregation;
+#[cfg(all(feature = "rayon", not(target_arch = "wasm32")))]
+use crate::data::functions::has_seeded_rng;
 use crate::data::program::{MagicSymbol, NoEntryError};
 use crate::data::symb::{Symbol, PROG_ENTRY};
 use crate::data::tuple::Tuple;
@@ -26,10 +29,40 @@ use crate::parse::SourceSpan;
 use crate::query::compile::{
     AggrKind, CompiledProgram, CompiledRule, CompiledRuleSet, ContainedRuleMultiplicity,
 };
+use crate::query::graph::{strongly_connected_components, Graph};
+#[cfg(all(feature = "rayon", not(target_arch = "wasm32")))]
+use crate::query::metrics::metrics_enabled;
+use crate::query::metrics::op_metrics;
 use crate::runtime::db::Poison;
 use crate::runtime::temp_store::{EpochStore, MeetAggrStore, RegularTempStore};
 use crate::runtime::transact::SessionTx;
 
+/// The rules of a stratum that depend on themselves, directly or through other rules.
+fn recursive_rules(prog: &CompiledProgram) -> Result<BTreeSet<&MagicSymbol>> {
+    let graph: Graph<&MagicSymbol> = prog
+        .iter()
+        .map(|(k, rule_set)| {
+            let deps = match rule_set {
+                CompiledRuleSet::Rules(rules) => rules
+                    .iter()
+                    .flat_map(|rule| rule.contained_rules.keys())
+                    .filter(|dep| prog.contains_key(dep))
+                    .collect_vec(),
+                // fixed rules are run once
+                CompiledRuleSet::Fixed(_) => vec![],
+            };
+            (k, deps)
+        })
+        .collect();
+    let mut ret = BTreeSet::new();
+    for scc in strongly_connected_components(&graph)? {
+        if scc.len() > 1 || graph[scc[0]].contains(scc[0]) {
+            ret.extend(scc.into_iter().copied());
+        }
+    }
+    Ok(ret)
+}
+
 pub(crate) struct QueryLimiter {
     total: Option<usize>,
     skip: Option<usize>,
@@ -68,6 +101,7 @@ impl<'a> SessionTx<'a> {
         store_lifetimes: BTreeMap<MagicSymbol, usize>,
         total_num_to_take: Option<usize>,
         num_to_skip: Option<usize>,
+        hop_limit: Option<(usize, bool)>,
         poison: Poison,
     ) -> Result<(EpochStore, bool)> {
         let mut stores: BTreeMap<MagicSymbol, EpochStore> = BTreeMap::new();
@@ -100,6 +134,7 @@ impl<'a> SessionTx<'a> {
                 &mut stores,
                 total_num_to_take,
                 num_to_skip,
+                hop_limit,
                 poison.clone(),
             )?;
         }
@@ -110,14 +145,28 @@ impl<'a> SessionTx<'a> {
         Ok((ret_area, early_return))
     }
     /// returns true if early return is activated
+    ///
+    /// With `hop_limit`, recursive rules are applied at most that many times after the
+    /// epoch in which they first derive rows, then evaluation either fails or, as the flag
+    /// requests truncation, goes on with their later rows dropped. Epochs only passing rows
+    /// along rules that do not depend on themselves are not hops.
     fn semi_naive_magic_evaluate(
         &self,
         prog: &CompiledProgram,
         stores: &mut BTreeMap<MagicSymbol, EpochStore>,
         total_num_to_take: Option<usize>,
         num_to_skip: Option<usize>,
+        hop_limit: Option<(usize, bool)>,
         poison: Poison,
     ) -> Result<bool> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Recursive rules still derive new rows after {0} hops")]
+        #[diagnostic(code(eval::max_hops_exceeded))]
+        #[diagnostic(help(
+            "Raise `:max_hops`, or add `:truncate_hops` to keep the rows derived within it"
+        ))]
+        struct MaxHopsExceeded(usize);
+
         let limiter = QueryLimiter {
             total: total_num_to_take,
             skip: num_to_skip,
@@ -125,6 +174,9 @@ impl<'a> SessionTx<'a> {
         };
 
         let used_limiter: AtomicBool = false.into();
+        let recursive = recursive_rules(prog)?;
+        // the epoch in which recursive rules first derived rows
+        let mut first_hop_epoch = None;
 
         for epoch in 0u32.. {
             debug!("epoch {}", epoch);
@@ -181,12 +233,15 @@ impl<'a> SessionTx<'a> {
                     };
                     Ok((k, new_store))
                 };
-                #[cfg(not(target_arch = "wasm32"))]
+                #[cfg(all(feature = "rayon", not(target_arch = "wasm32")))]
                 {
                     let limiter_enabled = limiter.total.is_some();
+                    // so are all rules when random functions draw from a seeded generator,
+                    // or when metrics are collected for the operators
+                    let sequential = has_seeded_rng() || metrics_enabled();
                     for res in prog
                         .iter()
-                        .filter(|(symb, _)| limiter_enabled && symb.is_prog_entry())
+                        .filter(|(symb, _)| sequential || (limiter_enabled && symb.is_prog_entry()))
                         .map(execution)
                     {
                         let (k, new_store) = res?;
@@ -198,7 +253,9 @@ impl<'a> SessionTx<'a> {
 
                     let execs = prog
                         .par_iter()
-                        .filter(|(symb, _)| !(limiter_enabled && symb.is_prog_entry()))
+                        .filter(|(symb, _)| {
+                            !(sequential || (limiter_enabled && symb.is_prog_entry()))
+                        })
                         .map(execution);
 
                     for res in execs.collect::<Vec<_>>() {
@@ -206,7 +263,7 @@ impl<'a> SessionTx<'a> {
                         to_merge.insert(k, new_store);
                     }
                 }
-                #[cfg(target_arch = "wasm32")]
+                #[cfg(any(not(feature = "rayon"), target_arch = "wasm32"))]
                 {
                     for res in prog.iter().map(execution) {
                         let (k, new_store) = res?;
@@ -255,13 +312,16 @@ impl<'a> SessionTx<'a> {
                     };
                     Ok((k, new_store))
                 };
-                #[cfg(not(target_arch = "wasm32"))]
+                #[cfg(all(feature = "rayon", not(target_arch = "wasm32")))]
                 {
                     let limiter_enabled = limiter.total.is_some();
+                    // so are all rules when random functions draw from a seeded generator,
+                    // or when metrics are collected for the operators
+                    let sequential = has_seeded_rng() || metrics_enabled();
                     // entry rules with limiter must execute sequentially in order to get deterministic ordering
                     for res in prog
                         .iter()
-                        .filter(|(symb, _)| limiter_enabled && symb.is_prog_entry())
+                        .filter(|(symb, _)| sequential || (limiter_enabled && symb.is_prog_entry()))
                         .map(execution)
                     {
                         let (k, new_store) = res?;
@@ -273,14 +333,16 @@ impl<'a> SessionTx<'a> {
 
                     let execs = prog
                         .par_iter()
-                        .filter(|(symb, _)| !(limiter_enabled && symb.is_prog_entry()))
+                        .filter(|(symb, _)| {
+                            !(sequential || (limiter_enabled && symb.is_prog_entry()))
+                        })
                         .map(execution);
                     for res in execs.collect::<Vec<_>>() {
                         let (k, new_store) = res?;
                         to_merge.insert(k, new_store);
                     }
                 }
-                #[cfg(target_arch = "wasm32")]
+                #[cfg(any(not(feature = "rayon"), target_arch = "wasm32"))]
                 {
                     for res in prog.iter().map(execution) {
                         let (k, new_store) = res?;
@@ -288,16 +350,36 @@ impl<'a> SessionTx<'a> {
                     }
                 }
             }
+            let beyond_hop_limit = match (hop_limit, first_hop_epoch) {
+                (Some((max_hops, truncate)), Some(first))
+                    if (epoch - first) as usize > max_hops =>
+                {
+                    Some((max_hops, truncate))
+                }
+                _ => None,
+            };
             let mut changed = false;
-            for (k, new_store) in to_merge {
+            let mut recursive_changed = false;
+            for (k, mut new_store) in to_merge {
+                let is_recursive = recursive.contains(k);
+                if is_recursive && matches!(beyond_hop_limit, Some((_, true))) {
+                    new_store.clear();
+                }
                 let old_store = stores.get_mut(k).unwrap();
                 old_store.merge_in(new_store)?;
                 trace!("delta for {}: {}", k, old_store.has_delta());
                 changed |= old_store.has_delta();
+                recursive_changed |= is_recursive && old_store.has_delta();
             }
             if !changed {
                 break;
             }
+            if recursive_changed {
+                if let Some((max_hops, _)) = beyond_hop_limit {
+                    bail!(MaxHopsExceeded(max_hops));
+                }
+                first_hop_epoch.get_or_insert(epoch);
+            }
         }
         Ok(used_limiter.load(Ordering::Acquire))
     }
@@ -388,6 +470,8 @@ impl<'a> SessionTx<'a> {
     ) -> Result<(bool, RegularTempStore)> {
         let mut out_store = RegularTempStore::default();
         let should_check_limit = limiter.total.is_some() && rule_symb.is_prog_entry();
+        // kept sorted so that groups are emitted in ascending order of their keys,
+        // whatever the order their rows arrive in
         let mut aggr_work: BTreeMap<Vec<DataValue>, Vec<Aggregation>> = BTreeMap::new();
 
         for (rule_n, rule) in ruleset.iter().enumerate() {
@@ -414,10 +498,22 @@ impl<'a> SessionTx<'a> {
                 .filter_map(|(i, a)| a.as_ref().map(|aggr| (i, aggr.clone())))
                 .collect_vec();
 
+            // the values kept by aggregations such as `collect` count against the memory limits,
+            // as do the groups
+            let retained_indices = val_indices_and_aggrs
+                .iter()
+                .filter(|(_, (aggr, _))| aggr.retains_values())
+                .map(|(i, _)| *i)
+                .collect_vec();
+            let op = || format!("the aggregation of rule {rule_symb}");
+
             for item_res in rule.relation.iter(self, None, stores)? {
                 let item = item_res?;
                 trace!("item for {:?}.{}: {:?} at {}", rule_symb, rule_n, item, 0);
 
+                for i in &retained_indices {
+                    self.memory.hold(std::slice::from_ref(&item[*i]), op)?;
+                }
                 let keys = extract_keys(&item);
 
                 match aggr_work.entry(keys) {
@@ -432,6 +528,7 @@ impl<'a> SessionTx<'a> {
                         }
                     }
                     Entry::Vacant(ent) => {
+                        self.memory.hold(ent.key(), op)?;
                         let mut aggr_ops = Vec::with_capacity(val_indices_and_aggrs.len());
                         for (i, (aggr, params)) in &val_indices_and_aggrs {
                             let mut cur_aggr = aggr.clone();
@@ -459,6 +556,10 @@ impl<'a> SessionTx<'a> {
             }
         }
 
+        if let Some(metrics) = op_metrics(ruleset[0].aggr_metrics_key()) {
+            metrics.buffer(aggr_work.len());
+        }
+
         if aggr_work.is_empty() && ruleset[0].aggr.iter().all(|v| v.is_some()) {
             let empty_result: Vec<_> = ruleset[0]
                 .aggr
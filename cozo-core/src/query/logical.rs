@@ -124,8 +124,12 @@ impl InputAtom {
                 InputAtom::Search { inner } => {
                     bail!(UnsafeNegation(inner.span))
                 }
+                InputAtom::Series { inner } => {
+                    bail!(UnsafeNegation(inner.span))
+                }
             },
             InputAtom::Search { inner } => InputAtom::Search { inner },
+            InputAtom::Series { inner } => InputAtom::Series { inner },
         })
     }
 
@@ -140,6 +144,7 @@ impl InputAtom {
             name,
             mut args,
             valid_at,
+            include_deleted,
             span,
         }: InputNamedFieldRelationApplyAtom,
         gen: &mut TempSymbGen,
@@ -177,6 +182,7 @@ impl InputAtom {
             args: new_args,
             span,
             valid_at,
+            include_deleted,
         })
     }
 
@@ -232,6 +238,7 @@ impl InputAtom {
                 Disjunction::singlet(NormalFormAtom::Unification(u))
             }
             InputAtom::Search { inner } => inner.normalize(gen, tx)?,
+            InputAtom::Series { inner } => Disjunction::singlet(NormalFormAtom::Series(inner)),
         })
     }
 }
@@ -344,6 +351,7 @@ impl InputRelationApplyAtom {
                 name: self.name,
                 args,
                 valid_at: self.valid_at,
+                include_deleted: self.include_deleted,
                 span: self.span,
             })
         } else {
@@ -351,6 +359,7 @@ impl InputRelationApplyAtom {
                 name: self.name,
                 args,
                 valid_at: self.valid_at,
+                include_deleted: self.include_deleted,
                 span: self.span,
             })
         });
@@ -25,6 +25,7 @@ use crate::data::symb::{Symbol, PROG_ENTRY};
 use crate::parse::SourceSpan;
 use crate::query::logical::NamedFieldNotFound;
 use crate::query::ra::InvalidTimeTravelScanning;
+use crate::runtime::relation::SOFT_DELETE_COL;
 use crate::runtime::transact::SessionTx;
 
 impl NormalFormProgram {
@@ -176,6 +177,10 @@ fn magic_rewrite_ruleset(
                     seen_bindings.insert(u.binding.clone());
                     collected_atoms.push(MagicAtom::Unification(u));
                 }
+                MagicAtom::Series(s) => {
+                    seen_bindings.insert(s.binding.clone());
+                    collected_atoms.push(MagicAtom::Series(s));
+                }
                 MagicAtom::HnswSearch(s) => {
                     seen_bindings.extend(s.all_bindings().cloned());
                     collected_atoms.push(MagicAtom::HnswSearch(s));
@@ -411,6 +416,10 @@ impl NormalFormProgram {
                                                     .keys
                                                     .iter()
                                                     .chain(relation.metadata.non_keys.iter())
+                                                    .filter(|col| {
+                                                        !relation.soft_delete
+                                                            || col.name != SOFT_DELETE_COL
+                                                    })
                                                     .map(|col| &col.name)
                                                     .collect();
                                                 for k in bindings.keys() {
@@ -428,6 +437,10 @@ impl NormalFormProgram {
                                                     .keys
                                                     .iter()
                                                     .chain(relation.metadata.non_keys.iter())
+                                                    .filter(|col| {
+                                                        !relation.soft_delete
+                                                            || col.name != SOFT_DELETE_COL
+                                                    })
                                                     .enumerate()
                                                     .map(|(i, col)| match bindings.get(&col.name) {
                                                         None => Symbol::new(
@@ -522,6 +535,7 @@ impl NormalFormAtom {
                     name: v.name.clone(),
                     args: v.args.clone(),
                     valid_at: v.valid_at,
+                    include_deleted: v.include_deleted,
                     span: v.span,
                 };
                 for arg in v.args.iter() {
@@ -602,6 +616,7 @@ impl NormalFormAtom {
                     name: nv.name.clone(),
                     args: nv.args.clone(),
                     valid_at: nv.valid_at,
+                    include_deleted: nv.include_deleted,
                     span: nv.span,
                 })
             }
@@ -609,6 +624,10 @@ impl NormalFormAtom {
                 seen_bindings.insert(u.binding.clone());
                 MagicAtom::Unification(u.clone())
             }
+            NormalFormAtom::Series(s) => {
+                seen_bindings.insert(s.binding.clone());
+                MagicAtom::Series(s.clone())
+            }
         }
     }
 }
@@ -0,0 +1,217 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+
+use itertools::Itertools;
+use miette::Result;
+
+use crate::data::tuple::{Tuple, TupleIter};
+use crate::data::value::DataValue;
+use crate::runtime::db::NamedRows;
+
+thread_local! {
+    /// The counters of the script running on this thread, present only if
+    /// its session collects metrics.
+    static COLLECTOR: RefCell<Option<Collector>> = const { RefCell::new(None) };
+}
+
+#[derive(Default)]
+struct Collector {
+    ops: BTreeMap<usize, Rc<OpMetrics>>,
+    last: Option<QueryMetrics>,
+}
+
+/// Key of the counters of the final sort of a query, which no operator of the plan has.
+pub(crate) const SORT_KEY: usize = 0;
+
+/// Counters of a single operator, summed over all the times it is run.
+#[derive(Debug, Default)]
+pub(crate) struct OpMetrics {
+    pub(crate) rows: Cell<u64>,
+    pub(crate) elapsed: Cell<Duration>,
+    /// The largest number of rows held in memory at once
+    pub(crate) buffered: Cell<usize>,
+    /// Set for the right side of a join done by looking up each row of the left side,
+    /// whose time is spent within the join itself
+    pub(crate) lookup: Cell<bool>,
+}
+
+impl OpMetrics {
+    pub(crate) fn time<T>(&self, f: impl FnOnce() -> T) -> T {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let start = Instant::now();
+            let ret = f();
+            self.elapsed.set(self.elapsed.get() + start.elapsed());
+            ret
+        }
+        #[cfg(target_arch = "wasm32")]
+        f()
+    }
+    pub(crate) fn add_rows(&self, n: usize) {
+        self.rows.set(self.rows.get() + n as u64);
+    }
+    pub(crate) fn buffer(&self, n: usize) {
+        self.buffered.set(self.buffered.get().max(n));
+    }
+}
+
+/// Runs `f` with metrics collected on this thread, returning the metrics of the
+/// last query it evaluated.
+pub(crate) fn with_metrics<T>(f: impl FnOnce() -> T) -> (T, Option<QueryMetrics>) {
+    let outer = COLLECTOR.with(|c| c.borrow_mut().replace(Collector::default()));
+    let ret = f();
+    let collected = COLLECTOR.with(|c| std::mem::replace(&mut *c.borrow_mut(), outer));
+    (ret, collected.and_then(|c| c.last))
+}
+
+/// Whether metrics are being collected on this thread, in which case rules
+/// must be evaluated on it as well.
+pub(crate) fn metrics_enabled() -> bool {
+    COLLECTOR.with(|c| c.borrow().is_some())
+}
+
+/// The counters for the operator identified by `key`, or `None` if metrics are not collected.
+pub(crate) fn op_metrics(key: usize) -> Option<Rc<OpMetrics>> {
+    COLLECTOR.with(|c| {
+        c.borrow_mut()
+            .as_mut()
+            .map(|c| c.ops.entry(key).or_default().clone())
+    })
+}
+
+/// Takes the counters collected so far, starting afresh.
+pub(crate) fn take_op_metrics() -> BTreeMap<usize, Rc<OpMetrics>> {
+    COLLECTOR.with(|c| {
+        c.borrow_mut()
+            .as_mut()
+            .map(|c| std::mem::take(&mut c.ops))
+            .unwrap_or_default()
+    })
+}
+
+pub(crate) fn set_last_query_metrics(metrics: QueryMetrics) {
+    COLLECTOR.with(|c| {
+        if let Some(c) = c.borrow_mut().as_mut() {
+            c.last = Some(metrics)
+        }
+    })
+}
+
+/// Counts the rows passing through an operator and the time spent producing them.
+pub(crate) struct Metered<'a> {
+    inner: TupleIter<'a>,
+    metrics: Rc<OpMetrics>,
+    timed: bool,
+}
+
+impl<'a> Metered<'a> {
+    pub(crate) fn wrap(inner: TupleIter<'a>, metrics: Rc<OpMetrics>, timed: bool) -> TupleIter<'a> {
+        if !timed {
+            metrics.lookup.set(true);
+        }
+        Box::new(Self {
+            inner,
+            metrics,
+            timed,
+        })
+    }
+}
+
+impl Iterator for Metered<'_> {
+    type Item = Result<Tuple>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ret = if self.timed {
+            self.metrics.time(|| self.inner.next())
+        } else {
+            self.inner.next()
+        };
+        if let Some(Ok(_)) = ret {
+            self.metrics.add_rows(1);
+        }
+        ret
+    }
+}
+
+/// Metrics of the execution of a query, collected when enabled with
+/// [crate::Db::set_collect_metrics].
+///
+/// The rows of `plan` are those of `::explain` for the query, in the same order,
+/// with the columns `rows` (the rows produced by the operator over all iterations),
+/// `time_ms` (the time spent producing them, including the time spent in its inputs)
+/// and `buffered` (the largest number of rows held in memory at once, by a join
+/// for its right side, by an aggregation for its groups, or by `:order` for sorting).
+/// For the right side of a join that looks up rows for each row of its left side,
+/// `rows` counts the rows it contributed and `time_ms` the time spent in the lookups.
+/// A last row with op `sort` is present if the query is sorted.
+#[derive(Debug, Clone)]
+pub struct QueryMetrics {
+    /// The annotated plan
+    pub plan: NamedRows,
+    /// The stored rows skipped as they cannot be decoded, see [crate::Db::set_skip_corrupt]
+    pub corrupt_skipped: usize,
+    /// Warnings about the conversion of the result, such as columns of
+    /// [crate::Db::run_query_arrow] given as strings
+    pub warnings: Vec<String>,
+}
+
+impl Display for QueryMetrics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let cells = self
+            .plan
+            .rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|v| match v.get_str() {
+                        Some(s) => s.to_string(),
+                        None if *v == DataValue::Null => String::new(),
+                        None => v.to_string(),
+                    })
+                    .collect_vec()
+            })
+            .collect_vec();
+        let widths = self
+            .plan
+            .headers
+            .iter()
+            .enumerate()
+            .map(|(i, h)| {
+                cells
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .chain([h.chars().count()])
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect_vec();
+        let mut write_line = |row: &mut dyn Iterator<Item = &String>| -> std::fmt::Result {
+            let line = row.zip(&widths).map(|(s, w)| format!("{s:<w$}")).join("  ");
+            writeln!(f, "{}", line.trim_end())
+        };
+        write_line(&mut self.plan.headers.iter())?;
+        for row in &cells {
+            write_line(&mut row.iter())?;
+        }
+        if self.corrupt_skipped > 0 {
+            writeln!(f, "{} corrupt rows skipped", self.corrupt_skipped)?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "warning: {warning}")?;
+        }
+        Ok(())
+    }
+}
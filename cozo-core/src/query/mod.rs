@@ -6,13 +6,17 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+pub(crate) mod builder;
 pub(crate) mod compile;
 pub(crate) mod eval;
 pub(crate) mod graph;
 pub(crate) mod logical;
 pub(crate) mod magic;
+pub(crate) mod metrics;
+pub(crate) mod plan;
 pub(crate) mod ra;
 pub(crate) mod reorder;
 pub(crate) mod sort;
 pub(crate) mod stored;
 pub(crate) mod stratify;
+pub(crate) mod typing;
@@ -0,0 +1,944 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Compiled queries as JSON, so that a query compiled once can be shipped to and run by
+//! other processes, possibly against other databases with compatible schemas.
+//!
+//! Stored relations are referred to by name, and when a plan is rebuilt they must exist
+//! with the columns it reads in the same positions. Expressions are kept in their own
+//! serialized form, which does not depend on the source the query was parsed from.
+//! Fixed rules are referred to by name as well. Searches of indices and mutations of
+//! stored relations cannot be planned.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use itertools::Itertools;
+use miette::{bail, ensure, Diagnostic, Result};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use smartstring::SmartString;
+use thiserror::Error;
+
+use crate::data::aggr::{parse_aggr, Aggregation};
+use crate::data::expr::Expr;
+use crate::data::program::{
+    InputProgram, MagicFixedRuleApply, MagicFixedRuleRuleArg, MagicSymbol, Series, SortDir,
+};
+use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, ValidityTs};
+use crate::fixed_rule::{FixedRule, FixedRuleHandle};
+use crate::parse::SourceSpan;
+use crate::query::compile::{
+    CompiledProgram, CompiledRule, CompiledRuleSet, ContainedRuleMultiplicity,
+};
+use crate::query::ra::{
+    FilteredRA, InlineFixedRA, RelAlgebra, SeriesRA, StoredRA, StoredWithValidityRA, TempStoreRA,
+    UnificationRA,
+};
+use crate::query::sort::scan_in_sort_order;
+use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel};
+use crate::runtime::transact::SessionTx;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("the query cannot be turned into a plan as it contains {0}")]
+#[diagnostic(code(plan::not_serializable))]
+struct NotPlannable(&'static str);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("invalid query plan: {0}")]
+#[diagnostic(code(plan::invalid))]
+struct InvalidPlan(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("the plan reads stored relation '{0}', which does not exist")]
+#[diagnostic(code(plan::relation_not_found))]
+struct PlanRelationNotFound(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("the plan calls fixed rule '{0}', which this database does not have")]
+#[diagnostic(code(plan::fixed_rule_not_found))]
+struct PlanFixedRuleNotFound(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("the plan reads column '{1}' of stored relation '{0}', which does not exist")]
+#[diagnostic(code(plan::column_not_found))]
+#[diagnostic(help("the columns of the relation are: {2}"))]
+struct PlanColumnNotFound(String, String, String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("the plan reads the columns [{1}] of stored relation '{0}', but they are [{2}]")]
+#[diagnostic(code(plan::column_mismatch))]
+#[diagnostic(help("columns are read by position, so they must be in the same order"))]
+struct PlanColumnMismatch(String, String, String);
+
+/// A node of the operator tree of a rule, with stored relations referred to by name
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum PlanNode {
+    Fixed {
+        bindings: Vec<Symbol>,
+        data: Vec<Tuple>,
+        to_eliminate: BTreeSet<Symbol>,
+    },
+    TempStore {
+        rule: MagicSymbol,
+        bindings: Vec<Symbol>,
+        filters: Vec<Expr>,
+    },
+    Stored {
+        relation: String,
+        columns: Vec<String>,
+        bindings: Vec<Symbol>,
+        filters: Vec<Expr>,
+        keys_only: bool,
+        reverse: bool,
+    },
+    StoredWithValidity {
+        relation: String,
+        columns: Vec<String>,
+        bindings: Vec<Symbol>,
+        filters: Vec<Expr>,
+        valid_at: ValidityTs,
+    },
+    Join {
+        left: Box<PlanNode>,
+        right: Box<PlanNode>,
+        left_keys: Vec<Symbol>,
+        right_keys: Vec<Symbol>,
+        to_eliminate: BTreeSet<Symbol>,
+    },
+    NegJoin {
+        left: Box<PlanNode>,
+        right: Box<PlanNode>,
+        left_keys: Vec<Symbol>,
+        right_keys: Vec<Symbol>,
+        to_eliminate: BTreeSet<Symbol>,
+    },
+    Reorder {
+        new_order: Vec<Symbol>,
+        relation: Box<PlanNode>,
+    },
+    Filter {
+        parent: Box<PlanNode>,
+        filters: Vec<Expr>,
+        to_eliminate: BTreeSet<Symbol>,
+    },
+    Unification {
+        parent: Box<PlanNode>,
+        binding: Symbol,
+        expr: Expr,
+        is_multi: bool,
+        to_eliminate: BTreeSet<Symbol>,
+    },
+    Series {
+        binding: Symbol,
+        series_binding: Symbol,
+        start: DataValue,
+        end: DataValue,
+        step: DataValue,
+        inclusive: bool,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlanRule {
+    /// The name of the aggregation applied to each column of the head and its arguments
+    aggr: Vec<Option<(String, Vec<DataValue>)>>,
+    contained_rules: Vec<(MagicSymbol, ContainedRuleMultiplicity)>,
+    /// As given by [RelAlgebra::to_plan_json]
+    body: JsonValue,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum PlanFixedRuleArg {
+    InMem {
+        rule: MagicSymbol,
+        bindings: Vec<Symbol>,
+    },
+    Stored {
+        relation: String,
+        bindings: Vec<Symbol>,
+        valid_at: Option<ValidityTs>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlanFixedRule {
+    name: String,
+    rule_args: Vec<PlanFixedRuleArg>,
+    options: BTreeMap<String, Expr>,
+    arity: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum PlanRuleSet {
+    Rules(Vec<PlanRule>),
+    Fixed(PlanFixedRule),
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlanJson {
+    strata: Vec<Vec<(MagicSymbol, PlanRuleSet)>>,
+    store_lifetimes: Vec<(MagicSymbol, usize)>,
+    head: Vec<String>,
+    sorters: Vec<(String, SortDir)>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    sorted_by_scan: bool,
+    hop_limit: Option<(usize, bool)>,
+}
+
+/// A compiled read-only query together with what its evaluation needs
+pub(crate) struct QueryPlan {
+    pub(crate) strata: Vec<CompiledProgram>,
+    pub(crate) store_lifetimes: BTreeMap<MagicSymbol, usize>,
+    pub(crate) head: Vec<Symbol>,
+    pub(crate) sorters: Vec<(Symbol, SortDir)>,
+    pub(crate) limit: Option<usize>,
+    pub(crate) offset: Option<usize>,
+    /// Set when the rows are scanned in the order of the sorters, so that
+    /// the evaluation can stop once enough of them are found
+    pub(crate) sorted_by_scan: bool,
+    pub(crate) hop_limit: Option<(usize, bool)>,
+}
+
+impl QueryPlan {
+    /// Compile `program` as it would be compiled for running it.
+    pub(crate) fn compile(tx: &mut SessionTx<'_>, program: InputProgram) -> Result<Self> {
+        if program.out_opts.store_relation.is_some() {
+            bail!(NotPlannable("a mutation of a stored relation"))
+        }
+        if program.out_opts.assertion.is_some() {
+            bail!(NotPlannable("an assertion"))
+        }
+        if program.out_opts.after.is_some() {
+            bail!(NotPlannable("a cursor"))
+        }
+        let head = program.get_entry_out_head_or_default()?;
+        let (normalized_program, out_opts) = program.into_normalized_program(tx)?;
+        let (stratified_program, store_lifetimes) = normalized_program.into_stratified_program()?;
+        let magic_program = stratified_program.magic_sets_rewrite(tx)?;
+        let mut strata = tx.stratified_magic_compile(magic_program)?;
+        let sorted_by_scan =
+            out_opts.limit.is_some() && scan_in_sort_order(&mut strata, &out_opts.sorters, None)?;
+        Ok(Self {
+            strata,
+            store_lifetimes,
+            head,
+            sorters: out_opts.sorters,
+            limit: out_opts.limit,
+            offset: out_opts.offset,
+            sorted_by_scan,
+            hop_limit: out_opts
+                .max_hops
+                .map(|n| (n as usize, out_opts.truncate_hops)),
+        })
+    }
+    pub(crate) fn to_json(&self) -> Result<JsonValue> {
+        let strata = self
+            .strata
+            .iter()
+            .map(|stratum| {
+                stratum
+                    .iter()
+                    .map(|(rule, rule_set)| -> Result<_> {
+                        Ok((rule.clone(), PlanRuleSet::new(rule_set)?))
+                    })
+                    .try_collect()
+            })
+            .try_collect()?;
+        let plan = PlanJson {
+            strata,
+            store_lifetimes: self
+                .store_lifetimes
+                .iter()
+                .map(|(k, v)| (k.clone(), *v))
+                .collect(),
+            head: self.head.iter().map(|s| s.name.to_string()).collect(),
+            sorters: self
+                .sorters
+                .iter()
+                .map(|(s, dir)| (s.name.to_string(), *dir))
+                .collect(),
+            limit: self.limit,
+            offset: self.offset,
+            sorted_by_scan: self.sorted_by_scan,
+            hop_limit: self.hop_limit,
+        };
+        serde_json::to_value(plan).map_err(|err| InvalidPlan(err.to_string()).into())
+    }
+    /// Rebuild a plan against the stored relations seen by `tx`,
+    /// with the given implementations of fixed rules.
+    pub(crate) fn from_json(
+        tx: &SessionTx<'_>,
+        fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
+        json: &JsonValue,
+    ) -> Result<Self> {
+        let plan: PlanJson =
+            serde_json::from_value(json.clone()).map_err(|err| InvalidPlan(err.to_string()))?;
+        let strata: Vec<CompiledProgram> = plan
+            .strata
+            .into_iter()
+            .map(|stratum| {
+                stratum
+                    .into_iter()
+                    .map(|(rule, rule_set)| -> Result<_> {
+                        Ok((rule, rule_set.build(tx, fixed_rules)?))
+                    })
+                    .try_collect()
+            })
+            .try_collect()?;
+        let arities: BTreeMap<&MagicSymbol, usize> = strata
+            .iter()
+            .flat_map(|stratum| stratum.iter())
+            .map(|(rule, rule_set)| (rule, rule_set.arity()))
+            .collect();
+        for rule_set in strata.iter().flat_map(|stratum| stratum.values()) {
+            match rule_set {
+                CompiledRuleSet::Rules(rules) => {
+                    for rule in rules {
+                        check_rule_reads(&rule.relation, &arities)?;
+                    }
+                }
+                CompiledRuleSet::Fixed(fixed) => {
+                    for arg in fixed.rule_args.iter() {
+                        if let MagicFixedRuleRuleArg::InMem { name, bindings, .. } = arg {
+                            check_rule_read(name, bindings.len(), &arities)?;
+                        }
+                    }
+                }
+            }
+        }
+        let entry = MagicSymbol::Muggle {
+            inner: Symbol::new(PROG_ENTRY, SourceSpan::default()),
+        };
+        let Some(&entry_arity) = arities.get(&entry) else {
+            bail!(InvalidPlan("the plan has no entry rule".to_string()))
+        };
+        ensure!(
+            entry_arity == plan.head.len(),
+            InvalidPlan(format!(
+                "the head has {} columns, but the entry rule gives {entry_arity}",
+                plan.head.len()
+            ))
+        );
+        if let Some((name, _)) = plan
+            .sorters
+            .iter()
+            .find(|(name, _)| !plan.head.contains(name))
+        {
+            bail!(InvalidPlan(format!(
+                "sorting by '{name}', which is not in the head"
+            )))
+        }
+        let symbol = |name: String| Symbol::new(name, SourceSpan::default());
+        Ok(Self {
+            strata,
+            store_lifetimes: plan.store_lifetimes.into_iter().collect(),
+            head: plan.head.into_iter().map(symbol).collect(),
+            sorters: plan
+                .sorters
+                .into_iter()
+                .map(|(name, dir)| (symbol(name), dir))
+                .collect(),
+            limit: plan.limit,
+            offset: plan.offset,
+            sorted_by_scan: plan.sorted_by_scan,
+            hop_limit: plan.hop_limit,
+        })
+    }
+}
+
+impl PlanRuleSet {
+    fn new(rule_set: &CompiledRuleSet) -> Result<Self> {
+        Ok(match rule_set {
+            CompiledRuleSet::Rules(rules) => PlanRuleSet::Rules(
+                rules
+                    .iter()
+                    .map(|r| -> Result<_> {
+                        Ok(PlanRule {
+                            aggr: r
+                                .aggr
+                                .iter()
+                                .map(|a| {
+                                    a.as_ref()
+                                        .map(|(aggr, args)| (aggr_name(aggr), args.clone()))
+                                })
+                                .collect(),
+                            contained_rules: r
+                                .contained_rules
+                                .iter()
+                                .map(|(k, v)| (k.clone(), *v))
+                                .collect(),
+                            body: r.relation.to_plan_json()?,
+                        })
+                    })
+                    .try_collect()?,
+            ),
+            CompiledRuleSet::Fixed(fixed) => {
+                check_plannable(&fixed.options.values().cloned().collect_vec())?;
+                PlanRuleSet::Fixed(PlanFixedRule {
+                    name: fixed.fixed_handle.name.name.to_string(),
+                    rule_args: fixed
+                        .rule_args
+                        .iter()
+                        .map(|arg| match arg {
+                            MagicFixedRuleRuleArg::InMem { name, bindings, .. } => {
+                                PlanFixedRuleArg::InMem {
+                                    rule: name.clone(),
+                                    bindings: bindings.clone(),
+                                }
+                            }
+                            MagicFixedRuleRuleArg::Stored {
+                                name,
+                                bindings,
+                                valid_at,
+                                ..
+                            } => PlanFixedRuleArg::Stored {
+                                relation: name.name.to_string(),
+                                bindings: bindings.clone(),
+                                valid_at: *valid_at,
+                            },
+                        })
+                        .collect(),
+                    options: fixed
+                        .options
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), v.clone()))
+                        .collect(),
+                    arity: fixed.arity,
+                })
+            }
+        })
+    }
+    fn build(
+        self,
+        tx: &SessionTx<'_>,
+        fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
+    ) -> Result<CompiledRuleSet> {
+        Ok(match self {
+            PlanRuleSet::Rules(rules) => {
+                let rules = rules
+                    .into_iter()
+                    .map(|r| -> Result<_> {
+                        let aggr: Vec<_> = r
+                            .aggr
+                            .into_iter()
+                            .map(|a| match a {
+                                None => Ok(None),
+                                Some((name, args)) => match parse_aggr(&name) {
+                                    Some(aggr) => Ok(Some((aggr.clone(), args))),
+                                    None => {
+                                        bail!(InvalidPlan(format!("unknown aggregation '{name}'")))
+                                    }
+                                },
+                            })
+                            .try_collect()?;
+                        let relation = RelAlgebra::build_from_plan_json(tx, &r.body)?;
+                        let arity = relation.bindings_after_eliminate().len();
+                        ensure!(
+                            aggr.len() == arity,
+                            InvalidPlan(format!(
+                                "a rule gives {arity} columns, but has {} aggregations",
+                                aggr.len()
+                            ))
+                        );
+                        Ok(CompiledRule {
+                            aggr,
+                            relation,
+                            contained_rules: r.contained_rules.into_iter().collect(),
+                        })
+                    })
+                    .try_collect::<_, Vec<_>, _>()?;
+                let Some(first) = rules.first() else {
+                    bail!(InvalidPlan("a rule set has no rules".to_string()))
+                };
+                ensure!(
+                    rules.iter().all(|r| r.aggr.len() == first.aggr.len()),
+                    InvalidPlan(
+                        "the rules of a rule set give different numbers of columns".to_string()
+                    )
+                );
+                CompiledRuleSet::Rules(rules)
+            }
+            PlanRuleSet::Fixed(fixed) => {
+                let Some(fixed_impl) = fixed_rules.get(&fixed.name) else {
+                    bail!(PlanFixedRuleNotFound(fixed.name))
+                };
+                let span = SourceSpan::default();
+                let rule_args = fixed
+                    .rule_args
+                    .into_iter()
+                    .map(|arg| -> Result<_> {
+                        Ok(match arg {
+                            PlanFixedRuleArg::InMem { rule, bindings } => {
+                                MagicFixedRuleRuleArg::InMem {
+                                    name: rule,
+                                    bindings,
+                                    span,
+                                }
+                            }
+                            PlanFixedRuleArg::Stored {
+                                relation,
+                                bindings,
+                                valid_at,
+                            } => {
+                                ensure!(
+                                    tx.relation_exists(&relation)?,
+                                    PlanRelationNotFound(relation)
+                                );
+                                MagicFixedRuleRuleArg::Stored {
+                                    name: Symbol::new(relation, span),
+                                    bindings,
+                                    valid_at,
+                                    span,
+                                }
+                            }
+                        })
+                    })
+                    .try_collect()?;
+                CompiledRuleSet::Fixed(MagicFixedRuleApply {
+                    fixed_handle: FixedRuleHandle {
+                        name: Symbol::new(fixed.name, span),
+                    },
+                    rule_args,
+                    options: Arc::new(
+                        fixed
+                            .options
+                            .into_iter()
+                            .map(|(k, v)| (SmartString::from(k), v))
+                            .collect(),
+                    ),
+                    span,
+                    arity: fixed.arity,
+                    fixed_impl: fixed_impl.clone(),
+                })
+            }
+        })
+    }
+}
+
+impl RelAlgebra {
+    /// A self-contained description of the operator tree, see [QueryPlan].
+    pub(crate) fn to_plan_json(&self) -> Result<JsonValue> {
+        ensure!(
+            nesting_depth(self) <= MAX_PLAN_DEPTH,
+            NotPlannable("operators nested too deeply")
+        );
+        serde_json::to_value(PlanNode::from_ra(self)?)
+            .map_err(|err| InvalidPlan(err.to_string()).into())
+    }
+    /// Rebuild an operator tree described by [RelAlgebra::to_plan_json], ready to be run.
+    pub(crate) fn build_from_plan_json(tx: &SessionTx<'_>, json: &JsonValue) -> Result<Self> {
+        ensure!(
+            json_nesting_depth(json) <= MAX_PLAN_DEPTH,
+            InvalidPlan("operators nested too deeply".to_string())
+        );
+        let node: PlanNode =
+            serde_json::from_value(json.clone()).map_err(|err| InvalidPlan(err.to_string()))?;
+        let mut ra = *node.into_ra(tx)?;
+        ra.fill_binding_indices_and_compile()?;
+        Ok(ra)
+    }
+}
+
+/// Deeper operator trees are not planned, as (de)serializing them recurses as deep.
+const MAX_PLAN_DEPTH: usize = 128;
+
+fn nesting_depth(ra: &RelAlgebra) -> usize {
+    1 + match ra {
+        RelAlgebra::Join(r) => nesting_depth(&r.left).max(nesting_depth(&r.right)),
+        RelAlgebra::NegJoin(r) => nesting_depth(&r.left).max(nesting_depth(&r.right)),
+        RelAlgebra::Reorder(r) => nesting_depth(&r.relation),
+        RelAlgebra::Filter(r) => nesting_depth(&r.parent),
+        RelAlgebra::Unification(r) => nesting_depth(&r.parent),
+        _ => 0,
+    }
+}
+
+/// The depth of the operators described by the JSON of a plan, found without recursing.
+fn json_nesting_depth(json: &JsonValue) -> usize {
+    let mut max_depth = 0;
+    let mut stack = vec![(json, 1)];
+    while let Some((node, depth)) = stack.pop() {
+        max_depth = max_depth.max(depth);
+        for key in ["left", "right", "relation", "parent"] {
+            if let Some(child) = node.get(key).filter(|child| child.is_object()) {
+                stack.push((child, depth + 1));
+            }
+        }
+    }
+    max_depth
+}
+
+/// The name of the aggregation in scripts, which [parse_aggr] recognizes
+fn aggr_name(aggr: &Aggregation) -> String {
+    aggr.name
+        .strip_prefix("AGGR_")
+        .unwrap_or(aggr.name)
+        .to_ascii_lowercase()
+}
+
+/// Regexes are compiled when the query is, and cannot be serialized.
+fn check_plannable(exprs: &[Expr]) -> Result<()> {
+    fn walk(expr: &Expr) -> bool {
+        match expr {
+            Expr::Binding { .. } => true,
+            Expr::Const { val, .. } => !matches!(val, DataValue::Regex(_)),
+            Expr::Apply { args, .. } | Expr::UnboundApply { args, .. } => args.iter().all(walk),
+            Expr::Cond { clauses, .. } => clauses.iter().all(|(a, b)| walk(a) && walk(b)),
+        }
+    }
+    ensure!(
+        exprs.iter().all(walk),
+        NotPlannable("a precompiled regular expression")
+    );
+    Ok(())
+}
+
+fn check_columns(tx: &SessionTx<'_>, relation: &str, columns: &[String]) -> Result<()> {
+    ensure!(
+        tx.relation_exists(relation)?,
+        PlanRelationNotFound(relation.to_string())
+    );
+    let handle = tx.get_relation(relation, false)?;
+    if handle.access_level < AccessLevel::ReadOnly {
+        bail!(InsufficientAccessLevel(
+            handle.name.to_string(),
+            "reading rows".to_string(),
+            handle.access_level
+        ));
+    }
+    let actual = handle
+        .metadata
+        .keys
+        .iter()
+        .chain(handle.metadata.non_keys.iter())
+        .map(|c| c.name.to_string())
+        .collect_vec();
+    if let Some(col) = columns.iter().find(|c| !actual.contains(c)) {
+        bail!(PlanColumnNotFound(
+            relation.to_string(),
+            col.clone(),
+            actual.join(", ")
+        ))
+    }
+    ensure!(
+        actual == columns,
+        PlanColumnMismatch(relation.to_string(), columns.join(", "), actual.join(", "))
+    );
+    Ok(())
+}
+
+/// Rules must be read with as many bindings as they have columns.
+fn check_rule_reads(ra: &RelAlgebra, arities: &BTreeMap<&MagicSymbol, usize>) -> Result<()> {
+    match ra {
+        RelAlgebra::TempStore(r) => check_rule_read(&r.storage_key, r.bindings.len(), arities),
+        RelAlgebra::Join(r) => {
+            check_rule_reads(&r.left, arities)?;
+            check_rule_reads(&r.right, arities)
+        }
+        RelAlgebra::NegJoin(r) => {
+            check_rule_reads(&r.left, arities)?;
+            check_rule_reads(&r.right, arities)
+        }
+        RelAlgebra::Reorder(r) => check_rule_reads(&r.relation, arities),
+        RelAlgebra::Filter(r) => check_rule_reads(&r.parent, arities),
+        RelAlgebra::Unification(r) => check_rule_reads(&r.parent, arities),
+        _ => Ok(()),
+    }
+}
+
+fn check_rule_read(
+    rule: &MagicSymbol,
+    num_bindings: usize,
+    arities: &BTreeMap<&MagicSymbol, usize>,
+) -> Result<()> {
+    let Some(&arity) = arities.get(rule) else {
+        bail!(InvalidPlan(format!(
+            "rule '{rule}' is read but not defined"
+        )))
+    };
+    ensure!(
+        arity == num_bindings,
+        InvalidPlan(format!(
+            "rule '{rule}' has {arity} columns, but is read with {num_bindings} bindings"
+        ))
+    );
+    Ok(())
+}
+
+fn check_bindings(relation: &str, bindings: &[Symbol], columns: &[String]) -> Result<()> {
+    ensure!(
+        bindings.len() == columns.len(),
+        InvalidPlan(format!(
+            "stored relation '{relation}' is read with {} bindings for its {} columns",
+            bindings.len(),
+            columns.len()
+        ))
+    );
+    Ok(())
+}
+
+impl PlanNode {
+    fn from_ra(ra: &RelAlgebra) -> Result<Self> {
+        let columns = |handle: &crate::runtime::relation::RelationHandle| {
+            handle
+                .metadata
+                .keys
+                .iter()
+                .chain(handle.metadata.non_keys.iter())
+                .map(|c| c.name.to_string())
+                .collect_vec()
+        };
+        Ok(match ra {
+            RelAlgebra::Fixed(r) => PlanNode::Fixed {
+                bindings: r.bindings.clone(),
+                data: r.data.clone(),
+                to_eliminate: r.to_eliminate.clone(),
+            },
+            RelAlgebra::TempStore(r) => {
+                check_plannable(&r.filters)?;
+                PlanNode::TempStore {
+                    rule: r.storage_key.clone(),
+                    bindings: r.bindings.clone(),
+                    filters: r.filters.clone(),
+                }
+            }
+            RelAlgebra::Stored(r) => {
+                check_plannable(&r.filters)?;
+                PlanNode::Stored {
+                    relation: r.storage.name.to_string(),
+                    columns: columns(&r.storage),
+                    bindings: r.bindings.clone(),
+                    filters: r.filters.clone(),
+                    keys_only: r.keys_only,
+                    reverse: r.reverse,
+                }
+            }
+            RelAlgebra::StoredWithValidity(r) => {
+                check_plannable(&r.filters)?;
+                PlanNode::StoredWithValidity {
+                    relation: r.storage.name.to_string(),
+                    columns: columns(&r.storage),
+                    bindings: r.bindings.clone(),
+                    filters: r.filters.clone(),
+                    valid_at: r.valid_at,
+                }
+            }
+            RelAlgebra::Join(r) => PlanNode::Join {
+                left: Box::new(Self::from_ra(&r.left)?),
+                right: Box::new(Self::from_ra(&r.right)?),
+                left_keys: r.joiner.left_keys.clone(),
+                right_keys: r.joiner.right_keys.clone(),
+                to_eliminate: r.to_eliminate.clone(),
+            },
+            RelAlgebra::NegJoin(r) => PlanNode::NegJoin {
+                left: Box::new(Self::from_ra(&r.left)?),
+                right: Box::new(Self::from_ra(&r.right)?),
+                left_keys: r.joiner.left_keys.clone(),
+                right_keys: r.joiner.right_keys.clone(),
+                to_eliminate: r.to_eliminate.clone(),
+            },
+            RelAlgebra::Reorder(r) => PlanNode::Reorder {
+                new_order: r.new_order.clone(),
+                relation: Box::new(Self::from_ra(&r.relation)?),
+            },
+            RelAlgebra::Filter(r) => {
+                check_plannable(&r.filters)?;
+                PlanNode::Filter {
+                    parent: Box::new(Self::from_ra(&r.parent)?),
+                    filters: r.filters.clone(),
+                    to_eliminate: r.to_eliminate.clone(),
+                }
+            }
+            RelAlgebra::Unification(r) => {
+                check_plannable(std::slice::from_ref(&r.expr))?;
+                PlanNode::Unification {
+                    parent: Box::new(Self::from_ra(&r.parent)?),
+                    binding: r.binding.clone(),
+                    expr: r.expr.clone(),
+                    is_multi: r.is_multi,
+                    to_eliminate: r.to_eliminate.clone(),
+                }
+            }
+            RelAlgebra::Series(r) => PlanNode::Series {
+                binding: r.binding.clone(),
+                series_binding: r.series.binding.clone(),
+                start: r.series.start.clone(),
+                end: r.series.end.clone(),
+                step: r.series.step.clone(),
+                inclusive: r.series.inclusive,
+            },
+            RelAlgebra::HnswSearch(_) => bail!(NotPlannable("a vector search")),
+            RelAlgebra::FtsSearch(_) => bail!(NotPlannable("a full-text search")),
+            RelAlgebra::LshSearch(_) => bail!(NotPlannable("a similarity search")),
+        })
+    }
+    /// Operators are built after their inputs so that only small values stay on the
+    /// stack while recursing into deep trees.
+    fn into_ra(self, tx: &SessionTx<'_>) -> Result<Box<RelAlgebra>> {
+        let span = SourceSpan::default();
+        Ok(match self {
+            PlanNode::Join {
+                left,
+                right,
+                left_keys,
+                right_keys,
+                to_eliminate,
+            } => {
+                let left = left.into_ra(tx)?;
+                let right = right.into_ra(tx)?;
+                join_ra(left, right, left_keys, right_keys, to_eliminate, false)
+            }
+            PlanNode::NegJoin {
+                left,
+                right,
+                left_keys,
+                right_keys,
+                to_eliminate,
+            } => {
+                let left = left.into_ra(tx)?;
+                let right = right.into_ra(tx)?;
+                join_ra(left, right, left_keys, right_keys, to_eliminate, true)
+            }
+            PlanNode::Reorder {
+                new_order,
+                relation,
+            } => Box::new(relation.into_ra(tx)?.reorder(new_order)),
+            PlanNode::Filter {
+                parent,
+                filters,
+                to_eliminate,
+            } => Box::new(RelAlgebra::Filter(FilteredRA {
+                parent: parent.into_ra(tx)?,
+                filters,
+                filters_bytecodes: vec![],
+                to_eliminate,
+                span,
+            })),
+            PlanNode::Unification {
+                parent,
+                binding,
+                expr,
+                is_multi,
+                to_eliminate,
+            } => Box::new(RelAlgebra::Unification(UnificationRA {
+                parent: parent.into_ra(tx)?,
+                binding,
+                expr,
+                expr_bytecode: vec![],
+                is_multi,
+                to_eliminate,
+                span,
+            })),
+            leaf => Box::new(leaf.leaf_into_ra(tx)?),
+        })
+    }
+    fn leaf_into_ra(self, tx: &SessionTx<'_>) -> Result<RelAlgebra> {
+        let span = SourceSpan::default();
+        Ok(match self {
+            PlanNode::Fixed {
+                bindings,
+                data,
+                to_eliminate,
+            } => RelAlgebra::Fixed(InlineFixedRA {
+                bindings,
+                data,
+                to_eliminate,
+                span,
+            }),
+            PlanNode::TempStore {
+                rule,
+                bindings,
+                filters,
+            } => RelAlgebra::TempStore(TempStoreRA {
+                bindings,
+                storage_key: rule,
+                filters,
+                filters_bytecodes: vec![],
+                span,
+            }),
+            PlanNode::Stored {
+                relation,
+                columns,
+                bindings,
+                filters,
+                keys_only,
+                reverse,
+            } => {
+                check_columns(tx, &relation, &columns)?;
+                check_bindings(&relation, &bindings, &columns)?;
+                RelAlgebra::Stored(StoredRA {
+                    bindings,
+                    storage: tx.get_relation(&relation, false)?,
+                    filters,
+                    filters_bytecodes: vec![],
+                    keys_only,
+                    reverse,
+                    span,
+                })
+            }
+            PlanNode::StoredWithValidity {
+                relation,
+                columns,
+                bindings,
+                filters,
+                valid_at,
+            } => {
+                check_columns(tx, &relation, &columns)?;
+                check_bindings(&relation, &bindings, &columns)?;
+                let storage = tx.get_relation(&relation, false)?;
+                match RelAlgebra::relation(bindings, storage, span, Some(valid_at))? {
+                    RelAlgebra::StoredWithValidity(r) => {
+                        RelAlgebra::StoredWithValidity(StoredWithValidityRA { filters, ..r })
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            PlanNode::Series {
+                binding,
+                series_binding,
+                start,
+                end,
+                step,
+                inclusive,
+            } => RelAlgebra::Series(SeriesRA {
+                series: Series::new(series_binding, start, end, step, inclusive, span)?,
+                binding,
+            }),
+            _ => unreachable!(),
+        })
+    }
+}
+
+fn join_ra(
+    left: Box<RelAlgebra>,
+    right: Box<RelAlgebra>,
+    left_keys: Vec<Symbol>,
+    right_keys: Vec<Symbol>,
+    to_eliminate: BTreeSet<Symbol>,
+    negated: bool,
+) -> Box<RelAlgebra> {
+    let span = SourceSpan::default();
+    let mut ret = if negated {
+        left.neg_join(*right, left_keys, right_keys, span)
+    } else {
+        left.join(*right, left_keys, right_keys, span)
+    };
+    match &mut ret {
+        RelAlgebra::Join(j) => j.to_eliminate = to_eliminate,
+        RelAlgebra::NegJoin(j) => j.to_eliminate = to_eliminate,
+        _ => {}
+    }
+    Box::new(ret)
+}
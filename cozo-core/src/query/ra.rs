@@ -18,12 +18,14 @@ use smartstring::SmartString;
 use thiserror::Error;
 
 use crate::data::expr::{compute_bounds, eval_bytecode, eval_bytecode_pred, Bytecode, Expr};
-use crate::data::program::{FtsSearch, HnswSearch, MagicSymbol};
+use crate::data::functions::{OP_IN, OP_IS_IN};
+use crate::data::program::{FtsSearch, HnswSearch, MagicSymbol, Series};
 use crate::data::relation::{ColType, NullableColType};
 use crate::data::symb::Symbol;
 use crate::data::tuple::{Tuple, TupleIter};
 use crate::data::value::{DataValue, ValidityTs};
 use crate::parse::SourceSpan;
+use crate::query::metrics::{op_metrics, Metered};
 use crate::runtime::minhash_lsh::LshSearch;
 use crate::runtime::relation::RelationHandle;
 use crate::runtime::temp_store::EpochStore;
@@ -40,6 +42,7 @@ pub(crate) enum RelAlgebra {
     Reorder(ReorderRA),
     Filter(FilteredRA),
     Unification(UnificationRA),
+    Series(SeriesRA),
     HnswSearch(HnswSearchRA),
     FtsSearch(FtsSearchRA),
     LshSearch(LshSearchRA),
@@ -56,6 +59,7 @@ impl RelAlgebra {
             RelAlgebra::Reorder(i) => i.relation.span(),
             RelAlgebra::Filter(i) => i.span,
             RelAlgebra::Unification(i) => i.span,
+            RelAlgebra::Series(i) => i.series.span,
             RelAlgebra::StoredWithValidity(i) => i.span,
             RelAlgebra::HnswSearch(i) => i.hnsw_search.span,
             RelAlgebra::FtsSearch(i) => i.fts_search.span,
@@ -177,6 +181,50 @@ impl UnificationRA {
     }
 }
 
+/// Generates the elements of a [`Series`] as they are consumed, bound to `binding`.
+pub(crate) struct SeriesRA {
+    pub(crate) series: Series,
+    pub(crate) binding: Symbol,
+}
+
+impl SeriesRA {
+    fn iter(&self) -> TupleIter<'_> {
+        Box::new(self.series.iter().map(|val| Ok(vec![val])))
+    }
+    /// Joining on the binding checks each value from the left for membership,
+    /// otherwise the series is generated anew for each row from the left.
+    fn join<'a>(
+        &'a self,
+        left_iter: TupleIter<'a>,
+        (left_join_indices, _): (Vec<usize>, Vec<usize>),
+        eliminate_indices: BTreeSet<usize>,
+    ) -> TupleIter<'a> {
+        match left_join_indices.first() {
+            Some(&idx) => Box::new(left_iter.filter_map_ok(move |tuple| {
+                if self.series.contains(&tuple[idx]) {
+                    let mut ret = tuple;
+                    ret.push(ret[idx].clone());
+                    Some(eliminate_from_tuple(ret, &eliminate_indices))
+                } else {
+                    None
+                }
+            })),
+            None => Box::new(
+                left_iter
+                    .map_ok(move |tuple| {
+                        let eliminate_indices = eliminate_indices.clone();
+                        self.series.iter().map(move |val| {
+                            let mut ret = tuple.clone();
+                            ret.push(val);
+                            eliminate_from_tuple(ret, &eliminate_indices)
+                        })
+                    })
+                    .flatten_ok(),
+            ),
+        }
+    }
+}
+
 pub(crate) struct FilteredRA {
     pub(crate) parent: Box<RelAlgebra>,
     pub(crate) filters: Vec<Expr>,
@@ -343,6 +391,11 @@ impl Debug for RelAlgebra {
                 .field(&r.binding)
                 .field(&r.expr)
                 .finish(),
+            RelAlgebra::Series(r) => f
+                .debug_tuple("Series")
+                .field(&bindings)
+                .field(&r.series.to_string())
+                .finish(),
         }
     }
 }
@@ -358,7 +411,7 @@ pub(crate) struct InvalidTimeTravelScanning(pub(crate) String, #[label] pub(crat
 impl RelAlgebra {
     pub(crate) fn fill_binding_indices_and_compile(&mut self) -> Result<()> {
         match self {
-            RelAlgebra::Fixed(_) => {}
+            RelAlgebra::Fixed(_) | RelAlgebra::Series(_) => {}
             RelAlgebra::TempStore(d) => {
                 d.fill_binding_indices_and_compile()?;
             }
@@ -436,6 +489,8 @@ impl RelAlgebra {
                 storage,
                 filters: vec![],
                 filters_bytecodes: vec![],
+                keys_only: false,
+                reverse: false,
                 span,
             })),
             Some(vld) => {
@@ -470,6 +525,7 @@ impl RelAlgebra {
             | RelAlgebra::Reorder(_)
             | RelAlgebra::NegJoin(_)
             | RelAlgebra::Unification(_)
+            | RelAlgebra::Series(_)
             | RelAlgebra::HnswSearch(_)
             | RelAlgebra::FtsSearch(_)
             | RelAlgebra::LshSearch(_)) => {
@@ -519,6 +575,8 @@ impl RelAlgebra {
                 storage,
                 mut filters,
                 filters_bytecodes,
+                keys_only,
+                reverse,
                 span,
             }) => {
                 filters.push(filter);
@@ -527,6 +585,8 @@ impl RelAlgebra {
                     storage,
                     filters,
                     filters_bytecodes,
+                    keys_only,
+                    reverse,
                     span,
                 })
             }
@@ -613,6 +673,9 @@ impl RelAlgebra {
             span,
         })
     }
+    pub(crate) fn series(series: Series, binding: Symbol) -> Self {
+        Self::Series(SeriesRA { series, binding })
+    }
     pub(crate) fn hnsw_search(
         self,
         hnsw_search: HnswSearch,
@@ -889,6 +952,12 @@ pub(crate) struct StoredRA {
     pub(crate) storage: RelationHandle,
     pub(crate) filters: Vec<Expr>,
     pub(crate) filters_bytecodes: Vec<(Vec<Bytecode>, SourceSpan)>,
+    /// Set when none of the non-key columns are used downstream,
+    /// in which case the stored values are not decoded at all.
+    pub(crate) keys_only: bool,
+    /// Set when the rows are to be scanned in descending key order,
+    /// so that queries sorted that way can stop early.
+    pub(crate) reverse: bool,
     pub(crate) span: SourceSpan,
 }
 
@@ -1241,6 +1310,16 @@ impl StoredWithValidityRA {
 }
 
 impl StoredRA {
+    pub(crate) fn do_eliminate_temp_vars(&mut self, used: &BTreeSet<Symbol>) -> Result<()> {
+        let mut used = used.clone();
+        for filter in self.filters.iter() {
+            used.extend(filter.bindings()?);
+        }
+        let key_len = self.storage.metadata.keys.len();
+        self.keys_only = self.bindings[key_len..].iter().all(|b| !used.contains(b));
+        Ok(())
+    }
+
     fn fill_binding_indices_and_compile(&mut self) -> Result<()> {
         let bindings: BTreeMap<_, _> = self
             .bindings
@@ -1256,6 +1335,130 @@ impl StoredRA {
         Ok(())
     }
 
+    /// Adds a filter to a scan that has already been compiled.
+    pub(crate) fn add_filter(&mut self, mut filter: Expr) -> Result<()> {
+        let bindings: BTreeMap<_, _> = self
+            .bindings
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(|(a, b)| (b, a))
+            .collect();
+        filter.fill_binding_indices(&bindings)?;
+        self.filters_bytecodes
+            .push((filter.compile()?, filter.span()));
+        self.filters.push(filter);
+        Ok(())
+    }
+
+    /// Computes the range on the key columns following a join prefix of
+    /// length `prefix_len` that the filters allow, so that only this range is
+    /// scanned. Only key columns take part, as the rest are not encoded in the key.
+    /// The bounds are trimmed after the last constrained column; if no
+    /// column is constrained, empty bounds are returned.
+    pub(crate) fn key_bounds(&self, prefix_len: usize) -> Result<(Vec<DataValue>, Vec<DataValue>)> {
+        let key_len = self.storage.metadata.keys.len();
+        if self.filters.is_empty() || prefix_len >= key_len {
+            return Ok((vec![], vec![]));
+        }
+        let (mut lower, mut upper) =
+            compute_bounds(&self.filters, &self.bindings[prefix_len..key_len])?;
+        while let (Some(DataValue::Null), Some(DataValue::Bot)) = (lower.last(), upper.last()) {
+            lower.pop();
+            upper.pop();
+        }
+        Ok((lower, upper))
+    }
+
+    /// If a filter restricts the key column following a join prefix of length
+    /// `prefix_len` to a constant list, as `k in [1, 2, 3]` does, returns the
+    /// values of the list in key order, so that each can be looked up in turn.
+    pub(crate) fn key_points(&self, prefix_len: usize) -> Option<Vec<DataValue>> {
+        let key_len = self.storage.metadata.keys.len();
+        if prefix_len >= key_len {
+            return None;
+        }
+        let target = &self.bindings[prefix_len];
+        for filter in self.filters.iter() {
+            if let Expr::Apply { op, args, .. } = filter {
+                if op.name != OP_IN.name && op.name != OP_IS_IN.name {
+                    continue;
+                }
+                if args[0].get_binding() != Some(target) {
+                    continue;
+                }
+                if let Some(DataValue::List(items)) = args[1].get_const() {
+                    let mut points = vec![];
+                    for item in items {
+                        // integers and floats of equal value are different keys
+                        if let DataValue::Num(n) = item {
+                            if let Some(i) = n.get_int() {
+                                points.push(DataValue::from(i));
+                            }
+                            points.push(DataValue::from(n.get_float()));
+                        } else {
+                            points.push(item.clone());
+                        }
+                    }
+                    points.sort();
+                    points.dedup();
+                    return Some(points);
+                }
+            }
+        }
+        None
+    }
+
+    fn key_points_join<'a>(
+        &'a self,
+        tx: &'a SessionTx<'_>,
+        left_iter: TupleIter<'a>,
+        left_to_prefix_indices: Vec<usize>,
+        mut points: Vec<DataValue>,
+        eliminate_indices: BTreeSet<usize>,
+    ) -> Result<TupleIter<'a>> {
+        if self.reverse {
+            points.reverse();
+        }
+        let it = left_iter
+            .map_ok(move |tuple| {
+                let prefix = left_to_prefix_indices
+                    .iter()
+                    .map(|i| tuple[*i].clone())
+                    .collect_vec();
+                let mut stack = vec![];
+                points
+                    .iter()
+                    .map(|point| {
+                        let mut key = prefix.clone();
+                        key.push(point.clone());
+                        self.scan_prefix(tx, &key)
+                    })
+                    .collect_vec()
+                    .into_iter()
+                    .flatten()
+                    .map(move |res_found| -> Result<Option<Tuple>> {
+                        let found = res_found?;
+                        for (p, span) in self.filters_bytecodes.iter() {
+                            if !eval_bytecode_pred(p, &found, &mut stack, *span)? {
+                                return Ok(None);
+                            }
+                        }
+                        let mut ret = tuple.clone();
+                        ret.extend(found);
+                        Ok(Some(ret))
+                    })
+                    .filter_map(swap_option_result)
+            })
+            .flatten_ok()
+            .map(flatten_err);
+        Ok(if eliminate_indices.is_empty() {
+            Box::new(it)
+        } else {
+            Box::new(it.map_ok(move |t| eliminate_from_tuple(t, &eliminate_indices)))
+        })
+    }
+
     fn point_lookup_join<'a>(
         &'a self,
         tx: &'a SessionTx<'_>,
@@ -1275,7 +1478,14 @@ impl StoredRA {
                     .map(|i| tuple[*i].clone())
                     .collect_vec();
                 let key = &prefix[0..key_len];
-                match self.storage.get(tx, key)? {
+                let found = if self.keys_only {
+                    self.storage
+                        .exists(tx, key)?
+                        .then(|| self.storage.pad_key_tuple(key.to_vec()))
+                } else {
+                    self.storage.get(tx, key)?
+                };
+                match found {
                     None => Ok(None),
                     Some(found) => {
                         for (lk, rk) in left_join_indices.iter().zip(right_join_indices.iter()) {
@@ -1330,7 +1540,20 @@ impl StoredRA {
             );
         }
 
-        let mut skip_range_check = false;
+        if let Some(points) = self.key_points(right_join_indices.len()) {
+            return self.key_points_join(
+                tx,
+                left_iter,
+                left_to_prefix_indices,
+                points,
+                eliminate_indices,
+            );
+        }
+
+        let (l_bound, u_bound) = self
+            .key_bounds(right_join_indices.len())
+            .unwrap_or_default();
+        let skip_range_check = l_bound.is_empty();
         // In some cases, maybe we can stop as soon as we get one result?
         let it = left_iter
             .map_ok(move |tuple| {
@@ -1340,37 +1563,25 @@ impl StoredRA {
                     .collect_vec();
                 let mut stack = vec![];
 
-                if !skip_range_check && !self.filters.is_empty() {
-                    let other_bindings = &self.bindings[right_join_indices.len()..];
-                    let (l_bound, u_bound) = match compute_bounds(&self.filters, other_bindings) {
-                        Ok(b) => b,
-                        _ => (vec![], vec![]),
-                    };
-                    if !l_bound.iter().all(|v| *v == DataValue::Null)
-                        || !u_bound.iter().all(|v| *v == DataValue::Bot)
-                    {
-                        return Left(
-                            self.storage
-                                .scan_bounded_prefix(tx, &prefix, &l_bound, &u_bound)
-                                .map(move |res_found| -> Result<Option<Tuple>> {
-                                    let found = res_found?;
-                                    for (p, span) in self.filters_bytecodes.iter() {
-                                        if !eval_bytecode_pred(p, &found, &mut stack, *span)? {
-                                            return Ok(None);
-                                        }
+                if !skip_range_check {
+                    return Left(
+                        self.scan_bounded_prefix(tx, &prefix, &l_bound, &u_bound)
+                            .map(move |res_found| -> Result<Option<Tuple>> {
+                                let found = res_found?;
+                                for (p, span) in self.filters_bytecodes.iter() {
+                                    if !eval_bytecode_pred(p, &found, &mut stack, *span)? {
+                                        return Ok(None);
                                     }
-                                    let mut ret = tuple.clone();
-                                    ret.extend(found);
-                                    Ok(Some(ret))
-                                })
-                                .filter_map(swap_option_result),
-                        );
-                    }
+                                }
+                                let mut ret = tuple.clone();
+                                ret.extend(found);
+                                Ok(Some(ret))
+                            })
+                            .filter_map(swap_option_result),
+                    );
                 }
-                skip_range_check = true;
                 Right(
-                    self.storage
-                        .scan_prefix(tx, &prefix)
+                    self.scan_prefix(tx, &prefix)
                         .map(move |res_found| -> Result<Option<Tuple>> {
                             let found = res_found?;
                             for (p, span) in self.filters_bytecodes.iter() {
@@ -1497,13 +1708,54 @@ impl StoredRA {
     }
 
     fn iter<'a>(&'a self, tx: &'a SessionTx<'_>) -> Result<TupleIter<'a>> {
-        let it = self.storage.scan_all(tx);
+        let it: TupleIter<'a> = if self.keys_only {
+            Box::new(self.storage.scan_all_keys(tx))
+        } else {
+            Box::new(self.storage.scan_all(tx))
+        };
         Ok(if self.filters.is_empty() {
-            Box::new(it)
+            it
         } else {
             Box::new(filter_iter(self.filters_bytecodes.clone(), it))
         })
     }
+
+    fn scan_prefix<'a>(&self, tx: &'a SessionTx<'_>, prefix: &Tuple) -> TupleIter<'a> {
+        if self.reverse {
+            let key_len = self.storage.metadata.keys.len();
+            let prefix = &prefix[..prefix.len().min(key_len)];
+            Box::new(
+                self.storage
+                    .scan_bounded_prefix_rev(tx, prefix, &[], &[], self.keys_only),
+            )
+        } else if self.keys_only {
+            Box::new(self.storage.scan_prefix_keys(tx, prefix))
+        } else {
+            Box::new(self.storage.scan_prefix(tx, prefix))
+        }
+    }
+
+    fn scan_bounded_prefix<'a>(
+        &self,
+        tx: &'a SessionTx<'_>,
+        prefix: &[DataValue],
+        lower: &[DataValue],
+        upper: &[DataValue],
+    ) -> TupleIter<'a> {
+        if self.reverse {
+            Box::new(
+                self.storage
+                    .scan_bounded_prefix_rev(tx, prefix, lower, upper, self.keys_only),
+            )
+        } else if self.keys_only {
+            Box::new(
+                self.storage
+                    .scan_bounded_prefix_keys(tx, prefix, lower, upper),
+            )
+        } else {
+            Box::new(self.storage.scan_bounded_prefix(tx, prefix, lower, upper))
+        }
+    }
 }
 
 fn join_is_prefix(right_join_indices: &[usize]) -> bool {
@@ -1823,13 +2075,14 @@ impl RelAlgebra {
         match self {
             RelAlgebra::Fixed(r) => r.do_eliminate_temp_vars(used),
             RelAlgebra::TempStore(_r) => Ok(()),
-            RelAlgebra::Stored(_v) => Ok(()),
+            RelAlgebra::Stored(v) => v.do_eliminate_temp_vars(used),
             RelAlgebra::StoredWithValidity(_v) => Ok(()),
             RelAlgebra::Join(r) => r.do_eliminate_temp_vars(used),
             RelAlgebra::Reorder(r) => r.relation.eliminate_temp_vars(used),
             RelAlgebra::Filter(r) => r.do_eliminate_temp_vars(used),
             RelAlgebra::NegJoin(r) => r.do_eliminate_temp_vars(used),
             RelAlgebra::Unification(r) => r.do_eliminate_temp_vars(used),
+            RelAlgebra::Series(_) => Ok(()),
             RelAlgebra::HnswSearch(_) => Ok(()),
             RelAlgebra::FtsSearch(_) => Ok(()),
             RelAlgebra::LshSearch(_) => Ok(()),
@@ -1847,6 +2100,7 @@ impl RelAlgebra {
             RelAlgebra::Filter(r) => Some(&r.to_eliminate),
             RelAlgebra::NegJoin(r) => Some(&r.to_eliminate),
             RelAlgebra::Unification(u) => Some(&u.to_eliminate),
+            RelAlgebra::Series(_) => None,
             RelAlgebra::HnswSearch(_) => None,
             RelAlgebra::FtsSearch(_) => None,
             RelAlgebra::LshSearch(_) => None,
@@ -1879,6 +2133,7 @@ impl RelAlgebra {
                 bindings.push(u.binding.clone());
                 bindings
             }
+            RelAlgebra::Series(s) => vec![s.binding.clone()],
             RelAlgebra::HnswSearch(s) => {
                 let mut bindings = s.parent.bindings_after_eliminate();
                 bindings.extend_from_slice(&s.own_bindings);
@@ -1896,11 +2151,29 @@ impl RelAlgebra {
             }
         }
     }
+    /// Identifies the operator in the metrics collected for a query.
+    pub(crate) fn metrics_key(&self) -> usize {
+        self as *const Self as usize
+    }
     pub(crate) fn iter<'a>(
         &'a self,
         tx: &'a SessionTx<'_>,
         delta_rule: Option<&MagicSymbol>,
         stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+    ) -> Result<TupleIter<'a>> {
+        match op_metrics(self.metrics_key()) {
+            None => self.unmetered_iter(tx, delta_rule, stores),
+            Some(metrics) => {
+                let it = metrics.time(|| self.unmetered_iter(tx, delta_rule, stores))?;
+                Ok(Metered::wrap(it, metrics, true))
+            }
+        }
+    }
+    fn unmetered_iter<'a>(
+        &'a self,
+        tx: &'a SessionTx<'_>,
+        delta_rule: Option<&MagicSymbol>,
+        stores: &'a BTreeMap<MagicSymbol, EpochStore>,
     ) -> Result<TupleIter<'a>> {
         match self {
             RelAlgebra::Fixed(f) => Ok(Box::new(f.data.iter().map(|t| Ok(t.clone())))),
@@ -1912,6 +2185,7 @@ impl RelAlgebra {
             RelAlgebra::Filter(r) => r.iter(tx, delta_rule, stores),
             RelAlgebra::NegJoin(r) => r.iter(tx, delta_rule, stores),
             RelAlgebra::Unification(r) => r.iter(tx, delta_rule, stores),
+            RelAlgebra::Series(r) => Ok(r.iter()),
             RelAlgebra::HnswSearch(r) => r.iter(tx, delta_rule, stores),
             RelAlgebra::FtsSearch(r) => r.iter(tx, delta_rule, stores),
             RelAlgebra::LshSearch(r) => r.iter(tx, delta_rule, stores),
@@ -2066,6 +2340,7 @@ impl InnerJoin {
     pub(crate) fn join_type(&self) -> &str {
         match &self.right {
             RelAlgebra::Fixed(f) => f.join_type(),
+            RelAlgebra::Series(_) => "series_join",
             RelAlgebra::TempStore(_) => {
                 let join_indices = self
                     .joiner
@@ -2144,6 +2419,21 @@ impl InnerJoin {
                     join_indices,
                     eliminate_indices,
                 )
+                .map(|it| self.metered_lookup(it))
+            }
+            RelAlgebra::Series(r) => {
+                let join_indices = self
+                    .joiner
+                    .join_indices(
+                        &self.left.bindings_after_eliminate(),
+                        &self.right.bindings_after_eliminate(),
+                    )
+                    .unwrap();
+                Ok(self.metered_lookup(r.join(
+                    self.left.iter(tx, delta_rule, stores)?,
+                    join_indices,
+                    eliminate_indices,
+                )))
             }
             RelAlgebra::TempStore(r) => {
                 let join_indices = self
@@ -2161,6 +2451,7 @@ impl InnerJoin {
                         delta_rule,
                         stores,
                     )
+                    .map(|it| self.metered_lookup(it))
                 } else {
                     self.materialized_join(tx, eliminate_indices, delta_rule, stores)
                 }
@@ -2180,6 +2471,7 @@ impl InnerJoin {
                         join_indices,
                         eliminate_indices,
                     )
+                    .map(|it| self.metered_lookup(it))
                 } else {
                     self.materialized_join(tx, eliminate_indices, delta_rule, stores)
                 }
@@ -2199,6 +2491,7 @@ impl InnerJoin {
                         join_indices,
                         eliminate_indices,
                     )
+                    .map(|it| self.metered_lookup(it))
                 } else {
                     self.materialized_join(tx, eliminate_indices, delta_rule, stores)
                 }
@@ -2219,6 +2512,14 @@ impl InnerJoin {
             }
         }
     }
+    /// Counts the rows the right side contributes when it is joined by lookups,
+    /// as its own iterator is then never run.
+    fn metered_lookup<'a>(&self, it: TupleIter<'a>) -> TupleIter<'a> {
+        match op_metrics(self.right.metrics_key()) {
+            None => it,
+            Some(metrics) => Metered::wrap(it, metrics, false),
+        }
+    }
     fn materialized_join<'a>(
         &'a self,
         tx: &'a SessionTx<'_>,
@@ -2263,6 +2564,9 @@ impl InnerJoin {
                             .iter()
                             .map(|i| tuple[*i].clone())
                             .collect_vec();
+                        tx.memory.hold(&stored_tuple, || {
+                            format!("the join holding [{}]", right_bindings.iter().join(", "))
+                        })?;
                         cache.insert(stored_tuple);
                     }
                     Err(e) => return Err(e),
@@ -2270,6 +2574,9 @@ impl InnerJoin {
             }
             cache.into_iter().collect_vec()
         };
+        if let Some(metrics) = op_metrics(self.right.metrics_key()) {
+            metrics.buffer(cached_data.len());
+        }
 
         let (prefix, right_idx) =
             build_mat_range_iter(&cached_data, &left_join_indices, &left_cache);
@@ -13,6 +13,7 @@ use miette::{bail, Diagnostic, Result};
 use thiserror::Error;
 
 use crate::data::program::{NormalFormAtom, NormalFormInlineRule};
+use crate::data::symb::Symbol;
 use crate::parse::SourceSpan;
 
 #[derive(Diagnostic, Debug, Error)]
@@ -35,9 +36,11 @@ impl NormalFormInlineRule {
         let mut seen_variables = BTreeSet::default();
         let mut round_1_collected = vec![];
         let mut pending = vec![];
+        let mut body = self.body;
+        drive_from_constrained_relation(&mut body)?;
 
         // first round: collect all unifications that are completely bounded
-        for atom in self.body {
+        for atom in body {
             match atom {
                 NormalFormAtom::Unification(u) => {
                     if u.is_const() {
@@ -65,6 +68,10 @@ impl NormalFormInlineRule {
                     }
                     round_1_collected.push(NormalFormAtom::Relation(v))
                 }
+                NormalFormAtom::Series(s) => {
+                    seen_variables.insert(s.binding.clone());
+                    round_1_collected.push(NormalFormAtom::Series(s))
+                }
                 NormalFormAtom::NegatedRule(r) => pending.push(NormalFormAtom::NegatedRule(r)),
                 NormalFormAtom::NegatedRelation(v) => {
                     pending.push(NormalFormAtom::NegatedRelation(v))
@@ -115,6 +122,10 @@ impl NormalFormInlineRule {
                     seen_variables.extend(v.args.iter().cloned());
                     collected.push(NormalFormAtom::Relation(v))
                 }
+                NormalFormAtom::Series(s) => {
+                    seen_variables.insert(s.binding.clone());
+                    collected.push(NormalFormAtom::Series(s))
+                }
                 NormalFormAtom::NegatedRule(_)
                 | NormalFormAtom::NegatedRelation(_)
                 | NormalFormAtom::Predicate(_) => {
@@ -139,7 +150,9 @@ impl NormalFormInlineRule {
             }
             for atom in last_pending.iter() {
                 match atom {
-                    NormalFormAtom::Rule(_) | NormalFormAtom::Relation(_) => unreachable!(),
+                    NormalFormAtom::Rule(_)
+                    | NormalFormAtom::Relation(_)
+                    | NormalFormAtom::Series(_) => unreachable!(),
                     NormalFormAtom::NegatedRule(r) => {
                         if r.args.iter().all(|a| seen_variables.contains(a)) {
                             collected.push(NormalFormAtom::NegatedRule(r.clone()));
@@ -199,7 +212,9 @@ impl NormalFormInlineRule {
         if !pending.is_empty() {
             for atom in pending {
                 match atom {
-                    NormalFormAtom::Rule(_) | NormalFormAtom::Relation(_) => unreachable!(),
+                    NormalFormAtom::Rule(_)
+                    | NormalFormAtom::Relation(_)
+                    | NormalFormAtom::Series(_) => unreachable!(),
                     NormalFormAtom::NegatedRule(r) => {
                         if r.args.iter().any(|a| seen_variables.contains(a)) {
                             collected.push(NormalFormAtom::NegatedRule(r.clone()));
@@ -240,3 +255,62 @@ impl NormalFormInlineRule {
         })
     }
 }
+
+/// Atoms are joined in the order they are written, but when the first one joined is not
+/// constrained at all, a stored relation filtered on its own columns further on is moved to
+/// its place, so that the filtered scan drives the joins instead of a full one. Of several
+/// such relations the one with the most filters is taken, the earliest of them on ties.
+fn drive_from_constrained_relation(body: &mut Vec<NormalFormAtom>) -> Result<()> {
+    let first = match body.iter().position(|atom| {
+        matches!(
+            atom,
+            NormalFormAtom::Rule(_) | NormalFormAtom::Relation(_) | NormalFormAtom::Series(_)
+        )
+    }) {
+        Some(i) => i,
+        None => return Ok(()),
+    };
+    let mut const_bound: BTreeSet<&Symbol> = BTreeSet::new();
+    let mut filtered_on = vec![];
+    for atom in body.iter() {
+        match atom {
+            NormalFormAtom::Unification(u) if u.is_const() => {
+                const_bound.insert(&u.binding);
+            }
+            NormalFormAtom::Predicate(p) => filtered_on.push(p.bindings()?),
+            _ => {}
+        }
+    }
+    let n_constraints = |args: &[Symbol]| {
+        let consts = args.iter().filter(|a| const_bound.contains(a)).count();
+        let filters = filtered_on
+            .iter()
+            .filter(|bindings| !bindings.is_empty() && bindings.iter().all(|b| args.contains(b)))
+            .count();
+        consts + filters
+    };
+    let first_constraints = match &body[first] {
+        NormalFormAtom::Rule(r) => n_constraints(&r.args),
+        NormalFormAtom::Relation(r) => n_constraints(&r.args),
+        _ => 1,
+    };
+    if first_constraints > 0 {
+        return Ok(());
+    }
+    let mut driver = None;
+    let mut most = 0;
+    for (i, atom) in body.iter().enumerate().skip(first + 1) {
+        if let NormalFormAtom::Relation(r) = atom {
+            let n = n_constraints(&r.args);
+            if n > most {
+                most = n;
+                driver = Some(i);
+            }
+        }
+    }
+    if let Some(i) = driver {
+        let atom = body.remove(i);
+        body.insert(first, atom);
+    }
+    Ok(())
+}
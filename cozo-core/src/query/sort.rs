@@ -12,18 +12,26 @@ use std::collections::BTreeMap;
 use itertools::Itertools;
 use miette::Result;
 
+use crate::data::expr::Expr;
+use crate::data::functions::{OP_GE, OP_GT, OP_LE, OP_LIST, OP_LT};
 use crate::data::program::SortDir;
 use crate::data::symb::Symbol;
 use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::query::compile::{CompiledProgram, CompiledRuleSet};
+use crate::query::ra::{RelAlgebra, StoredRA};
 use crate::runtime::temp_store::EpochStore;
 use crate::runtime::transact::SessionTx;
 
 impl<'a> SessionTx<'a> {
+    /// Sorts the rows by `sorters`. Rows equal on all the sorters come in the ascending order
+    /// of the whole rows, so that the result does not depend on how the rows were produced.
     pub(crate) fn sort_and_collect(
         &mut self,
         original: EpochStore,
         sorters: &[(Symbol, SortDir)],
         head: &[Symbol],
+        after: Option<&[DataValue]>,
     ) -> Result<Vec<Tuple>> {
         let head_indices: BTreeMap<_, _> = head.iter().enumerate().map(|(i, k)| (k, i)).collect();
         let idx_sorters = sorters
@@ -31,22 +39,167 @@ impl<'a> SessionTx<'a> {
             .map(|(k, dir)| (head_indices[k], *dir))
             .collect_vec();
 
-        let mut all_data: Vec<_> = original.all_iter().map(|v| v.into_tuple()).collect_vec();
-        all_data.sort_by(|a, b| {
-            for (idx, dir) in &idx_sorters {
-                match a[*idx].cmp(&b[*idx]) {
-                    Ordering::Equal => {}
-                    o => {
-                        return match dir {
-                            SortDir::Asc => o,
-                            SortDir::Dsc => o.reverse(),
-                        }
-                    }
+        let mut all_data = vec![];
+        for row in original.all_iter() {
+            let row = row.into_tuple();
+            self.memory
+                .hold(&row, || "sorting with ':order'".to_string())?;
+            all_data.push(row);
+        }
+        sort_rows(&mut all_data, &idx_sorters);
+
+        // resume strictly after the cursor
+        if let Some(after) = after {
+            let start = all_data.partition_point(|row| {
+                cmp_in_sort_order(
+                    idx_sorters
+                        .iter()
+                        .zip(after)
+                        .map(|((idx, dir), val)| (&row[*idx], val, *dir)),
+                ) != Ordering::Greater
+            });
+            all_data.drain(..start);
+        }
+
+        Ok(all_data)
+    }
+}
+
+/// Sorts `rows` by the columns at the indices in `idx_sorters`. Rows equal on all of them
+/// are sorted by the whole rows.
+pub(crate) fn sort_rows(rows: &mut [Tuple], idx_sorters: &[(usize, SortDir)]) {
+    rows.sort_by(|a, b| {
+        cmp_in_sort_order(
+            idx_sorters
+                .iter()
+                .map(|(idx, dir)| (&a[*idx], &b[*idx], *dir)),
+        )
+        .then_with(|| a.cmp(b))
+    });
+}
+
+fn cmp_in_sort_order<'a>(
+    pairs: impl Iterator<Item = (&'a DataValue, &'a DataValue, SortDir)>,
+) -> Ordering {
+    for (a, b, dir) in pairs {
+        match a.cmp(b) {
+            Ordering::Equal => {}
+            o => {
+                return match dir {
+                    SortDir::Asc => o,
+                    SortDir::Dsc => o.reverse(),
                 }
             }
-            Ordering::Equal
-        });
+        }
+    }
+    Ordering::Equal
+}
 
-        Ok(all_data)
+/// Lets the scan in the entry rule produce rows in the order given by `sorters`, so that
+/// evaluation can stop as soon as enough rows are produced instead of sorting all of them.
+/// This is only done if the entry rule does nothing but scan a single stored relation, and
+/// the sorters are on all of its key columns in key order and in the same direction.
+/// If `after` is given, the scan also seeks past this key.
+/// Returns whether the scan now produces rows in sorted order.
+pub(crate) fn scan_in_sort_order(
+    strata: &mut [CompiledProgram],
+    sorters: &[(Symbol, SortDir)],
+    after: Option<&[DataValue]>,
+) -> Result<bool> {
+    let dir = match sorters.first() {
+        Some((_, dir)) => *dir,
+        None => return Ok(false),
+    };
+    if sorters.iter().any(|(_, d)| *d != dir) {
+        return Ok(false);
+    }
+    let stored = match entry_stored_scan(strata) {
+        Some(stored) => stored,
+        None => return Ok(false),
+    };
+    let key_len = stored.storage.metadata.keys.len();
+    if sorters.len() != key_len
+        || !sorters
+            .iter()
+            .zip(stored.bindings.iter())
+            .all(|((symb, _), binding)| symb == binding)
+    {
+        return Ok(false);
+    }
+    stored.reverse = dir == SortDir::Dsc;
+    if let Some(after) = after {
+        let span = stored.span;
+        let keys = Expr::Apply {
+            op: &OP_LIST,
+            args: stored.bindings[..key_len]
+                .iter()
+                .map(|b| Expr::Binding {
+                    var: b.clone(),
+                    tuple_pos: None,
+                })
+                .collect(),
+            span,
+        };
+        let cursor = Expr::Const {
+            val: DataValue::List(after.to_vec()),
+            span,
+        };
+        let (past_cursor, seek) = match dir {
+            SortDir::Asc => (&OP_GT, &OP_GE),
+            SortDir::Dsc => (&OP_LT, &OP_LE),
+        };
+        // comparing whole key tuples is exact, but only a bound on the first key column
+        // lets the scan seek, so that one is added too; it is implied by the exact one
+        stored.add_filter(Expr::Apply {
+            op: past_cursor,
+            args: [keys, cursor].into(),
+            span,
+        })?;
+        stored.add_filter(Expr::Apply {
+            op: seek,
+            args: [
+                Expr::Binding {
+                    var: stored.bindings[0].clone(),
+                    tuple_pos: None,
+                },
+                Expr::Const {
+                    val: after[0].clone(),
+                    span,
+                },
+            ]
+            .into(),
+            span,
+        })?;
+    }
+    Ok(true)
+}
+
+fn entry_stored_scan(strata: &mut [CompiledProgram]) -> Option<&mut StoredRA> {
+    let rule_set = strata
+        .iter_mut()
+        .find_map(|stratum| stratum.iter_mut().find(|(k, _)| k.is_prog_entry()))?
+        .1;
+    let rule = match rule_set {
+        CompiledRuleSet::Rules(rules) if rules.len() == 1 => &mut rules[0],
+        _ => return None,
+    };
+    if rule.aggr.iter().any(|a| a.is_some()) {
+        return None;
+    }
+    // reordering columns, filtering and binding new variables all keep the order of rows
+    let mut relation = &mut rule.relation;
+    loop {
+        relation = match relation {
+            RelAlgebra::Reorder(r) => &mut r.relation,
+            RelAlgebra::Filter(r) => &mut r.parent,
+            RelAlgebra::Unification(r) => &mut r.parent,
+            RelAlgebra::Join(join) if join.left.is_unit() && join.joiner.left_keys.is_empty() => {
+                return match &mut join.right {
+                    RelAlgebra::Stored(stored) => Some(stored),
+                    _ => None,
+                };
+            }
+            _ => return None,
+        }
     }
 }
@@ -10,12 +10,12 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
 
 use itertools::Itertools;
-use miette::{bail, Diagnostic, IntoDiagnostic, Result, WrapErr};
+use miette::{bail, ensure, Diagnostic, IntoDiagnostic, Report, Result, WrapErr};
 use pest::Parser;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use crate::data::expr::{Bytecode, Expr};
+use crate::data::expr::{eval_bytecode, Bytecode, Expr};
 use crate::data::program::{FixedRuleApply, InputInlineRulesOrFixed, InputProgram, RelationOp};
 use crate::data::relation::{ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::Symbol;
@@ -25,16 +25,26 @@ use crate::fixed_rule::utilities::constant::Constant;
 use crate::fixed_rule::FixedRuleHandle;
 use crate::fts::tokenizer::TextAnalyzer;
 use crate::parse::expr::build_expr;
+use crate::parse::schema::BindingExprs;
 use crate::parse::{parse_script, CozoScriptParser, Rule};
 use crate::runtime::callback::{CallbackCollector, CallbackOp};
 use crate::runtime::minhash_lsh::HashPermutations;
 use crate::runtime::relation::{
-    extend_tuple_from_v, AccessLevel, InputRelationHandle, InsufficientAccessLevel, RelationHandle,
+    extend_tuple_from_v, is_expired, AccessLevel, InputRelationHandle, InsufficientAccessLevel,
+    OnConflict, RelationHandle,
 };
 use crate::runtime::transact::SessionTx;
 use crate::storage::Storage;
 use crate::{Db, NamedRows, SourceSpan, StoreTx};
 
+/// Triggers may write to relations with triggers of their own, which fire in turn
+/// up to this depth.
+pub(crate) const MAX_TRIGGER_DEPTH: usize = 16;
+
+/// The most expired rows `::expire` removes at once, in a transaction of their own
+/// unless it is run in a script with others
+pub(crate) const EXPIRE_BATCH_SIZE: usize = 1000;
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("attempting to write into relation {0} of arity {1} with data of arity {2}")]
 #[diagnostic(code(eval::relation_arity_mismatch))]
@@ -57,7 +67,7 @@ impl<'a> SessionTx<'a> {
         let mut to_clear = vec![];
         let mut replaced_old_triggers = None;
         if op == RelationOp::Replace {
-            if !propagate_triggers {
+            if !propagate_triggers || self.trigger_depth > 0 {
                 #[derive(Debug, Error, Diagnostic)]
                 #[error("replace op in trigger is not allowed: {0}")]
                 #[diagnostic(code(eval::replace_in_trigger))]
@@ -65,6 +75,8 @@ impl<'a> SessionTx<'a> {
                 bail!(ReplaceInTrigger(meta.name.to_string()))
             }
             if let Ok(old_handle) = self.get_relation(&meta.name, true) {
+                old_handle.ensure_not_view("replace")?;
+                old_handle.ensure_no_views("replace")?;
                 if !old_handle.indices.is_empty() {
                     #[derive(Debug, Error, Diagnostic)]
                     #[error("cannot replace relation {0} since it has indices")]
@@ -82,7 +94,7 @@ impl<'a> SessionTx<'a> {
                 if old_handle.has_triggers() {
                     replaced_old_triggers = Some((old_handle.put_triggers, old_handle.rm_triggers))
                 }
-                for trigger in &old_handle.replace_triggers {
+                for (i, trigger) in old_handle.replace_triggers.iter().enumerate() {
                     let program = parse_script(
                         trigger,
                         &Default::default(),
@@ -91,22 +103,17 @@ impl<'a> SessionTx<'a> {
                     )?
                     .get_single_program()?;
 
-                    let (_, cleanups) = db
-                        .run_query(
-                            self,
-                            program,
-                            cur_vld,
-                            callback_targets,
-                            callback_collector,
-                            false,
-                        )
-                        .map_err(|err| {
-                            if err.source_code().is_some() {
-                                err
-                            } else {
-                                err.with_source_code(format!("{trigger}"))
-                            }
-                        })?;
+                    let cleanups = self.run_trigger(
+                        db,
+                        &old_handle.name,
+                        "replace",
+                        i,
+                        trigger,
+                        program,
+                        cur_vld,
+                        callback_targets,
+                        callback_collector,
+                    )?;
                     to_clear.extend(cleanups);
                 }
                 let destroy_res = self.destroy_relation(&meta.name)?;
@@ -120,6 +127,9 @@ impl<'a> SessionTx<'a> {
         } else {
             self.get_relation(&meta.name, false)?
         };
+        if !matches!(op, RelationOp::Ensure | RelationOp::EnsureNot) {
+            relation_store.ensure_not_view("write to")?;
+        }
         if let Some((old_put, old_retract)) = replaced_old_triggers {
             relation_store.put_triggers = old_put;
             relation_store.rm_triggers = old_retract;
@@ -128,6 +138,8 @@ impl<'a> SessionTx<'a> {
             metadata,
             key_bindings,
             dep_bindings,
+            binding_exprs,
+            on_conflict,
             span,
             ..
         } = meta;
@@ -145,6 +157,7 @@ impl<'a> SessionTx<'a> {
                 &relation_store,
                 metadata,
                 key_bindings,
+                binding_exprs,
                 op == RelationOp::Delete,
                 force_collect,
                 *span,
@@ -156,6 +169,7 @@ impl<'a> SessionTx<'a> {
                 &relation_store,
                 metadata,
                 key_bindings,
+                binding_exprs,
                 *span,
             )?,
             RelationOp::EnsureNot => self.ensure_not_in_relation(
@@ -165,9 +179,10 @@ impl<'a> SessionTx<'a> {
                 &relation_store,
                 metadata,
                 key_bindings,
+                binding_exprs,
                 *span,
             )?,
-            RelationOp::Update => self.update_in_relation(
+            RelationOp::Update | RelationOp::Upsert => self.update_in_relation(
                 db,
                 res_iter,
                 headers,
@@ -179,6 +194,8 @@ impl<'a> SessionTx<'a> {
                 &relation_store,
                 metadata,
                 key_bindings,
+                binding_exprs,
+                op == RelationOp::Upsert,
                 force_collect,
                 *span,
             )?,
@@ -196,7 +213,9 @@ impl<'a> SessionTx<'a> {
                     metadata,
                     key_bindings,
                     dep_bindings,
+                    binding_exprs,
                     op == RelationOp::Insert,
+                    on_conflict,
                     force_collect,
                     *span,
                 )?,
@@ -205,6 +224,76 @@ impl<'a> SessionTx<'a> {
         Ok(to_clear)
     }
 
+    /// Physically removes the expired rows of a relation with a TTL, together with
+    /// their index entries, at most [`EXPIRE_BATCH_SIZE`] of them with keys from `from` on.
+    /// Returns the key to continue from if there may be more of them. Triggers are not run,
+    /// as the rows are already gone for queries.
+    pub(crate) fn expire_rows<'s, S: Storage<'s>>(
+        &mut self,
+        db: &Db<S>,
+        rel: &Symbol,
+        cur_vld: ValidityTs,
+        from: &[DataValue],
+    ) -> Result<Option<Tuple>> {
+        let relation_store = self.get_relation(rel, true)?;
+        if relation_store.ttl.is_none() {
+            bail!(
+                "Stored relation `{}` does not have a TTL",
+                relation_store.name
+            );
+        }
+        let n_keys = relation_store.metadata.keys.len();
+        let mut raw = relation_store.clone();
+        raw.ttl = None;
+        let mut expired = vec![];
+        for tuple in raw.scan_bounded_prefix(self, &[], from, &[]) {
+            let mut tuple = tuple?;
+            if is_expired(tuple.last(), self.now) {
+                tuple.truncate(n_keys);
+                expired.push(tuple);
+                if expired.len() == EXPIRE_BATCH_SIZE {
+                    break;
+                }
+            }
+        }
+        if expired.is_empty() {
+            return Ok(None);
+        }
+        let next = (expired.len() == EXPIRE_BATCH_SIZE).then(|| expired.last().unwrap().clone());
+
+        let key_bindings = relation_store
+            .metadata
+            .keys
+            .iter()
+            .map(|col| Symbol::new(col.name.clone(), rel.span))
+            .collect_vec();
+        let meta = InputRelationHandle {
+            name: Symbol::new(relation_store.name.clone(), rel.span),
+            metadata: StoredRelationMetadata {
+                keys: relation_store.metadata.keys.clone(),
+                non_keys: vec![],
+            },
+            key_bindings: key_bindings.clone(),
+            dep_bindings: vec![],
+            binding_exprs: Default::default(),
+            on_conflict: Default::default(),
+            span: rel.span,
+        };
+        self.execute_relation(
+            db,
+            expired.into_iter(),
+            RelationOp::Rm,
+            &meta,
+            &key_bindings,
+            cur_vld,
+            &Default::default(),
+            &mut Default::default(),
+            false,
+            "",
+        )?;
+        Ok(next)
+    }
+
     fn put_into_relation<'s, S: Storage<'s>>(
         &mut self,
         db: &Db<S>,
@@ -219,7 +308,9 @@ impl<'a> SessionTx<'a> {
         metadata: &StoredRelationMetadata,
         key_bindings: &[Symbol],
         dep_bindings: &[Symbol],
+        binding_exprs: &BindingExprs,
         is_insert: bool,
+        on_conflict: &OnConflict,
         force_collect: &str,
         span: SourceSpan,
     ) -> Result<()> {
@@ -238,12 +329,14 @@ impl<'a> SessionTx<'a> {
             &relation_store.metadata.keys,
             &metadata.keys,
             key_bindings,
+            binding_exprs,
             headers,
         )?;
 
         let need_to_collect = !force_collect.is_empty()
             || (!relation_store.is_temp
                 && (is_callback_target
+                    || !relation_store.views.is_empty()
                     || (propagate_triggers && !relation_store.put_triggers.is_empty())));
         let has_indices = !relation_store.indices.is_empty();
         let has_hnsw_indices = !relation_store.hnsw_indices.is_empty();
@@ -257,6 +350,7 @@ impl<'a> SessionTx<'a> {
                 &relation_store.metadata.non_keys,
                 &metadata.keys,
                 key_bindings,
+                binding_exprs,
                 headers,
             )?
         } else {
@@ -264,36 +358,79 @@ impl<'a> SessionTx<'a> {
                 &relation_store.metadata.non_keys,
                 &metadata.non_keys,
                 dep_bindings,
+                binding_exprs,
                 headers,
             )?
         };
         key_extractors.extend(val_extractors);
+        let conflict_updaters = match on_conflict {
+            OnConflict::Update(assignments) => {
+                make_conflict_updaters(relation_store, assignments, headers)?
+            }
+            _ => vec![],
+        };
         let mut stack = vec![];
         let hnsw_filters = Self::make_hnsw_filters(relation_store)?;
         let fts_lsh_processors = self.make_fts_lsh_processors(relation_store)?;
         let lsh_perms = self.make_lsh_hash_perms(relation_store);
+        let mut outcomes = if is_insert && force_collect == relation_store.name {
+            Some(vec![])
+        } else {
+            None
+        };
 
         for tuple in res_iter {
-            let extracted: Vec<DataValue> = key_extractors
+            let mut extracted: Vec<DataValue> = key_extractors
                 .iter()
                 .map(|ex| ex.extract_data(&tuple, cur_vld))
                 .try_collect()?;
 
             let key = relation_store.encode_key_for_store(&extracted, span)?;
+            let mut replaced = None;
 
             if is_insert {
-                let already_exists = if relation_store.is_temp {
-                    self.temp_store_tx.exists(&key, true)?
-                } else {
-                    self.store_tx.exists(&key, true)?
-                };
-
-                if already_exists {
-                    bail!(TransactAssertionFailure {
-                        relation: relation_store.name.to_string(),
-                        key: extracted,
-                        notice: "key exists in database".to_string()
-                    });
+                let existing = relation_store.get_stored_val(self, &key, true)?;
+
+                if let Some(existing) = existing {
+                    match on_conflict {
+                        OnConflict::Error => {
+                            bail!(TransactAssertionFailure {
+                                relation: relation_store.name.to_string(),
+                                key: extracted,
+                                notice: "key exists in database".to_string()
+                            });
+                        }
+                        OnConflict::Ignore => {
+                            if let Some(outcomes) = &mut outcomes {
+                                outcomes.push(("skipped", extracted));
+                            }
+                            continue;
+                        }
+                        OnConflict::Update(_) => {
+                            extracted.truncate(relation_store.metadata.keys.len());
+                            extend_tuple_from_v(&mut extracted, &existing);
+                            replaced = Some(extracted.clone());
+                            // the expressions see the incoming row followed by the stored one
+                            let mut combined = tuple.clone();
+                            combined.extend_from_slice(&extracted);
+                            for (i, updater) in &conflict_updaters {
+                                extracted[*i] = updater.extract_data(&combined, cur_vld)?;
+                            }
+                        }
+                    }
+                }
+            }
+            // writing a row refreshes its expiry
+            if let Some(ttl) = relation_store.ttl {
+                *extracted.last_mut().unwrap() = DataValue::from(self.now + ttl);
+            }
+            if let Some(outcomes) = &mut outcomes {
+                match replaced {
+                    None => outcomes.push(("inserted", extracted.clone())),
+                    Some(old) => {
+                        outcomes.push(("updated", extracted.clone()));
+                        outcomes.push(("replaced", old));
+                    }
                 }
             }
 
@@ -305,7 +442,11 @@ impl<'a> SessionTx<'a> {
                 || has_fts_indices
                 || has_lsh_indices
             {
-                if let Some(existing) = self.store_tx.get(&key, false)? {
+                let existing = match self.store_tx.get(&key, false)? {
+                    Some(v) if !relation_store.is_tombstone_val(&key, &v)? => Some(v),
+                    _ => None,
+                };
+                if let Some(existing) = existing {
                     let mut tup = extracted[0..relation_store.metadata.keys.len()].to_vec();
                     extend_tuple_from_v(&mut tup, &existing);
                     if has_indices && extracted != tup {
@@ -314,19 +455,13 @@ impl<'a> SessionTx<'a> {
                         self.del_in_lsh(relation_store, &tup)?;
                     }
 
-                    if need_to_collect {
+                    if need_to_collect
+                        && !relation_store.is_expired_val(&key, &existing, self.now)?
+                    {
                         old_tuples.push(DataValue::List(tup));
                     }
                 } else if has_indices {
-                    for (idx_rel, extractor) in relation_store.indices.values() {
-                        let idx_tup_new = extractor
-                            .iter()
-                            .map(|i| extracted[*i].clone())
-                            .collect_vec();
-                        let encoded_new =
-                            idx_rel.encode_key_for_store(&idx_tup_new, Default::default())?;
-                        self.store_tx.put(&encoded_new, &[])?;
-                    }
+                    self.put_in_index(relation_store, &extracted)?;
                 }
 
                 self.update_in_hnsw(relation_store, &mut stack, &hnsw_filters, &extracted)?;
@@ -351,6 +486,10 @@ impl<'a> SessionTx<'a> {
             }
         }
 
+        if let Some(outcomes) = outcomes {
+            self.insert_outcomes = Some(outcomes);
+        }
+
         if need_to_collect && !new_tuples.is_empty() {
             self.collect_mutations(
                 db,
@@ -530,6 +669,8 @@ impl<'a> SessionTx<'a> {
         relation_store: &RelationHandle,
         metadata: &StoredRelationMetadata,
         key_bindings: &[Symbol],
+        binding_exprs: &BindingExprs,
+        is_upsert: bool,
         force_collect: &str,
         span: SourceSpan,
     ) -> Result<()> {
@@ -548,12 +689,14 @@ impl<'a> SessionTx<'a> {
             &relation_store.metadata.keys,
             &metadata.keys,
             key_bindings,
+            binding_exprs,
             headers,
         )?;
 
         let need_to_collect = !force_collect.is_empty()
             || (!relation_store.is_temp
                 && (is_callback_target
+                    || !relation_store.views.is_empty()
                     || (propagate_triggers && !relation_store.put_triggers.is_empty())));
         let has_indices = !relation_store.indices.is_empty();
         let has_hnsw_indices = !relation_store.hnsw_indices.is_empty();
@@ -566,8 +709,20 @@ impl<'a> SessionTx<'a> {
             &relation_store.metadata.non_keys,
             &metadata.keys,
             key_bindings,
+            binding_exprs,
             headers,
         )?;
+        // used by upserts to fill in columns not given for keys not yet stored
+        let default_extractors = relation_store
+            .metadata
+            .non_keys
+            .iter()
+            .map(|col| {
+                col.default_gen
+                    .as_ref()
+                    .map(|expr| DataExtractor::DefaultExtractor(expr.clone(), col.typing.clone()))
+            })
+            .collect_vec();
 
         let mut stack = vec![];
         let hnsw_filters = Self::make_hnsw_filters(relation_store)?;
@@ -581,12 +736,9 @@ impl<'a> SessionTx<'a> {
                 .try_collect()?;
 
             let key = relation_store.encode_key_for_store(&new_kv, span)?;
-            let original_val_bytes = if relation_store.is_temp {
-                self.temp_store_tx.get(&key, true)?
-            } else {
-                self.store_tx.get(&key, true)?
-            };
-            let original_val: Tuple = match original_val_bytes {
+            let original_val_bytes = relation_store.get_stored_val(self, &key, true)?;
+            let original_val: Option<Tuple> = match original_val_bytes {
+                None if is_upsert => None,
                 None => {
                     bail!(TransactAssertionFailure {
                         relation: relation_store.name.to_string(),
@@ -594,23 +746,56 @@ impl<'a> SessionTx<'a> {
                         notice: "key to update does not exist".to_string()
                     })
                 }
-                Some(v) => rmp_serde::from_slice(&v[ENCODED_KEY_MIN_LEN..]).unwrap(),
+                Some(v) => Some(rmp_serde::from_slice(&v[ENCODED_KEY_MIN_LEN..]).unwrap()),
+            };
+            let old_kv = original_val.as_ref().map(|original_val| {
+                let mut old_kv = Vec::with_capacity(relation_store.arity());
+                old_kv.extend_from_slice(&new_kv);
+                old_kv.extend_from_slice(original_val);
+                old_kv
+            });
+            // an expired row is absent for the update, but its index entries are still there
+            let expired_kv = match (&old_kv, relation_store.ttl) {
+                (None, Some(_)) => self.store_tx.get(&key, false)?.map(|existing| {
+                    let mut tup = new_kv.clone();
+                    extend_tuple_from_v(&mut tup, &existing);
+                    tup
+                }),
+                _ => None,
             };
-            let mut old_kv = Vec::with_capacity(relation_store.arity());
-            old_kv.extend_from_slice(&new_kv);
-            old_kv.extend_from_slice(&original_val);
             new_kv.reserve_exact(relation_store.arity());
             for (i, extractor) in val_extractors.iter().enumerate() {
-                match extractor {
-                    None => {
-                        new_kv.push(original_val[i].clone());
-                    }
-                    Some(ex) => {
+                match (extractor, &original_val) {
+                    (Some(ex), _) => {
                         let val = ex.extract_data(&tuple, cur_vld)?;
                         new_kv.push(val);
                     }
+                    (None, Some(original_val)) => {
+                        new_kv.push(original_val[i].clone());
+                    }
+                    (None, None) => match &default_extractors[i] {
+                        Some(ex) => {
+                            let val = ex.extract_data(&tuple, cur_vld)?;
+                            new_kv.push(val);
+                        }
+                        None => {
+                            let notice = format!(
+                                "key to upsert does not exist and column {} is not provided",
+                                relation_store.metadata.non_keys[i].name
+                            );
+                            new_kv.truncate(relation_store.metadata.keys.len());
+                            bail!(TransactAssertionFailure {
+                                relation: relation_store.name.to_string(),
+                                key: new_kv,
+                                notice
+                            })
+                        }
+                    },
                 }
             }
+            if let Some(ttl) = relation_store.ttl {
+                *new_kv.last_mut().unwrap() = DataValue::from(self.now + ttl);
+            }
             let new_val = relation_store.encode_val_for_store(&new_kv, span)?;
 
             if need_to_collect
@@ -619,12 +804,20 @@ impl<'a> SessionTx<'a> {
                 || has_fts_indices
                 || has_lsh_indices
             {
-                self.del_in_fts(relation_store, &mut stack, &fts_lsh_processors, &old_kv)?;
-                self.del_in_lsh(relation_store, &old_kv)?;
-                self.update_in_index(relation_store, &new_kv, &old_kv)?;
+                if let Some(old_kv) = old_kv {
+                    self.del_in_fts(relation_store, &mut stack, &fts_lsh_processors, &old_kv)?;
+                    self.del_in_lsh(relation_store, &old_kv)?;
+                    self.update_in_index(relation_store, &new_kv, &old_kv)?;
 
-                if need_to_collect {
-                    old_tuples.push(DataValue::List(old_kv));
+                    if need_to_collect {
+                        old_tuples.push(DataValue::List(old_kv));
+                    }
+                } else if let Some(expired_kv) = expired_kv {
+                    self.del_in_fts(relation_store, &mut stack, &fts_lsh_processors, &expired_kv)?;
+                    self.del_in_lsh(relation_store, &expired_kv)?;
+                    self.update_in_index(relation_store, &new_kv, &expired_kv)?;
+                } else {
+                    self.put_in_index(relation_store, &new_kv)?;
                 }
 
                 self.update_in_hnsw(relation_store, &mut stack, &hnsw_filters, &new_kv)?;
@@ -666,6 +859,57 @@ impl<'a> SessionTx<'a> {
         Ok(())
     }
 
+    /// Runs a trigger of a relation in the transaction of the write firing it. The writes
+    /// of the trigger fire the triggers of the relations written in turn.
+    fn run_trigger<'s, S: Storage<'s>>(
+        &mut self,
+        db: &Db<S>,
+        relation: &str,
+        event: &str,
+        index: usize,
+        trigger: &str,
+        program: InputProgram,
+        cur_vld: ValidityTs,
+        callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+        callback_collector: &mut CallbackCollector,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Triggers firing triggers nested more than {0} deep, at stored relation '{1}'")]
+        #[diagnostic(code(eval::trigger_depth_exceeded))]
+        #[diagnostic(help("Triggers writing to each other in a cycle never stop"))]
+        struct TriggerDepthExceeded(usize, String);
+
+        ensure!(
+            self.trigger_depth < MAX_TRIGGER_DEPTH,
+            TriggerDepthExceeded(MAX_TRIGGER_DEPTH, relation.to_string())
+        );
+        self.trigger_depth += 1;
+        let res = db.run_query(
+            self,
+            program,
+            cur_vld,
+            callback_targets,
+            callback_collector,
+            true,
+        );
+        self.trigger_depth -= 1;
+        let (_, cleanups) = res
+            .map_err(|err| {
+                if err.source_code().is_some() {
+                    err
+                } else {
+                    err.with_source_code(format!("{trigger} "))
+                }
+            })
+            .wrap_err_with(|| {
+                format!(
+                    "in {event} trigger #{} of stored relation '{relation}'",
+                    index + 1
+                )
+            })?;
+        Ok(cleanups)
+    }
+
     fn collect_mutations<'s, S: Storage<'s>>(
         &mut self,
         db: &Db<S>,
@@ -693,8 +937,11 @@ impl<'a> SessionTx<'a> {
         bindings.extend(v_bindings);
 
         let kv_bindings = bindings;
+        if !relation_store.views.is_empty() {
+            self.maintain_views(db, relation_store, &new_tuples, &old_tuples, cur_vld)?;
+        }
         if propagate_triggers {
-            for trigger in &relation_store.put_triggers {
+            for (i, trigger) in relation_store.put_triggers.iter().enumerate() {
                 let mut program = parse_script(
                     trigger,
                     &Default::default(),
@@ -716,22 +963,17 @@ impl<'a> SessionTx<'a> {
                     old_tuples.to_vec(),
                 );
 
-                let (_, cleanups) = db
-                    .run_query(
-                        self,
-                        program,
-                        cur_vld,
-                        callback_targets,
-                        callback_collector,
-                        false,
-                    )
-                    .map_err(|err| {
-                        if err.source_code().is_some() {
-                            err
-                        } else {
-                            err.with_source_code(format!("{trigger} "))
-                        }
-                    })?;
+                let cleanups = self.run_trigger(
+                    db,
+                    &relation_store.name,
+                    "put",
+                    i,
+                    trigger,
+                    program,
+                    cur_vld,
+                    callback_targets,
+                    callback_collector,
+                )?;
                 to_clear.extend(cleanups);
             }
         }
@@ -795,6 +1037,22 @@ impl<'a> SessionTx<'a> {
         Ok(())
     }
 
+    fn put_in_index(
+        &mut self,
+        relation_store: &RelationHandle,
+        new_kv: &[DataValue],
+    ) -> Result<()> {
+        for (idx_rel, idx_extractor) in relation_store.indices.values() {
+            let idx_tup_new = idx_extractor
+                .iter()
+                .map(|i| new_kv[*i].clone())
+                .collect_vec();
+            let encoded_new = idx_rel.encode_key_for_store(&idx_tup_new, Default::default())?;
+            self.store_tx.put(&encoded_new, &[])?;
+        }
+        Ok(())
+    }
+
     fn ensure_not_in_relation(
         &mut self,
         res_iter: impl Iterator<Item = Tuple>,
@@ -803,6 +1061,7 @@ impl<'a> SessionTx<'a> {
         relation_store: &RelationHandle,
         metadata: &StoredRelationMetadata,
         key_bindings: &[Symbol],
+        binding_exprs: &BindingExprs,
         span: SourceSpan,
     ) -> Result<()> {
         if relation_store.access_level < AccessLevel::ReadOnly {
@@ -817,6 +1076,7 @@ impl<'a> SessionTx<'a> {
             &relation_store.metadata.keys,
             &metadata.keys,
             key_bindings,
+            binding_exprs,
             headers,
         )?;
 
@@ -826,7 +1086,9 @@ impl<'a> SessionTx<'a> {
                 .map(|ex| ex.extract_data(&tuple, cur_vld))
                 .try_collect()?;
             let key = relation_store.encode_key_for_store(&extracted, span)?;
-            let already_exists = if relation_store.is_temp {
+            let already_exists = if relation_store.soft_delete {
+                relation_store.get_stored_val(self, &key, true)?.is_some()
+            } else if relation_store.is_temp {
                 self.temp_store_tx.exists(&key, true)?
             } else {
                 self.store_tx.exists(&key, true)?
@@ -850,6 +1112,7 @@ impl<'a> SessionTx<'a> {
         relation_store: &RelationHandle,
         metadata: &StoredRelationMetadata,
         key_bindings: &[Symbol],
+        binding_exprs: &BindingExprs,
         span: SourceSpan,
     ) -> Result<()> {
         if relation_store.access_level < AccessLevel::ReadOnly {
@@ -864,6 +1127,7 @@ impl<'a> SessionTx<'a> {
             &relation_store.metadata.keys,
             &metadata.keys,
             key_bindings,
+            binding_exprs,
             headers,
         )?;
 
@@ -871,6 +1135,7 @@ impl<'a> SessionTx<'a> {
             &relation_store.metadata.non_keys,
             &metadata.keys,
             key_bindings,
+            binding_exprs,
             headers,
         )?;
         key_extractors.extend(val_extractors);
@@ -884,11 +1149,7 @@ impl<'a> SessionTx<'a> {
             let key = relation_store.encode_key_for_store(&extracted, span)?;
             let val = relation_store.encode_val_for_store(&extracted, span)?;
 
-            let existing = if relation_store.is_temp {
-                self.temp_store_tx.get(&key, true)?
-            } else {
-                self.store_tx.get(&key, true)?
-            };
+            let existing = relation_store.get_stored_val(self, &key, true)?;
             match existing {
                 None => {
                     bail!(TransactAssertionFailure {
@@ -924,6 +1185,7 @@ impl<'a> SessionTx<'a> {
         relation_store: &RelationHandle,
         metadata: &StoredRelationMetadata,
         key_bindings: &[Symbol],
+        binding_exprs: &BindingExprs,
         check_exists: bool,
         force_collect: &str,
         span: SourceSpan,
@@ -942,12 +1204,14 @@ impl<'a> SessionTx<'a> {
             &relation_store.metadata.keys,
             &metadata.keys,
             key_bindings,
+            binding_exprs,
             headers,
         )?;
 
         let need_to_collect = !force_collect.is_empty()
             || (!relation_store.is_temp
                 && (is_callback_target
+                    || !relation_store.views.is_empty()
                     || (propagate_triggers && !relation_store.rm_triggers.is_empty())));
         let has_indices = !relation_store.indices.is_empty();
         let has_hnsw_indices = !relation_store.hnsw_indices.is_empty();
@@ -964,8 +1228,26 @@ impl<'a> SessionTx<'a> {
                 .map(|ex| ex.extract_data(&tuple, cur_vld))
                 .try_collect()?;
             let key = relation_store.encode_key_for_store(&extracted, span)?;
+            // rows already soft-deleted are treated as absent
+            let tombstone = if relation_store.soft_delete {
+                match relation_store.get_stored_val(self, &key, false)? {
+                    None => None,
+                    Some(existing) => {
+                        let mut tup = extracted.clone();
+                        extend_tuple_from_v(&mut tup, &existing);
+                        *tup.last_mut().unwrap() = DataValue::from(self.now);
+                        Some(tup)
+                    }
+                }
+            } else {
+                None
+            };
             if check_exists {
-                let exists = if relation_store.is_temp {
+                let exists = if relation_store.soft_delete {
+                    tombstone.is_some()
+                } else if relation_store.ttl.is_some() {
+                    relation_store.get_stored_val(self, &key, false)?.is_some()
+                } else if relation_store.is_temp {
                     self.temp_store_tx.exists(&key, false)?
                 } else {
                     self.store_tx.exists(&key, false)?
@@ -978,6 +1260,9 @@ impl<'a> SessionTx<'a> {
                     });
                 }
             }
+            if relation_store.soft_delete && tombstone.is_none() {
+                continue;
+            }
             if need_to_collect || has_indices || has_hnsw_indices || has_fts_indices || has_lsh_indices {
                 if let Some(existing) = self.store_tx.get(&key, false)? {
                     let mut tup = extracted.clone();
@@ -1005,7 +1290,14 @@ impl<'a> SessionTx<'a> {
                     new_tuples.push(DataValue::List(extracted.clone()));
                 }
             }
-            if relation_store.is_temp {
+            if let Some(tombstone) = tombstone {
+                let val = relation_store.encode_val_for_store(&tombstone, span)?;
+                if relation_store.is_temp {
+                    self.temp_store_tx.put(&key, &val)?;
+                } else {
+                    self.store_tx.put(&key, &val)?;
+                }
+            } else if relation_store.is_temp {
                 self.temp_store_tx.del(&key)?;
             } else {
                 self.store_tx.del(&key)?;
@@ -1030,8 +1322,12 @@ impl<'a> SessionTx<'a> {
             kv_bindings.extend(v_bindings);
             let kv_bindings = kv_bindings;
 
+            if !relation_store.views.is_empty() {
+                self.maintain_views(db, relation_store, &[], &old_tuples, cur_vld)?;
+            }
+
             if propagate_triggers {
-                for trigger in &relation_store.rm_triggers {
+                for (i, trigger) in relation_store.rm_triggers.iter().enumerate() {
                     let mut program = parse_script(
                         trigger,
                         &Default::default(),
@@ -1049,22 +1345,17 @@ impl<'a> SessionTx<'a> {
                         old_tuples.clone(),
                     );
 
-                    let (_, cleanups) = db
-                        .run_query(
-                            self,
-                            program,
-                            cur_vld,
-                            callback_targets,
-                            callback_collector,
-                            false,
-                        )
-                        .map_err(|err| {
-                            if err.source_code().is_some() {
-                                err
-                            } else {
-                                err.with_source_code(format!("{trigger} "))
-                            }
-                        })?;
+                    let cleanups = self.run_trigger(
+                        db,
+                        &relation_store.name,
+                        "rm",
+                        i,
+                        trigger,
+                        program,
+                        cur_vld,
+                        callback_targets,
+                        callback_collector,
+                    )?;
                     to_clear.extend(cleanups);
                 }
             }
@@ -1117,9 +1408,11 @@ struct TransactAssertionFailure {
     notice: String,
 }
 
+#[allow(clippy::enum_variant_names)]
 enum DataExtractor {
     DefaultExtractor(Expr, NullableColType),
     IndexExtractor(usize, NullableColType),
+    ExprExtractor(Vec<Bytecode>, SourceSpan, NullableColType),
 }
 
 impl DataExtractor {
@@ -1131,19 +1424,31 @@ impl DataExtractor {
             DataExtractor::IndexExtractor(i, typ) => typ
                 .coerce(tuple[*i].clone(), cur_vld)
                 .wrap_err_with(|| format!("when processing tuple {tuple:?}"))?,
+            DataExtractor::ExprExtractor(bytecode, span, typ) => {
+                let val = eval_bytecode(bytecode, tuple, &mut vec![])?;
+                typ.coerce(val, cur_vld)
+                    .map_err(|err| ColumnExprError(*span, [err]))
+                    .wrap_err_with(|| format!("when processing tuple {tuple:?}"))?
+            }
         })
     }
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("The value computed for the column cannot be stored")]
+#[diagnostic(code(eval::bad_column_expr_value))]
+struct ColumnExprError(#[label] SourceSpan, #[related] [Report; 1]);
+
 fn make_extractors(
     stored: &[ColumnDef],
     input: &[ColumnDef],
     bindings: &[Symbol],
+    binding_exprs: &BindingExprs,
     tuple_headers: &[Symbol],
 ) -> Result<Vec<DataExtractor>> {
     stored
         .iter()
-        .map(|s| make_extractor(s, input, bindings, tuple_headers))
+        .map(|s| make_extractor(s, input, bindings, binding_exprs, tuple_headers))
         .try_collect()
 }
 
@@ -1151,13 +1456,20 @@ fn make_update_extractors(
     stored: &[ColumnDef],
     input: &[ColumnDef],
     bindings: &[Symbol],
+    binding_exprs: &BindingExprs,
     tuple_headers: &[Symbol],
 ) -> Result<Vec<Option<DataExtractor>>> {
     let input_keys: BTreeSet<_> = input.iter().map(|b| &b.name).collect();
     let mut extractors = Vec::with_capacity(stored.len());
     for col in stored.iter() {
         if input_keys.contains(&col.name) {
-            extractors.push(Some(make_extractor(col, input, bindings, tuple_headers)?));
+            extractors.push(Some(make_extractor(
+                col,
+                input,
+                bindings,
+                binding_exprs,
+                tuple_headers,
+            )?));
         } else {
             extractors.push(None);
         }
@@ -1165,14 +1477,82 @@ fn make_update_extractors(
     Ok(extractors)
 }
 
+fn make_conflict_updaters(
+    relation_store: &RelationHandle,
+    assignments: &BTreeMap<SmartString<LazyCompact>, Expr>,
+    tuple_headers: &[Symbol],
+) -> Result<Vec<(usize, DataExtractor)>> {
+    let mut binding_map: BTreeMap<Symbol, usize> = tuple_headers
+        .iter()
+        .enumerate()
+        .map(|(i, h)| (h.clone(), i))
+        .collect();
+    let stored_cols = relation_store
+        .metadata
+        .keys
+        .iter()
+        .chain(relation_store.metadata.non_keys.iter());
+    for (i, col) in stored_cols.enumerate() {
+        binding_map.insert(
+            Symbol::new(format!("old.{}", col.name), Default::default()),
+            tuple_headers.len() + i,
+        );
+    }
+    let key_len = relation_store.metadata.keys.len();
+    let mut updaters = Vec::with_capacity(assignments.len());
+    for (name, expr) in assignments {
+        let idx = match relation_store
+            .metadata
+            .non_keys
+            .iter()
+            .position(|col| col.name == *name)
+        {
+            Some(idx) => idx,
+            None => {
+                #[derive(Debug, Error, Diagnostic)]
+                #[error("Cannot assign to '{0}' on conflict as it is not a non-key column")]
+                #[diagnostic(code(eval::bad_on_conflict_col))]
+                struct BadOnConflictColumn(String, #[label] SourceSpan);
+                bail!(BadOnConflictColumn(name.to_string(), expr.span()))
+            }
+        };
+        let mut expr = expr.clone();
+        expr.fill_binding_indices(&binding_map)?;
+        updaters.push((
+            key_len + idx,
+            DataExtractor::ExprExtractor(
+                expr.compile()?,
+                expr.span(),
+                relation_store.metadata.non_keys[idx].typing.clone(),
+            ),
+        ));
+    }
+    Ok(updaters)
+}
+
 fn make_extractor(
     stored: &ColumnDef,
     input: &[ColumnDef],
     bindings: &[Symbol],
+    binding_exprs: &BindingExprs,
     tuple_headers: &[Symbol],
 ) -> Result<DataExtractor> {
     for (inp_col, inp_binding) in input.iter().zip(bindings.iter()) {
         if inp_col.name == stored.name {
+            if let Some(expr) = binding_exprs.get(&inp_binding.name) {
+                let binding_map = tuple_headers
+                    .iter()
+                    .enumerate()
+                    .map(|(i, h)| (h.clone(), i))
+                    .collect();
+                let mut expr = expr.clone();
+                expr.fill_binding_indices(&binding_map)?;
+                return Ok(DataExtractor::ExprExtractor(
+                    expr.compile()?,
+                    expr.span(),
+                    stored.typing.clone(),
+                ));
+            }
             for (idx, tuple_head) in tuple_headers.iter().enumerate() {
                 if tuple_head == inp_binding {
                     return Ok(DataExtractor::IndexExtractor(idx, stored.typing.clone()));
@@ -1194,7 +1574,7 @@ fn make_extractor(
     }
 }
 
-fn make_const_rule(
+pub(crate) fn make_const_rule(
     program: &mut InputProgram,
     rule_name: &str,
     bindings: Vec<Symbol>,
@@ -30,6 +30,7 @@ impl NormalFormAtom {
             | NormalFormAtom::NegatedRelation(_)
             | NormalFormAtom::Predicate(_)
             | NormalFormAtom::Unification(_)
+            | NormalFormAtom::Series(_)
             | NormalFormAtom::HnswSearch(_)
             | NormalFormAtom::FtsSearch(_)
             | NormalFormAtom::LshSearch(_) => Default::default(),
@@ -0,0 +1,603 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Inference of the types of the columns a query returns, without running it.
+//!
+//! Types flow from the schemas of stored relations, from constants, and from functions
+//! and aggregations, through the rules of the program up to its entry. Arithmetic on
+//! integers gives integers unless floats are involved, comparisons give booleans, and
+//! conditionals the join of their branches. Whatever cannot be inferred is reported as `Any?`.
+
+use std::collections::BTreeMap;
+
+use miette::Result;
+
+use crate::data::aggr::Aggregation;
+use crate::data::expr::{Expr, Op};
+use crate::data::program::{
+    FixedRuleApply, InputAtom, InputInlineRule, InputInlineRulesOrFixed, InputProgram,
+    ReturnMutation,
+};
+use crate::data::relation::{ColType, NullableColType, StoredRelationMetadata, VecElementType};
+use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::data::value::{DataValue, Num, Vector};
+use crate::parse::parse_type;
+use crate::runtime::transact::SessionTx;
+
+/// What is known about the values of a column or of a variable
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Inferred {
+    /// Only `null` has been seen, which fits any nullable type
+    Null,
+    Known(NullableColType),
+}
+
+impl Inferred {
+    pub(crate) fn of(coltype: ColType) -> Self {
+        Inferred::Known(NullableColType {
+            coltype,
+            nullable: false,
+        })
+    }
+
+    pub(crate) fn any() -> Self {
+        Inferred::Known(NullableColType {
+            coltype: ColType::Any,
+            nullable: true,
+        })
+    }
+
+    pub(crate) fn into_col_type(self) -> NullableColType {
+        match self {
+            Inferred::Null => NullableColType {
+                coltype: ColType::Any,
+                nullable: true,
+            },
+            Inferred::Known(t) => t,
+        }
+    }
+
+    fn nullable(self) -> Self {
+        match self {
+            Inferred::Null => Inferred::Null,
+            Inferred::Known(t) => Inferred::Known(NullableColType {
+                coltype: t.coltype,
+                nullable: true,
+            }),
+        }
+    }
+
+    /// The least type holding the values of both, with integers promoted to floats
+    pub(crate) fn join(self, other: Self) -> Self {
+        match (self, other) {
+            (Inferred::Null, t) | (t, Inferred::Null) => t.nullable(),
+            (Inferred::Known(a), Inferred::Known(b)) => {
+                let nullable = a.nullable || b.nullable;
+                let coltype = match (a.coltype, b.coltype) {
+                    (a, b) if a == b => a,
+                    (ColType::Int, ColType::Float) | (ColType::Float, ColType::Int) => {
+                        ColType::Float
+                    }
+                    _ => ColType::Any,
+                };
+                Inferred::Known(NullableColType { coltype, nullable })
+            }
+        }
+    }
+
+    pub(crate) fn of_value(val: &DataValue) -> Self {
+        match val {
+            DataValue::Null => Inferred::Null,
+            DataValue::Bool(_) => Inferred::of(ColType::Bool),
+            DataValue::Num(Num::Int(_)) => Inferred::of(ColType::Int),
+            DataValue::Num(Num::Float(_)) => Inferred::of(ColType::Float),
+            DataValue::Str(_) => Inferred::of(ColType::String),
+            DataValue::Bytes(_) => Inferred::of(ColType::Bytes),
+            DataValue::Uuid(_) => Inferred::of(ColType::Uuid),
+            DataValue::List(l) => Inferred::of(ColType::List {
+                eltype: Box::new(join_all(l.iter().map(Inferred::of_value)).into_col_type()),
+                len: None,
+            }),
+            DataValue::Vec(v) => {
+                let (eltype, len) = match v {
+                    Vector::F32(a) => (VecElementType::F32, a.len()),
+                    Vector::F64(a) => (VecElementType::F64, a.len()),
+                };
+                Inferred::of(ColType::Vec { eltype, len })
+            }
+            DataValue::Json(_) => Inferred::of(ColType::Json),
+            DataValue::Validity(_) => Inferred::of(ColType::Validity),
+            DataValue::Regex(_) | DataValue::Set(_) | DataValue::Bot => Inferred::any(),
+        }
+    }
+}
+
+/// The join of all the types, `null` if there are none
+fn join_all(types: impl Iterator<Item = Inferred>) -> Inferred {
+    types.reduce(|acc, t| acc.join(t)).unwrap_or(Inferred::Null)
+}
+
+/// The result type of functions for which it does not depend on the arguments
+fn op_result_type(op: &Op) -> Option<ColType> {
+    Some(match op.name {
+        "OP_LENGTH" | "OP_TO_INT" | "OP_RAND_INT" | "OP_RANDOM_INT" => ColType::Int,
+        "OP_TO_FLOAT"
+        | "OP_RAND_FLOAT"
+        | "OP_RANDOM"
+        | "OP_NOW"
+        | "OP_PARSE_TIMESTAMP"
+        | "OP_SQRT"
+        | "OP_EXP"
+        | "OP_EXP2"
+        | "OP_LN"
+        | "OP_LOG2"
+        | "OP_LOG10"
+        | "OP_SIN"
+        | "OP_COS"
+        | "OP_TAN"
+        | "OP_ASIN"
+        | "OP_ACOS"
+        | "OP_ATAN"
+        | "OP_ATAN2"
+        | "OP_SINH"
+        | "OP_COSH"
+        | "OP_TANH"
+        | "OP_ASINH"
+        | "OP_ACOSH"
+        | "OP_ATANH"
+        | "OP_HAVERSINE"
+        | "OP_HAVERSINE_DEG_INPUT"
+        | "OP_DEG_TO_RAD"
+        | "OP_RAD_TO_DEG"
+        | "OP_L2_DIST"
+        | "OP_IP_DIST"
+        | "OP_COS_DIST" => ColType::Float,
+        "OP_TO_STRING"
+        | "OP_LOWERCASE"
+        | "OP_UPPERCASE"
+        | "OP_TRIM"
+        | "OP_TRIM_START"
+        | "OP_TRIM_END"
+        | "OP_UNICODE_NORMALIZE"
+        | "OP_SLICE_STRING"
+        | "OP_FROM_SUBSTRINGS"
+        | "OP_REGEX_REPLACE"
+        | "OP_REGEX_REPLACE_ALL"
+        | "OP_ENCODE_BASE64"
+        | "OP_DUMP_JSON"
+        | "OP_TO_JSON"
+        | "OP_FORMAT_TIMESTAMP" => ColType::String,
+        "OP_IS_NULL" | "OP_IS_INT" | "OP_IS_FLOAT" | "OP_IS_NUM" | "OP_IS_STRING"
+        | "OP_IS_LIST" | "OP_IS_BYTES" | "OP_IS_IN" | "OP_IN" | "OP_NOT_IN" | "OP_IS_FINITE"
+        | "OP_IS_INFINITE" | "OP_IS_NAN" | "OP_IS_UUID" | "OP_IS_VEC" | "OP_IS_JSON"
+        | "OP_STR_INCLUDES" | "OP_STARTS_WITH" | "OP_ENDS_WITH" | "OP_REGEX_MATCHES"
+        | "OP_TO_BOOL" | "OP_RAND_BERNOULLI" => ColType::Bool,
+        "OP_DECODE_BASE64" => ColType::Bytes,
+        "OP_TO_UUID" | "OP_RAND_UUID_V1" | "OP_RAND_UUID_V4" => ColType::Uuid,
+        "OP_JSON"
+        | "OP_PARSE_JSON"
+        | "OP_JSON_OBJECT"
+        | "OP_JSON_MERGE"
+        | "OP_SET_JSON_PATH"
+        | "OP_REMOVE_JSON_PATH" => ColType::Json,
+        "OP_VALIDITY" => ColType::Validity,
+        "OP_CHARS" | "OP_REGEX_EXTRACT" => ColType::List {
+            eltype: Box::new(NullableColType {
+                coltype: ColType::String,
+                nullable: false,
+            }),
+            len: None,
+        },
+        "OP_INT_RANGE" => ColType::List {
+            eltype: Box::new(NullableColType {
+                coltype: ColType::Int,
+                nullable: false,
+            }),
+            len: None,
+        },
+        _ => return None,
+    })
+}
+
+/// The type of what `aggr` computes from values of type `arg`
+fn aggr_result_type(aggr: &Aggregation, arg: Inferred) -> Inferred {
+    match aggr.name {
+        "AGGR_COUNT"
+        | "AGGR_COUNT_UNIQUE"
+        | "AGGR_COUNT_DISTINCT"
+        | "AGGR_APPROX_COUNT_DISTINCT" => Inferred::of(ColType::Int),
+        "AGGR_SUM"
+        | "AGGR_PRODUCT"
+        | "AGGR_MEAN"
+        | "AGGR_VARIANCE"
+        | "AGGR_STD_DEV"
+        | "AGGR_PERCENTILE_CONT" => Inferred::of(ColType::Float),
+        "AGGR_AND" | "AGGR_OR" => Inferred::of(ColType::Bool),
+        "AGGR_MIN" | "AGGR_MAX" | "AGGR_CHOICE" | "AGGR_CHOICE_RAND" | "AGGR_PERCENTILE_DISC" => {
+            arg
+        }
+        "AGGR_COLLECT" | "AGGR_UNIQUE" => Inferred::of(ColType::List {
+            eltype: Box::new(arg.into_col_type()),
+            len: None,
+        }),
+        _ => Inferred::any(),
+    }
+}
+
+/// The type of arithmetic on numbers of the types `args`: integers if all of them are,
+/// floats if any is a float, and unknown for anything else, such as vectors
+fn arithmetic_type(args: &[Inferred], always_float: bool) -> Inferred {
+    let mut ret = if always_float {
+        ColType::Float
+    } else {
+        ColType::Int
+    };
+    for arg in args {
+        match arg {
+            Inferred::Known(NullableColType {
+                coltype: ColType::Int,
+                ..
+            }) => {}
+            Inferred::Known(NullableColType {
+                coltype: ColType::Float,
+                ..
+            }) => ret = ColType::Float,
+            _ => return Inferred::any(),
+        }
+    }
+    Inferred::of(ret)
+}
+
+/// The type of `op` applied to arguments of the types `args`
+fn apply_type(op: &Op, args: &[Inferred]) -> Inferred {
+    if let Some(t) = op_result_type(op) {
+        return Inferred::of(t);
+    }
+    match op.name {
+        "OP_ADD" | "OP_SUB" | "OP_MUL" | "OP_MOD" | "OP_MINUS" | "OP_ABS" | "OP_FLOOR"
+        | "OP_CEIL" | "OP_ROUND" | "OP_MAX" | "OP_MIN" => arithmetic_type(args, false),
+        "OP_DIV" | "OP_POW" => arithmetic_type(args, true),
+        "OP_EQ" | "OP_NEQ" | "OP_GT" | "OP_GE" | "OP_LT" | "OP_LE" | "OP_AND" | "OP_OR"
+        | "OP_NEGATE" => Inferred::of(ColType::Bool),
+        "OP_LIST" => Inferred::of(ColType::List {
+            eltype: Box::new(join_all(args.iter().cloned()).into_col_type()),
+            len: None,
+        }),
+        "OP_CONCAT" => match args.first() {
+            Some(Inferred::Known(NullableColType {
+                coltype: ColType::String,
+                ..
+            })) => Inferred::of(ColType::String),
+            Some(Inferred::Known(NullableColType {
+                coltype: ColType::List { .. },
+                ..
+            })) => {
+                let mut eltype: Option<Inferred> = None;
+                for arg in args {
+                    let el = match arg {
+                        Inferred::Known(NullableColType {
+                            coltype: ColType::List { eltype, .. },
+                            ..
+                        }) => Inferred::Known((**eltype).clone()),
+                        _ => Inferred::any(),
+                    };
+                    eltype = Some(match eltype {
+                        None => el,
+                        Some(prev) => prev.join(el),
+                    });
+                }
+                Inferred::of(ColType::List {
+                    eltype: Box::new(eltype.unwrap_or_else(Inferred::any).into_col_type()),
+                    len: None,
+                })
+            }
+            _ => Inferred::any(),
+        },
+        // null only if all the arguments are
+        "OP_COALESCE" => match join_all(args.iter().cloned()) {
+            Inferred::Known(NullableColType { coltype, .. }) => {
+                let nullable = args.iter().all(|arg| match arg {
+                    Inferred::Null => true,
+                    Inferred::Known(t) => t.nullable,
+                });
+                Inferred::Known(NullableColType { coltype, nullable })
+            }
+            Inferred::Null => Inferred::Null,
+        },
+        _ => Inferred::any(),
+    }
+}
+
+/// The type of `expr`, or `None` if it is not known yet
+pub(crate) fn expr_type(expr: &Expr, env: &BTreeMap<Symbol, Inferred>) -> Option<Inferred> {
+    match expr {
+        Expr::Binding { var, .. } => env.get(var).cloned(),
+        Expr::Const { val, .. } => Some(Inferred::of_value(val)),
+        Expr::Apply { op, args, .. } => {
+            let args = args
+                .iter()
+                .map(|arg| expr_type(arg, env))
+                .collect::<Option<Vec<_>>>()?;
+            Some(apply_type(op, &args))
+        }
+        // the value of the first clause whose condition holds, or null if none does
+        Expr::Cond { clauses, .. } => {
+            let mut ret: Option<Inferred> = None;
+            for (_, val) in clauses {
+                let val = expr_type(val, env)?;
+                ret = Some(match ret {
+                    None => val,
+                    Some(prev) => prev.join(val),
+                });
+            }
+            Some(ret.unwrap_or(Inferred::Null))
+        }
+        Expr::UnboundApply { .. } => Some(Inferred::any()),
+    }
+}
+
+fn column_types(meta: &StoredRelationMetadata) -> Vec<(&str, Inferred)> {
+    meta.keys
+        .iter()
+        .chain(meta.non_keys.iter())
+        .map(|col| (col.name.as_str(), Inferred::Known(col.typing.clone())))
+        .collect()
+}
+
+/// Bind `expr`, if it is a variable not yet bound, to `typing`
+fn bind(env: &mut BTreeMap<Symbol, Inferred>, expr: &Expr, typing: Inferred) {
+    if let Expr::Binding { var, .. } = expr {
+        env.entry(var.clone()).or_insert(typing);
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    /// The names and types of the columns `prog` returns, computed from the catalog only
+    pub(crate) fn result_schema(
+        &self,
+        prog: &InputProgram,
+    ) -> Result<Vec<(String, NullableColType)>> {
+        if let Some((handle, _, returning)) = &prog.out_opts.store_relation {
+            let string = NullableColType {
+                coltype: ColType::String,
+                nullable: false,
+            };
+            return Ok(match returning {
+                ReturnMutation::NotReturning => vec![("status".to_string(), string)],
+                ReturnMutation::Returning => {
+                    let stored;
+                    let meta = match self.get_relation(&handle.name, false) {
+                        Ok(h) => {
+                            stored = h;
+                            &stored.metadata
+                        }
+                        Err(_) => &handle.metadata,
+                    };
+                    let n_keys = meta.keys.len();
+                    let mut ret = vec![("_kind".to_string(), string)];
+                    for (i, (name, typing)) in column_types(meta).into_iter().enumerate() {
+                        let typing = if i < n_keys {
+                            typing
+                        } else {
+                            typing.nullable()
+                        };
+                        ret.push((name.to_string(), typing.into_col_type()));
+                    }
+                    ret
+                }
+            });
+        }
+
+        let headers = prog.get_entry_out_head_or_default()?;
+        let mut rule_types: BTreeMap<Symbol, Vec<Option<Inferred>>> = BTreeMap::new();
+        // types only grow, so this reaches a fixed point for recursive rules
+        let max_rounds = 2 * prog.prog.len() + 2;
+        for _ in 0..max_rounds {
+            let mut changed = false;
+            for (name, rules) in &prog.prog {
+                let types = match rules {
+                    InputInlineRulesOrFixed::Rules { rules } => {
+                        self.rules_types(rules, &rule_types)?
+                    }
+                    InputInlineRulesOrFixed::Fixed { fixed } => fixed_rule_types(fixed)?,
+                };
+                if rule_types.get(name) != Some(&types) {
+                    rule_types.insert(name.clone(), types);
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let entry = rule_types
+            .remove(&Symbol::new(PROG_ENTRY, Default::default()))
+            .unwrap_or_default();
+        Ok(headers
+            .into_iter()
+            .enumerate()
+            .map(|(i, symb)| {
+                let typing = entry
+                    .get(i)
+                    .cloned()
+                    .flatten()
+                    .unwrap_or_else(Inferred::any);
+                (symb.name.to_string(), typing.into_col_type())
+            })
+            .collect())
+    }
+
+    fn rules_types(
+        &self,
+        rules: &[InputInlineRule],
+        rule_types: &BTreeMap<Symbol, Vec<Option<Inferred>>>,
+    ) -> Result<Vec<Option<Inferred>>> {
+        let mut ret: Vec<Option<Inferred>> = vec![None; rules[0].head.len()];
+        for rule in rules {
+            let mut env = BTreeMap::new();
+            // variables may be used before the atom binding them
+            for _ in 0..2 {
+                for atom in &rule.body {
+                    self.bind_atom(atom, &mut env, rule_types)?;
+                }
+            }
+            for (i, (symb, aggr)) in rule.head.iter().zip(rule.aggr.iter()).enumerate() {
+                let typing = match aggr {
+                    None => env.get(symb).cloned(),
+                    Some((aggr, _)) => Some(aggr_result_type(
+                        aggr,
+                        env.get(symb).cloned().unwrap_or_else(Inferred::any),
+                    )),
+                };
+                if let Some(typing) = typing {
+                    ret[i] = Some(match ret[i].take() {
+                        None => typing,
+                        Some(prev) => prev.join(typing),
+                    });
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    fn bind_atom(
+        &self,
+        atom: &InputAtom,
+        env: &mut BTreeMap<Symbol, Inferred>,
+        rule_types: &BTreeMap<Symbol, Vec<Option<Inferred>>>,
+    ) -> Result<()> {
+        match atom {
+            InputAtom::Rule { inner } => {
+                if let Some(types) = rule_types.get(&inner.name) {
+                    for (arg, typing) in inner.args.iter().zip(types) {
+                        if let Some(typing) = typing {
+                            bind(env, arg, typing.clone());
+                        }
+                    }
+                }
+            }
+            InputAtom::NamedFieldRelation { inner } => {
+                let handle = self.get_relation(&inner.name, false)?;
+                for (col, typing) in column_types(&handle.metadata) {
+                    if let Some(arg) = inner.args.get(col) {
+                        bind(env, arg, typing);
+                    }
+                }
+            }
+            InputAtom::Relation { inner } => {
+                let handle = self.get_relation(&inner.name, false)?;
+                for (arg, (_, typing)) in inner.args.iter().zip(column_types(&handle.metadata)) {
+                    bind(env, arg, typing);
+                }
+            }
+            InputAtom::Search { inner } => {
+                let handle = self.get_relation(&inner.relation, false)?;
+                let cols = column_types(&handle.metadata);
+                for (name, arg) in &inner.bindings {
+                    let typing = match cols.iter().find(|(col, _)| *col == name.as_str()) {
+                        Some((_, typing)) => typing.clone(),
+                        None => Inferred::any(),
+                    };
+                    bind(env, arg, typing);
+                }
+            }
+            InputAtom::Unification { inner } => {
+                if env.contains_key(&inner.binding) {
+                    return Ok(());
+                }
+                if let Some(typing) = expr_type(&inner.expr, env) {
+                    let typing = if inner.one_many_unif {
+                        match typing {
+                            Inferred::Known(NullableColType {
+                                coltype: ColType::List { eltype, .. },
+                                ..
+                            }) => Inferred::Known(*eltype),
+                            _ => Inferred::any(),
+                        }
+                    } else {
+                        typing
+                    };
+                    env.insert(inner.binding.clone(), typing);
+                }
+            }
+            InputAtom::Series { inner } => {
+                let typing = match (&inner.start, &inner.step) {
+                    (DataValue::Num(Num::Int(_)), DataValue::Num(Num::Int(_))) => ColType::Int,
+                    _ => ColType::Float,
+                };
+                env.entry(inner.binding.clone())
+                    .or_insert(Inferred::of(typing));
+            }
+            InputAtom::Conjunction { inner, .. } => {
+                for atom in inner {
+                    self.bind_atom(atom, env, rule_types)?;
+                }
+            }
+            InputAtom::Disjunction { inner, .. } => {
+                let mut joined: BTreeMap<Symbol, Inferred> = BTreeMap::new();
+                for atom in inner {
+                    let mut branch = env.clone();
+                    self.bind_atom(atom, &mut branch, rule_types)?;
+                    for (symb, typing) in branch {
+                        if env.contains_key(&symb) {
+                            continue;
+                        }
+                        let typing = match joined.remove(&symb) {
+                            None => typing,
+                            Some(prev) => prev.join(typing),
+                        };
+                        joined.insert(symb, typing);
+                    }
+                }
+                env.extend(joined);
+            }
+            InputAtom::Predicate { .. } | InputAtom::Negation { .. } => {}
+        }
+        Ok(())
+    }
+}
+
+fn fixed_rule_types(fixed: &FixedRuleApply) -> Result<Vec<Option<Inferred>>> {
+    if fixed.fixed_handle.name.name == "Constant" {
+        if let Some(rows) = fixed
+            .options
+            .get("data")
+            .and_then(|data| data.get_const())
+            .and_then(|data| data.get_slice())
+        {
+            let mut ret: Vec<Option<Inferred>> = vec![None; fixed.arity()?];
+            for row in rows {
+                if let Some(row) = row.get_slice() {
+                    for (typing, val) in ret.iter_mut().zip(row) {
+                        let val = Inferred::of_value(val);
+                        *typing = Some(match typing.take() {
+                            None => val,
+                            Some(prev) => prev.join(val),
+                        });
+                    }
+                }
+            }
+            // types declared in the head take precedence over those of the data
+            if let Some(declared) = fixed
+                .options
+                .get("types")
+                .and_then(|types| types.get_const())
+                .and_then(|types| types.get_slice())
+            {
+                for (typing, declared) in ret.iter_mut().zip(declared) {
+                    if let Some(declared) = declared.get_str() {
+                        *typing = Some(Inferred::Known(parse_type(declared)?));
+                    }
+                }
+            }
+            return Ok(ret);
+        }
+    }
+    Ok(vec![Some(Inferred::any()); fixed.arity()?])
+}
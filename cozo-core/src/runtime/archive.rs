@@ -0,0 +1,75 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Whole-database archives written by [crate::Db::export_all].
+//!
+//! An archive starts with [ARCHIVE_MAGIC], followed by records each prefixed
+//! by its length as a big-endian `u32`. Records are MessagePack-encoded
+//! [ArchiveRecord]s: the namespaces, then for every stored relation (indices
+//! included) its catalog entry followed by its rows. Rows are stored as decoded
+//! tuples, so archives do not depend on the storage engine or on the key encoding.
+
+use std::io::{ErrorKind, Read, Write};
+
+use miette::{bail, IntoDiagnostic, Result};
+use rmp_serde::Serializer;
+use serde::Serialize;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::data::tuple::Tuple;
+use crate::runtime::relation::RelationHandle;
+
+pub(crate) const ARCHIVE_MAGIC: &[u8; 8] = b"COZOARC1";
+
+/// The number of rows in each [ArchiveRecord::Rows] record
+pub(crate) const ARCHIVE_ROWS_PER_RECORD: usize = 1024;
+
+#[derive(Serialize, Deserialize)]
+pub(crate) enum ArchiveRecord {
+    Namespace(String),
+    Relation(Box<RelationHandle>),
+    /// Rows of the relation of the last [ArchiveRecord::Relation] record
+    Rows(Vec<Tuple>),
+}
+
+pub(crate) fn write_archive_header(out: &mut impl Write) -> Result<()> {
+    out.write_all(ARCHIVE_MAGIC).into_diagnostic()
+}
+
+pub(crate) fn write_archive_record(out: &mut impl Write, record: &ArchiveRecord) -> Result<()> {
+    let mut buf = vec![];
+    record
+        .serialize(&mut Serializer::new(&mut buf).with_struct_map())
+        .into_diagnostic()?;
+    let len = u32::try_from(buf.len()).into_diagnostic()?;
+    out.write_all(&len.to_be_bytes()).into_diagnostic()?;
+    out.write_all(&buf).into_diagnostic()
+}
+
+pub(crate) fn read_archive_header(src: &mut impl Read) -> Result<()> {
+    let mut magic = [0u8; 8];
+    src.read_exact(&mut magic).into_diagnostic()?;
+    if &magic != ARCHIVE_MAGIC {
+        bail!("Not a database archive created by `export_all`");
+    }
+    Ok(())
+}
+
+/// The next record of the archive, or `None` at its end
+pub(crate) fn read_archive_record(src: &mut impl Read) -> Result<Option<ArchiveRecord>> {
+    let mut len = [0u8; 4];
+    match src.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err).into_diagnostic(),
+    }
+    let mut buf = vec![0u8; u32::from_be_bytes(len) as usize];
+    src.read_exact(&mut buf).into_diagnostic()?;
+    let record = rmp_serde::from_slice(&buf).into_diagnostic()?;
+    Ok(Some(record))
+}
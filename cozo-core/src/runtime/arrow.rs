@@ -0,0 +1,313 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Converting between rows and Arrow record batches.
+//!
+//! The Arrow type of a column is inferred from all of its values: integers become `Int64`,
+//! unless floats are also present, when they all become `Float64`, strings `Utf8`,
+//! bytes `Binary` and lists `List`s of the type inferred from their elements.
+//! Values without a corresponding Arrow type, such as UUIDs and JSON values,
+//! are given as `Utf8`, and so are columns holding values of different types,
+//! which are reported as warnings. All fields are nullable.
+
+use std::sync::Arc;
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+    Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, UInt16Type, UInt32Type,
+    UInt64Type, UInt8Type,
+};
+use arrow_array::{
+    Array, ArrayRef, BinaryArray, BooleanArray, Float64Array, Int64Array, ListArray, NullArray,
+    RecordBatch, StringArray,
+};
+use arrow_buffer::{NullBuffer, OffsetBuffer};
+use arrow_schema::{DataType, Field, Schema};
+use itertools::Itertools;
+use miette::{bail, Diagnostic, IntoDiagnostic, Result};
+use thiserror::Error;
+
+use crate::data::relation::ColumnDef;
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, Num, ValidityTs, Vector};
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("column '{0}' of the record batch has no corresponding column in relation '{1}'")]
+#[diagnostic(code(arrow::unknown_column))]
+#[diagnostic(help("the columns of the relation are: {2}"))]
+struct UnknownColumn(String, String, String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("column '{0}: {1}' of relation '{2}' has no corresponding column in the record batch")]
+#[diagnostic(code(arrow::missing_column))]
+struct MissingColumn(String, String, String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("column '{0}' of the record batch has type {1}, which has no corresponding value type")]
+#[diagnostic(code(arrow::unsupported_type))]
+struct UnsupportedType(String, DataType);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("row {0} of column '{1}' cannot be stored in column '{1}: {2}' of relation '{3}': {4}")]
+#[diagnostic(code(arrow::type_mismatch))]
+struct ValueTypeMismatch(usize, String, String, String, String);
+
+/// The Arrow type inferred for a column, or for the elements of a list
+#[derive(Debug, Clone, PartialEq)]
+enum Kind {
+    Null,
+    Bool,
+    Int,
+    Float,
+    Str,
+    Bytes,
+    List(Box<Kind>),
+    /// Values without a corresponding Arrow type, given as strings
+    Text,
+}
+
+impl Kind {
+    fn of(v: &DataValue) -> Option<Kind> {
+        Some(match v {
+            DataValue::Null => Kind::Null,
+            DataValue::Bool(_) => Kind::Bool,
+            DataValue::Num(Num::Int(_)) => Kind::Int,
+            DataValue::Num(Num::Float(_)) => Kind::Float,
+            DataValue::Str(_) => Kind::Str,
+            DataValue::Bytes(_) => Kind::Bytes,
+            DataValue::List(l) => Kind::List(Box::new(Kind::join_all(l.iter())?)),
+            DataValue::Set(s) => Kind::List(Box::new(Kind::join_all(s.iter())?)),
+            DataValue::Vec(_) => Kind::List(Box::new(Kind::Float)),
+            _ => Kind::Text,
+        })
+    }
+    /// The kind holding values of both kinds, `None` if they are of different types
+    fn join(self, other: Kind) -> Option<Kind> {
+        Some(match (self, other) {
+            (a, b) if a == b => a,
+            (Kind::Null, k) | (k, Kind::Null) => k,
+            (Kind::Int, Kind::Float) | (Kind::Float, Kind::Int) => Kind::Float,
+            (Kind::List(a), Kind::List(b)) => Kind::List(Box::new(a.join(*b)?)),
+            _ => return None,
+        })
+    }
+    fn join_all<'a>(mut vals: impl Iterator<Item = &'a DataValue>) -> Option<Kind> {
+        vals.try_fold(Kind::Null, |k, v| k.join(Kind::of(v)?))
+    }
+    fn data_type(&self) -> DataType {
+        match self {
+            Kind::Null => DataType::Null,
+            Kind::Bool => DataType::Boolean,
+            Kind::Int => DataType::Int64,
+            Kind::Float => DataType::Float64,
+            Kind::Str | Kind::Text => DataType::Utf8,
+            Kind::Bytes => DataType::Binary,
+            Kind::List(k) => DataType::List(Arc::new(k.item_field())),
+        }
+    }
+    fn item_field(&self) -> Field {
+        Field::new("item", self.data_type(), true)
+    }
+}
+
+fn to_text(v: &DataValue) -> Option<String> {
+    match v {
+        DataValue::Null => None,
+        DataValue::Str(s) => Some(s.to_string()),
+        DataValue::Uuid(u) => Some(u.0.to_string()),
+        DataValue::Json(j) => Some(j.0.to_string()),
+        v => Some(v.to_string()),
+    }
+}
+
+fn list_items(v: &DataValue) -> Option<Vec<DataValue>> {
+    match v {
+        DataValue::List(l) => Some(l.clone()),
+        DataValue::Set(s) => Some(s.iter().cloned().collect()),
+        DataValue::Vec(Vector::F32(a)) => {
+            Some(a.iter().map(|f| DataValue::from(*f as f64)).collect())
+        }
+        DataValue::Vec(Vector::F64(a)) => Some(a.iter().map(|f| DataValue::from(*f)).collect()),
+        _ => None,
+    }
+}
+
+fn build_array(kind: &Kind, vals: &[&DataValue]) -> ArrayRef {
+    match kind {
+        Kind::Null => Arc::new(NullArray::new(vals.len())),
+        Kind::Bool => Arc::new(vals.iter().map(|v| v.get_bool()).collect::<BooleanArray>()),
+        Kind::Int => Arc::new(vals.iter().map(|v| v.get_int()).collect::<Int64Array>()),
+        Kind::Float => Arc::new(vals.iter().map(|v| v.get_float()).collect::<Float64Array>()),
+        Kind::Str => Arc::new(vals.iter().map(|v| v.get_str()).collect::<StringArray>()),
+        Kind::Text => Arc::new(vals.iter().map(|v| to_text(v)).collect::<StringArray>()),
+        Kind::Bytes => Arc::new(
+            vals.iter()
+                .map(|v| match v {
+                    DataValue::Bytes(b) => Some(b.as_slice()),
+                    _ => None,
+                })
+                .collect::<BinaryArray>(),
+        ),
+        Kind::List(item) => {
+            let lists = vals.iter().map(|v| list_items(v)).collect_vec();
+            let lengths = lists.iter().map(|l| l.as_ref().map_or(0, |l| l.len()));
+            let offsets = OffsetBuffer::from_lengths(lengths);
+            let nulls = NullBuffer::from_iter(lists.iter().map(|l| l.is_some()));
+            let items = lists.iter().flatten().flatten().collect_vec();
+            let values = build_array(item, &items);
+            Arc::new(ListArray::new(
+                Arc::new(item.item_field()),
+                offsets,
+                values,
+                Some(nulls),
+            ))
+        }
+    }
+}
+
+/// Convert the rows of a result into record batches of at most `batch_size` rows each,
+/// returning them together with warnings about the columns given as strings as their
+/// values are of different types. An empty result gives a single empty batch.
+pub(crate) fn rows_to_record_batches(
+    headers: &[String],
+    rows: &[Tuple],
+    batch_size: usize,
+) -> Result<(Vec<RecordBatch>, Vec<String>)> {
+    let mut warnings = vec![];
+    let kinds = headers
+        .iter()
+        .enumerate()
+        .map(|(i, h)| {
+            Kind::join_all(rows.iter().map(|row| &row[i])).unwrap_or_else(|| {
+                warnings.push(format!(
+                    "column '{h}' holds values of different types and is given as strings"
+                ));
+                Kind::Text
+            })
+        })
+        .collect_vec();
+    let schema = Arc::new(Schema::new(
+        headers
+            .iter()
+            .zip(&kinds)
+            .map(|(h, k)| Field::new(h, k.data_type(), true))
+            .collect_vec(),
+    ));
+    if rows.is_empty() {
+        return Ok((vec![RecordBatch::new_empty(schema)], warnings));
+    }
+    let batches = rows
+        .chunks(batch_size.max(1))
+        .map(|chunk| {
+            let columns = kinds
+                .iter()
+                .enumerate()
+                .map(|(i, k)| build_array(k, &chunk.iter().map(|row| &row[i]).collect_vec()))
+                .collect_vec();
+            RecordBatch::try_new(schema.clone(), columns).into_diagnostic()
+        })
+        .try_collect()?;
+    Ok((batches, warnings))
+}
+
+/// The value at `idx` of `arr`, `None` if its type has no corresponding value type
+fn cell(arr: &dyn Array, idx: usize) -> Option<DataValue> {
+    if arr.is_null(idx) {
+        return Some(DataValue::Null);
+    }
+    Some(match arr.data_type() {
+        DataType::Null => DataValue::Null,
+        DataType::Boolean => DataValue::Bool(arr.as_boolean().value(idx)),
+        DataType::Int8 => DataValue::from(arr.as_primitive::<Int8Type>().value(idx) as i64),
+        DataType::Int16 => DataValue::from(arr.as_primitive::<Int16Type>().value(idx) as i64),
+        DataType::Int32 => DataValue::from(arr.as_primitive::<Int32Type>().value(idx) as i64),
+        DataType::Int64 => DataValue::from(arr.as_primitive::<Int64Type>().value(idx)),
+        DataType::UInt8 => DataValue::from(arr.as_primitive::<UInt8Type>().value(idx) as i64),
+        DataType::UInt16 => DataValue::from(arr.as_primitive::<UInt16Type>().value(idx) as i64),
+        DataType::UInt32 => DataValue::from(arr.as_primitive::<UInt32Type>().value(idx) as i64),
+        DataType::UInt64 => {
+            let n = arr.as_primitive::<UInt64Type>().value(idx);
+            match i64::try_from(n) {
+                Ok(i) => DataValue::from(i),
+                Err(_) => DataValue::from(n as f64),
+            }
+        }
+        DataType::Float32 => DataValue::from(arr.as_primitive::<Float32Type>().value(idx) as f64),
+        DataType::Float64 => DataValue::from(arr.as_primitive::<Float64Type>().value(idx)),
+        DataType::Utf8 => DataValue::from(arr.as_string::<i32>().value(idx)),
+        DataType::LargeUtf8 => DataValue::from(arr.as_string::<i64>().value(idx)),
+        DataType::Binary => DataValue::Bytes(arr.as_binary::<i32>().value(idx).to_vec()),
+        DataType::LargeBinary => DataValue::Bytes(arr.as_binary::<i64>().value(idx).to_vec()),
+        DataType::List(_) => list_cell(arr.as_list::<i32>().value(idx).as_ref())?,
+        DataType::LargeList(_) => list_cell(arr.as_list::<i64>().value(idx).as_ref())?,
+        _ => return None,
+    })
+}
+
+fn list_cell(items: &dyn Array) -> Option<DataValue> {
+    let items: Option<Vec<_>> = (0..items.len()).map(|i| cell(items, i)).collect();
+    Some(DataValue::List(items?))
+}
+
+/// Convert a record batch into rows of `relation`, which has the columns `columns`
+/// in storage order, matching columns by name and coercing each value to the type
+/// of its column.
+pub(crate) fn record_batch_to_rows(
+    batch: &RecordBatch,
+    relation: &str,
+    columns: &[&ColumnDef],
+    cur_vld: ValidityTs,
+) -> Result<Vec<Tuple>> {
+    let schema = batch.schema();
+    if let Some(field) = schema
+        .fields()
+        .iter()
+        .find(|f| !columns.iter().any(|c| &c.name as &str == f.name()))
+    {
+        bail!(UnknownColumn(
+            field.name().to_string(),
+            relation.to_string(),
+            columns.iter().map(|c| &c.name).join(", ")
+        ))
+    }
+    let arrays: Vec<_> = columns
+        .iter()
+        .map(|col| {
+            let Some(arr) = batch.column_by_name(&col.name) else {
+                bail!(MissingColumn(
+                    col.name.to_string(),
+                    col.typing.to_string(),
+                    relation.to_string()
+                ))
+            };
+            Ok(arr)
+        })
+        .try_collect()?;
+    (0..batch.num_rows())
+        .map(|idx| {
+            columns
+                .iter()
+                .zip(&arrays)
+                .map(|(col, arr)| {
+                    let val = cell(arr.as_ref(), idx).ok_or_else(|| {
+                        UnsupportedType(col.name.to_string(), arr.data_type().clone())
+                    })?;
+                    Ok(col.typing.coerce(val, cur_vld).map_err(|err| {
+                        ValueTypeMismatch(
+                            idx,
+                            col.name.to_string(),
+                            col.typing.to_string(),
+                            relation.to_string(),
+                            err.to_string(),
+                        )
+                    })?)
+                })
+                .try_collect()
+        })
+        .try_collect()
+}
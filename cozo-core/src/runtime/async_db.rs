@@ -0,0 +1,245 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Running scripts from async code without blocking the executor.
+//!
+//! The database itself is unchanged: each call is run on tokio's blocking thread pool,
+//! with a [Poison] of its own that is killed when the future or stream is dropped.
+
+use std::collections::BTreeMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use miette::{miette, Diagnostic, IntoDiagnostic, Result};
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::spawn_blocking;
+
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::runtime::db::RowSink;
+use crate::{DbInstance, MultiTransaction, NamedRows, Poison, QueryBuilder, ScriptMutability};
+
+/// Kills the poison of a call when the future or stream of the call is dropped,
+/// which terminates the query if it is still running.
+struct KillOnDrop(Poison);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        self.0.kill()
+    }
+}
+
+/// A wrapper around [DbInstance] whose methods return futures.
+///
+/// The methods must be called from within a tokio runtime.
+/// Dropping a future before it completes terminates its query.
+#[derive(Clone)]
+pub struct AsyncDb {
+    db: DbInstance,
+    stream_buffer: usize,
+}
+
+impl AsyncDb {
+    /// Wrap `db`. Streams buffer at most 1024 rows that are not yet consumed.
+    pub fn new(db: DbInstance) -> Self {
+        Self {
+            db,
+            stream_buffer: 1024,
+        }
+    }
+    /// Set the number of rows streams buffer before waiting for them to be consumed.
+    pub fn with_stream_buffer(mut self, rows: usize) -> Self {
+        self.stream_buffer = rows.max(1);
+        self
+    }
+    /// The wrapped database.
+    pub fn db(&self) -> &DbInstance {
+        &self.db
+    }
+    fn cancellable(&self) -> (DbInstance, KillOnDrop) {
+        let poison = Poison::default();
+        (
+            self.db.with_cancellation(poison.clone()),
+            KillOnDrop(poison),
+        )
+    }
+    /// See [crate::Db::run_script].
+    pub async fn run_script(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<NamedRows> {
+        let (db, _kill) = self.cancellable();
+        let payload = payload.to_string();
+        spawn_blocking(move || db.run_script(&payload, params, mutability))
+            .await
+            .into_diagnostic()?
+    }
+    /// See [crate::DbInstance::run_default].
+    pub async fn run_default(&self, payload: &str) -> Result<NamedRows> {
+        self.run_script(payload, BTreeMap::new(), ScriptMutability::Mutable)
+            .await
+    }
+    /// See [crate::Db::run_built_query].
+    pub async fn run_built_query(&self, query: QueryBuilder) -> Result<NamedRows> {
+        let (db, _kill) = self.cancellable();
+        spawn_blocking(move || db.run_built_query(query))
+            .await
+            .into_diagnostic()?
+    }
+    /// Run a script as [Self::run_script] does and return its rows as a stream.
+    ///
+    /// For a script consisting of a single query that does not store its rows, the future
+    /// resolves once the rows start to be read out of the result, and the rows are handed to
+    /// the stream as it is polled, with at most the configured number buffered: the query
+    /// waits for them to be consumed, keeping its transaction open, and is terminated when
+    /// the stream is dropped.
+    /// Otherwise the future resolves once the script has run. Errors the query fails with
+    /// after its rows have started to be streamed end the stream.
+    pub async fn run_script_stream(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<RowStream> {
+        let (db, kill) = self.cancellable();
+        let payload = payload.to_string();
+        let (headers_sender, headers_receiver) = oneshot::channel();
+        let (rows_sender, rows_receiver) = mpsc::channel(self.stream_buffer);
+        let sink = Arc::new(ChannelSink {
+            headers: Mutex::new(Some(headers_sender)),
+            rows: rows_sender.clone(),
+        });
+        spawn_blocking(move || {
+            let res = db
+                .with_row_sink(sink.clone())
+                .run_script(&payload, params, mutability);
+            let headers_sender = sink.headers.lock().unwrap().take();
+            match (res, headers_sender) {
+                (Err(err), None) => {
+                    let _ = rows_sender.blocking_send(Err(err));
+                }
+                (Err(err), Some(headers_sender)) => {
+                    let _ = headers_sender.send(Err(err));
+                }
+                // the rows have been streamed
+                (Ok(_), None) => {}
+                (Ok(res), Some(headers_sender)) => {
+                    if headers_sender.send(Ok(res.headers)).is_err() {
+                        return;
+                    }
+                    for row in res.rows {
+                        // the stream has been dropped
+                        if rows_sender.blocking_send(Ok(row)).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+        let headers = headers_receiver
+            .await
+            .map_err(|_| miette!("the query ended without a result"))??;
+        Ok(RowStream {
+            headers,
+            rows: rows_receiver,
+            _kill: kill,
+        })
+    }
+    /// See [crate::DbInstance::multi_transaction].
+    pub fn multi_transaction(&self, write: bool) -> AsyncSession {
+        AsyncSession {
+            tx: Arc::new(self.db.multi_transaction(write)),
+        }
+    }
+}
+
+/// Hands the rows of a query to a [RowStream].
+struct ChannelSink {
+    headers: Mutex<Option<oneshot::Sender<Result<Vec<String>>>>>,
+    rows: mpsc::Sender<Result<Tuple>>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The stream of the rows of the query has been dropped")]
+#[diagnostic(code(eval::row_stream_dropped))]
+struct RowStreamDropped;
+
+impl RowSink for ChannelSink {
+    fn headers(&self, headers: Vec<String>) -> Result<()> {
+        if let Some(sender) = self.headers.lock().unwrap().take() {
+            sender.send(Ok(headers)).map_err(|_| RowStreamDropped)?;
+        }
+        Ok(())
+    }
+    fn row(&self, row: Tuple) -> Result<()> {
+        self.rows
+            .blocking_send(Ok(row))
+            .map_err(|_| RowStreamDropped.into())
+    }
+}
+
+/// The rows of a script run with [AsyncDb::run_script_stream].
+pub struct RowStream {
+    headers: Vec<String>,
+    rows: mpsc::Receiver<Result<Tuple>>,
+    _kill: KillOnDrop,
+}
+
+impl RowStream {
+    /// The headers of the rows.
+    pub fn headers(&self) -> &[String] {
+        &self.headers
+    }
+}
+
+impl Stream for RowStream {
+    type Item = Result<Tuple>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Tuple>>> {
+        self.rows.poll_recv(cx)
+    }
+}
+
+/// A wrapper around [MultiTransaction] whose methods return futures.
+///
+/// As with [MultiTransaction], nothing is written unless [Self::commit] is called.
+pub struct AsyncSession {
+    tx: Arc<MultiTransaction>,
+}
+
+impl AsyncSession {
+    /// See [MultiTransaction::run_script].
+    pub async fn run_script(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        let tx = self.tx.clone();
+        let payload = payload.to_string();
+        spawn_blocking(move || tx.run_script(&payload, params))
+            .await
+            .into_diagnostic()?
+    }
+    /// See [MultiTransaction::commit].
+    pub async fn commit(&self) -> Result<()> {
+        let tx = self.tx.clone();
+        spawn_blocking(move || tx.commit())
+            .await
+            .into_diagnostic()?
+    }
+    /// See [MultiTransaction::abort].
+    pub async fn abort(&self) -> Result<()> {
+        let tx = self.tx.clone();
+        spawn_blocking(move || tx.abort()).await.into_diagnostic()?
+    }
+}
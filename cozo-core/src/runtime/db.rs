@@ -10,16 +10,21 @@ use std::collections::btree_map::Entry;
 use std::collections::{BTreeMap, BTreeSet};
 use std::default::Default;
 use std::fmt::{Debug, Formatter};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::iter;
 use std::path::Path;
+use std::rc::Rc;
 #[allow(unused_imports)]
-use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 #[allow(unused_imports)]
 use std::thread;
 #[allow(unused_imports)]
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+#[cfg(feature = "arrow")]
+use arrow_array::RecordBatch;
 #[allow(unused_imports)]
 use crossbeam::channel::{bounded, unbounded, Receiver, Sender};
 use crossbeam::sync::ShardedLock;
@@ -28,36 +33,66 @@ use itertools::Itertools;
 use miette::Report;
 #[allow(unused_imports)]
 use miette::{bail, ensure, miette, Diagnostic, IntoDiagnostic, Result, WrapErr};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json::json;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use crate::data::functions::current_validity;
+use crate::data::functions::{current_validity, with_seeded_rng};
 use crate::data::json::JsonValue;
-use crate::data::program::{InputProgram, QueryAssertion, RelationOp, ReturnMutation};
+use crate::data::program::{
+    InputProgram, MagicSymbol, QueryAssertion, QueryCursor, QueryOutOptions, RelationOp,
+    ReturnMutation,
+};
 use crate::data::relation::ColumnDef;
+use crate::data::symb::NameKind;
 use crate::data::tuple::{Tuple, TupleT};
-use crate::data::value::{DataValue, ValidityTs, LARGEST_UTF_CHAR};
+use crate::data::value::{DataValue, ValidityTs};
 use crate::fixed_rule::DEFAULT_FIXED_RULES;
 use crate::fts::TokenizerCache;
 use crate::parse::sys::SysOp;
-use crate::parse::{parse_expressions, parse_script, CozoScript, SourceSpan};
+use crate::parse::{
+    parse_expressions, parse_script, parse_script_with_limits, CozoScript, ParseLimits, SourceSpan,
+};
+use crate::query::builder::QueryBuilder;
 use crate::query::compile::{CompiledProgram, CompiledRule, CompiledRuleSet};
+use crate::query::metrics::{
+    metrics_enabled, op_metrics, set_last_query_metrics, take_op_metrics, with_metrics, OpMetrics,
+    QueryMetrics, SORT_KEY,
+};
+use crate::query::plan::QueryPlan;
 use crate::query::ra::{
     FilteredRA, FtsSearchRA, HnswSearchRA, InnerJoin, LshSearchRA, NegJoin, RelAlgebra, ReorderRA,
-    StoredRA, StoredWithValidityRA, TempStoreRA, UnificationRA,
+    SeriesRA, StoredRA, StoredWithValidityRA, TempStoreRA, UnificationRA,
 };
+use crate::query::sort::scan_in_sort_order;
+use crate::runtime::archive::{
+    read_archive_header, read_archive_record, write_archive_header, write_archive_record,
+    ArchiveRecord, ARCHIVE_ROWS_PER_RECORD,
+};
+#[cfg(feature = "arrow")]
+use crate::runtime::arrow::{record_batch_to_rows, rows_to_record_batches};
 #[allow(unused_imports)]
 use crate::runtime::callback::{
     CallbackCollector, CallbackDeclaration, CallbackOp, EventCallbackRegistry,
 };
+use crate::runtime::ddl::{missing_schema_objects, schema_script};
+use crate::runtime::import::stream_relations;
+use crate::runtime::memory::{MemoryAccountant, MemoryLimits, MemoryRelease};
+use crate::runtime::migration::{check_destructive_steps, plan_migration, MigrationPlan};
+use crate::runtime::plan_cache::{PlanCacheStats, PlanKey};
 use crate::runtime::relation::{
-    extend_tuple_from_v, AccessLevel, InsufficientAccessLevel, RelationHandle, RelationId,
+    extend_tuple_from_v, AccessLevel, ColumnInfo, InsufficientAccessLevel, NamespaceNotFound,
+    RelationHandle, RelationId, RelationInfo,
 };
+use crate::runtime::structs::{row_to_struct, struct_to_row};
 use crate::runtime::transact::SessionTx;
 use crate::storage::temp::TempStorage;
 use crate::storage::Storage;
-use crate::{decode_tuple_from_kv, FixedRule, Symbol};
+use crate::{try_decode_tuple_from_kv, FixedRule, Symbol};
 
 pub(crate) struct RunningQueryHandle {
     pub(crate) started_at: f64,
@@ -92,6 +127,24 @@ pub enum ScriptMutability {
     Immutable,
 }
 
+/// Which committed data the scripts of a multi-transaction read, see
+/// [crate::DbInstance::multi_transaction_with_consistency]. Either way the scripts see the
+/// writes made earlier in the same transaction, and all the statements of a single script
+/// read the same snapshot.
+///
+/// The two only differ with RocksDB. With the memory and SQLite engines, a read transaction
+/// keeps writers waiting, and a write transaction keeps out everything else, until it ends,
+/// so that nothing else commits in between. Sled always reads the latest committed data, and
+/// TiKV the snapshot of the start of the transaction.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub enum ReadConsistency {
+    /// All the scripts read the data as it was committed when the transaction started.
+    #[default]
+    Snapshot,
+    /// Each script reads the data as it is committed when the script starts.
+    Latest,
+}
+
 /// The database object of Cozo.
 #[derive(Clone)]
 pub struct Db<S> {
@@ -107,6 +160,31 @@ pub struct Db<S> {
     #[cfg(not(target_arch = "wasm32"))]
     pub(crate) event_callbacks: Arc<ShardedLock<EventCallbackRegistry>>,
     relation_locks: Arc<ShardedLock<BTreeMap<SmartString<LazyCompact>, Arc<ShardedLock<()>>>>>,
+    /// Source of the random functions once a seed is set, shared by the scripts run in turn.
+    rng: Arc<Mutex<Option<StdRng>>>,
+    parse_limits: Arc<Mutex<ParseLimits>>,
+    collect_metrics: Arc<AtomicBool>,
+    last_query_metrics: Arc<Mutex<Option<QueryMetrics>>>,
+    memory_limits: Arc<Mutex<MemoryLimits>>,
+    /// Memory held by the buffering operators of all running queries
+    memory_used: Arc<AtomicUsize>,
+    skip_corrupt: Arc<AtomicBool>,
+    case_insensitive_names: Arc<AtomicBool>,
+    read_only: Arc<AtomicBool>,
+    /// The namespace of the stored relations named without one
+    namespace: Arc<Mutex<Option<SmartString<LazyCompact>>>>,
+    plan_cache: Arc<Mutex<crate::runtime::plan_cache::PlanCache>>,
+    /// Bumped by each committed transaction changing the schemas of stored relations
+    catalog_version: Arc<AtomicU64>,
+    /// The time used instead of the system clock for the expiry of rows, if set
+    clock: Arc<Mutex<Option<f64>>>,
+    /// The most rows a query may return
+    max_result_rows: Arc<Mutex<Option<usize>>>,
+    /// Queries run through this handle are terminated with this instead of a poison of their own
+    pub(crate) cancel: Option<Poison>,
+    /// The rows of the queries of single-query scripts run through this handle are handed to
+    /// this as they are read out, see [Db::with_row_sink]
+    pub(crate) row_sink: Option<Arc<dyn RowSink>>,
 }
 
 impl<S> Debug for Db<S> {
@@ -120,6 +198,171 @@ impl<S> Debug for Db<S> {
 #[diagnostic(code(db::init))]
 pub(crate) struct BadDbInit(#[help] pub(crate) String);
 
+/// A query returned more rows than allowed by [Db::set_max_result_rows].
+#[derive(Debug, Diagnostic, Error)]
+#[error("The query returns more than the limit of {limit} rows")]
+#[diagnostic(code(eval::result_row_limit_exceeded))]
+#[diagnostic(help("Narrow down the query, page through it with `:limit` and `:offset`, or raise the limit with `set_max_result_rows`"))]
+pub struct ResultRowLimitExceeded {
+    /// The most rows a query may return
+    pub limit: usize,
+}
+
+/// Receives the rows of a query as they are read out, see [Db::with_row_sink].
+pub(crate) trait RowSink: Send + Sync {
+    /// Called once with the headers of the rows, before any of them.
+    fn headers(&self, headers: Vec<String>) -> Result<()>;
+    /// Failing terminates the query with the error.
+    fn row(&self, row: Tuple) -> Result<()>;
+}
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("Cannot {0}: the database is in read-only mode")]
+#[diagnostic(code(db::read_only))]
+#[diagnostic(help("Call `set_read_only(false)` to allow mutations again"))]
+pub(crate) struct ReadOnlyMode(pub(crate) String);
+
+/// Imports `rows` with the given `headers` into a stored relation, or deletes them if
+/// `relation_op` starts with `-`. Errors for malformed rows mention their position
+/// counted from `first_row`.
+pub(crate) fn import_rows(
+    tx: &mut SessionTx<'_>,
+    relation_op: &str,
+    headers: &[String],
+    rows: Vec<Tuple>,
+    first_row: usize,
+) -> Result<()> {
+    let cur_vld = current_validity();
+    let is_delete;
+    let relation: &str = match relation_op.strip_prefix('-') {
+        None => {
+            is_delete = false;
+            relation_op
+        }
+        Some(s) => {
+            is_delete = true;
+            s
+        }
+    };
+    if relation.contains(':') {
+        bail!(ImportIntoIndex(relation.to_string()))
+    }
+    let handle = tx.get_relation(relation, false)?;
+    handle.ensure_not_view("import into")?;
+    handle.ensure_no_views("import into")?;
+    let has_indices = !handle.indices.is_empty();
+
+    if handle.access_level < AccessLevel::Protected {
+        bail!(InsufficientAccessLevel(
+            handle.name.to_string(),
+            "data import".to_string(),
+            handle.access_level
+        ));
+    }
+
+    let header2idx: BTreeMap<_, _> = headers
+        .iter()
+        .enumerate()
+        .map(|(i, k)| -> Result<(&str, usize)> { Ok((k as &str, i)) })
+        .try_collect()?;
+
+    let key_indices: Vec<_> = handle
+        .metadata
+        .keys
+        .iter()
+        .map(|col| -> Result<(usize, &ColumnDef)> {
+            let idx = header2idx.get(&col.name as &str).ok_or_else(|| {
+                miette!(
+                    "required header {} not found for relation {}",
+                    col.name,
+                    relation
+                )
+            })?;
+            Ok((*idx, col))
+        })
+        .try_collect()?;
+
+    let val_indices: Vec<_> = if is_delete {
+        vec![]
+    } else {
+        handle
+            .metadata
+            .non_keys
+            .iter()
+            .map(|col| -> Result<(usize, &ColumnDef)> {
+                let idx = header2idx.get(&col.name as &str).ok_or_else(|| {
+                    miette!(
+                        "required header {} not found for relation {}",
+                        col.name,
+                        relation
+                    )
+                })?;
+                Ok((*idx, col))
+            })
+            .try_collect()?
+    };
+
+    for (n, row) in rows.into_iter().enumerate() {
+        let row_context = || {
+            format!(
+                "malformed row {} for relation '{}'",
+                first_row + n,
+                relation
+            )
+        };
+        let keys: Vec<_> = key_indices
+            .iter()
+            .map(|(i, col)| -> Result<DataValue> {
+                let v = row
+                    .get(*i)
+                    .ok_or_else(|| miette!("row too short: {:?}", row))?;
+                col.typing.coerce(v.clone(), cur_vld)
+            })
+            .try_collect()
+            .wrap_err_with(row_context)?;
+        let k_store = handle.encode_key_for_store(&keys, Default::default())?;
+        if has_indices {
+            if let Some(existing) = tx.store_tx.get(&k_store, false)? {
+                let mut old = keys.clone();
+                extend_tuple_from_v(&mut old, &existing);
+                if is_delete || old != row {
+                    for (idx_rel, extractor) in handle.indices.values() {
+                        let idx_tup = extractor.iter().map(|i| old[*i].clone()).collect_vec();
+                        let encoded = idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
+                        tx.store_tx.del(&encoded)?;
+                    }
+                }
+            }
+        }
+        if is_delete {
+            tx.store_tx.del(&k_store)?;
+        } else {
+            let vals: Vec<_> = val_indices
+                .iter()
+                .map(|(i, col)| -> Result<DataValue> {
+                    let v = row
+                        .get(*i)
+                        .ok_or_else(|| miette!("row too short: {:?}", row))?;
+                    col.typing.coerce(v.clone(), cur_vld)
+                })
+                .try_collect()
+                .wrap_err_with(row_context)?;
+            let v_store = handle.encode_val_only_for_store(&vals, Default::default())?;
+            tx.store_tx.put(&k_store, &v_store)?;
+            if has_indices {
+                let mut kv = keys;
+                kv.extend(vals);
+                for (idx_rel, extractor) in handle.indices.values() {
+                    let idx_tup = extractor.iter().map(|i| kv[*i].clone()).collect_vec();
+                    let encoded = idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
+                    tx.store_tx.put(&encoded, &[])?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("Cannot import data into relation {0} as it is an index")]
 #[diagnostic(code(tx::import_into_index))]
@@ -278,6 +521,22 @@ impl<'s, S: Storage<'s>> Db<S> {
             #[cfg(not(target_arch = "wasm32"))]
             event_callbacks: Default::default(),
             relation_locks: Default::default(),
+            rng: Default::default(),
+            parse_limits: Default::default(),
+            collect_metrics: Default::default(),
+            last_query_metrics: Default::default(),
+            memory_limits: Default::default(),
+            memory_used: Default::default(),
+            skip_corrupt: Default::default(),
+            case_insensitive_names: Default::default(),
+            read_only: Default::default(),
+            namespace: Default::default(),
+            plan_cache: Default::default(),
+            catalog_version: Default::default(),
+            clock: Default::default(),
+            max_result_rows: Default::default(),
+            cancel: None,
+            row_sink: None,
         };
         Ok(ret)
     }
@@ -295,11 +554,31 @@ impl<'s, S: Storage<'s>> Db<S> {
     ///
     /// Write transactions _may_ block other reads, but we guarantee that this does not happen
     /// for the RocksDB backend.
+    ///
+    /// The scripts read the snapshot taken when the transaction starts, see
+    /// [Db::run_multi_transaction_with_consistency].
     pub fn run_multi_transaction(
         &'s self,
         is_write: bool,
         payloads: Receiver<TransactionPayload>,
         results: Sender<Result<NamedRows>>,
+    ) {
+        self.run_multi_transaction_with_consistency(
+            is_write,
+            ReadConsistency::Snapshot,
+            payloads,
+            results,
+        )
+    }
+
+    /// Like [Db::run_multi_transaction], with the committed data read by the scripts given
+    /// by `consistency`.
+    pub fn run_multi_transaction_with_consistency(
+        &'s self,
+        is_write: bool,
+        consistency: ReadConsistency,
+        payloads: Receiver<TransactionPayload>,
+        results: Sender<Result<NamedRows>>,
     ) {
         let tx = if is_write {
             self.transact_write()
@@ -342,18 +621,31 @@ impl<'s, S: Storage<'s>> Db<S> {
                     break;
                 }
                 TransactionPayload::Query((script, params)) => {
-                    let p =
-                        match parse_script(&script, &params, &self.fixed_rules.read().unwrap(), ts)
-                        {
-                            Ok(p) => p,
-                            Err(err) => {
-                                if results.send(Err(err)).is_err() {
-                                    break;
-                                } else {
-                                    continue;
-                                }
+                    if consistency == ReadConsistency::Latest {
+                        if let Err(err) = tx.store_tx.refresh_snapshot() {
+                            if results.send(Err(err)).is_err() {
+                                break;
+                            } else {
+                                continue;
                             }
-                        };
+                        }
+                    }
+                    let p = match parse_script_with_limits(
+                        &script,
+                        &params,
+                        &self.fixed_rules.read().unwrap(),
+                        ts,
+                        &self.parse_limits(),
+                    ) {
+                        Ok(p) => p,
+                        Err(err) => {
+                            if results.send(Err(err)).is_err() {
+                                break;
+                            } else {
+                                continue;
+                            }
+                        }
+                    };
 
                     let p = match p.get_single_program() {
                         Ok(p) => p,
@@ -366,6 +658,13 @@ impl<'s, S: Storage<'s>> Db<S> {
                         }
                     };
                     if let Some(write_lock_name) = p.needs_write_lock() {
+                        if let Err(err) = self.ensure_writable("write to stored relations") {
+                            if results.send(Err(err)).is_err() {
+                                break;
+                            } else {
+                                continue;
+                            }
+                        }
                         match write_locks.entry(write_lock_name) {
                             Entry::Vacant(e) => {
                                 let lock = self
@@ -400,22 +699,38 @@ impl<'s, S: Storage<'s>> Db<S> {
     }
 
     /// Run the CozoScript passed in. The `params` argument is a map of parameters.
+    ///
+    /// The plans of read-only queries are cached, see [Db::set_plan_cache_size].
     pub fn run_script(
         &'s self,
         payload: &str,
         params: BTreeMap<String, DataValue>,
         mutability: ScriptMutability,
     ) -> Result<NamedRows> {
-        self.run_script_ast(
-            parse_script(
-                payload,
-                &params,
-                &self.get_fixed_rules(),
-                current_validity(),
-            )?,
+        let cache_key = self
+            .plan_cache
+            .lock()
+            .unwrap()
+            .enabled()
+            .then(|| PlanKey::new(payload, &params));
+        if let Some(key) = &cache_key {
+            if let Some(res) = self.run_with_script_state(|| self.run_cached_plan(key))? {
+                return Ok(res);
+            }
+        }
+        let script = parse_script_with_limits(
+            payload,
+            &params,
+            &self.get_fixed_rules(),
             current_validity(),
-            mutability,
-        )
+            &self.parse_limits(),
+        )?;
+        match (cache_key, script) {
+            (Some(key), CozoScript::Single(program)) if plan_cacheable(&program) => {
+                self.run_with_script_state(|| self.run_and_cache_plan(key, program))
+            }
+            (_, script) => self.run_script_ast(script, current_validity(), mutability),
+        }
     }
 
     /// Run the CozoScript passed in. The `params` argument is a map of parameters.
@@ -427,6 +742,142 @@ impl<'s, S: Storage<'s>> Db<S> {
         self.run_script(payload, params, ScriptMutability::Immutable)
     }
 
+    /// Run a query built with [QueryBuilder]. Such queries never mutate the database.
+    pub fn run_built_query(&'s self, query: QueryBuilder) -> Result<NamedRows> {
+        self.run_script_ast(
+            CozoScript::Single(query.build()?),
+            current_validity(),
+            ScriptMutability::Immutable,
+        )
+    }
+
+    /// The plan of the single query in `payload`, as `::explain` describes it, in the JSON
+    /// format of [NamedRows::into_json]. The query is only compiled, in a read-only
+    /// transaction, so this never runs it or takes locks on the relations it writes to.
+    pub fn explain(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<JsonValue> {
+        let program = parse_script_with_limits(
+            payload,
+            &params,
+            &self.get_fixed_rules(),
+            current_validity(),
+            &self.parse_limits(),
+        )?
+        .get_single_program()?;
+        let mut tx = self.transact()?;
+        Ok(self.explain_program(&mut tx, &program)?.into_json())
+    }
+
+    /// Compile the single read-only query in `payload` into a self-contained plan,
+    /// which [Db::run_plan_json] can run later, in this or another database whose stored
+    /// relations read by the query have the same columns.
+    ///
+    /// Queries searching indices or mutating relations cannot be planned.
+    pub fn plan_json(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<JsonValue> {
+        let program = parse_script_with_limits(
+            payload,
+            &params,
+            &self.get_fixed_rules(),
+            current_validity(),
+            &self.parse_limits(),
+        )?
+        .get_single_program()?;
+        let mut tx = self.transact()?;
+        QueryPlan::compile(&mut tx, program)?.to_json()
+    }
+
+    /// Run a plan made by [Db::plan_json], failing if the stored relations it reads
+    /// are missing or do not have the columns it expects, or if it calls fixed rules
+    /// this database does not have.
+    pub fn run_plan_json(&'s self, plan: &JsonValue) -> Result<NamedRows> {
+        let mut tx = self.transact()?;
+        let plan = QueryPlan::from_json(&tx, &self.get_fixed_rules(), plan)?;
+        let res = self.run_query_plan(&mut tx, plan)?;
+        tx.commit_tx()?;
+        Ok(res)
+    }
+
+    /// The names and types of the columns the single query in `payload` returns, worked out
+    /// from the schemas of the stored relations it reads without running it.
+    /// Types are written as in schemas, e.g. `Int` or `String?`, and are `Any?` where they
+    /// cannot be inferred.
+    pub fn result_schema(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<Vec<(String, String)>> {
+        let program = parse_script_with_limits(
+            payload,
+            &params,
+            &self.get_fixed_rules(),
+            current_validity(),
+            &self.parse_limits(),
+        )?
+        .get_single_program()?;
+        let tx = self.transact()?;
+        let schema = tx.result_schema(&program)?;
+        Ok(schema
+            .into_iter()
+            .map(|(name, typing)| (name, typing.to_string()))
+            .collect())
+    }
+
+    /// Run a single query sorted with `:order`, resuming strictly after `cursor` if given.
+    ///
+    /// Returns the rows together with the cursor for fetching the next page, which is `None`
+    /// when the query has no `:limit` or returned fewer rows than it. Inside scripts,
+    /// the same can be done with the `:after` option, e.g. `:after $cursor`.
+    pub fn run_query_after(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        cursor: Option<&str>,
+    ) -> Result<(NamedRows, Option<String>)> {
+        let cur_vld = current_validity();
+        let mut program = parse_script_with_limits(
+            payload,
+            &params,
+            &self.get_fixed_rules(),
+            cur_vld,
+            &self.parse_limits(),
+        )?
+        .get_single_program()?;
+        ensure!(
+            !program.out_opts.sorters.is_empty(),
+            "paginated queries must be sorted with ':order'"
+        );
+        if let Some(cursor) = cursor {
+            program.out_opts.after = Some(Box::new(QueryCursor::decode(cursor)?));
+        }
+        let fingerprint = program.fingerprint();
+        let sorters = program.out_opts.sorters.clone();
+        let limit = program.out_opts.limit;
+        let rows = self.execute_single(cur_vld, program, true)?;
+        let next = match rows.rows.last() {
+            Some(last) if limit.is_some_and(|l| rows.rows.len() >= l) => {
+                let mut key = Vec::with_capacity(sorters.len());
+                for (symb, _) in &sorters {
+                    let idx = rows
+                        .headers
+                        .iter()
+                        .position(|h| *h == symb.name)
+                        .ok_or_else(|| miette!("sort key '{}' not found in output", symb))?;
+                    key.push(last[idx].clone());
+                }
+                Some(QueryCursor { fingerprint, key }.encode())
+            }
+            _ => None,
+        };
+        Ok((rows, next))
+    }
+
     /// Run the AST CozoScript passed in.
     pub fn run_script_ast(
         &'s self,
@@ -434,12 +885,293 @@ impl<'s, S: Storage<'s>> Db<S> {
         cur_vld: ValidityTs,
         mutability: ScriptMutability,
     ) -> Result<NamedRows> {
-        let read_only = mutability == ScriptMutability::Immutable;
-        match payload {
+        let read_only =
+            mutability == ScriptMutability::Immutable || self.read_only.load(Ordering::Acquire);
+        self.run_with_script_state(|| match payload {
             CozoScript::Single(p) => self.execute_single(cur_vld, p, read_only),
             CozoScript::Imperative(ps) => self.execute_imperative(cur_vld, &ps, read_only),
             CozoScript::Sys(op) => self.run_sys_op(op, read_only),
+        })
+    }
+
+    /// Run a script collecting its metrics if asked to, and drawing from the seeded
+    /// generator of random values if there is one.
+    fn run_with_script_state<T>(&'s self, run: impl FnOnce() -> Result<T>) -> Result<T> {
+        let run = || {
+            if !self.collect_metrics.load(Ordering::Acquire) {
+                return run();
+            }
+            let (ret, metrics) = with_metrics(run);
+            *self.last_query_metrics.lock().unwrap() = metrics;
+            ret
+        };
+        let mut rng = self.rng.lock().unwrap();
+        if rng.is_none() {
+            drop(rng);
+            return run();
+        }
+        // with a seed, scripts take turns drawing from the generator so that results are reproducible
+        with_seeded_rng(&mut rng, run)
+    }
+
+    /// Run the plan cached for `key`, if there is one compiled against the current catalog
+    /// that can still be rebuilt.
+    fn run_cached_plan(&'s self, key: &PlanKey) -> Result<Option<NamedRows>> {
+        let catalog_version = self.catalog_version.load(Ordering::Acquire);
+        let Some(plan) = self.plan_cache.lock().unwrap().get(key, catalog_version) else {
+            return Ok(None);
+        };
+        let mut tx = self.transact()?;
+        let plan = match QueryPlan::from_json(&tx, &self.get_fixed_rules(), &plan) {
+            Ok(plan) => plan,
+            Err(_) => {
+                // e.g. a fixed rule it calls has been unregistered
+                self.plan_cache.lock().unwrap().remove(key);
+                return Ok(None);
+            }
+        };
+        self.plan_cache.lock().unwrap().record_hit();
+        tx.row_sink = self.row_sink.clone();
+        let res = self.run_query_plan(&mut tx, plan)?;
+        tx.commit_tx()?;
+        Ok(Some(res))
+    }
+
+    /// Compile and run a read-only query, caching its plan under `key` if it can be serialized.
+    fn run_and_cache_plan(&'s self, key: PlanKey, program: InputProgram) -> Result<NamedRows> {
+        let catalog_version = self.catalog_version.load(Ordering::Acquire);
+        let mut tx = self.transact()?;
+        let plan = QueryPlan::compile(&mut tx, program)?;
+        if let Ok(json) = plan.to_json() {
+            if !key.depends_on_time(&json) {
+                let mut cache = self.plan_cache.lock().unwrap();
+                cache.record_miss();
+                cache.insert(key, json, catalog_version);
+            }
+        }
+        tx.row_sink = self.row_sink.clone();
+        let res = self.run_query_plan(&mut tx, plan)?;
+        tx.commit_tx()?;
+        Ok(res)
+    }
+
+    /// Set the number of plans of read-only queries kept, 128 by default. When a query is
+    /// run again with the same parameters, its cached plan is run without parsing or
+    /// compiling the query. Queries are the same when they differ only in comments and
+    /// whitespace outside of strings. Plans are dropped when the schemas of stored
+    /// relations change. Setting the size to 0 turns the cache off.
+    pub fn set_plan_cache_size(&'s self, size: usize) {
+        self.plan_cache.lock().unwrap().set_capacity(size);
+    }
+
+    /// The numbers of hits and misses of the plan cache, see [Db::set_plan_cache_size].
+    pub fn plan_cache_stats(&'s self) -> PlanCacheStats {
+        self.plan_cache.lock().unwrap().stats()
+    }
+
+    /// Seed the generator behind `random`, `random_int` and the other random functions.
+    ///
+    /// Afterwards, these functions draw from this generator in the order they are evaluated,
+    /// so that running the same scripts after setting the same seed gives the same results.
+    /// Scripts then run one at a time.
+    pub fn set_rng_seed(&'s self, seed: u64) {
+        *self.rng.lock().unwrap() = Some(StdRng::seed_from_u64(seed));
+    }
+
+    /// The limits that scripts run by this database are parsed with.
+    pub fn parse_limits(&'s self) -> ParseLimits {
+        *self.parse_limits.lock().unwrap()
+    }
+
+    /// Set the limits on nesting and rule length that scripts are parsed with.
+    /// Raising them allows larger scripts, at the risk of running out of stack
+    /// on threads with small stacks.
+    pub fn set_parse_limits(&'s self, limits: ParseLimits) {
+        *self.parse_limits.lock().unwrap() = limits;
+        // cached plans were parsed under the old limits
+        self.plan_cache.lock().unwrap().clear();
+    }
+
+    /// Collect metrics for the operators of the queries run from now on, or stop doing so.
+    ///
+    /// While collecting, the rules of a query are evaluated one at a time.
+    pub fn set_collect_metrics(&'s self, enabled: bool) {
+        self.collect_metrics.store(enabled, Ordering::Release);
+    }
+
+    /// The metrics of the last query of the last script run while collecting metrics,
+    /// see [Db::set_collect_metrics].
+    pub fn last_query_metrics(&'s self) -> Option<QueryMetrics> {
+        self.last_query_metrics.lock().unwrap().clone()
+    }
+
+    /// The limits on the memory held by the queries run by this database.
+    pub fn memory_limits(&'s self) -> MemoryLimits {
+        *self.memory_limits.lock().unwrap()
+    }
+
+    /// Limit the memory that operators buffering rows may hold, for each query and for
+    /// all running queries together. A query exceeding a limit fails with an error
+    /// naming the operator.
+    pub fn set_memory_limits(&'s self, limits: MemoryLimits) {
+        *self.memory_limits.lock().unwrap() = limits;
+    }
+
+    /// Skip the stored rows that cannot be decoded when scanning relations, instead of failing
+    /// with [crate::CorruptData]. The number of rows skipped by a query is part of its metrics.
+    pub fn set_skip_corrupt(&'s self, skip: bool) {
+        self.skip_corrupt.store(skip, Ordering::Release);
+    }
+
+    /// Resolve the names of stored relations ignoring case when no relation has the exact name,
+    /// so that `*employee{..}` finds `Employee`. Names matching several relations this way are
+    /// an error. Bindings and column names stay case-sensitive.
+    pub fn set_case_insensitive_names(&'s self, enabled: bool) {
+        self.case_insensitive_names
+            .store(enabled, Ordering::Release);
+        self.plan_cache.lock().unwrap().clear();
+    }
+
+    /// Fail the queries returning more than `max` rows with [ResultRowLimitExceeded], instead
+    /// of copying all their rows into the result. Rows written to stored relations do not count.
+    /// `None` removes the limit, which is the default.
+    ///
+    /// Only the returned rows are counted, after `:order`, `:offset` and `:limit` are applied,
+    /// so that a query paging through a large result is not rejected. The rows computed while
+    /// evaluating and sorting are not bounded by this, but by [Self::set_memory_limits].
+    pub fn set_max_result_rows(&'s self, max: Option<usize>) {
+        *self.max_result_rows.lock().unwrap() = max;
+    }
+
+    /// Fix the time, in seconds since the epoch, at which transactions started from now on
+    /// write and read the rows of relations with a TTL and mark the rows they soft-delete,
+    /// instead of using the system clock.
+    /// `None` restores the system clock.
+    pub fn set_clock(&'s self, now: Option<f64>) {
+        *self.clock.lock().unwrap() = now;
+    }
+
+    fn now(&self) -> f64 {
+        match *self.clock.lock().unwrap() {
+            Some(now) => now,
+            None => current_validity().0 .0 as f64 / 1_000_000.,
+        }
+    }
+
+    /// Reject everything that writes to the database while `read_only` is set: scripts are
+    /// run as with [ScriptMutability::Immutable], and methods such as [Db::import_relations]
+    /// fail with [ReadOnlyMode]. Write transactions of [Db::run_multi_transaction] can still
+    /// be started, but the scripts in them that write fail the same way.
+    pub fn set_read_only(&'s self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::Release);
+    }
+
+    /// A handle to the same database whose queries all stop with an error once `poison` is
+    /// killed, e.g. from another thread. The `:timeout` of a query run through the handle
+    /// kills `poison` too, so that later queries through it fail immediately.
+    pub fn with_cancellation(&'s self, poison: Poison) -> Self {
+        Self {
+            cancel: Some(poison),
+            ..self.clone()
+        }
+    }
+
+    /// A handle to the same database that hands the rows of the query of each script
+    /// consisting of a single query to `sink` as they are read out of the result, instead
+    /// of returning them. The rows of queries storing into relations, of imperative
+    /// scripts and of system ops are returned as before, without calling `sink`.
+    #[cfg(feature = "async")]
+    pub(crate) fn with_row_sink(&'s self, sink: Arc<dyn RowSink>) -> Self {
+        Self {
+            row_sink: Some(sink),
+            ..self.clone()
+        }
+    }
+
+    fn ensure_writable(&'s self, what: &str) -> Result<()> {
+        if self.read_only.load(Ordering::Acquire) {
+            bail!(ReadOnlyMode(what.to_string()))
+        }
+        Ok(())
+    }
+
+    /// Put the stored relations named without a namespace in `namespace`, created with
+    /// `::namespace create`, or in no namespace if `None`.
+    ///
+    /// Relations in other namespaces are still available by their qualified names,
+    /// such as `tenant_a.employee`, but `::relations` only lists those in the namespace.
+    pub fn set_namespace(&'s self, namespace: Option<&str>) -> Result<()> {
+        if let Some(ns) = namespace {
+            if !self.transact()?.namespace_exists(ns)? {
+                bail!(NamespaceNotFound(ns.to_string()));
+            }
         }
+        *self.namespace.lock().unwrap() = namespace.map(SmartString::from);
+        self.plan_cache.lock().unwrap().clear();
+        Ok(())
+    }
+
+    /// The namespace set with [Db::set_namespace].
+    pub fn namespace(&'s self) -> Option<String> {
+        self.namespace
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|ns| ns.to_string())
+    }
+
+    /// List the stored relations, including indices, as `::relations` does.
+    pub fn relations(&'s self) -> Result<Vec<RelationInfo>> {
+        self.transact()?.relation_infos()
+    }
+
+    /// List the columns of a stored relation, keys first, as `::columns` does.
+    pub fn columns(&'s self, relation: &str) -> Result<Vec<ColumnInfo>> {
+        self.transact()?.column_infos(relation)
+    }
+
+    /// Remove a stored relation, as `::remove` does. Relations with indices
+    /// can only be removed with `cascade` set, which removes the indices as well.
+    pub fn remove_relation(&'s self, relation: &str, cascade: bool) -> Result<()> {
+        let rel = Symbol::new(relation, Default::default());
+        self.ensure_writable("remove relations")?;
+        self.run_sys_op(SysOp::RemoveRelation(vec![rel], cascade), false)?;
+        Ok(())
+    }
+
+    /// Rename a stored relation together with its indices, as `::rename` does.
+    pub fn rename_relation(&'s self, old: &str, new: &str) -> Result<()> {
+        let old = Symbol::new(old, Default::default());
+        let new = Symbol::new_checked(new, Default::default(), NameKind::Relation)?;
+        self.ensure_writable("rename relations")?;
+        self.run_sys_op(SysOp::RenameRelation(vec![(old, new)]), false)?;
+        Ok(())
+    }
+
+    /// Create an index on `columns` of a stored relation, as `::index create` does.
+    /// Queries filtering these columns by constants then seek the index
+    /// instead of scanning the relation. The `payload` columns are stored in the
+    /// index as well, so that queries needing only indexed columns never read the relation.
+    pub fn create_index(
+        &'s self,
+        relation: &str,
+        index: &str,
+        columns: &[&str],
+        payload: &[&str],
+    ) -> Result<()> {
+        let to_symbols = |cols: &[&str]| {
+            cols.iter()
+                .map(|c| Symbol::new_checked(c, Default::default(), NameKind::Column))
+                .try_collect()
+        };
+        let rel = Symbol::new(relation, Default::default());
+        let idx = Symbol::new_checked(index, Default::default(), NameKind::Index)?;
+        self.ensure_writable("create indices")?;
+        self.run_sys_op(
+            SysOp::CreateIndex(rel, idx, to_symbols(columns)?, to_symbols(payload)?),
+            false,
+        )?;
+        Ok(())
     }
 
     /// Export relations to JSON data.
@@ -485,7 +1217,7 @@ impl<'s, S: Storage<'s>> Db<S> {
             let mut rows = vec![];
             for data in tx.store_tx.range_scan(&start, &end) {
                 let (k, v) = data?;
-                let tuple = decode_tuple_from_kv(&k, &v, Some(size_hint));
+                let tuple = try_decode_tuple_from_kv(&k, &v, Some(size_hint))?;
                 rows.push(tuple);
             }
             let headers = cols.iter().map(|col| col.to_string()).collect_vec();
@@ -501,141 +1233,142 @@ impl<'s, S: Storage<'s>> Db<S> {
     /// Note that triggers and callbacks are _not_ run for the relations, if any exists.
     /// If you need to activate triggers or callbacks, use queries with parameters.
     pub fn import_relations(&'s self, data: BTreeMap<String, NamedRows>) -> Result<()> {
-        #[derive(Debug, Diagnostic, Error)]
-        #[error("cannot import data for relation '{0}': {1}")]
-        #[diagnostic(code(import::bad_data))]
-        struct BadDataForRelation(String, JsonValue);
-
+        self.ensure_writable("import relations")?;
         let rel_names = data.keys().map(SmartString::from).collect_vec();
         let locks = self.obtain_relation_locks(rel_names.iter());
         let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
 
-        let cur_vld = current_validity();
-
         let mut tx = self.transact_write()?;
 
         for (relation_op, in_data) in data {
-            let is_delete;
-            let relation: &str = match relation_op.strip_prefix('-') {
-                None => {
-                    is_delete = false;
-                    &relation_op
-                }
-                Some(s) => {
-                    is_delete = true;
-                    s
-                }
-            };
-            if relation.contains(':') {
-                bail!(ImportIntoIndex(relation.to_string()))
-            }
-            let handle = tx.get_relation(relation, false)?;
-            let has_indices = !handle.indices.is_empty();
+            import_rows(&mut tx, &relation_op, &in_data.headers, in_data.rows, 0)?;
+        }
+        tx.commit_tx()?;
+        Ok(())
+    }
+    /// Import relations from JSON in the shape of what was returned by
+    /// [Self::export_relations], as [Self::import_relations] does, but without
+    /// holding all of the data in memory: rows are parsed as they are read and
+    /// written in transactions of at most `batch_size` rows each, so that an error
+    /// leaves the batches before it imported. For each relation, `headers` must
+    /// come before `rows`. Returns the number of rows read for each relation.
+    pub fn import_relations_from_reader(
+        &'s self,
+        reader: impl Read,
+        batch_size: usize,
+    ) -> Result<BTreeMap<String, usize>> {
+        self.ensure_writable("import relations")?;
+        stream_relations(
+            reader,
+            batch_size,
+            &mut |relation_op, headers, rows, first_row| {
+                let rel_name =
+                    SmartString::from(relation_op.strip_prefix('-').unwrap_or(relation_op));
+                let locks = self.obtain_relation_locks(iter::once(&rel_name));
+                let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
+                let mut tx = self.transact_write()?;
+                import_rows(&mut tx, relation_op, headers, rows, first_row)?;
+                tx.commit_tx()
+            },
+        )
+    }
+    /// Store `rows` into the stored relation `relation`, replacing the rows with the same keys.
+    ///
+    /// Each row is serialized with serde and its fields matched with the columns of the relation
+    /// by name: every column needs a field, and every field a column. `Option`s map to nullable
+    /// columns, nested structs and maps to `Json` columns.
+    ///
+    /// As with [Self::import_relations], triggers and callbacks are _not_ run. Unlike there,
+    /// `relation` cannot start with `-` to remove the rows instead.
+    pub fn insert_structs<T: Serialize>(&'s self, relation: &str, rows: &[T]) -> Result<()> {
+        self.ensure_writable("insert rows")?;
+        if relation.starts_with('-') {
+            bail!("Cannot insert into '{relation}': 'insert_structs' does not remove rows")
+        }
+        let rel_name = SmartString::from(relation);
+        let locks = self.obtain_relation_locks(iter::once(&rel_name));
+        let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
 
-            if handle.access_level < AccessLevel::Protected {
-                bail!(InsufficientAccessLevel(
-                    handle.name.to_string(),
-                    "data import".to_string(),
-                    handle.access_level
-                ));
+        let mut tx = self.transact_write()?;
+        let handle = tx.get_relation(relation, false)?;
+        let columns = handle
+            .metadata
+            .keys
+            .iter()
+            .chain(handle.metadata.non_keys.iter())
+            .collect_vec();
+        let headers = columns.iter().map(|c| c.name.to_string()).collect_vec();
+        let cur_vld = current_validity();
+        let tuples: Vec<_> = rows
+            .iter()
+            .map(|row| struct_to_row(row, relation, &columns, cur_vld))
+            .try_collect()?;
+        import_rows(&mut tx, relation, &headers, tuples, 0)?;
+        tx.commit_tx()?;
+        Ok(())
+    }
+    /// Run a read-only script and deserialize each row of its result into `T`,
+    /// taking the column names of the result as field names.
+    pub fn query_as<T: DeserializeOwned>(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<Vec<T>> {
+        let res = self.run_script(payload, params, ScriptMutability::Immutable)?;
+        res.rows
+            .into_iter()
+            .enumerate()
+            .map(|(idx, row)| row_to_struct(&res.headers, idx, row))
+            .collect()
+    }
+    /// Run a read-only script and return its result as Arrow record batches
+    /// of at most `batch_size` rows each.
+    ///
+    /// Columns holding values of different types are given as strings, which is logged
+    /// and, when collecting metrics, added to the warnings of [Db::last_query_metrics].
+    #[cfg(feature = "arrow")]
+    pub fn run_query_arrow(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        batch_size: usize,
+    ) -> Result<Vec<RecordBatch>> {
+        let res = self.run_script(payload, params, ScriptMutability::Immutable)?;
+        let (batches, warnings) = rows_to_record_batches(&res.headers, &res.rows, batch_size)?;
+        for warning in &warnings {
+            log::warn!("{warning}");
+        }
+        if self.collect_metrics.load(Ordering::Acquire) {
+            if let Some(metrics) = self.last_query_metrics.lock().unwrap().as_mut() {
+                metrics.warnings.extend(warnings);
             }
+        }
+        Ok(batches)
+    }
+    /// Insert the rows of Arrow record batches into a stored relation, matching the
+    /// columns of the batches with those of the relation by name.
+    #[cfg(feature = "arrow")]
+    pub fn insert_arrow(&'s self, relation: &str, batches: &[RecordBatch]) -> Result<()> {
+        self.ensure_writable("insert rows")?;
+        let rel_name = SmartString::from(relation);
+        let locks = self.obtain_relation_locks(iter::once(&rel_name));
+        let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
 
-            let header2idx: BTreeMap<_, _> = in_data
-                .headers
-                .iter()
-                .enumerate()
-                .map(|(i, k)| -> Result<(&str, usize)> { Ok((k as &str, i)) })
-                .try_collect()?;
-
-            let key_indices: Vec<_> = handle
-                .metadata
-                .keys
-                .iter()
-                .map(|col| -> Result<(usize, &ColumnDef)> {
-                    let idx = header2idx.get(&col.name as &str).ok_or_else(|| {
-                        miette!(
-                            "required header {} not found for relation {}",
-                            col.name,
-                            relation
-                        )
-                    })?;
-                    Ok((*idx, col))
-                })
-                .try_collect()?;
-
-            let val_indices: Vec<_> = if is_delete {
-                vec![]
-            } else {
-                handle
-                    .metadata
-                    .non_keys
-                    .iter()
-                    .map(|col| -> Result<(usize, &ColumnDef)> {
-                        let idx = header2idx.get(&col.name as &str).ok_or_else(|| {
-                            miette!(
-                                "required header {} not found for relation {}",
-                                col.name,
-                                relation
-                            )
-                        })?;
-                        Ok((*idx, col))
-                    })
-                    .try_collect()?
-            };
-
-            for row in in_data.rows {
-                let keys: Vec<_> = key_indices
-                    .iter()
-                    .map(|(i, col)| -> Result<DataValue> {
-                        let v = row
-                            .get(*i)
-                            .ok_or_else(|| miette!("row too short: {:?}", row))?;
-                        col.typing.coerce(v.clone(), cur_vld)
-                    })
-                    .try_collect()?;
-                let k_store = handle.encode_key_for_store(&keys, Default::default())?;
-                if has_indices {
-                    if let Some(existing) = tx.store_tx.get(&k_store, false)? {
-                        let mut old = keys.clone();
-                        extend_tuple_from_v(&mut old, &existing);
-                        if is_delete || old != row {
-                            for (idx_rel, extractor) in handle.indices.values() {
-                                let idx_tup =
-                                    extractor.iter().map(|i| old[*i].clone()).collect_vec();
-                                let encoded =
-                                    idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
-                                tx.store_tx.del(&encoded)?;
-                            }
-                        }
-                    }
-                }
-                if is_delete {
-                    tx.store_tx.del(&k_store)?;
-                } else {
-                    let vals: Vec<_> = val_indices
-                        .iter()
-                        .map(|(i, col)| -> Result<DataValue> {
-                            let v = row
-                                .get(*i)
-                                .ok_or_else(|| miette!("row too short: {:?}", row))?;
-                            col.typing.coerce(v.clone(), cur_vld)
-                        })
-                        .try_collect()?;
-                    let v_store = handle.encode_val_only_for_store(&vals, Default::default())?;
-                    tx.store_tx.put(&k_store, &v_store)?;
-                    if has_indices {
-                        let mut kv = keys;
-                        kv.extend(vals);
-                        for (idx_rel, extractor) in handle.indices.values() {
-                            let idx_tup = extractor.iter().map(|i| kv[*i].clone()).collect_vec();
-                            let encoded =
-                                idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
-                            tx.store_tx.put(&encoded, &[])?;
-                        }
-                    }
-                }
-            }
+        let mut tx = self.transact_write()?;
+        let handle = tx.get_relation(relation, false)?;
+        let columns = handle
+            .metadata
+            .keys
+            .iter()
+            .chain(handle.metadata.non_keys.iter())
+            .collect_vec();
+        let headers = columns.iter().map(|c| c.name.to_string()).collect_vec();
+        let cur_vld = current_validity();
+        let mut tuples = vec![];
+        for batch in batches {
+            tuples.extend(record_batch_to_rows(batch, relation, &columns, cur_vld)?);
         }
+        import_rows(&mut tx, relation, &headers, tuples, 0)?;
         tx.commit_tx()?;
         Ok(())
     }
@@ -660,6 +1393,7 @@ impl<'s, S: Storage<'s>> Db<S> {
     /// Restore from an Sqlite backup
     #[allow(unused_variables)]
     pub fn restore_backup(&'s self, in_file: impl AsRef<Path>) -> Result<()> {
+        self.ensure_writable("restore backups")?;
         #[cfg(feature = "storage-sqlite")]
         {
             let sqlite_db = crate::new_cozo_sqlite(in_file)?;
@@ -697,6 +1431,7 @@ impl<'s, S: Storage<'s>> Db<S> {
         in_file: impl AsRef<Path>,
         relations: &[String],
     ) -> Result<()> {
+        self.ensure_writable("import from backups")?;
         #[cfg(not(feature = "storage-sqlite"))]
         bail!("backup requires the 'storage-sqlite' feature to be enabled");
 
@@ -756,6 +1491,147 @@ impl<'s, S: Storage<'s>> Db<S> {
             dst_tx.commit_tx()
         }
     }
+    /// Create a consistent snapshot of the database in the directory `dir`, which must not
+    /// exist yet. Only committed data is in the snapshot. With RocksDB, the snapshot shares
+    /// files with the running database through hard links where possible, so it is cheap to
+    /// create, and it can be opened as a database of its own.
+    ///
+    /// The snapshot is specific to the storage engine and to its storage format:
+    /// use [Db::export_all] for backups that should outlive either.
+    pub fn checkpoint(&'s self, dir: impl AsRef<Path>) -> Result<()> {
+        self.db.checkpoint(dir.as_ref())
+    }
+    /// Write out the schema of the database, without its data, as a script recreating
+    /// it: the namespaces, stored relations with their indices, materialized views,
+    /// triggers, descriptions and access levels. Definitions come in the order they
+    /// depend on each other and are otherwise sorted by name, so the script is the
+    /// same for the same schema and can be kept under version control.
+    pub fn dump_schema(&'s self) -> Result<String> {
+        Ok(schema_script(&self.transact()?.schema_objects()?))
+    }
+    /// Create the definitions of a schema script, such as the ones written by
+    /// [Db::dump_schema], that the database does not have yet, all in one transaction.
+    /// Definitions the database already has are skipped if they are identical, and are
+    /// reported together with the ones in the script if not, in which case nothing is
+    /// created. Definitions the database has but the script does not are left alone.
+    pub fn apply_schema(&'s self, script: &str) -> Result<()> {
+        self.ensure_writable("apply schemas")?;
+        // The script is run in a database of its own first, so that it is compared
+        // in the same form as the definitions already there.
+        let scratch = crate::new_cozo_mem()?;
+        scratch
+            .run_script(script, Default::default(), ScriptMutability::Mutable)
+            .wrap_err("The schema script cannot be run")?;
+        let wanted = scratch.transact()?.schema_objects()?;
+        let existing = self.transact()?.schema_objects()?;
+        let missing = missing_schema_objects(&existing, wanted)?;
+        if !missing.is_empty() {
+            self.run_script(
+                &schema_script(&missing),
+                Default::default(),
+                ScriptMutability::Mutable,
+            )?;
+        }
+        Ok(())
+    }
+    /// Plan the migration of the schema of the database to the one of a schema script,
+    /// such as the ones written by [Db::dump_schema]. Changes that cannot be made
+    /// in place, such as changing the keys or the type of a column of a relation,
+    /// are reported as errors. Column order is not migrated: added columns come
+    /// after the existing ones.
+    pub fn plan_migration(&'s self, target_schema: &str) -> Result<MigrationPlan> {
+        let target = crate::new_cozo_mem()?;
+        target
+            .run_script(target_schema, Default::default(), ScriptMutability::Mutable)
+            .wrap_err("The schema script cannot be run")?;
+        let plan = plan_migration(&self.transact()?, &target.transact()?)?;
+        Ok(plan)
+    }
+    /// Carry out a plan from [Db::plan_migration] in one transaction. Plans with
+    /// destructive steps are refused unless `allow_destructive` is set.
+    pub fn apply_migration(&'s self, plan: &MigrationPlan, allow_destructive: bool) -> Result<()> {
+        self.ensure_writable("apply migrations")?;
+        check_destructive_steps(plan, allow_destructive)?;
+        if !plan.is_empty() {
+            self.run_script(
+                &plan.script(),
+                Default::default(),
+                ScriptMutability::Mutable,
+            )?;
+        }
+        Ok(())
+    }
+    /// Write the whole database into an archive file: the namespaces, and the schema
+    /// and rows of every stored relation, indices included. Unlike [Db::backup_db], the
+    /// archive holds decoded rows instead of raw storage, so it can be imported with
+    /// [Db::import_all] into a database using any storage engine or a later storage format.
+    pub fn export_all(&'s self, out_file: impl AsRef<Path>) -> Result<()> {
+        let tx = self.transact()?;
+        let mut out = BufWriter::new(File::create(out_file).into_diagnostic()?);
+        write_archive_header(&mut out)?;
+        for ns in tx.namespaces()? {
+            write_archive_record(&mut out, &ArchiveRecord::Namespace(ns))?;
+        }
+        for handle in tx.relation_handles()? {
+            let size_hint = handle.arity();
+            let lower = Tuple::default().encode_as_key(handle.id);
+            let upper = Tuple::default().encode_as_key(handle.id.next());
+            write_archive_record(&mut out, &ArchiveRecord::Relation(Box::new(handle)))?;
+            for chunk in &tx
+                .store_tx
+                .range_scan(&lower, &upper)
+                .chunks(ARCHIVE_ROWS_PER_RECORD)
+            {
+                let rows = chunk
+                    .map(|kv| {
+                        let (k, v) = kv?;
+                        try_decode_tuple_from_kv(&k, &v, Some(size_hint))
+                    })
+                    .try_collect()?;
+                write_archive_record(&mut out, &ArchiveRecord::Rows(rows))?;
+            }
+        }
+        out.flush().into_diagnostic()
+    }
+    /// Recreate the namespaces and stored relations of an archive written by [Db::export_all],
+    /// with their rows. The database must not have any stored relation.
+    ///
+    /// Note that triggers and callbacks are _not_ run for the imported rows.
+    pub fn import_all(&'s self, in_file: impl AsRef<Path>) -> Result<()> {
+        self.ensure_writable("import archives")?;
+        let mut src = BufReader::new(File::open(in_file).into_diagnostic()?);
+        read_archive_header(&mut src)?;
+        let mut tx = self.transact_write()?;
+        if !tx.relation_handles()?.is_empty() {
+            bail!("Cannot import archive: stored relations exist in the current database. You can only import into a new database.");
+        }
+        let mut last_id = tx.relation_store_id.load(Ordering::SeqCst);
+        let mut current: Option<RelationHandle> = None;
+        while let Some(record) = read_archive_record(&mut src)? {
+            match record {
+                ArchiveRecord::Namespace(ns) => {
+                    tx.create_namespace(&Symbol::new(ns, Default::default()))?
+                }
+                ArchiveRecord::Relation(handle) => {
+                    tx.put_relation_handle(&handle)?;
+                    last_id = last_id.max(handle.id.0);
+                    current = Some(*handle);
+                }
+                ArchiveRecord::Rows(rows) => {
+                    let handle = current
+                        .as_ref()
+                        .ok_or_else(|| miette!("Malformed archive: rows before any relation"))?;
+                    for row in rows {
+                        let key = handle.encode_key_for_store(&row, Default::default())?;
+                        let val = handle.encode_val_for_store(&row, Default::default())?;
+                        tx.store_tx.put(&key, &val)?;
+                    }
+                }
+            }
+        }
+        tx.set_last_relation_id(RelationId::new(last_id))?;
+        tx.commit_tx()
+    }
     /// Register a custom fixed rule implementation.
     pub fn register_fixed_rule<R>(&self, name: String, rule_impl: R) -> Result<()>
     where
@@ -780,6 +1656,7 @@ impl<'s, S: Storage<'s>> Db<S> {
         if DEFAULT_FIXED_RULES.contains_key(name) {
             bail!("Cannot unregister builtin fixed rule {}", name);
         }
+        self.plan_cache.lock().unwrap().clear();
         Ok(self.fixed_rules.write().unwrap().remove(name).is_some())
     }
 
@@ -855,13 +1732,33 @@ impl<'s, S: Storage<'s>> Db<S> {
         collected
     }
 
-    fn compact_relation(&'s self) -> Result<()> {
-        let l = Tuple::default().encode_as_key(RelationId(0));
-        let u = vec![DataValue::Bot].encode_as_key(RelationId(u64::MAX));
-        self.db.range_compact(&l, &u)?;
+    fn compact_ranges(
+        &'s self,
+        ranges: &[(Vec<u8>, Vec<u8>)],
+        drop_tombstones: bool,
+    ) -> Result<()> {
+        for (l, u) in ranges {
+            if drop_tombstones {
+                self.db.range_compact_dropping_tombstones(l, u)?;
+            } else {
+                self.db.range_compact(l, u)?;
+            }
+        }
         Ok(())
     }
 
+    /// The approximate number of bytes a stored relation and its indices take in storage,
+    /// as estimated by the storage engine. After removing many rows,
+    /// `::compact <relation>` may be needed for the storage to shrink.
+    pub fn relation_disk_usage(&'s self, relation: &str) -> Result<u64> {
+        let ranges = self.transact()?.relation_key_ranges(relation)?;
+        let mut size = 0;
+        for (l, u) in ranges {
+            size += self.db.approximate_size(&l, &u)?;
+        }
+        Ok(size)
+    }
+
     fn load_last_ids(&'s self) -> Result<()> {
         let mut tx = self.transact_write()?;
         self.relation_store_id
@@ -876,6 +1773,17 @@ impl<'s, S: Storage<'s>> Db<S> {
             relation_store_id: self.relation_store_id.clone(),
             temp_store_id: Default::default(),
             tokenizers: self.tokenizers.clone(),
+            memory: Default::default(),
+            skip_corrupt: self.skip_corrupt.load(Ordering::Acquire),
+            corrupt_skipped: Default::default(),
+            namespace: self.namespace.lock().unwrap().clone(),
+            case_insensitive_names: self.case_insensitive_names.load(Ordering::Acquire),
+            catalog_version: self.catalog_version.clone(),
+            catalog_changed: false,
+            now: self.now(),
+            trigger_depth: 0,
+            insert_outcomes: None,
+            row_sink: None,
         };
         Ok(ret)
     }
@@ -886,6 +1794,17 @@ impl<'s, S: Storage<'s>> Db<S> {
             relation_store_id: self.relation_store_id.clone(),
             temp_store_id: Default::default(),
             tokenizers: self.tokenizers.clone(),
+            memory: Default::default(),
+            skip_corrupt: self.skip_corrupt.load(Ordering::Acquire),
+            corrupt_skipped: Default::default(),
+            namespace: self.namespace.lock().unwrap().clone(),
+            case_insensitive_names: self.case_insensitive_names.load(Ordering::Acquire),
+            catalog_version: self.catalog_version.clone(),
+            catalog_changed: false,
+            now: self.now(),
+            trigger_depth: 0,
+            insert_outcomes: None,
+            row_sink: None,
         };
         Ok(ret)
     }
@@ -942,6 +1861,7 @@ impl<'s, S: Storage<'s>> Db<S> {
             } else {
                 self.transact()?
             };
+            tx.row_sink = self.row_sink.clone();
 
             res = self.execute_single_program(
                 p,
@@ -965,7 +1885,24 @@ impl<'s, S: Storage<'s>> Db<S> {
 
         Ok(res)
     }
-    fn explain_compiled(&self, strata: &[CompiledProgram]) -> Result<NamedRows> {
+    fn explain_program(&self, tx: &mut SessionTx<'_>, prog: &InputProgram) -> Result<NamedRows> {
+        let (normalized_program, out_opts) = prog.clone().into_normalized_program(tx)?;
+        let (stratified_program, _) = normalized_program.into_stratified_program()?;
+        let program = stratified_program.magic_sets_rewrite(tx)?;
+        let mut compiled = tx.stratified_magic_compile(program)?;
+        if out_opts.limit.is_some() || out_opts.after.is_some() {
+            let after = out_opts.after.as_ref().map(|c| &c.key[..]);
+            scan_in_sort_order(&mut compiled, &out_opts.sorters, after)?;
+        }
+        self.explain_compiled(&compiled, None)
+    }
+    /// Describes the compiled plan as `::explain` does. With `metrics`, the operators are
+    /// annotated with the counters collected while running it.
+    fn explain_compiled(
+        &self,
+        strata: &[CompiledProgram],
+        metrics: Option<&BTreeMap<usize, Rc<OpMetrics>>>,
+    ) -> Result<NamedRows> {
         let mut ret: Vec<JsonValue> = vec![];
         const STRATUM: &str = "stratum";
         const ATOM_IDX: &str = "atom_idx";
@@ -976,8 +1913,12 @@ impl<'s, S: Storage<'s>> Db<S> {
         const OUT_BINDINGS: &str = "out_relation";
         const JOINS_ON: &str = "joins_on";
         const FILTERS: &str = "filters/expr";
+        const KEY_RANGE: &str = "key_range";
+        const ROWS: &str = "rows";
+        const TIME: &str = "time_ms";
+        const BUFFERED: &str = "buffered";
 
-        let headers = vec![
+        let mut headers = vec![
             STRATUM.to_string(),
             RULE_IDX.to_string(),
             RULE_NAME.to_string(),
@@ -987,14 +1928,23 @@ impl<'s, S: Storage<'s>> Db<S> {
             JOINS_ON.to_string(),
             FILTERS.to_string(),
             OUT_BINDINGS.to_string(),
+            KEY_RANGE.to_string(),
         ];
+        if metrics.is_some() {
+            headers.extend([ROWS.to_string(), TIME.to_string(), BUFFERED.to_string()]);
+        }
+        let op_metrics = |key: usize| metrics.and_then(|m| m.get(&key));
+        let time_ms = |elapsed: Duration| elapsed.as_secs_f64() * 1000.;
+        // operators joined by lookups spend their time within the join
+        let mut lookup_times = BTreeMap::new();
 
         for (stratum, p) in strata.iter().enumerate() {
             let mut clause_idx = -1;
             for (rule_name, v) in p {
                 match v {
                     CompiledRuleSet::Rules(rules) => {
-                        for CompiledRule { aggr, relation, .. } in rules.iter() {
+                        for rule in rules.iter() {
+                            let CompiledRule { aggr, relation, .. } = rule;
                             clause_idx += 1;
                             let mut ret_for_relation = vec![];
                             let mut rel_stack = vec![relation];
@@ -1010,17 +1960,25 @@ impl<'s, S: Storage<'s>> Db<S> {
                                 }
                             }
 
-                            ret_for_relation.push(json!({
+                            let mut out_row = json!({
                                 STRATUM: stratum,
                                 ATOM_IDX: idx,
                                 OP: atom_type,
                                 RULE_IDX: clause_idx,
                                 RULE_NAME: rule_name.to_string(),
                                 OUT_BINDINGS: relation.bindings_after_eliminate().into_iter().map(|v| v.to_string()).collect_vec()
-                            }));
+                            });
+                            if let Some(m) = op_metrics(rule.aggr_metrics_key()) {
+                                out_row[BUFFERED] = json!(m.buffered.get());
+                            }
+                            ret_for_relation.push(out_row);
                             idx += 1;
 
                             while let Some(rel) = rel_stack.pop() {
+                                let key_range = match rel {
+                                    RelAlgebra::Stored(s) => explain_key_range(s)?,
+                                    _ => json!(null),
+                                };
                                 let (atom_type, ref_name, joins_on, filters) = match rel {
                                     r @ RelAlgebra::Fixed(..) => {
                                         if r.is_unit() {
@@ -1039,9 +1997,18 @@ impl<'s, S: Storage<'s>> Db<S> {
                                         json!(filters.iter().map(|f| f.to_string()).collect_vec()),
                                     ),
                                     RelAlgebra::Stored(StoredRA {
-                                        storage, filters, ..
+                                        storage,
+                                        filters,
+                                        keys_only,
+                                        reverse,
+                                        ..
                                     }) => (
-                                        "load_stored",
+                                        match (*keys_only, *reverse) {
+                                            (true, true) => "load_stored_keys_rev",
+                                            (true, false) => "load_stored_keys",
+                                            (false, true) => "load_stored_rev",
+                                            (false, false) => "load_stored",
+                                        },
                                         json!(format!(":{}", storage.name)),
                                         json!(null),
                                         json!(filters.iter().map(|f| f.to_string()).collect_vec()),
@@ -1057,6 +2024,18 @@ impl<'s, S: Storage<'s>> Db<S> {
                                         json!(filters.iter().map(|f| f.to_string()).collect_vec()),
                                     ),
                                     RelAlgebra::Join(inner) => {
+                                        if let (Some(join_m), Some(left_m)) = (
+                                            op_metrics(rel.metrics_key()),
+                                            op_metrics(inner.left.metrics_key()),
+                                        ) {
+                                            lookup_times.insert(
+                                                inner.right.metrics_key(),
+                                                join_m
+                                                    .elapsed
+                                                    .get()
+                                                    .saturating_sub(left_m.elapsed.get()),
+                                            );
+                                        }
                                         if inner.left.is_unit() {
                                             rel_stack.push(&inner.right);
                                             continue;
@@ -1116,6 +2095,12 @@ impl<'s, S: Storage<'s>> Db<S> {
                                             json!(expr.to_string()),
                                         )
                                     }
+                                    RelAlgebra::Series(SeriesRA { series, binding }) => (
+                                        "series",
+                                        json!(binding.name),
+                                        json!(null),
+                                        json!(series.to_string()),
+                                    ),
                                     RelAlgebra::HnswSearch(HnswSearchRA {
                                         hnsw_search, ..
                                     }) => (
@@ -1149,7 +2134,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                                             .collect_vec()),
                                     ),
                                 };
-                                ret_for_relation.push(json!({
+                                let mut row = json!({
                                     STRATUM: stratum,
                                     ATOM_IDX: idx,
                                     OP: atom_type,
@@ -1159,7 +2144,20 @@ impl<'s, S: Storage<'s>> Db<S> {
                                     OUT_BINDINGS: rel.bindings_after_eliminate().into_iter().map(|v| v.to_string()).collect_vec(),
                                     JOINS_ON: joins_on,
                                     FILTERS: filters,
-                                }));
+                                    KEY_RANGE: key_range,
+                                });
+                                if let Some(m) = op_metrics(rel.metrics_key()) {
+                                    let elapsed = match lookup_times.get(&rel.metrics_key()) {
+                                        Some(t) if m.lookup.get() => *t,
+                                        _ => m.elapsed.get(),
+                                    };
+                                    row[ROWS] = json!(m.rows.get());
+                                    row[TIME] = json!(time_ms(elapsed));
+                                    if m.buffered.get() > 0 {
+                                        row[BUFFERED] = json!(m.buffered.get());
+                                    }
+                                }
+                                ret_for_relation.push(row);
                                 idx += 1;
                             }
                             ret_for_relation.reverse();
@@ -1176,6 +2174,14 @@ impl<'s, S: Storage<'s>> Db<S> {
                 }
             }
         }
+        if let Some(m) = op_metrics(SORT_KEY) {
+            ret.push(json!({
+                OP: "sort",
+                ROWS: m.rows.get(),
+                TIME: time_ms(m.elapsed.get()),
+                BUFFERED: m.buffered.get(),
+            }));
+        }
 
         let rows = ret
             .into_iter()
@@ -1187,7 +2193,90 @@ impl<'s, S: Storage<'s>> Db<S> {
             })
             .collect_vec();
 
-        Ok(NamedRows::new(headers, rows))
+        Ok(NamedRows::new(headers, rows))
+    }
+    /// Keeps the counters collected for the operators of the query that just ran, if enabled.
+    fn record_query_metrics(&self, compiled: &[CompiledProgram], tx: &SessionTx<'_>) -> Result<()> {
+        if metrics_enabled() {
+            let ops = take_op_metrics();
+            let plan = self.explain_compiled(compiled, Some(&ops))?;
+            let corrupt_skipped = tx.corrupt_skipped.load(Ordering::Relaxed);
+            set_last_query_metrics(QueryMetrics {
+                plan,
+                corrupt_skipped,
+                warnings: vec![],
+            });
+        }
+        Ok(())
+    }
+    /// Rejects triggers that would end up firing themselves again, by following the writes
+    /// of the triggers of all stored relations, with `puts` and `rms` as the triggers of
+    /// `name`. Triggers that are not single queries are not followed.
+    fn check_trigger_cycles(
+        &self,
+        tx: &SessionTx<'_>,
+        name: &Symbol,
+        puts: &[String],
+        rms: &[String],
+    ) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("The triggers of stored relation '{0}' would fire themselves: {1}")]
+        #[diagnostic(code(eval::trigger_cycle))]
+        struct TriggerCycle(String, String);
+
+        let target = tx.get_relation(name, false)?.name;
+        let cur_vld = current_validity();
+        let fixed_rules = self.fixed_rules.read().unwrap();
+        let mut edges: BTreeMap<TriggerEvent, BTreeSet<TriggerEvent>> = BTreeMap::new();
+        for handle in tx.relation_handles()? {
+            let (rel_puts, rel_rms) = if handle.name == target {
+                (puts, rms)
+            } else {
+                (&handle.put_triggers[..], &handle.rm_triggers[..])
+            };
+            for (event, triggers) in [("put", rel_puts), ("rm", rel_rms)] {
+                for trigger in triggers {
+                    let program =
+                        match parse_script(trigger, &Default::default(), &fixed_rules, cur_vld)
+                            .and_then(|script| script.get_single_program())
+                        {
+                            Ok(program) => program,
+                            Err(_) => continue,
+                        };
+                    let Some((written, op, _)) = &program.out_opts.store_relation else {
+                        continue;
+                    };
+                    let fired = match op {
+                        RelationOp::Put
+                        | RelationOp::Insert
+                        | RelationOp::Upsert
+                        | RelationOp::Update => "put",
+                        RelationOp::Rm | RelationOp::Delete => "rm",
+                        _ => continue,
+                    };
+                    let Ok(written) = tx.get_relation(&written.name, false) else {
+                        continue;
+                    };
+                    edges
+                        .entry((handle.name.clone(), event))
+                        .or_default()
+                        .insert((written.name, fired));
+                }
+            }
+        }
+
+        for start in [(target.clone(), "put"), (target.clone(), "rm")] {
+            let mut path = vec![start.clone()];
+            let mut visited = BTreeSet::new();
+            if trigger_path_back(&edges, &start, &mut path, &mut visited) {
+                let path = path
+                    .iter()
+                    .map(|(rel, event)| format!("{rel} ({event})"))
+                    .join(" -> ");
+                bail!(TriggerCycle(target.to_string(), path));
+            }
+        }
+        Ok(())
     }
     pub(crate) fn run_sys_op_with_tx(
         &'s self,
@@ -1196,19 +2285,40 @@ impl<'s, S: Storage<'s>> Db<S> {
         read_only: bool,
         skip_locking: bool,
     ) -> Result<NamedRows> {
+        if !matches!(
+            op,
+            SysOp::Explain(_)
+                | SysOp::Compact(..)
+                | SysOp::ListRelations
+                | SysOp::ListFixedRules
+                | SysOp::ListColumns(_)
+                | SysOp::ListIndices(_)
+                | SysOp::ListRunning
+                | SysOp::KillRunning(_)
+                | SysOp::ShowTrigger(_)
+                | SysOp::CheckRelation(_)
+                | SysOp::ListNamespaces
+                | SysOp::PurgeDeleted(..)
+                | SysOp::ExpireRows(_)
+                | SysOp::RefreshView(_)
+                | SysOp::CompactHistory(..)
+        ) {
+            tx.catalog_changed = true;
+        }
         match op {
-            SysOp::Explain(prog) => {
-                let (normalized_program, _) = prog.clone().into_normalized_program(tx)?;
-                let (stratified_program, _) = normalized_program.into_stratified_program()?;
-                let program = stratified_program.magic_sets_rewrite(tx)?;
-                let compiled = tx.stratified_magic_compile(program)?;
-                self.explain_compiled(&compiled)
-            }
-            SysOp::Compact => {
+            SysOp::Explain(prog) => self.explain_program(tx, prog),
+            SysOp::Compact(rel, drop_tombstones) => {
                 if read_only {
                     bail!("Cannot compact in read-only mode");
                 }
-                self.compact_relation()?;
+                let ranges = match rel {
+                    None => vec![(
+                        Tuple::default().encode_as_key(RelationId(0)),
+                        vec![DataValue::Bot].encode_as_key(RelationId(u64::MAX)),
+                    )],
+                    Some(rel) => tx.relation_key_ranges(rel)?,
+                };
+                self.compact_ranges(&ranges, *drop_tombstones)?;
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
                     vec![vec![DataValue::from(OK_STR)]],
@@ -1225,7 +2335,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                         .collect_vec(),
                 ))
             }
-            SysOp::RemoveRelation(rel_names) => {
+            SysOp::RemoveRelation(rel_names, cascade) => {
                 if read_only {
                     bail!("Cannot remove relations in read-only mode");
                 }
@@ -1238,7 +2348,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                 let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
                 let mut bounds = vec![];
                 for rs in rel_names {
-                    let bound = tx.destroy_relation(rs)?;
+                    let bound = tx.remove_relation(rs, *cascade)?;
                     if !rs.is_temp_store_name() {
                         bounds.extend(bound);
                     }
@@ -1258,19 +2368,19 @@ impl<'s, S: Storage<'s>> Db<S> {
                     vec![vec![DataValue::from(OK_STR)]],
                 ))
             }
-            SysOp::CreateIndex(rel_name, idx_name, cols) => {
+            SysOp::CreateIndex(rel_name, idx_name, cols, payload) => {
                 if read_only {
                     bail!("Cannot create index in read-only mode");
                 }
                 if skip_locking {
-                    tx.create_index(rel_name, idx_name, cols)?;
+                    tx.create_index(rel_name, idx_name, cols, payload)?;
                 } else {
                     let lock = self
                         .obtain_relation_locks(iter::once(&rel_name.name))
                         .pop()
                         .unwrap();
                     let _guard = lock.write().unwrap();
-                    tx.create_index(rel_name, idx_name, cols)?;
+                    tx.create_index(rel_name, idx_name, cols, payload)?;
                 }
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
@@ -1421,12 +2531,224 @@ impl<'s, S: Storage<'s>> Db<S> {
                 if read_only {
                     bail!("Cannot set triggers in read-only mode");
                 }
+                self.check_trigger_cycles(tx, name, puts, rms)?;
                 tx.set_relation_triggers(name, puts, rms, replaces)?;
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
                     vec![vec![DataValue::from(OK_STR)]],
                 ))
             }
+            SysOp::AddColumn(name, col) => {
+                if read_only {
+                    bail!("Cannot add columns in read-only mode");
+                }
+                if skip_locking {
+                    tx.add_column(name, col)?;
+                } else {
+                    let lock = self
+                        .obtain_relation_locks(iter::once(&name.name))
+                        .pop()
+                        .unwrap();
+                    let _guard = lock.write().unwrap();
+                    tx.add_column(name, col)?;
+                }
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::DropColumn(name, col) => {
+                if read_only {
+                    bail!("Cannot drop columns in read-only mode");
+                }
+                if skip_locking {
+                    tx.drop_column(name, col)?;
+                } else {
+                    let lock = self
+                        .obtain_relation_locks(iter::once(&name.name))
+                        .pop()
+                        .unwrap();
+                    let _guard = lock.write().unwrap();
+                    tx.drop_column(name, col)?;
+                }
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::SoftDelete(name) => {
+                if read_only {
+                    bail!("Cannot enable soft deletes in read-only mode");
+                }
+                if skip_locking {
+                    tx.enable_soft_delete(name)?;
+                } else {
+                    let lock = self
+                        .obtain_relation_locks(iter::once(&name.name))
+                        .pop()
+                        .unwrap();
+                    let _guard = lock.write().unwrap();
+                    tx.enable_soft_delete(name)?;
+                }
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::PurgeDeleted(name, before) => {
+                if read_only {
+                    bail!("Cannot purge deleted rows in read-only mode");
+                }
+                if skip_locking {
+                    tx.purge_deleted(name, *before)?;
+                } else {
+                    let lock = self
+                        .obtain_relation_locks(iter::once(&name.name))
+                        .pop()
+                        .unwrap();
+                    let _guard = lock.write().unwrap();
+                    tx.purge_deleted(name, *before)?;
+                }
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::SetTtl(name, ttl) => {
+                if read_only {
+                    bail!("Cannot set the TTL in read-only mode");
+                }
+                if skip_locking {
+                    tx.set_ttl(name, *ttl)?;
+                } else {
+                    let lock = self
+                        .obtain_relation_locks(iter::once(&name.name))
+                        .pop()
+                        .unwrap();
+                    let _guard = lock.write().unwrap();
+                    tx.set_ttl(name, *ttl)?;
+                }
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::ExpireRows(name) => {
+                if read_only {
+                    bail!("Cannot remove expired rows in read-only mode");
+                }
+                let cur_vld = current_validity();
+                let expire_all = |tx: &mut SessionTx<'_>| -> Result<()> {
+                    let mut from = vec![];
+                    while let Some(next) = tx.expire_rows(self, name, cur_vld, &from)? {
+                        from = next;
+                    }
+                    Ok(())
+                };
+                if skip_locking {
+                    expire_all(tx)?;
+                } else {
+                    let lock = self
+                        .obtain_relation_locks(iter::once(&name.name))
+                        .pop()
+                        .unwrap();
+                    let _guard = lock.write().unwrap();
+                    expire_all(tx)?;
+                }
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::CreateView(name, query) => {
+                if read_only {
+                    bail!("Cannot create views in read-only mode");
+                }
+                let cur_vld = current_validity();
+                if skip_locking {
+                    tx.create_view(self, name, query, cur_vld)?;
+                } else {
+                    let lock = self
+                        .obtain_relation_locks(iter::once(&name.name))
+                        .pop()
+                        .unwrap();
+                    let _guard = lock.write().unwrap();
+                    tx.create_view(self, name, query, cur_vld)?;
+                }
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::RefreshView(name) => {
+                if read_only {
+                    bail!("Cannot refresh views in read-only mode");
+                }
+                let cur_vld = current_validity();
+                if skip_locking {
+                    tx.refresh_view(self, name, cur_vld)?;
+                } else {
+                    let lock = self
+                        .obtain_relation_locks(iter::once(&name.name))
+                        .pop()
+                        .unwrap();
+                    let _guard = lock.write().unwrap();
+                    tx.refresh_view(self, name, cur_vld)?;
+                }
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::CheckRelation(name) => tx.check_relation(name),
+            SysOp::CreateNamespace(ns) => {
+                if read_only {
+                    bail!("Cannot create namespace in read-only mode");
+                }
+                tx.create_namespace(ns)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::DropNamespace(ns, cascade) => {
+                if read_only {
+                    bail!("Cannot drop namespace in read-only mode");
+                }
+                for (lower, upper) in tx.drop_namespace(ns, *cascade)? {
+                    tx.store_tx.del_range_from_persisted(&lower, &upper)?;
+                }
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::ListNamespaces => Ok(NamedRows::new(
+                vec!["namespace".to_string()],
+                tx.namespaces()?
+                    .into_iter()
+                    .map(|ns| vec![DataValue::from(ns)])
+                    .collect(),
+            )),
+            SysOp::CompactHistory(name, before) => {
+                if read_only {
+                    bail!("Cannot compact history in read-only mode");
+                }
+                if skip_locking {
+                    tx.compact_history(name, *before)?;
+                } else {
+                    let lock = self
+                        .obtain_relation_locks(iter::once(&name.name))
+                        .pop()
+                        .unwrap();
+                    let _guard = lock.write().unwrap();
+                    tx.compact_history(name, *before)?;
+                }
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
             SysOp::SetAccessLevel(names, level) => {
                 if read_only {
                     bail!("Cannot set access level in read-only mode");
@@ -1442,6 +2764,9 @@ impl<'s, S: Storage<'s>> Db<S> {
         }
     }
     fn run_sys_op(&'s self, op: SysOp, read_only: bool) -> Result<NamedRows> {
+        if let (SysOp::ExpireRows(name), false) = (&op, read_only) {
+            return self.expire_rows_in_batches(name);
+        }
         let mut tx = if read_only {
             self.transact()?
         } else {
@@ -1451,6 +2776,30 @@ impl<'s, S: Storage<'s>> Db<S> {
         tx.commit_tx()?;
         Ok(res)
     }
+    /// Removes the expired rows of a relation in batches, each in a transaction of its own,
+    /// so that writers to the relation get their turns in between.
+    fn expire_rows_in_batches(&'s self, name: &Symbol) -> Result<NamedRows> {
+        let cur_vld = current_validity();
+        let lock = self
+            .obtain_relation_locks(iter::once(&name.name))
+            .pop()
+            .unwrap();
+        let mut from = vec![];
+        loop {
+            let _guard = lock.write().unwrap();
+            let mut tx = self.transact_write()?;
+            let next = tx.expire_rows(self, name, cur_vld, &from)?;
+            tx.commit_tx()?;
+            match next {
+                Some(next) => from = next,
+                None => break,
+            }
+        }
+        Ok(NamedRows::new(
+            vec![STATUS_STR.to_string()],
+            vec![vec![DataValue::from(OK_STR)]],
+        ))
+    }
     /// This is the entry to query evaluation
     pub(crate) fn run_query(
         &self,
@@ -1466,6 +2815,9 @@ impl<'s, S: Storage<'s>> Db<S> {
 
         // Some checks in case the query specifies mutation
         if let Some((meta, op, _)) = &input_program.out_opts.store_relation {
+            if matches!(op, RelationOp::Create | RelationOp::Replace) {
+                tx.catalog_changed = true;
+            }
             if *op == RelationOp::Create {
                 #[derive(Debug, Error, Diagnostic)]
                 #[error("Stored relation {0} conflicts with an existing one")]
@@ -1477,34 +2829,98 @@ impl<'s, S: Storage<'s>> Db<S> {
                     StoreRelationConflict(meta.name.to_string())
                 )
             } else if *op != RelationOp::Replace {
-                #[derive(Debug, Error, Diagnostic)]
-                #[error("Stored relation {0} not found")]
-                #[diagnostic(code(eval::stored_relation_not_found))]
-                struct StoreRelationNotFoundError(String);
-
+                // fails if the relation does not exist
                 let existing = tx.get_relation(&meta.name, false)?;
 
-                ensure!(
-                    tx.relation_exists(&meta.name)?,
-                    StoreRelationNotFoundError(meta.name.to_string())
-                );
-
                 existing.ensure_compatible(
                     meta,
-                    *op == RelationOp::Rm || *op == RelationOp::Delete || *op == RelationOp::Update,
+                    matches!(
+                        op,
+                        RelationOp::Rm
+                            | RelationOp::Delete
+                            | RelationOp::Update
+                            | RelationOp::Upsert
+                    ),
                 )?;
             }
         };
 
+        input_program.check_cursor()?;
+
         // query compilation
         let entry_head_or_default = input_program.get_entry_out_head_or_default()?;
         let (normalized_program, out_opts) = input_program.into_normalized_program(tx)?;
         let (stratified_program, store_lifetimes) = normalized_program.into_stratified_program()?;
         let program = stratified_program.magic_sets_rewrite(tx)?;
-        let compiled = tx.stratified_magic_compile(program)?;
+        let mut compiled = tx.stratified_magic_compile(program)?;
+        // with a limit, sorting can be skipped if the rows are scanned in sorted order,
+        // and with a cursor, the scan can seek past it
+        let after = out_opts.after.as_ref().map(|c| &c.key[..]);
+        let sorted_by_scan = (out_opts.limit.is_some() || after.is_some())
+            && scan_in_sort_order(&mut compiled, &out_opts.sorters, after)?;
+
+        let (res, to_clear) = self.run_compiled_query(
+            tx,
+            &entry_head_or_default,
+            &compiled,
+            store_lifetimes,
+            &out_opts,
+            sorted_by_scan,
+            cur_vld,
+            callback_targets,
+            callback_collector,
+            top_level,
+        )?;
+        clean_ups.extend(to_clear);
+        Ok((res, clean_ups))
+    }
+    /// Run a plan made by [QueryPlan::compile] or rebuilt from JSON.
+    pub(crate) fn run_query_plan(
+        &self,
+        tx: &mut SessionTx<'_>,
+        plan: QueryPlan,
+    ) -> Result<NamedRows> {
+        let out_opts = QueryOutOptions {
+            limit: plan.limit,
+            offset: plan.offset,
+            max_hops: plan.hop_limit.map(|(n, _)| n as u32),
+            truncate_hops: plan.hop_limit.is_some_and(|(_, truncate)| truncate),
+            sorters: plan.sorters,
+            ..Default::default()
+        };
+        let (res, _) = self.run_compiled_query(
+            tx,
+            &plan.head,
+            &plan.strata,
+            plan.store_lifetimes,
+            &out_opts,
+            plan.sorted_by_scan,
+            current_validity(),
+            &Default::default(),
+            &mut Default::default(),
+            true,
+        )?;
+        Ok(res)
+    }
+    /// Evaluate a compiled query and collect or store its results.
+    fn run_compiled_query(
+        &self,
+        tx: &mut SessionTx<'_>,
+        entry_head_or_default: &[Symbol],
+        compiled: &[CompiledProgram],
+        store_lifetimes: BTreeMap<MagicSymbol, usize>,
+        out_opts: &QueryOutOptions,
+        sorted_by_scan: bool,
+        cur_vld: ValidityTs,
+        callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+        callback_collector: &mut CallbackCollector,
+        top_level: bool,
+    ) -> Result<(NamedRows, Vec<(Vec<u8>, Vec<u8>)>)> {
+        let mut clean_ups = vec![];
+        let after = out_opts.after.as_ref().map(|c| &c.key[..]);
 
         // poison is used to terminate queries early
-        let poison = Poison::default();
+        let poison = self.cancel.clone().unwrap_or_default();
         if let Some(secs) = out_opts.timeout {
             poison.set_timeout(secs)?;
         }
@@ -1526,7 +2942,7 @@ impl<'s, S: Storage<'s>> Db<S> {
             running_queries: self.running_queries.clone(),
         };
 
-        let total_num_to_take = if out_opts.sorters.is_empty() {
+        let total_num_to_take = if out_opts.sorters.is_empty() || sorted_by_scan {
             out_opts.num_to_take()
         } else {
             None
@@ -1538,12 +2954,26 @@ impl<'s, S: Storage<'s>> Db<S> {
             None
         };
 
+        // counters left over from queries run while compiling this one
+        take_op_metrics();
+        tx.corrupt_skipped.store(0, Ordering::Relaxed);
+        // buffering operators count the memory they hold against the limits
+        let memory = Arc::new(MemoryAccountant::new(
+            *self.memory_limits.lock().unwrap(),
+            self.memory_used.clone(),
+        ));
+        tx.memory = memory.clone();
+        let _memory_release = MemoryRelease(memory);
+
         // the real evaluation
         let (result_store, early_return) = tx.stratified_magic_evaluate(
-            &compiled,
+            compiled,
             store_lifetimes,
             total_num_to_take,
             num_to_skip,
+            out_opts
+                .max_hops
+                .map(|n| (n as usize, out_opts.truncate_hops)),
             poison,
         )?;
 
@@ -1575,8 +3005,24 @@ impl<'s, S: Storage<'s>> Db<S> {
 
         if !out_opts.sorters.is_empty() {
             // sort outputs if required
-            let sorted_result =
-                tx.sort_and_collect(result_store, &out_opts.sorters, &entry_head_or_default)?;
+            let sort = || {
+                tx.sort_and_collect(
+                    result_store,
+                    &out_opts.sorters,
+                    entry_head_or_default,
+                    after,
+                )
+            };
+            let sorted_result = match op_metrics(SORT_KEY) {
+                None => sort()?,
+                Some(metrics) => {
+                    let sorted_result = metrics.time(sort)?;
+                    metrics.add_rows(sorted_result.len());
+                    metrics.buffer(sorted_result.len());
+                    sorted_result
+                }
+            };
+            self.record_query_metrics(compiled, tx)?;
             let sorted_iter = if let Some(offset) = out_opts.offset {
                 Left(sorted_result.into_iter().skip(offset))
             } else {
@@ -1594,7 +3040,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                         sorted_iter,
                         *relation_op,
                         meta,
-                        &entry_head_or_default,
+                        entry_head_or_default,
                         cur_vld,
                         callback_targets,
                         callback_collector,
@@ -1612,7 +3058,8 @@ impl<'s, S: Storage<'s>> Db<S> {
                 Ok((returned_rows, clean_ups))
             } else {
                 // not sorting outputs
-                let rows: Vec<Tuple> = sorted_iter.collect_vec();
+                let rows =
+                    self.collect_result_rows(tx, top_level, entry_head_or_default, sorted_iter)?;
                 Ok((
                     NamedRows::new(
                         entry_head_or_default
@@ -1625,6 +3072,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                 ))
             }
         } else {
+            self.record_query_metrics(compiled, tx)?;
             let scan = if early_return {
                 Right(Left(
                     result_store.early_returned_iter().map(|t| t.into_tuple()),
@@ -1650,7 +3098,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                         scan,
                         *relation_op,
                         meta,
-                        &entry_head_or_default,
+                        entry_head_or_default,
                         cur_vld,
                         callback_targets,
                         callback_collector,
@@ -1668,7 +3116,7 @@ impl<'s, S: Storage<'s>> Db<S> {
 
                 Ok((returned_rows, clean_ups))
             } else {
-                let rows: Vec<Tuple> = scan.collect_vec();
+                let rows = self.collect_result_rows(tx, top_level, entry_head_or_default, scan)?;
 
                 Ok((
                     NamedRows::new(
@@ -1683,6 +3131,31 @@ impl<'s, S: Storage<'s>> Db<S> {
             }
         }
     }
+    fn collect_result_rows(
+        &self,
+        tx: &SessionTx<'_>,
+        top_level: bool,
+        headers: &[Symbol],
+        rows: impl Iterator<Item = Tuple>,
+    ) -> Result<Vec<Tuple>> {
+        if let (true, Some(sink)) = (top_level, &tx.row_sink) {
+            // the rows are not held, so the limit on their number does not apply
+            sink.headers(headers.iter().map(|s| s.to_string()).collect_vec())?;
+            for row in rows {
+                sink.row(row)?;
+            }
+            return Ok(vec![]);
+        }
+        let max = *self.max_result_rows.lock().unwrap();
+        let mut collected = vec![];
+        for row in rows {
+            if let Some(max) = max {
+                ensure!(collected.len() < max, ResultRowLimitExceeded { limit: max });
+            }
+            collected.push(row);
+        }
+        Ok(collected)
+    }
     pub(crate) fn list_running(&self) -> Result<NamedRows> {
         let rows = self
             .running_queries
@@ -1776,104 +3249,66 @@ impl<'s, S: Storage<'s>> Db<S> {
         ))
     }
     fn list_columns(&'s self, tx: &SessionTx<'_>, name: &str) -> Result<NamedRows> {
-        let handle = tx.get_relation(name, false)?;
-        let mut rows = vec![];
-        let mut idx = 0;
-        for col in &handle.metadata.keys {
-            let default_expr = col.default_gen.as_ref().map(|gen| format!("{}", gen));
-
-            rows.push(vec![
-                json!(col.name),
-                json!(true),
-                json!(idx),
-                json!(col.typing.to_string()),
-                json!(col.default_gen.is_some()),
-                json!(default_expr),
-            ]);
-            idx += 1;
-        }
-        for col in &handle.metadata.non_keys {
-            let default_expr = col.default_gen.as_ref().map(|gen| format!("{}", gen));
-
-            rows.push(vec![
-                json!(col.name),
-                json!(false),
-                json!(idx),
-                json!(col.typing.to_string()),
-                json!(col.default_gen.is_some()),
-                json!(default_expr),
-            ]);
-            idx += 1;
-        }
-        let rows = rows
-            .into_iter()
-            .map(|row| row.into_iter().map(DataValue::from).collect_vec())
-            .collect_vec();
         Ok(NamedRows::new(
-            vec![
-                "column".to_string(),
-                "is_key".to_string(),
-                "index".to_string(),
-                "type".to_string(),
-                "has_default".to_string(),
-                "default_expr".to_string(),
-            ],
-            rows,
+            ColumnInfo::HEADERS.iter().map(|h| h.to_string()).collect(),
+            tx.column_infos(name)?
+                .into_iter()
+                .map(ColumnInfo::into_tuple)
+                .collect(),
         ))
     }
     fn list_relations(&'s self, tx: &SessionTx<'_>) -> Result<NamedRows> {
-        let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
-        let upper =
-            vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
-        let mut rows: Vec<Vec<JsonValue>> = vec![];
-        for kv_res in tx.store_tx.range_scan(&lower, &upper) {
-            let (k_slice, v_slice) = kv_res?;
-            if upper <= k_slice {
-                break;
-            }
-            let meta = RelationHandle::decode(&v_slice)?;
-            let n_keys = meta.metadata.keys.len();
-            let n_dependents = meta.metadata.non_keys.len();
-            let arity = n_keys + n_dependents;
-            let name = meta.name;
-            let access_level = if name.contains(':') {
-                "index".to_string()
-            } else {
-                meta.access_level.to_string()
-            };
-            rows.push(vec![
-                json!(name),
-                json!(arity),
-                json!(access_level),
-                json!(n_keys),
-                json!(n_dependents),
-                json!(meta.put_triggers.len()),
-                json!(meta.rm_triggers.len()),
-                json!(meta.replace_triggers.len()),
-                json!(meta.description),
-            ]);
-        }
-        let rows = rows
-            .into_iter()
-            .map(|row| row.into_iter().map(DataValue::from).collect_vec())
-            .collect_vec();
         Ok(NamedRows::new(
-            vec![
-                "name".to_string(),
-                "arity".to_string(),
-                "access_level".to_string(),
-                "n_keys".to_string(),
-                "n_non_keys".to_string(),
-                "n_put_triggers".to_string(),
-                "n_rm_triggers".to_string(),
-                "n_replace_triggers".to_string(),
-                "description".to_string(),
-            ],
-            rows,
+            RelationInfo::HEADERS
+                .iter()
+                .map(|h| h.to_string())
+                .collect(),
+            tx.relation_infos()?
+                .into_iter()
+                .map(RelationInfo::into_tuple)
+                .collect(),
         ))
     }
 }
 
+/// A stored relation and the kind of write to it, `put` or `rm`, firing its triggers.
+type TriggerEvent = (SmartString<LazyCompact>, &'static str);
+
+/// Looks for a way from the last event of `path` back to its first one through `edges`,
+/// leaving the way found in `path`.
+fn trigger_path_back(
+    edges: &BTreeMap<TriggerEvent, BTreeSet<TriggerEvent>>,
+    start: &TriggerEvent,
+    path: &mut Vec<TriggerEvent>,
+    visited: &mut BTreeSet<TriggerEvent>,
+) -> bool {
+    let Some(next) = edges.get(path.last().unwrap()) else {
+        return false;
+    };
+    for event in next {
+        path.push(event.clone());
+        if event == start {
+            return true;
+        }
+        if visited.insert(event.clone()) && trigger_path_back(edges, start, path, visited) {
+            return true;
+        }
+        path.pop();
+    }
+    false
+}
+
+/// Whether the plan of a query may be cached: it must only read, and must not have
+/// options that are not part of its plan.
+fn plan_cacheable(program: &InputProgram) -> bool {
+    let opts = &program.out_opts;
+    opts.store_relation.is_none()
+        && opts.assertion.is_none()
+        && opts.after.is_none()
+        && opts.timeout.is_none()
+        && opts.sleep.is_none()
+}
+
 /// Evaluate a string expression in the context of a set of parameters and variables
 pub fn evaluate_expressions(
     src: &str,
@@ -1940,6 +3375,10 @@ impl Poison {
         }
         Ok(())
     }
+    /// Terminate the queries using this poison.
+    pub fn kill(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
     #[cfg(target_arch = "wasm32")]
     pub(crate) fn set_timeout(&self, _secs: f64) -> Result<()> {
         bail!("Cannot set timeout when threading is disallowed");
@@ -1964,6 +3403,32 @@ pub(crate) fn seconds_since_the_epoch() -> Result<f64> {
         .into_diagnostic()?
         .as_secs_f64());
 
+    // milliseconds in JS
     #[cfg(target_arch = "wasm32")]
-    Ok(js_sys::Date::now())
+    Ok(js_sys::Date::now() / 1000.)
+}
+
+fn explain_key_range(rel: &StoredRA) -> Result<JsonValue> {
+    let (lower, upper) = rel.key_bounds(0)?;
+    if lower.is_empty() {
+        return Ok(json!(null));
+    }
+    let mut ret = serde_json::Map::new();
+    for ((binding, l), u) in rel.bindings.iter().zip(lower).zip(upper) {
+        if l == DataValue::Null && u == DataValue::Bot {
+            continue;
+        }
+        let l = if l == DataValue::Null {
+            JsonValue::Null
+        } else {
+            JsonValue::from(l)
+        };
+        let u = if u == DataValue::Bot {
+            JsonValue::Null
+        } else {
+            JsonValue::from(u)
+        };
+        ret.insert(binding.name.to_string(), json!([l, u]));
+    }
+    Ok(JsonValue::Object(ret))
 }
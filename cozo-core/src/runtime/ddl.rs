@@ -0,0 +1,501 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Schemas as scripts: the definitions in the catalog written out as the statements
+//! recreating them, for [crate::Db::dump_schema] and [crate::Db::apply_schema].
+//!
+//! A schema is an imperative script with one statement per line, in the order the
+//! definitions depend on each other: namespaces, relations, indices, views, triggers,
+//! descriptions and access levels. Within each group definitions are sorted by name,
+//! so the same catalog always gives the same script.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use itertools::Itertools;
+use miette::{Diagnostic, IntoDiagnostic, Result};
+use pest::Parser;
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::relation::{ColumnDef, VecElementType};
+use crate::data::value::{DataValue, Num};
+use crate::fts::TokenizerConfig;
+use crate::parse::expr::build_expr;
+use crate::parse::sys::HnswDistance;
+use crate::parse::{quote_ident, CozoScriptParser, Rule};
+use crate::runtime::relation::{AccessLevel, RelationHandle, SOFT_DELETE_COL, TTL_COL};
+use crate::runtime::transact::SessionTx;
+
+/// The kinds of definitions, in the order they are created
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum SchemaObjectKind {
+    Namespace,
+    Relation,
+    Index,
+    View,
+    Triggers,
+    Description,
+    AccessLevel,
+}
+
+impl Display for SchemaObjectKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SchemaObjectKind::Namespace => "namespace",
+            SchemaObjectKind::Relation => "relation",
+            SchemaObjectKind::Index => "index",
+            SchemaObjectKind::View => "view",
+            SchemaObjectKind::Triggers => "triggers of",
+            SchemaObjectKind::Description => "description of",
+            SchemaObjectKind::AccessLevel => "access level of",
+        })
+    }
+}
+
+/// A definition in the catalog, with the statements recreating it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SchemaObject {
+    pub(crate) kind: SchemaObjectKind,
+    /// The name of what is defined or what the definition belongs to, `relation:index`
+    /// for indices
+    pub(crate) name: String,
+    pub(crate) statements: Vec<String>,
+}
+
+impl SchemaObject {
+    pub(crate) fn key(&self) -> (SchemaObjectKind, &str) {
+        (self.kind, &self.name)
+    }
+}
+
+impl Display for SchemaObject {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.kind, self.name)
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The schema conflicts with existing definitions:\n{0}")]
+#[diagnostic(code(eval::schema_conflict))]
+#[diagnostic(help(
+    "Lines starting with '-' are the existing definitions, those starting with '+' the ones in the schema"
+))]
+pub(crate) struct SchemaConflict(pub(crate) String);
+
+/// Writes out the definitions as a script.
+pub(crate) fn schema_script(objects: &[SchemaObject]) -> String {
+    let mut ret = String::new();
+    for obj in objects {
+        for stmt in &obj.statements {
+            ret.push_str(stmt);
+            ret.push('\n');
+        }
+    }
+    ret
+}
+
+/// The definitions of `new` that are not in `old`, in the order of `new`.
+/// Definitions in both must be identical.
+pub(crate) fn missing_schema_objects(
+    old: &[SchemaObject],
+    new: Vec<SchemaObject>,
+) -> Result<Vec<SchemaObject>> {
+    let old: BTreeMap<_, _> = old.iter().map(|obj| (obj.key(), obj)).collect();
+    let mut conflicts = vec![];
+    let mut missing = vec![];
+    for obj in new {
+        match old.get(&obj.key()) {
+            None => missing.push(obj),
+            Some(existing) => {
+                if existing.statements != obj.statements {
+                    let mut diff = obj.to_string();
+                    for stmt in &existing.statements {
+                        diff.push_str(&format!("\n- {stmt}"));
+                    }
+                    for stmt in &obj.statements {
+                        diff.push_str(&format!("\n+ {stmt}"));
+                    }
+                    conflicts.push(diff);
+                }
+            }
+        }
+    }
+    if !conflicts.is_empty() {
+        return Err(SchemaConflict(conflicts.join("\n")).into());
+    }
+    Ok(missing)
+}
+
+impl<'a> SessionTx<'a> {
+    /// The definitions in the catalog, in the order they must be created.
+    pub(crate) fn schema_objects(&self) -> Result<Vec<SchemaObject>> {
+        let handles: BTreeMap<_, _> = self
+            .relation_handles()?
+            .into_iter()
+            .filter(|handle| !handle.is_temp)
+            .map(|handle| (handle.name.clone(), handle))
+            .collect();
+        let (views, relations): (Vec<_>, Vec<_>) = handles
+            .values()
+            .filter(|handle| !handle.name.contains(':'))
+            .partition(|handle| handle.view.is_some());
+
+        let mut ret = vec![];
+        for ns in self.namespaces()?.into_iter().sorted() {
+            ret.push(SchemaObject {
+                kind: SchemaObjectKind::Namespace,
+                name: ns.clone(),
+                statements: vec![format!("{{::namespace create {}}}", quote_ident(&ns))],
+            })
+        }
+        for handle in &relations {
+            let mut statements = vec![create_statement(handle)];
+            if handle.soft_delete {
+                statements.push(format!("{{::soft_delete {}}}", quote_ident(&handle.name)));
+            }
+            if let Some(ttl) = handle.ttl {
+                statements.push(format!(
+                    "{{::ttl {} {}}}",
+                    quote_ident(&handle.name),
+                    float_script(ttl)
+                ));
+            }
+            ret.push(SchemaObject {
+                kind: SchemaObjectKind::Relation,
+                name: handle.name.to_string(),
+                statements,
+            })
+        }
+        for handle in &relations {
+            ret.extend(index_objects(handle)?);
+        }
+        for handle in &views {
+            let manifest = handle.view.as_ref().unwrap();
+            ret.push(SchemaObject {
+                kind: SchemaObjectKind::View,
+                name: handle.name.to_string(),
+                statements: vec![format!(
+                    "{{::view create {} {{{}}}}}",
+                    quote_ident(&handle.name),
+                    manifest.query.trim()
+                )],
+            })
+        }
+        for handle in &relations {
+            let triggers = [
+                ("put", &handle.put_triggers),
+                ("rm", &handle.rm_triggers),
+                ("replace", &handle.replace_triggers),
+            ];
+            if triggers.iter().all(|(_, scripts)| scripts.is_empty()) {
+                continue;
+            }
+            let mut stmt = format!("{{::set_triggers {}", quote_ident(&handle.name));
+            for (event, scripts) in triggers {
+                for script in scripts {
+                    stmt.push_str(&format!(" on {event} {{{}}}", script.trim()));
+                }
+            }
+            stmt.push('}');
+            ret.push(SchemaObject {
+                kind: SchemaObjectKind::Triggers,
+                name: handle.name.to_string(),
+                statements: vec![stmt],
+            })
+        }
+        for handle in handles.values() {
+            if !handle.description.is_empty() {
+                ret.push(SchemaObject {
+                    kind: SchemaObjectKind::Description,
+                    name: handle.name.to_string(),
+                    statements: vec![format!(
+                        "{{::describe {} {}}}",
+                        quote_ident(&handle.name),
+                        DataValue::Str(handle.description.clone())
+                    )],
+                })
+            }
+        }
+        for handle in relations.iter().chain(views.iter()) {
+            if handle.access_level != AccessLevel::Normal {
+                ret.push(SchemaObject {
+                    kind: SchemaObjectKind::AccessLevel,
+                    name: handle.name.to_string(),
+                    statements: vec![format!(
+                        "{{::access_level {} {}}}",
+                        handle.access_level,
+                        quote_ident(&handle.name)
+                    )],
+                })
+            }
+        }
+        Ok(ret)
+    }
+}
+
+fn create_statement(handle: &RelationHandle) -> String {
+    let is_reserved = |col: &&ColumnDef| {
+        (handle.soft_delete && col.name == SOFT_DELETE_COL)
+            || (handle.ttl.is_some() && col.name == TTL_COL)
+    };
+    let keys = handle.metadata.keys.iter().map(column_script).join(", ");
+    let non_keys = handle
+        .metadata
+        .non_keys
+        .iter()
+        .filter(|col| !is_reserved(col))
+        .map(column_script)
+        .join(", ");
+    let name = quote_ident(&handle.name);
+    if non_keys.is_empty() {
+        format!("{{:create {name} {{{keys}}}}}")
+    } else if keys.is_empty() {
+        format!("{{:create {name} {{=> {non_keys}}}}}")
+    } else {
+        format!("{{:create {name} {{{keys} => {non_keys}}}}}")
+    }
+}
+
+pub(crate) fn column_script(col: &ColumnDef) -> String {
+    match &col.default_gen {
+        None => format!("{}: {}", quote_ident(&col.name), col.typing),
+        Some(expr) => format!(
+            "{}: {} default {}",
+            quote_ident(&col.name),
+            col.typing,
+            expr_script(expr)
+        ),
+    }
+}
+
+fn index_objects(handle: &RelationHandle) -> Result<Vec<SchemaObject>> {
+    let col_names = handle
+        .metadata
+        .keys
+        .iter()
+        .chain(handle.metadata.non_keys.iter())
+        .map(|col| &col.name)
+        .collect_vec();
+    let mut ret = vec![];
+    for (name, (idx_handle, _)) in &handle.indices {
+        // Key columns of the relation left out when the index was created are
+        // appended to its keys, listing them all creates the same index.
+        let cols = idx_handle
+            .metadata
+            .keys
+            .iter()
+            .map(|col| quote_ident(&col.name))
+            .join(", ");
+        ret.push((
+            name,
+            format!(
+                "{{::index create {} {{{cols}}}}}",
+                quote_ident(&format!("{}:{name}", handle.name))
+            ),
+        ));
+    }
+    for (name, (_, manifest)) in &handle.hnsw_indices {
+        let mut opts = vec![
+            format!("dim: {}", manifest.vec_dim),
+            format!("m: {}", manifest.m_neighbours),
+            format!("ef: {}", manifest.ef_construction),
+            format!(
+                "dtype: {}",
+                match manifest.dtype {
+                    VecElementType::F32 => "F32",
+                    VecElementType::F64 => "F64",
+                }
+            ),
+            format!(
+                "fields: [{}]",
+                manifest.vec_fields.iter().map(|i| col_names[*i]).join(", ")
+            ),
+            format!(
+                "distance: {}",
+                match manifest.distance {
+                    HnswDistance::L2 => "L2",
+                    HnswDistance::InnerProduct => "IP",
+                    HnswDistance::Cosine => "Cosine",
+                }
+            ),
+        ];
+        if let Some(filter) = &manifest.index_filter {
+            opts.push(format!("filter: {}", filter.trim()));
+        }
+        opts.push(format!("extend_candidates: {}", manifest.extend_candidates));
+        opts.push(format!(
+            "keep_pruned_connections: {}",
+            manifest.keep_pruned_connections
+        ));
+        ret.push((
+            name,
+            format!(
+                "{{::hnsw create {} {{{}}}}}",
+                quote_ident(&format!("{}:{name}", handle.name)),
+                opts.join(", ")
+            ),
+        ));
+    }
+    for (name, (_, manifest)) in &handle.fts_indices {
+        let opts = text_index_opts(&manifest.extractor, &manifest.tokenizer, &manifest.filters)?;
+        ret.push((
+            name,
+            format!(
+                "{{::fts create {} {{{}}}}}",
+                quote_ident(&format!("{}:{name}", handle.name)),
+                opts.join(", ")
+            ),
+        ));
+    }
+    for (name, (_, _, manifest)) in &handle.lsh_indices {
+        let n_perm = if manifest.n_perm == 0 {
+            manifest.num_perm
+        } else {
+            manifest.n_perm
+        };
+        let mut opts =
+            text_index_opts(&manifest.extractor, &manifest.tokenizer, &manifest.filters)?;
+        opts.extend([
+            format!("n_gram: {}", manifest.n_gram),
+            format!("n_perm: {n_perm}"),
+            format!("target_threshold: {}", float_script(manifest.threshold)),
+            format!(
+                "false_positive_weight: {}",
+                float_script(manifest.false_positive_weight)
+            ),
+            format!(
+                "false_negative_weight: {}",
+                float_script(manifest.false_negative_weight)
+            ),
+        ]);
+        ret.push((
+            name,
+            format!(
+                "{{::lsh create {} {{{}}}}}",
+                quote_ident(&format!("{}:{name}", handle.name)),
+                opts.join(", ")
+            ),
+        ));
+    }
+    Ok(ret
+        .into_iter()
+        .sorted_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(name, stmt)| SchemaObject {
+            kind: SchemaObjectKind::Index,
+            name: format!("{}:{name}", handle.name),
+            statements: vec![stmt],
+        })
+        .collect())
+}
+
+/// The options shared by full-text and LSH indices.
+fn text_index_opts(
+    extractor: &str,
+    tokenizer: &TokenizerConfig,
+    filters: &[TokenizerConfig],
+) -> Result<Vec<String>> {
+    let mut opts = vec![
+        format!("extractor: {}", stored_expr_script(extractor)?),
+        format!("tokenizer: {}", tokenizer_script(tokenizer)),
+    ];
+    if !filters.is_empty() {
+        opts.push(format!(
+            "filters: [{}]",
+            filters.iter().map(tokenizer_script).join(", ")
+        ));
+    }
+    Ok(opts)
+}
+
+fn tokenizer_script(config: &TokenizerConfig) -> String {
+    if config.args.is_empty() {
+        config.name.to_string()
+    } else {
+        format!(
+            "{}({})",
+            config.name,
+            config.args.iter().map(value_script).join(", ")
+        )
+    }
+}
+
+/// Extractors of indices are kept as text, which is parsed again so that equivalent
+/// ones are written the same way.
+fn stored_expr_script(text: &str) -> Result<String> {
+    let parsed = CozoScriptParser::parse(Rule::expr, text)
+        .into_diagnostic()?
+        .next()
+        .unwrap();
+    Ok(expr_script(&build_expr(parsed, &Default::default())?))
+}
+
+/// Writes out an expression as it would be written in a script.
+fn expr_script(expr: &Expr) -> String {
+    match expr {
+        Expr::Binding { var, .. } => var.name.to_string(),
+        Expr::Const { val, .. } => value_script(val),
+        Expr::Apply { op, args, .. } if op.name == "OP_LIST" => {
+            format!("[{}]", args.iter().map(expr_script).join(", "))
+        }
+        Expr::Apply { op, args, .. } => format!(
+            "{}({})",
+            op.name.strip_prefix("OP_").unwrap().to_lowercase(),
+            args.iter().map(expr_script).join(", ")
+        ),
+        Expr::UnboundApply { op, args, .. } => {
+            format!("{op}({})", args.iter().map(expr_script).join(", "))
+        }
+        Expr::Cond { clauses, .. } => format!(
+            "cond({})",
+            clauses
+                .iter()
+                .flat_map(|(cond, val)| [expr_script(cond), expr_script(val)])
+                .join(", ")
+        ),
+    }
+}
+
+fn value_script(val: &DataValue) -> String {
+    match val {
+        DataValue::Num(Num::Float(f)) => float_script(*f),
+        DataValue::Str(s) => string_script(s),
+        DataValue::List(l) => format!("[{}]", l.iter().map(value_script).join(", ")),
+        v => v.to_string(),
+    }
+}
+
+/// Strings are written single-quoted with escapes, as double-quoted strings are raw
+/// and could not hold every string.
+pub(crate) fn string_script(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len() + 2);
+    ret.push('\'');
+    for c in s.chars() {
+        match c {
+            '\'' => ret.push_str("\\'"),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            '\t' => ret.push_str("\\t"),
+            c if c.is_control() => ret.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('\'');
+    ret
+}
+
+/// Floats with no fractional part are written with one, so that they are read back
+/// as floats.
+pub(crate) fn float_script(f: f64) -> String {
+    if f.is_finite() {
+        format!("{f:?}")
+    } else {
+        DataValue::from(f).to_string()
+    }
+}
@@ -16,7 +16,7 @@ use crate::runtime::relation::RelationHandle;
 use crate::runtime::transact::SessionTx;
 use crate::{DataValue, SourceSpan};
 use itertools::Itertools;
-use miette::{bail, miette, Result};
+use miette::{bail, Result};
 use ordered_float::OrderedFloat;
 use priority_queue::PriorityQueue;
 use rand::Rng;
@@ -126,7 +126,7 @@ impl VectorCache {
         tx: &SessionTx<'_>,
     ) -> Result<()> {
         if !self.cache.contains_key(key) {
-            match handle.get(tx, &key.0)? {
+            match handle.get_including_expired(tx, &key.0)? {
                 Some(tuple) => {
                     let mut field = &tuple[key.1];
                     if key.2 >= 0 {
@@ -940,7 +940,8 @@ impl<'a> SessionTx<'a> {
                 return Ok(vec![]);
             }
 
-            if config.filter.is_none() {
+            // expired rows are skipped like filtered ones
+            if config.filter.is_none() && config.base_handle.ttl.is_none() {
                 while found_nn.len() > config.k {
                     found_nn.pop();
                 }
@@ -955,10 +956,11 @@ impl<'a> SessionTx<'a> {
                     }
                 }
 
-                let mut cand_tuple = config
-                    .base_handle
-                    .get(self, &cand_key.0)?
-                    .ok_or_else(|| miette!("corrupted index"))?;
+                let mut cand_tuple = match config.base_handle.get(self, &cand_key.0)? {
+                    Some(tuple) => tuple,
+                    None if config.base_handle.ttl.is_some() => continue,
+                    None => bail!("corrupted index"),
+                };
 
                 // make sure the order is the same as in all_bindings()!!!
                 if config.bind_field.is_some() {
@@ -284,7 +284,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                 self.transact()?
             };
 
-            let poison = Poison::default();
+            let poison = self.cancel.clone().unwrap_or_default();
             let qid = self.queries_count.fetch_add(1, Ordering::AcqRel);
             let since_the_epoch = seconds_since_the_epoch()?;
 
@@ -379,6 +379,8 @@ impl SessionTx<'_> {
             },
             key_bindings,
             dep_bindings: vec![],
+            binding_exprs: Default::default(),
+            on_conflict: Default::default(),
             span: Default::default(),
         };
         let headers = meta.key_bindings.clone();
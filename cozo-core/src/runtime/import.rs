@@ -0,0 +1,179 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::fmt::Formatter;
+use std::io::{BufReader, Read};
+
+use miette::{Diagnostic, Report, Result};
+use serde::de::{DeserializeSeed, Error as _, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::Deserializer;
+use thiserror::Error;
+
+use crate::data::json::JsonValue;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("malformed import data for relation '{0}' near row {1}: {2}")]
+#[diagnostic(code(import::malformed_json))]
+pub(crate) struct MalformedImportData(String, usize, String);
+
+/// Receives a batch of rows for a relation, with its headers and the
+/// position of the first row of the batch within the relation.
+pub(crate) type ImportSink<'a> = dyn FnMut(&str, &[String], Vec<Tuple>, usize) -> Result<()> + 'a;
+
+/// Reads relations in the shape returned by [crate::Db::export_relations] from JSON
+/// without holding the whole payload in memory: rows are parsed one at a time and
+/// passed to `sink` in batches of at most `batch_size`. For each relation, `headers`
+/// must come before `rows`. Returns the number of rows read for each relation.
+pub(crate) fn stream_relations(
+    reader: impl Read,
+    batch_size: usize,
+    sink: &mut ImportSink<'_>,
+) -> Result<BTreeMap<String, usize>> {
+    let mut importer = Importer {
+        batch_size: batch_size.max(1),
+        sink,
+        counts: Default::default(),
+        relation: String::new(),
+        failure: None,
+    };
+    let mut de = serde_json::Deserializer::from_reader(BufReader::new(reader));
+    let res = de.deserialize_map(&mut importer).and_then(|_| de.end());
+    if let Some(err) = importer.failure {
+        return Err(err);
+    }
+    if let Err(err) = res {
+        let row = importer
+            .counts
+            .get(&importer.relation)
+            .copied()
+            .unwrap_or_default();
+        return Err(MalformedImportData(importer.relation, row, err.to_string()).into());
+    }
+    Ok(importer.counts)
+}
+
+struct Importer<'a, 'b> {
+    batch_size: usize,
+    sink: &'a mut ImportSink<'b>,
+    counts: BTreeMap<String, usize>,
+    /// The relation being read, for error reporting
+    relation: String,
+    /// Set when the sink fails, so that its error is reported instead of the parser's
+    failure: Option<Report>,
+}
+
+impl Importer<'_, '_> {
+    fn flush(&mut self, headers: &[String], batch: &mut Vec<Tuple>) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let first_row = self.counts[&self.relation] - batch.len();
+        (self.sink)(&self.relation, headers, std::mem::take(batch), first_row)
+    }
+}
+
+impl<'de> Visitor<'de> for &mut Importer<'_, '_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        formatter.write_str("a map from relation names to headers and rows")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(relation) = map.next_key::<String>()? {
+            self.counts.entry(relation.clone()).or_default();
+            self.relation = relation;
+            map.next_value_seed(RelationSeed(&mut *self))?;
+        }
+        Ok(())
+    }
+}
+
+struct RelationSeed<'i, 'a, 'b>(&'i mut Importer<'a, 'b>);
+
+impl<'de> DeserializeSeed<'de> for RelationSeed<'_, '_, '_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for RelationSeed<'_, '_, '_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        formatter.write_str("a map with `headers` and `rows`")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let mut headers: Option<Vec<String>> = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "headers" => headers = Some(map.next_value()?),
+                "rows" => {
+                    let headers = headers
+                        .as_ref()
+                        .ok_or_else(|| A::Error::custom("`headers` must come before `rows`"))?;
+                    map.next_value_seed(RowsSeed {
+                        importer: &mut *self.0,
+                        headers,
+                    })?;
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct RowsSeed<'i, 'h, 'a, 'b> {
+    importer: &'i mut Importer<'a, 'b>,
+    headers: &'h [String],
+}
+
+impl<'de> DeserializeSeed<'de> for RowsSeed<'_, '_, '_, '_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for RowsSeed<'_, '_, '_, '_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        formatter.write_str("a list of rows")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let importer = self.importer;
+        let mut batch = Vec::with_capacity(importer.batch_size);
+        while let Some(row) = seq.next_element::<Vec<JsonValue>>()? {
+            batch.push(row.into_iter().map(DataValue::from).collect());
+            *importer.counts.get_mut(&importer.relation).unwrap() += 1;
+            if batch.len() >= importer.batch_size {
+                if let Err(err) = importer.flush(self.headers, &mut batch) {
+                    importer.failure = Some(err);
+                    return Err(A::Error::custom("import aborted"));
+                }
+            }
+        }
+        if let Err(err) = importer.flush(self.headers, &mut batch) {
+            importer.failure = Some(err);
+            return Err(A::Error::custom("import aborted"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use miette::{Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::memcmp::MemCmpEncoder;
+use crate::data::value::DataValue;
+
+/// Limits on the memory held by the operators that buffer rows while evaluating queries:
+/// aggregations, sorting and joins that materialize one of their sides.
+///
+/// Memory is counted as the size of the rows held, encoded as in stored keys, and is
+/// released when the query ends. The limits are off by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryLimits {
+    /// The bytes a single query may hold
+    pub per_query: Option<usize>,
+    /// The bytes all queries running on the database may hold together
+    pub per_session: Option<usize>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Memory limit of {limit} bytes per {scope} exceeded by {op}")]
+#[diagnostic(code(eval::memory_limit_exceeded))]
+#[diagnostic(help("The limits can be raised with `set_memory_limits`"))]
+pub(crate) struct MemoryLimitExceeded {
+    pub(crate) op: String,
+    pub(crate) limit: usize,
+    pub(crate) scope: &'static str,
+}
+
+/// Counts the bytes held by the buffering operators of a query, and by those of all the
+/// queries of the database in `session_used`.
+#[derive(Debug, Default)]
+pub(crate) struct MemoryAccountant {
+    limits: MemoryLimits,
+    used: AtomicUsize,
+    session_used: Arc<AtomicUsize>,
+}
+
+impl MemoryAccountant {
+    pub(crate) fn new(limits: MemoryLimits, session_used: Arc<AtomicUsize>) -> Self {
+        Self {
+            limits,
+            used: Default::default(),
+            session_used,
+        }
+    }
+    /// Counts a row held by the operator described by `op`, failing if this exceeds a limit.
+    /// Does nothing if there are no limits.
+    pub(crate) fn hold(&self, row: &[DataValue], op: impl FnOnce() -> String) -> Result<()> {
+        if self.limits == MemoryLimits::default() {
+            return Ok(());
+        }
+        let mut encoded = vec![];
+        for val in row {
+            encoded.encode_datavalue(val);
+        }
+        let bytes = encoded.len();
+        let used = self.used.fetch_add(bytes, Ordering::AcqRel) + bytes;
+        let session_used = self.session_used.fetch_add(bytes, Ordering::AcqRel) + bytes;
+        let exceeded = match (self.limits.per_query, self.limits.per_session) {
+            (Some(limit), _) if used > limit => Some((limit, "query")),
+            (_, Some(limit)) if session_used > limit => Some((limit, "session")),
+            _ => None,
+        };
+        match exceeded {
+            None => Ok(()),
+            Some((limit, scope)) => Err(MemoryLimitExceeded {
+                op: op(),
+                limit,
+                scope,
+            }
+            .into()),
+        }
+    }
+    /// Gives back all the memory counted so far, as the query has ended.
+    pub(crate) fn release(&self) {
+        let used = self.used.swap(0, Ordering::AcqRel);
+        self.session_used.fetch_sub(used, Ordering::AcqRel);
+    }
+}
+
+/// Releases the memory counted for a query when it ends, however it ends.
+pub(crate) struct MemoryRelease(pub(crate) Arc<MemoryAccountant>);
+
+impl Drop for MemoryRelease {
+    fn drop(&mut self) {
+        self.0.release()
+    }
+}
@@ -0,0 +1,383 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Migrations: the steps bringing the schema of a database to the one of a schema
+//! script, planned by [crate::Db::plan_migration] by comparing the definitions of
+//! both as written out for [crate::Db::dump_schema].
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::relation::ColumnDef;
+use crate::parse::quote_ident;
+use crate::runtime::ddl::{column_script, float_script, SchemaObject, SchemaObjectKind};
+use crate::runtime::relation::{AccessLevel, RelationHandle, SOFT_DELETE_COL, TTL_COL};
+use crate::runtime::transact::SessionTx;
+
+/// A step of a [MigrationPlan]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationStep {
+    /// What the step does, such as `add column hired to relation hr.employee`
+    pub description: String,
+    /// The statement carrying out the step, a block of an imperative script
+    pub script: String,
+    /// Whether the step loses data, as dropping columns and removing relations
+    /// or namespaces do. Indices and materialized views are not data.
+    pub destructive: bool,
+}
+
+/// The steps bringing the schema of a database to a target schema, in the order
+/// they must be carried out, as planned by [crate::Db::plan_migration].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrationPlan {
+    /// The steps of the plan
+    pub steps: Vec<MigrationStep>,
+}
+
+impl MigrationPlan {
+    /// Whether the schema is already the target one
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+    /// The steps losing data
+    pub fn destructive_steps(&self) -> impl Iterator<Item = &MigrationStep> {
+        self.steps.iter().filter(|step| step.destructive)
+    }
+    /// The imperative script carrying out the plan
+    pub fn script(&self) -> String {
+        self.steps
+            .iter()
+            .map(|step| format!("{}\n", step.script))
+            .collect()
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The schema cannot be migrated to the target schema:\n{0}")]
+#[diagnostic(code(eval::incompatible_migration))]
+#[diagnostic(help(
+    "Such changes need the relations to be created anew, with their rows copied over"
+))]
+pub(crate) struct IncompatibleMigration(pub(crate) String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The migration loses data:\n{0}")]
+#[diagnostic(code(eval::destructive_migration))]
+#[diagnostic(help("Allow destructive steps to apply the migration anyway"))]
+pub(crate) struct DestructiveMigration(pub(crate) String);
+
+/// Fails if the plan has destructive steps that are not allowed.
+pub(crate) fn check_destructive_steps(plan: &MigrationPlan, allow_destructive: bool) -> Result<()> {
+    if !allow_destructive && plan.destructive_steps().next().is_some() {
+        bail!(DestructiveMigration(
+            plan.destructive_steps()
+                .map(|step| step.description.as_str())
+                .join("\n")
+        ))
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Planner {
+    /// Access levels lowered so that the relations can be changed
+    unlock: Vec<MigrationStep>,
+    /// Views, indices, relations and namespaces going away, in this order
+    drops: Vec<MigrationStep>,
+    creates: Vec<MigrationStep>,
+    /// Triggers, descriptions and access levels
+    finish: Vec<MigrationStep>,
+    incompatible: Vec<String>,
+    /// The relations changed, whose access level must allow it
+    touched: BTreeSet<String>,
+}
+
+fn step(description: String, script: String, destructive: bool) -> MigrationStep {
+    MigrationStep {
+        description,
+        script,
+        destructive,
+    }
+}
+
+/// The relation an index or a relation with the given name belongs to.
+fn relation_of(name: &str) -> &str {
+    name.split_once(':').map(|(rel, _)| rel).unwrap_or(name)
+}
+
+fn of_kind(objects: &[SchemaObject], kind: SchemaObjectKind) -> Vec<&SchemaObject> {
+    objects.iter().filter(|obj| obj.kind == kind).collect()
+}
+
+/// Plans the migration of the schema seen by `live` to the one seen by `target`.
+pub(crate) fn plan_migration(
+    live: &SessionTx<'_>,
+    target: &SessionTx<'_>,
+) -> Result<MigrationPlan> {
+    let old_objects = live.schema_objects()?;
+    let new_objects = target.schema_objects()?;
+    let old: BTreeMap<_, _> = old_objects.iter().map(|obj| (obj.key(), obj)).collect();
+    let new: BTreeMap<_, _> = new_objects.iter().map(|obj| (obj.key(), obj)).collect();
+    let mut planner = Planner::default();
+
+    // Indices and views are dropped when they change, and created anew
+    let mut removed = BTreeSet::new();
+    let mut recreated = BTreeSet::new();
+    for kind in [SchemaObjectKind::View, SchemaObjectKind::Index] {
+        for obj in of_kind(&old_objects, kind) {
+            match new.get(&obj.key()) {
+                Some(target) if target.statements == obj.statements => continue,
+                Some(_) => recreated.insert(obj.name.clone()),
+                None => removed.insert(obj.name.clone()),
+            };
+            let script = match kind {
+                SchemaObjectKind::View => format!("{{::remove {}}}", quote_ident(&obj.name)),
+                _ => format!("{{::index drop {}}}", quote_ident(&obj.name)),
+            };
+            if kind == SchemaObjectKind::Index {
+                planner.touched.insert(relation_of(&obj.name).to_string());
+            }
+            planner
+                .drops
+                .push(step(format!("drop {obj}"), script, false));
+        }
+    }
+    for obj in of_kind(&old_objects, SchemaObjectKind::Relation) {
+        if !new.contains_key(&obj.key()) {
+            removed.insert(obj.name.clone());
+            planner.touched.insert(obj.name.clone());
+            planner.drops.push(step(
+                format!("remove {obj}"),
+                format!("{{::remove {} cascade}}", quote_ident(&obj.name)),
+                true,
+            ));
+        }
+    }
+    for obj in of_kind(&old_objects, SchemaObjectKind::Namespace) {
+        if !new.contains_key(&obj.key()) {
+            planner.drops.push(step(
+                format!("drop {obj}"),
+                format!("{{::namespace drop {}}}", quote_ident(&obj.name)),
+                true,
+            ));
+        }
+    }
+
+    for kind in [SchemaObjectKind::Namespace, SchemaObjectKind::Relation] {
+        for obj in of_kind(&new_objects, kind) {
+            match old.get(&obj.key()) {
+                None => planner.creates.push(step(
+                    format!("create {obj}"),
+                    obj.statements.join("\n"),
+                    false,
+                )),
+                Some(existing) if existing.statements != obj.statements => {
+                    planner.touched.insert(obj.name.clone());
+                    planner.alter_relation(
+                        &live.get_relation(&obj.name, false)?,
+                        &target.get_relation(&obj.name, false)?,
+                    )
+                }
+                Some(_) => {}
+            }
+        }
+    }
+    for kind in [SchemaObjectKind::Index, SchemaObjectKind::View] {
+        for obj in of_kind(&new_objects, kind) {
+            if !old.contains_key(&obj.key()) || recreated.contains(&obj.name) {
+                if kind == SchemaObjectKind::Index {
+                    planner.touched.insert(relation_of(&obj.name).to_string());
+                }
+                planner.creates.push(step(
+                    format!("create {obj}"),
+                    obj.statements.join("\n"),
+                    false,
+                ));
+            }
+        }
+    }
+
+    // What is left belongs to relations, indices and views: it goes away with them,
+    // and must be set again for the ones created anew
+    let is_gone = |name: &str| removed.contains(name) || removed.contains(relation_of(name));
+    for kind in [SchemaObjectKind::Triggers, SchemaObjectKind::Description] {
+        for obj in of_kind(&new_objects, kind) {
+            let unchanged = old
+                .get(&obj.key())
+                .is_some_and(|existing| existing.statements == obj.statements);
+            if !unchanged || recreated.contains(&obj.name) {
+                if kind == SchemaObjectKind::Triggers {
+                    planner.touched.insert(obj.name.clone());
+                }
+                planner
+                    .finish
+                    .push(step(format!("set {obj}"), obj.statements.join("\n"), false));
+            }
+        }
+        for obj in of_kind(&old_objects, kind) {
+            if new.contains_key(&obj.key()) || is_gone(&obj.name) || recreated.contains(&obj.name) {
+                continue;
+            }
+            let script = match kind {
+                SchemaObjectKind::Triggers => {
+                    planner.touched.insert(obj.name.clone());
+                    format!("{{::set_triggers {}}}", quote_ident(&obj.name))
+                }
+                _ => format!("{{::describe {} \"\"}}", quote_ident(&obj.name)),
+            };
+            planner
+                .finish
+                .push(step(format!("clear {obj}"), script, false));
+        }
+    }
+
+    // Relations to be changed are made writable first, and given the access level
+    // of the target at the end
+    let level_of = |objects: &BTreeMap<(SchemaObjectKind, &str), &SchemaObject>, name: &str| {
+        objects
+            .get(&(SchemaObjectKind::AccessLevel, name))
+            .map(|obj| obj.statements.clone())
+    };
+    for obj in of_kind(&old_objects, SchemaObjectKind::AccessLevel) {
+        if planner.touched.contains(&obj.name) {
+            planner.unlock.push(step(
+                format!("lower {obj} for the migration"),
+                format!(
+                    "{{::access_level {} {}}}",
+                    AccessLevel::Normal,
+                    quote_ident(&obj.name)
+                ),
+                false,
+            ));
+        }
+    }
+    let names: BTreeSet<_> = old_objects
+        .iter()
+        .chain(new_objects.iter())
+        .filter(|obj| obj.kind == SchemaObjectKind::AccessLevel)
+        .map(|obj| obj.name.as_str())
+        .collect();
+    for name in names {
+        let old_level = level_of(&old, name);
+        let new_level = level_of(&new, name);
+        let relocked = planner.touched.contains(name) && old_level.is_some();
+        if is_gone(name) || (old_level == new_level && !relocked && !recreated.contains(name)) {
+            continue;
+        }
+        match new_level {
+            Some(statements) => planner.finish.push(step(
+                format!("set access level of {name}"),
+                statements.join("\n"),
+                false,
+            )),
+            None if !relocked => planner.finish.push(step(
+                format!("set access level of {name}"),
+                format!(
+                    "{{::access_level {} {}}}",
+                    AccessLevel::Normal,
+                    quote_ident(name)
+                ),
+                false,
+            )),
+            None => {}
+        }
+    }
+
+    if !planner.incompatible.is_empty() {
+        bail!(IncompatibleMigration(planner.incompatible.join("\n")))
+    }
+    Ok(MigrationPlan {
+        steps: [
+            planner.unlock,
+            planner.drops,
+            planner.creates,
+            planner.finish,
+        ]
+        .concat(),
+    })
+}
+
+impl Planner {
+    /// Plans the changes to the columns and to the handling of rows of a relation
+    /// in both schemas. Only its non-key columns can be changed.
+    fn alter_relation(&mut self, old: &RelationHandle, new: &RelationHandle) {
+        let name = &old.name;
+        let quoted = quote_ident(name);
+        let col_list = |cols: &[ColumnDef]| cols.iter().map(column_script).join(", ");
+        // Columns are compared as written out, as their defaults keep where they were parsed
+        let old_keys = col_list(&old.metadata.keys);
+        let new_keys = col_list(&new.metadata.keys);
+        if old_keys != new_keys {
+            self.incompatible.push(format!(
+                "the keys of relation {name} would change from {{{old_keys}}} to {{{new_keys}}}"
+            ));
+        }
+        let non_keys = |handle: &RelationHandle| {
+            handle
+                .metadata
+                .non_keys
+                .iter()
+                .filter(|col| {
+                    !(handle.soft_delete && col.name == SOFT_DELETE_COL
+                        || handle.ttl.is_some() && col.name == TTL_COL)
+                })
+                .map(|col| (col.name.clone(), column_script(col)))
+                .collect::<BTreeMap<_, _>>()
+        };
+        let old_cols = non_keys(old);
+        let new_cols = non_keys(new);
+        for (col_name, col) in &old_cols {
+            match new_cols.get(col_name) {
+                None => self.creates.push(step(
+                    format!("drop column {col_name} of relation {name}"),
+                    format!("{{::alter {quoted} drop {}}}", quote_ident(col_name)),
+                    true,
+                )),
+                Some(new_col) if new_col != col => self.incompatible.push(format!(
+                    "column {col_name} of relation {name} would change from `{col}` to `{new_col}`"
+                )),
+                Some(_) => {}
+            }
+        }
+        // Columns are added after the existing ones, whatever their order in the target
+        for col in new.metadata.non_keys.iter() {
+            if new_cols.contains_key(&col.name) && !old_cols.contains_key(&col.name) {
+                self.creates.push(step(
+                    format!("add column {} to relation {name}", col.name),
+                    format!("{{::alter {quoted} add {}}}", column_script(col)),
+                    false,
+                ))
+            }
+        }
+        match (old.soft_delete, new.soft_delete) {
+            (false, true) => self.creates.push(step(
+                format!("keep removed rows of relation {name}"),
+                format!("{{::soft_delete {quoted}}}"),
+                false,
+            )),
+            (true, false) => self
+                .incompatible
+                .push(format!("relation {name} would stop keeping removed rows")),
+            _ => {}
+        }
+        match (old.ttl, new.ttl) {
+            (_, Some(ttl)) if old.ttl != Some(ttl) => self.creates.push(step(
+                format!("set the TTL of relation {name}"),
+                format!("{{::ttl {quoted} {}}}", float_script(ttl)),
+                false,
+            )),
+            (Some(_), None) => self
+                .incompatible
+                .push(format!("the TTL of relation {name} would be removed")),
+            _ => {}
+        }
+    }
+}
@@ -16,7 +16,7 @@ use crate::runtime::relation::RelationHandle;
 use crate::runtime::transact::SessionTx;
 use crate::{DataValue, Expr, SourceSpan, Symbol};
 use itertools::Itertools;
-use miette::{bail, miette, Result};
+use miette::{bail, Result};
 use quadrature::integrate;
 use rand::{thread_rng, RngCore};
 use rustc_hash::FxHashSet;
@@ -181,10 +181,11 @@ impl<'a> SessionTx<'a> {
         }
         let mut ret = vec![];
         for key in found_tuples {
-            let orig_tuple = config
-                .base_handle
-                .get(self, &key)?
-                .ok_or_else(|| miette!("Tuple not found in base LSH relation"))?;
+            let orig_tuple = match config.base_handle.get(self, &key)? {
+                Some(tuple) => tuple,
+                None if config.base_handle.ttl.is_some() => continue,
+                None => bail!("Tuple not found in base LSH relation"),
+            };
             if let Some((filter_code, span)) = filter_code {
                 if !eval_bytecode_pred(filter_code, &orig_tuple, stack, *span)? {
                     continue;
@@ -236,6 +237,18 @@ pub(crate) struct MinHashLshIndexManifest {
     pub(crate) n_rows_in_band: usize,
     pub(crate) threshold: f64,
     pub(crate) perms: Vec<u8>,
+    /// The number of permutations asked for, `0` for indices created before it was kept
+    #[serde(default)]
+    pub(crate) n_perm: usize,
+    /// The weights given at creation, normalized to sum to one
+    #[serde(default = "default_lsh_weight")]
+    pub(crate) false_positive_weight: f64,
+    #[serde(default = "default_lsh_weight")]
+    pub(crate) false_negative_weight: f64,
+}
+
+fn default_lsh_weight() -> f64 {
+    0.5
 }
 
 impl MinHashLshIndexManifest {
@@ -6,12 +6,25 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+pub(crate) mod archive;
+#[cfg(feature = "arrow")]
+pub(crate) mod arrow;
+#[cfg(feature = "async")]
+pub(crate) mod async_db;
 pub(crate) mod callback;
 pub(crate) mod db;
+pub(crate) mod ddl;
 pub(crate) mod imperative;
+pub(crate) mod import;
+pub(crate) mod memory;
+pub(crate) mod migration;
+pub(crate) mod plan_cache;
 pub(crate) mod relation;
+pub(crate) mod rows_diff;
+pub(crate) mod structs;
 pub(crate) mod temp_store;
 pub(crate) mod transact;
+pub(crate) mod view;
 pub(crate) mod hnsw;
 pub(crate) mod minhash_lsh;
 #[cfg(test)]
@@ -0,0 +1,200 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Plans of the read-only queries run by a database, kept so that running the same
+//! query again skips parsing and compiling it.
+//!
+//! Plans are kept as the JSON of [crate::query::plan::QueryPlan] and rebuilt against
+//! the transaction running them. Each plan records the version of the catalog it was
+//! compiled against, which is bumped whenever a transaction changing the schemas of
+//! stored relations commits, so that plans compiled before are never reused.
+
+use std::collections::BTreeMap;
+
+use serde_json::Value as JsonValue;
+
+use crate::data::value::DataValue;
+use crate::parse::{scan_script, ScriptPiece};
+
+/// The number of plans kept unless set otherwise with [crate::Db::set_plan_cache_size].
+pub(crate) const DEFAULT_PLAN_CACHE_SIZE: usize = 128;
+
+/// Counters of the plan cache of a database, see [crate::Db::plan_cache_stats].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlanCacheStats {
+    /// Queries run from a cached plan
+    pub hits: u64,
+    /// Queries whose plans could be cached but were compiled, as they were not cached
+    /// or were compiled against an older catalog
+    pub misses: u64,
+    /// The plans currently cached
+    pub entries: usize,
+}
+
+/// Key of a cached plan: the text of the script without comments and with runs of
+/// whitespace collapsed, together with the parameters. Parameters are substituted into
+/// queries when parsing them, so their values are part of the key.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct PlanKey {
+    text: String,
+    params: Vec<(String, DataValue)>,
+}
+
+impl PlanKey {
+    pub(crate) fn new(script: &str, params: &BTreeMap<String, DataValue>) -> Self {
+        Self {
+            text: normalize_script(script),
+            params: params.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+        }
+    }
+    /// Whether the plan of the script may depend on the time it is parsed at, which
+    /// is substituted into validity specifications and values of the `Validity` type.
+    pub(crate) fn depends_on_time(&self, plan: &JsonValue) -> bool {
+        self.text.contains("Validity") || reads_at_validity(plan)
+    }
+}
+
+fn reads_at_validity(plan: &JsonValue) -> bool {
+    match plan {
+        JsonValue::Object(fields) => {
+            fields.get("op").and_then(|op| op.as_str()) == Some("stored_with_validity")
+                || fields.values().any(reads_at_validity)
+        }
+        JsonValue::Array(items) => items.iter().any(reads_at_validity),
+        _ => false,
+    }
+}
+
+struct CachedPlan {
+    plan: JsonValue,
+    catalog_version: u64,
+    last_used: u64,
+}
+
+/// Least recently used plans, up to a capacity.
+pub(crate) struct PlanCache {
+    capacity: usize,
+    plans: BTreeMap<PlanKey, CachedPlan>,
+    /// Keys of the plans by the time they were last used
+    by_use: BTreeMap<u64, PlanKey>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl Default for PlanCache {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_PLAN_CACHE_SIZE,
+            plans: Default::default(),
+            by_use: Default::default(),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+}
+
+impl PlanCache {
+    pub(crate) fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+    /// The plan cached for `key`, unless it was compiled against another version of
+    /// the catalog, in which case it is dropped.
+    pub(crate) fn get(&mut self, key: &PlanKey, catalog_version: u64) -> Option<JsonValue> {
+        let cached = self.plans.get_mut(key)?;
+        if cached.catalog_version != catalog_version {
+            self.remove(key);
+            return None;
+        }
+        self.clock += 1;
+        self.by_use.remove(&cached.last_used);
+        cached.last_used = self.clock;
+        self.by_use.insert(self.clock, key.clone());
+        Some(cached.plan.clone())
+    }
+    pub(crate) fn insert(&mut self, key: PlanKey, plan: JsonValue, catalog_version: u64) {
+        if !self.enabled() {
+            return;
+        }
+        self.remove(&key);
+        self.clock += 1;
+        self.by_use.insert(self.clock, key.clone());
+        self.plans.insert(
+            key,
+            CachedPlan {
+                plan,
+                catalog_version,
+                last_used: self.clock,
+            },
+        );
+        self.evict();
+    }
+    pub(crate) fn remove(&mut self, key: &PlanKey) {
+        if let Some(cached) = self.plans.remove(key) {
+            self.by_use.remove(&cached.last_used);
+        }
+    }
+    pub(crate) fn clear(&mut self) {
+        self.plans.clear();
+        self.by_use.clear();
+    }
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+    pub(crate) fn record_hit(&mut self) {
+        self.hits += 1;
+    }
+    pub(crate) fn record_miss(&mut self) {
+        self.misses += 1;
+    }
+    pub(crate) fn stats(&self) -> PlanCacheStats {
+        PlanCacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.plans.len(),
+        }
+    }
+    fn evict(&mut self) {
+        while self.plans.len() > self.capacity {
+            let (_, key) = self.by_use.pop_first().unwrap();
+            self.plans.remove(&key);
+        }
+    }
+}
+
+/// Strip the comments of a script and collapse the whitespace between its tokens into
+/// single spaces, leaving string literals and quoted names as they are.
+pub(crate) fn normalize_script(script: &str) -> String {
+    let bytes = script.as_bytes();
+    let mut ret = Vec::with_capacity(bytes.len());
+    let mut separated = false;
+    // the visitor never fails, and unterminated literals are kept as they are
+    let _ = scan_script(script, |piece| {
+        let text = match piece {
+            ScriptPiece::Code(i) if matches!(bytes[i], b' ' | b'\t' | b'\r' | b'\n') => None,
+            ScriptPiece::Code(i) => Some(&bytes[i..i + 1]),
+            ScriptPiece::Quoted(start, end) => Some(&bytes[start..end]),
+            ScriptPiece::Comment => None,
+        };
+        match text {
+            None => separated = true,
+            Some(text) => {
+                if separated && !ret.is_empty() {
+                    ret.push(b' ');
+                }
+                separated = false;
+                ret.extend_from_slice(text);
+            }
+        }
+        Ok(())
+    });
+    // only whole pieces of ASCII are dropped, so what is left is still UTF-8
+    String::from_utf8_lossy(&ret).into_owned()
+}
@@ -6,7 +6,7 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::sync::atomic::Ordering;
 
@@ -19,20 +19,26 @@ use serde::Serialize;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
+use crate::data::expr::Expr;
+use crate::data::functions::current_validity;
 use crate::data::memcmp::MemCmpEncoder;
 use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::Symbol;
-use crate::data::tuple::{decode_tuple_from_key, Tuple, TupleT, ENCODED_KEY_MIN_LEN};
-use crate::data::value::{DataValue, ValidityTs};
+use crate::data::tuple::{
+    try_check_key_for_validity, try_decode_tuple_from_key, Tuple, TupleT, ENCODED_KEY_MIN_LEN,
+};
+use crate::data::value::{DataValue, ValidityTs, LARGEST_UTF_CHAR};
 use crate::fts::FtsIndexManifest;
 use crate::parse::expr::build_expr;
+use crate::parse::schema::BindingExprs;
 use crate::parse::sys::{FtsIndexConfig, HnswIndexConfig, MinHashLshConfig};
 use crate::parse::{CozoScriptParser, Rule, SourceSpan};
 use crate::query::compile::IndexPositionUse;
 use crate::runtime::hnsw::HnswIndexManifest;
 use crate::runtime::minhash_lsh::{HashPermutations, LshParams, MinHashLshIndexManifest, Weights};
 use crate::runtime::transact::SessionTx;
-use crate::utils::TempCollector;
+use crate::runtime::view::ViewManifest;
+use crate::utils::{edit_distance, TempCollector};
 use crate::{NamedRows, StoreTx};
 
 #[derive(
@@ -90,8 +96,29 @@ pub(crate) struct RelationHandle {
         (RelationHandle, RelationHandle, MinHashLshIndexManifest),
     >,
     pub(crate) description: SmartString<LazyCompact>,
+    /// Removed rows are kept as tombstones, with the time of removal
+    /// in the trailing [`SOFT_DELETE_COL`] column
+    #[serde(default)]
+    pub(crate) soft_delete: bool,
+    /// Rows expire this many seconds after they are last written, with the time
+    /// of expiry in the trailing [`TTL_COL`] column
+    #[serde(default)]
+    pub(crate) ttl: Option<f64>,
+    /// The materialized views defined over the relation
+    #[serde(default)]
+    pub(crate) views: BTreeSet<SmartString<LazyCompact>>,
+    /// Set if the relation is a materialized view, whose rows are only written by
+    /// keeping it up to date
+    #[serde(default)]
+    pub(crate) view: Option<ViewManifest>,
 }
 
+/// The reserved column holding the removal time of soft-deleted rows
+pub(crate) const SOFT_DELETE_COL: &str = "_deleted_at";
+
+/// The reserved column holding the expiry time of the rows of relations with a TTL
+pub(crate) const TTL_COL: &str = "_expires_at";
+
 impl RelationHandle {
     pub(crate) fn has_index(&self, index_name: &str) -> bool {
         self.indices.contains_key(index_name)
@@ -105,6 +132,25 @@ impl RelationHandle {
             && self.fts_indices.is_empty()
             && self.lsh_indices.is_empty()
     }
+    /// The key ranges holding the rows of the relation and of all its indices.
+    pub(crate) fn key_ranges(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut handles = vec![self];
+        handles.extend(self.indices.values().map(|(h, _)| h));
+        handles.extend(self.hnsw_indices.values().map(|(h, _)| h));
+        handles.extend(self.fts_indices.values().map(|(h, _)| h));
+        for (h, inv, _) in self.lsh_indices.values() {
+            handles.extend([h, inv]);
+        }
+        handles
+            .into_iter()
+            .map(|h| {
+                (
+                    Tuple::default().encode_as_key(h.id),
+                    Tuple::default().encode_as_key(h.id.next()),
+                )
+            })
+            .collect()
+    }
 }
 
 #[derive(
@@ -138,6 +184,97 @@ impl Display for AccessLevel {
     }
 }
 
+/// Summary of a stored relation, as listed by `::relations`.
+#[derive(Debug, Clone, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct RelationInfo {
+    /// Name of the relation, `relation:index` for indices.
+    pub name: String,
+    /// Number of columns.
+    pub arity: usize,
+    /// `index` for indices, otherwise the access level of the relation.
+    pub access_level: String,
+    /// Number of key columns.
+    pub n_keys: usize,
+    /// Number of non-key columns.
+    pub n_non_keys: usize,
+    /// Number of triggers run on puts.
+    pub n_put_triggers: usize,
+    /// Number of triggers run on removals.
+    pub n_rm_triggers: usize,
+    /// Number of triggers run when the relation is replaced.
+    pub n_replace_triggers: usize,
+    /// The description set with `::describe`.
+    pub description: String,
+}
+
+impl RelationInfo {
+    pub(crate) const HEADERS: [&'static str; 9] = [
+        "name",
+        "arity",
+        "access_level",
+        "n_keys",
+        "n_non_keys",
+        "n_put_triggers",
+        "n_rm_triggers",
+        "n_replace_triggers",
+        "description",
+    ];
+
+    pub(crate) fn into_tuple(self) -> Tuple {
+        vec![
+            DataValue::from(self.name),
+            DataValue::from(self.arity as i64),
+            DataValue::from(self.access_level),
+            DataValue::from(self.n_keys as i64),
+            DataValue::from(self.n_non_keys as i64),
+            DataValue::from(self.n_put_triggers as i64),
+            DataValue::from(self.n_rm_triggers as i64),
+            DataValue::from(self.n_replace_triggers as i64),
+            DataValue::from(self.description),
+        ]
+    }
+}
+
+/// A column of a stored relation, as listed by `::columns`.
+#[derive(Debug, Clone, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct ColumnInfo {
+    /// Name of the column.
+    pub name: String,
+    /// Whether the column is part of the key.
+    pub is_key: bool,
+    /// Position of the column in the rows of the relation.
+    pub index: usize,
+    /// The type as written in the schema, e.g. `Int?`.
+    pub typing: String,
+    /// The default expression, if any, as written in the schema.
+    pub default_expr: Option<String>,
+}
+
+impl ColumnInfo {
+    pub(crate) const HEADERS: [&'static str; 6] = [
+        "column",
+        "is_key",
+        "index",
+        "type",
+        "has_default",
+        "default_expr",
+    ];
+
+    pub(crate) fn into_tuple(self) -> Tuple {
+        vec![
+            DataValue::from(self.name),
+            DataValue::from(self.is_key),
+            DataValue::from(self.index as i64),
+            DataValue::from(self.typing),
+            DataValue::from(self.default_expr.is_some()),
+            match self.default_expr {
+                None => DataValue::Null,
+                Some(expr) => DataValue::from(expr),
+            },
+        ]
+    }
+}
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("Arity mismatch for stored relation {name}: expect {expect_arity}, got {actual_arity}")]
 #[diagnostic(code(eval::stored_rel_arity_mismatch))]
@@ -201,7 +338,11 @@ impl RelationHandle {
         if self.indices.is_empty() {
             return None;
         }
-        if *arg_uses.first().unwrap() == IndexPositionUse::Join {
+        // a seek on the relation itself is at least as good
+        if matches!(
+            arg_uses.first().unwrap(),
+            IndexPositionUse::Join | IndexPositionUse::Bounded
+        ) {
             return None;
         }
         let mut max_prefix_len = 0;
@@ -224,7 +365,10 @@ impl RelationHandle {
 
             let mut cur_prefix_len = 0;
             for i in mapper {
-                if arg_uses[*i] == IndexPositionUse::Join {
+                if matches!(
+                    arg_uses[*i],
+                    IndexPositionUse::Join | IndexPositionUse::Bounded
+                ) {
                     cur_prefix_len += 1;
                 } else {
                     break;
@@ -232,7 +376,8 @@ impl RelationHandle {
             }
             if cur_prefix_len > max_prefix_len {
                 max_prefix_len = cur_prefix_len;
-                let mut need_join = false;
+                // expired rows stay indexed until purged: only the relation knows if they are gone
+                let mut need_join = self.ttl.is_some();
                 for need_pos in required_positions.iter() {
                     if !mapper.contains(need_pos) {
                         need_join = true;
@@ -300,6 +445,46 @@ impl RelationHandle {
         is_remove_or_update: bool,
     ) -> Result<()> {
         let InputRelationHandle { metadata, .. } = inp;
+        if self.soft_delete {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Column '{0}' of stored relation '{1}' is reserved for soft deletes")]
+            #[diagnostic(code(eval::reserved_soft_delete_col))]
+            #[diagnostic(help("Remove rows with ':rm' or ':delete' instead"))]
+            struct ReservedSoftDeleteColumn(String, String, #[label] SourceSpan);
+
+            if let Some(col) = metadata
+                .keys
+                .iter()
+                .chain(metadata.non_keys.iter())
+                .find(|col| col.name == SOFT_DELETE_COL)
+            {
+                bail!(ReservedSoftDeleteColumn(
+                    col.name.to_string(),
+                    self.name.to_string(),
+                    inp.span
+                ))
+            }
+        }
+        if self.ttl.is_some() {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Column '{0}' of stored relation '{1}' is reserved for the expiry of rows")]
+            #[diagnostic(code(eval::reserved_ttl_col))]
+            #[diagnostic(help("Rows expire by themselves once written, and writing them again refreshes their expiry"))]
+            struct ReservedTtlColumn(String, String, #[label] SourceSpan);
+
+            if let Some(col) = metadata
+                .keys
+                .iter()
+                .chain(metadata.non_keys.iter())
+                .find(|col| col.name == TTL_COL)
+            {
+                bail!(ReservedTtlColumn(
+                    col.name.to_string(),
+                    self.name.to_string(),
+                    inp.span
+                ))
+            }
+        }
         // check that every given key is found and compatible
         for col in metadata.keys.iter().chain(self.metadata.non_keys.iter()) {
             self.metadata.compatible_with_col(col)?
@@ -323,9 +508,29 @@ pub(crate) struct InputRelationHandle {
     pub(crate) metadata: StoredRelationMetadata,
     pub(crate) key_bindings: Vec<Symbol>,
     pub(crate) dep_bindings: Vec<Symbol>,
+    /// Columns mapped to expressions instead of plain bindings, keyed by
+    /// the names of the placeholder bindings standing in for them
+    pub(crate) binding_exprs: BindingExprs,
+    pub(crate) on_conflict: OnConflict,
     pub(crate) span: SourceSpan,
 }
 
+/// What `:insert` does with rows whose keys are already stored
+#[derive(
+    Debug, Clone, Default, Eq, PartialEq, serde_derive::Serialize, serde_derive::Deserialize,
+)]
+pub(crate) enum OnConflict {
+    /// Abort the whole query
+    #[default]
+    Error,
+    /// Leave the stored row alone and skip the incoming one
+    Ignore,
+    /// Keep the stored row but overwrite the given columns with the results
+    /// of the expressions, which can refer to the incoming row by its bindings
+    /// and to the stored row by `old.<column>`
+    Update(BTreeMap<SmartString<LazyCompact>, Expr>),
+}
+
 impl Debug for RelationHandle {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "Relation<{}>", self.name)
@@ -360,11 +565,135 @@ impl RelationHandle {
     ) -> impl Iterator<Item = Result<Tuple>> + 'a {
         let lower = Tuple::default().encode_as_key(self.id);
         let upper = Tuple::default().encode_as_key(self.id.next());
-        if self.is_temp {
+        let it = if self.is_temp {
             tx.temp_store_tx.range_scan_tuple(&lower, &upper)
         } else {
             tx.store_tx.range_scan_tuple(&lower, &upper)
+        };
+        self.checked_rows(tx, it)
+    }
+
+    /// The reserved column ending the stored value bytes of a row, failing with
+    /// [CorruptData] if they cannot be decoded
+    fn trailing_mark(&self, key_bytes: &[u8], val_bytes: &[u8]) -> Result<Option<DataValue>> {
+        let mut vals = vec![];
+        try_extend_tuple_from_v(&mut vals, val_bytes).map_err(|reason| CorruptData {
+            table: self.name.to_string(),
+            key_bytes: key_bytes.to_vec(),
+            reason,
+        })?;
+        Ok(vals.pop())
+    }
+
+    /// Whether the stored value bytes are those of the tombstone of a soft-deleted row
+    pub(crate) fn is_tombstone_val(&self, key_bytes: &[u8], val_bytes: &[u8]) -> Result<bool> {
+        if !self.soft_delete {
+            return Ok(false);
+        }
+        let mark = self.trailing_mark(key_bytes, val_bytes)?;
+        Ok(matches!(mark, Some(v) if v != DataValue::Null))
+    }
+
+    /// Whether the stored value bytes are those of a row expired at `now`
+    pub(crate) fn is_expired_val(
+        &self,
+        key_bytes: &[u8],
+        val_bytes: &[u8],
+        now: f64,
+    ) -> Result<bool> {
+        if self.ttl.is_none() {
+            return Ok(false);
         }
+        let mark = self.trailing_mark(key_bytes, val_bytes)?;
+        Ok(is_expired(mark.as_ref(), now))
+    }
+
+    /// Whether the rows end with a reserved column, which must be decoded even
+    /// when only the keys are asked for
+    pub(crate) fn has_trailing_mark(&self) -> bool {
+        self.soft_delete || self.ttl.is_some()
+    }
+
+    fn skip_tombstones<'a>(
+        &self,
+        now: f64,
+        it: impl Iterator<Item = Result<Tuple>> + 'a,
+    ) -> impl Iterator<Item = Result<Tuple>> + 'a {
+        let mark_pos = self.has_trailing_mark().then(|| self.arity() - 1);
+        let ttl = self.ttl.is_some();
+        it.filter(move |res| match (mark_pos, res) {
+            (Some(i), Ok(tuple)) if ttl => !is_expired(tuple.get(i), now),
+            (Some(i), Ok(tuple)) => matches!(tuple.get(i), None | Some(DataValue::Null)),
+            _ => true,
+        })
+    }
+
+    /// Skips the tombstones and expired rows in the rows of a scan, as well as the corrupt rows if the session
+    /// skips them, counting these. Otherwise the errors for corrupt rows get the name of
+    /// the relation.
+    fn checked_rows<'a>(
+        &self,
+        tx: &'a SessionTx<'_>,
+        it: impl Iterator<Item = Result<Tuple>> + 'a,
+    ) -> impl Iterator<Item = Result<Tuple>> + 'a {
+        let name = self.name.clone();
+        let skip_corrupt = tx.skip_corrupt;
+        let corrupt_skipped = &tx.corrupt_skipped;
+        let it = it.filter_map(move |res| match res {
+            Ok(tuple) => Some(Ok(tuple)),
+            Err(err) => match err.downcast::<CorruptData>() {
+                Ok(_) if skip_corrupt => {
+                    corrupt_skipped.fetch_add(1, Ordering::Relaxed);
+                    None
+                }
+                Ok(mut corrupt) => {
+                    corrupt.table = name.to_string();
+                    Some(Err(corrupt.into()))
+                }
+                Err(err) => Some(Err(err)),
+            },
+        });
+        self.skip_tombstones(tx.now, it)
+    }
+
+    /// Like [`Self::scan_all`], but the non-key columns are not decoded and set to null.
+    pub(crate) fn scan_all_keys<'a>(
+        &self,
+        tx: &'a SessionTx<'_>,
+    ) -> impl Iterator<Item = Result<Tuple>> + 'a {
+        let lower = Tuple::default().encode_as_key(self.id);
+        let upper = Tuple::default().encode_as_key(self.id.next());
+        self.range_scan_keys(tx, &lower, &upper)
+    }
+
+    /// Extends a key tuple to the arity of the relation by appending nulls.
+    pub(crate) fn pad_key_tuple(&self, mut key: Tuple) -> Tuple {
+        key.resize(self.arity(), DataValue::Null);
+        key
+    }
+
+    fn range_scan_keys<'a>(
+        &self,
+        tx: &'a SessionTx<'_>,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> impl Iterator<Item = Result<Tuple>> + 'a {
+        let arity = self.arity();
+        let has_mark = self.has_trailing_mark();
+        let it = if self.is_temp {
+            tx.temp_store_tx.range_scan(lower, upper)
+        } else {
+            tx.store_tx.range_scan(lower, upper)
+        };
+        let it = it.map(move |kv| {
+            let (k, v) = kv?;
+            if has_mark {
+                // the mark is needed to tell tombstones and expired rows apart
+                return try_decode_tuple_from_kv(&k, &v, Some(arity));
+            }
+            decode_key_only(&k, arity)
+        });
+        self.checked_rows(tx, it)
     }
 
     pub(crate) fn skip_scan_all<'a>(
@@ -374,51 +703,92 @@ impl RelationHandle {
     ) -> impl Iterator<Item = Result<Tuple>> + 'a {
         let lower = Tuple::default().encode_as_key(self.id);
         let upper = Tuple::default().encode_as_key(self.id.next());
-        if self.is_temp {
+        let it = if self.is_temp {
             tx.temp_store_tx
                 .range_skip_scan_tuple(&lower, &upper, valid_at)
         } else {
             tx.store_tx.range_skip_scan_tuple(&lower, &upper, valid_at)
-        }
+        };
+        self.checked_rows(tx, it)
     }
 
     pub(crate) fn get(&self, tx: &SessionTx<'_>, key: &[DataValue]) -> Result<Option<Tuple>> {
         let key_data = key.encode_as_key(self.id);
-        if self.is_temp {
-            Ok(tx
-                .temp_store_tx
-                .get(&key_data, false)?
-                .map(|val_data| decode_tuple_from_kv(&key_data, &val_data, Some(self.arity()))))
-        } else {
-            Ok(tx
-                .store_tx
-                .get(&key_data, false)?
-                .map(|val_data| decode_tuple_from_kv(&key_data, &val_data, Some(self.arity()))))
+        match self.get_stored_val(tx, &key_data, false)? {
+            None => Ok(None),
+            Some(val_data) => try_decode_tuple_from_kv(&key_data, &val_data, Some(self.arity()))
+                .map(Some)
+                .map_err(|err| match err.downcast::<CorruptData>() {
+                    Ok(mut corrupt) => {
+                        corrupt.table = self.name.to_string();
+                        corrupt.into()
+                    }
+                    Err(err) => err,
+                }),
         }
     }
 
-    pub(crate) fn get_val_only(
+    /// Like [`Self::get`], but expired rows are returned as well, as they stay in the
+    /// indices until they are purged.
+    pub(crate) fn get_including_expired(
         &self,
         tx: &SessionTx<'_>,
         key: &[DataValue],
     ) -> Result<Option<Tuple>> {
+        if self.ttl.is_none() {
+            return self.get(tx, key);
+        }
         let key_data = key.encode_as_key(self.id);
-        if self.is_temp {
-            Ok(tx
-                .temp_store_tx
-                .get(&key_data, false)?
-                .map(|val_data| rmp_serde::from_slice(&val_data[ENCODED_KEY_MIN_LEN..]).unwrap()))
+        let found = if self.is_temp {
+            tx.temp_store_tx.get(&key_data, false)?
+        } else {
+            tx.store_tx.get(&key_data, false)?
+        };
+        found
+            .map(|val_data| try_decode_tuple_from_kv(&key_data, &val_data, Some(self.arity())))
+            .transpose()
+    }
+
+    /// Gets the stored value bytes for the encoded key, treating tombstones and expired rows
+    /// as absent.
+    pub(crate) fn get_stored_val(
+        &self,
+        tx: &SessionTx<'_>,
+        key_data: &[u8],
+        lock: bool,
+    ) -> Result<Option<Vec<u8>>> {
+        let found = if self.is_temp {
+            tx.temp_store_tx.get(key_data, lock)?
         } else {
-            Ok(tx
-                .store_tx
-                .get(&key_data, false)?
-                .map(|val_data| rmp_serde::from_slice(&val_data[ENCODED_KEY_MIN_LEN..]).unwrap()))
+            tx.store_tx.get(key_data, lock)?
+        };
+        match found {
+            Some(val_data)
+                if !self.is_tombstone_val(key_data, &val_data)?
+                    && !self.is_expired_val(key_data, &val_data, tx.now)? =>
+            {
+                Ok(Some(val_data))
+            }
+            _ => Ok(None),
         }
     }
 
+    pub(crate) fn get_val_only(
+        &self,
+        tx: &SessionTx<'_>,
+        key: &[DataValue],
+    ) -> Result<Option<Tuple>> {
+        let key_data = key.encode_as_key(self.id);
+        Ok(self
+            .get_stored_val(tx, &key_data, false)?
+            .map(|val_data| rmp_serde::from_slice(&val_data[ENCODED_KEY_MIN_LEN..]).unwrap()))
+    }
+
     pub(crate) fn exists(&self, tx: &SessionTx<'_>, key: &[DataValue]) -> Result<bool> {
         let key_data = key.encode_as_key(self.id);
-        if self.is_temp {
+        if self.has_trailing_mark() {
+            Ok(self.get_stored_val(tx, &key_data, false)?.is_some())
+        } else if self.is_temp {
             tx.temp_store_tx.exists(&key_data, false)
         } else {
             tx.store_tx.exists(&key_data, false)
@@ -436,13 +806,29 @@ impl RelationHandle {
         upper.push(DataValue::Bot);
         let prefix_encoded = lower.encode_as_key(self.id);
         let upper_encoded = upper.encode_as_key(self.id);
-        if self.is_temp {
+        let it = if self.is_temp {
             tx.temp_store_tx
                 .range_scan_tuple(&prefix_encoded, &upper_encoded)
         } else {
             tx.store_tx
                 .range_scan_tuple(&prefix_encoded, &upper_encoded)
-        }
+        };
+        self.checked_rows(tx, it)
+    }
+
+    /// Like [`Self::scan_prefix`], but the non-key columns are not decoded and set to null.
+    pub(crate) fn scan_prefix_keys<'a>(
+        &self,
+        tx: &'a SessionTx<'_>,
+        prefix: &Tuple,
+    ) -> impl Iterator<Item = Result<Tuple>> + 'a {
+        let mut lower = prefix.clone();
+        lower.truncate(self.metadata.keys.len());
+        let mut upper = lower.clone();
+        upper.push(DataValue::Bot);
+        let prefix_encoded = lower.encode_as_key(self.id);
+        let upper_encoded = upper.encode_as_key(self.id);
+        self.range_scan_keys(tx, &prefix_encoded, &upper_encoded)
     }
 
     pub(crate) fn skip_scan_prefix<'a>(
@@ -457,13 +843,14 @@ impl RelationHandle {
         upper.push(DataValue::Bot);
         let prefix_encoded = lower.encode_as_key(self.id);
         let upper_encoded = upper.encode_as_key(self.id);
-        if self.is_temp {
+        let it = if self.is_temp {
             tx.temp_store_tx
                 .range_skip_scan_tuple(&prefix_encoded, &upper_encoded, valid_at)
         } else {
             tx.store_tx
                 .range_skip_scan_tuple(&prefix_encoded, &upper_encoded, valid_at)
-        }
+        };
+        self.checked_rows(tx, it)
     }
 
     pub(crate) fn scan_bounded_prefix<'a>(
@@ -480,12 +867,65 @@ impl RelationHandle {
         upper_t.push(DataValue::Bot);
         let lower_encoded = lower_t.encode_as_key(self.id);
         let upper_encoded = upper_t.encode_as_key(self.id);
-        if self.is_temp {
+        let it = if self.is_temp {
             tx.temp_store_tx
                 .range_scan_tuple(&lower_encoded, &upper_encoded)
         } else {
             tx.store_tx.range_scan_tuple(&lower_encoded, &upper_encoded)
-        }
+        };
+        self.checked_rows(tx, it)
+    }
+    /// Like [`Self::scan_bounded_prefix`], but the non-key columns are not decoded and set to null.
+    pub(crate) fn scan_bounded_prefix_keys<'a>(
+        &self,
+        tx: &'a SessionTx<'_>,
+        prefix: &[DataValue],
+        lower: &[DataValue],
+        upper: &[DataValue],
+    ) -> impl Iterator<Item = Result<Tuple>> + 'a {
+        let mut lower_t = prefix.to_vec();
+        lower_t.extend_from_slice(lower);
+        let mut upper_t = prefix.to_vec();
+        upper_t.extend_from_slice(upper);
+        upper_t.push(DataValue::Bot);
+        let lower_encoded = lower_t.encode_as_key(self.id);
+        let upper_encoded = upper_t.encode_as_key(self.id);
+        self.range_scan_keys(tx, &lower_encoded, &upper_encoded)
+    }
+    /// Like [`Self::scan_bounded_prefix`], but the rows come in descending key order.
+    /// If `keys_only` is set, the non-key columns are not decoded and set to null.
+    pub(crate) fn scan_bounded_prefix_rev<'a>(
+        &self,
+        tx: &'a SessionTx<'_>,
+        prefix: &[DataValue],
+        lower: &[DataValue],
+        upper: &[DataValue],
+        keys_only: bool,
+    ) -> impl Iterator<Item = Result<Tuple>> + 'a {
+        let mut lower_t = prefix.to_vec();
+        lower_t.extend_from_slice(lower);
+        let mut upper_t = prefix.to_vec();
+        upper_t.extend_from_slice(upper);
+        upper_t.push(DataValue::Bot);
+        let lower_encoded = lower_t.encode_as_key(self.id);
+        let upper_encoded = upper_t.encode_as_key(self.id);
+        let it = if self.is_temp {
+            tx.temp_store_tx
+                .range_scan_rev(&lower_encoded, &upper_encoded)
+        } else {
+            tx.store_tx.range_scan_rev(&lower_encoded, &upper_encoded)
+        };
+        let arity = self.arity();
+        let keys_only = keys_only && !self.has_trailing_mark();
+        let it = it.map(move |kv| {
+            let (k, v) = kv?;
+            if keys_only {
+                decode_key_only(&k, arity)
+            } else {
+                try_decode_tuple_from_kv(&k, &v, Some(arity))
+            }
+        });
+        self.checked_rows(tx, it)
     }
     pub(crate) fn skip_scan_bounded_prefix<'a>(
         &self,
@@ -502,32 +942,124 @@ impl RelationHandle {
         upper_t.push(DataValue::Bot);
         let lower_encoded = lower_t.encode_as_key(self.id);
         let upper_encoded = upper_t.encode_as_key(self.id);
-        if self.is_temp {
+        let it = if self.is_temp {
             tx.temp_store_tx
                 .range_skip_scan_tuple(&lower_encoded, &upper_encoded, valid_at)
         } else {
             tx.store_tx
                 .range_skip_scan_tuple(&lower_encoded, &upper_encoded, valid_at)
-        }
+        };
+        self.checked_rows(tx, it)
     }
 }
 
 const DEFAULT_SIZE_HINT: usize = 16;
 
+/// Whether the expiry mark of a row, in seconds since the epoch, has passed at `now`
+pub(crate) fn is_expired(mark: Option<&DataValue>, now: f64) -> bool {
+    matches!(mark.and_then(|v| v.get_float()), Some(expires_at) if expires_at <= now)
+}
+
 /// Decode tuple from key-value pairs. Used for customizing storage
 /// in trait [`StoreTx`](crate::StoreTx).
+///
+/// Panics if the data is malformed: storage engines should prefer [try_decode_tuple_from_kv].
 #[inline]
 pub fn decode_tuple_from_kv(key: &[u8], val: &[u8], size_hint: Option<usize>) -> Tuple {
-    let mut tup = decode_tuple_from_key(key, size_hint.unwrap_or(DEFAULT_SIZE_HINT));
-    extend_tuple_from_v(&mut tup, val);
-    tup
+    try_decode_tuple_from_kv(key, val, size_hint).unwrap_or_else(|err| panic!("{err:?}"))
+}
+
+/// Decode tuple from key-value pairs, failing with [CorruptData] if the data is malformed.
+/// Used for customizing storage in trait [`StoreTx`](crate::StoreTx).
+#[inline]
+pub fn try_decode_tuple_from_kv(key: &[u8], val: &[u8], size_hint: Option<usize>) -> Result<Tuple> {
+    let decoded = try_decode_tuple_from_key(key, size_hint.unwrap_or(DEFAULT_SIZE_HINT))
+        .and_then(|mut tup| try_extend_tuple_from_v(&mut tup, val).map(|_| tup));
+    decoded.map_err(|reason| CorruptData::new(key, reason).into())
+}
+
+/// Decodes a row met by a skip scan, giving it if it is visible at `valid_at`, and the key
+/// to seek to next. A malformed row fails with [CorruptData], the scan going on after it.
+pub(crate) fn decode_skip_scan_row(
+    key: &[u8],
+    val: &[u8],
+    valid_at: ValidityTs,
+    size_hint: Option<usize>,
+) -> (Result<Option<Tuple>>, Vec<u8>) {
+    match try_check_key_for_validity(key, valid_at, size_hint) {
+        Ok((ret, next_bound)) => {
+            let ret = ret
+                .map(|mut tuple| {
+                    try_extend_tuple_from_v(&mut tuple, val)
+                        .map_err(|reason| CorruptData::new(key, reason))?;
+                    Ok(tuple)
+                })
+                .transpose();
+            (ret, next_bound)
+        }
+        Err(err) => {
+            let mut next_bound = key.to_vec();
+            next_bound.push(0);
+            (Err(err), next_bound)
+        }
+    }
+}
+
+/// Decodes the key columns of a stored row, setting the others to null.
+fn decode_key_only(key: &[u8], arity: usize) -> Result<Tuple> {
+    let mut tuple =
+        try_decode_tuple_from_key(key, arity).map_err(|reason| CorruptData::new(key, reason))?;
+    tuple.resize(arity, DataValue::Null);
+    Ok(tuple)
 }
 
 pub fn extend_tuple_from_v(key: &mut Tuple, val: &[u8]) {
+    try_extend_tuple_from_v(key, val).unwrap_or_else(|err| panic!("{err}: {val:?}"))
+}
+
+fn try_extend_tuple_from_v(key: &mut Tuple, val: &[u8]) -> Result<(), String> {
     if !val.is_empty() {
-        let vals: Vec<DataValue> = rmp_serde::from_slice(&val[ENCODED_KEY_MIN_LEN..]).unwrap();
+        if val.len() < ENCODED_KEY_MIN_LEN {
+            return Err(format!("value of {} bytes is too short", val.len()));
+        }
+        let vals: Vec<DataValue> =
+            rmp_serde::from_slice(&val[ENCODED_KEY_MIN_LEN..]).map_err(|err| err.to_string())?;
         key.extend(vals);
     }
+    Ok(())
+}
+
+/// A stored row that cannot be decoded, for example because it was truncated or
+/// written by an incompatible version.
+///
+/// Raised by scans unless corrupt rows are skipped with [crate::Db::set_skip_corrupt].
+/// `::check <relation>` lists all the corrupt rows of a relation.
+#[derive(Debug, Error, Diagnostic)]
+#[error("Corrupt data in stored relation '{table}': {reason}")]
+#[diagnostic(code(eval::corrupt_data))]
+#[diagnostic(help("Run `::check {table}` to list the corrupt rows"))]
+pub struct CorruptData {
+    /// The name of the relation, or its internal id if the name is not known
+    pub table: String,
+    /// The stored key of the row
+    pub key_bytes: Vec<u8>,
+    /// Why the row cannot be decoded
+    pub reason: String,
+}
+
+impl CorruptData {
+    pub(crate) fn new(key: &[u8], reason: String) -> Self {
+        let table = if key.len() >= ENCODED_KEY_MIN_LEN {
+            format!("#{}", RelationId::raw_decode(key).0)
+        } else {
+            "#?".to_string()
+        };
+        Self {
+            table,
+            key_bytes: key.to_vec(),
+            reason,
+        }
+    }
 }
 
 #[derive(Debug, Error, Diagnostic)]
@@ -540,9 +1072,127 @@ pub(crate) struct IndexAlreadyExists(String, String);
 #[diagnostic(code(eval::rel_name_conflict))]
 struct RelNameConflictError(String);
 
+#[derive(Debug, Diagnostic, Error)]
+#[error("Namespace '{0}' not found")]
+#[diagnostic(code(eval::namespace_not_found))]
+pub(crate) struct NamespaceNotFound(pub(crate) String);
+
+const NAMESPACE_STR: &str = "NAMESPACE";
+
+fn namespace_key(ns: &str) -> Vec<u8> {
+    vec![
+        DataValue::Null,
+        DataValue::from(NAMESPACE_STR),
+        DataValue::from(ns),
+    ]
+    .encode_as_key(RelationId::SYSTEM)
+}
+
 impl<'a> SessionTx<'a> {
+    /// The name of the stored relation `name` refers to: names without a namespace
+    /// are put in the default namespace of the session, if there is one.
+    /// Temp relations are never in a namespace.
+    pub(crate) fn qualified_name(&self, name: &str) -> SmartString<LazyCompact> {
+        match &self.namespace {
+            Some(ns) if !name.starts_with('_') && !name.contains('.') => {
+                SmartString::from(format!("{ns}.{name}"))
+            }
+            _ => SmartString::from(name),
+        }
+    }
+    pub(crate) fn namespace_exists(&self, ns: &str) -> Result<bool> {
+        self.store_tx.exists(&namespace_key(ns), false)
+    }
+    pub(crate) fn namespaces(&self) -> Result<Vec<String>> {
+        let lower = namespace_key("");
+        let upper = namespace_key(&String::from(LARGEST_UTF_CHAR));
+        let mut ret = vec![];
+        for kv in self.store_tx.range_scan(&lower, &upper) {
+            let (k, _) = kv?;
+            let decoded =
+                try_decode_tuple_from_key(&k, 3).map_err(|reason| CorruptData::new(&k, reason))?;
+            if let Some(ns) = decoded.get(2).and_then(|v| v.get_str()) {
+                ret.push(ns.to_string());
+            }
+        }
+        Ok(ret)
+    }
+    pub(crate) fn create_namespace(&mut self, ns: &Symbol) -> Result<()> {
+        if self.namespace_exists(&ns.name)? {
+            bail!("Namespace '{}' already exists", ns.name);
+        }
+        self.store_tx.put(&namespace_key(&ns.name), &[])
+    }
+    /// Removes a namespace, first removing its relations with their indices if
+    /// `cascade` is set. Returns the key ranges to clean up.
+    pub(crate) fn drop_namespace(
+        &mut self,
+        ns: &Symbol,
+        cascade: bool,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        if !self.namespace_exists(&ns.name)? {
+            bail!(NamespaceNotFound(ns.name.to_string()));
+        }
+        let prefix = format!("{}.", ns.name);
+        let rels = self
+            .relation_names()?
+            .into_iter()
+            .filter(|name| name.starts_with(&prefix) && !name.contains(':'))
+            .collect_vec();
+        if !rels.is_empty() && !cascade {
+            bail!(
+                "Cannot drop namespace '{}' as it still contains the relations {}, use `cascade` to remove them as well",
+                ns.name,
+                rels.join(", ")
+            );
+        }
+        let mut to_clean = vec![];
+        for rel in rels {
+            to_clean.extend(self.remove_relation(&Symbol::new(rel, ns.span), true)?);
+        }
+        self.store_tx.del(&namespace_key(&ns.name))?;
+        Ok(to_clean)
+    }
+    /// The names of all stored relations, including indices.
+    fn relation_names(&self) -> Result<Vec<String>> {
+        Ok(self
+            .relation_handles()?
+            .into_iter()
+            .map(|handle| handle.name.to_string())
+            .collect())
+    }
+    /// The catalog entries of all stored relations, including indices.
+    pub(crate) fn relation_handles(&self) -> Result<Vec<RelationHandle>> {
+        let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+        let upper =
+            vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
+        let mut ret = vec![];
+        for kv in self.store_tx.range_scan(&lower, &upper) {
+            let (_, v) = kv?;
+            ret.push(RelationHandle::decode(&v)?);
+        }
+        Ok(ret)
+    }
+    /// Writes the catalog entry of a stored relation as is, for restoring archives.
+    /// The ids of relations created afterwards must be moved past its id with
+    /// [SessionTx::set_last_relation_id].
+    pub(crate) fn put_relation_handle(&mut self, handle: &RelationHandle) -> Result<()> {
+        let name_key = vec![DataValue::Str(handle.name.clone())].encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        handle
+            .serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&name_key, &meta_val)
+    }
+    pub(crate) fn set_last_relation_id(&mut self, id: RelationId) -> Result<()> {
+        let t_encoded = vec![DataValue::Null].encode_as_key(RelationId::SYSTEM);
+        self.store_tx.put(&t_encoded, &id.raw_encode())?;
+        self.relation_store_id.store(id.0, Ordering::SeqCst);
+        Ok(())
+    }
     pub(crate) fn relation_exists(&self, name: &str) -> Result<bool> {
-        let key = DataValue::from(name);
+        let name = self.qualified_name(name);
+        let key = DataValue::from(name.as_str());
         let encoded = vec![key].encode_as_key(RelationId::SYSTEM);
         if name.starts_with('_') {
             self.temp_store_tx.exists(&encoded, false)
@@ -561,6 +1211,7 @@ impl<'a> SessionTx<'a> {
             bail!("Cannot set triggers for temp store")
         }
         let mut original = self.get_relation(name, true)?;
+        original.ensure_not_view("set triggers on")?;
         if original.access_level < AccessLevel::Protected {
             bail!(InsufficientAccessLevel(
                 original.name.to_string(),
@@ -585,8 +1236,9 @@ impl<'a> SessionTx<'a> {
     }
     pub(crate) fn create_relation(
         &mut self,
-        input_meta: InputRelationHandle,
+        mut input_meta: InputRelationHandle,
     ) -> Result<RelationHandle> {
+        input_meta.name.name = self.qualified_name(&input_meta.name.name);
         let key = DataValue::Str(input_meta.name.name.clone());
         let encoded = vec![key].encode_as_key(RelationId::SYSTEM);
 
@@ -620,6 +1272,10 @@ impl<'a> SessionTx<'a> {
             fts_indices: Default::default(),
             lsh_indices: Default::default(),
             description: Default::default(),
+            soft_delete: false,
+            ttl: None,
+            views: Default::default(),
+            view: None,
         };
 
         let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
@@ -641,27 +1297,142 @@ impl<'a> SessionTx<'a> {
 
         Ok(meta)
     }
+    pub(crate) fn relation_infos(&self) -> Result<Vec<RelationInfo>> {
+        let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+        let upper =
+            vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
+        let ns_prefix = self.namespace.as_ref().map(|ns| format!("{ns}."));
+        let mut ret = vec![];
+        for kv_res in self.store_tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = kv_res?;
+            if upper <= k_slice {
+                break;
+            }
+            let meta = RelationHandle::decode(&v_slice)?;
+            // with a default namespace, only its relations are listed
+            if let Some(prefix) = &ns_prefix {
+                if !meta.name.starts_with(prefix.as_str()) {
+                    continue;
+                }
+            }
+            let n_keys = meta.metadata.keys.len();
+            let n_non_keys = meta.metadata.non_keys.len();
+            let access_level = if meta.name.contains(':') {
+                "index".to_string()
+            } else {
+                meta.access_level.to_string()
+            };
+            ret.push(RelationInfo {
+                name: meta.name.to_string(),
+                arity: n_keys + n_non_keys,
+                access_level,
+                n_keys,
+                n_non_keys,
+                n_put_triggers: meta.put_triggers.len(),
+                n_rm_triggers: meta.rm_triggers.len(),
+                n_replace_triggers: meta.replace_triggers.len(),
+                description: meta.description.to_string(),
+            });
+        }
+        Ok(ret)
+    }
+    pub(crate) fn column_infos(&self, name: &str) -> Result<Vec<ColumnInfo>> {
+        let handle = self.get_relation(name, false)?;
+        let keys = handle.metadata.keys.iter().map(|col| (true, col));
+        let non_keys = handle.metadata.non_keys.iter().map(|col| (false, col));
+        Ok(keys
+            .chain(non_keys)
+            .enumerate()
+            .map(|(index, (is_key, col))| ColumnInfo {
+                name: col.name.to_string(),
+                is_key,
+                index,
+                typing: col.typing.to_string(),
+                default_expr: col.default_gen.as_ref().map(|gen| gen.to_string()),
+            })
+            .collect())
+    }
     pub(crate) fn get_relation(&self, name: &str, lock: bool) -> Result<RelationHandle> {
         #[derive(Error, Diagnostic, Debug)]
         #[error("Cannot find requested stored relation '{0}'")]
         #[diagnostic(code(query::relation_not_found))]
-        struct StoredRelationNotFoundError(String);
+        struct StoredRelationNotFoundError(String, #[help] Option<String>);
+
+        #[derive(Error, Diagnostic, Debug)]
+        #[error("The name '{0}' is ambiguous as it matches the stored relations {1} ignoring case")]
+        #[diagnostic(code(query::ambiguous_relation_name))]
+        #[diagnostic(help("Use the exact name of the relation"))]
+        struct AmbiguousRelationName(String, String);
 
+        let name = self.qualified_name(name);
+        let name = name.as_str();
         let key = DataValue::from(name);
         let encoded = vec![key].encode_as_key(RelationId::SYSTEM);
 
         let found = if name.starts_with('_') {
             self.temp_store_tx
                 .get(&encoded, lock)?
-                .ok_or_else(|| StoredRelationNotFoundError(name.to_string()))?
+                .ok_or_else(|| StoredRelationNotFoundError(name.to_string(), None))?
         } else {
-            self.store_tx
-                .get(&encoded, lock)?
-                .ok_or_else(|| StoredRelationNotFoundError(name.to_string()))?
+            match self.store_tx.get(&encoded, lock)? {
+                Some(found) => found,
+                None => {
+                    let folded = name.to_lowercase();
+                    let candidates = self
+                        .relation_names()?
+                        .into_iter()
+                        .filter(|n| n.to_lowercase() == folded)
+                        .collect_vec();
+                    match candidates.as_slice() {
+                        [] => bail!(StoredRelationNotFoundError(
+                            name.to_string(),
+                            self.similar_relation_names(name)?
+                        )),
+                        [found] if self.case_insensitive_names => {
+                            let encoded = vec![DataValue::from(found.as_str())]
+                                .encode_as_key(RelationId::SYSTEM);
+                            self.store_tx.get(&encoded, lock)?.ok_or_else(|| {
+                                StoredRelationNotFoundError(name.to_string(), None)
+                            })?
+                        }
+                        _ if self.case_insensitive_names => bail!(AmbiguousRelationName(
+                            name.to_string(),
+                            candidates.join(", ")
+                        )),
+                        _ => bail!(StoredRelationNotFoundError(
+                            name.to_string(),
+                            Some(format!(
+                                "Names are case-sensitive, did you mean {}?",
+                                candidates.join(" or ")
+                            ))
+                        )),
+                    }
+                }
+            }
         };
         let metadata = RelationHandle::decode(&found)?;
         Ok(metadata)
     }
+    /// A hint naming the relations whose names are a few typos away from `name`, if any.
+    /// Indices are only considered if `name` names an index.
+    fn similar_relation_names(&self, name: &str) -> Result<Option<String>> {
+        let max_distance = (name.chars().count() / 3).clamp(1, 3);
+        let similar = self
+            .relation_names()?
+            .into_iter()
+            .filter(|n| n.contains(':') == name.contains(':'))
+            .map(|n| (edit_distance(name, &n), n))
+            .filter(|(d, _)| *d <= max_distance)
+            .sorted()
+            .take(3)
+            .map(|(_, n)| n)
+            .collect_vec();
+        Ok(if similar.is_empty() {
+            None
+        } else {
+            Some(format!("Did you mean {}?", similar.join(" or ")))
+        })
+    }
     pub(crate) fn describe_relation(&mut self, name: &str, description: &str) -> Result<()> {
         let mut meta = self.get_relation(name, true)?;
 
@@ -678,7 +1449,33 @@ impl<'a> SessionTx<'a> {
 
         Ok(())
     }
+    /// Removes a relation, first removing its indices if `cascade` is set.
+    pub(crate) fn remove_relation(
+        &mut self,
+        rel: &Symbol,
+        cascade: bool,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut to_clean = vec![];
+        if cascade && !rel.is_temp_store_name() {
+            let store = self.get_relation(rel, false)?;
+            let idx_names = store
+                .indices
+                .keys()
+                .chain(store.hnsw_indices.keys())
+                .chain(store.fts_indices.keys())
+                .chain(store.lsh_indices.keys())
+                .cloned()
+                .collect_vec();
+            for idx_name in idx_names {
+                to_clean.extend(self.remove_index(rel, &Symbol::new(idx_name, rel.span))?);
+            }
+        }
+        to_clean.extend(self.destroy_relation(rel)?);
+        Ok(to_clean)
+    }
     pub(crate) fn destroy_relation(&mut self, name: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let name = self.qualified_name(name);
+        let name = name.as_str();
         let is_temp = name.starts_with('_');
         let mut to_clean = vec![];
 
@@ -686,9 +1483,12 @@ impl<'a> SessionTx<'a> {
         //     bail!("Cannot destroy temp relation");
         // }
         let store = self.get_relation(name, true)?;
+        // the name found may differ in case from the one given
+        let name = store.name.as_str();
+        store.ensure_no_views("remove")?;
         if !store.has_no_index() {
             bail!(
-                "Cannot remove stored relation `{}` with indices attached.",
+                "Cannot remove stored relation `{}` with indices attached, use `cascade` to remove them as well.",
                 name
             );
         }
@@ -700,6 +1500,13 @@ impl<'a> SessionTx<'a> {
             ))
         }
 
+        if let Some(manifest) = &store.view {
+            if let Ok(mut base) = self.get_relation(&manifest.base, true) {
+                base.views.remove(name);
+                self.put_relation_handle(&base)?;
+            }
+        }
+
         for k in store.indices.keys() {
             let more_to_clean = self.destroy_relation(&format!("{name}:{k}"))?;
             to_clean.extend(more_to_clean);
@@ -736,9 +1543,293 @@ impl<'a> SessionTx<'a> {
         Ok(())
     }
 
+    pub(crate) fn enable_soft_delete(&mut self, rel: &Symbol) -> Result<()> {
+        let mut meta = self.get_relation(rel, true)?;
+        meta.ensure_not_view("enable soft deletes on")?;
+        meta.ensure_no_views("enable soft deletes on")?;
+        if meta.soft_delete {
+            return Ok(());
+        }
+        if meta.access_level < AccessLevel::Normal {
+            bail!(InsufficientAccessLevel(
+                meta.name.to_string(),
+                "enabling soft deletes".to_string(),
+                meta.access_level
+            ));
+        }
+        if meta.metadata.keys.last().unwrap().typing.coltype == ColType::Validity {
+            bail!(
+                "Cannot enable soft deletes on stored relation `{}` with validity",
+                meta.name
+            );
+        }
+        if meta.ttl.is_some() {
+            bail!(
+                "Cannot enable soft deletes on stored relation `{}` with a TTL",
+                meta.name
+            );
+        }
+        if meta
+            .metadata
+            .keys
+            .iter()
+            .chain(meta.metadata.non_keys.iter())
+            .any(|col| col.name == SOFT_DELETE_COL)
+        {
+            bail!(
+                "Cannot enable soft deletes on stored relation `{}`: column `{}` already exists",
+                meta.name,
+                SOFT_DELETE_COL
+            );
+        }
+
+        // every existing row gets the removal mark appended, unset
+        let rows: Vec<_> = meta.scan_all(self).try_collect()?;
+        meta.metadata.non_keys.push(ColumnDef {
+            name: SmartString::from(SOFT_DELETE_COL),
+            typing: NullableColType {
+                coltype: ColType::Float,
+                nullable: true,
+            },
+            default_gen: Some(Expr::Const {
+                val: DataValue::Null,
+                span: Default::default(),
+            }),
+        });
+        meta.soft_delete = true;
+        for mut row in rows {
+            row.push(DataValue::Null);
+            let key = meta.encode_key_for_store(&row, rel.span)?;
+            let val = meta.encode_val_for_store(&row, rel.span)?;
+            if meta.is_temp {
+                self.temp_store_tx.put(&key, &val)?;
+            } else {
+                self.store_tx.put(&key, &val)?;
+            }
+        }
+
+        let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        if meta.is_temp {
+            self.temp_store_tx.put(&name_key, &meta_val)?;
+        } else {
+            self.store_tx.put(&name_key, &meta_val)?;
+        }
+        Ok(())
+    }
+
+    /// Makes the rows of a relation expire `ttl` seconds after they are last written.
+    /// The first time, the [`TTL_COL`] column is appended, and the existing rows
+    /// expire `ttl` seconds from now. Later, only the rows written afterwards get the new TTL.
+    pub(crate) fn set_ttl(&mut self, rel: &Symbol, ttl: f64) -> Result<()> {
+        let mut meta = self.get_relation(rel, true)?;
+        meta.ensure_not_view("set the TTL of")?;
+        meta.ensure_no_views("set the TTL of")?;
+        if meta.access_level < AccessLevel::Normal {
+            bail!(InsufficientAccessLevel(
+                meta.name.to_string(),
+                "setting the TTL".to_string(),
+                meta.access_level
+            ));
+        }
+        if ttl.is_nan() || ttl <= 0. {
+            bail!("The TTL must be a positive number of seconds, got {}", ttl);
+        }
+        if meta.ttl.is_some() {
+            meta.ttl = Some(ttl);
+            return self.put_relation_meta(&meta);
+        }
+        if meta.metadata.keys.last().unwrap().typing.coltype == ColType::Validity {
+            bail!(
+                "Cannot set a TTL on stored relation `{}` with validity",
+                meta.name
+            );
+        }
+        if meta.soft_delete {
+            bail!(
+                "Cannot set a TTL on stored relation `{}` with soft deletes",
+                meta.name
+            );
+        }
+        if meta
+            .metadata
+            .keys
+            .iter()
+            .chain(meta.metadata.non_keys.iter())
+            .any(|col| col.name == TTL_COL)
+        {
+            bail!(
+                "Cannot set a TTL on stored relation `{}`: column `{}` already exists",
+                meta.name,
+                TTL_COL
+            );
+        }
+
+        let rows: Vec<_> = meta.scan_all(self).try_collect()?;
+        meta.metadata.non_keys.push(ColumnDef {
+            name: SmartString::from(TTL_COL),
+            typing: NullableColType {
+                coltype: ColType::Float,
+                nullable: true,
+            },
+            default_gen: Some(Expr::Const {
+                val: DataValue::Null,
+                span: Default::default(),
+            }),
+        });
+        meta.ttl = Some(ttl);
+        let expires_at = DataValue::from(self.now + ttl);
+        for mut row in rows {
+            row.push(expires_at.clone());
+            self.put_row(&meta, &row, rel.span)?;
+        }
+        self.put_relation_meta(&meta)
+    }
+
+    /// Physically removes the tombstones of rows soft-deleted before `before`,
+    /// given in seconds since the epoch.
+    pub(crate) fn purge_deleted(&mut self, rel: &Symbol, before: f64) -> Result<()> {
+        let mut meta = self.get_relation(rel, true)?;
+        if !meta.soft_delete {
+            bail!(
+                "Stored relation `{}` does not have soft deletes enabled",
+                meta.name
+            );
+        }
+        if meta.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                meta.name.to_string(),
+                "purging deleted rows".to_string(),
+                meta.access_level
+            ));
+        }
+
+        meta.soft_delete = false;
+        let mut to_purge = vec![];
+        for tuple in meta.scan_all(self) {
+            let tuple = tuple?;
+            if let Some(deleted_at) = tuple.last().and_then(|v| v.get_float()) {
+                if deleted_at < before {
+                    to_purge.push(meta.encode_key_for_store(&tuple, rel.span)?);
+                }
+            }
+        }
+        for key in to_purge {
+            if meta.is_temp {
+                self.temp_store_tx.del(&key)?;
+            } else {
+                self.store_tx.del(&key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The key ranges in storage of a stored relation and its indices.
+    pub(crate) fn relation_key_ranges(&self, rel: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        if rel.starts_with('_') {
+            bail!("Temp relation '{rel}' is not kept in storage");
+        }
+        Ok(self.get_relation(rel, false)?.key_ranges())
+    }
+
+    /// Scans all the stored rows of a relation for those that cannot be decoded,
+    /// returning their keys and the reasons.
+    pub(crate) fn check_relation(&self, rel: &Symbol) -> Result<NamedRows> {
+        let handle = self.get_relation(rel, false)?;
+        let lower = Tuple::default().encode_as_key(handle.id);
+        let upper = Tuple::default().encode_as_key(handle.id.next());
+        let it = if handle.is_temp {
+            self.temp_store_tx.range_scan(&lower, &upper)
+        } else {
+            self.store_tx.range_scan(&lower, &upper)
+        };
+        let mut rows = vec![];
+        for kv in it {
+            let (k, v) = kv?;
+            if let Err(err) = try_decode_tuple_from_kv(&k, &v, Some(handle.arity())) {
+                let corrupt = err.downcast::<CorruptData>()?;
+                rows.push(vec![DataValue::Bytes(k), DataValue::from(corrupt.reason)]);
+            }
+        }
+        Ok(NamedRows::new(
+            vec!["key_bytes".to_string(), "reason".to_string()],
+            rows,
+        ))
+    }
+    /// Removes the versions of rows in a relation with validity that no longer matter
+    /// for reads at `before` or later: for every key, everything older than the newest
+    /// version at or before `before` goes, as does that version itself if it is a retraction.
+    pub(crate) fn compact_history(&mut self, rel: &Symbol, before: ValidityTs) -> Result<()> {
+        let meta = self.get_relation(rel, true)?;
+        let n_keys = meta.metadata.keys.len();
+        if meta.metadata.keys[n_keys - 1].typing.coltype != ColType::Validity {
+            bail!(
+                "Cannot compact history of stored relation `{}` without validity",
+                meta.name
+            );
+        }
+        if meta.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                meta.name.to_string(),
+                "compacting history".to_string(),
+                meta.access_level
+            ));
+        }
+        if !meta.hnsw_indices.is_empty()
+            || !meta.fts_indices.is_empty()
+            || !meta.lsh_indices.is_empty()
+        {
+            bail!(
+                "Cannot compact history of stored relation `{}` with vector, FTS or LSH indices",
+                meta.name
+            );
+        }
+
+        let mut to_remove = vec![];
+        let mut cur_prefix: Option<Tuple> = None;
+        let mut settled = false;
+        // versions of the same key come newest first
+        for tuple in meta.scan_all(self) {
+            let tuple = tuple?;
+            if cur_prefix.as_deref() != Some(&tuple[..n_keys - 1]) {
+                cur_prefix = Some(tuple[..n_keys - 1].to_vec());
+                settled = false;
+            }
+            let vld = match &tuple[n_keys - 1] {
+                DataValue::Validity(vld) => *vld,
+                v => bail!("Bad validity {:?} in stored relation `{}`", v, meta.name),
+            };
+            if vld.timestamp.0 .0 > before.0 .0 {
+                continue;
+            }
+            if settled || !vld.is_assert.0 {
+                to_remove.push(tuple);
+            }
+            settled = true;
+        }
+
+        for tuple in to_remove {
+            for (idx_rel, extractor) in meta.indices.values() {
+                let idx_tup = extractor.iter().map(|i| tuple[*i].clone()).collect_vec();
+                let encoded = idx_rel.encode_key_for_store(&idx_tup, rel.span)?;
+                self.store_tx.del(&encoded)?;
+            }
+            let key = meta.encode_key_for_store(&tuple, rel.span)?;
+            if meta.is_temp {
+                self.temp_store_tx.del(&key)?;
+            } else {
+                self.store_tx.del(&key)?;
+            }
+        }
+        Ok(())
+    }
+
     pub(crate) fn create_minhash_lsh_index(&mut self, config: &MinHashLshConfig) -> Result<()> {
         // Get relation handle
         let mut rel_handle = self.get_relation(&config.base_relation, true)?;
+        rel_handle.ensure_not_view("create indices on")?;
 
         // Check if index already exists
         if rel_handle.has_index(&config.index_name) {
@@ -812,6 +1903,9 @@ impl<'a> SessionTx<'a> {
             n_rows_in_band: params.r,
             threshold: config.target_threshold.0,
             perms: perms.as_bytes().to_vec(),
+            n_perm: config.n_perm,
+            false_positive_weight: config.false_positive_weight.0,
+            false_negative_weight: config.false_negative_weight.0,
         };
 
         // populate index
@@ -869,6 +1963,7 @@ impl<'a> SessionTx<'a> {
     pub(crate) fn create_fts_index(&mut self, config: &FtsIndexConfig) -> Result<()> {
         // Get relation handle
         let mut rel_handle = self.get_relation(&config.base_relation, true)?;
+        rel_handle.ensure_not_view("create indices on")?;
 
         // Check if index already exists
         if rel_handle.has_index(&config.index_name) {
@@ -1010,6 +2105,7 @@ impl<'a> SessionTx<'a> {
     pub(crate) fn create_hnsw_index(&mut self, config: &HnswIndexConfig) -> Result<()> {
         // Get relation handle
         let mut rel_handle = self.get_relation(&config.base_relation, true)?;
+        rel_handle.ensure_not_view("create indices on")?;
 
         // Check if index already exists
         if rel_handle.has_index(&config.index_name) {
@@ -1190,7 +2286,7 @@ impl<'a> SessionTx<'a> {
 
         // update relation metadata
         let new_encoded =
-            vec![DataValue::from(&config.base_relation as &str)].encode_as_key(RelationId::SYSTEM);
+            vec![DataValue::from(&rel_handle.name as &str)].encode_as_key(RelationId::SYSTEM);
         let mut meta_val = vec![];
         rel_handle
             .serialize(&mut Serializer::new(&mut meta_val))
@@ -1223,6 +2319,8 @@ impl<'a> SessionTx<'a> {
             },
             key_bindings,
             dep_bindings,
+            binding_exprs: Default::default(),
+            on_conflict: Default::default(),
             span: Default::default(),
         };
         let idx_handle = self.create_relation(idx_handle)?;
@@ -1234,9 +2332,11 @@ impl<'a> SessionTx<'a> {
         rel_name: &Symbol,
         idx_name: &Symbol,
         cols: &[Symbol],
+        payload: &[Symbol],
     ) -> Result<()> {
         // Get relation handle
         let mut rel_handle = self.get_relation(rel_name, true)?;
+        rel_handle.ensure_not_view("create indices on")?;
 
         // Check if index already exists
         if rel_handle.has_index(&idx_name.name) {
@@ -1246,6 +2346,11 @@ impl<'a> SessionTx<'a> {
             ));
         }
 
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("column {0} in index {1} for relation {2} not found")]
+        #[diagnostic(code(tx::col_in_idx_not_found))]
+        pub(crate) struct ColInIndexNotFound(String, String, String);
+
         // Build column definitions
         let mut col_defs = vec![];
         'outer: for col in cols.iter() {
@@ -1261,11 +2366,6 @@ impl<'a> SessionTx<'a> {
                 }
             }
 
-            #[derive(Debug, Error, Diagnostic)]
-            #[error("column {0} in index {1} for relation {2} not found")]
-            #[diagnostic(code(tx::col_in_idx_not_found))]
-            pub(crate) struct ColInIndexNotFound(String, String, String);
-
             bail!(ColInIndexNotFound(
                 col.name.to_string(),
                 idx_name.name.to_string(),
@@ -1282,6 +2382,45 @@ impl<'a> SessionTx<'a> {
             col_defs.push(key.clone());
         }
 
+        // Payload columns come after the keys of the relation, so they do not
+        // take part in seeks, but let the index answer queries on its own
+        if !payload.is_empty() {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("index {0} for relation {1} cannot store payload columns")]
+            #[diagnostic(code(tx::bad_index_payload))]
+            pub(crate) struct BadIndexPayload(String, String, #[help] String);
+
+            if rel_handle.metadata.keys.last().unwrap().typing.coltype == ColType::Validity {
+                bail!(BadIndexPayload(
+                    idx_name.name.to_string(),
+                    rel_name.name.to_string(),
+                    "the last key of the index must be the validity of the relation".to_string()
+                ));
+            }
+            for col in payload {
+                if col_defs.iter().any(|c| c.name == col.name) {
+                    bail!(BadIndexPayload(
+                        idx_name.name.to_string(),
+                        rel_name.name.to_string(),
+                        format!("column {} is already in the index", col.name)
+                    ));
+                }
+                match rel_handle
+                    .metadata
+                    .non_keys
+                    .iter()
+                    .find(|c| c.name == col.name)
+                {
+                    Some(col_def) => col_defs.push(col_def.clone()),
+                    None => bail!(ColInIndexNotFound(
+                        col.name.to_string(),
+                        idx_name.name.to_string(),
+                        rel_name.name.to_string()
+                    )),
+                }
+            }
+        }
+
         let key_bindings = col_defs
             .iter()
             .map(|col| Symbol::new(col.name.clone(), Default::default()))
@@ -1300,6 +2439,8 @@ impl<'a> SessionTx<'a> {
             metadata: idx_meta,
             key_bindings,
             dep_bindings: vec![],
+            binding_exprs: Default::default(),
+            on_conflict: Default::default(),
             span: Default::default(),
         };
 
@@ -1357,7 +2498,7 @@ impl<'a> SessionTx<'a> {
 
         // update relation metadata
         let new_encoded =
-            vec![DataValue::from(&rel_name.name as &str)].encode_as_key(RelationId::SYSTEM);
+            vec![DataValue::from(&rel_handle.name as &str)].encode_as_key(RelationId::SYSTEM);
         let mut meta_val = vec![];
         rel_handle
             .serialize(&mut Serializer::new(&mut meta_val))
@@ -1401,7 +2542,7 @@ impl<'a> SessionTx<'a> {
         }
 
         let new_encoded =
-            vec![DataValue::from(&rel_name.name as &str)].encode_as_key(RelationId::SYSTEM);
+            vec![DataValue::from(&rel.name as &str)].encode_as_key(RelationId::SYSTEM);
         let mut meta_val = vec![];
         rel.serialize(&mut Serializer::new(&mut meta_val)).unwrap();
         self.store_tx.put(&new_encoded, &meta_val)?;
@@ -1409,10 +2550,201 @@ impl<'a> SessionTx<'a> {
         Ok(to_clean)
     }
 
+    /// Adds a non-key column to a stored relation. The existing rows are rewritten
+    /// with the default of the column, evaluated for each row. A nullable column
+    /// without a default gets null as its default, so that existing writes remain valid.
+    pub(crate) fn add_column(&mut self, rel: &Symbol, col: &ColumnDef) -> Result<()> {
+        let mut meta = self.get_relation(rel, true)?;
+        meta.ensure_not_view("add columns to")?;
+        meta.ensure_no_views("add columns to")?;
+        if meta.access_level < AccessLevel::Normal {
+            bail!(InsufficientAccessLevel(
+                meta.name.to_string(),
+                "adding columns".to_string(),
+                meta.access_level
+            ));
+        }
+        if meta
+            .metadata
+            .keys
+            .iter()
+            .chain(meta.metadata.non_keys.iter())
+            .any(|existing| existing.name == col.name)
+        {
+            bail!(
+                "Column `{}` already exists in stored relation `{}`",
+                col.name,
+                meta.name
+            );
+        }
+        if col.default_gen.is_none() && !col.typing.nullable {
+            bail!(
+                "Column `{}` added to stored relation `{}` must be nullable or have a default",
+                col.name,
+                meta.name
+            );
+        }
+
+        let mut col = col.clone();
+        if col.default_gen.is_none() {
+            col.default_gen = Some(Expr::Const {
+                val: DataValue::Null,
+                span: rel.span,
+            });
+        }
+        let default_gen = col.default_gen.clone().unwrap();
+
+        let rows = self.scan_all_with_tombstones(&meta)?;
+        // the removal mark of soft deletes and the expiry mark stay the last column
+        let pos = if meta.has_trailing_mark() {
+            meta.arity() - 1
+        } else {
+            meta.arity()
+        };
+        meta.metadata
+            .non_keys
+            .insert(pos - meta.metadata.keys.len(), col.clone());
+        shift_index_positions(&mut meta, |i| if i >= pos { i + 1 } else { i });
+
+        let cur_vld = current_validity();
+        for mut row in rows {
+            let val = col
+                .typing
+                .coerce(default_gen.clone().eval_to_const()?, cur_vld)?;
+            row.insert(pos, val);
+            self.put_row(&meta, &row, rel.span)?;
+        }
+        self.put_relation_meta(&meta)
+    }
+
+    /// Drops a non-key column of a stored relation, removing its values from the stored rows.
+    /// Columns used by indices cannot be dropped.
+    pub(crate) fn drop_column(&mut self, rel: &Symbol, col: &Symbol) -> Result<()> {
+        let mut meta = self.get_relation(rel, true)?;
+        meta.ensure_not_view("drop columns of")?;
+        meta.ensure_no_views("drop columns of")?;
+        if meta.access_level < AccessLevel::Normal {
+            bail!(InsufficientAccessLevel(
+                meta.name.to_string(),
+                "dropping columns".to_string(),
+                meta.access_level
+            ));
+        }
+        if meta.metadata.keys.iter().any(|key| key.name == col.name) {
+            bail!(
+                "Cannot drop key column `{}` of stored relation `{}`",
+                col.name,
+                meta.name
+            );
+        }
+        if meta.soft_delete && col.name == SOFT_DELETE_COL {
+            bail!(
+                "Column `{}` of stored relation `{}` is reserved for soft deletes",
+                col.name,
+                meta.name
+            );
+        }
+        if meta.ttl.is_some() && col.name == TTL_COL {
+            bail!(
+                "Column `{}` of stored relation `{}` is reserved for the expiry of rows",
+                col.name,
+                meta.name
+            );
+        }
+        let idx = match meta
+            .metadata
+            .non_keys
+            .iter()
+            .position(|existing| existing.name == col.name)
+        {
+            Some(idx) => idx,
+            None => bail!(
+                "Column `{}` not found in stored relation `{}`",
+                col.name,
+                meta.name
+            ),
+        };
+        let pos = meta.metadata.keys.len() + idx;
+
+        let mut users = vec![];
+        for (name, (_, mapping)) in meta.indices.iter() {
+            if mapping.contains(&pos) {
+                users.push(name.clone());
+            }
+        }
+        for (name, (_, manifest)) in meta.hnsw_indices.iter() {
+            let in_filter = match &manifest.index_filter {
+                Some(code) => code_mentions_column(code, &col.name)?,
+                None => false,
+            };
+            if manifest.vec_fields.contains(&pos) || in_filter {
+                users.push(name.clone());
+            }
+        }
+        for (name, (_, manifest)) in meta.fts_indices.iter() {
+            if code_mentions_column(&manifest.extractor, &col.name)? {
+                users.push(name.clone());
+            }
+        }
+        for (name, (_, _, manifest)) in meta.lsh_indices.iter() {
+            if code_mentions_column(&manifest.extractor, &col.name)? {
+                users.push(name.clone());
+            }
+        }
+        if !users.is_empty() {
+            bail!(
+                "Cannot drop column `{}` of stored relation `{}` used by indices: {}",
+                col.name,
+                meta.name,
+                users.iter().join(", ")
+            );
+        }
+
+        let rows = self.scan_all_with_tombstones(&meta)?;
+        meta.metadata.non_keys.remove(idx);
+        shift_index_positions(&mut meta, |i| if i > pos { i - 1 } else { i });
+        for mut row in rows {
+            row.remove(pos);
+            self.put_row(&meta, &row, rel.span)?;
+        }
+        self.put_relation_meta(&meta)
+    }
+
+    fn scan_all_with_tombstones(&self, meta: &RelationHandle) -> Result<Vec<Tuple>> {
+        let mut raw = meta.clone();
+        raw.soft_delete = false;
+        raw.ttl = None;
+        raw.scan_all(self).try_collect()
+    }
+
+    fn put_row(&mut self, meta: &RelationHandle, row: &Tuple, span: SourceSpan) -> Result<()> {
+        let key = meta.encode_key_for_store(row, span)?;
+        let val = meta.encode_val_for_store(row, span)?;
+        if meta.is_temp {
+            self.temp_store_tx.put(&key, &val)
+        } else {
+            self.store_tx.put(&key, &val)
+        }
+    }
+
+    fn put_relation_meta(&mut self, meta: &RelationHandle) -> Result<()> {
+        let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        if meta.is_temp {
+            self.temp_store_tx.put(&name_key, &meta_val)
+        } else {
+            self.store_tx.put(&name_key, &meta_val)
+        }
+    }
+
     pub(crate) fn rename_relation(&mut self, old: &Symbol, new: &Symbol) -> Result<()> {
         if old.name.starts_with('_') || new.name.starts_with('_') {
             bail!("Bad name given");
         }
+        let old = &Symbol::new(self.qualified_name(&old.name), old.span);
+        let new = &Symbol::new(self.qualified_name(&new.name), new.span);
         let new_key = DataValue::Str(new.name.clone());
         let new_encoded = vec![new_key].encode_as_key(RelationId::SYSTEM);
 
@@ -1420,10 +2752,10 @@ impl<'a> SessionTx<'a> {
             bail!(RelNameConflictError(new.name.to_string()))
         };
 
-        let old_key = DataValue::Str(old.name.clone());
-        let old_encoded = vec![old_key].encode_as_key(RelationId::SYSTEM);
-
         let mut rel = self.get_relation(old, true)?;
+        rel.ensure_not_view("rename")?;
+        rel.ensure_no_views("rename")?;
+        let old_encoded = vec![DataValue::Str(rel.name.clone())].encode_as_key(RelationId::SYSTEM);
         if rel.access_level < AccessLevel::Normal {
             bail!(InsufficientAccessLevel(
                 rel.name.to_string(),
@@ -1433,6 +2765,24 @@ impl<'a> SessionTx<'a> {
         }
         rel.name = new.name.clone();
 
+        // indices are stored as relations named after the base relation
+        for (idx_name, (idx, _)) in rel.indices.iter_mut() {
+            self.rename_index_relation(idx, &format!("{}:{}", new.name, idx_name))?;
+        }
+        for (idx_name, (idx, manifest)) in rel.hnsw_indices.iter_mut() {
+            self.rename_index_relation(idx, &format!("{}:{}", new.name, idx_name))?;
+            manifest.base_relation = new.name.clone();
+        }
+        for (idx_name, (idx, manifest)) in rel.fts_indices.iter_mut() {
+            self.rename_index_relation(idx, &format!("{}:{}", new.name, idx_name))?;
+            manifest.base_relation = new.name.clone();
+        }
+        for (idx_name, (idx, inv_idx, manifest)) in rel.lsh_indices.iter_mut() {
+            self.rename_index_relation(idx, &format!("{}:{}", new.name, idx_name))?;
+            self.rename_index_relation(inv_idx, &format!("{}:{}:inv", new.name, idx_name))?;
+            manifest.base_relation = new.name.clone();
+        }
+
         let mut meta_val = vec![];
         rel.serialize(&mut Serializer::new(&mut meta_val)).unwrap();
         self.store_tx.del(&old_encoded)?;
@@ -1440,6 +2790,20 @@ impl<'a> SessionTx<'a> {
 
         Ok(())
     }
+    fn rename_index_relation(&mut self, idx: &mut RelationHandle, new_name: &str) -> Result<()> {
+        let old_encoded = vec![DataValue::Str(idx.name.clone())].encode_as_key(RelationId::SYSTEM);
+        let new_encoded = vec![DataValue::from(new_name)].encode_as_key(RelationId::SYSTEM);
+        let mut stored = self.get_relation(&idx.name, true)?;
+        stored.name = SmartString::from(new_name);
+        idx.name = stored.name.clone();
+        let mut meta_val = vec![];
+        stored
+            .serialize(&mut Serializer::new(&mut meta_val))
+            .unwrap();
+        self.store_tx.del(&old_encoded)?;
+        self.store_tx.put(&new_encoded, &meta_val)?;
+        Ok(())
+    }
     pub(crate) fn rename_temp_relation(&mut self, old: Symbol, new: Symbol) -> Result<()> {
         let new_key = DataValue::Str(new.name.clone());
         let new_encoded = vec![new_key].encode_as_key(RelationId::SYSTEM);
@@ -1463,6 +2827,30 @@ impl<'a> SessionTx<'a> {
     }
 }
 
+/// Applies `f` to the positions of base relation columns recorded by the indices.
+fn shift_index_positions(meta: &mut RelationHandle, f: impl Fn(usize) -> usize) {
+    for (_, mapping) in meta.indices.values_mut() {
+        for i in mapping.iter_mut() {
+            *i = f(*i);
+        }
+    }
+    for (_, manifest) in meta.hnsw_indices.values_mut() {
+        for i in manifest.vec_fields.iter_mut() {
+            *i = f(*i);
+        }
+    }
+}
+
+/// Whether the stored code of an index extractor or filter refers to the column.
+fn code_mentions_column(code: &str, col: &str) -> Result<bool> {
+    let parsed = CozoScriptParser::parse(Rule::expr, code)
+        .into_diagnostic()?
+        .next()
+        .unwrap();
+    let expr = build_expr(parsed, &Default::default())?;
+    Ok(expr.bindings()?.iter().any(|b| b.name == col))
+}
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("Insufficient access level {2} for {1} on stored relation '{0}'")]
 #[diagnostic(code(tx::insufficient_access_level))]
@@ -0,0 +1,90 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Comparing the rows of a result with the expected ones, for tests: [diff_rows] ignores
+//! the order of the rows, [diff_rows_ordered] does not.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use crate::data::tuple::Tuple;
+
+/// How the rows of a result differ from the expected ones, as found by [diff_rows] or
+/// [diff_rows_ordered]. Rows repeated in one side more often than in the other are
+/// listed as many times as they are in excess.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RowsDiff {
+    /// The expected rows not in the result
+    pub missing: Vec<Tuple>,
+    /// The rows of the result not expected
+    pub extra: Vec<Tuple>,
+    /// For [diff_rows_ordered], the first position where the result and the expected
+    /// rows differ, which is set even if both have the same rows in another order
+    pub first_mismatch: Option<usize>,
+}
+
+impl RowsDiff {
+    /// Whether the result is as expected
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.first_mismatch.is_none()
+    }
+}
+
+impl Display for RowsDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "rows are as expected");
+        }
+        for row in &self.missing {
+            writeln!(f, "missing: {row:?}")?;
+        }
+        for row in &self.extra {
+            writeln!(f, "extra: {row:?}")?;
+        }
+        if let Some(pos) = self.first_mismatch {
+            writeln!(f, "first mismatch at row {pos}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare the rows of a result with the expected ones as multisets, so that the
+/// order does not matter but the number of times each row occurs does.
+pub fn diff_rows(expected: &[Tuple], actual: &[Tuple]) -> RowsDiff {
+    let mut counts: BTreeMap<&Tuple, isize> = BTreeMap::new();
+    for row in expected {
+        *counts.entry(row).or_default() += 1;
+    }
+    for row in actual {
+        *counts.entry(row).or_default() -= 1;
+    }
+    let mut diff = RowsDiff::default();
+    for (row, count) in counts {
+        let target = if count > 0 {
+            &mut diff.missing
+        } else {
+            &mut diff.extra
+        };
+        for _ in 0..count.unsigned_abs() {
+            target.push(row.clone());
+        }
+    }
+    diff
+}
+
+/// Compare the rows of a result with the expected ones as sequences: as [diff_rows],
+/// and in addition giving the first position where the two differ.
+pub fn diff_rows_ordered(expected: &[Tuple], actual: &[Tuple]) -> RowsDiff {
+    let mut diff = diff_rows(expected, actual);
+    diff.first_mismatch = expected
+        .iter()
+        .zip(actual)
+        .position(|(e, a)| e != a)
+        .or_else(|| (expected.len() != actual.len()).then_some(expected.len().min(actual.len())));
+    diff
+}
@@ -0,0 +1,133 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Converting between rows and user types implementing serde's traits.
+//!
+//! Struct fields are matched with columns by name, so their order does not matter.
+//! `Option`s are stored as nulls and nested structs and maps as `Json` values.
+
+use std::any::type_name;
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::data::json::JsonValue;
+use crate::data::relation::{ColType, ColumnDef};
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, ValidityTs};
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("cannot convert {0} to a row of relation '{1}': {2}")]
+#[diagnostic(code(serde::not_a_struct))]
+#[diagnostic(help("only structs and maps can be stored as rows"))]
+struct NotAStruct(&'static str, String, String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("field '{0}' of {1} has no corresponding column in relation '{2}'")]
+#[diagnostic(code(serde::unknown_field))]
+#[diagnostic(help("the columns of the relation are: {3}"))]
+struct UnknownField(String, &'static str, String, String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("column '{0}: {1}' of relation '{2}' has no corresponding field in {3}")]
+#[diagnostic(code(serde::missing_field))]
+struct MissingField(String, String, String, &'static str);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("field '{0}' of {1} cannot be stored in column '{0}: {2}' of relation '{3}': {4}")]
+#[diagnostic(code(serde::type_mismatch))]
+struct FieldTypeMismatch(String, &'static str, String, String, String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("row {0} with columns [{1}] cannot be read as {2}: {3}")]
+#[diagnostic(code(serde::deserialize_failed))]
+struct RowNotDeserializable(usize, String, &'static str, String);
+
+/// Serialize `value` into a row of `relation`, which has the columns `columns`
+/// in storage order, coercing each field to the type of its column.
+pub(crate) fn struct_to_row<T: Serialize>(
+    value: &T,
+    relation: &str,
+    columns: &[&ColumnDef],
+    cur_vld: ValidityTs,
+) -> Result<Tuple> {
+    let ty = type_name::<T>();
+    let mut fields = match serde_json::to_value(value) {
+        Ok(JsonValue::Object(fields)) => fields,
+        Ok(v) => bail!(NotAStruct(ty, relation.to_string(), format!("got {v}"))),
+        Err(err) => bail!(NotAStruct(ty, relation.to_string(), err.to_string())),
+    };
+    if let Some(field) = fields
+        .keys()
+        .find(|f| !columns.iter().any(|c| &c.name as &str == f.as_str()))
+    {
+        bail!(UnknownField(
+            field.clone(),
+            ty,
+            relation.to_string(),
+            columns.iter().map(|c| &c.name).join(", ")
+        ))
+    }
+    let mut row = Vec::with_capacity(columns.len());
+    for col in columns {
+        let Some(field) = fields.remove(&col.name as &str) else {
+            bail!(MissingField(
+                col.name.to_string(),
+                col.typing.to_string(),
+                relation.to_string(),
+                ty
+            ))
+        };
+        let val = match (&col.typing.coltype, field) {
+            (ColType::Bytes, JsonValue::Array(arr)) => arr
+                .iter()
+                .map(|v| v.as_u64().and_then(|b| u8::try_from(b).ok()))
+                .collect::<Option<Vec<_>>>()
+                .map(DataValue::Bytes)
+                .unwrap_or_else(|| DataValue::from(JsonValue::Array(arr))),
+            (_, field) => DataValue::from(field),
+        };
+        let val = col.typing.coerce(val, cur_vld).map_err(|err| {
+            FieldTypeMismatch(
+                col.name.to_string(),
+                ty,
+                col.typing.to_string(),
+                relation.to_string(),
+                err.to_string(),
+            )
+        })?;
+        row.push(val);
+    }
+    Ok(row)
+}
+
+/// Deserialize the `idx`-th row of a result with the given headers,
+/// taking the headers as field names.
+pub(crate) fn row_to_struct<T: DeserializeOwned>(
+    headers: &[String],
+    idx: usize,
+    row: Tuple,
+) -> Result<T> {
+    let fields = headers
+        .iter()
+        .zip(row)
+        .map(|(name, val)| {
+            let val = match val {
+                DataValue::Bytes(b) => JsonValue::from(b),
+                v => JsonValue::from(v),
+            };
+            (name.clone(), val)
+        })
+        .collect();
+    serde_json::from_value(JsonValue::Object(fields)).map_err(|err| {
+        RowNotDeserializable(idx, headers.join(", "), type_name::<T>(), err.to_string()).into()
+    })
+}
@@ -243,6 +243,13 @@ impl TempStore {
             TempStore::MeetAggr(m) => m.inner.is_empty(),
         }
     }
+    /// Drops all the rows, keeping the kind of store.
+    pub(crate) fn clear(&mut self) {
+        match self {
+            TempStore::Normal(n) => n.inner.clear(),
+            TempStore::MeetAggr(m) => m.inner.clear(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -6,17 +6,20 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::sync::atomic::{AtomicU32, AtomicU64};
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use miette::{bail, Result};
+use smartstring::{LazyCompact, SmartString};
 use crate::data::program::ReturnMutation;
 
-use crate::data::tuple::TupleT;
+use crate::data::tuple::{Tuple, TupleT};
 use crate::data::value::DataValue;
 use crate::fts::TokenizerCache;
 use crate::{CallbackOp, NamedRows};
 use crate::runtime::callback::CallbackCollector;
+use crate::runtime::db::RowSink;
+use crate::runtime::memory::MemoryAccountant;
 use crate::runtime::relation::RelationId;
 use crate::storage::temp::TempTx;
 use crate::storage::StoreTx;
@@ -27,6 +30,30 @@ pub struct SessionTx<'a> {
     pub(crate) relation_store_id: Arc<AtomicU64>,
     pub(crate) temp_store_id: AtomicU32,
     pub(crate) tokenizers: Arc<TokenizerCache>,
+    /// Counts the memory held by the query being run
+    pub(crate) memory: Arc<MemoryAccountant>,
+    /// Whether scans skip the rows that cannot be decoded instead of failing
+    pub(crate) skip_corrupt: bool,
+    /// The rows skipped by scans as they cannot be decoded
+    pub(crate) corrupt_skipped: AtomicUsize,
+    /// The namespace of the stored relations named without one
+    pub(crate) namespace: Option<SmartString<LazyCompact>>,
+    /// Whether stored relations not found by their exact names are looked up ignoring case
+    pub(crate) case_insensitive_names: bool,
+    pub(crate) catalog_version: Arc<AtomicU64>,
+    /// Set when the schemas of stored relations are changed, so that committing
+    /// bumps the catalog version and the plans cached before are dropped
+    pub(crate) catalog_changed: bool,
+    /// The time, in seconds since the epoch, at which rows of relations with a TTL
+    /// are written and read, and at which rows are soft-deleted
+    pub(crate) now: f64,
+    /// The number of triggers being run, each fired by a write of the one before
+    pub(crate) trigger_depth: usize,
+    /// What happened to each row of an `:insert` returning its rows: `inserted`, `updated`,
+    /// `replaced` for the rows overwritten by updates, or `skipped` for the ignored conflicts
+    pub(crate) insert_outcomes: Option<Vec<(&'static str, Tuple)>>,
+    /// Receives the rows of the query of the script instead of them being returned
+    pub(crate) row_sink: Option<Arc<dyn RowSink>>,
 }
 
 pub const CURRENT_STORAGE_VERSION: [u8; 1] = [0x00];
@@ -40,7 +67,7 @@ const STATUS_STR: &str = "status";
 const OK_STR: &str = "OK";
 
 impl<'a> SessionTx<'a> {
-    pub(crate) fn get_returning_rows(&self, callback_collector: &mut CallbackCollector, rel: &str, returning: &ReturnMutation) -> Result<NamedRows> {
+    pub(crate) fn get_returning_rows(&mut self, callback_collector: &mut CallbackCollector, rel: &str, returning: &ReturnMutation) -> Result<NamedRows> {
         let returned_rows = {
             match returning {
                 ReturnMutation::NotReturning => {
@@ -53,7 +80,14 @@ impl<'a> SessionTx<'a> {
                     let meta = self.get_relation(rel, false)?;
                     let target_len = meta.metadata.keys.len() + meta.metadata.non_keys.len();
                     let mut returned_rows = Vec::new();
-                    if let Some(collected) = callback_collector.get(&meta.name) {
+                    if let Some(outcomes) = self.insert_outcomes.take() {
+                        for (kind, row) in outcomes {
+                            let mut v = Vec::with_capacity(target_len + 1);
+                            v.push(DataValue::from(kind));
+                            v.extend(row);
+                            returned_rows.push(v);
+                        }
+                    } else if let Some(collected) = callback_collector.get(&meta.name) {
                         for (kind, insertions, deletions) in collected {
                             let (pos_key, neg_key) = match kind {
                                 CallbackOp::Put => { ("inserted", "replaced") }
@@ -131,6 +165,9 @@ impl<'a> SessionTx<'a> {
 
     pub fn commit_tx(&mut self) -> Result<()> {
         self.store_tx.commit()?;
+        if self.catalog_changed {
+            self.catalog_version.fetch_add(1, Ordering::AcqRel);
+        }
         Ok(())
     }
 }
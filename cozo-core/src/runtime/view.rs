@@ -0,0 +1,636 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Materialized views: stored relations holding the result of a query over another
+//! stored relation, kept up to date within the transactions writing to it.
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::{eval_bytecode_pred, Bytecode, Expr};
+use crate::data::program::{InputAtom, InputInlineRulesOrFixed, InputProgram, InputRuleApplyAtom};
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, ValidityTs};
+use crate::parse::{parse_script, SourceSpan};
+use crate::query::stored::make_const_rule;
+use crate::runtime::relation::{InputRelationHandle, RelationHandle};
+use crate::runtime::transact::SessionTx;
+use crate::storage::Storage;
+use crate::Db;
+
+/// The definition of a materialized view, as stored in its catalog entry
+#[derive(Clone, Debug, Eq, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct ViewManifest {
+    /// The stored relation the view is defined over
+    pub(crate) base: SmartString<LazyCompact>,
+    /// The query whose result the view holds
+    pub(crate) query: String,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot {1} materialized view '{0}'")]
+#[diagnostic(code(eval::view_not_writable))]
+#[diagnostic(help(
+    "The rows of a view are kept up to date with the relation it is defined over, use `::view refresh` to recompute them"
+))]
+pub(crate) struct ViewNotWritable(pub(crate) String, pub(crate) &'static str);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot {1} stored relation '{0}' with materialized views defined over it: {2}")]
+#[diagnostic(code(eval::relation_has_views))]
+#[diagnostic(help("Remove the views first"))]
+pub(crate) struct RelationHasViews(
+    pub(crate) String,
+    pub(crate) &'static str,
+    pub(crate) String,
+);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Unsupported view: {0}")]
+#[diagnostic(code(eval::unsupported_view))]
+#[diagnostic(help(
+    "A view is defined by a single rule reading one stored relation, with filters, \
+    grouping by the columns not aggregated and aggregating with `count`, `sum`, `min` or `max`"
+))]
+struct UnsupportedView(String);
+
+impl RelationHandle {
+    pub(crate) fn ensure_not_view(&self, action: &'static str) -> Result<()> {
+        if self.view.is_some() {
+            bail!(ViewNotWritable(self.name.to_string(), action))
+        }
+        Ok(())
+    }
+    pub(crate) fn ensure_no_views(&self, action: &'static str) -> Result<()> {
+        if !self.views.is_empty() {
+            bail!(RelationHasViews(
+                self.name.to_string(),
+                action,
+                self.views.iter().join(", ")
+            ))
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum ViewAggr {
+    Count,
+    Sum,
+    Min,
+    Max,
+}
+
+enum ViewFilter {
+    /// The column holds the constant given in the relation application
+    Equals(usize, DataValue),
+    /// The variable bound to the first column is bound to the second one as well
+    SameAs(usize, usize),
+    Predicate(Vec<Bytecode>, SourceSpan),
+}
+
+/// A view query taken apart, with everything referring to the columns of the base relation
+struct ViewDef {
+    program: InputProgram,
+    base: RelationHandle,
+    /// The variables grouped by, which are the keys of the view
+    key_vars: Vec<Symbol>,
+    /// The base columns of the keys of the view
+    key_cols: Vec<usize>,
+    /// The aggregations giving the non-key columns of the view, and the base columns aggregated
+    vals: Vec<(ViewAggr, usize)>,
+    /// The position within the rows of the view of each column of the query
+    head_pos: Vec<usize>,
+    filters: Vec<ViewFilter>,
+    span: SourceSpan,
+}
+
+impl ViewDef {
+    fn new(tx: &SessionTx<'_>, program: InputProgram) -> Result<Self> {
+        let entry = Symbol::new(PROG_ENTRY, Default::default());
+        let span = program
+            .prog
+            .get(&entry)
+            .map(|rules| rules.first_span())
+            .unwrap_or_default();
+        let unsupported = |reason: &str| UnsupportedView(reason.to_string());
+
+        if program.prog.len() != 1 {
+            bail!(unsupported("the query may only have its entry rule"))
+        }
+        let opts = &program.out_opts;
+        if opts.limit.is_some()
+            || opts.offset.is_some()
+            || !opts.sorters.is_empty()
+            || opts.after.is_some()
+            || opts.store_relation.is_some()
+            || opts.assertion.is_some()
+        {
+            bail!(unsupported("query options are not allowed"))
+        }
+        let rule = match program.prog.get(&entry) {
+            Some(InputInlineRulesOrFixed::Rules { rules }) if rules.len() == 1 => &rules[0],
+            Some(InputInlineRulesOrFixed::Rules { .. }) => {
+                bail!(unsupported(
+                    "the entry rule may only have a single definition"
+                ))
+            }
+            _ => bail!(unsupported("fixed rules are not allowed")),
+        };
+
+        let mut applied = None;
+        let mut predicates = vec![];
+        for atom in &rule.body {
+            match atom {
+                InputAtom::Relation { inner } if applied.is_none() => {
+                    if inner.valid_at.is_some() || inner.include_deleted {
+                        bail!(unsupported("the stored relation must be read as it is now"))
+                    }
+                    applied = Some((&inner.name, inner.args.iter().enumerate().collect_vec()));
+                }
+                InputAtom::NamedFieldRelation { inner } if applied.is_none() => {
+                    if inner.valid_at.is_some() || inner.include_deleted {
+                        bail!(unsupported("the stored relation must be read as it is now"))
+                    }
+                    let handle = tx.get_relation(&inner.name, false)?;
+                    let args: Vec<(usize, &Expr)> = inner
+                        .args
+                        .iter()
+                        .map(|(field, arg)| {
+                            handle
+                                .metadata
+                                .keys
+                                .iter()
+                                .chain(handle.metadata.non_keys.iter())
+                                .position(|col| col.name == *field)
+                                .map(|i| (i, arg))
+                                .ok_or_else(|| {
+                                    unsupported(&format!(
+                                        "stored relation '{}' has no column '{field}'",
+                                        handle.name
+                                    ))
+                                })
+                        })
+                        .try_collect()?;
+                    applied = Some((&inner.name, args));
+                }
+                InputAtom::Relation { .. } | InputAtom::NamedFieldRelation { .. } => {
+                    bail!(unsupported("only a single stored relation may be read"))
+                }
+                InputAtom::Predicate { inner } => predicates.push(inner.clone()),
+                _ => bail!(unsupported(
+                    "only a stored relation and filters may appear in the body"
+                )),
+            }
+        }
+        let Some((base_name, args)) = applied else {
+            bail!(unsupported("the body must read a stored relation"))
+        };
+        let base = tx.get_relation(base_name, false)?;
+        if base.is_temp || base.name.contains(':') || base.view.is_some() {
+            bail!(unsupported(
+                "views may only be defined over stored relations that are not temporary, indices or views"
+            ))
+        }
+        if base.ttl.is_some()
+            || base.metadata.keys.last().unwrap().typing.coltype == ColType::Validity
+        {
+            bail!(unsupported(
+                "views cannot be defined over stored relations with a TTL or validity"
+            ))
+        }
+
+        let mut binding_map: BTreeMap<Symbol, usize> = BTreeMap::new();
+        let mut filters = vec![];
+        for (col, arg) in args {
+            match arg {
+                Expr::Binding { var, .. } => {
+                    if var.is_ignored_symbol() || var.is_generated_ignored_symbol() {
+                        continue;
+                    }
+                    match binding_map.get(var) {
+                        Some(first) => filters.push(ViewFilter::SameAs(*first, col)),
+                        None => {
+                            binding_map.insert(var.clone(), col);
+                        }
+                    }
+                }
+                Expr::Const { val, .. } => filters.push(ViewFilter::Equals(col, val.clone())),
+                _ => bail!(unsupported(
+                    "the stored relation may only be applied to variables and constants"
+                )),
+            }
+        }
+        for mut pred in predicates {
+            let span = pred.span();
+            pred.fill_binding_indices(&binding_map)?;
+            filters.push(ViewFilter::Predicate(pred.compile()?, span));
+        }
+
+        let mut key_vars = vec![];
+        let mut key_cols = vec![];
+        let mut vals = vec![];
+        let mut is_key = vec![];
+        for (var, aggr) in rule.head.iter().zip(rule.aggr.iter()) {
+            if rule.head.iter().filter(|v| *v == var).count() > 1 {
+                bail!(unsupported(&format!(
+                    "variable '{var}' appears more than once in the head, bind the column to another variable"
+                )))
+            }
+            let col = *binding_map.get(var).ok_or_else(|| {
+                unsupported(&format!(
+                    "variable '{var}' in the head is not bound to a column"
+                ))
+            })?;
+            match aggr {
+                None => {
+                    key_vars.push(var.clone());
+                    key_cols.push(col);
+                    is_key.push(true);
+                }
+                Some((aggr, args)) => {
+                    let aggr = match aggr.name {
+                        "AGGR_COUNT" => ViewAggr::Count,
+                        "AGGR_SUM" => ViewAggr::Sum,
+                        "AGGR_MIN" => ViewAggr::Min,
+                        "AGGR_MAX" => ViewAggr::Max,
+                        name => bail!(unsupported(&format!(
+                            "aggregation '{}' cannot be kept up to date",
+                            name.trim_start_matches("AGGR_").to_lowercase()
+                        ))),
+                    };
+                    if !args.is_empty() {
+                        bail!(unsupported("aggregations may not take arguments"))
+                    }
+                    vals.push((aggr, col));
+                    is_key.push(false);
+                }
+            }
+        }
+        if key_vars.is_empty() {
+            bail!(unsupported(
+                "at least one column must be grouped by instead of aggregated"
+            ))
+        }
+        let mut n_keys = 0;
+        let mut n_vals = key_vars.len();
+        let head_pos = is_key
+            .into_iter()
+            .map(|is_key| {
+                let counter = if is_key { &mut n_keys } else { &mut n_vals };
+                *counter += 1;
+                *counter - 1
+            })
+            .collect_vec();
+
+        Ok(Self {
+            program,
+            base,
+            key_vars,
+            key_cols,
+            vals,
+            head_pos,
+            filters,
+            span,
+        })
+    }
+
+    /// Counts and sums can be updated by the rows written alone, as long as there is
+    /// a count telling when a group becomes empty.
+    fn is_incremental(&self) -> bool {
+        self.vals
+            .iter()
+            .all(|(aggr, _)| matches!(aggr, ViewAggr::Count | ViewAggr::Sum))
+            && self.vals.iter().any(|(aggr, _)| *aggr == ViewAggr::Count)
+    }
+
+    fn passes(&self, row: &[DataValue], stack: &mut Vec<DataValue>) -> Result<bool> {
+        for filter in &self.filters {
+            let passed = match filter {
+                ViewFilter::Equals(col, val) => row[*col] == *val,
+                ViewFilter::SameAs(first, second) => row[*first] == row[*second],
+                ViewFilter::Predicate(code, span) => eval_bytecode_pred(code, row, stack, *span)?,
+            };
+            if !passed {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn group_key(&self, row: &[DataValue]) -> Tuple {
+        self.key_cols.iter().map(|i| row[*i].clone()).collect_vec()
+    }
+
+    fn columns(&self) -> StoredRelationMetadata {
+        let col_def = |var: &Symbol, typing: NullableColType| ColumnDef {
+            name: var.name.clone(),
+            typing,
+            default_gen: None,
+        };
+        let base_cols = self
+            .base
+            .metadata
+            .keys
+            .iter()
+            .chain(self.base.metadata.non_keys.iter())
+            .collect_vec();
+        let rule = match self.program.prog.values().next() {
+            Some(InputInlineRulesOrFixed::Rules { rules }) => &rules[0],
+            _ => unreachable!(),
+        };
+        let val_vars = rule
+            .head
+            .iter()
+            .zip(rule.aggr.iter())
+            .filter(|(_, aggr)| aggr.is_some())
+            .map(|(var, _)| var);
+        StoredRelationMetadata {
+            keys: self
+                .key_vars
+                .iter()
+                .zip(self.key_cols.iter())
+                .map(|(var, col)| col_def(var, base_cols[*col].typing.clone()))
+                .collect(),
+            non_keys: val_vars
+                .zip(self.vals.iter())
+                .map(|(var, (aggr, col))| {
+                    let typing = match aggr {
+                        ViewAggr::Count => NullableColType {
+                            coltype: ColType::Int,
+                            nullable: false,
+                        },
+                        ViewAggr::Sum => NullableColType {
+                            coltype: ColType::Float,
+                            nullable: false,
+                        },
+                        ViewAggr::Min | ViewAggr::Max => base_cols[*col].typing.clone(),
+                    };
+                    col_def(var, typing)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    fn view_def<'s, S: Storage<'s>>(
+        &self,
+        db: &Db<S>,
+        query: &str,
+        cur_vld: ValidityTs,
+    ) -> Result<ViewDef> {
+        let program = parse_script(
+            query,
+            &Default::default(),
+            &db.fixed_rules.read().unwrap(),
+            cur_vld,
+        )?
+        .get_single_program()?;
+        ViewDef::new(self, program)
+    }
+
+    /// Creates a stored relation holding the result of `query`, which is kept up to
+    /// date with the relation the query reads.
+    pub(crate) fn create_view<'s, S: Storage<'s>>(
+        &mut self,
+        db: &Db<S>,
+        name: &Symbol,
+        query: &str,
+        cur_vld: ValidityTs,
+    ) -> Result<()> {
+        if name.is_temp_store_name() || name.name.contains(':') {
+            bail!("Bad name for a view: {}", name.name)
+        }
+        let def = self.view_def(db, query, cur_vld)?;
+        let metadata = def.columns();
+        let mut view = self.create_relation(InputRelationHandle {
+            name: name.clone(),
+            key_bindings: metadata
+                .keys
+                .iter()
+                .map(|col| Symbol::new(col.name.clone(), name.span))
+                .collect(),
+            dep_bindings: metadata
+                .non_keys
+                .iter()
+                .map(|col| Symbol::new(col.name.clone(), name.span))
+                .collect(),
+            metadata,
+            binding_exprs: Default::default(),
+            on_conflict: Default::default(),
+            span: name.span,
+        })?;
+        view.view = Some(ViewManifest {
+            base: def.base.name.clone(),
+            query: query.to_string(),
+        });
+        self.put_relation_handle(&view)?;
+
+        let mut base = def.base.clone();
+        base.views.insert(view.name.clone());
+        self.put_relation_handle(&base)?;
+
+        self.recompute_view(db, &view, &def, None, cur_vld)
+    }
+
+    /// Recomputes all rows of a view from the relation it is defined over.
+    pub(crate) fn refresh_view<'s, S: Storage<'s>>(
+        &mut self,
+        db: &Db<S>,
+        name: &Symbol,
+        cur_vld: ValidityTs,
+    ) -> Result<()> {
+        let view = self.get_relation(name, false)?;
+        let Some(manifest) = &view.view else {
+            bail!("Stored relation '{}' is not a view", view.name)
+        };
+        let def = self.view_def(db, &manifest.query, cur_vld)?;
+        let n_keys = view.metadata.keys.len();
+        let keys: Vec<_> = view
+            .scan_all(self)
+            .map_ok(|mut row| {
+                row.truncate(n_keys);
+                row
+            })
+            .try_collect()?;
+        for key in keys {
+            let encoded = view.encode_key_for_store(&key, def.span)?;
+            self.store_tx.del(&encoded)?;
+        }
+        self.recompute_view(db, &view, &def, None, cur_vld)
+    }
+
+    /// Brings the views defined over `base` up to date after rows were written to it.
+    /// `new_rows` are the rows written, and `old_rows` the rows they replaced or removed.
+    pub(crate) fn maintain_views<'s, S: Storage<'s>>(
+        &mut self,
+        db: &Db<S>,
+        base: &RelationHandle,
+        new_rows: &[DataValue],
+        old_rows: &[DataValue],
+        cur_vld: ValidityTs,
+    ) -> Result<()> {
+        let mut stack = vec![];
+        for name in &base.views {
+            let view = self.get_relation(name, false)?;
+            let Some(manifest) = &view.view else {
+                continue;
+            };
+            let def = self.view_def(db, &manifest.query, cur_vld)?;
+            let signed_rows = old_rows
+                .iter()
+                .map(|row| (-1, row))
+                .chain(new_rows.iter().map(|row| (1, row)));
+
+            if def.is_incremental() {
+                // keyed by the encoded keys of the rows of the view
+                let mut deltas: BTreeMap<Vec<u8>, (Tuple, i64, Vec<f64>)> = BTreeMap::new();
+                for (sign, row) in signed_rows {
+                    let DataValue::List(row) = row else {
+                        unreachable!()
+                    };
+                    if !def.passes(row, &mut stack)? {
+                        continue;
+                    }
+                    let key = def.group_key(row);
+                    let (_, count, sums) = deltas
+                        .entry(view.encode_key_for_store(&key, def.span)?)
+                        .or_insert_with(|| (key, 0, vec![0.; def.vals.len()]));
+                    *count += sign;
+                    for (sum, (aggr, col)) in sums.iter_mut().zip(def.vals.iter()) {
+                        if *aggr == ViewAggr::Sum {
+                            match &row[*col] {
+                                DataValue::Num(n) => *sum += sign as f64 * n.get_float(),
+                                v => bail!("cannot compute 'sum': encountered value {:?}", v),
+                            }
+                        }
+                    }
+                }
+                let n_keys = def.key_cols.len();
+                let count_pos = def
+                    .vals
+                    .iter()
+                    .position(|(aggr, _)| *aggr == ViewAggr::Count)
+                    .unwrap();
+                for (encoded, (key, count_delta, sum_deltas)) in deltas {
+                    let existing = view.get(self, &key)?;
+                    let count = existing
+                        .as_ref()
+                        .and_then(|row| row[n_keys + count_pos].get_int())
+                        .unwrap_or(0)
+                        + count_delta;
+                    if count <= 0 {
+                        self.store_tx.del(&encoded)?;
+                        continue;
+                    }
+                    let mut row = key;
+                    for (i, ((aggr, _), delta)) in def.vals.iter().zip(sum_deltas).enumerate() {
+                        row.push(match aggr {
+                            ViewAggr::Count => DataValue::from(count),
+                            _ => {
+                                let sum = existing
+                                    .as_ref()
+                                    .and_then(|row| row[n_keys + i].get_float())
+                                    .unwrap_or(0.);
+                                DataValue::from(sum + delta)
+                            }
+                        });
+                    }
+                    let val = view.encode_val_for_store(&row, def.span)?;
+                    self.store_tx.put(&encoded, &val)?;
+                }
+            } else {
+                let mut affected = BTreeMap::new();
+                for (_, row) in signed_rows {
+                    let DataValue::List(row) = row else {
+                        unreachable!()
+                    };
+                    if def.passes(row, &mut stack)? {
+                        let key = def.group_key(row);
+                        affected.insert(view.encode_key_for_store(&key, def.span)?, key);
+                    }
+                }
+                if affected.is_empty() {
+                    continue;
+                }
+                for encoded in affected.keys() {
+                    self.store_tx.del(encoded)?;
+                }
+                let groups = affected.into_values().collect_vec();
+                self.recompute_view(db, &view, &def, Some(groups), cur_vld)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the rows of the view computed from the base relation, for the groups given or all.
+    fn recompute_view<'s, S: Storage<'s>>(
+        &mut self,
+        db: &Db<S>,
+        view: &RelationHandle,
+        def: &ViewDef,
+        groups: Option<Vec<Tuple>>,
+        cur_vld: ValidityTs,
+    ) -> Result<()> {
+        let mut program = def.program.clone();
+        if let Some(groups) = groups {
+            let affected = Symbol::new("_affected", def.span);
+            make_const_rule(
+                &mut program,
+                &affected.name,
+                def.key_vars.clone(),
+                groups.into_iter().map(DataValue::List).collect(),
+            );
+            let entry = Symbol::new(PROG_ENTRY, Default::default());
+            if let Some(InputInlineRulesOrFixed::Rules { rules }) = program.prog.get_mut(&entry) {
+                rules[0].body.insert(
+                    0,
+                    InputAtom::Rule {
+                        inner: InputRuleApplyAtom {
+                            name: affected,
+                            args: def
+                                .key_vars
+                                .iter()
+                                .map(|var| Expr::Binding {
+                                    var: var.clone(),
+                                    tuple_pos: None,
+                                })
+                                .collect(),
+                            span: def.span,
+                        },
+                    },
+                );
+            }
+        }
+        let (res, _) = db.run_query(
+            self,
+            program,
+            cur_vld,
+            &Default::default(),
+            &mut Default::default(),
+            false,
+        )?;
+        for row in res.rows {
+            let mut view_row = vec![DataValue::Null; row.len()];
+            for (val, pos) in row.into_iter().zip(def.head_pos.iter()) {
+                view_row[*pos] = val;
+            }
+            let key = view.encode_key_for_store(&view_row, def.span)?;
+            let val = view.encode_val_for_store(&view_row, def.span)?;
+            self.store_tx.put(&key, &val)?;
+        }
+        Ok(())
+    }
+}
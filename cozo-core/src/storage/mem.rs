@@ -19,9 +19,9 @@ use std::sync::Arc;
 use itertools::Itertools;
 use miette::{bail, Result};
 
-use crate::data::tuple::{check_key_for_validity, Tuple};
+use crate::data::tuple::Tuple;
 use crate::data::value::ValidityTs;
-use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
+use crate::runtime::relation::{decode_skip_scan_row, try_decode_tuple_from_kv};
 use crate::storage::{Storage, StoreTx};
 use crate::utils::swap_option_result;
 
@@ -187,7 +187,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
         match self {
             MemTx::Reader(rdr) => Box::new(
                 rdr.range(lower.to_vec()..upper.to_vec())
-                    .map(|(k, v)| Ok(decode_tuple_from_kv(k, v, None))),
+                    .map(|(k, v)| try_decode_tuple_from_kv(k, v, None)),
             ),
             MemTx::Writer(wtr, cache) => Box::new(CacheIter {
                 change_iter: cache.range(lower.to_vec()..upper.to_vec()).fuse(),
@@ -212,8 +212,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
                     valid_at,
                     next_bound: lower.to_vec(),
                     size_hint: None,
-                }
-                .map(Ok),
+                },
             ),
             MemTx::Writer(stored, delta) => Box::new(
                 SkipDualIterator {
@@ -222,8 +221,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
                     upper: upper.to_vec(),
                     valid_at,
                     next_bound: lower.to_vec(),
-                }
-                .map(Ok),
+                },
             ),
         }
     }
@@ -246,6 +244,31 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
                 db_iter: wtr.range(lower.to_vec()..upper.to_vec()).fuse(),
                 change_cache: None,
                 db_cache: None,
+                reverse: false,
+            }),
+        }
+    }
+
+    fn range_scan_rev<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        match self {
+            MemTx::Reader(rdr) => Box::new(
+                rdr.range(lower.to_vec()..upper.to_vec())
+                    .rev()
+                    .map(|(k, v)| Ok((k.clone(), v.clone()))),
+            ),
+            MemTx::Writer(wtr, cache) => Box::new(CacheIterRaw {
+                change_iter: cache.range(lower.to_vec()..upper.to_vec()).rev().fuse(),
+                db_iter: wtr.range(lower.to_vec()..upper.to_vec()).rev().fuse(),
+                change_cache: None,
+                db_cache: None,
+                reverse: true,
             }),
         }
     }
@@ -261,6 +284,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
                 db_iter: wtr.range(lower.to_vec()..upper.to_vec()).fuse(),
                 change_cache: None,
                 db_cache: None,
+                reverse: false,
             })
             .count(),
         })
@@ -277,6 +301,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
                 db_iter: wtr.iter().fuse(),
                 change_cache: None,
                 db_cache: None,
+                reverse: false,
             }),
         }
     }
@@ -291,6 +316,8 @@ where
     db_iter: T,
     change_cache: Option<(&'a Vec<u8>, &'a Option<Vec<u8>>)>,
     db_cache: Option<(&'a Vec<u8>, &'a Vec<u8>)>,
+    /// Set when both iterators run in descending order
+    reverse: bool,
 }
 
 impl<'a, C, T> CacheIterRaw<'a, C, T>
@@ -332,23 +359,26 @@ where
                     let (k, v) = self.db_cache.take().unwrap();
                     return Ok(Some((k.clone(), v.clone())));
                 }
-                (Some((ck, _)), Some((dk, _))) => match ck.cmp(dk) {
-                    Ordering::Less => {
-                        let (k, sv) = self.change_cache.take().unwrap();
-                        match sv {
-                            None => continue,
-                            Some(v) => return Ok(Some((k.clone(), v.clone()))),
+                (Some((ck, _)), Some((dk, _))) => {
+                    let order = if self.reverse { dk.cmp(ck) } else { ck.cmp(dk) };
+                    match order {
+                        Ordering::Less => {
+                            let (k, sv) = self.change_cache.take().unwrap();
+                            match sv {
+                                None => continue,
+                                Some(v) => return Ok(Some((k.clone(), v.clone()))),
+                            }
+                        }
+                        Ordering::Greater => {
+                            let (k, v) = self.db_cache.take().unwrap();
+                            return Ok(Some((k.clone(), v.clone())));
+                        }
+                        Ordering::Equal => {
+                            self.db_cache.take();
+                            continue;
                         }
                     }
-                    Ordering::Greater => {
-                        let (k, v) = self.db_cache.take().unwrap();
-                        return Ok(Some((k.clone(), v.clone())));
-                    }
-                    Ordering::Equal => {
-                        self.db_cache.take();
-                        continue;
-                    }
-                },
+                }
             }
         }
     }
@@ -402,24 +432,24 @@ impl CacheIter<'_> {
                     let (k, cv) = self.change_cache.take().unwrap();
                     match cv {
                         None => continue,
-                        Some(v) => return Ok(Some(decode_tuple_from_kv(k, v, None))),
+                        Some(v) => return try_decode_tuple_from_kv(k, v, None).map(Some),
                     }
                 }
                 (None, Some(_)) => {
                     let (k, v) = self.db_cache.take().unwrap();
-                    return Ok(Some(decode_tuple_from_kv(k, v, None)));
+                    return try_decode_tuple_from_kv(k, v, None).map(Some);
                 }
                 (Some((ck, _)), Some((dk, _))) => match ck.cmp(dk) {
                     Ordering::Less => {
                         let (k, sv) = self.change_cache.take().unwrap();
                         match sv {
                             None => continue,
-                            Some(v) => return Ok(Some(decode_tuple_from_kv(k, v, None))),
+                            Some(v) => return try_decode_tuple_from_kv(k, v, None).map(Some),
                         }
                     }
                     Ordering::Greater => {
                         let (k, v) = self.db_cache.take().unwrap();
-                        return Ok(Some(decode_tuple_from_kv(k, v, None)));
+                        return try_decode_tuple_from_kv(k, v, None).map(Some);
                     }
                     Ordering::Equal => {
                         self.db_cache.take();
@@ -450,7 +480,7 @@ pub(crate) struct SkipIterator<'a> {
 }
 
 impl<'a> Iterator for SkipIterator<'a> {
-    type Item = Tuple;
+    type Item = Result<Tuple>;
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
@@ -464,12 +494,15 @@ impl<'a> Iterator for SkipIterator<'a> {
             match nxt {
                 None => return None,
                 Some((candidate_key, candidate_val)) => {
-                    let (ret, nxt_bound) =
-                        check_key_for_validity(candidate_key, self.valid_at, self.size_hint);
+                    let (ret, nxt_bound) = decode_skip_scan_row(
+                        candidate_key,
+                        candidate_val,
+                        self.valid_at,
+                        self.size_hint,
+                    );
                     self.next_bound = nxt_bound;
-                    if let Some(mut nk) = ret {
-                        extend_tuple_from_v(&mut nk, candidate_val);
-                        return Some(nk);
+                    if let Some(ret) = ret.transpose() {
+                        return Some(ret);
                     }
                 }
             }
@@ -486,7 +519,7 @@ struct SkipDualIterator<'a> {
 }
 
 impl<'a> Iterator for SkipDualIterator<'a> {
-    type Item = Tuple;
+    type Item = Result<Tuple>;
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
@@ -508,8 +541,8 @@ impl<'a> Iterator for SkipDualIterator<'a> {
                 (None, None) => return None,
                 (None, Some((delta_key, maybe_delta_val))) => match maybe_delta_val {
                     None => {
-                        let (_, nxt_seek) = check_key_for_validity(delta_key, self.valid_at, None);
-                        self.next_bound = nxt_seek;
+                        // deleted in this transaction: only where to seek next matters
+                        self.next_bound = decode_skip_scan_row(delta_key, &[], self.valid_at, None).1;
                         continue;
                     }
                     Some(delta_val) => (delta_key, delta_val),
@@ -521,9 +554,8 @@ impl<'a> Iterator for SkipDualIterator<'a> {
                     } else {
                         match maybe_delta_val {
                             None => {
-                                let (_, nxt_seek) =
-                                    check_key_for_validity(delta_key, self.valid_at, None);
-                                self.next_bound = nxt_seek;
+                                self.next_bound =
+                                    decode_skip_scan_row(delta_key, &[], self.valid_at, None).1;
                                 continue;
                             }
                             Some(delta_val) => (delta_key, delta_val),
@@ -531,11 +563,11 @@ impl<'a> Iterator for SkipDualIterator<'a> {
                     }
                 }
             };
-            let (ret, nxt_bound) = check_key_for_validity(candidate_key, self.valid_at, None);
+            let (ret, nxt_bound) =
+                decode_skip_scan_row(candidate_key, candidate_val, self.valid_at, None);
             self.next_bound = nxt_bound;
-            if let Some(mut nk) = ret {
-                extend_tuple_from_v(&mut nk, candidate_val);
-                return Some(nk);
+            if let Some(ret) = ret.transpose() {
+                return Some(ret);
             }
         }
     }
@@ -6,12 +6,13 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use itertools::Itertools;
-use miette::Result;
+use std::path::Path;
+
+use miette::{bail, Result};
 
 use crate::data::tuple::Tuple;
 use crate::data::value::ValidityTs;
-use crate::decode_tuple_from_kv;
+use crate::try_decode_tuple_from_kv;
 
 pub(crate) mod mem;
 #[cfg(feature = "storage-rocksdb")]
@@ -40,6 +41,37 @@ pub trait Storage<'s>: Send + Sync + Clone {
     /// have the concept of compaction.
     fn range_compact(&'s self, lower: &[u8], upper: &[u8]) -> Result<()>;
 
+    /// Compact the key range like [Self::range_compact], but also rewrite the data
+    /// that is already at the bottommost level of the storage, so that the tombstones
+    /// left there by removals are dropped too. The default implementation is
+    /// [Self::range_compact], for storages that do not keep tombstones.
+    fn range_compact_dropping_tombstones(&'s self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.range_compact(lower, upper)
+    }
+
+    /// The approximate number of bytes the key range takes in storage.
+    /// The default implementation sums the sizes of the keys and values in the range.
+    fn approximate_size(&'s self, lower: &[u8], upper: &[u8]) -> Result<u64> {
+        let tx = self.transact(false)?;
+        let mut size = 0;
+        for kv in tx.range_scan(lower, upper) {
+            let (k, v) = kv?;
+            size += (k.len() + v.len()) as u64;
+        }
+        Ok(size)
+    }
+
+    /// Create a consistent snapshot of the whole store in the directory `dir`, which must not
+    /// exist yet, that can be opened as a database with the same storage engine.
+    /// The default implementation fails: only engines with cheap physical snapshots support this.
+    fn checkpoint(&'s self, dir: &Path) -> Result<()> {
+        bail!(
+            "Cannot create checkpoint in {}: not supported by the {} storage engine",
+            dir.display(),
+            self.storage_kind()
+        )
+    }
+
     /// Put multiple key-value pairs into the database.
     /// No duplicate data will be sent, and the order data come in is strictly ascending.
     /// There will be no other access to the database while this function is running.
@@ -101,11 +133,19 @@ pub trait StoreTx<'s>: Sync {
     /// and discard all changes introduced by this transaction.
     fn commit(&mut self) -> Result<()>;
 
+    /// Move the snapshot read by the transaction to the latest committed data, keeping the
+    /// writes of the transaction itself. Used for [`ReadConsistency::Latest`](crate::ReadConsistency).
+    /// The default implementation does nothing, which is right for engines where no other
+    /// transaction can commit while this one is open.
+    fn refresh_snapshot(&mut self) -> Result<()> {
+        Ok(())
+    }
+
     /// Scan on a range. `lower` is inclusive whereas `upper` is exclusive.
     /// The default implementation calls [`range_scan_owned`](Self::range_scan) and converts the results.
     ///
-    /// The implementation must call [`decode_tuple_from_kv`](crate::decode_tuple_from_kv) to obtain
-    /// a decoded tuple in the loop of the iterator.
+    /// The implementation must call [`try_decode_tuple_from_kv`](crate::try_decode_tuple_from_kv)
+    /// to obtain a decoded tuple in the loop of the iterator.
     fn range_scan_tuple<'a>(
         &'a self,
         lower: &[u8],
@@ -115,7 +155,7 @@ pub trait StoreTx<'s>: Sync {
         's: 'a,
     {
         let it = self.range_scan(lower, upper);
-        Box::new(it.map_ok(|(k, v)| decode_tuple_from_kv(&k, &v, None)))
+        Box::new(it.map(|kv| kv.and_then(|(k, v)| try_decode_tuple_from_kv(&k, &v, None))))
     }
 
     /// Scan on a range with a certain validity.
@@ -151,6 +191,18 @@ pub trait StoreTx<'s>: Sync {
     where
         's: 'a;
 
+    /// Scan on a range in descending order and return the raw results.
+    /// `lower` is inclusive whereas `upper` is exclusive.
+    ///
+    /// Scans stopping early, as for `:order` with `:limit`, should not read the whole range.
+    fn range_scan_rev<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a;
+
     /// Return the number of rows in the range.
     fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
     where
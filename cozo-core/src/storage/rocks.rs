@@ -10,14 +10,14 @@ use std::fs;
 use std::path::{Path, PathBuf};
 
 use log::info;
-use miette::{miette, IntoDiagnostic, Result, WrapErr};
+use miette::{bail, miette, IntoDiagnostic, Result, WrapErr};
 
 use cozorocks::{DbBuilder, DbIter, RocksDb, Tx};
 
-use crate::data::tuple::{check_key_for_validity, Tuple};
+use crate::data::tuple::Tuple;
 use crate::data::value::ValidityTs;
 use crate::runtime::db::{BadDbInit, DbManifest};
-use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
+use crate::runtime::relation::{decode_skip_scan_row, try_decode_tuple_from_kv};
 use crate::storage::{Storage, StoreTx};
 use crate::utils::swap_option_result;
 use crate::Db;
@@ -135,7 +135,36 @@ impl Storage<'_> for RocksDbStorage {
     }
 
     fn range_compact(&self, lower: &[u8], upper: &[u8]) -> Result<()> {
-        self.db.range_compact(lower, upper).into_diagnostic()
+        self.db.range_compact(lower, upper, false).into_diagnostic()
+    }
+
+    fn range_compact_dropping_tombstones(&self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.db.range_compact(lower, upper, true).into_diagnostic()
+    }
+
+    fn approximate_size(&self, lower: &[u8], upper: &[u8]) -> Result<u64> {
+        self.db.approximate_size(lower, upper).into_diagnostic()
+    }
+
+    fn checkpoint(&self, dir: &Path) -> Result<()> {
+        if dir.exists() {
+            bail!("Cannot create checkpoint: {} already exists", dir.display());
+        }
+        // laid out as `new_cozo_rocksdb` expects, with the checkpoint as the data directory
+        fs::create_dir_all(dir).into_diagnostic()?;
+        fs::write(
+            dir.join("manifest"),
+            rmp_serde::to_vec_named(&DbManifest {
+                storage_version: CURRENT_STORAGE_VERSION,
+            })
+            .into_diagnostic()?,
+        )
+        .into_diagnostic()?;
+        let store_path = dir.join("data");
+        let store_path = store_path
+            .to_str()
+            .ok_or_else(|| miette!("bad path name"))?;
+        self.db.create_checkpoint(store_path).into_diagnostic()
     }
 
     fn batch_put<'a>(
@@ -208,6 +237,11 @@ impl<'s> StoreTx<'s> for RocksDbTx {
         Ok(self.db_tx.commit()?)
     }
 
+    fn refresh_snapshot(&mut self) -> Result<()> {
+        self.db_tx.set_snapshot();
+        Ok(())
+    }
+
     fn range_scan_tuple<'a>(
         &'a self,
         lower: &[u8],
@@ -257,6 +291,24 @@ impl<'s> StoreTx<'s> for RocksDbTx {
         })
     }
 
+    fn range_scan_rev<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        let mut inner = self.db_tx.iterator().upper_bound(upper).start();
+        inner.seek_back(upper);
+        Box::new(RocksDbIteratorRawRev {
+            inner,
+            started: false,
+            lower_bound: lower.to_vec(),
+            upper_bound: upper.to_vec(),
+        })
+    }
+
     fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
     where
         's: 'a,
@@ -303,7 +355,7 @@ impl RocksDbIterator {
                     None
                 } else {
                     // upper bound is exclusive
-                    Some(decode_tuple_from_kv(k_slice, v_slice, None))
+                    Some(try_decode_tuple_from_kv(k_slice, v_slice, None)?)
                 }
             }
         })
@@ -337,10 +389,10 @@ impl RocksDbSkipIterator {
                         return Ok(None);
                     }
 
-                    let (ret, nxt_bound) = check_key_for_validity(k_slice, self.valid_at, None);
+                    let (ret, nxt_bound) =
+                        decode_skip_scan_row(k_slice, v_slice, self.valid_at, None);
                     self.next_bound = nxt_bound;
-                    if let Some(mut tup) = ret {
-                        extend_tuple_from_v(&mut tup, v_slice);
+                    if let Some(tup) = ret? {
                         return Ok(Some(tup));
                     }
                 }
@@ -392,3 +444,45 @@ impl Iterator for RocksDbIteratorRaw {
         swap_option_result(self.next_inner())
     }
 }
+
+/// Like [RocksDbIteratorRaw], in descending order
+pub(crate) struct RocksDbIteratorRawRev {
+    inner: DbIter,
+    started: bool,
+    lower_bound: Vec<u8>,
+    upper_bound: Vec<u8>,
+}
+
+impl RocksDbIteratorRawRev {
+    #[inline]
+    fn next_inner(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        if self.started {
+            self.inner.prev()
+        } else {
+            self.started = true;
+        }
+        loop {
+            match self.inner.pair()? {
+                None => return Ok(None),
+                Some((k_slice, v_slice)) => {
+                    if k_slice < self.lower_bound.as_slice() {
+                        return Ok(None);
+                    }
+                    // seeking back lands on the upper bound if it is a key, which is excluded
+                    if k_slice < self.upper_bound.as_slice() {
+                        return Ok(Some((k_slice.to_vec(), v_slice.to_vec())));
+                    }
+                }
+            }
+            self.inner.prev();
+        }
+    }
+}
+
+impl Iterator for RocksDbIteratorRawRev {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        swap_option_result(self.next_inner())
+    }
+}
@@ -17,7 +17,7 @@ use sled::{Batch, Config, Db, IVec, Iter, Mode};
 
 use crate::data::tuple::Tuple;
 use crate::data::value::ValidityTs;
-use crate::runtime::relation::decode_tuple_from_kv;
+use crate::runtime::relation::try_decode_tuple_from_kv;
 use crate::storage::{Storage, StoreTx};
 use crate::utils::{swap_option_result, TempCollector};
 
@@ -132,7 +132,7 @@ impl<'s> StoreTx<'s> for SledTx {
     #[inline]
     fn del(&mut self, key: &[u8]) -> Result<()> {
         self.ensure_changes_db()?;
-        let val_to_write = [PUT_MARKER];
+        let val_to_write = [DEL_MARKER];
         self.changes
             .as_mut()
             .unwrap()
@@ -200,12 +200,10 @@ impl<'s> StoreTx<'s> for SledTx {
                 db_cache: None,
             })
         } else {
-            Box::new(
-                self.db
-                    .range(lower.to_vec()..upper.to_vec())
-                    .map(|d| d.into_diagnostic())
-                    .map_ok(|(k, v)| decode_tuple_from_kv(&k, &v, None)),
-            )
+            Box::new(self.db.range(lower.to_vec()..upper.to_vec()).map(|d| {
+                let (k, v) = d.into_diagnostic()?;
+                try_decode_tuple_from_kv(&k, &v, None)
+            }))
         }
     }
 
@@ -236,6 +234,7 @@ impl<'s> StoreTx<'s> for SledTx {
                 db_iter,
                 change_cache: None,
                 db_cache: None,
+                reverse: false,
             })
         } else {
             Box::new(
@@ -247,6 +246,35 @@ impl<'s> StoreTx<'s> for SledTx {
         }
     }
 
+    fn range_scan_rev<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        if let Some(changes) = &self.changes {
+            let change_iter = changes.range(lower.to_vec()..upper.to_vec()).rev().fuse();
+            let db_iter = self.db.range(lower.to_vec()..upper.to_vec()).rev().fuse();
+            Box::new(SledIterRaw {
+                change_iter,
+                db_iter,
+                change_cache: None,
+                db_cache: None,
+                reverse: true,
+            })
+        } else {
+            Box::new(
+                self.db
+                    .range(lower.to_vec()..upper.to_vec())
+                    .rev()
+                    .map(|d| d.into_diagnostic())
+                    .map_ok(|(k, v)| (k.to_vec(), v.to_vec())),
+            )
+        }
+    }
+
     fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
     where
         's: 'a,
@@ -259,6 +287,7 @@ impl<'s> StoreTx<'s> for SledTx {
                 db_iter,
                 change_cache: None,
                 db_cache: None,
+                reverse: false,
             })
             .count()
         } else {
@@ -274,14 +303,22 @@ impl<'s> StoreTx<'s> for SledTx {
     }
 }
 
-struct SledIterRaw {
-    change_iter: Fuse<Iter>,
-    db_iter: Fuse<Iter>,
+struct SledIterRaw<I = Iter>
+where
+    I: Iterator<Item = sled::Result<(IVec, IVec)>>,
+{
+    change_iter: Fuse<I>,
+    db_iter: Fuse<I>,
     change_cache: Option<(IVec, IVec)>,
     db_cache: Option<(IVec, IVec)>,
+    /// Set when both iterators run in descending order
+    reverse: bool,
 }
 
-impl SledIterRaw {
+impl<I> SledIterRaw<I>
+where
+    I: Iterator<Item = sled::Result<(IVec, IVec)>>,
+{
     #[inline]
     fn fill_cache(&mut self) -> Result<()> {
         if self.change_cache.is_none() {
@@ -317,30 +354,36 @@ impl SledIterRaw {
                     let (k, v) = self.db_cache.take().unwrap();
                     return Ok(Some((k.to_vec(), v.to_vec())));
                 }
-                (Some((ck, _)), Some((dk, _))) => match ck.cmp(dk) {
-                    Ordering::Less => {
-                        let (k, sv) = self.change_cache.take().unwrap();
-                        if sv[0] == DEL_MARKER {
+                (Some((ck, _)), Some((dk, _))) => {
+                    let order = if self.reverse { dk.cmp(ck) } else { ck.cmp(dk) };
+                    match order {
+                        Ordering::Less => {
+                            let (k, sv) = self.change_cache.take().unwrap();
+                            if sv[0] == DEL_MARKER {
+                                continue;
+                            } else {
+                                return Ok(Some((k.to_vec(), sv[1..].to_vec())));
+                            }
+                        }
+                        Ordering::Greater => {
+                            let (k, v) = self.db_cache.take().unwrap();
+                            return Ok(Some((k.to_vec(), v.to_vec())));
+                        }
+                        Ordering::Equal => {
+                            self.db_cache.take();
                             continue;
-                        } else {
-                            return Ok(Some((k.to_vec(), sv[1..].to_vec())));
                         }
                     }
-                    Ordering::Greater => {
-                        let (k, v) = self.db_cache.take().unwrap();
-                        return Ok(Some((k.to_vec(), v.to_vec())));
-                    }
-                    Ordering::Equal => {
-                        self.db_cache.take();
-                        continue;
-                    }
-                },
+                }
             }
         }
     }
 }
 
-impl Iterator for SledIterRaw {
+impl<I> Iterator for SledIterRaw<I>
+where
+    I: Iterator<Item = sled::Result<(IVec, IVec)>>,
+{
     type Item = Result<(Vec<u8>, Vec<u8>)>;
 
     #[inline]
@@ -385,12 +428,12 @@ impl SledIter {
                     if cv[0] == DEL_MARKER {
                         continue;
                     } else {
-                        return Ok(Some(decode_tuple_from_kv(&k, &cv[1..], None)));
+                        return try_decode_tuple_from_kv(&k, &cv[1..], None).map(Some);
                     }
                 }
                 (None, Some(_)) => {
                     let (k, v) = self.db_cache.take().unwrap();
-                    return Ok(Some(decode_tuple_from_kv(&k, &v, None)));
+                    return try_decode_tuple_from_kv(&k, &v, None).map(Some);
                 }
                 (Some((ck, _)), Some((dk, _))) => match ck.cmp(dk) {
                     Ordering::Less => {
@@ -398,12 +441,12 @@ impl SledIter {
                         if sv[0] == DEL_MARKER {
                             continue;
                         } else {
-                            return Ok(Some(decode_tuple_from_kv(&k, &sv[1..], None)));
+                            return try_decode_tuple_from_kv(&k, &sv[1..], None).map(Some);
                         }
                     }
                     Ordering::Greater => {
                         let (k, v) = self.db_cache.take().unwrap();
-                        return Ok(Some(decode_tuple_from_kv(&k, &v, None)));
+                        return try_decode_tuple_from_kv(&k, &v, None).map(Some);
                     }
                     Ordering::Equal => {
                         self.db_cache.take();
@@ -15,9 +15,9 @@ use either::{Either, Left, Right};
 use miette::{bail, miette, IntoDiagnostic, Result};
 use sqlite::{ConnectionThreadSafe, State, Statement};
 
-use crate::data::tuple::{check_key_for_validity, Tuple};
+use crate::data::tuple::Tuple;
 use crate::data::value::ValidityTs;
-use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
+use crate::runtime::relation::{decode_skip_scan_row, try_decode_tuple_from_kv};
 use crate::storage::{Storage, StoreTx};
 use crate::utils::swap_option_result;
 
@@ -126,7 +126,7 @@ pub struct SqliteTx<'a> {
 
 unsafe impl Sync for SqliteTx<'_> {}
 
-const N_QUERIES: usize = 7;
+const N_QUERIES: usize = 8;
 const N_CACHED_QUERIES: usize = 4;
 const QUERIES: [&str; N_QUERIES] = [
     "select v from cozo where k = ?;",
@@ -136,6 +136,7 @@ const QUERIES: [&str; N_QUERIES] = [
     "select k, v from cozo where k >= ? and k < ? order by k;",
     "select k, v from cozo where k >= ? and k < ? order by k limit 1;",
     "select count(*) from cozo where k >= ? and k < ?;",
+    "select k, v from cozo where k >= ? and k < ? order by k desc;",
 ];
 
 const GET_QUERY: usize = 0;
@@ -145,6 +146,7 @@ const EXISTS_QUERY: usize = 3;
 const RANGE_QUERY: usize = 4;
 const SKIP_RANGE_QUERY: usize = 5;
 const COUNT_RANGE_QUERY: usize = 6;
+const RANGE_REV_QUERY: usize = 7;
 
 impl Drop for SqliteTx<'_> {
     fn drop(&mut self) {
@@ -317,6 +319,21 @@ impl<'s> StoreTx<'s> for SqliteTx<'s> {
         Box::new(RawIter(statement))
     }
 
+    fn range_scan_rev<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        let query = QUERIES[RANGE_REV_QUERY];
+        let mut statement = self.conn.as_ref().unwrap().prepare(query).unwrap();
+        statement.bind((1, lower)).unwrap();
+        statement.bind((2, upper)).unwrap();
+        Box::new(RawIter(statement))
+    }
+
     fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
     where
         's: 'a,
@@ -360,8 +377,7 @@ impl<'l> Iterator for TupleIter<'l> {
             Ok(State::Row) => {
                 let k = self.0.read::<Vec<u8>, _>(0).unwrap();
                 let v = self.0.read::<Vec<u8>, _>(1).unwrap();
-                let tuple = decode_tuple_from_kv(&k, &v, None);
-                Some(Ok(tuple))
+                Some(try_decode_tuple_from_kv(&k, &v, None))
             }
             Err(err) => Some(Err(miette!(err))),
         }
@@ -404,11 +420,10 @@ impl<'l> SkipIter<'l> {
                 State::Done => return Ok(None),
                 State::Row => {
                     let k = self.stmt.read::<Vec<u8>, _>(0).unwrap();
-                    let (ret, nxt_bound) = check_key_for_validity(&k, self.valid_at, None);
+                    let v = self.stmt.read::<Vec<u8>, _>(1).unwrap();
+                    let (ret, nxt_bound) = decode_skip_scan_row(&k, &v, self.valid_at, None);
                     self.next_bound = nxt_bound;
-                    if let Some(mut tup) = ret {
-                        let v = self.stmt.read::<Vec<u8>, _>(1).unwrap();
-                        extend_tuple_from_v(&mut tup, &v);
+                    if let Some(tup) = ret? {
                         return Ok(Some(tup));
                     }
                 }
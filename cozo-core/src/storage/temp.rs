@@ -13,7 +13,7 @@ use miette::Result;
 
 use crate::data::tuple::Tuple;
 use crate::data::value::ValidityTs;
-use crate::runtime::relation::decode_tuple_from_kv;
+use crate::runtime::relation::try_decode_tuple_from_kv;
 use crate::storage::mem::SkipIterator;
 use crate::storage::{Storage, StoreTx};
 
@@ -91,7 +91,7 @@ impl<'s> StoreTx<'s> for TempTx {
         Box::new(
             self.store
                 .range(lower.to_vec()..upper.to_vec())
-                .map(|(k, v)| Ok(decode_tuple_from_kv(k, v, None))),
+                .map(|(k, v)| try_decode_tuple_from_kv(k, v, None)),
         )
     }
 
@@ -101,19 +101,31 @@ impl<'s> StoreTx<'s> for TempTx {
         upper: &[u8],
         valid_at: ValidityTs,
     ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        Box::new(SkipIterator {
+            inner: &self.store,
+            upper: upper.to_vec(),
+            valid_at,
+            next_bound: lower.to_vec(),
+            size_hint: None,
+        })
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
         Box::new(
-            SkipIterator {
-                inner: &self.store,
-                upper: upper.to_vec(),
-                valid_at,
-                next_bound: lower.to_vec(),
-                size_hint: None,
-            }
-            .map(Ok),
+            self.store
+                .range(lower.to_vec()..upper.to_vec())
+                .map(|(k, v)| Ok((k.clone(), v.clone()))),
         )
     }
 
-    fn range_scan<'a>(
+    fn range_scan_rev<'a>(
         &'a self,
         lower: &[u8],
         upper: &[u8],
@@ -124,6 +136,7 @@ impl<'s> StoreTx<'s> for TempTx {
         Box::new(
             self.store
                 .range(lower.to_vec()..upper.to_vec())
+                .rev()
                 .map(|(k, v)| Ok((k.clone(), v.clone()))),
         )
     }
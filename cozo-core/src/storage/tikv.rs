@@ -7,7 +7,7 @@
  */
 
 use std::iter;
-use std::ops::Bound::{Excluded, Included};
+use std::ops::Bound::{self, Excluded, Included};
 use std::sync::{Arc, Mutex};
 
 use itertools::Itertools;
@@ -18,7 +18,7 @@ use tokio::runtime::Runtime;
 
 use crate::data::tuple::Tuple;
 use crate::data::value::ValidityTs;
-use crate::runtime::relation::decode_tuple_from_kv;
+use crate::runtime::relation::try_decode_tuple_from_kv;
 use crate::storage::{Storage, StoreTx};
 use crate::utils::{swap_option_result, TempCollector};
 use crate::Db;
@@ -191,6 +191,17 @@ impl<'s> StoreTx<'s> for TiKvTx {
         Box::new(BatchScannerRaw::new(self.tx.clone(), lower, upper))
     }
 
+    fn range_scan_rev<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        Box::new(BatchScannerRaw::new_rev(self.tx.clone(), lower, upper))
+    }
+
     fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
     where
         's: 'a,
@@ -212,6 +223,8 @@ struct BatchScannerRaw {
     upper: Vec<u8>,
     fetched: Option<Vec<(Vec<u8>, Vec<u8>)>>,
     iter_idx: usize,
+    /// Set when scanning in descending order
+    reverse: bool,
 }
 
 impl BatchScannerRaw {
@@ -222,6 +235,13 @@ impl BatchScannerRaw {
             upper: upper.to_vec(),
             fetched: None,
             iter_idx: 0,
+            reverse: false,
+        }
+    }
+    fn new_rev(tx: Arc<Mutex<Transaction>>, lower: &[u8], upper: &[u8]) -> Self {
+        Self {
+            reverse: true,
+            ..Self::new(tx, lower, upper)
         }
     }
 }
@@ -229,19 +249,30 @@ impl BatchScannerRaw {
 const BATCH_SIZE: u32 = 100;
 
 impl BatchScannerRaw {
+    fn scan_batch(
+        &self,
+        range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut tx = self.tx.lock().unwrap();
+        let res = if self.reverse {
+            RT.block_on(tx.scan_reverse(range, BATCH_SIZE))
+                .into_diagnostic()?
+                .map(|pair| -> (Vec<u8>, Vec<u8>) { (pair.0.into(), pair.1) })
+                .collect_vec()
+        } else {
+            RT.block_on(tx.scan(range, BATCH_SIZE))
+                .into_diagnostic()?
+                .map(|pair| -> (Vec<u8>, Vec<u8>) { (pair.0.into(), pair.1) })
+                .collect_vec()
+        };
+        Ok(res)
+    }
     fn get_batch(&mut self) -> Result<bool> {
         match &mut self.fetched {
             None => {
                 self.iter_idx = 0;
-                let mut tx = self.tx.lock().unwrap();
-                let fut = tx.scan(
-                    (Included(self.lower.clone()), Excluded(self.upper.clone())),
-                    BATCH_SIZE,
-                );
-                let res = RT.block_on(fut).into_diagnostic()?;
-                let res_vec = res
-                    .map(|pair| -> (Vec<u8>, Vec<u8>) { (pair.0.into(), pair.1) })
-                    .collect_vec();
+                let res_vec =
+                    self.scan_batch((Included(self.lower.clone()), Excluded(self.upper.clone())))?;
                 let has_content = !res_vec.is_empty();
                 if has_content {
                     self.fetched = Some(res_vec);
@@ -252,16 +283,12 @@ impl BatchScannerRaw {
                 let l = fetched.len();
                 if l as u32 == BATCH_SIZE && self.iter_idx == l {
                     let last_key = fetched.pop().unwrap().0;
-
-                    let mut tx = self.tx.lock().unwrap();
-                    let fut = tx.scan(
-                        (Excluded(last_key), Excluded(self.upper.clone())),
-                        BATCH_SIZE,
-                    );
-                    let res = RT.block_on(fut).into_diagnostic()?;
-                    let res_vec = res
-                        .map(|pair| -> (Vec<u8>, Vec<u8>) { (pair.0.into(), pair.1) })
-                        .collect_vec();
+                    let range = if self.reverse {
+                        (Included(self.lower.clone()), Excluded(last_key))
+                    } else {
+                        (Excluded(last_key), Excluded(self.upper.clone()))
+                    };
+                    let res_vec = self.scan_batch(range)?;
                     let has_content = !res_vec.is_empty();
                     if has_content {
                         self.iter_idx = 0;
@@ -311,10 +338,9 @@ impl Iterator for BatchScanner {
     type Item = Result<Tuple>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        swap_option_result(
-            self.raw
-                .next_inner()
-                .map(|mkv| mkv.map(|(k, v)| decode_tuple_from_kv(k, v, None))),
-        )
+        swap_option_result(self.raw.next_inner().and_then(|mkv| {
+            mkv.map(|(k, v)| try_decode_tuple_from_kv(k, v, None))
+                .transpose()
+        }))
     }
 }
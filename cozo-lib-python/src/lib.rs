@@ -9,6 +9,7 @@
 use std::collections::{BTreeMap, BTreeSet};
 
 use miette::{IntoDiagnostic, Report, Result};
+use pyo3::create_exception;
 use pyo3::exceptions::PyException;
 use pyo3::prelude::*;
 use pyo3::types::{PyBool, PyByteArray, PyBytes, PyDict, PyList, PyString, PyTuple};
@@ -16,6 +17,14 @@ use serde_json::json;
 
 use cozo::*;
 
+create_exception!(
+    cozo_embedded,
+    CozoError,
+    PyException,
+    "Raised when the database reports an error. For errors in queries, the argument is a dict \
+     with the message, the error code, the labelled spans in the query and the rendered report."
+);
+
 fn py_to_rows(ob: &PyAny) -> PyResult<Vec<Vec<DataValue>>> {
     let rows = ob.extract::<Vec<Vec<&PyAny>>>()?;
     let res: Vec<Vec<DataValue>> = rows
@@ -26,7 +35,13 @@ fn py_to_rows(ob: &PyAny) -> PyResult<Vec<Vec<DataValue>>> {
 }
 
 fn report2py(r: Report) -> PyErr {
-    PyException::new_err(r.to_string())
+    CozoError::new_err(r.to_string())
+}
+
+/// Raise the error of a query as a [CozoError] carrying the report as a dict
+fn query_error_to_py(err: Report, query: &str, py: Python<'_>) -> PyErr {
+    let report = format_error_as_json(err, Some(query));
+    CozoError::new_err(json_to_py(report, py))
 }
 
 fn py_to_named_rows(ob: &PyAny) -> PyResult<NamedRows> {
@@ -71,6 +86,9 @@ fn py_to_value(ob: &PyAny) -> PyResult<DataValue> {
             coll.push(el)
         }
         DataValue::List(coll)
+    } else if is_datetime(ob)? {
+        // as seconds since the epoch, like `now()`
+        DataValue::from(ob.call_method0("timestamp")?.extract::<f64>()?)
     } else if let Ok(d) = ob.downcast::<PyDict>() {
         let mut coll = serde_json::Map::default();
         for (k, v) in d {
@@ -90,6 +108,11 @@ fn py_to_value(ob: &PyAny) -> PyResult<DataValue> {
     })
 }
 
+fn is_datetime(ob: &PyAny) -> PyResult<bool> {
+    let datetime = ob.py().import("datetime")?.getattr("datetime")?;
+    ob.is_instance(datetime)
+}
+
 fn convert_params(ob: &PyDict) -> PyResult<BTreeMap<String, DataValue>> {
     let mut ret = BTreeMap::new();
     for (k, v) in ob {
@@ -198,6 +221,57 @@ fn named_rows_to_py(named_rows: NamedRows, py: Python<'_>) -> PyObject {
     BTreeMap::from([("rows", rows), ("headers", headers), ("next", next)]).into_py(py)
 }
 
+/// The value of a column of a row returned as a dict, with validities as aware datetimes in UTC
+fn column_to_py(val: DataValue, py: Python<'_>) -> PyResult<PyObject> {
+    match val {
+        DataValue::Validity(vld) => {
+            let datetime = py.import("datetime")?;
+            let utc = datetime.getattr("timezone")?.getattr("utc")?;
+            let secs = vld.timestamp.0 .0 as f64 / 1_000_000.;
+            Ok(datetime
+                .getattr("datetime")?
+                .call_method1("fromtimestamp", (secs, utc))?
+                .into())
+        }
+        val => Ok(value_to_py(val, py)),
+    }
+}
+
+fn row_to_py_dict(headers: &[String], row: Vec<DataValue>, py: Python<'_>) -> PyResult<PyObject> {
+    let d = PyDict::new(py);
+    for (k, v) in headers.iter().zip(row) {
+        d.set_item(k, column_to_py(v, py)?)?;
+    }
+    Ok(d.into())
+}
+
+/// The rows of a result as dicts, converted to Python objects only when reached.
+#[pyclass]
+struct CozoRowIter {
+    headers: Vec<String>,
+    rows: std::vec::IntoIter<Vec<DataValue>>,
+}
+
+#[pymethods]
+impl CozoRowIter {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        match self.rows.next() {
+            None => Ok(None),
+            Some(row) => row_to_py_dict(&self.headers, row, py).map(Some),
+        }
+    }
+    fn __len__(&self) -> usize {
+        self.rows.len()
+    }
+    #[getter]
+    fn headers(&self) -> Vec<String> {
+        self.headers.clone()
+    }
+}
+
 #[pyclass]
 struct CozoDbPy {
     db: Option<DbInstance>,
@@ -210,15 +284,74 @@ struct CozoDbMulTx {
 
 const DB_CLOSED_MSG: &str = r##"{"ok":false,"message":"database closed"}"##;
 
+impl CozoDbPy {
+    fn run_named_rows(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        params: Option<&PyDict>,
+        immutable: bool,
+    ) -> PyResult<NamedRows> {
+        let Some(db) = &self.db else {
+            return Err(CozoError::new_err(DB_CLOSED_MSG));
+        };
+        let params = match params {
+            Some(params) => convert_params(params)?,
+            None => BTreeMap::new(),
+        };
+        let mutability = if immutable {
+            ScriptMutability::Immutable
+        } else {
+            ScriptMutability::Mutable
+        };
+        py.allow_threads(|| db.run_script(query, params, mutability))
+            .map_err(|err| query_error_to_py(err, query, py))
+    }
+}
+
 #[pymethods]
 impl CozoDbPy {
     #[new]
+    #[pyo3(signature = (engine = "mem", path = "", options = "{}"))]
     fn new(engine: &str, path: &str, options: &str) -> PyResult<Self> {
         match DbInstance::new(engine, path, options) {
             Ok(db) => Ok(Self { db: Some(db) }),
-            Err(err) => Err(PyException::new_err(format!("{err:?}"))),
+            Err(err) => Err(CozoError::new_err(format!("{err:?}"))),
         }
     }
+    /// Run `query` and return its rows as a list of dicts keyed by the headers.
+    #[pyo3(signature = (query, params = None, immutable = false))]
+    pub fn run(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        params: Option<&PyDict>,
+        immutable: bool,
+    ) -> PyResult<PyObject> {
+        let res = self.run_named_rows(py, query, params, immutable)?;
+        let rows = res
+            .rows
+            .into_iter()
+            .map(|row| row_to_py_dict(&res.headers, row, py))
+            .collect::<PyResult<Vec<_>>>()?;
+        Ok(rows.into_py(py))
+    }
+    /// Run `query` and return an iterator over its rows as dicts,
+    /// which converts each row only when it is reached.
+    #[pyo3(signature = (query, params = None, immutable = false))]
+    pub fn run_iter(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        params: Option<&PyDict>,
+        immutable: bool,
+    ) -> PyResult<CozoRowIter> {
+        let res = self.run_named_rows(py, query, params, immutable)?;
+        Ok(CozoRowIter {
+            headers: res.headers,
+            rows: res.rows.into_iter(),
+        })
+    }
     pub fn run_script(
         &self,
         py: Python<'_>,
@@ -226,32 +359,8 @@ impl CozoDbPy {
         params: &PyDict,
         immutable: bool,
     ) -> PyResult<PyObject> {
-        if let Some(db) = &self.db {
-            let params = convert_params(params)?;
-            match py.allow_threads(|| {
-                db.run_script(
-                    query,
-                    params,
-                    if immutable {
-                        ScriptMutability::Immutable
-                    } else {
-                        ScriptMutability::Mutable
-                    },
-                )
-            }) {
-                Ok(rows) => Ok(named_rows_to_py(rows, py)),
-                Err(err) => {
-                    let reports = format_error_as_json(err, Some(query)).to_string();
-                    let json_mod = py.import("json")?;
-                    let loads_fn = json_mod.getattr("loads")?;
-                    let args = PyTuple::new(py, [PyString::new(py, &reports)]);
-                    let msg = loads_fn.call1(args)?;
-                    Err(PyException::new_err(PyObject::from(msg)))
-                }
-            }
-        } else {
-            Err(PyException::new_err(DB_CLOSED_MSG))
-        }
+        let rows = self.run_named_rows(py, query, Some(params), immutable)?;
+        Ok(named_rows_to_py(rows, py))
     }
     pub fn register_callback(&self, rel: &str, callback: &PyAny) -> PyResult<u32> {
         if let Some(db) = &self.db {
@@ -273,7 +382,7 @@ impl CozoDbPy {
             });
             Ok(id)
         } else {
-            Err(PyException::new_err(DB_CLOSED_MSG))
+            Err(CozoError::new_err(DB_CLOSED_MSG))
         }
     }
     pub fn register_fixed_rule(
@@ -298,7 +407,7 @@ impl CozoDbPy {
             });
             db.register_fixed_rule(name, rule_impl).map_err(report2py)
         } else {
-            Err(PyException::new_err(DB_CLOSED_MSG))
+            Err(CozoError::new_err(DB_CLOSED_MSG))
         }
     }
     pub fn unregister_callback(&self, id: u32) -> bool {
@@ -312,7 +421,7 @@ impl CozoDbPy {
         if let Some(db) = &self.db {
             match db.unregister_fixed_rule(name) {
                 Ok(b) => Ok(b),
-                Err(err) => Err(PyException::new_err(err.to_string())),
+                Err(err) => Err(CozoError::new_err(err.to_string())),
             }
         } else {
             Ok(false)
@@ -322,7 +431,7 @@ impl CozoDbPy {
         if let Some(db) = &self.db {
             let res = match py.allow_threads(|| db.export_relations(relations.iter())) {
                 Ok(res) => res,
-                Err(err) => return Err(PyException::new_err(err.to_string())),
+                Err(err) => return Err(CozoError::new_err(err.to_string())),
             };
             let ret = PyDict::new(py);
             for (k, v) in res {
@@ -330,9 +439,37 @@ impl CozoDbPy {
             }
             Ok(ret.into())
         } else {
-            Err(PyException::new_err(DB_CLOSED_MSG.to_string()))
+            Err(CozoError::new_err(DB_CLOSED_MSG.to_string()))
         }
     }
+    /// Export the stored relations `relations`, or all of them if not given,
+    /// as a dict of relation names to dicts with `headers` and `rows`.
+    #[pyo3(signature = (relations = None))]
+    pub fn export_tagged(
+        &self,
+        py: Python<'_>,
+        relations: Option<Vec<String>>,
+    ) -> PyResult<PyObject> {
+        let relations = match relations {
+            Some(relations) => relations,
+            None => {
+                let res = self.run_named_rows(py, "::relations", None, true)?;
+                res.rows
+                    .into_iter()
+                    .filter_map(|row| match row.into_iter().next() {
+                        // indices are exported with their relations
+                        Some(DataValue::Str(name)) if !name.contains(':') => Some(name.to_string()),
+                        _ => None,
+                    })
+                    .collect()
+            }
+        };
+        self.export_relations(py, relations)
+    }
+    /// Import the data in a dict as [Self::export_tagged] returns it.
+    pub fn import_tagged(&self, py: Python<'_>, data: &PyDict) -> PyResult<()> {
+        self.import_relations(py, data)
+    }
     pub fn import_relations(&self, py: Python<'_>, data: &PyDict) -> PyResult<()> {
         if let Some(db) = &self.db {
             let mut arg = BTreeMap::new();
@@ -344,14 +481,14 @@ impl CozoDbPy {
             py.allow_threads(|| db.import_relations(arg))
                 .map_err(report2py)
         } else {
-            Err(PyException::new_err(DB_CLOSED_MSG.to_string()))
+            Err(CozoError::new_err(DB_CLOSED_MSG.to_string()))
         }
     }
     pub fn backup(&self, py: Python<'_>, path: &str) -> PyResult<()> {
         if let Some(db) = &self.db {
             py.allow_threads(|| db.backup_db(path)).map_err(report2py)
         } else {
-            Err(PyException::new_err(DB_CLOSED_MSG.to_string()))
+            Err(CozoError::new_err(DB_CLOSED_MSG.to_string()))
         }
     }
     pub fn restore(&self, py: Python<'_>, path: &str) -> PyResult<()> {
@@ -359,7 +496,7 @@ impl CozoDbPy {
             py.allow_threads(|| db.restore_backup(path))
                 .map_err(report2py)
         } else {
-            Err(PyException::new_err(DB_CLOSED_MSG.to_string()))
+            Err(CozoError::new_err(DB_CLOSED_MSG.to_string()))
         }
     }
     pub fn import_from_backup(
@@ -372,7 +509,7 @@ impl CozoDbPy {
             py.allow_threads(|| db.import_from_backup(in_file, &relations))
                 .map_err(report2py)
         } else {
-            Err(PyException::new_err(DB_CLOSED_MSG.to_string()))
+            Err(CozoError::new_err(DB_CLOSED_MSG.to_string()))
         }
     }
     pub fn close(&mut self) -> bool {
@@ -384,7 +521,7 @@ impl CozoDbPy {
                 tx: db.multi_transaction(write),
             })
         } else {
-            Err(PyException::new_err(DB_CLOSED_MSG.to_string()))
+            Err(CozoError::new_err(DB_CLOSED_MSG.to_string()))
         }
     }
 }
@@ -394,25 +531,18 @@ impl CozoDbMulTx {
     pub fn abort(&self) -> PyResult<()> {
         self.tx
             .abort()
-            .map_err(|err| PyException::new_err(err.to_string()))
+            .map_err(|err| CozoError::new_err(err.to_string()))
     }
     pub fn commit(&self) -> PyResult<()> {
         self.tx
             .commit()
-            .map_err(|err| PyException::new_err(err.to_string()))
+            .map_err(|err| CozoError::new_err(err.to_string()))
     }
     pub fn run_script(&self, py: Python<'_>, query: &str, params: &PyDict) -> PyResult<PyObject> {
         let params = convert_params(params)?;
         match py.allow_threads(|| self.tx.run_script(query, params)) {
             Ok(rows) => Ok(named_rows_to_py(rows, py)),
-            Err(err) => {
-                let reports = format_error_as_json(err, Some(query)).to_string();
-                let json_mod = py.import("json")?;
-                let loads_fn = json_mod.getattr("loads")?;
-                let args = PyTuple::new(py, [PyString::new(py, &reports)]);
-                let msg = loads_fn.call1(args)?;
-                Err(PyException::new_err(PyObject::from(msg)))
-            }
+            Err(err) => Err(query_error_to_py(err, query, py)),
         }
     }
 }
@@ -428,14 +558,7 @@ fn eval_expressions(
     let bindings = convert_params(bindings).unwrap();
     match evaluate_expressions(query, &params, &bindings) {
         Ok(v) => Ok(value_to_py(v, py)),
-        Err(err) => {
-            let reports = format_error_as_json(err, Some(query)).to_string();
-            let json_mod = py.import("json")?;
-            let loads_fn = json_mod.getattr("loads")?;
-            let args = PyTuple::new(py, [PyString::new(py, &reports)]);
-            let msg = loads_fn.call1(args)?;
-            Err(PyException::new_err(PyObject::from(msg)))
-        }
+        Err(err) => Err(query_error_to_py(err, query, py)),
     }
 }
 
@@ -444,21 +567,16 @@ fn variables(py: Python<'_>, query: &str, params: &PyDict) -> PyResult<BTreeSet<
     let params = convert_params(params).unwrap();
     match get_variables(query, &params) {
         Ok(rows) => Ok(rows),
-        Err(err) => {
-            let reports = format_error_as_json(err, Some(query)).to_string();
-            let json_mod = py.import("json")?;
-            let loads_fn = json_mod.getattr("loads")?;
-            let args = PyTuple::new(py, [PyString::new(py, &reports)]);
-            let msg = loads_fn.call1(args)?;
-            Err(PyException::new_err(PyObject::from(msg)))
-        }
+        Err(err) => Err(query_error_to_py(err, query, py)),
     }
 }
 
 #[pymodule]
-fn cozo_embedded(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+fn cozo_embedded(py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_class::<CozoDbPy>()?;
     m.add_class::<CozoDbMulTx>()?;
+    m.add_class::<CozoRowIter>()?;
+    m.add("CozoError", py.get_type::<CozoError>())?;
     m.add_function(wrap_pyfunction!(eval_expressions, m)?)?;
     m.add_function(wrap_pyfunction!(variables, m)?)?;
     Ok(())
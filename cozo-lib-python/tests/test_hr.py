@@ -0,0 +1,155 @@
+#  Copyright 2023, The Cozo Project Authors.
+#
+#  This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+#  If a copy of the MPL was not distributed with this file,
+#  You can obtain one at https://mozilla.org/MPL/2.0/.
+
+# Run with `maturin develop -F compact && pytest tests`.
+
+import threading
+from datetime import datetime, timezone
+
+import pytest
+
+from cozo_embedded import CozoDbPy, CozoError
+
+
+@pytest.fixture
+def db():
+    db = CozoDbPy()
+    db.run(":create dept {id: Int => name: String}")
+    db.run(":create emp {id: Int => name: String, dept: Int, salary: Float, manager: Int?}")
+    db.run(":create hired {emp: Int, at: Validity => note: String}")
+    db.import_tagged({
+        "dept": {"headers": ["id", "name"], "rows": [[1, "Engineering"], [2, "Sales"]]},
+        "emp": {
+            "headers": ["id", "name", "dept", "salary", "manager"],
+            "rows": [
+                [10, "Ann", 1, 150.0, None],
+                [11, "Bob", 1, 120.0, 10],
+                [12, "Cid", 1, 110.0, 11],
+                [20, "Dee", 2, 90.0, None],
+                [21, "Eve", 2, 80.5, 20],
+            ],
+        },
+    })
+    yield db
+    db.close()
+
+
+def test_rows_as_dicts(db):
+    rows = db.run(
+        "?[dept, sum(salary), count(id)] := *emp{id, dept: d, salary}, *dept{id: d, name: dept}"
+    )
+    assert rows == [
+        {"dept": "Engineering", "sum(salary)": 380.0, "count(id)": 3},
+        {"dept": "Sales", "sum(salary)": 170.5, "count(id)": 2},
+    ]
+
+
+def test_params_and_recursion(db):
+    rows = db.run(
+        """
+        reports[e] := *emp{id: e, manager: $boss}
+        reports[e] := reports[m], *emp{id: e, manager: m}
+        ?[name] := reports[e], *emp{id: e, name}
+        """,
+        {"boss": 10},
+    )
+    assert [r["name"] for r in rows] == ["Bob", "Cid"]
+
+
+def test_iterator(db):
+    it = db.run_iter("?[id, name] := *emp{id, name}", immutable=True)
+    assert it.headers == ["id", "name"]
+    assert len(it) == 5
+    assert next(it) == {"id": 10, "name": "Ann"}
+    assert [r["id"] for r in it] == [11, 12, 20, 21]
+
+
+def test_value_conversions(db):
+    [row] = db.run(
+        "?[i, f, s, b, l, m, n, t] <- [[$i, $f, $s, $b, $l, $m, $n, $t]]",
+        {
+            "i": 1,
+            "f": 1.5,
+            "s": "x",
+            "b": b"\x00\x01",
+            "l": [1, [2, None]],
+            "m": {"k": [True]},
+            "n": None,
+            "t": True,
+        },
+    )
+    assert row == {
+        "i": 1,
+        "f": 1.5,
+        "s": "x",
+        "b": b"\x00\x01",
+        "l": [1, [2, None]],
+        "m": {"k": [True]},
+        "n": None,
+        "t": True,
+    }
+
+
+def test_datetimes(db):
+    at = datetime(2023, 5, 6, 7, 8, 9, tzinfo=timezone.utc)
+    [row] = db.run("?[secs] <- [[$at]]", {"at": at})
+    assert row["secs"] == at.timestamp()
+
+    # validities are written as RFC 3339 strings and read back as datetimes
+    db.run(
+        "?[emp, at, note] <- [[10, $at, 'joined']] :put hired {emp, at => note}",
+        {"at": at.isoformat()},
+    )
+    [row] = db.run("?[at] := *hired{at}")
+    assert row["at"] == at
+
+
+def test_export_and_import(db):
+    exported = db.export_tagged()
+    assert set(exported) == {"dept", "emp", "hired"}
+    assert exported["dept"]["rows"] == [[1, "Engineering"], [2, "Sales"]]
+
+    other = CozoDbPy("mem", "", "{}")
+    other.run(":create dept {id: Int => name: String}")
+    other.import_tagged({"dept": exported["dept"]})
+    assert other.export_tagged(["dept"]) == {"dept": exported["dept"]}
+
+
+def test_errors(db):
+    with pytest.raises(CozoError) as e:
+        db.run("?[x] := *emp{id: x, nope}")
+    report = e.value.args[0]
+    assert report["ok"] is False
+    assert "nope" in report["message"]
+    assert report["display"]
+
+    with pytest.raises(CozoError) as e:
+        db.run("?[x] := y = 1")
+    report = e.value.args[0]
+    assert report["code"] == "eval::unbound_symb_in_head"
+    assert report["labels"] == [{"span": {"offset": 2, "length": 1}}]
+
+    with pytest.raises(CozoError):
+        db.run(":put dept {id: 3, name: 'x'}", immutable=True)
+    assert isinstance(CozoError("x"), Exception)
+
+
+def test_queries_release_the_gil(db):
+    # a long query on another thread must not keep this one from running
+    done = threading.Event()
+    ticks = []
+
+    def slow():
+        db.run("?[sum(x)] := x in int_range(3000000)")
+        done.set()
+
+    t = threading.Thread(target=slow)
+    t.start()
+    while not done.is_set():
+        ticks.append(1)
+        done.wait(0.001)
+    t.join()
+    assert len(ticks) > 1
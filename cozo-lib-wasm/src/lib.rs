@@ -6,6 +6,7 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use js_sys::JSON;
 use wasm_bindgen::prelude::*;
 
 use cozo::*;
@@ -46,3 +47,35 @@ impl CozoDb {
         self.db.import_relations_str(data)
     }
 }
+
+/// Turn the JSON returned by the database into a JS object.
+fn json_to_js(json: String) -> JsValue {
+    JSON::parse(&json).unwrap_or_else(|_| JsValue::from_str(&json))
+}
+
+/// Open a new in-memory database.
+#[wasm_bindgen(js_name = openDb)]
+pub fn open_db() -> CozoDb {
+    CozoDb::new()
+}
+
+/// Run `query` without parameters, returning the results as an object,
+/// which has `ok` set to `false` if the query failed.
+#[wasm_bindgen(js_name = runQuery)]
+pub fn run_query(db: &CozoDb, query: &str) -> JsValue {
+    json_to_js(db.db.run_script_str(query, "", false))
+}
+
+/// Import relations from an object of the form `{"rel": {"headers": [...], "rows": [...]}}`,
+/// given either as an object or as a JSON string.
+#[wasm_bindgen(js_name = importTagged)]
+pub fn import_tagged(db: &CozoDb, data: JsValue) -> JsValue {
+    let data = match data.as_string() {
+        Some(s) => s,
+        None => match JSON::stringify(&data) {
+            Ok(s) => String::from(s),
+            Err(err) => return err,
+        },
+    };
+    json_to_js(db.db.import_relations_str(&data))
+}
@@ -11,11 +11,51 @@
 #![cfg(target_arch = "wasm32")]
 
 extern crate wasm_bindgen_test;
+use js_sys::{Reflect, JSON};
+use wasm_bindgen::JsValue;
 use wasm_bindgen_test::*;
 
+use cozo_lib_wasm::{import_tagged, open_db, run_query};
+
 wasm_bindgen_test_configure!(run_in_browser);
 
 #[wasm_bindgen_test]
 fn pass() {
     assert_eq!(1 + 1, 2);
 }
+
+fn field(obj: &JsValue, name: &str) -> String {
+    let val = Reflect::get(obj, &JsValue::from_str(name)).unwrap();
+    String::from(JSON::stringify(&val).unwrap())
+}
+
+#[wasm_bindgen_test]
+fn create_import_and_query() {
+    let db = open_db();
+    let res = run_query(&db, ":create person {id: Int => name: String}");
+    assert_eq!(field(&res, "ok"), "true");
+
+    let res = import_tagged(
+        &db,
+        JSON::parse(
+            r#"{"person": {"headers": ["id", "name"], "rows": [[1, "Alice"], [2, "Bob"]]}}"#,
+        )
+        .unwrap(),
+    );
+    assert_eq!(field(&res, "ok"), "true");
+    let res = import_tagged(
+        &db,
+        JsValue::from_str(r#"{"person": {"headers": ["id", "name"], "rows": [[3, "Eve"]]}}"#),
+    );
+    assert_eq!(field(&res, "ok"), "true");
+
+    let res = run_query(
+        &db,
+        "?[name] := *person{id, name}, id > 1, now() > 1e9, now() < 1e11",
+    );
+    assert_eq!(field(&res, "headers"), r#"["name"]"#);
+    assert_eq!(field(&res, "rows"), r#"[["Bob"],["Eve"]]"#);
+
+    let res = run_query(&db, "?[name] := *nobody{name}");
+    assert_eq!(field(&res, "ok"), "false");
+}
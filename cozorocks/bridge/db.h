@@ -101,8 +101,11 @@ struct RocksDbBridge {
         write_status(s, status);
     }
 
-    void compact_range(RustBytes start, RustBytes end, RocksDbStatus &status) const {
+    void compact_range(RustBytes start, RustBytes end, bool force_bottommost, RocksDbStatus &status) const {
         CompactRangeOptions options;
+        if (force_bottommost) {
+            options.bottommost_level_compaction = BottommostLevelCompaction::kForceOptimized;
+        }
         auto cf = db->DefaultColumnFamily();
         auto start_s = convert_slice(start);
         auto end_s = convert_slice(end);
@@ -110,6 +113,30 @@ struct RocksDbBridge {
         write_status(s, status);
     }
 
+    uint64_t approximate_size(RustBytes start, RustBytes end, RocksDbStatus &status) const {
+        auto start_s = convert_slice(start);
+        auto end_s = convert_slice(end);
+        Range range(start_s, end_s);
+        SizeApproximationOptions options;
+        options.include_memtables = true;
+        uint64_t size = 0;
+        auto s = db->GetApproximateSizes(options, db->DefaultColumnFamily(), &range, 1, &size);
+        write_status(s, status);
+        return size;
+    }
+
+    void create_checkpoint(rust::Str path, RocksDbStatus &status) const {
+        Checkpoint *checkpoint_ptr = nullptr;
+        auto s = Checkpoint::Create(get_base_db(), &checkpoint_ptr);
+        if (!s.ok()) {
+            write_status(s, status);
+            return;
+        }
+        unique_ptr<Checkpoint> checkpoint(checkpoint_ptr);
+        string path_(path);
+        write_status(checkpoint->CreateCheckpoint(path_), status);
+    }
+
     DB *get_base_db() const {
         return db->GetBaseDB();
     }
@@ -51,13 +51,17 @@ struct TxBridge {
     }
 
     inline unique_ptr<IterBridge> iterator() const {
-        return make_unique<IterBridge>(&*tx);
+        auto ret = make_unique<IterBridge>(&*tx);
+        ret->set_snapshot(r_opts->snapshot);
+        return ret;
     };
 
     inline void set_snapshot(bool val) {
         if (tx != nullptr) {
             if (val) {
                 tx->SetSnapshot();
+                // reads see the snapshot, together with the writes of the transaction
+                r_opts->snapshot = tx->GetSnapshot();
             }
         } else if (o_tx_opts != nullptr) {
             o_tx_opts->set_snapshot = val;
@@ -68,6 +72,7 @@ struct TxBridge {
 
     inline void clear_snapshot() {
         tx->ClearSnapshot();
+        r_opts->snapshot = nullptr;
     }
 
     [[nodiscard]] inline DB *get_db() const {
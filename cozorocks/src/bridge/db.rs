@@ -168,15 +168,31 @@ impl RocksDb {
         }
     }
     #[inline]
-    pub fn range_compact(&self, lower: &[u8], upper: &[u8]) -> Result<(), RocksDbStatus> {
+    pub fn range_compact(
+        &self,
+        lower: &[u8],
+        upper: &[u8],
+        force_bottommost: bool,
+    ) -> Result<(), RocksDbStatus> {
         let mut status = RocksDbStatus::default();
-        self.inner.compact_range(lower, upper, &mut status);
+        self.inner
+            .compact_range(lower, upper, force_bottommost, &mut status);
         if status.is_ok() {
             Ok(())
         } else {
             Err(status)
         }
     }
+    #[inline]
+    pub fn approximate_size(&self, lower: &[u8], upper: &[u8]) -> Result<u64, RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        let size = self.inner.approximate_size(lower, upper, &mut status);
+        if status.is_ok() {
+            Ok(size)
+        } else {
+            Err(status)
+        }
+    }
     pub fn get_sst_writer(&self, path: &str) -> Result<SstWriter, RocksDbStatus> {
         let mut status = RocksDbStatus::default();
         let ret = self.inner.get_sst_writer(path, &mut status);
@@ -195,6 +211,17 @@ impl RocksDb {
             Err(status)
         }
     }
+    /// Create a consistent snapshot of the database in the directory `path`,
+    /// which must not exist. Files are hard-linked when possible.
+    pub fn create_checkpoint(&self, path: &str) -> Result<(), RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        self.inner.create_checkpoint(path, &mut status);
+        if status.is_ok() {
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
 }
 
 pub struct SstWriter {
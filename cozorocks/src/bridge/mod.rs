@@ -132,14 +132,22 @@ pub(crate) mod ffi {
             self: &RocksDbBridge,
             lower: &[u8],
             upper: &[u8],
+            force_bottommost: bool,
             status: &mut RocksDbStatus,
         );
+        fn approximate_size(
+            self: &RocksDbBridge,
+            lower: &[u8],
+            upper: &[u8],
+            status: &mut RocksDbStatus,
+        ) -> u64;
         fn get_sst_writer(
             self: &RocksDbBridge,
             path: &str,
             status: &mut RocksDbStatus,
         ) -> UniquePtr<SstFileWriterBridge>;
         fn ingest_sst(self: &RocksDbBridge, path: &str, status: &mut RocksDbStatus);
+        fn create_checkpoint(self: &RocksDbBridge, path: &str, status: &mut RocksDbStatus);
 
         type SstFileWriterBridge;
         fn put(